@@ -1,61 +1,303 @@
-use derive_more::Display;
+use derive_more::{Display, From, FromStr, Into};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 
+use crate::engine::EnginePolicy;
 use crate::errors::PaymentsError;
-use crate::transaction::Amount;
+use crate::transaction::{Amount, TransactionType, TxId};
 
-/// Unique identifier for a client.
-pub type ClientId = u16;
+/// Unique identifier for a client. Reads and writes as a plain integer.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    Display,
+    FromStr,
+    From,
+    Into,
+    Deserialize,
+    Serialize,
+)]
+#[serde(transparent)]
+pub struct ClientId(u16);
+
+impl ClientId {
+    pub const MAX: ClientId = ClientId(u16::MAX);
+
+    pub const fn new(id: u16) -> Self {
+        Self(id)
+    }
+
+    pub const fn get(self) -> u16 {
+        self.0
+    }
+}
+
+/// Identifier of a tenant (e.g. a merchant) whose client and transaction IDs are
+/// independent of every other tenant's. Reads and writes as a plain string.
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    Display,
+    From,
+    Into,
+    Deserialize,
+    Serialize,
+)]
+#[serde(transparent)]
+pub struct TenantId(String);
+
+impl TenantId {
+    pub fn new(id: impl Into<String>) -> Self {
+        Self(id.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for TenantId {
+    fn from(id: &str) -> Self {
+        Self::new(id)
+    }
+}
+
+/// Why an account is locked. Each level blocks everything the one before it does:
+///
+/// | State | Blocks |
+/// |-------|--------|
+/// | `Unlocked` | nothing |
+/// | `ChargebackLock` | withdrawals and closing; deposits and disputes unless `EnginePolicy` allows them |
+/// | `ComplianceHold` | withdrawals, closing, deposits and disputes; open disputes can still be resolved or charged back |
+/// | `AdminFreeze` | every transaction |
+///
+/// A chargeback raises an account to `ChargebackLock` and never lowers a stricter state.
+/// Reads and writes as the snake_case name, e.g. `compliance_hold`.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum LockState {
+    #[default]
+    Unlocked,
+    ChargebackLock,
+    ComplianceHold,
+    AdminFreeze,
+}
+
+impl LockState {
+    pub const ALL: [LockState; 4] = [
+        Self::Unlocked,
+        Self::ChargebackLock,
+        Self::ComplianceHold,
+        Self::AdminFreeze,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Unlocked => "unlocked",
+            Self::ChargebackLock => "chargeback_lock",
+            Self::ComplianceHold => "compliance_hold",
+            Self::AdminFreeze => "admin_freeze",
+        }
+    }
+
+    pub fn is_locked(self) -> bool {
+        self != Self::Unlocked
+    }
+
+    /// Whether an account in this state rejects a transaction of `tx_type` under `policy`.
+    pub fn blocks(self, tx_type: &TransactionType, policy: &EnginePolicy) -> bool {
+        match (self, tx_type) {
+            (Self::Unlocked, _) => false,
+            (Self::AdminFreeze, _) => true,
+            (_, TransactionType::Withdrawal | TransactionType::CloseAccount) => true,
+            (_, TransactionType::Resolve | TransactionType::Chargeback) => false,
+            (Self::ChargebackLock, TransactionType::Deposit) => !policy.allow_deposit_when_locked,
+            (Self::ChargebackLock, TransactionType::Dispute) => !policy.allow_dispute_when_locked,
+            (Self::ComplianceHold, _) => true,
+        }
+    }
+}
+
+impl std::fmt::Display for LockState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
 
-/// Represents a client's account with available, held, and total funds, as well as a locked status.
+impl std::str::FromStr for LockState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_lowercase().replace('-', "_");
+        Self::ALL
+            .into_iter()
+            .find(|state| state.as_str() == name)
+            .ok_or_else(|| {
+                format!(
+                    "unknown lock state '{}' (expected unlocked, chargeback_lock, compliance_hold or admin_freeze)",
+                    s
+                )
+            })
+    }
+}
+
+/// Represents a client's account with available, held, and total funds, as well as its lock state.
 ///
-#[derive(Debug, Clone, Display, Deserialize, Serialize)]
+/// Written as `client,available,held,total,locked,closed,lock_state,lock_reason`, where
+/// `locked` is true in every state but `unlocked`. Rows without `lock_state`, as written
+/// before lock states existed, read a `locked` account as `chargeback_lock`.
+#[derive(Debug, Clone, PartialEq, Display, Deserialize, Serialize)]
+#[serde(from = "AccountRecord", into = "AccountRecord")]
 #[display(
-    "Client {}: available={}, held={}, total={}, locked={}",
+    "Client {}: available={}, held={}, total={}, lock={}, closed={}",
     client,
     available,
     held,
     total,
-    locked
+    lock,
+    closed
 )]
 pub struct Account {
     /// Unique identifier for the client.
     pub client: ClientId,
 
     /// Funds available for transactions.
-    #[serde(with = "rust_decimal::serde::str")]
     pub available: Amount,
 
     /// Funds held due to disputes.
-    #[serde(with = "rust_decimal::serde::str")]
     pub held: Amount,
 
     /// Total funds (available + held).
-    #[serde(with = "rust_decimal::serde::str")]
     pub total: Amount,
 
-    /// Indicates if the account is locked (e.g., after a chargeback).
-    pub locked: bool,
+    /// Whether and why the account is locked, e.g. after a chargeback.
+    pub lock: LockState,
+
+    /// Why the account got its lock state, e.g. the charged back transaction or a
+    /// compliance case.
+    pub lock_reason: Option<String>,
+
+    /// Indicates if the account was closed by a close transaction.
+    /// Closed accounts reject every further transaction.
+    pub closed: bool,
+}
+
+/// `Account` as it is read and written.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct AccountRecord {
+    client: ClientId,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+    #[serde(default)]
+    closed: bool,
+    #[serde(default)]
+    lock_state: Option<LockState>,
+    #[serde(default)]
+    lock_reason: Option<String>,
+}
+
+impl From<AccountRecord> for Account {
+    fn from(record: AccountRecord) -> Self {
+        let lock = match (record.lock_state, record.locked) {
+            (Some(state), _) => state,
+            (None, true) => LockState::ChargebackLock,
+            (None, false) => LockState::Unlocked,
+        };
+        Self {
+            client: record.client,
+            available: record.available,
+            held: record.held,
+            total: record.total,
+            lock,
+            lock_reason: record.lock_reason.filter(|reason| !reason.is_empty()),
+            closed: record.closed,
+        }
+    }
+}
+
+impl From<Account> for AccountRecord {
+    fn from(account: Account) -> Self {
+        Self {
+            client: account.client,
+            available: account.available,
+            held: account.held,
+            total: account.total,
+            locked: account.lock.is_locked(),
+            closed: account.closed,
+            lock_state: Some(account.lock),
+            lock_reason: account.lock_reason,
+        }
+    }
 }
 
 impl Account {
     /// Creates a new account for the given client ID with zero balances and unlocked status.
-    pub fn new(client: ClientId) -> Self {
+    pub fn new(client: impl Into<ClientId>) -> Self {
         Self {
-            client,
+            client: client.into(),
             available: Amount::new(0, 0),
             held: Amount::new(0, 0),
             total: Amount::new(0, 0),
-            locked: false,
+            lock: LockState::Unlocked,
+            lock_reason: None,
+            closed: false,
         }
     }
 
-    /// Deposits the specified amount into the account, updating available and total balances.
-    /// Returns an error if the account is locked.
-    pub fn deposit(&mut self, amount: Amount) -> Result<(), PaymentsError> {
-        if self.locked {
-            return Err(PaymentsError::AccountFrozen);
+    /// Whether the account is in any lock state but `Unlocked`.
+    pub fn is_locked(&self) -> bool {
+        self.lock.is_locked()
+    }
+
+    /// Put the account in `lock`, for `reason`, replacing its current state, e.g. to
+    /// freeze it or to lift a lock.
+    pub fn set_lock(&mut self, lock: LockState, reason: Option<String>) {
+        self.lock = lock;
+        self.lock_reason = reason;
+    }
+
+    /// Returns `AccountFrozen` if the account's lock state blocks `tx_type`.
+    fn ensure_allowed(
+        &self,
+        tx_type: TransactionType,
+        policy: &EnginePolicy,
+    ) -> Result<(), PaymentsError> {
+        if self.lock.blocks(&tx_type, policy) {
+            return Err(PaymentsError::AccountFrozen(self.client));
         }
+        Ok(())
+    }
+
+    /// Returns an error if the account has been closed.
+    fn ensure_open(&self) -> Result<(), PaymentsError> {
+        if self.closed {
+            return Err(PaymentsError::AccountClosed(self.client));
+        }
+        Ok(())
+    }
+
+    /// Deposits the specified amount into the account, updating available and total balances.
+    /// Returns an error if the account's lock state blocks deposits under `policy`.
+    pub fn deposit(&mut self, amount: Amount, policy: &EnginePolicy) -> Result<(), PaymentsError> {
+        self.ensure_open()?;
+        self.ensure_allowed(TransactionType::Deposit, policy)?;
 
         self.available += amount;
         self.total += amount;
@@ -65,12 +307,13 @@ impl Account {
     /// Withdraws the specified amount from the account, updating available and total balances.
     /// Returns an error if the account is locked or if there are insufficient funds.
     pub fn withdraw(&mut self, amount: Amount) -> Result<(), PaymentsError> {
-        if self.locked {
-            return Err(PaymentsError::AccountFrozen);
+        self.ensure_open()?;
+        if self.is_locked() {
+            return Err(PaymentsError::AccountFrozen(self.client));
         }
 
         if self.available < amount {
-            return Err(PaymentsError::InsufficientFunds);
+            return Err(PaymentsError::InsufficientFunds(self.client));
         }
 
         self.available -= amount;
@@ -79,13 +322,14 @@ impl Account {
     }
 
     /// Places a hold on the specified amount, moving it from available to held funds.
-    /// Returns an error if the account is locked or if there are insufficient available funds.
-    pub fn hold(&mut self, amount: Amount) -> Result<(), PaymentsError> {
-        if self.locked {
-            return Err(PaymentsError::AccountFrozen);
-        }
-        if self.available < amount {
-            return Err(PaymentsError::InsufficientFunds);
+    /// Returns an error if the account's lock state blocks disputes under `policy` or if
+    /// there are insufficient available funds (unless `policy` allows a negative available
+    /// balance).
+    pub fn hold(&mut self, amount: Amount, policy: &EnginePolicy) -> Result<(), PaymentsError> {
+        self.ensure_open()?;
+        self.ensure_allowed(TransactionType::Dispute, policy)?;
+        if self.available < amount && !policy.allow_negative_available {
+            return Err(PaymentsError::InsufficientFunds(self.client));
         }
         self.available -= amount;
         self.held += amount;
@@ -93,24 +337,134 @@ impl Account {
     }
 
     /// Releases a hold on the specified amount, moving it from held to available funds.
+    /// Returns an error if the account is frozen by an administrator.
     pub fn release(&mut self, amount: Amount) -> Result<(), PaymentsError> {
+        self.ensure_open()?;
+        self.ensure_allowed(TransactionType::Resolve, &EnginePolicy::default())?;
+        self.force_release(amount)
+    }
+
+    /// Releases a hold like `release`, whatever the account's lock state, e.g. when an
+    /// operator settles a case on a frozen account. The lock state is kept.
+    pub fn force_release(&mut self, amount: Amount) -> Result<(), PaymentsError> {
+        self.ensure_open()?;
         if self.held < amount {
-            return Err(PaymentsError::InsufficientFunds);
+            return Err(PaymentsError::InsufficientFunds(self.client));
         }
         self.held -= amount;
         self.available += amount;
         Ok(())
     }
 
-    pub fn chargeback(&mut self, amount: Amount) -> Result<(), PaymentsError> {
+    /// Removes the held amount of the charged back transaction `tx` and raises the
+    /// account to `ChargebackLock`, naming `tx` as the reason. A stricter lock state is
+    /// kept. Returns an error if the account is frozen by an administrator.
+    pub fn chargeback(&mut self, amount: Amount, tx: TxId) -> Result<(), PaymentsError> {
+        self.ensure_open()?;
+        self.ensure_allowed(TransactionType::Chargeback, &EnginePolicy::default())?;
         if self.held < amount {
-            return Err(PaymentsError::InsufficientFunds);
+            return Err(PaymentsError::InsufficientFunds(self.client));
         }
         self.held -= amount;
         self.total -= amount;
-        self.locked = true;
+        if self.lock < LockState::ChargebackLock {
+            self.set_lock(
+                LockState::ChargebackLock,
+                Some(format!("chargeback of transaction {}", tx)),
+            );
+        }
         Ok(())
     }
+
+    /// Closes the account. With `payout`, the available balance is withdrawn first.
+    /// Returns the amount paid out. Returns an error if the account is already closed,
+    /// locked, or still has held funds.
+    pub fn close(&mut self, payout: bool) -> Result<Amount, PaymentsError> {
+        self.ensure_open()?;
+        if self.is_locked() {
+            return Err(PaymentsError::AccountFrozen(self.client));
+        }
+        if !self.held.is_zero() {
+            return Err(PaymentsError::InvalidTransaction(format!(
+                "Cannot close account {} while funds are held",
+                self.client
+            )));
+        }
+
+        let paid = if payout {
+            self.available.max(Amount::ZERO)
+        } else {
+            Amount::ZERO
+        };
+        self.available -= paid;
+        self.total -= paid;
+        self.closed = true;
+        Ok(paid)
+    }
+}
+
+/// Selects which accounts are written to the output. Every condition that is set must hold.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccountFilter {
+    /// Only these clients, when not empty.
+    pub clients: HashSet<ClientId>,
+
+    /// Only locked accounts.
+    pub only_locked: bool,
+
+    /// Only accounts whose total is at least this amount.
+    pub min_total: Option<Amount>,
+}
+
+impl AccountFilter {
+    /// Returns true if `account` meets every condition.
+    pub fn matches(&self, account: &Account) -> bool {
+        (self.clients.is_empty() || self.clients.contains(&account.client))
+            && (!self.only_locked || account.is_locked())
+            && self.min_total.is_none_or(|min| account.total >= min)
+    }
+}
+
+/// How balances are written to account output, the same for every engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct DecimalFormat {
+    /// Places every balance is rounded and padded to.
+    pub decimal_places: u32,
+
+    /// Drop the zeros padding, e.g. write `10` instead of `10.0000`.
+    pub strip_trailing_zeros: bool,
+}
+
+impl Default for DecimalFormat {
+    fn default() -> Self {
+        Self {
+            decimal_places: 4,
+            strip_trailing_zeros: false,
+        }
+    }
+}
+
+impl DecimalFormat {
+    /// `amount` as it is written.
+    pub fn apply(self, amount: Amount) -> Amount {
+        let amount = amount.rescale(self.decimal_places);
+        if self.strip_trailing_zeros {
+            amount.normalize()
+        } else {
+            amount
+        }
+    }
+
+    /// A copy of `account` with its balances as they are written.
+    pub fn account(self, account: &Account) -> Account {
+        Account {
+            available: self.apply(account.available),
+            held: self.apply(account.held),
+            total: self.apply(account.total),
+            ..account.clone()
+        }
+    }
 }
 
 #[cfg(test)]
@@ -120,17 +474,19 @@ mod tests {
     #[test]
     fn test_account_creation() {
         let account = Account::new(1);
-        assert_eq!(account.client, 1);
+        assert_eq!(account.client, ClientId::new(1));
         assert_eq!(account.available, Amount::new(0, 0));
         assert_eq!(account.held, Amount::new(0, 0));
         assert_eq!(account.total, Amount::new(0, 0));
-        assert!(!account.locked);
+        assert!(!account.is_locked());
     }
 
     #[test]
     fn test_deposit() {
         let mut account = Account::new(1);
-        account.deposit(Amount::new(100, 0)).unwrap();
+        account
+            .deposit(Amount::new(100, 0), &EnginePolicy::default())
+            .unwrap();
         assert_eq!(account.available, Amount::new(100, 0));
         assert_eq!(account.total, Amount::new(100, 0));
     }
@@ -138,7 +494,9 @@ mod tests {
     #[test]
     fn test_withdraw() {
         let mut account = Account::new(1);
-        account.deposit(Amount::new(100, 0)).unwrap();
+        account
+            .deposit(Amount::new(100, 0), &EnginePolicy::default())
+            .unwrap();
         account.withdraw(Amount::new(50, 0)).unwrap();
         assert_eq!(account.available, Amount::new(50, 0));
         assert_eq!(account.total, Amount::new(50, 0));
@@ -148,14 +506,18 @@ mod tests {
     fn test_withdraw_insufficient_funds() {
         let mut account = Account::new(1);
         let result = account.withdraw(Amount::new(50, 0));
-        assert!(matches!(result, Err(PaymentsError::InsufficientFunds)));
+        assert!(matches!(result, Err(PaymentsError::InsufficientFunds(_))));
     }
 
     #[test]
     fn test_hold() {
         let mut account = Account::new(1);
-        account.deposit(Amount::new(100, 0)).unwrap();
-        account.hold(Amount::new(30, 0)).unwrap();
+        account
+            .deposit(Amount::new(100, 0), &EnginePolicy::default())
+            .unwrap();
+        account
+            .hold(Amount::new(30, 0), &EnginePolicy::default())
+            .unwrap();
         assert_eq!(account.available, Amount::new(70, 0));
         assert_eq!(account.held, Amount::new(30, 0));
         assert_eq!(account.total, Amount::new(100, 0));
@@ -164,8 +526,12 @@ mod tests {
     #[test]
     fn test_release() {
         let mut account = Account::new(1);
-        account.deposit(Amount::new(100, 0)).unwrap();
-        account.hold(Amount::new(30, 0)).unwrap();
+        account
+            .deposit(Amount::new(100, 0), &EnginePolicy::default())
+            .unwrap();
+        account
+            .hold(Amount::new(30, 0), &EnginePolicy::default())
+            .unwrap();
         account.release(Amount::new(20, 0)).unwrap();
         assert_eq!(account.available, Amount::new(90, 0));
         assert_eq!(account.held, Amount::new(10, 0));
@@ -175,24 +541,104 @@ mod tests {
     #[test]
     fn test_chargeback() {
         let mut account = Account::new(1);
-        account.deposit(Amount::new(100, 0)).unwrap();
-        account.hold(Amount::new(50, 0)).unwrap();
-        account.chargeback(Amount::new(50, 0)).unwrap();
+        account
+            .deposit(Amount::new(100, 0), &EnginePolicy::default())
+            .unwrap();
+        account
+            .hold(Amount::new(50, 0), &EnginePolicy::default())
+            .unwrap();
+        account
+            .chargeback(Amount::new(50, 0), TxId::new(7))
+            .unwrap();
         assert_eq!(account.available, Amount::new(50, 0));
         assert_eq!(account.held, Amount::new(0, 0));
         assert_eq!(account.total, Amount::new(50, 0));
-        assert!(account.locked);
+        assert_eq!(account.lock, LockState::ChargebackLock);
+        assert_eq!(
+            account.lock_reason.as_deref(),
+            Some("chargeback of transaction 7")
+        );
     }
 
     #[test]
     fn test_account_locked() {
         let mut account = Account::new(1);
-        account.locked = true;
-        let deposit_result = account.deposit(Amount::new(100, 0));
-        assert!(matches!(deposit_result, Err(PaymentsError::AccountFrozen)));
+        account.lock = LockState::ChargebackLock;
+        let deposit_result = account.deposit(Amount::new(100, 0), &EnginePolicy::default());
+        assert!(matches!(
+            deposit_result,
+            Err(PaymentsError::AccountFrozen(_))
+        ));
         let withdraw_result = account.withdraw(Amount::new(50, 0));
-        assert!(matches!(withdraw_result, Err(PaymentsError::AccountFrozen)));
-        let hold_result = account.hold(Amount::new(30, 0));
-        assert!(matches!(hold_result, Err(PaymentsError::AccountFrozen)));
+        assert!(matches!(
+            withdraw_result,
+            Err(PaymentsError::AccountFrozen(_))
+        ));
+        let hold_result = account.hold(Amount::new(30, 0), &EnginePolicy::default());
+        assert!(matches!(hold_result, Err(PaymentsError::AccountFrozen(_))));
+    }
+
+    #[test]
+    fn test_locked_account_policy() {
+        let policy = EnginePolicy {
+            allow_deposit_when_locked: true,
+            allow_dispute_when_locked: true,
+            ..EnginePolicy::default()
+        };
+        let mut account = Account::new(1);
+        account.lock = LockState::ChargebackLock;
+        account.deposit(Amount::new(100, 0), &policy).unwrap();
+        account.hold(Amount::new(30, 0), &policy).unwrap();
+        assert_eq!(account.available, Amount::new(70, 0));
+        assert_eq!(account.held, Amount::new(30, 0));
+        assert!(matches!(
+            account.withdraw(Amount::new(10, 0)),
+            Err(PaymentsError::AccountFrozen(_))
+        ));
+    }
+
+    #[test]
+    fn test_hold_negative_available() {
+        let mut account = Account::new(1);
+        account
+            .deposit(Amount::new(100, 0), &EnginePolicy::default())
+            .unwrap();
+        account.withdraw(Amount::new(100, 0)).unwrap();
+        let result = account.hold(Amount::new(100, 0), &EnginePolicy::default());
+        assert!(matches!(result, Err(PaymentsError::InsufficientFunds(_))));
+
+        let policy = EnginePolicy {
+            allow_negative_available: true,
+            ..EnginePolicy::default()
+        };
+        account.hold(Amount::new(100, 0), &policy).unwrap();
+        assert_eq!(account.available, Amount::new(-100, 0));
+        assert_eq!(account.held, Amount::new(100, 0));
+        assert_eq!(account.total, Amount::new(0, 0));
+    }
+
+    #[test]
+    fn test_close() {
+        let policy = EnginePolicy::default();
+        let mut account = Account::new(1);
+        account.deposit(Amount::new(100, 0), &policy).unwrap();
+        account.hold(Amount::new(30, 0), &policy).unwrap();
+        assert!(matches!(
+            account.close(true),
+            Err(PaymentsError::InvalidTransaction(_))
+        ));
+
+        account.release(Amount::new(30, 0)).unwrap();
+        assert_eq!(account.close(true).unwrap(), Amount::new(100, 0));
+        assert!(account.closed);
+        assert_eq!(account.total, Amount::new(0, 0));
+        assert!(matches!(
+            account.deposit(Amount::new(1, 0), &policy),
+            Err(PaymentsError::AccountClosed(client)) if client == account.client
+        ));
+        assert!(matches!(
+            account.close(false),
+            Err(PaymentsError::AccountClosed(client)) if client == account.client
+        ));
     }
 }
@@ -0,0 +1,322 @@
+//! Administrative commands for an engine that keeps running, e.g. in watch mode.
+//!
+//! `AdminChannel` listens on a unix socket that only the engine's user may connect to.
+//! A client sends one command per connection as a line of text and reads back one line,
+//! `ok` followed by details or `error` followed by the reason:
+//!
+//! ```text
+//! freeze <client> [reason]   put the account in an admin freeze
+//! hold <client> [reason]     put the account on compliance hold
+//! unfreeze <client>          lift any lock
+//! resolve <client> <tx>      force-resolve an open dispute
+//! snapshot                   write the output now
+//! stats                      engine statistics
+//! drain                      stop reading input, finish queued work and write the output
+//! shutdown                   write the output and exit
+//! ```
+//!
+//! The channel is polled from the engine's own thread, so commands never run
+//! concurrently with processing.
+
+use std::fmt;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::str::{FromStr, SplitWhitespace};
+use std::time::Duration;
+
+use crate::account::{ClientId, LockState};
+use crate::engine::{EngineInfo, PaymentsEngine};
+use crate::errors::PaymentsError;
+use crate::transaction::TxId;
+
+/// How long a connected client may take to send its command
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Longest command line read; the rest is ignored
+const MAX_LINE_BYTES: u64 = 4096;
+
+/// An operator's intervention in a running engine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdminCommand {
+    /// Put the account into `lock`, an admin freeze or a compliance hold.
+    Freeze {
+        client: ClientId,
+        lock: LockState,
+        reason: Option<String>,
+    },
+    Unfreeze {
+        client: ClientId,
+    },
+    /// Resolve an open dispute even if the account is frozen.
+    ForceResolve {
+        client: ClientId,
+        tx: TxId,
+    },
+    Snapshot,
+    Stats,
+    Drain,
+    Shutdown,
+}
+
+impl AdminCommand {
+    /// Apply the command to `engine` and describe the outcome. `Snapshot`, `Drain` and
+    /// `Shutdown` only wait for queued transactions here; writing the output, pausing the
+    /// input and stopping are up to the caller.
+    pub fn apply(&self, engine: &mut PaymentsEngine) -> Result<String, PaymentsError> {
+        match self {
+            Self::Freeze {
+                client,
+                lock,
+                reason,
+            } => {
+                engine.set_lock(*client, *lock, reason.clone())?;
+                Ok(format!("client {} {}", client, lock))
+            }
+            Self::Unfreeze { client } => {
+                if engine.peek_account(*client)?.is_none() {
+                    return Ok(format!("client {} has no account", client));
+                }
+                engine.set_lock(*client, LockState::Unlocked, None)?;
+                Ok(format!("client {} unlocked", client))
+            }
+            Self::ForceResolve { client, tx } => {
+                engine.force_resolve(*client, *tx)?;
+                Ok(format!("dispute of transaction {} resolved", tx))
+            }
+            Self::Stats => Ok(stats_line(&engine.get_engine_info())),
+            Self::Snapshot | Self::Drain | Self::Shutdown => {
+                engine.drain();
+                Ok(String::new())
+            }
+        }
+    }
+}
+
+impl FromStr for AdminCommand {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or_default().to_lowercase();
+        let command = match name.as_str() {
+            "freeze" | "hold" => {
+                let client = argument(&mut words, "client ID")?;
+                let reason = words.by_ref().collect::<Vec<_>>().join(" ");
+                Self::Freeze {
+                    client,
+                    lock: if name == "freeze" {
+                        LockState::AdminFreeze
+                    } else {
+                        LockState::ComplianceHold
+                    },
+                    reason: (!reason.is_empty()).then_some(reason),
+                }
+            }
+            "unfreeze" => Self::Unfreeze {
+                client: argument(&mut words, "client ID")?,
+            },
+            "resolve" => Self::ForceResolve {
+                client: argument(&mut words, "client ID")?,
+                tx: argument(&mut words, "transaction ID")?,
+            },
+            "snapshot" => Self::Snapshot,
+            "stats" => Self::Stats,
+            "drain" => Self::Drain,
+            "shutdown" => Self::Shutdown,
+            "" => return Err("empty command".to_string()),
+            _ => return Err(format!("unknown command '{}'", name)),
+        };
+        match words.next() {
+            Some(extra) => Err(format!("unexpected argument '{}'", extra)),
+            None => Ok(command),
+        }
+    }
+}
+
+fn argument<T: FromStr>(words: &mut SplitWhitespace, name: &str) -> Result<T, String>
+where
+    T::Err: fmt::Display,
+{
+    let word = words.next().ok_or_else(|| format!("missing {}", name))?;
+    word.parse()
+        .map_err(|e| format!("invalid {} '{}': {}", name, word, e))
+}
+
+fn stats_line(info: &EngineInfo) -> String {
+    format!(
+        "engine={} accounts={} disputable={} transactions={} p50={:?} p99={:?} throughput={:.0}",
+        info.engine_type,
+        info.account_count,
+        info.transaction_count
+            .map_or_else(|| "-".to_string(), |count| count.to_string()),
+        info.stats.transactions,
+        info.stats.p50,
+        info.stats.p99,
+        info.stats.throughput
+    )
+}
+
+/// Unix socket taking admin commands, without blocking the engine between them.
+/// The socket file is removed when the channel is dropped.
+#[derive(Debug)]
+pub struct AdminChannel {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl AdminChannel {
+    /// Listen on `path`, replacing a socket left behind by an earlier run. The socket is
+    /// only accessible to the current user.
+    pub fn bind(path: &Path) -> std::io::Result<Self> {
+        if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        listener.set_nonblocking(true)?;
+        log::info!("Accepting admin commands on {:?}", path);
+        Ok(Self {
+            listener,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// The next waiting command, or `None` if no client is waiting. Clients sending
+    /// something that is not a command are answered with an error and skipped.
+    pub fn next_request(&self) -> std::io::Result<Option<AdminRequest>> {
+        loop {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            match read_command(&stream) {
+                Ok(Ok(command)) => return Ok(Some(AdminRequest { command, stream })),
+                Ok(Err(e)) => AdminRequest::reply(stream, Err::<String, _>(e)),
+                Err(e) => log::warn!("Failed to read admin command: {}", e),
+            }
+        }
+    }
+}
+
+impl Drop for AdminChannel {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            log::warn!("Failed to remove admin socket {:?}: {}", self.path, e);
+        }
+    }
+}
+
+fn read_command(stream: &UnixStream) -> std::io::Result<Result<AdminCommand, String>> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(stream.take(MAX_LINE_BYTES)).read_line(&mut line)?;
+    Ok(line.parse())
+}
+
+/// A command received on an `AdminChannel`, waiting for its answer
+#[derive(Debug)]
+pub struct AdminRequest {
+    pub command: AdminCommand,
+    stream: UnixStream,
+}
+
+impl AdminRequest {
+    /// Answer the client with the command's outcome.
+    pub fn respond<E: fmt::Display>(self, result: Result<String, E>) {
+        Self::reply(self.stream, result);
+    }
+
+    fn reply<E: fmt::Display>(mut stream: UnixStream, result: Result<String, E>) {
+        let line = match result {
+            Ok(detail) if detail.is_empty() => "ok\n".to_string(),
+            Ok(detail) => format!("ok {}\n", detail),
+            Err(e) => format!("error {}\n", e),
+        };
+        if let Err(e) = stream.write_all(line.as_bytes()) {
+            log::warn!("Failed to answer admin command: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::EngineConfig;
+    use crate::transaction::{Amount, Transaction};
+
+    #[test]
+    fn test_parse_admin_commands() {
+        assert_eq!(
+            "freeze 7 case 12 fraud".parse(),
+            Ok(AdminCommand::Freeze {
+                client: ClientId::new(7),
+                lock: LockState::AdminFreeze,
+                reason: Some("case 12 fraud".to_string()),
+            })
+        );
+        assert_eq!(
+            "HOLD 7\n".parse(),
+            Ok(AdminCommand::Freeze {
+                client: ClientId::new(7),
+                lock: LockState::ComplianceHold,
+                reason: None,
+            })
+        );
+        assert_eq!(
+            "resolve 7 42".parse(),
+            Ok(AdminCommand::ForceResolve {
+                client: ClientId::new(7),
+                tx: TxId::from(42),
+            })
+        );
+        assert_eq!("stats".parse(), Ok(AdminCommand::Stats));
+        assert!("resolve 7".parse::<AdminCommand>().is_err());
+        assert!("unfreeze seven".parse::<AdminCommand>().is_err());
+        assert!("stats now".parse::<AdminCommand>().is_err());
+        assert!("restart".parse::<AdminCommand>().is_err());
+    }
+
+    #[test]
+    fn test_admin_channel() {
+        let path = std::env::temp_dir().join(format!("admin-{}.sock", std::process::id()));
+        let channel = AdminChannel::bind(&path).unwrap();
+        assert!(channel.next_request().unwrap().is_none());
+
+        let mut engine = PaymentsEngine::new(EngineConfig::standard());
+        engine
+            .process_transaction(&Transaction::deposit(1, 1, Amount::new(5, 0)))
+            .unwrap();
+
+        let send = |line: &str| {
+            let mut client = UnixStream::connect(&path).unwrap();
+            client.write_all(line.as_bytes()).unwrap();
+            client
+        };
+        let answer = |client: UnixStream| {
+            let mut answer = String::new();
+            BufReader::new(client).read_line(&mut answer).unwrap();
+            answer
+        };
+
+        let client = send("freeze 1 chargeback review\n");
+        let request = channel.next_request().unwrap().unwrap();
+        let result = request.command.apply(&mut engine);
+        request.respond(result);
+        assert_eq!(answer(client), "ok client 1 admin_freeze\n");
+        let account = engine.peek_account(ClientId::new(1)).unwrap().unwrap();
+        assert_eq!(account.lock, LockState::AdminFreeze);
+        assert_eq!(account.lock_reason.as_deref(), Some("chargeback review"));
+
+        // Malformed commands are answered without reaching the caller
+        let client = send("freeze\n");
+        assert!(channel.next_request().unwrap().is_none());
+        assert_eq!(answer(client), "error missing client ID\n");
+
+        drop(channel);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,164 @@
+//! Anti-money-laundering threshold reporting.
+//!
+//! `AmlMonitor` sums each client's accepted deposits per UTC day (from the optional
+//! `timestamp` column) and reports every client-day whose total exceeds the configured
+//! threshold, with the deposits that make it up. Deposits without a timestamp are all
+//! counted towards a single undated bucket.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::account::ClientId;
+use crate::engine::observer::EngineObserver;
+use crate::errors::PaymentsError;
+use crate::transaction::{Amount, SECONDS_PER_DAY, Transaction, TransactionType, TxId, format_day};
+
+/// Reporting thresholds.
+///
+/// ```toml
+/// [aml]
+/// daily_deposit_threshold = "10000"
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AmlConfig {
+    /// Report clients whose deposits in one day add up to more than this
+    pub daily_deposit_threshold: Amount,
+}
+
+impl Default for AmlConfig {
+    fn default() -> Self {
+        Self {
+            daily_deposit_threshold: Amount::new(10_000, 0),
+        }
+    }
+}
+
+/// One client-day over the threshold
+#[derive(Debug, Clone, PartialEq)]
+pub struct AmlReportEntry {
+    pub client: ClientId,
+    /// Days since the Unix epoch (UTC), or `None` for undated deposits
+    pub day: Option<u64>,
+    pub total_deposits: Amount,
+    /// The day's deposits, in processing order
+    pub transactions: Vec<TxId>,
+}
+
+/// A client and UTC day (days since the epoch, or `None` when undated)
+type ClientDay = (ClientId, Option<u64>);
+
+#[derive(Debug, Default)]
+struct DailyDeposits {
+    total: Amount,
+    transactions: Vec<TxId>,
+}
+
+/// Engine observer that accumulates deposits per client and day. Clones share the same
+/// totals, so keep one to write the report after processing.
+#[derive(Debug, Clone, Default)]
+pub struct AmlMonitor {
+    config: AmlConfig,
+    deposits: Arc<Mutex<HashMap<ClientDay, DailyDeposits>>>,
+}
+
+impl AmlMonitor {
+    pub fn new(config: AmlConfig) -> Self {
+        Self {
+            config,
+            deposits: Arc::default(),
+        }
+    }
+
+    /// Client-days whose deposits exceed the threshold, by client and then day
+    pub fn report(&self) -> Vec<AmlReportEntry> {
+        let deposits = match self.deposits.lock() {
+            Ok(deposits) => deposits,
+            Err(e) => e.into_inner(),
+        };
+        let mut report: Vec<_> = deposits
+            .iter()
+            .filter(|(_, daily)| daily.total > self.config.daily_deposit_threshold)
+            .map(|((client, day), daily)| AmlReportEntry {
+                client: *client,
+                day: *day,
+                total_deposits: daily.total,
+                transactions: daily.transactions.clone(),
+            })
+            .collect();
+        report.sort_unstable_by_key(|entry| (entry.client, entry.day));
+        report
+    }
+
+    /// Write the report as `client,date,total_deposits,transactions` CSV, with dates as
+    /// `YYYY-MM-DD` (empty when undated) and transaction IDs separated by `;`
+    pub fn write_report_csv<W: std::io::Write>(&self, writer: W) -> Result<(), PaymentsError> {
+        let mut wtr = csv::Writer::from_writer(writer);
+        wtr.write_record(["client", "date", "total_deposits", "transactions"])?;
+        for entry in self.report() {
+            let transactions: Vec<_> = entry.transactions.iter().map(TxId::to_string).collect();
+            wtr.write_record([
+                entry.client.to_string(),
+                entry.day.map(format_day).unwrap_or_default(),
+                entry.total_deposits.to_string(),
+                transactions.join(";"),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+impl EngineObserver for AmlMonitor {
+    fn on_accepted(&self, transaction: &Transaction) {
+        let (TransactionType::Deposit, Some(amount)) = (&transaction.tx_type, transaction.amount)
+        else {
+            return;
+        };
+        let Ok(mut deposits) = self.deposits.lock() else {
+            return;
+        };
+        let day = transaction.timestamp.map(|ts| ts / SECONDS_PER_DAY);
+        let daily = deposits.entry((transaction.client, day)).or_default();
+        daily.total += amount;
+        daily.transactions.push(transaction.tx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{EngineConfig, PaymentsEngine};
+
+    #[test]
+    fn test_daily_threshold_report() {
+        // 2024-03-01 00:00:00 UTC and a day later
+        let day1 = 1_709_251_200;
+        let day2 = day1 + SECONDS_PER_DAY;
+        let input = format!(
+            "type,client,tx,amount,timestamp\n\
+             deposit,1,1,6000,{day1}\n\
+             deposit,1,2,4000.01,{}\n\
+             deposit,1,3,9000,{day2}\n\
+             deposit,2,4,10000,{day1}\n\
+             withdrawal,1,5,100,{day1}\n",
+            day1 + 3600
+        );
+
+        let monitor = AmlMonitor::new(AmlConfig::default());
+        let mut engine = PaymentsEngine::new(EngineConfig::standard());
+        engine.add_observer(monitor.clone());
+        engine
+            .process_transactions_from_reader(input.as_bytes())
+            .unwrap();
+
+        // Client 2's deposit equals the threshold without exceeding it
+        let mut output = Vec::new();
+        monitor.write_report_csv(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,date,total_deposits,transactions\n1,2024-03-01,10000.01,1;2\n"
+        );
+    }
+}
@@ -1,10 +1,405 @@
+use crate::account::ClientId;
+use crate::engine::concurrent::ConcurrentEngine;
+use crate::engine::metrics::{EngineMetrics, EngineStats, start_timer};
 use crate::engine::{EngineConfig, PaymentsEngine};
-use crate::transaction::{Transaction, TransactionType};
-use rust_decimal::Decimal;
-use std::io::Cursor;
+#[cfg(feature = "fs")]
+use crate::errors::PaymentsError;
+use crate::transaction::{Amount, Transaction, TransactionType, TxId};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::time::Duration;
 
 use memory_stats::memory_stats;
-/// Benchmark utilities for testing memory usage and performance
+
+/// Shape of a synthetic workload produced by `PaymentEngineBenchmark::generate_workload`.
+/// The defaults roughly mirror `generate_transactions` (uniform clients, one withdrawal
+/// in three) but pick disputed transactions at random and close some of them out.
+#[derive(Debug, Clone)]
+pub struct WorkloadConfig {
+    /// Zipf exponent for client selection. `0.0` is uniform, `1.0` is classic Zipf.
+    pub zipf_exponent: f64,
+
+    /// Probability that a non-dispute transaction is a withdrawal rather than a deposit.
+    pub withdrawal_ratio: f64,
+
+    /// Fraction of clients (the lowest client IDs) treated as hot accounts.
+    pub hot_account_fraction: f64,
+
+    /// Share of all traffic routed to the hot accounts.
+    pub hot_traffic_fraction: f64,
+
+    /// Probability that a deposit or withdrawal is followed by a dispute on an earlier transaction.
+    pub dispute_rate: f64,
+
+    /// Probability that a dispute is eventually resolved.
+    pub resolve_rate: f64,
+
+    /// Probability that a dispute is eventually charged back.
+    /// `resolve_rate + chargeback_rate` should not exceed `1.0`; the remainder stays open.
+    pub chargeback_rate: f64,
+
+    /// Seed for the random number generator, so runs are reproducible.
+    pub seed: u64,
+}
+
+impl Default for WorkloadConfig {
+    fn default() -> Self {
+        Self {
+            zipf_exponent: 0.0,
+            withdrawal_ratio: 1.0 / 3.0,
+            hot_account_fraction: 0.0,
+            hot_traffic_fraction: 0.0,
+            dispute_rate: 0.05,
+            resolve_rate: 0.5,
+            chargeback_rate: 0.1,
+            seed: 42,
+        }
+    }
+}
+
+/// Named workloads that stress one part of the engine, for `benchmark_scenario`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scenario {
+    /// Half of all payments are disputed, and most disputes are resolved. Nothing is
+    /// charged back, so accounts stay unlocked and keep taking traffic.
+    DisputeStorm,
+    /// Most disputes end in a chargeback, locking account after account until most
+    /// traffic is rejected
+    ChargebackWave,
+    /// Nearly all traffic, disputes included, hits a single client, which is never
+    /// charged back
+    HotAccount,
+}
+
+impl Scenario {
+    pub const ALL: [Scenario; 3] = [Self::DisputeStorm, Self::ChargebackWave, Self::HotAccount];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::DisputeStorm => "dispute-storm",
+            Self::ChargebackWave => "chargeback-wave",
+            Self::HotAccount => "hot-account",
+        }
+    }
+
+    /// The scenario's workload, generated from `seed`
+    pub fn workload(self, seed: u64) -> WorkloadConfig {
+        let base = WorkloadConfig {
+            seed,
+            ..WorkloadConfig::default()
+        };
+        match self {
+            Self::DisputeStorm => WorkloadConfig {
+                withdrawal_ratio: 0.1,
+                dispute_rate: 0.5,
+                resolve_rate: 0.9,
+                chargeback_rate: 0.0,
+                ..base
+            },
+            Self::ChargebackWave => WorkloadConfig {
+                withdrawal_ratio: 0.1,
+                dispute_rate: 0.02,
+                resolve_rate: 0.1,
+                chargeback_rate: 0.9,
+                ..base
+            },
+            // Clamped to a single hot client however many accounts there are
+            Self::HotAccount => WorkloadConfig {
+                hot_account_fraction: f64::MIN_POSITIVE,
+                hot_traffic_fraction: 0.95,
+                dispute_rate: 0.1,
+                resolve_rate: 0.9,
+                chargeback_rate: 0.0,
+                ..base
+            },
+        }
+    }
+}
+
+impl std::str::FromStr for Scenario {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|scenario| scenario.name() == s.to_lowercase())
+            .ok_or_else(|| {
+                format!(
+                    "unknown scenario '{}' (expected dispute-storm, chargeback-wave or hot-account)",
+                    s
+                )
+            })
+    }
+}
+
+/// Samples client IDs according to a `WorkloadConfig`.
+struct ClientSampler {
+    /// Cumulative Zipf weights, normalised so the last entry is `1.0`.
+    cdf: Vec<f64>,
+    hot_accounts: usize,
+    hot_traffic_fraction: f64,
+}
+
+impl ClientSampler {
+    fn new(unique_accounts: usize, config: &WorkloadConfig) -> Self {
+        let unique_accounts = unique_accounts.clamp(1, u16::MAX as usize);
+
+        let mut cdf = Vec::with_capacity(unique_accounts);
+        let mut sum = 0.0;
+        for rank in 1..=unique_accounts {
+            sum += 1.0 / (rank as f64).powf(config.zipf_exponent);
+            cdf.push(sum);
+        }
+        for weight in &mut cdf {
+            *weight /= sum;
+        }
+
+        let hot_accounts = if config.hot_account_fraction > 0.0 {
+            ((unique_accounts as f64 * config.hot_account_fraction).ceil() as usize)
+                .clamp(1, unique_accounts)
+        } else {
+            0
+        };
+
+        Self {
+            cdf,
+            hot_accounts,
+            hot_traffic_fraction: config.hot_traffic_fraction,
+        }
+    }
+
+    fn sample(&self, rng: &mut StdRng) -> ClientId {
+        let index = if self.hot_accounts > 0 && rng.r#gen::<f64>() < self.hot_traffic_fraction {
+            rng.gen_range(0..self.hot_accounts)
+        } else {
+            let draw = rng.r#gen::<f64>();
+            self.cdf
+                .partition_point(|&weight| weight < draw)
+                .min(self.cdf.len() - 1)
+        };
+        ClientId::new(index as u16 + 1)
+    }
+}
+
+/// Maximum number of undisputed transactions remembered as dispute candidates.
+/// Keeps the generator's memory flat no matter how many rows it produces.
+const DISPUTE_WINDOW: usize = 100_000;
+
+/// Lazily generates a `WorkloadConfig`-shaped transaction stream.
+/// Clients follow the configured Zipf/hot-account skew, disputes target random earlier
+/// transactions, and disputes are later resolved or charged back according to the
+/// configured mix. Dispute, resolve and chargeback rows are added on top of `count`.
+pub struct WorkloadGenerator {
+    rng: StdRng,
+    sampler: ClientSampler,
+    config: WorkloadConfig,
+    count: usize,
+    next_index: usize,
+    /// Transactions that have not been disputed yet, as (client, tx) pairs
+    disputable: Vec<(ClientId, TxId)>,
+    /// Resolves and chargebacks waiting to be emitted
+    pending: VecDeque<Transaction>,
+    /// Transactions generated by the current step but not yet returned
+    ready: VecDeque<Transaction>,
+}
+
+impl WorkloadGenerator {
+    pub fn new(count: usize, unique_accounts: usize, config: &WorkloadConfig) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(config.seed),
+            sampler: ClientSampler::new(unique_accounts, config),
+            config: config.clone(),
+            count,
+            next_index: 0,
+            disputable: Vec::new(),
+            pending: VecDeque::new(),
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Generate one deposit or withdrawal plus any dispute traffic that follows it.
+    fn step(&mut self) {
+        let rng = &mut self.rng;
+        let tx_id = TxId::new(self.next_index as u32 + 1);
+        self.next_index += 1;
+
+        let client_id = self.sampler.sample(rng);
+        let amount = Amount::new(rng.gen_range(100..=10_000), 2); // $1-$100
+
+        let transaction = if rng.r#gen::<f64>() < self.config.withdrawal_ratio {
+            Transaction::withdrawal(client_id, tx_id, amount)
+        } else {
+            Transaction::deposit(client_id, tx_id, amount)
+        };
+
+        self.ready.push_back(transaction);
+        if self.disputable.len() >= DISPUTE_WINDOW {
+            let evicted = rng.gen_range(0..self.disputable.len());
+            self.disputable.swap_remove(evicted);
+        }
+        self.disputable.push((client_id, tx_id));
+
+        if rng.r#gen::<f64>() < self.config.dispute_rate {
+            let (client, tx) = self
+                .disputable
+                .swap_remove(rng.gen_range(0..self.disputable.len()));
+            self.ready.push_back(Transaction::dispute(client, tx));
+
+            let outcome = rng.r#gen::<f64>();
+            let follow_up = if outcome < self.config.resolve_rate {
+                Some(TransactionType::Resolve)
+            } else if outcome < self.config.resolve_rate + self.config.chargeback_rate {
+                Some(TransactionType::Chargeback)
+            } else {
+                None
+            };
+            if let Some(tx_type) = follow_up {
+                self.pending
+                    .push_back(Transaction::new(tx_type, client, tx, None));
+            }
+        }
+
+        // Release follow-ups gradually so disputes stay open for a while
+        if rng.gen_bool(0.5)
+            && let Some(follow_up) = self.pending.pop_front()
+        {
+            self.ready.push_back(follow_up);
+        }
+    }
+}
+
+impl Iterator for WorkloadGenerator {
+    type Item = Transaction;
+
+    fn next(&mut self) -> Option<Transaction> {
+        while self.ready.is_empty() && self.next_index < self.count {
+            self.step();
+        }
+        self.ready.pop_front().or_else(|| self.pending.pop_front())
+    }
+}
+
+/// What a `PaymentEngineBenchmark` run generates and the limits of the engine it runs.
+/// Every parameter is named, so call sites cannot silently swap them by position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchmarkSpec {
+    /// Deposits and withdrawals to generate
+    pub transaction_count: usize,
+    /// Share of them disputed afterwards (uniform data only; a `WorkloadConfig` or
+    /// `Scenario` brings its own)
+    pub dispute_rate: f32,
+    /// Distinct clients in the generated data
+    pub unique_accounts: usize,
+    /// Limits of the bounded and concurrent engines
+    pub max_accounts: usize,
+    pub max_transactions: usize,
+    pub max_processed_ids: usize,
+    /// Input streams of the concurrent engine; 0 or 1 uses its worker pool
+    pub streams: usize,
+}
+
+impl Default for BenchmarkSpec {
+    fn default() -> Self {
+        Self {
+            transaction_count: 10_000,
+            dispute_rate: 0.05,
+            unique_accounts: 1_000,
+            max_accounts: 1_000,
+            max_transactions: 2_000,
+            max_processed_ids: 50_000,
+            streams: 4,
+        }
+    }
+}
+
+impl BenchmarkSpec {
+    /// The uniform data set of `PaymentEngineBenchmark::uniform_transactions`
+    fn uniform_transactions(&self) -> impl Iterator<Item = Transaction> + Send + 'static {
+        PaymentEngineBenchmark::uniform_transactions(
+            self.transaction_count,
+            self.dispute_rate,
+            self.unique_accounts,
+        )
+    }
+}
+
+/// Columns written by `write_transactions_csv` and `CsvReader`
+const CSV_HEADER: [&str; 4] = ["type", "client", "tx", "amount"];
+
+/// Bytes of CSV `CsvReader` encodes at a time
+const CSV_CHUNK: usize = 64 * 1024;
+
+/// Streams transactions as CSV (with header) through `Read`, encoding a chunk of rows
+/// whenever the previous one has been read. Feeding a generator through it means a
+/// benchmark's input never exists in memory as a whole, however many rows it has. Rows
+/// are encoded like `write_transactions_csv`, so a transaction with a timestamp, reason
+/// code or case reference fails the read.
+pub struct CsvReader<I> {
+    transactions: I,
+    header_written: bool,
+    chunk: Vec<u8>,
+    position: usize,
+    rows: usize,
+}
+
+impl<I: Iterator<Item = Transaction>> CsvReader<I> {
+    pub fn new(transactions: impl IntoIterator<IntoIter = I>) -> Self {
+        Self {
+            transactions: transactions.into_iter(),
+            header_written: false,
+            chunk: Vec::new(),
+            position: 0,
+            rows: 0,
+        }
+    }
+
+    /// Rows encoded so far, excluding the header
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Encode the next chunk, reusing the buffer of the previous one
+    fn fill(&mut self) -> std::io::Result<()> {
+        let mut chunk = std::mem::take(&mut self.chunk);
+        chunk.clear();
+        self.position = 0;
+
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(chunk);
+        if !self.header_written {
+            writer.write_record(CSV_HEADER)?;
+            self.header_written = true;
+        }
+        while writer.get_ref().len() < CSV_CHUNK {
+            let Some(tx) = self.transactions.next() else {
+                break;
+            };
+            writer.serialize(tx)?;
+            self.rows += 1;
+        }
+        self.chunk = writer.into_inner().map_err(|e| e.into_error())?;
+        Ok(())
+    }
+}
+
+impl<I: Iterator<Item = Transaction>> Read for CsvReader<I> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position == self.chunk.len() {
+            self.fill()?;
+        }
+        let n = (&self.chunk[self.position..]).read(buf)?;
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// Benchmark utilities for testing memory usage and performance. Benchmarks stream
+/// their input through a `CsvReader`, so generating and encoding the rows is part of
+/// the measured processing time.
 pub struct PaymentEngineBenchmark;
 
 impl PaymentEngineBenchmark {
@@ -14,176 +409,387 @@ impl PaymentEngineBenchmark {
         dispute_rate: f32,
         unique_accounts: usize,
     ) -> Vec<Transaction> {
-        let mut transactions = Vec::with_capacity(count);
+        Self::uniform_transactions(count, dispute_rate, unique_accounts).collect()
+    }
 
-        // Generate deposits and withdrawals
-        for i in 0..count {
-            let tx_id = i as u32 + 1;
-            let client_id = (i % unique_accounts) as u16 + 1;
-            let amount = Decimal::new((i % 10000) as i64 + 100, 2); // $1-$100
+    /// Lazily generate the transactions of `generate_transactions`: `count` deposits and
+    /// withdrawals spread evenly over `unique_accounts` clients, then disputes of the
+    /// first `dispute_rate` share of them
+    pub fn uniform_transactions(
+        count: usize,
+        dispute_rate: f32,
+        unique_accounts: usize,
+    ) -> impl Iterator<Item = Transaction> + Send + 'static {
+        let payments = (0..count).map(move |i| {
+            let tx_id = TxId::new(i as u32 + 1);
+            let client_id = ClientId::new((i % unique_accounts) as u16 + 1);
+            let amount = Amount::new((i % 10000) as i64 + 100, 2); // $1-$100
 
-            let tx_type = if i % 3 == 0 {
-                TransactionType::Withdrawal
+            if i % 3 == 0 {
+                Transaction::withdrawal(client_id, tx_id, amount)
             } else {
-                TransactionType::Deposit
-            };
-
-            transactions.push(Transaction {
-                tx_type,
-                client: client_id,
-                tx: tx_id,
-                amount: Some(amount),
-            });
-        }
+                Transaction::deposit(client_id, tx_id, amount)
+            }
+        });
 
         // Add disputes for a percentage of transactions
         let dispute_count = (count as f32 * dispute_rate) as usize;
-        for i in 0..dispute_count {
-            let disputed_tx_id = (i + 1) as u32;
-            let client_id = ((i % unique_accounts) as u16) + 1;
-
-            transactions.push(Transaction {
-                tx_type: TransactionType::Dispute,
-                client: client_id,
-                tx: disputed_tx_id,
-                amount: None,
-            });
-        }
+        let disputes = (0..dispute_count).map(move |i| {
+            let disputed_tx_id = TxId::new((i + 1) as u32);
+            let client_id = ClientId::new(((i % unique_accounts) as u16) + 1);
+            Transaction::dispute(client_id, disputed_tx_id)
+        });
 
-        transactions
+        payments.chain(disputes)
     }
 
-    /// Convert transactions to CSV format for streaming tests
-    pub fn transactions_to_csv(transactions: &[Transaction]) -> String {
-        let mut csv = String::from("type,client,tx,amount\n");
+    /// Generate a seeded, production-like workload of `count` deposits and withdrawals.
+    /// See `WorkloadGenerator` for the shape of the generated stream; use the generator
+    /// directly, e.g. through a `CsvReader`, when the workload is too large to hold in
+    /// memory.
+    pub fn generate_workload(
+        count: usize,
+        unique_accounts: usize,
+        config: &WorkloadConfig,
+    ) -> Vec<Transaction> {
+        WorkloadGenerator::new(count, unique_accounts, config).collect()
+    }
 
-        for tx in transactions {
-            let amount_str = match tx.amount {
-                Some(amount) => amount.to_string(),
-                None => String::new(),
-            };
+    /// Convert transactions to CSV format for streaming tests. Use a `CsvReader` when
+    /// the data set is too large to hold as a `String`.
+    pub fn transactions_to_csv(transactions: &[Transaction]) -> String {
+        let mut csv = Vec::new();
+        Self::write_transactions_csv(&mut csv, transactions.iter().cloned())
+            .expect("writing to a Vec cannot fail");
+        String::from_utf8(csv).expect("generated CSV is valid UTF-8")
+    }
 
-            let type_str = match tx.tx_type {
-                TransactionType::Deposit => "deposit",
-                TransactionType::Withdrawal => "withdrawal",
-                TransactionType::Dispute => "dispute",
-                TransactionType::Resolve => "resolve",
-                TransactionType::Chargeback => "chargeback",
-            };
+    /// Stream transactions as CSV (with header) into `writer`, one row at a time.
+    /// Returns the number of rows written, excluding the header. The header has no
+    /// optional columns, so a transaction with a timestamp, reason code or case
+    /// reference is an error.
+    pub fn write_transactions_csv<W, I>(writer: W, transactions: I) -> std::io::Result<usize>
+    where
+        W: Write,
+        I: IntoIterator<Item = Transaction>,
+    {
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(writer);
+        writer.write_record(CSV_HEADER)?;
 
-            csv.push_str(&format!(
-                "{},{},{},{}\n",
-                type_str, tx.client, tx.tx, amount_str
-            ));
+        let mut rows = 0;
+        for tx in transactions {
+            writer.serialize(tx)?;
+            rows += 1;
         }
 
-        csv
+        writer.flush()?;
+        Ok(rows)
     }
 
-    /// Benchmark standard PaymentsEngine
-    pub fn benchmark_standard_engine(
-        transaction_count: usize,
-        dispute_rate: f32,
-        unique_accounts: usize,
-    ) -> BenchmarkResult {
-        let transactions =
-            Self::generate_transactions(transaction_count, dispute_rate, unique_accounts);
-        let csv_data = Self::transactions_to_csv(&transactions);
+    /// Benchmark standard PaymentsEngine on `spec`'s uniform data; the engine limits
+    /// are not used
+    pub fn benchmark_standard_engine(spec: &BenchmarkSpec) -> BenchmarkResult {
+        let reader = CsvReader::new(spec.uniform_transactions());
 
-        let start_memory = Self::get_memory_usage();
+        let memory = MemoryProbe::start();
         let start_time = std::time::Instant::now();
 
         let mut engine = PaymentsEngine::new(EngineConfig::standard());
-        let cursor = Cursor::new(csv_data.as_bytes());
-        engine.process_transactions_from_reader(cursor).unwrap();
+        engine.process_transactions_from_reader(reader).unwrap();
 
         let end_time = std::time::Instant::now();
-        let end_memory = Self::get_memory_usage();
+        let (memory_used, heap) = memory.finish();
 
         BenchmarkResult {
             engine_type: "Standard".to_string(),
-            transaction_count,
-            dispute_rate,
+            transaction_count: spec.transaction_count,
+            dispute_rate: spec.dispute_rate,
             processing_time: end_time.duration_since(start_time),
-            memory_used: end_memory.saturating_sub(start_memory),
+            memory_used,
+            heap,
             account_count: engine.get_engine_info().account_count,
+            stream_results: Vec::new(),
+            operation_stats: Vec::new(),
         }
     }
 
-    /// Benchmark BoundedPaymentsEngine
-    pub fn benchmark_bounded_engine(
-        transaction_count: usize,
-        dispute_rate: f32,
-        unique_accounts: usize,
-        max_accounts: usize,
-        max_transactions: usize,
-        max_processed_ids: usize,
-    ) -> BenchmarkResult {
-        let transactions =
-            Self::generate_transactions(transaction_count, dispute_rate, unique_accounts);
-        let csv_data = Self::transactions_to_csv(&transactions);
+    /// Benchmark BoundedPaymentsEngine on `spec`'s uniform data
+    pub fn benchmark_bounded_engine(spec: &BenchmarkSpec) -> BenchmarkResult {
+        let reader = CsvReader::new(spec.uniform_transactions());
 
-        let start_memory = Self::get_memory_usage();
+        let memory = MemoryProbe::start();
         let start_time = std::time::Instant::now();
 
         let mut engine = PaymentsEngine::new(EngineConfig::bounded(
-            max_accounts,
-            max_transactions,
-            max_processed_ids,
+            spec.max_accounts,
+            spec.max_transactions,
+            spec.max_processed_ids,
         ));
-        let cursor = Cursor::new(csv_data.as_bytes());
-        engine.process_transactions_from_reader(cursor).unwrap();
+        engine.process_transactions_from_reader(reader).unwrap();
 
         let end_time = std::time::Instant::now();
-        let end_memory = Self::get_memory_usage();
+        let (memory_used, heap) = memory.finish();
 
         BenchmarkResult {
             engine_type: format!(
                 "Bounded({}/{}/{})",
-                max_accounts, max_transactions, max_processed_ids
+                spec.max_accounts, spec.max_transactions, spec.max_processed_ids
             ),
-            transaction_count,
-            dispute_rate,
+            transaction_count: spec.transaction_count,
+            dispute_rate: spec.dispute_rate,
             processing_time: end_time.duration_since(start_time),
-            memory_used: end_memory.saturating_sub(start_memory),
+            memory_used,
+            heap,
             account_count: engine.get_engine_info().account_count,
+            stream_results: Vec::new(),
+            operation_stats: Vec::new(),
         }
     }
 
-    /// Benchmark ConcurrentPaymentsEngine with multiple streams
-    pub fn benchmark_concurrent_engine(
-        transaction_count: usize,
-        dispute_rate: f32,
-        unique_accounts: usize,
-        stream_count: usize,
-        max_accounts: usize,
-        max_transactions: usize,
-        max_processed_ids: usize,
-    ) -> BenchmarkResult {
-        let transactions =
-            Self::generate_transactions(transaction_count, dispute_rate, unique_accounts);
-        let csv_data = Self::transactions_to_csv(&transactions);
+    /// Benchmark ConcurrentPaymentsEngine with multiple streams.
+    /// `spec`'s uniform data is split by client ID into `spec.streams` CSV streams before
+    /// timing starts, so every client's transactions stay ordered within one stream, and
+    /// the streams are fed through `process_concurrent_streams`. 0 or 1 streams use the
+    /// client-partitioned worker pool of `process_transactions_from_reader` instead.
+    pub fn benchmark_concurrent_engine(spec: &BenchmarkSpec) -> BenchmarkResult {
+        let mut engine = ConcurrentEngine::new(
+            spec.max_accounts,
+            spec.max_transactions,
+            spec.max_processed_ids,
+        );
+        let stream_count = spec.streams;
+        let generate = || spec.uniform_transactions();
+
+        if stream_count <= 1 {
+            let reader = CsvReader::new(generate());
+
+            let memory = MemoryProbe::start();
+            let start_time = std::time::Instant::now();
+            engine.process_transactions_from_reader(reader).unwrap();
+            let end_time = std::time::Instant::now();
+            let (memory_used, heap) = memory.finish();
 
-        let start_memory = Self::get_memory_usage();
+            return BenchmarkResult {
+                engine_type: "Concurrent(workers)".to_string(),
+                transaction_count: spec.transaction_count,
+                dispute_rate: spec.dispute_rate,
+                processing_time: end_time.duration_since(start_time),
+                memory_used,
+                heap,
+                account_count: engine.get_engine_info().account_count,
+                stream_results: Vec::new(),
+                operation_stats: Vec::new(),
+            };
+        }
+
+        // Split the data set by client once, before the clock starts
+        let mut streams = vec![Vec::new(); stream_count];
+        for tx in generate() {
+            streams[usize::from(tx.client.get()) % stream_count].push(tx);
+        }
+        let counts: Vec<usize> = streams.iter().map(Vec::len).collect();
+        let readers: Vec<_> = streams.into_iter().map(CsvReader::new).collect();
+
+        let memory = MemoryProbe::start();
         let start_time = std::time::Instant::now();
-        let cursor = Cursor::new(csv_data.as_bytes());
-        let mut engine = PaymentsEngine::new(EngineConfig::concurrent(
-            max_accounts,
-            max_transactions,
-            max_processed_ids,
-        ));
-        engine.process_transactions_from_reader(cursor).unwrap();
+
+        let stream_results: Vec<StreamResult> = engine
+            .process_concurrent_streams(readers)
+            .into_iter()
+            .zip(counts)
+            .map(|(result, transaction_count)| {
+                if let Some(failure) = result.failure {
+                    panic!("stream {} failed: {}", result.stream_id, failure);
+                }
+                StreamResult {
+                    stream_id: result.stream_id,
+                    transaction_count,
+                    processing_time: result.elapsed,
+                }
+            })
+            .collect();
 
         let end_time = std::time::Instant::now();
-        let end_memory = Self::get_memory_usage();
+        let (memory_used, heap) = memory.finish();
 
         BenchmarkResult {
             engine_type: format!("Concurrent({} streams)", stream_count),
-            transaction_count,
-            dispute_rate,
+            transaction_count: spec.transaction_count,
+            dispute_rate: spec.dispute_rate,
             processing_time: end_time.duration_since(start_time),
-            memory_used: end_memory.saturating_sub(start_memory),
+            memory_used,
+            heap,
             account_count: engine.get_engine_info().account_count,
+            stream_results,
+            operation_stats: Vec::new(),
+        }
+    }
+
+    /// Benchmark any engine configuration against a `WorkloadConfig`-shaped workload of
+    /// `spec`'s size. `spec`'s dispute rate and engine limits are not used.
+    pub fn benchmark_workload(
+        engine_config: EngineConfig,
+        spec: &BenchmarkSpec,
+        workload: &WorkloadConfig,
+    ) -> BenchmarkResult {
+        let reader = CsvReader::new(WorkloadGenerator::new(
+            spec.transaction_count,
+            spec.unique_accounts,
+            workload,
+        ));
+
+        let memory = MemoryProbe::start();
+        let start_time = std::time::Instant::now();
+
+        let mut engine = PaymentsEngine::new(engine_config);
+        engine.process_transactions_from_reader(reader).unwrap();
+
+        let end_time = std::time::Instant::now();
+        let (memory_used, heap) = memory.finish();
+        let info = engine.get_engine_info();
+
+        BenchmarkResult {
+            engine_type: format!("{} (zipf={})", info.engine_type, workload.zipf_exponent),
+            transaction_count: spec.transaction_count,
+            dispute_rate: workload.dispute_rate as f32,
+            processing_time: end_time.duration_since(start_time),
+            memory_used,
+            heap,
+            account_count: info.account_count,
+            stream_results: Vec::new(),
+            operation_stats: Vec::new(),
+        }
+    }
+
+    /// Benchmark any engine configuration against a `Scenario` of `spec`'s size,
+    /// applying its transactions one at a time to time each of them. Fills in
+    /// `operation_stats` with the latency of every transaction type the scenario produced.
+    /// `spec`'s dispute rate and engine limits are not used.
+    pub fn benchmark_scenario(
+        engine_config: EngineConfig,
+        spec: &BenchmarkSpec,
+        scenario: Scenario,
+        seed: u64,
+    ) -> BenchmarkResult {
+        let workload = scenario.workload(seed);
+        let generator =
+            WorkloadGenerator::new(spec.transaction_count, spec.unique_accounts, &workload);
+        let mut metrics: [EngineMetrics; TransactionType::ALL.len()] = Default::default();
+        let mut rejected = [0u64; TransactionType::ALL.len()];
+
+        let memory = MemoryProbe::start();
+        let start_time = std::time::Instant::now();
+
+        let mut engine = PaymentsEngine::new(engine_config);
+        for transaction in generator {
+            let index = transaction.tx_type.index();
+            let started = start_timer();
+            let result = engine.process_transaction(&transaction);
+            metrics[index].record_since(started);
+            if result.is_err() {
+                rejected[index] += 1;
+            }
+        }
+
+        let end_time = std::time::Instant::now();
+        let (memory_used, heap) = memory.finish();
+        let info = engine.get_engine_info();
+
+        let operation_stats = TransactionType::ALL
+            .into_iter()
+            .zip(metrics.iter().zip(rejected))
+            .filter(|(_, (metrics, _))| metrics.stats().transactions > 0)
+            .map(|(tx_type, (metrics, rejected))| OperationStats {
+                tx_type,
+                rejected,
+                latency: metrics.stats(),
+            })
+            .collect();
+
+        BenchmarkResult {
+            engine_type: format!("{} [{}]", info.engine_type, scenario.name()),
+            transaction_count: spec.transaction_count,
+            dispute_rate: workload.dispute_rate as f32,
+            processing_time: end_time.duration_since(start_time),
+            memory_used,
+            heap,
+            account_count: info.account_count,
+            stream_results: Vec::new(),
+            operation_stats,
+        }
+    }
+
+    /// Run `engine_config` for `config.duration`, feeding it `config.workload` over
+    /// `unique_accounts` clients at `config.rate`, and snapshot its memory and throughput
+    /// every `config.snapshot_interval` and once more at the end. `on_snapshot` sees each
+    /// snapshot as it is taken, e.g. to report progress of a long run. Rejected
+    /// transactions are expected (e.g. withdrawals from locked accounts) and count as
+    /// processed.
+    pub fn soak(
+        engine_config: EngineConfig,
+        unique_accounts: usize,
+        config: &SoakConfig,
+        mut on_snapshot: impl FnMut(&SoakSnapshot),
+    ) -> SoakResult {
+        // Transaction IDs wrap around after u32::MAX rows; repeats are rejected as duplicates
+        let mut generator =
+            WorkloadGenerator::new(u32::MAX as usize, unique_accounts, &config.workload);
+        let mut engine = PaymentsEngine::new(engine_config);
+        let mut snapshots: Vec<SoakSnapshot> = Vec::new();
+
+        let started = std::time::Instant::now();
+        let mut next_snapshot = started + config.snapshot_interval;
+        let mut processed = 0u64;
+        let mut take_snapshot = |engine: &PaymentsEngine, processed: u64| {
+            let elapsed = started.elapsed();
+            let (since, before) = snapshots.last().map_or((Duration::ZERO, 0), |last| {
+                (last.elapsed, last.transactions)
+            });
+            let snapshot = SoakSnapshot {
+                elapsed,
+                transactions: processed,
+                throughput: (processed - before) as f64 / (elapsed - since).as_secs_f64(),
+                memory_used: Self::get_memory_usage(),
+                #[cfg(feature = "bench-alloc")]
+                heap: Some(crate::heap::allocated()),
+                #[cfg(not(feature = "bench-alloc"))]
+                heap: None,
+                account_count: engine.get_engine_info().account_count,
+            };
+            on_snapshot(&snapshot);
+            snapshots.push(snapshot);
+        };
+
+        loop {
+            let now = std::time::Instant::now();
+            if now.duration_since(started) >= config.duration {
+                break;
+            }
+            if now >= next_snapshot {
+                take_snapshot(&engine, processed);
+                next_snapshot += config.snapshot_interval;
+            }
+            if config.rate > 0 {
+                let due = started + Duration::from_secs_f64(processed as f64 / config.rate as f64);
+                if let Some(wait) = due.checked_duration_since(now) {
+                    std::thread::sleep(wait.min(next_snapshot.saturating_duration_since(now)));
+                    continue;
+                }
+            }
+            let Some(transaction) = generator.next() else {
+                break;
+            };
+            let _ = engine.process_transaction(&transaction);
+            processed += 1;
+        }
+        take_snapshot(&engine, processed);
+
+        SoakResult {
+            engine_type: engine.get_engine_info().engine_type,
+            warmup: config.warmup,
+            snapshots,
         }
     }
 
@@ -197,29 +803,322 @@ impl PaymentEngineBenchmark {
     }
 }
 
+/// How `PaymentEngineBenchmark::soak` runs
+#[derive(Debug, Clone)]
+pub struct SoakConfig {
+    /// How long to keep generating transactions
+    pub duration: Duration,
+    /// Transactions per second to generate; 0 generates them as fast as the engine
+    /// takes them
+    pub rate: u64,
+    /// Time between snapshots
+    pub snapshot_interval: Duration,
+    /// Time for caches and bounded stores to fill up. Snapshots taken earlier are kept,
+    /// but memory growth is measured from the first snapshot after it.
+    pub warmup: Duration,
+    pub workload: WorkloadConfig,
+}
+
+impl Default for SoakConfig {
+    fn default() -> Self {
+        Self {
+            duration: Duration::from_secs(600),
+            rate: 10_000,
+            snapshot_interval: Duration::from_secs(10),
+            warmup: Duration::from_secs(60),
+            workload: WorkloadConfig::default(),
+        }
+    }
+}
+
+/// Memory and throughput of a soak test at one point in time
+#[derive(Debug, Clone, PartialEq)]
+pub struct SoakSnapshot {
+    /// Time since the soak test started
+    pub elapsed: Duration,
+    /// Transactions processed so far
+    pub transactions: u64,
+    /// Transactions per second since the previous snapshot
+    pub throughput: f64,
+    /// Resident set size of the process
+    pub memory_used: usize,
+    /// Bytes allocated on the heap, only counted with the `bench-alloc` feature
+    pub heap: Option<usize>,
+    pub account_count: usize,
+}
+
+impl SoakSnapshot {
+    /// Heap usage when it is counted, resident set size otherwise
+    pub fn memory(&self) -> usize {
+        self.heap.unwrap_or(self.memory_used)
+    }
+}
+
+impl std::fmt::Display for SoakSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{:>6}s] {} tx, {:.0} tx/sec, {} bytes resident",
+            self.elapsed.as_secs(),
+            self.transactions,
+            self.throughput,
+            self.memory_used
+        )?;
+        if let Some(heap) = self.heap {
+            write!(f, ", {} bytes heap", heap)?;
+        }
+        write!(f, ", {} accounts", self.account_count)
+    }
+}
+
+/// Snapshots of a soak test, oldest first
+#[derive(Debug, Clone)]
+pub struct SoakResult {
+    pub engine_type: String,
+    pub warmup: Duration,
+    pub snapshots: Vec<SoakSnapshot>,
+}
+
+impl SoakResult {
+    /// The first snapshot after the warm-up and the last one, when they differ
+    fn settled(&self) -> Option<(&SoakSnapshot, &SoakSnapshot)> {
+        let mut settled = self
+            .snapshots
+            .iter()
+            .filter(|snapshot| snapshot.elapsed >= self.warmup);
+        Some((settled.next()?, settled.next_back()?))
+    }
+
+    /// Growth of `SoakSnapshot::memory` from the first snapshot after the warm-up to the
+    /// last, in percent. `None` when the run ended before two snapshots followed the
+    /// warm-up.
+    pub fn memory_growth(&self) -> Option<f64> {
+        let (first, last) = self.settled()?;
+        Some((last.memory() as f64 - first.memory() as f64) / first.memory().max(1) as f64 * 100.0)
+    }
+
+    /// Print the totals and the memory growth to the console stream of the `output`
+    /// router
+    pub fn print_summary(&self) {
+        let mut console = crate::output::router().console();
+        let _ = write!(console, "{}", self);
+    }
+}
+
+impl std::fmt::Display for SoakResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "=== Soak Test Results: {} ===", self.engine_type)?;
+        if let Some(last) = self.snapshots.last() {
+            writeln!(f, "Duration: {:?}", last.elapsed)?;
+            writeln!(f, "Transactions processed: {}", last.transactions)?;
+            writeln!(
+                f,
+                "Throughput: {:.0} tx/sec",
+                last.transactions as f64 / last.elapsed.as_secs_f64()
+            )?;
+        }
+        writeln!(f, "Snapshots: {}", self.snapshots.len())?;
+        match self.settled() {
+            Some((first, last)) => {
+                writeln!(
+                    f,
+                    "Memory after warm-up: {} -> {} bytes ({:+.1}%)",
+                    first.memory(),
+                    last.memory(),
+                    self.memory_growth().unwrap_or_default()
+                )?;
+            }
+            None => writeln!(f, "Memory after warm-up: too few snapshots")?,
+        }
+        writeln!(f)
+    }
+}
+
+/// RSS and, with `bench-alloc`, heap readings taken when a benchmark run starts
+struct MemoryProbe {
+    rss: usize,
+    #[cfg(feature = "bench-alloc")]
+    heap: usize,
+}
+
+impl MemoryProbe {
+    fn start() -> Self {
+        let rss = PaymentEngineBenchmark::get_memory_usage();
+        #[cfg(feature = "bench-alloc")]
+        crate::heap::reset_peak();
+        Self {
+            rss,
+            #[cfg(feature = "bench-alloc")]
+            heap: crate::heap::allocated(),
+        }
+    }
+
+    /// RSS growth since `start`, and heap usage when it is counted
+    fn finish(&self) -> (usize, Option<HeapUsage>) {
+        let rss = PaymentEngineBenchmark::get_memory_usage().saturating_sub(self.rss);
+        #[cfg(feature = "bench-alloc")]
+        let heap = Some(HeapUsage {
+            peak: crate::heap::peak().saturating_sub(self.heap),
+            net: crate::heap::allocated() as isize - self.heap as isize,
+        });
+        #[cfg(not(feature = "bench-alloc"))]
+        let heap = None;
+        (rss, heap)
+    }
+}
+
+/// Heap usage of a benchmark run, counted by the `bench-alloc` allocator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeapUsage {
+    /// Most bytes allocated at once during the run, above what was allocated before it
+    pub peak: usize,
+    /// Bytes allocated at the end of the run minus those allocated before it; negative
+    /// when the run freed more than it kept
+    pub net: isize,
+}
+
 #[derive(Debug)]
 pub struct BenchmarkResult {
     pub engine_type: String,
     pub transaction_count: usize,
     pub dispute_rate: f32,
     pub processing_time: std::time::Duration,
+    /// RSS growth over the run
     pub memory_used: usize,
+    /// Heap usage over the run, only counted with the `bench-alloc` feature
+    pub heap: Option<HeapUsage>,
     pub account_count: usize,
+    /// Per-stream timings, only populated by multi-stream concurrent benchmarks
+    pub stream_results: Vec<StreamResult>,
+    /// Latency per transaction type, only populated by scenario benchmarks
+    pub operation_stats: Vec<OperationStats>,
+}
+
+/// Latency of one transaction type in a scenario benchmark
+#[derive(Debug)]
+pub struct OperationStats {
+    pub tx_type: TransactionType,
+    /// Transactions of this type the engine rejected; their latency is included
+    pub rejected: u64,
+    pub latency: EngineStats,
+}
+
+/// The figures of a `BenchmarkResult` worth storing, e.g. as the baseline a later run
+/// has to keep up with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchmarkReport {
+    pub engine_type: String,
+    pub transaction_count: usize,
+    pub dispute_rate: f32,
+    pub processing_time_ms: f64,
+    /// Transactions processed per second
+    pub throughput: f64,
+    pub memory_used: usize,
+    #[serde(default)]
+    pub heap: Option<HeapUsage>,
+}
+
+impl BenchmarkReport {
+    /// How far this run's throughput fell below `baseline`'s, in percent of the
+    /// baseline; negative when this run was faster
+    pub fn regression_from(&self, baseline: &BenchmarkReport) -> f64 {
+        if baseline.throughput <= 0.0 {
+            return 0.0;
+        }
+        (baseline.throughput - self.throughput) / baseline.throughput * 100.0
+    }
+
+    /// Read a report written by `save`
+    #[cfg(feature = "fs")]
+    pub fn load(path: &std::path::Path) -> Result<Self, PaymentsError> {
+        let file = std::fs::File::open(path)?;
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .map_err(|e| PaymentsError::ConfigError(format!("invalid benchmark report: {}", e)))
+    }
+
+    /// Write the report as pretty-printed JSON
+    #[cfg(feature = "fs")]
+    pub fn save(&self, path: &std::path::Path) -> Result<(), PaymentsError> {
+        let mut file = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(&mut file, self)
+            .map_err(|e| PaymentsError::IoError(e.into()))?;
+        writeln!(file)?;
+        Ok(())
+    }
+}
+
+/// Timing for a single stream in a multi-stream benchmark
+#[derive(Debug)]
+pub struct StreamResult {
+    pub stream_id: u64,
+    pub transaction_count: usize,
+    /// Time from the start of the benchmark until this stream finished
+    pub processing_time: std::time::Duration,
 }
 
 impl BenchmarkResult {
+    /// Transactions processed per second
+    pub fn throughput(&self) -> f64 {
+        self.transaction_count as f64 / self.processing_time.as_secs_f64()
+    }
+
+    /// The figures to keep for comparing later runs against this one
+    pub fn report(&self) -> BenchmarkReport {
+        BenchmarkReport {
+            engine_type: self.engine_type.clone(),
+            transaction_count: self.transaction_count,
+            dispute_rate: self.dispute_rate,
+            processing_time_ms: self.processing_time.as_secs_f64() * 1e3,
+            throughput: self.throughput(),
+            memory_used: self.memory_used,
+            heap: self.heap,
+        }
+    }
+
+    /// Print the results to the console stream of the `output` router
     pub fn print_summary(&self) {
-        println!("=== Benchmark Results: {} ===", self.engine_type);
-        println!("Transactions processed: {}", self.transaction_count);
-        println!("Dispute rate: {:.1}%", self.dispute_rate * 100.0);
-        println!("Processing time: {:?}", self.processing_time);
-        println!("Memory used: {} bytes", self.memory_used);
-        println!("Final account count: {}", self.account_count);
-        println!(
-            "Throughput: {:.0} tx/sec",
-            self.transaction_count as f64 / self.processing_time.as_secs_f64()
-        );
-        println!();
+        let mut console = crate::output::router().console();
+        let _ = write!(console, "{}", self);
+    }
+}
+
+impl std::fmt::Display for BenchmarkResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "=== Benchmark Results: {} ===", self.engine_type)?;
+        writeln!(f, "Transactions processed: {}", self.transaction_count)?;
+        writeln!(f, "Dispute rate: {:.1}%", self.dispute_rate * 100.0)?;
+        writeln!(f, "Processing time: {:?}", self.processing_time)?;
+        writeln!(f, "Memory used: {} bytes", self.memory_used)?;
+        if let Some(heap) = &self.heap {
+            writeln!(f, "Heap peak: {} bytes", heap.peak)?;
+            writeln!(f, "Heap net: {} bytes", heap.net)?;
+        }
+        writeln!(f, "Final account count: {}", self.account_count)?;
+        writeln!(f, "Throughput: {:.0} tx/sec", self.throughput())?;
+        for stream in &self.stream_results {
+            writeln!(
+                f,
+                "  Stream {}: {} tx in {:?} ({:.0} tx/sec)",
+                stream.stream_id,
+                stream.transaction_count,
+                stream.processing_time,
+                stream.transaction_count as f64 / stream.processing_time.as_secs_f64()
+            )?;
+        }
+        for op in &self.operation_stats {
+            writeln!(
+                f,
+                "  {}: {} tx ({} rejected), p50 {:?}, p95 {:?}, p99 {:?}, max {:?}",
+                op.tx_type.as_str(),
+                op.latency.transactions,
+                op.rejected,
+                op.latency.p50,
+                op.latency.p95,
+                op.latency.p99,
+                op.latency.max
+            )?;
+        }
+        writeln!(f)
     }
 }
 
@@ -227,21 +1126,201 @@ impl BenchmarkResult {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_workload_is_reproducible() {
+        let config = WorkloadConfig {
+            zipf_exponent: 1.0,
+            ..WorkloadConfig::default()
+        };
+        let first = PaymentEngineBenchmark::generate_workload(1_000, 100, &config);
+        let second = PaymentEngineBenchmark::generate_workload(1_000, 100, &config);
+
+        assert_eq!(
+            PaymentEngineBenchmark::transactions_to_csv(&first),
+            PaymentEngineBenchmark::transactions_to_csv(&second)
+        );
+    }
+
+    #[test]
+    fn test_transactions_to_csv() {
+        let transactions = [
+            Transaction::deposit(1, 1, Amount::new(1050, 2)),
+            Transaction::dispute(1, 1),
+        ];
+        assert_eq!(
+            PaymentEngineBenchmark::transactions_to_csv(&transactions),
+            "type,client,tx,amount\ndeposit,1,1,10.50\ndispute,1,1,\n"
+        );
+
+        // Streaming the CSV in small reads gives the same bytes
+        let workload =
+            PaymentEngineBenchmark::generate_workload(5_000, 100, &WorkloadConfig::default());
+        let mut reader = CsvReader::new(workload.iter().cloned());
+        let mut streamed = Vec::new();
+        let mut buf = [0u8; 100];
+        loop {
+            let n = reader.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            streamed.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(reader.rows(), workload.len());
+        assert_eq!(
+            String::from_utf8(streamed).unwrap(),
+            PaymentEngineBenchmark::transactions_to_csv(&workload)
+        );
+
+        let mut out = Vec::new();
+        assert!(
+            PaymentEngineBenchmark::write_transactions_csv(
+                &mut out,
+                [Transaction::resolve(1, 1).at(1000)]
+            )
+            .is_err()
+        );
+        let mut reader = CsvReader::new([Transaction::resolve(1, 1).at(1000)]);
+        assert!(reader.read_to_end(&mut out).is_err());
+    }
+
+    #[test]
+    fn test_workload_skew() {
+        let config = WorkloadConfig {
+            zipf_exponent: 1.2,
+            dispute_rate: 0.1,
+            resolve_rate: 0.5,
+            chargeback_rate: 0.5,
+            ..WorkloadConfig::default()
+        };
+        let transactions = PaymentEngineBenchmark::generate_workload(10_000, 1_000, &config);
+
+        let mut counts = vec![0usize; 1_001];
+        for tx in &transactions {
+            counts[usize::from(tx.client.get())] += 1;
+        }
+        assert!(counts[1] > counts[500] * 10);
+
+        let disputes = transactions
+            .iter()
+            .filter(|tx| matches!(tx.tx_type, TransactionType::Dispute))
+            .count();
+        let closed = transactions
+            .iter()
+            .filter(|tx| {
+                matches!(
+                    tx.tx_type,
+                    TransactionType::Resolve | TransactionType::Chargeback
+                )
+            })
+            .count();
+        assert!(disputes > 0);
+        assert_eq!(disputes, closed);
+    }
+
+    #[test]
+    fn test_scenarios_reach_their_paths() {
+        let spec = BenchmarkSpec {
+            transaction_count: 5_000,
+            unique_accounts: 100,
+            ..BenchmarkSpec::default()
+        };
+        let result = PaymentEngineBenchmark::benchmark_scenario(
+            EngineConfig::standard(),
+            &spec,
+            "chargeback-wave".parse().unwrap(),
+            7,
+        );
+        let chargebacks = result
+            .operation_stats
+            .iter()
+            .find(|op| op.tx_type == TransactionType::Chargeback)
+            .unwrap();
+        assert!(chargebacks.latency.transactions > chargebacks.rejected);
+
+        let result = PaymentEngineBenchmark::benchmark_scenario(
+            EngineConfig::standard(),
+            &spec,
+            Scenario::HotAccount,
+            7,
+        );
+        assert!(result.account_count <= 100);
+        let total: u64 = result
+            .operation_stats
+            .iter()
+            .map(|op| op.latency.transactions)
+            .sum();
+        assert!(total >= 5_000);
+    }
+
+    #[test]
+    fn test_regression_from_baseline() {
+        let result = PaymentEngineBenchmark::benchmark_standard_engine(&BenchmarkSpec {
+            transaction_count: 1_000,
+            ..BenchmarkSpec::default()
+        });
+        // Round figures, which survive the JSON round trip exactly
+        let current = BenchmarkReport {
+            processing_time_ms: 10.0,
+            throughput: 100_000.0,
+            ..result.report()
+        };
+        let baseline = BenchmarkReport {
+            throughput: 200_000.0,
+            ..current.clone()
+        };
+        assert!((current.regression_from(&baseline) - 50.0).abs() < 1e-9);
+        assert!(baseline.regression_from(&current) < 0.0);
+
+        #[cfg(feature = "fs")]
+        {
+            let path = std::env::temp_dir().join(format!("baseline-{}.json", std::process::id()));
+            baseline.save(&path).unwrap();
+            assert_eq!(BenchmarkReport::load(&path).unwrap(), baseline);
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_soak_snapshots() {
+        let config = SoakConfig {
+            duration: Duration::from_millis(300),
+            rate: 0,
+            snapshot_interval: Duration::from_millis(50),
+            warmup: Duration::from_millis(100),
+            workload: WorkloadConfig::default(),
+        };
+        let mut seen = 0;
+        let result = PaymentEngineBenchmark::soak(
+            EngineConfig::bounded(100, 1_000, 10_000),
+            1_000,
+            &config,
+            |_| seen += 1,
+        );
+
+        assert_eq!(seen, result.snapshots.len());
+        assert!(result.snapshots.len() >= 3);
+        assert!(result.memory_growth().is_some());
+        let last = result.snapshots.last().unwrap();
+        assert!(last.transactions > 0);
+        assert!(last.account_count <= 100);
+    }
+
     #[test]
     fn test_memory_comparison() {
         const TX_COUNT: usize = 10_000;
         const DISPUTE_RATE: f32 = 0.05; // 5%
 
-        let standard_result =
-            PaymentEngineBenchmark::benchmark_standard_engine(TX_COUNT, DISPUTE_RATE, 1000);
-        let bounded_result = PaymentEngineBenchmark::benchmark_bounded_engine(
-            TX_COUNT,
-            DISPUTE_RATE,
-            1000,
-            1000,
-            1000,
-            10_000,
-        );
+        let spec = BenchmarkSpec {
+            transaction_count: TX_COUNT,
+            dispute_rate: DISPUTE_RATE,
+            unique_accounts: 1000,
+            max_accounts: 1000,
+            max_transactions: 1000,
+            max_processed_ids: 10_000,
+            ..BenchmarkSpec::default()
+        };
+        let standard_result = PaymentEngineBenchmark::benchmark_standard_engine(&spec);
+        let bounded_result = PaymentEngineBenchmark::benchmark_bounded_engine(&spec);
 
         standard_result.print_summary();
         bounded_result.print_summary();
@@ -249,6 +1328,11 @@ mod tests {
         // Bounded engine should use less memory (or at least not significantly more)
         // Note: Memory measurement is placeholder, in real tests this would show the difference
         assert!(bounded_result.account_count <= 1000); // Respects account limit
+        // Heap usage is only measured with the counting allocator
+        assert_eq!(
+            standard_result.heap.is_some(),
+            cfg!(feature = "bench-alloc")
+        );
     }
 
     #[test]
@@ -257,20 +1341,28 @@ mod tests {
         const DISPUTE_RATE: f32 = 0.02;
         const STREAM_COUNT: usize = 4;
 
-        let concurrent_result = PaymentEngineBenchmark::benchmark_concurrent_engine(
-            TX_COUNT,
-            DISPUTE_RATE,
-            500,
-            STREAM_COUNT,
-            500,
-            500,
-            5_000,
-        );
+        let concurrent_result =
+            PaymentEngineBenchmark::benchmark_concurrent_engine(&BenchmarkSpec {
+                transaction_count: TX_COUNT,
+                dispute_rate: DISPUTE_RATE,
+                unique_accounts: 500,
+                max_accounts: 500,
+                max_transactions: 500,
+                max_processed_ids: 5_000,
+                streams: STREAM_COUNT,
+            });
 
         concurrent_result.print_summary();
 
         assert!(concurrent_result.account_count > 0);
         assert!(concurrent_result.processing_time.as_millis() < 5000); // Should complete quickly
+        assert_eq!(concurrent_result.stream_results.len(), STREAM_COUNT);
+        let streamed: usize = concurrent_result
+            .stream_results
+            .iter()
+            .map(|stream| stream.transaction_count)
+            .sum();
+        assert!(streamed >= TX_COUNT);
     }
 
     #[test]
@@ -278,14 +1370,15 @@ mod tests {
         const TX_COUNT: usize = 100_000;
         const DISPUTE_RATE: f32 = 0.01; // 1%
 
-        let bounded_result = PaymentEngineBenchmark::benchmark_bounded_engine(
-            TX_COUNT,
-            DISPUTE_RATE,
-            1000,
-            1000,
-            2000,
-            50_000,
-        );
+        let bounded_result = PaymentEngineBenchmark::benchmark_bounded_engine(&BenchmarkSpec {
+            transaction_count: TX_COUNT,
+            dispute_rate: DISPUTE_RATE,
+            unique_accounts: 1000,
+            max_accounts: 1000,
+            max_transactions: 2000,
+            max_processed_ids: 50_000,
+            ..BenchmarkSpec::default()
+        });
 
         bounded_result.print_summary();
 
@@ -1,5 +1,10 @@
 use clap::Parser;
-use payment_engine::PaymentEngineBenchmark;
+use payment_engine::benchmark::{
+    BenchmarkReport, BenchmarkSpec, Scenario, SoakConfig, WorkloadConfig,
+};
+use payment_engine::{EngineConfig, PaymentEngineBenchmark};
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Run payment engine benchmarks", long_about = None)]
@@ -12,10 +17,14 @@ struct BenchArgs {
     #[arg(short = 'd', long, default_value_t = 5.0)]
     dispute_rate_percent: f32,
 
-    /// Engine to benchmark: standard | bounded | concurrent
+    /// Engine to benchmark: standard | bounded | concurrent | actor
     #[arg(short, long, default_value = "standard")]
     engine: String,
 
+    /// Number of distinct clients in the generated data
+    #[arg(long, default_value_t = 1000)]
+    accounts: usize,
+
     /// Max accounts (for bounded/concurrent)
     #[arg(long, default_value_t = 1000)]
     max_accounts: usize,
@@ -31,50 +40,236 @@ struct BenchArgs {
     /// Number of streams (for concurrent)
     #[arg(long, default_value_t = 4)]
     streams: usize,
+
+    /// Run a named scenario instead: dispute-storm | chargeback-wave | hot-account.
+    /// Reports latency per transaction type; uses --seed, --accounts and the --engine limits
+    #[arg(long)]
+    scenario: Option<Scenario>,
+
+    /// Use the seeded, skewed workload generator instead of the uniform one
+    #[arg(long)]
+    skewed: bool,
+
+    /// Zipf exponent for client selection (skewed workload only, 0 = uniform)
+    #[arg(long, default_value_t = 1.0)]
+    zipf_exponent: f64,
+
+    /// Probability that a transaction is a withdrawal (skewed workload only)
+    #[arg(long, default_value_t = 0.33)]
+    withdrawal_ratio: f64,
+
+    /// Fraction of clients treated as hot accounts (skewed workload only)
+    #[arg(long, default_value_t = 0.0)]
+    hot_account_fraction: f64,
+
+    /// Share of traffic sent to hot accounts (skewed workload only)
+    #[arg(long, default_value_t = 0.0)]
+    hot_traffic_fraction: f64,
+
+    /// Probability that a dispute is resolved (skewed workload only)
+    #[arg(long, default_value_t = 0.5)]
+    resolve_rate: f64,
+
+    /// Probability that a dispute is charged back (skewed workload only)
+    #[arg(long, default_value_t = 0.1)]
+    chargeback_rate: f64,
+
+    /// RNG seed (skewed workload only)
+    #[arg(long, default_value_t = 42)]
+    seed: u64,
+
+    /// Save the run's report as JSON, e.g. to use as a later run's --baseline
+    #[arg(long, value_name = "PATH")]
+    report: Option<PathBuf>,
+
+    /// Compare throughput with a report saved by --report and exit with 1 if it regressed
+    /// by more than --max-regression
+    #[arg(long, value_name = "PATH")]
+    baseline: Option<PathBuf>,
+
+    /// Throughput loss against --baseline that fails the run, in percent
+    #[arg(long, value_name = "PERCENT", default_value_t = 10.0)]
+    max_regression: f64,
+
+    /// Soak test: run --engine for this many seconds at --rate, generating the --scenario
+    /// or default workload, and snapshot memory and throughput as it goes
+    #[arg(long, value_name = "SECONDS")]
+    soak_secs: Option<u64>,
+
+    /// Transactions per second to generate in a soak test; 0 runs unpaced
+    #[arg(long, default_value_t = 10_000)]
+    rate: u64,
+
+    /// Seconds between soak test snapshots
+    #[arg(long, default_value_t = 10)]
+    snapshot_interval_secs: u64,
+
+    /// Seconds a soak test runs before memory growth is measured
+    #[arg(long, default_value_t = 60)]
+    warmup_secs: u64,
+
+    /// Memory growth after the warm-up that fails a bounded engine's soak test, in percent
+    #[arg(long, value_name = "PERCENT", default_value_t = 10.0)]
+    max_memory_growth: f64,
 }
 
-fn main() {
-    let args = BenchArgs::parse();
-    let dispute_rate = args.dispute_rate_percent / 100.0;
-
-    match args.engine.as_str() {
-        "standard" => {
-            let result = PaymentEngineBenchmark::benchmark_standard_engine(
-                args.transactions,
-                dispute_rate,
-                args.max_accounts,
-            );
-            result.print_summary();
+impl BenchArgs {
+    fn spec(&self) -> BenchmarkSpec {
+        BenchmarkSpec {
+            transaction_count: self.transactions,
+            dispute_rate: self.dispute_rate_percent / 100.0,
+            unique_accounts: self.accounts,
+            max_accounts: self.max_accounts,
+            max_transactions: self.max_transactions,
+            max_processed_ids: self.max_tx_ids,
+            streams: self.streams,
         }
-        "bounded" => {
-            let result = PaymentEngineBenchmark::benchmark_bounded_engine(
-                args.transactions,
-                dispute_rate,
-                args.max_accounts,
-                args.max_transactions,
-                args.max_tx_ids,
-                args.max_accounts,
+    }
+
+    /// The `--engine` configuration with the engine limits
+    fn engine_config(&self) -> EngineConfig {
+        EngineConfig::from_cli_params(
+            Some(&self.engine),
+            Some(self.max_accounts),
+            Some(self.max_transactions),
+            Some(self.max_tx_ids),
+            None,
+        )
+    }
+}
+
+/// Run a soak test and exit with 1 if a bounded engine's memory did not stay flat
+fn soak(args: &BenchArgs, spec: &BenchmarkSpec, duration: Duration) {
+    let workload = match args.scenario {
+        Some(scenario) => scenario.workload(args.seed),
+        None => WorkloadConfig {
+            dispute_rate: spec.dispute_rate as f64,
+            seed: args.seed,
+            ..WorkloadConfig::default()
+        },
+    };
+    let config = SoakConfig {
+        duration,
+        rate: args.rate,
+        snapshot_interval: Duration::from_secs(args.snapshot_interval_secs.max(1)),
+        warmup: Duration::from_secs(args.warmup_secs),
+        workload,
+    };
+    let result = PaymentEngineBenchmark::soak(
+        args.engine_config(),
+        spec.unique_accounts,
+        &config,
+        |snapshot| eprintln!("{}", snapshot),
+    );
+    result.print_summary();
+
+    if args.engine != "bounded" {
+        return;
+    }
+    match result.memory_growth() {
+        Some(growth) if growth > args.max_memory_growth => {
+            eprintln!(
+                "Memory grew by {:.1}% after the warm-up, more than the allowed {}%",
+                growth, args.max_memory_growth
             );
-            result.print_summary();
+            std::process::exit(1);
         }
-        "concurrent" => {
-            let result = PaymentEngineBenchmark::benchmark_concurrent_engine(
-                args.transactions,
-                dispute_rate,
-                args.streams,
-                args.max_accounts,
-                args.max_transactions,
-                args.max_tx_ids,
-                args.max_accounts,
+        Some(_) => {}
+        None => eprintln!(
+            "Warning: too few snapshots after the warm-up to check memory; run longer or lower --warmup-secs"
+        ),
+    }
+}
+
+fn main() {
+    let args = BenchArgs::parse();
+    let spec = args.spec();
+
+    // Read the baseline first, so a bad path fails before the run
+    let baseline = args.baseline.as_ref().map(|path| {
+        BenchmarkReport::load(path).unwrap_or_else(|e| {
+            eprintln!("Failed to read baseline {:?}: {}", path, e);
+            std::process::exit(2);
+        })
+    });
+
+    if let Some(soak_secs) = args.soak_secs {
+        soak(&args, &spec, Duration::from_secs(soak_secs));
+        return;
+    }
+
+    let result = if let Some(scenario) = args.scenario {
+        PaymentEngineBenchmark::benchmark_scenario(args.engine_config(), &spec, scenario, args.seed)
+    } else if args.skewed {
+        let workload = WorkloadConfig {
+            zipf_exponent: args.zipf_exponent,
+            withdrawal_ratio: args.withdrawal_ratio,
+            hot_account_fraction: args.hot_account_fraction,
+            hot_traffic_fraction: args.hot_traffic_fraction,
+            dispute_rate: spec.dispute_rate as f64,
+            resolve_rate: args.resolve_rate,
+            chargeback_rate: args.chargeback_rate,
+            seed: args.seed,
+        };
+        PaymentEngineBenchmark::benchmark_workload(args.engine_config(), &spec, &workload)
+    } else {
+        match args.engine.as_str() {
+            "standard" => PaymentEngineBenchmark::benchmark_standard_engine(&spec),
+            "bounded" => PaymentEngineBenchmark::benchmark_bounded_engine(&spec),
+            "concurrent" => PaymentEngineBenchmark::benchmark_concurrent_engine(&spec),
+            "actor" => {
+                let workload = WorkloadConfig {
+                    dispute_rate: spec.dispute_rate as f64,
+                    ..WorkloadConfig::default()
+                };
+                PaymentEngineBenchmark::benchmark_workload(
+                    EngineConfig::from_cli_params(Some("actor"), None, None, None, None),
+                    &spec,
+                    &workload,
+                )
+            }
+            other => {
+                eprintln!(
+                    "Unknown engine: {} (use standard|bounded|concurrent|actor)",
+                    other
+                );
+                std::process::exit(2);
+            }
+        }
+    };
+    result.print_summary();
+
+    let report = result.report();
+    if let Some(path) = &args.report
+        && let Err(e) = report.save(path)
+    {
+        eprintln!("Failed to write report {:?}: {}", path, e);
+        std::process::exit(2);
+    }
+
+    if let Some(baseline) = baseline {
+        if baseline.engine_type != report.engine_type
+            || baseline.transaction_count != report.transaction_count
+        {
+            eprintln!(
+                "Warning: baseline ran {} with {} transactions, this run {} with {}",
+                baseline.engine_type,
+                baseline.transaction_count,
+                report.engine_type,
+                report.transaction_count
             );
-            result.print_summary();
         }
-        other => {
+        let regression = report.regression_from(&baseline);
+        eprintln!(
+            "Throughput {:.0} tx/sec against baseline {:.0} tx/sec ({:+.1}%)",
+            report.throughput, baseline.throughput, -regression
+        );
+        if regression > args.max_regression {
             eprintln!(
-                "Unknown engine: {} (use standard|bounded|concurrent)",
-                other
+                "Throughput regressed by {:.1}%, more than the allowed {}%",
+                regression, args.max_regression
             );
-            std::process::exit(2);
+            std::process::exit(1);
         }
     }
 }
@@ -0,0 +1,95 @@
+use clap::Parser;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use payment_engine::PaymentEngineBenchmark;
+use payment_engine::benchmark::{WorkloadConfig, WorkloadGenerator};
+
+/// Synthetic transaction generator.
+/// Streams a seeded workload as CSV to a file or stdout, one row at a time, so very large
+/// datasets can be produced for load tests and CI fixtures without holding them in memory.
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Generate synthetic transaction CSV files", long_about = None)]
+#[command(name = "generate-data")]
+struct GenerateArgs {
+    /// Number of deposits and withdrawals to generate (dispute traffic is added on top)
+    #[arg(short = 'n', long, default_value_t = 1_000_000)]
+    transactions: usize,
+
+    /// Number of distinct clients
+    #[arg(short, long, default_value_t = 10_000)]
+    clients: usize,
+
+    /// Output file path (defaults to stdout)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Dispute rate in percent (e.g., 5 for 5%)
+    #[arg(short = 'd', long, default_value_t = 5.0)]
+    dispute_rate_percent: f64,
+
+    /// Share of disputes that are resolved, in percent
+    #[arg(long, default_value_t = 50.0)]
+    resolve_rate_percent: f64,
+
+    /// Share of disputes that are charged back, in percent
+    #[arg(long, default_value_t = 10.0)]
+    chargeback_rate_percent: f64,
+
+    /// Share of transactions that are withdrawals, in percent
+    #[arg(long, default_value_t = 33.0)]
+    withdrawal_rate_percent: f64,
+
+    /// Zipf exponent for client selection (0 = uniform)
+    #[arg(long, default_value_t = 0.0)]
+    zipf_exponent: f64,
+
+    /// RNG seed
+    #[arg(long, default_value_t = 42)]
+    seed: u64,
+}
+
+fn main() {
+    let args = GenerateArgs::parse();
+
+    if args.resolve_rate_percent + args.chargeback_rate_percent > 100.0 {
+        eprintln!(
+            "--resolve-rate-percent and --chargeback-rate-percent must not exceed 100 combined"
+        );
+        std::process::exit(2);
+    }
+
+    let config = WorkloadConfig {
+        zipf_exponent: args.zipf_exponent,
+        withdrawal_ratio: args.withdrawal_rate_percent / 100.0,
+        dispute_rate: args.dispute_rate_percent / 100.0,
+        resolve_rate: args.resolve_rate_percent / 100.0,
+        chargeback_rate: args.chargeback_rate_percent / 100.0,
+        seed: args.seed,
+        ..WorkloadConfig::default()
+    };
+    let generator = WorkloadGenerator::new(args.transactions, args.clients, &config);
+
+    let writer: Box<dyn Write> = match &args.output {
+        Some(path) => match std::fs::File::create(path) {
+            Ok(file) => Box::new(file),
+            Err(e) => {
+                eprintln!("Failed to create output file {:?}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        None => Box::new(std::io::stdout().lock()),
+    };
+
+    match PaymentEngineBenchmark::write_transactions_csv(BufWriter::new(writer), generator) {
+        Ok(rows) => {
+            if let Some(path) = &args.output {
+                eprintln!("Wrote {} transactions to {:?}", rows, path);
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to write transactions: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,300 @@
+//! Load test for sizing a concurrent deployment.
+//!
+//! Spawns `--clients` simulated upstream clients, each streaming its own generated
+//! workload as CSV through a pipe into `ConcurrentEngine::process_stream_transactions`,
+//! optionally throttled to `--rate` rows per second. Each simulated client owns a
+//! disjoint set of accounts and transaction IDs, as separate partners would, so streams
+//! only contend on the engine lock. Reports sustained throughput, engine latency
+//! percentiles (including lock waits) and error rates.
+
+// Results go to stdout only through `output::OutputRouter`
+#![deny(clippy::print_stdout)]
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use payment_engine::account::ClientId;
+use payment_engine::benchmark::{WorkloadConfig, WorkloadGenerator};
+use payment_engine::engine::EngineInfo;
+use payment_engine::engine::concurrent::ConcurrentEngine;
+use payment_engine::engine::observer::EngineObserver;
+use payment_engine::errors::PaymentsError;
+use payment_engine::output;
+use payment_engine::transaction::{Transaction, TxId};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Load test the concurrent engine with many streaming clients", long_about = None)]
+struct LoadTestArgs {
+    /// Simulated clients, each streaming on its own thread
+    #[arg(short, long, default_value_t = 16)]
+    clients: usize,
+
+    /// Deposits and withdrawals sent by each client; disputes come on top
+    #[arg(short = 'n', long, default_value_t = 10000)]
+    transactions_per_client: usize,
+
+    /// Accounts owned by each client
+    #[arg(long, default_value_t = 100)]
+    accounts_per_client: usize,
+
+    /// Rows per second sent by each client (0 = as fast as possible)
+    #[arg(short, long, default_value_t = 0)]
+    rate: u64,
+
+    /// Dispute rate in percent (e.g., 5 for 5%)
+    #[arg(short = 'd', long, default_value_t = 5.0)]
+    dispute_rate_percent: f64,
+
+    /// Zipf exponent for account selection within a client (0 = uniform)
+    #[arg(long, default_value_t = 1.0)]
+    zipf_exponent: f64,
+
+    /// Max accounts held by the engine
+    #[arg(long, default_value_t = 65535)]
+    max_accounts: usize,
+
+    /// Max disputable transactions held by the engine
+    #[arg(long, default_value_t = 1_000_000)]
+    max_transactions: usize,
+
+    /// Max processed tx ids held by the engine
+    #[arg(long, default_value_t = 10_000_000)]
+    max_tx_ids: usize,
+
+    /// RNG seed; client `i` uses `seed + i`
+    #[arg(long, default_value_t = 42)]
+    seed: u64,
+}
+
+/// Counts accepted transactions, and rejected ones by error code, across all streams
+#[derive(Debug, Default)]
+struct Outcomes {
+    accepted: AtomicU64,
+    rejected: Mutex<BTreeMap<u16, u64>>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct OutcomeCounter(Arc<Outcomes>);
+
+impl EngineObserver for OutcomeCounter {
+    fn on_accepted(&self, _transaction: &Transaction) {
+        self.0.accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_rejected(&self, _transaction: &Transaction, error: &PaymentsError) {
+        let mut rejected = self.0.rejected.lock().unwrap();
+        *rejected.entry(error.code()).or_default() += 1;
+    }
+}
+
+/// What a load test run saw, printed as its report
+#[derive(Debug)]
+struct LoadTestResults {
+    clients: usize,
+    failed_streams: usize,
+    sent: u64,
+    elapsed: Duration,
+    accepted: u64,
+    rejected_by_code: BTreeMap<u16, u64>,
+    info: EngineInfo,
+}
+
+impl fmt::Display for LoadTestResults {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stats = &self.info.stats;
+        let rejected: u64 = self.rejected_by_code.values().sum();
+        let unprocessed = self.sent.saturating_sub(self.accepted + rejected);
+        let percent = |count: u64| 100.0 * count as f64 / self.sent.max(1) as f64;
+
+        writeln!(f, "=== Load Test Results ===")?;
+        writeln!(
+            f,
+            "Clients: {} ({} failed)",
+            self.clients, self.failed_streams
+        )?;
+        writeln!(f, "Rows sent: {} in {:?}", self.sent, self.elapsed)?;
+        writeln!(
+            f,
+            "Sustained throughput: {:.0} tx/sec",
+            (self.accepted + rejected) as f64 / self.elapsed.as_secs_f64()
+        )?;
+        writeln!(
+            f,
+            "Latency - p50: {:?}, p95: {:?}, p99: {:?}, max: {:?}",
+            stats.p50, stats.p95, stats.p99, stats.max
+        )?;
+        writeln!(
+            f,
+            "Accepted: {} ({:.2}%)",
+            self.accepted,
+            percent(self.accepted)
+        )?;
+        writeln!(f, "Rejected: {} ({:.2}%)", rejected, percent(rejected))?;
+        for (code, count) in &self.rejected_by_code {
+            writeln!(f, "  code {}: {} ({:.2}%)", code, count, percent(*count))?;
+        }
+        writeln!(
+            f,
+            "Unparsed or lost: {} ({:.2}%)",
+            unprocessed,
+            percent(unprocessed)
+        )?;
+        writeln!(f, "Final account count: {}", self.info.account_count)
+    }
+}
+
+/// `transaction` moved into the account and ID ranges of simulated client `index`:
+/// account `a` becomes `(a - 1) * clients + index + 1`, and IDs are offset by
+/// `index * id_stride`
+fn remap(
+    mut transaction: Transaction,
+    index: usize,
+    clients: usize,
+    id_stride: u32,
+) -> Transaction {
+    let account = usize::from(transaction.client.get() - 1) * clients + index + 1;
+    transaction.client = ClientId::new(account as u16);
+    transaction.tx = TxId::new(transaction.tx.get() + index as u32 * id_stride);
+    transaction
+}
+
+/// Write client `index`'s workload as CSV into `writer`, pacing rows to `rate` per
+/// second. Returns the number of rows written.
+fn stream_client(
+    mut writer: impl Write,
+    args: &LoadTestArgs,
+    index: usize,
+    id_stride: u32,
+) -> std::io::Result<u64> {
+    let workload = WorkloadConfig {
+        zipf_exponent: args.zipf_exponent,
+        dispute_rate: args.dispute_rate_percent / 100.0,
+        seed: args.seed + index as u64,
+        ..WorkloadConfig::default()
+    };
+    let generator = WorkloadGenerator::new(
+        args.transactions_per_client,
+        args.accounts_per_client,
+        &workload,
+    );
+    let mut wtr = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(&mut writer);
+    wtr.write_record(["type", "client", "tx", "amount"])?;
+
+    let interval = (args.rate > 0).then(|| Duration::from_secs(1) / args.rate as u32);
+    let started = Instant::now();
+    let mut rows = 0;
+    for transaction in generator {
+        if let Some(interval) = interval {
+            let due = started + interval * rows as u32;
+            if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                // Let rows sent so far reach the engine before waiting
+                wtr.flush()?;
+                std::thread::sleep(wait);
+            }
+        }
+        wtr.serialize(remap(transaction, index, args.clients, id_stride))?;
+        rows += 1;
+    }
+    wtr.flush()?;
+    Ok(rows)
+}
+
+fn main() {
+    let args = LoadTestArgs::parse();
+    if args.clients == 0 || args.accounts_per_client == 0 {
+        eprintln!("--clients and --accounts-per-client must be at least 1");
+        std::process::exit(2);
+    }
+    if args.clients * args.accounts_per_client > usize::from(ClientId::MAX.get()) {
+        eprintln!(
+            "{} clients x {} accounts exceeds the {} available client IDs",
+            args.clients,
+            args.accounts_per_client,
+            ClientId::MAX
+        );
+        std::process::exit(2);
+    }
+    let Some(id_stride) = u32::try_from(args.transactions_per_client)
+        .ok()
+        .filter(|stride| u64::from(*stride) * args.clients as u64 <= u64::from(u32::MAX))
+    else {
+        eprintln!("--clients x --transactions-per-client exceeds the available transaction IDs");
+        std::process::exit(2);
+    };
+
+    let mut engine =
+        ConcurrentEngine::new(args.max_accounts, args.max_transactions, args.max_tx_ids);
+    let counter = OutcomeCounter::default();
+    engine.add_observer(counter.clone());
+
+    let mut console = output::router().console();
+    let _ = writeln!(
+        console,
+        "Starting {} clients x {} transactions ({})",
+        args.clients,
+        args.transactions_per_client,
+        match args.rate {
+            0 => "unthrottled".to_string(),
+            rate => format!("{} rows/s each", rate),
+        }
+    );
+    let started = Instant::now();
+    let (sent, failed_streams) = std::thread::scope(|scope| {
+        let mut producers = Vec::new();
+        let mut consumers = Vec::new();
+        for index in 0..args.clients {
+            let (reader, writer) = std::io::pipe().expect("failed to create pipe");
+            consumers.push(engine.process_stream_transactions(reader, index as u64));
+            let args = &args;
+            producers.push(scope.spawn(move || stream_client(writer, args, index, id_stride)));
+        }
+
+        let mut sent = 0;
+        let mut failed = 0;
+        for producer in producers {
+            match producer.join().expect("client thread panicked") {
+                Ok(rows) => sent += rows,
+                Err(e) => {
+                    eprintln!("Client failed to send: {}", e);
+                    failed += 1;
+                }
+            }
+        }
+        for consumer in consumers {
+            match consumer.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    eprintln!("Stream failed: {}", e);
+                    failed += 1;
+                }
+                Err(_) => {
+                    eprintln!("Stream thread panicked");
+                    failed += 1;
+                }
+            }
+        }
+        (sent, failed)
+    });
+    let elapsed = started.elapsed();
+
+    let results = LoadTestResults {
+        clients: args.clients,
+        failed_streams,
+        sent,
+        elapsed,
+        accepted: counter.0.accepted.load(Ordering::Relaxed),
+        rejected_by_code: counter.0.rejected.lock().unwrap().clone(),
+        info: engine.get_engine_info(),
+    };
+    let _ = write!(console, "{}", results);
+    if failed_streams > 0 {
+        std::process::exit(1);
+    }
+}
@@ -1,21 +1,73 @@
-use clap::Parser;
-use std::path::PathBuf;
+// Data goes to stdout only through `output::OutputRouter`
+#![deny(clippy::print_stdout)]
 
+use clap::{Parser, Subcommand, ValueEnum};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use payment_engine::account::{Account, AccountFilter, ClientId, DecimalFormat};
+use payment_engine::activity::{ActivityCollector, Bucket};
+#[cfg(all(feature = "admin", unix))]
+use payment_engine::admin::{AdminChannel, AdminCommand};
+#[cfg(feature = "aml")]
+use payment_engine::aml::AmlMonitor;
+use payment_engine::checkpoint::{Checkpoint, CheckpointOptions, process_file_with_checkpoints};
+use payment_engine::config::FileConfig;
+use payment_engine::disputes::DisputeTracker;
+use payment_engine::engine::cache::EvictionPolicy;
+#[cfg(feature = "websocket")]
+use payment_engine::engine::observer::AccountDelta;
+use payment_engine::engine::replay::Schedule;
+use payment_engine::engine::validation::{ClientBlocklist, MaxAmount, MaxPrecision};
+use payment_engine::engine::{DuplicatePolicy, ErrorPolicy, ParseErrorLimit};
+use payment_engine::errors::{ErrorCategory, PaymentsError};
+#[cfg(feature = "events")]
+use payment_engine::events::{EventPublisher, EventSink, LinePublisher, NatsPublisher};
+use payment_engine::follow::FollowingSource;
+use payment_engine::locked::LockRecorder;
+#[cfg(feature = "events")]
+use payment_engine::outbox::{Outbox, OutboxRelay};
+use payment_engine::output::{self, OutputRouter};
+use payment_engine::reconcile::{reconcile, write_differences_csv};
+#[cfg(feature = "risk")]
+use payment_engine::risk::RiskMonitor;
+use payment_engine::settlement::SettlementCollector;
+use payment_engine::shutdown::ShutdownFlag;
+use payment_engine::statement::HistoryRecorder;
+use payment_engine::summary::SummaryCollector;
+use payment_engine::transaction::{
+    Amount, AmountFormat, InputFormat, TypeMatching, parse_delimiter,
+};
+#[cfg(feature = "websocket")]
+use payment_engine::websocket::AccountFeed;
 use payment_engine::{EngineConfig, PaymentsEngine};
 
 /// Payment engine cli tool.
 /// Reads transactions from a CSV file, processes them, and outputs the final state of client accounts.
 /// Usage: payments-engine <input_file> [--output <output_file>] [--log-level <level>]
+///        payments-engine reconcile <a.csv> <b.csv> [--output <diff_file>]
 /// <input_file>: Path to the input CSV file containing transactions.
 /// --output <output_file>: Optional path to the output CSV file (defaults to stdout).
 /// --log-level <level>: Optional log level (e.g., info, debug, warn
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 #[command(name = "payments-engine")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Path to the input CSV file
-    #[arg(help = "transactions.csv file path")]
-    input_file: PathBuf,
+    #[arg(required = true, help = "transactions.csv file path")]
+    input_file: Option<PathBuf>,
+
+    /// TOML config file; flags given on the command line override its values
+    #[arg(
+        short,
+        long,
+        help = "TOML config file (command-line flags take precedence)"
+    )]
+    config: Option<PathBuf>,
 
     /// Output file path (defaults to stdout)
     #[arg(short, long, help = "Output CSV file path (defaults to stdout)")]
@@ -25,11 +77,26 @@ struct Args {
     #[arg(short, long, help = "Log level (e.g., info, debug, warn)")]
     log_level: Option<String>,
 
+    /// Write log output to this file instead of stderr
+    #[arg(
+        long,
+        help = "Write log messages and other diagnostics to this file instead of stderr"
+    )]
+    log_file: Option<PathBuf>,
+
+    /// Machine mode: stdout carries the account output and nothing else
+    #[arg(
+        short,
+        long,
+        help = "Machine mode: keep stdout for the account CSV only, send every diagnostic to stderr or --log-file, and log warnings and errors only unless --log-level is given"
+    )]
+    quiet: bool,
+
     /// Engine type to use
     #[arg(
         short,
         long,
-        help = "Engine type: standard, bounded, or concurrent (defaults to standard)"
+        help = "Engine type: standard, bounded, concurrent, actor, adaptive, or auto (defaults to standard)"
     )]
     engine: Option<String>,
 
@@ -54,12 +121,586 @@ struct Args {
     )]
     max_tx_ids: Option<usize>,
 
+    /// Which entries the bounded engine evicts when a cache is full
+    #[arg(
+        long,
+        help = "Bounded engine eviction policy: lru (default), lfu, fifo or segmented"
+    )]
+    eviction_policy: Option<EvictionPolicy>,
+
     /// Automatically configure engine based on available memory in MB
     #[arg(
         long,
-        help = "Auto-configure bounded engine for given memory limit in MB (overrides other max-* options)"
+        help = "Auto-configure bounded engine for given memory limit in MB (overrides other max-* options); memory budget for the adaptive engine"
     )]
     memory_limit_mb: Option<usize>,
+
+    /// Accounts CSV to start from instead of empty balances
+    #[arg(
+        long,
+        help = "Seed account balances from an accounts CSV (e.g. a previous run's output)"
+    )]
+    seed_accounts: Option<PathBuf>,
+
+    /// Number of worker threads for the concurrent engine, or of actors for the actor engine
+    #[arg(
+        long,
+        help = "Worker threads for the concurrent engine, or actors for the actor engine (defaults to available parallelism)"
+    )]
+    workers: Option<usize>,
+
+    /// What to do with rows that fail to parse or are rejected
+    #[arg(
+        long,
+        help = "Error policy: skip (log and continue, default) or abort (stop at the first error)"
+    )]
+    error_policy: Option<ErrorPolicy>,
+
+    /// Parse failures after which processing fails
+    #[arg(
+        long,
+        help = "Fail once more than this many rows (e.g. 100) or this percentage of rows (e.g. 5%) fail to parse"
+    )]
+    max_parse_errors: Option<ParseErrorLimit>,
+
+    /// Rejected transactions above which the run exits with status 2
+    #[arg(
+        long,
+        value_name = "LIMIT",
+        help = "Exit with status 2 after writing the output if more than this many transactions (e.g. 10) or this percentage of them (e.g. 1%) were rejected"
+    )]
+    max_rejected: Option<ParseErrorLimit>,
+
+    /// How strictly the `type` column is matched
+    #[arg(
+        long,
+        help = "Type matching: lenient (accept e.g. Deposit or charge_back, default) or strict (only the lowercase names)"
+    )]
+    type_matching: Option<TypeMatching>,
+
+    /// How the `amount` column is written
+    #[arg(
+        long,
+        help = "Amount format: plain (1234.5, default), grouped (1,234.5) or european (1.234,5)"
+    )]
+    amount_format: Option<AmountFormat>,
+
+    /// Field separator of the input
+    #[arg(
+        long,
+        help = "Field separator of the input: a single character such as ';', or tab (default ',')"
+    )]
+    delimiter: Option<String>,
+
+    /// The input has no header row
+    #[arg(
+        long,
+        help = "Read the input without a header row, as columns type, client, tx, amount in that order"
+    )]
+    no_headers: bool,
+
+    /// Input header names to read as another column
+    #[arg(
+        long = "column",
+        value_name = "HEADER=COLUMN",
+        help = "Read the input column named HEADER as COLUMN, e.g. transaction_type=type (repeatable)"
+    )]
+    columns: Vec<String>,
+
+    /// Credit deposits to accounts locked by a chargeback
+    #[arg(
+        long,
+        help = "Accept deposits (e.g. refunds) to accounts locked by a chargeback"
+    )]
+    allow_deposit_when_locked: bool,
+
+    /// Accept disputes against locked accounts
+    #[arg(long, help = "Accept disputes on accounts locked by a chargeback")]
+    allow_dispute_when_locked: bool,
+
+    /// Let a dispute hold more than the available balance
+    #[arg(
+        long,
+        help = "Hold disputed funds even if that drives the available balance negative"
+    )]
+    allow_negative_available: bool,
+
+    /// Cap on transactions under dispute at once per client
+    #[arg(
+        long,
+        help = "Reject disputes from clients that already have this many open disputes"
+    )]
+    max_open_disputes: Option<usize>,
+
+    /// Age after which transactions can no longer be disputed
+    #[arg(
+        long,
+        help = "Stop accepting disputes of a transaction this many seconds after its timestamp"
+    )]
+    dispute_window_secs: Option<u64>,
+
+    /// Rows after which transactions can no longer be disputed
+    #[arg(
+        long,
+        help = "Stop accepting disputes of a transaction once this many more rows were processed"
+    )]
+    dispute_window_transactions: Option<u64>,
+
+    /// What to do with deposits and withdrawals that reuse a transaction ID
+    #[arg(
+        long,
+        help = "Duplicate transaction IDs: error (default), skip-silently (ignore exact replays) or overwrite"
+    )]
+    duplicate_policy: Option<DuplicatePolicy>,
+
+    /// Pay out the available balance when an account is closed
+    #[arg(
+        long,
+        help = "Withdraw the remaining available balance when a close transaction closes an account"
+    )]
+    payout_on_close: bool,
+
+    /// Reject amounts with more decimal places than this
+    #[arg(long, help = "Reject amounts with more than this many decimal places")]
+    max_decimal_places: Option<u32>,
+
+    /// Reject deposits and withdrawals above this amount
+    #[arg(long, help = "Reject deposits and withdrawals above this amount")]
+    max_amount: Option<Amount>,
+
+    /// Clients whose transactions are all rejected
+    #[arg(
+        long = "block-client",
+        help = "Reject every transaction from this client (repeatable)"
+    )]
+    blocked_clients: Vec<ClientId>,
+
+    /// Apply the config file's fee schedule once the input is processed
+    #[arg(
+        long,
+        requires = "config",
+        conflicts_with = "watch",
+        help = "Charge fees and credit interest from the config file's [fees] schedule after processing"
+    )]
+    apply_fees: bool,
+
+    /// Record the order in which the concurrent engine applies transactions
+    #[arg(
+        long,
+        help = "Write the concurrent engine's processing order to this file for later replay"
+    )]
+    record_schedule: Option<PathBuf>,
+
+    /// Replay a previously recorded schedule instead of processing concurrently
+    #[arg(
+        long,
+        conflicts_with = "record_schedule",
+        help = "Apply transactions in the order recorded by --record-schedule"
+    )]
+    replay_schedule: Option<PathBuf>,
+
+    /// Periodically persist engine state and input position to this file
+    #[arg(
+        long,
+        conflicts_with_all = ["replay_schedule", "watch"],
+        help = "Periodically save engine state and input offset to this checkpoint file"
+    )]
+    checkpoint: Option<PathBuf>,
+
+    /// Records between checkpoints
+    #[arg(
+        long,
+        default_value_t = 100_000,
+        help = "Records processed between checkpoints"
+    )]
+    checkpoint_interval: u64,
+
+    /// Restore the checkpoint and continue from its recorded offset
+    #[arg(
+        long,
+        requires = "checkpoint",
+        help = "Resume from the --checkpoint file instead of starting over"
+    )]
+    resume: bool,
+
+    /// Keep following the input file and process rows as they are appended
+    #[arg(
+        long,
+        conflicts_with = "replay_schedule",
+        help = "Follow the input file like tail -f, periodically rewriting the output snapshot"
+    )]
+    watch: bool,
+
+    /// Only write accounts changed since the previous output
+    #[arg(
+        long,
+        help = "Write only accounts changed by this run's transactions (in watch mode: since the previous snapshot)"
+    )]
+    changed_only: bool,
+
+    /// Split the output over several files
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with = "changed_only",
+        value_parser = clap::value_parser!(u16).range(1..),
+        help = "Write the accounts to N files named like the output with .0, .1, ... before the extension, split by client ID, on N threads"
+    )]
+    output_shards: Option<u16>,
+
+    /// Only write these clients' accounts
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_name = "CLIENT,...",
+        help = "Write only these clients' accounts, e.g. --clients 1,7,42"
+    )]
+    clients: Vec<ClientId>,
+
+    /// Only write locked accounts
+    #[arg(long, help = "Write only locked accounts")]
+    only_locked: bool,
+
+    /// Only write accounts with at least this total
+    #[arg(long, help = "Write only accounts whose total is at least this amount")]
+    min_total: Option<Amount>,
+
+    /// Decimal places of output balances
+    #[arg(
+        long,
+        help = "Round and pad output balances to this many decimal places (default 4)"
+    )]
+    decimal_places: Option<u32>,
+
+    /// Write output balances without padding zeros
+    #[arg(
+        long,
+        help = "Write output balances without trailing zeros, e.g. 10 instead of 10.0000"
+    )]
+    strip_trailing_zeros: bool,
+
+    /// How often to check the input file for new rows in watch mode
+    #[arg(
+        long,
+        default_value_t = 500,
+        help = "Watch mode: poll interval in milliseconds"
+    )]
+    poll_interval_ms: u64,
+
+    /// How often to rewrite the output snapshot in watch mode
+    #[arg(
+        long,
+        default_value_t = 5,
+        help = "Watch mode: seconds between output snapshots"
+    )]
+    snapshot_interval_secs: u64,
+
+    /// Unix socket taking admin commands in watch mode
+    #[cfg(all(feature = "admin", unix))]
+    #[arg(
+        long,
+        requires = "watch",
+        help = "Watch mode: accept admin commands (freeze, hold, unfreeze, resolve, snapshot, stats, drain, shutdown) on this unix socket"
+    )]
+    admin_socket: Option<PathBuf>,
+
+    /// Address serving live account updates over WebSocket in watch mode
+    #[cfg(feature = "websocket")]
+    #[arg(
+        long,
+        requires = "watch",
+        value_name = "ADDR",
+        help = "Watch mode: stream account balance and lock changes to WebSocket subscribers on this address, e.g. 127.0.0.1:9090"
+    )]
+    websocket: Option<String>,
+
+    /// NATS server to publish domain events to
+    #[cfg(feature = "events")]
+    #[arg(
+        long,
+        value_name = "HOST:PORT",
+        conflicts_with = "events_file",
+        help = "Publish accepted transactions and account locks to this NATS server"
+    )]
+    events_nats: Option<String>,
+
+    /// NATS subject for domain events
+    #[cfg(feature = "events")]
+    #[arg(
+        long,
+        default_value = "payments.events",
+        help = "Subject used with --events-nats"
+    )]
+    events_subject: String,
+
+    /// File (or FIFO) to write domain events to as JSON lines
+    #[cfg(feature = "events")]
+    #[arg(
+        long,
+        help = "Write accepted transactions and account locks as JSON lines, e.g. to a FIFO read by kcat"
+    )]
+    events_file: Option<PathBuf>,
+
+    /// Outbox log staging domain events until the checkpoint holding their state is saved
+    #[cfg(feature = "events")]
+    #[arg(
+        long,
+        requires = "checkpoint",
+        help = "Save events with each checkpoint and append them to this outbox log, relaying it to --events-nats or --events-file"
+    )]
+    events_outbox: Option<PathBuf>,
+
+    /// Per-client risk signals and flags
+    #[cfg(feature = "risk")]
+    #[arg(
+        long,
+        help = "Write per-client fraud signals and risk flags to this CSV file"
+    )]
+    risk_report: Option<PathBuf>,
+
+    /// Clients whose daily deposits exceed the AML threshold
+    #[cfg(feature = "aml")]
+    #[arg(
+        long,
+        help = "Write client-days whose deposits exceed the AML threshold to this CSV file"
+    )]
+    compliance_report: Option<PathBuf>,
+
+    /// Accounts as an Arrow IPC file
+    #[cfg(feature = "arrow")]
+    #[arg(
+        long,
+        help = "Also write the final accounts to this Arrow IPC file, e.g. for pl.read_ipc"
+    )]
+    arrow_output: Option<PathBuf>,
+
+    /// Disputable transactions as an Arrow IPC file
+    #[cfg(feature = "arrow")]
+    #[arg(
+        long,
+        help = "Write the deposits and withdrawals kept for disputes to this Arrow IPC file"
+    )]
+    arrow_ledger: Option<PathBuf>,
+
+    /// Client whose statement is written to `--statement`
+    #[arg(
+        long,
+        requires = "statement",
+        value_name = "CLIENT",
+        help = "Record this client's transaction history for --statement"
+    )]
+    statement_client: Option<ClientId>,
+
+    /// Statement of `--statement-client`
+    #[arg(
+        long,
+        requires = "statement_client",
+        help = "Write the --statement-client's transactions with running balances to this file"
+    )]
+    statement: Option<PathBuf>,
+
+    /// Statement file format
+    #[arg(
+        long,
+        value_enum,
+        default_value = "csv",
+        help = "Format of the --statement file"
+    )]
+    statement_format: ReportFormat,
+
+    /// Settlement figures per client and overall
+    #[arg(
+        long,
+        help = "Write gross deposits, withdrawals, chargebacks and net position per client and overall to this file"
+    )]
+    settlement_report: Option<PathBuf>,
+
+    /// Settlement report file format
+    #[arg(
+        long,
+        value_enum,
+        default_value = "csv",
+        help = "Format of the --settlement-report file"
+    )]
+    settlement_format: ReportFormat,
+
+    /// Transaction volumes and values per period
+    #[arg(
+        long,
+        help = "Write accepted transaction counts and amounts per period and type to this CSV file"
+    )]
+    activity_report: Option<PathBuf>,
+
+    /// Period length of the activity report
+    #[arg(
+        long,
+        default_value = "day",
+        help = "Periods of the --activity-report: hour or day (UTC, from the timestamp column)"
+    )]
+    activity_bucket: Bucket,
+
+    /// Break the activity report down by client
+    #[arg(
+        long,
+        requires = "activity_report",
+        help = "Write one --activity-report row per client instead of totals over all clients"
+    )]
+    activity_by_client: bool,
+
+    /// Every dispute with its outcome
+    #[arg(
+        long,
+        help = "Write every dispute with its amount, outcome (resolved, chargeback or open) and rows until it closed to this CSV file"
+    )]
+    dispute_report: Option<PathBuf>,
+
+    /// Accounts locked during the run
+    #[arg(
+        long,
+        help = "Write every account a chargeback locked, with the triggering tx, amount charged back and balances, to this CSV file"
+    )]
+    locked_report: Option<PathBuf>,
+
+    /// Engine information after the run
+    #[arg(
+        long,
+        help = "Write engine type, account count, memory limits and latency stats to this file after the run"
+    )]
+    stats_output: Option<PathBuf>,
+
+    /// Engine information file format
+    #[arg(
+        long,
+        value_enum,
+        default_value = "json",
+        help = "Format of the --stats-output file: json or table"
+    )]
+    stats_format: StatsFormat,
+
+    /// Print global totals after processing
+    #[arg(
+        long,
+        help = "Print one line of totals (accounts, balances, transactions by type) to stderr"
+    )]
+    summary: bool,
+
+    /// Check balance invariants before writing the output
+    #[arg(
+        long,
+        help = "Check every account (total = available + held, held and total not negative) after processing and exit non-zero without writing output on a violation"
+    )]
+    verify_invariants: bool,
+
+    /// Rewrite mismatched totals before the invariant check
+    #[arg(
+        long,
+        requires = "verify_invariants",
+        help = "With --verify-invariants, rewrite mismatched totals as available + held instead of failing on them"
+    )]
+    repair_totals: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ReportFormat {
+    Csv,
+    Json,
+}
+
+/// Process exit status of a failed run, so orchestration can branch on the outcome
+/// without reading the log. A run that succeeds and stays within `--max-rejected` exits
+/// with 0. `reconcile` has its own: 0 when the snapshots match, 1 when they differ and 2
+/// on errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Exit {
+    /// Invalid arguments or configuration, or a file or socket that could not be opened
+    Usage = 1,
+    /// The output was written but more transactions were rejected than `--max-rejected`
+    /// allows, or a rejected transaction stopped an `--error-policy abort` run
+    Rejected = 2,
+    /// Input that could not be parsed, e.g. a bad header or too many unparseable rows
+    MalformedInput = 3,
+    /// The engine failed, or output, reports or events could not be written
+    Internal = 4,
+}
+
+impl Exit {
+    /// Status for an error that stopped processing, by its `ErrorCategory`
+    fn of(error: &(dyn std::error::Error + 'static)) -> Self {
+        match error
+            .downcast_ref::<PaymentsError>()
+            .map(PaymentsError::category)
+        {
+            Some(ErrorCategory::InputError) => Self::MalformedInput,
+            Some(ErrorCategory::BusinessRuleViolation) => Self::Rejected,
+            Some(ErrorCategory::InternalError) => Self::Internal,
+            None if error.is::<csv::Error>() => Self::MalformedInput,
+            None => Self::Internal,
+        }
+    }
+
+    fn exit(self) -> ! {
+        std::process::exit(self as i32)
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum StatsFormat {
+    Json,
+    Table,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Compare two account snapshots; exits with 1 when they differ and 2 on errors
+    Reconcile {
+        /// First accounts CSV, e.g. from the current engine version
+        a: PathBuf,
+
+        /// Second accounts CSV, e.g. from the candidate version
+        b: PathBuf,
+
+        /// Differences CSV path (defaults to stdout)
+        #[arg(short, long, help = "Differences CSV path (defaults to stdout)")]
+        output: Option<PathBuf>,
+    },
+}
+
+/// Diff two account snapshots and write the differences as CSV. Returns the exit code:
+/// 0 when the snapshots match, 1 when they differ and 2 when either cannot be read.
+fn run_reconcile(a: &Path, b: &Path, output: Option<&Path>) -> i32 {
+    let open = |path: &Path| {
+        std::fs::File::open(path)
+            .map(std::io::BufReader::new)
+            .map_err(|e| format!("Failed to open {:?}: {}", path, e))
+    };
+    let differences = match open(a)
+        .and_then(|a| Ok((a, open(b)?)))
+        .and_then(|(a, b)| reconcile(a, b).map_err(|e| e.to_string()))
+    {
+        Ok(differences) => differences,
+        Err(e) => {
+            log::error!("{}", e);
+            return 2;
+        }
+    };
+
+    let result = match output {
+        Some(path) => std::fs::File::create(path)
+            .map_err(PaymentsError::from)
+            .and_then(|file| write_differences_csv(&differences, std::io::BufWriter::new(file))),
+        None => write_differences_csv(&differences, output::router().data()),
+    };
+    if let Err(e) = result {
+        log::error!("Failed to write differences: {}", e);
+        return 2;
+    }
+    if differences.is_empty() {
+        log::info!("Snapshots match");
+        0
+    } else {
+        log::warn!("{} clients differ", differences.len());
+        1
+    }
 }
 
 fn init_logger(log_level: &str) {
@@ -78,35 +719,563 @@ fn init_logger(log_level: &str) {
         }
     };
 
-    env_logger::Builder::from_default_env()
-        .filter_level(level)
-        .format_timestamp_secs()
-        .init();
+    let mut builder = env_logger::Builder::from_default_env();
+    builder.filter_level(level).format_timestamp_secs();
+    if output::router().has_log_file() {
+        builder.target(env_logger::Target::Pipe(output::router().diagnostics()));
+    }
+    builder.init();
+}
+
+/// Which accounts `write_output` writes
+#[derive(Debug, Default)]
+struct OutputOptions {
+    /// Just the accounts changed since the previous write
+    changed_only: bool,
+    filter: AccountFilter,
+    /// Files the accounts are split over; see `shard_path`
+    shards: Option<u16>,
+}
+
+/// Path of shard `shard` of `path`, e.g. `accounts.1.csv` for `accounts.csv`
+fn shard_path(path: &Path, shard: usize) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_owned();
+    name.push(format!(".{}", shard));
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+/// Path of the temporary sibling `path` is written to before it is renamed into place
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    PathBuf::from(tmp_path)
+}
+
+/// Write the current accounts selected by `options` to `output_path`, or stdout when no
+/// path is given.
+/// Files are written to a temporary sibling and renamed into place, so readers of a
+/// watch-mode snapshot never see a half-written file.
+fn write_output(
+    engine: &mut PaymentsEngine,
+    output_path: Option<&Path>,
+    options: &OutputOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let filter = |account: &Account| options.filter.matches(account);
+    if let Some(shards) = options.shards {
+        let path = output_path.ok_or("--output-shards needs an output file")?;
+        let paths: Vec<PathBuf> = (0..usize::from(shards))
+            .map(|shard| shard_path(path, shard))
+            .collect();
+        let writers = paths
+            .iter()
+            .map(|path| {
+                Ok(std::io::BufWriter::new(std::fs::File::create(tmp_path(
+                    path,
+                ))?))
+            })
+            .collect::<Result<Vec<_>, std::io::Error>>()?;
+        engine.write_accounts_csv_sharded(writers, filter)?;
+        for path in &paths {
+            std::fs::rename(tmp_path(path), path)?;
+        }
+        return Ok(());
+    }
+    let mut write = |writer: Box<dyn std::io::Write>| {
+        if options.changed_only {
+            engine.write_changed_accounts_csv_filtered(writer, filter)
+        } else {
+            engine.write_accounts_csv_filtered(writer, filter)
+        }
+    };
+    match output_path {
+        Some(path) => {
+            let tmp_path = tmp_path(path);
+            let file = std::fs::File::create(&tmp_path)?;
+            write(Box::new(std::io::BufWriter::new(file)))?;
+            std::fs::rename(&tmp_path, path)?;
+            Ok(())
+        }
+        None => write(output::router().data()),
+    }
+}
+
+/// How `watch` polls the input and writes snapshots
+struct WatchOptions {
+    poll_interval: Duration,
+    snapshot_interval: Duration,
+    #[cfg(all(feature = "admin", unix))]
+    admin: Option<AdminSocket>,
+    #[cfg(feature = "websocket")]
+    feed: Option<LiveFeed>,
+}
+
+/// Admin commands taken between watch-mode batches
+#[cfg(all(feature = "admin", unix))]
+struct AdminSocket {
+    channel: AdminChannel,
+    /// A `drain` command paused reading the input
+    draining: bool,
+    /// Clients whose lock an admin command changed since the feed last published them
+    relocked: Vec<ClientId>,
+}
+
+#[cfg(all(feature = "admin", unix))]
+impl AdminSocket {
+    /// Answer every waiting command; returns whether any was applied
+    fn serve(
+        &mut self,
+        engine: &mut PaymentsEngine,
+        output_path: Option<&Path>,
+        output_options: &OutputOptions,
+        shutdown: &ShutdownFlag,
+    ) -> bool {
+        let mut served = false;
+        loop {
+            let request = match self.channel.next_request() {
+                Ok(Some(request)) => request,
+                Ok(None) => break,
+                Err(e) => {
+                    log::warn!("Failed to accept admin command: {}", e);
+                    break;
+                }
+            };
+            let result = request
+                .command
+                .apply(engine)
+                .map_err(|e| e.to_string())
+                .and_then(|detail| match request.command {
+                    AdminCommand::Snapshot => write_output(engine, output_path, output_options)
+                        .map(|()| "snapshot written".to_string())
+                        .map_err(|e| e.to_string()),
+                    AdminCommand::Drain => {
+                        self.draining = true;
+                        write_output(engine, output_path, output_options)
+                            .map(|()| "drained; input paused, snapshot written".to_string())
+                            .map_err(|e| e.to_string())
+                    }
+                    AdminCommand::Shutdown => {
+                        shutdown.request();
+                        Ok("shutting down".to_string())
+                    }
+                    _ => Ok(detail),
+                });
+            if let (
+                Ok(_),
+                AdminCommand::Freeze { client, .. } | AdminCommand::Unfreeze { client },
+            ) = (&result, &request.command)
+            {
+                self.relocked.push(*client);
+            }
+            match &result {
+                Ok(detail) => log::info!("Admin command {:?}: {}", request.command, detail),
+                Err(e) => log::warn!("Admin command {:?} failed: {}", request.command, e),
+            }
+            request.respond(result);
+            served = true;
+        }
+        served
+    }
+}
+
+/// Account changes forwarded to WebSocket subscribers between watch-mode batches
+#[cfg(feature = "websocket")]
+struct LiveFeed {
+    feed: AccountFeed,
+    changes: std::sync::mpsc::Receiver<AccountDelta>,
+}
+
+#[cfg(feature = "websocket")]
+impl LiveFeed {
+    /// Take on waiting subscribers and publish the changes made since the last call,
+    /// then the lock states of `relocked`, which change outside transactions
+    fn serve(&mut self, engine: &PaymentsEngine, relocked: impl IntoIterator<Item = ClientId>) {
+        if let Err(e) = self.feed.accept() {
+            log::warn!("Failed to accept account feed subscriber: {}", e);
+        }
+        for delta in self.changes.try_iter() {
+            self.feed.publish(&delta);
+        }
+        for client in relocked {
+            if let Ok(Some(account)) = engine.peek_account(client) {
+                self.feed
+                    .publish(&AccountDelta::new(None, Some(account.clone()), &account));
+            }
+        }
+    }
+}
+
+/// Follow `input_path` until `shutdown` is requested, processing appended rows and
+/// rewriting the output snapshot every `snapshot_interval` when something has changed.
+/// A final snapshot is written on shutdown.
+fn watch(
+    engine: &mut PaymentsEngine,
+    input_path: &Path,
+    output_path: Option<&Path>,
+    output_options: &OutputOptions,
+    #[allow(unused_mut)] mut options: WatchOptions,
+    shutdown: &ShutdownFlag,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut source =
+        FollowingSource::open(input_path)?.with_headers(engine.input_format().has_headers);
+    let mut last_snapshot = Instant::now();
+    let mut dirty = true;
+
+    log::info!(
+        "Watching {:?} (poll every {:?}, snapshot every {:?})",
+        input_path,
+        options.poll_interval,
+        options.snapshot_interval
+    );
+
+    while !shutdown.is_requested() {
+        #[cfg(all(feature = "admin", unix))]
+        let draining = match &mut options.admin {
+            Some(admin) => {
+                dirty |= admin.serve(engine, output_path, output_options, shutdown);
+                admin.draining
+            }
+            None => false,
+        };
+        #[cfg(not(all(feature = "admin", unix)))]
+        let draining = false;
+
+        let batch = if draining { None } else { source.next_batch()? };
+        match batch {
+            Some(batch) => {
+                engine.process_transactions_from_reader(batch.as_slice())?;
+                dirty = true;
+            }
+            None => std::thread::sleep(options.poll_interval),
+        }
+
+        #[cfg(feature = "websocket")]
+        if let Some(feed) = &mut options.feed {
+            #[cfg(all(feature = "admin", unix))]
+            let relocked = options
+                .admin
+                .as_mut()
+                .map(|admin| std::mem::take(&mut admin.relocked))
+                .unwrap_or_default();
+            #[cfg(not(all(feature = "admin", unix)))]
+            let relocked = Vec::new();
+            feed.serve(engine, relocked);
+        }
+
+        if dirty && last_snapshot.elapsed() >= options.snapshot_interval {
+            write_output(engine, output_path, output_options)?;
+            log::info!(
+                "Snapshot written ({} accounts)",
+                engine.get_engine_info().account_count
+            );
+            last_snapshot = Instant::now();
+            dirty = false;
+        }
+    }
+
+    write_output(engine, output_path, output_options)?;
+    log::info!(
+        "Watch mode stopped; final snapshot written ({} accounts)",
+        engine.get_engine_info().account_count
+    );
+    Ok(())
 }
 
 fn main() {
-    let args = Args::parse();
-    let log_level = args.log_level.unwrap_or_else(|| "info".to_string());
+    // clap exits with 2 on usage errors, which is `Exit::Rejected` here
+    let args = Args::try_parse().unwrap_or_else(|e| {
+        let _ = e.print();
+        if e.use_stderr() {
+            Exit::Usage.exit();
+        }
+        std::process::exit(0);
+    });
+
+    let file_config = match &args.config {
+        Some(path) => FileConfig::from_path(path).unwrap_or_else(|e| {
+            eprintln!("Failed to load config file {:?}: {}", path, e);
+            Exit::Usage.exit();
+        }),
+        None => FileConfig::default(),
+    };
+
+    let log_level = args
+        .log_level
+        .or(file_config.log_level)
+        .unwrap_or_else(|| if args.quiet { "warn" } else { "info" }.to_string());
+    let mut router = OutputRouter::new();
+    if args.quiet {
+        router = router.machine();
+    }
+    if let Some(path) = &args.log_file {
+        let file = std::fs::File::create(path).unwrap_or_else(|e| {
+            eprintln!("Failed to open log file {:?}: {}", path, e);
+            Exit::Usage.exit();
+        });
+        router = router.with_log_file(file);
+    }
+    let _ = output::install(router);
     init_logger(&log_level);
 
-    let input_path = args.input_file;
+    if let Some(Command::Reconcile { a, b, output }) = &args.command {
+        std::process::exit(run_reconcile(a, b, output.as_deref()));
+    }
+
+    let Some(input_path) = args.input_file else {
+        unreachable!("clap requires an input file when no subcommand is given");
+    };
     if !input_path.exists() {
         log::error!("Input file does not exist: {:?}", input_path);
-        std::process::exit(1);
+        Exit::Usage.exit();
     }
     if input_path.extension().is_none_or(|ext| ext != "csv") {
         log::error!("Input file is not a CSV file: {:?}", input_path);
-        std::process::exit(1);
+        Exit::Usage.exit();
     }
 
-    let config = EngineConfig::from_cli_params(
-        args.engine.as_deref(),
-        args.max_accounts,
-        args.max_transactions,
-        args.max_tx_ids,
-        args.memory_limit_mb,
-    );
+    let engine_type = args.engine.or(file_config.engine);
+    let config = if engine_type
+        .as_deref()
+        .is_some_and(|engine_type| engine_type.eq_ignore_ascii_case("auto"))
+    {
+        EngineConfig::auto_for_file(&input_path).unwrap_or_else(|e| {
+            log::error!("Failed to inspect input file {:?}: {}", input_path, e);
+            Exit::of(&e).exit();
+        })
+    } else {
+        EngineConfig::from_cli_params(
+            engine_type.as_deref(),
+            args.max_accounts.or(file_config.max_accounts),
+            args.max_transactions.or(file_config.max_transactions),
+            args.max_tx_ids.or(file_config.max_tx_ids),
+            args.memory_limit_mb.or(file_config.memory_limit_mb),
+        )
+    };
+    let config = match args.eviction_policy.or(file_config.eviction_policy) {
+        Some(eviction_policy) => config.with_eviction_policy(eviction_policy),
+        None => config,
+    };
     let mut engine = PaymentsEngine::new(config);
+    let error_policy = args
+        .error_policy
+        .or(file_config.error_policy)
+        .unwrap_or_default();
+    engine.set_error_policy(error_policy);
+    engine.set_parse_error_limit(args.max_parse_errors.or(file_config.max_parse_errors));
+    let mut decimal_format = DecimalFormat::default();
+    if let Some(decimal_places) = args.decimal_places.or(file_config.decimal_places) {
+        decimal_format.decimal_places = decimal_places;
+    }
+    decimal_format.strip_trailing_zeros =
+        args.strip_trailing_zeros || file_config.strip_trailing_zeros.unwrap_or(false);
+    engine.set_decimal_format(decimal_format);
+    let delimiter = match args
+        .delimiter
+        .as_deref()
+        .or(file_config.delimiter.as_deref())
+        .map(parse_delimiter)
+        .transpose()
+    {
+        Ok(delimiter) => delimiter.unwrap_or(b','),
+        Err(e) => {
+            eprintln!("{}", e);
+            Exit::Usage.exit();
+        }
+    };
+    let mut columns = file_config.columns.clone().unwrap_or_default();
+    for column in &args.columns {
+        let Some((header, name)) = column.split_once('=') else {
+            eprintln!("Invalid --column '{}': expected HEADER=COLUMN", column);
+            Exit::Usage.exit();
+        };
+        columns.insert(header.trim().to_string(), name.trim().to_string());
+    }
+    engine.set_input_format(InputFormat {
+        type_matching: args
+            .type_matching
+            .or(file_config.type_matching)
+            .unwrap_or_default(),
+        amount_format: args
+            .amount_format
+            .or(file_config.amount_format)
+            .unwrap_or_default(),
+        delimiter,
+        has_headers: !args.no_headers && file_config.has_headers.unwrap_or(true),
+        columns,
+        ..InputFormat::default()
+    });
+    let mut policy = file_config.policy.unwrap_or_default();
+    policy.allow_deposit_when_locked |= args.allow_deposit_when_locked;
+    policy.allow_dispute_when_locked |= args.allow_dispute_when_locked;
+    policy.allow_negative_available |= args.allow_negative_available;
+    policy.payout_on_close |= args.payout_on_close;
+    if args.max_open_disputes.is_some() {
+        policy.max_open_disputes = args.max_open_disputes;
+    }
+    if let Some(duplicates) = args.duplicate_policy {
+        policy.duplicates = duplicates;
+    }
+    engine.set_policy(policy);
+    if let Some(schedule) = file_config.recurring.clone()
+        && let Err(e) = engine.set_recurring_schedule(schedule)
+    {
+        log::error!("Invalid recurring schedule: {}", e);
+        Exit::Usage.exit();
+    }
+    let mut dispute_window = file_config.dispute_window.unwrap_or_default();
+    if args.dispute_window_secs.is_some() {
+        dispute_window.max_age_secs = args.dispute_window_secs;
+    }
+    if args.dispute_window_transactions.is_some() {
+        dispute_window.max_transactions = args.dispute_window_transactions;
+    }
+    if !dispute_window.is_unlimited()
+        && let Err(e) = engine.set_dispute_window(dispute_window)
+    {
+        log::error!("Invalid dispute window: {}", e);
+        Exit::Usage.exit();
+    }
+    if let Some(places) = args.max_decimal_places.or(file_config.max_decimal_places) {
+        engine.add_validator(MaxPrecision(places));
+    }
+    if let Some(max) = args.max_amount.or(file_config.max_amount) {
+        engine.add_validator(MaxAmount(max));
+    }
+    let mut blocked_clients = file_config.blocked_clients.unwrap_or_default();
+    blocked_clients.extend(args.blocked_clients);
+    if !blocked_clients.is_empty() {
+        engine.add_validator(ClientBlocklist(blocked_clients.into_iter().collect()));
+    }
+    if let Some(workers) = args.workers.or(file_config.workers) {
+        let engine_info = engine.get_engine_info();
+        if engine_info.concurrent {
+            engine.set_num_workers(workers);
+        } else {
+            log::warn!(
+                "Ignoring workers = {}: the {} engine is single-threaded",
+                workers,
+                engine_info.engine_type
+            );
+        }
+    }
+
+    #[cfg(feature = "events")]
+    let publisher = open_event_publisher(
+        args.events_nats.as_deref(),
+        &args.events_subject,
+        args.events_file.as_deref(),
+    );
+    #[cfg(feature = "events")]
+    let outbox = args.events_outbox.as_deref().map(|path| {
+        Outbox::open(path).unwrap_or_else(|e| {
+            log::error!("Failed to open outbox {:?}: {}", path, e);
+            Exit::Usage.exit();
+        })
+    });
+    #[cfg(feature = "events")]
+    let (event_sink, relay) = match (&outbox, &args.events_outbox) {
+        (Some(outbox), Some(path)) => (
+            Some(EventSink::new(outbox.clone())),
+            publisher.map(|publisher| spawn_outbox_relay(path, publisher)),
+        ),
+        _ => (publisher.map(EventSink::new), None),
+    };
+    #[cfg(feature = "events")]
+    if let Some(sink) = &event_sink {
+        engine.add_observer(sink.clone());
+    }
+    #[cfg(feature = "events")]
+    let flush_events = || {
+        if let Some(sink) = &event_sink
+            && let Err(e) = sink.flush()
+        {
+            log::error!("{}", e);
+            Exit::Internal.exit();
+        }
+    };
+    #[cfg(not(feature = "events"))]
+    let flush_events = || {};
+
+    #[cfg(feature = "risk")]
+    let risk_monitor = args.risk_report.as_ref().map(|_| {
+        let monitor = RiskMonitor::new(file_config.risk.clone().unwrap_or_default());
+        engine.add_observer(monitor.clone());
+        monitor
+    });
+
+    #[cfg(feature = "aml")]
+    let aml_monitor = args.compliance_report.as_ref().map(|_| {
+        let monitor = AmlMonitor::new(file_config.aml.clone().unwrap_or_default());
+        engine.add_observer(monitor.clone());
+        monitor
+    });
+
+    // Also counts the rejections checked against --max-rejected
+    let summary = (args.summary || args.max_rejected.is_some()).then(|| {
+        let collector = SummaryCollector::new();
+        engine.add_observer(collector.clone());
+        collector
+    });
+
+    let settlement = args.settlement_report.as_ref().map(|_| {
+        let collector = SettlementCollector::new().with_decimal_format(engine.decimal_format());
+        engine.add_observer(collector.clone());
+        collector
+    });
+
+    let activity = args.activity_report.as_ref().map(|_| {
+        let collector = ActivityCollector::new(args.activity_bucket);
+        engine.add_observer(collector.clone());
+        collector
+    });
+
+    let disputes = args.dispute_report.as_ref().map(|_| {
+        let tracker = DisputeTracker::new();
+        engine.add_observer(tracker.clone());
+        tracker
+    });
+
+    let locks = args.locked_report.as_ref().map(|_| {
+        let recorder = LockRecorder::new().with_decimal_format(engine.decimal_format());
+        engine.add_observer(recorder.clone());
+        recorder
+    });
+
+    let history = args.statement_client.map(|client| {
+        let recorder =
+            HistoryRecorder::for_clients([client]).with_decimal_format(engine.decimal_format());
+        engine.add_observer(recorder.clone());
+        (client, recorder)
+    });
+
+    let output_options = OutputOptions {
+        changed_only: args.changed_only,
+        filter: AccountFilter {
+            clients: args.clients.iter().copied().collect(),
+            only_locked: args.only_locked,
+            min_total: args.min_total,
+        },
+        shards: args.output_shards,
+    };
+
+    let shutdown = ShutdownFlag::new();
+    if let Err(e) = shutdown.install_signal_handler() {
+        log::warn!("Failed to install signal handler: {}", e);
+    }
+    engine.set_shutdown_flag(shutdown.clone());
+
+    if let Some(seed_path) = args.seed_accounts.or(file_config.seed_accounts) {
+        std::fs::File::open(&seed_path)
+            .map_err(PaymentsError::from)
+            .and_then(|file| engine.load_accounts(std::io::BufReader::new(file)))
+            .unwrap_or_else(|e| {
+                log::error!("Failed to seed accounts from {:?}: {}", seed_path, e);
+                Exit::of(&e).exit();
+            });
+    }
 
     let engine_info = engine.get_engine_info();
     log::info!(
@@ -122,40 +1291,438 @@ fn main() {
             limits.max_disputable_transactions,
             limits.max_processed_tx_ids
         );
+        log::info!(
+            "Estimated memory when full: {} MB ({} bytes/account, {} bytes/transaction, {} bytes/tx_id)",
+            limits.estimated_bytes() / (1024 * 1024),
+            limits.estimates.account_bytes,
+            limits.estimates.transaction_bytes,
+            limits.estimates.tx_id_bytes
+        );
     }
 
-    engine
-        .process_transactions_from_file(&input_path)
+    if let Some(schedule_path) = &args.replay_schedule {
+        let schedule = std::fs::File::open(schedule_path)
+            .map_err(PaymentsError::from)
+            .and_then(Schedule::from_reader)
+            .unwrap_or_else(|e| {
+                log::error!("Failed to read schedule {:?}: {}", schedule_path, e);
+                Exit::of(&e).exit();
+            });
+        let input = std::fs::File::open(&input_path).unwrap_or_else(|e| {
+            log::error!("Failed to open input file {:?}: {}", input_path, e);
+            Exit::Usage.exit();
+        });
+        engine
+            .replay_transactions_from_reader(std::io::BufReader::new(input), &schedule)
+            .unwrap_or_else(|e| {
+                log::error!("Failed to replay transactions: {}", e);
+                Exit::of(&*e).exit();
+            });
+    } else if let Some(checkpoint_path) = &args.checkpoint {
+        let resume = if args.resume && checkpoint_path.exists() {
+            Some(Checkpoint::load(checkpoint_path).unwrap_or_else(|e| {
+                log::error!("Failed to load checkpoint {:?}: {}", checkpoint_path, e);
+                Exit::of(&e).exit();
+            }))
+        } else {
+            if args.resume {
+                log::warn!(
+                    "No checkpoint at {:?}; starting from the beginning",
+                    checkpoint_path
+                );
+            }
+            None
+        };
+
+        let options = CheckpointOptions {
+            path: checkpoint_path.clone(),
+            interval: args.checkpoint_interval,
+            #[cfg(feature = "events")]
+            outbox,
+            #[cfg(not(feature = "events"))]
+            outbox: None,
+        };
+        process_file_with_checkpoints(
+            &mut engine,
+            &input_path,
+            &options,
+            error_policy,
+            resume,
+            &shutdown,
+        )
         .unwrap_or_else(|e| {
             log::error!("Failed to process transactions: {}", e);
-            std::process::exit(1);
+            Exit::of(&*e).exit();
         });
+        #[cfg(feature = "events")]
+        if let Some((stop, handle)) = relay {
+            stop.request();
+            let result = handle
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            if let Err(e) = result {
+                log::error!("Failed to relay outbox events: {}", e);
+                Exit::Internal.exit();
+            }
+        }
+    } else if args.watch {
+        let output_path = args.output.or(file_config.output);
+        let options = WatchOptions {
+            poll_interval: Duration::from_millis(args.poll_interval_ms),
+            snapshot_interval: Duration::from_secs(args.snapshot_interval_secs),
+            #[cfg(all(feature = "admin", unix))]
+            admin: args.admin_socket.as_deref().map(|path| AdminSocket {
+                channel: AdminChannel::bind(path).unwrap_or_else(|e| {
+                    log::error!("Failed to open admin socket {:?}: {}", path, e);
+                    Exit::Usage.exit();
+                }),
+                draining: false,
+                relocked: Vec::new(),
+            }),
+            #[cfg(feature = "websocket")]
+            feed: args.websocket.as_deref().map(|address| LiveFeed {
+                feed: AccountFeed::bind(address).unwrap_or_else(|e| {
+                    log::error!("Failed to open account feed on {}: {}", address, e);
+                    Exit::Usage.exit();
+                }),
+                changes: engine.subscribe_account_changes(),
+            }),
+        };
+        watch(
+            &mut engine,
+            &input_path,
+            output_path.as_deref(),
+            &output_options,
+            options,
+            &shutdown,
+        )
+        .unwrap_or_else(|e| {
+            log::error!("Watch mode stopped: {}", e);
+            Exit::of(&*e).exit();
+        });
+        flush_events();
+        return;
+    } else {
+        if args.record_schedule.is_some() {
+            engine.enable_schedule_recording();
+        }
+
+        engine
+            .process_transactions_from_file(&input_path)
+            .unwrap_or_else(|e| {
+                log::error!("Failed to process transactions: {}", e);
+                Exit::of(&*e).exit();
+            });
+    }
+
+    if let Some(schedule_path) = &args.record_schedule {
+        match engine.take_schedule() {
+            Some(schedule) => {
+                let result = std::fs::File::create(schedule_path)
+                    .map_err(PaymentsError::from)
+                    .and_then(|file| schedule.write_csv(std::io::BufWriter::new(file)));
+                if let Err(e) = result {
+                    log::error!("Failed to write schedule {:?}: {}", schedule_path, e);
+                    Exit::Internal.exit();
+                }
+                log::info!("Schedule written to {:?}", schedule_path);
+            }
+            None => log::warn!(
+                "{} engine applies transactions in input order; no schedule recorded",
+                engine_info.engine_type
+            ),
+        }
+    }
+
+    if shutdown.is_requested() {
+        log::warn!("Interrupted; writing accounts processed so far");
+    } else if args.apply_fees {
+        let Some(schedule) = &file_config.fees else {
+            log::error!("--apply-fees needs a [fees] section in the config file");
+            Exit::Usage.exit();
+        };
+        if let Err(e) = engine.apply_fee_schedule(schedule) {
+            log::error!("Failed to apply fee schedule: {}", e);
+            Exit::of(&e).exit();
+        }
+    }
+
+    #[cfg(feature = "concurrent")]
+    for stats in engine.get_worker_stats() {
+        log::info!(
+            "Worker {}: {} processed, {} errors, {} accounts, {} clients taken over ({} records)",
+            stats.worker_id,
+            stats.processed,
+            stats.errors,
+            stats.accounts,
+            stats.steals,
+            stats.stolen
+        );
+    }
+    #[cfg(feature = "concurrent")]
+    if let Some(stats) = engine.pipeline_stats() {
+        for (stage, stats) in [
+            ("read", stats.read),
+            ("parse", stats.parse),
+            ("apply", stats.apply),
+        ] {
+            log::info!(
+                "Stage {}: {} rows, busy {:?}, waited {:?} for input and {:?} for the next stage",
+                stage,
+                stats.rows,
+                stats.busy,
+                stats.input_wait,
+                stats.output_wait
+            );
+        }
+    }
+
+    flush_events();
+
+    #[cfg(feature = "risk")]
+    if let (Some(monitor), Some(path)) = (&risk_monitor, &args.risk_report) {
+        let result = std::fs::File::create(path)
+            .map_err(PaymentsError::from)
+            .and_then(|file| monitor.write_report_csv(std::io::BufWriter::new(file)));
+        if let Err(e) = result {
+            log::error!("Failed to write risk report {:?}: {}", path, e);
+            Exit::Internal.exit();
+        }
+        log::info!("Risk report written to {:?}", path);
+    }
+
+    #[cfg(feature = "aml")]
+    if let (Some(monitor), Some(path)) = (&aml_monitor, &args.compliance_report) {
+        let result = std::fs::File::create(path)
+            .map_err(PaymentsError::from)
+            .and_then(|file| monitor.write_report_csv(std::io::BufWriter::new(file)));
+        if let Err(e) = result {
+            log::error!("Failed to write compliance report {:?}: {}", path, e);
+            Exit::Internal.exit();
+        }
+        log::info!("Compliance report written to {:?}", path);
+    }
+
+    if let (Some((client, recorder)), Some(path)) = (&history, &args.statement) {
+        let result = std::fs::File::create(path)
+            .map_err(PaymentsError::from)
+            .and_then(|file| {
+                let writer = std::io::BufWriter::new(file);
+                match args.statement_format {
+                    ReportFormat::Csv => recorder.write_statement_csv(*client, writer),
+                    ReportFormat::Json => recorder.write_statement_json(*client, writer),
+                }
+            });
+        if let Err(e) = result {
+            log::error!("Failed to write statement {:?}: {}", path, e);
+            Exit::Internal.exit();
+        }
+        log::info!("Statement for client {} written to {:?}", client, path);
+    }
+
+    if let (Some(collector), Some(path)) = (&settlement, &args.settlement_report) {
+        let result = std::fs::File::create(path)
+            .map_err(PaymentsError::from)
+            .and_then(|file| {
+                let writer = std::io::BufWriter::new(file);
+                match args.settlement_format {
+                    ReportFormat::Csv => collector.write_report_csv(writer),
+                    ReportFormat::Json => collector.write_report_json(writer),
+                }
+            });
+        if let Err(e) = result {
+            log::error!("Failed to write settlement report {:?}: {}", path, e);
+            Exit::Internal.exit();
+        }
+        log::info!("Settlement report written to {:?}", path);
+    }
+
+    if let (Some(collector), Some(path)) = (&activity, &args.activity_report) {
+        let result = std::fs::File::create(path)
+            .map_err(PaymentsError::from)
+            .and_then(|file| {
+                collector.write_report_csv(args.activity_by_client, std::io::BufWriter::new(file))
+            });
+        if let Err(e) = result {
+            log::error!("Failed to write activity report {:?}: {}", path, e);
+            Exit::Internal.exit();
+        }
+        log::info!("Activity report written to {:?}", path);
+    }
+
+    if let (Some(tracker), Some(path)) = (&disputes, &args.dispute_report) {
+        let result = std::fs::File::create(path)
+            .map_err(PaymentsError::from)
+            .and_then(|file| tracker.write_report_csv(std::io::BufWriter::new(file)));
+        if let Err(e) = result {
+            log::error!("Failed to write dispute report {:?}: {}", path, e);
+            Exit::Internal.exit();
+        }
+        log::info!("Dispute report written to {:?}", path);
+    }
+
+    if let (Some(recorder), Some(path)) = (&locks, &args.locked_report) {
+        let result = std::fs::File::create(path)
+            .map_err(PaymentsError::from)
+            .and_then(|file| recorder.write_report_csv(std::io::BufWriter::new(file)));
+        if let Err(e) = result {
+            log::error!("Failed to write locked accounts report {:?}: {}", path, e);
+            Exit::Internal.exit();
+        }
+        log::info!("Locked accounts report written to {:?}", path);
+    }
+
+    if let Some(collector) = summary.as_ref().filter(|_| args.summary) {
+        match collector.summarize(&engine) {
+            Ok(summary) => {
+                let _ = writeln!(output::router().diagnostics(), "{}", summary);
+            }
+            Err(e) => {
+                log::error!("Failed to summarize accounts: {}", e);
+                Exit::Internal.exit();
+            }
+        }
+    }
+
+    if args.verify_invariants {
+        let result = if args.repair_totals {
+            engine
+                .repair_invariants()
+                .and_then(|_| engine.verify_invariants())
+        } else {
+            engine.verify_invariants()
+        };
+        match result {
+            Ok(report) if report.is_clean() => {
+                log::info!("Invariant check passed: {}", report);
+            }
+            Ok(report) => {
+                log::error!("Invariant check failed: {}", report);
+                Exit::Internal.exit();
+            }
+            Err(e) => {
+                log::error!("Failed to check invariants: {}", e);
+                Exit::Internal.exit();
+            }
+        }
+    }
 
     let final_info = engine.get_engine_info();
     log::info!(
         "Processing completed. Final account count: {}",
         final_info.account_count
     );
+    let stats = &final_info.stats;
+    log::info!(
+        "Latency over {} transactions - p50: {:?}, p95: {:?}, p99: {:?}, max: {:?}; throughput: {:.0} tx/s",
+        stats.transactions,
+        stats.p50,
+        stats.p95,
+        stats.p99,
+        stats.max,
+        stats.throughput
+    );
     if let Some(tx_count) = final_info.transaction_count {
         log::info!("Disputable transactions in memory: {}", tx_count);
     }
-    let output_path = args.output;
+    if let Some(path) = &args.stats_output {
+        let contents = match args.stats_format {
+            StatsFormat::Json => serde_json::to_string_pretty(&final_info)
+                .map(|json| json + "\n")
+                .map_err(|e| PaymentsError::IoError(e.into())),
+            StatsFormat::Table => Ok(final_info.to_string()),
+        };
+        let result = contents
+            .and_then(|contents| std::fs::write(path, contents).map_err(PaymentsError::from));
+        if let Err(e) = result {
+            log::error!("Failed to write engine stats {:?}: {}", path, e);
+            Exit::Internal.exit();
+        }
+        log::info!("Engine stats written to {:?}", path);
+    }
+    let output_path = args.output.or(file_config.output);
+    write_output(&mut engine, output_path.as_deref(), &output_options).unwrap_or_else(|e| {
+        log::error!("Failed to write accounts to CSV: {}", e);
+        Exit::Internal.exit();
+    });
     if let Some(path) = output_path {
-        let file = std::fs::File::create(&path).unwrap_or_else(|e| {
-            log::error!("Failed to create output file {:?}: {}", path, e);
-            std::process::exit(1);
-        });
-        let writer = std::io::BufWriter::new(file);
-        engine.write_accounts_csv(writer).unwrap_or_else(|e| {
-            log::error!("Failed to write accounts to CSV: {}", e);
-            std::process::exit(1);
-        });
         log::info!("Accounts written to {:?}", path);
-    } else {
-        let writer = std::io::stdout();
-        engine.write_accounts_csv(writer).unwrap_or_else(|e| {
-            log::error!("Failed to write accounts to stdout: {}", e);
-            std::process::exit(1);
+    }
+
+    #[cfg(feature = "arrow")]
+    for (path, ledger) in [(&args.arrow_output, false), (&args.arrow_ledger, true)] {
+        let Some(path) = path else {
+            continue;
+        };
+        let result = std::fs::File::create(path)
+            .map_err(PaymentsError::from)
+            .and_then(|file| {
+                let writer = std::io::BufWriter::new(file);
+                if ledger {
+                    engine.write_ledger_ipc(writer)
+                } else {
+                    engine.write_accounts_ipc(writer)
+                }
+            });
+        if let Err(e) = result {
+            log::error!("Failed to write Arrow file {:?}: {}", path, e);
+            Exit::Internal.exit();
+        }
+        log::info!("Arrow file written to {:?}", path);
+    }
+
+    if let (Some(limit), Some(collector)) = (&args.max_rejected, &summary) {
+        let counts = collector.counts();
+        let rejected: u64 = counts.iter().map(|counts| counts.rejected).sum();
+        let processed = rejected + counts.iter().map(|counts| counts.accepted).sum::<u64>();
+        if limit.exceeded_by(rejected, processed) {
+            log::warn!(
+                "{} of {} transactions were rejected, more than --max-rejected allows",
+                rejected,
+                processed
+            );
+            Exit::Rejected.exit();
+        }
+    }
+}
+
+/// Connect the event publisher selected on the command line, if any
+#[cfg(feature = "events")]
+fn open_event_publisher(
+    nats_address: Option<&str>,
+    subject: &str,
+    events_file: Option<&Path>,
+) -> Option<Box<dyn EventPublisher>> {
+    if let Some(address) = nats_address {
+        let publisher = NatsPublisher::connect(address, subject).unwrap_or_else(|e| {
+            log::error!("Failed to connect to NATS at {}: {}", address, e);
+            Exit::Usage.exit();
         });
+        return Some(Box::new(publisher));
     }
+    let path = events_file?;
+    let file = std::fs::File::create(path).unwrap_or_else(|e| {
+        log::error!("Failed to open events file {:?}: {}", path, e);
+        Exit::Usage.exit();
+    });
+    Some(Box::new(LinePublisher::new(std::io::BufWriter::new(file))))
+}
+
+/// Relay the outbox log at `path` to `publisher` on a background thread until the
+/// returned flag is requested
+#[cfg(feature = "events")]
+fn spawn_outbox_relay(
+    path: &Path,
+    publisher: Box<dyn EventPublisher>,
+) -> (
+    ShutdownFlag,
+    std::thread::JoinHandle<Result<(), PaymentsError>>,
+) {
+    let stop = ShutdownFlag::new();
+    let mut relay = OutboxRelay::new(path, publisher);
+    let handle = std::thread::spawn({
+        let stop = stop.clone();
+        move || relay.follow(Duration::from_millis(200), &stop)
+    });
+    (stop, handle)
 }
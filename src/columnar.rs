@@ -0,0 +1,251 @@
+//! Account state and the transaction ledger as Arrow record batches, for analytics that
+//! read columnar data instead of reparsing the CSV output.
+//!
+//! `write_accounts_ipc` and `write_ledger_ipc` write Arrow IPC files, which Polars reads
+//! as DataFrames without conversion (`pl.read_ipc` in Python, `IpcReader` in Rust), as do
+//! pyarrow, pandas and DuckDB. Amounts are `Decimal128(38, 4)`, rounded to four decimal
+//! places like the CSV output's default.
+
+use std::io::Write;
+use std::sync::Arc;
+
+use arrow::array::{
+    ArrayRef, BooleanArray, Decimal128Array, StringArray, UInt16Array, UInt32Array,
+};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::error::ArrowError;
+use arrow::ipc::writer::FileWriter;
+use arrow::record_batch::RecordBatch;
+
+use crate::account::Account;
+use crate::errors::PaymentsError;
+use crate::transaction::{Amount, DisputeCase, StoredTransaction, TxId};
+
+/// Decimal places kept in amount columns
+pub const AMOUNT_SCALE: i8 = 4;
+
+/// Rows per record batch written by `write_accounts_ipc` and `write_ledger_ipc`
+pub const BATCH_ROWS: usize = 64 * 1024;
+
+fn amount_type() -> DataType {
+    DataType::Decimal128(38, AMOUNT_SCALE)
+}
+
+/// Columns of `accounts_batch`, in the order of the CSV output
+pub fn accounts_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("client", DataType::UInt16, false),
+        Field::new("available", amount_type(), false),
+        Field::new("held", amount_type(), false),
+        Field::new("total", amount_type(), false),
+        Field::new("locked", DataType::Boolean, false),
+        Field::new("closed", DataType::Boolean, false),
+        Field::new("lock_state", DataType::Utf8, false),
+        Field::new("lock_reason", DataType::Utf8, true),
+    ]))
+}
+
+/// Columns of `ledger_batch`: the deposits and withdrawals kept for disputes
+pub fn ledger_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("tx", DataType::UInt32, false),
+        Field::new("client", DataType::UInt16, false),
+        Field::new("amount", amount_type(), false),
+        Field::new("withdrawal", DataType::Boolean, false),
+        Field::new("disputed", DataType::Boolean, false),
+        Field::new("reason_code", DataType::Utf8, true),
+        Field::new("case_ref", DataType::Utf8, true),
+    ]))
+}
+
+fn arrow_error(e: ArrowError) -> PaymentsError {
+    PaymentsError::IoError(std::io::Error::other(e))
+}
+
+fn amounts(values: impl Iterator<Item = Amount>) -> Result<ArrayRef, PaymentsError> {
+    let values: Vec<i128> = values
+        .map(|amount| amount.rescale(AMOUNT_SCALE as u32).value().mantissa())
+        .collect();
+    let array = Decimal128Array::from(values)
+        .with_precision_and_scale(38, AMOUNT_SCALE)
+        .map_err(arrow_error)?;
+    Ok(Arc::new(array))
+}
+
+/// One record batch of `accounts`
+pub fn accounts_batch(accounts: &[Account]) -> Result<RecordBatch, PaymentsError> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt16Array::from_iter_values(
+            accounts.iter().map(|account| u16::from(account.client)),
+        )),
+        amounts(accounts.iter().map(|account| account.available))?,
+        amounts(accounts.iter().map(|account| account.held))?,
+        amounts(accounts.iter().map(|account| account.total))?,
+        Arc::new(BooleanArray::from_iter(
+            accounts.iter().map(|account| Some(account.is_locked())),
+        )),
+        Arc::new(BooleanArray::from_iter(
+            accounts.iter().map(|account| Some(account.closed)),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            accounts.iter().map(|account| account.lock.as_str()),
+        )),
+        Arc::new(StringArray::from_iter(
+            accounts
+                .iter()
+                .map(|account| account.lock_reason.as_deref()),
+        )),
+    ];
+    RecordBatch::try_new(accounts_schema(), columns).map_err(arrow_error)
+}
+
+/// One record batch of `records`, as in `EngineState::disputable_transactions`
+pub fn ledger_batch(records: &[(TxId, StoredTransaction)]) -> Result<RecordBatch, PaymentsError> {
+    let case = |field: fn(&DisputeCase) -> &Option<String>| {
+        StringArray::from_iter(
+            records
+                .iter()
+                .map(|(_, record)| record.case.as_deref().and_then(|case| field(case).clone())),
+        )
+    };
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt32Array::from_iter_values(
+            records.iter().map(|(tx, _)| u32::from(*tx)),
+        )),
+        Arc::new(UInt16Array::from_iter_values(
+            records.iter().map(|(_, record)| u16::from(record.client)),
+        )),
+        amounts(records.iter().map(|(_, record)| record.amount))?,
+        Arc::new(BooleanArray::from_iter(
+            records.iter().map(|(_, record)| Some(record.withdrawal)),
+        )),
+        Arc::new(BooleanArray::from_iter(
+            records.iter().map(|(_, record)| Some(record.disputed)),
+        )),
+        Arc::new(case(|case| &case.reason_code)),
+        Arc::new(case(|case| &case.case_ref)),
+    ];
+    RecordBatch::try_new(ledger_schema(), columns).map_err(arrow_error)
+}
+
+/// Write `rows` as an Arrow IPC file of `BATCH_ROWS`-row batches made by `batch`
+fn write_ipc<W: Write, T>(
+    writer: W,
+    schema: SchemaRef,
+    rows: impl IntoIterator<Item = Result<T, PaymentsError>>,
+    batch: fn(&[T]) -> Result<RecordBatch, PaymentsError>,
+) -> Result<(), PaymentsError> {
+    let mut writer = FileWriter::try_new(writer, &schema).map_err(arrow_error)?;
+    let mut buffer = Vec::with_capacity(BATCH_ROWS);
+    for row in rows {
+        buffer.push(row?);
+        if buffer.len() == BATCH_ROWS {
+            writer.write(&batch(&buffer)?).map_err(arrow_error)?;
+            buffer.clear();
+        }
+    }
+    if !buffer.is_empty() {
+        writer.write(&batch(&buffer)?).map_err(arrow_error)?;
+    }
+    writer.finish().map_err(arrow_error)
+}
+
+/// Write `accounts` as an Arrow IPC file with the columns of `accounts_schema`
+pub fn write_accounts_ipc<W: Write>(
+    writer: W,
+    accounts: impl IntoIterator<Item = Result<Account, PaymentsError>>,
+) -> Result<(), PaymentsError> {
+    write_ipc(writer, accounts_schema(), accounts, accounts_batch)
+}
+
+/// Write `records` as an Arrow IPC file with the columns of `ledger_schema`
+pub fn write_ledger_ipc<W: Write>(
+    writer: W,
+    records: impl IntoIterator<Item = (TxId, StoredTransaction)>,
+) -> Result<(), PaymentsError> {
+    write_ipc(
+        writer,
+        ledger_schema(),
+        records.into_iter().map(Ok),
+        ledger_batch,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use arrow::array::{Array, AsArray};
+    use arrow::datatypes::{Decimal128Type, UInt16Type};
+    use arrow::ipc::reader::FileReader;
+
+    use super::*;
+    use crate::account::{ClientId, LockState};
+
+    #[test]
+    fn test_accounts_ipc_round_trip() {
+        let mut account = Account::new(ClientId::new(7));
+        account.available = Amount::new(15, 1);
+        account.total = Amount::new(15, 1);
+        account.set_lock(LockState::AdminFreeze, Some("case 12".to_string()));
+        let accounts = vec![Account::new(ClientId::new(1)), account];
+
+        let mut file = Vec::new();
+        write_accounts_ipc(&mut file, accounts.into_iter().map(Ok)).unwrap();
+        let batches: Vec<RecordBatch> = FileReader::try_new(Cursor::new(file), None)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.schema(), accounts_schema());
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(batch.column(0).as_primitive::<UInt16Type>().value(1), 7);
+        let available = batch.column(1).as_primitive::<Decimal128Type>();
+        assert_eq!(available.value_as_string(1), "1.5000");
+        assert!(batch.column(4).as_boolean().value(1));
+        assert!(!batch.column(4).as_boolean().value(0));
+        assert_eq!(batch.column(6).as_string::<i32>().value(1), "admin_freeze");
+        assert_eq!(batch.column(7).as_string::<i32>().value(1), "case 12");
+        assert!(batch.column(7).is_null(0));
+    }
+
+    #[test]
+    fn test_ledger_batch() {
+        let records = vec![
+            (
+                TxId::new(1),
+                StoredTransaction {
+                    client: ClientId::new(2),
+                    amount: Amount::new(123456, 5),
+                    disputed: true,
+                    withdrawal: false,
+                    case: Some(Box::new(DisputeCase {
+                        reason_code: Some("10.4".to_string()),
+                        case_ref: None,
+                    })),
+                },
+            ),
+            (
+                TxId::new(2),
+                StoredTransaction {
+                    client: ClientId::new(2),
+                    amount: Amount::new(5, 0),
+                    disputed: false,
+                    withdrawal: true,
+                    case: None,
+                },
+            ),
+        ];
+
+        let batch = ledger_batch(&records).unwrap();
+        assert_eq!(batch.num_rows(), 2);
+        let amount = batch.column(2).as_primitive::<Decimal128Type>();
+        assert_eq!(amount.value_as_string(0), "1.2346");
+        let reason_code = batch.column(5).as_string::<i32>();
+        assert_eq!(reason_code.value(0), "10.4");
+        assert!(reason_code.is_null(1));
+        assert!(batch.column(6).is_null(0));
+    }
+}
@@ -0,0 +1,289 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::account::ClientId;
+use crate::engine::cache::EvictionPolicy;
+use crate::engine::expiry::DisputeWindow;
+use crate::engine::fees::FeeSchedule;
+use crate::engine::recurring::RecurringSchedule;
+use crate::engine::{EnginePolicy, ErrorPolicy, ParseErrorLimit};
+use crate::errors::PaymentsError;
+use crate::transaction::{Amount, AmountFormat, TypeMatching};
+
+/// Settings read from a `--config` TOML file.
+/// Every field is optional; command-line flags take precedence over values set here.
+///
+/// ```toml
+/// engine = "bounded"
+/// max_accounts = 10000
+/// max_transactions = 50000
+/// max_tx_ids = 1000000
+/// eviction_policy = "lfu"
+/// workers = 8
+/// error_policy = "skip"
+/// type_matching = "lenient"
+/// amount_format = "plain"
+/// output = "accounts.csv"
+/// decimal_places = 4
+/// log_level = "warn"
+/// max_decimal_places = 4
+/// max_amount = "1000000"
+/// blocked_clients = [13, 42]
+///
+/// [policy]
+/// allow_deposit_when_locked = true
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    /// Engine type: standard, bounded, or concurrent
+    pub engine: Option<String>,
+
+    /// Maximum accounts in memory (bounded/concurrent)
+    pub max_accounts: Option<usize>,
+
+    /// Maximum disputable transactions in memory (bounded/concurrent)
+    pub max_transactions: Option<usize>,
+
+    /// Maximum processed transaction IDs in memory (bounded/concurrent)
+    pub max_tx_ids: Option<usize>,
+
+    /// Auto-configure the bounded engine for this memory budget in MB
+    pub memory_limit_mb: Option<usize>,
+
+    /// Which entries the bounded engine evicts when full: lru, lfu, fifo or segmented
+    pub eviction_policy: Option<EvictionPolicy>,
+
+    /// Accounts CSV to seed balances from before processing
+    pub seed_accounts: Option<PathBuf>,
+
+    /// Worker threads for the concurrent engine, or actors for the actor engine.
+    /// The single-threaded engines ignore it with a warning.
+    pub workers: Option<usize>,
+
+    /// Whether to skip or abort on bad rows and rejected transactions
+    pub error_policy: Option<ErrorPolicy>,
+
+    /// Parse failures after which processing fails: a count (`100`) or a percentage (`"5%"`)
+    pub max_parse_errors: Option<ParseErrorLimit>,
+
+    /// Whether the `type` column must be spelled exactly: lenient or strict
+    pub type_matching: Option<TypeMatching>,
+
+    /// How the `amount` column is written: plain, grouped or european
+    pub amount_format: Option<AmountFormat>,
+
+    /// Field separator of the input: a single character, or `tab`
+    pub delimiter: Option<String>,
+
+    /// Whether the input starts with a header row (defaults to true)
+    pub has_headers: Option<bool>,
+
+    /// Input header names to read as another column, e.g. `transaction_type = "type"`
+    pub columns: Option<BTreeMap<String, String>>,
+
+    /// Output file path (defaults to stdout)
+    pub output: Option<PathBuf>,
+
+    /// Decimal places of output balances (defaults to 4)
+    pub decimal_places: Option<u32>,
+
+    /// Write output balances without padding zeros
+    pub strip_trailing_zeros: Option<bool>,
+
+    /// Log level (error, warn, info, debug, trace)
+    pub log_level: Option<String>,
+
+    /// Reject amounts with more decimal places than this
+    pub max_decimal_places: Option<u32>,
+
+    /// Reject deposits and withdrawals above this amount
+    pub max_amount: Option<Amount>,
+
+    /// Clients whose transactions are all rejected
+    pub blocked_clients: Option<Vec<ClientId>>,
+
+    /// Which operations are still allowed on locked accounts
+    pub policy: Option<EnginePolicy>,
+
+    /// Fees and interest applied after processing with `--apply-fees`
+    pub fees: Option<FeeSchedule>,
+
+    /// Recurring deposits and withdrawals expanded as row timestamps advance
+    pub recurring: Option<RecurringSchedule>,
+
+    /// How long deposits and withdrawals stay disputable
+    pub dispute_window: Option<DisputeWindow>,
+
+    /// Thresholds for the flags in the `--risk-report`
+    #[cfg(feature = "risk")]
+    pub risk: Option<crate::risk::RiskConfig>,
+
+    /// Thresholds for the `--compliance-report`
+    #[cfg(feature = "aml")]
+    pub aml: Option<crate::aml::AmlConfig>,
+}
+
+impl FileConfig {
+    /// Parse a config from TOML text
+    pub fn from_toml_str(contents: &str) -> Result<Self, PaymentsError> {
+        toml::from_str(contents).map_err(|e| PaymentsError::ConfigError(e.to_string()))
+    }
+
+    /// Read and parse a TOML config file
+    pub fn from_path(path: &Path) -> Result<Self, PaymentsError> {
+        let contents = std::fs::read_to_string(path)?;
+        Self::from_toml_str(&contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::DuplicatePolicy;
+    use crate::engine::fees::FeeRule;
+    use crate::transaction::TxId;
+
+    #[test]
+    fn test_parse_full_config() {
+        let config = FileConfig::from_toml_str(
+            r#"
+            engine = "concurrent"
+            max_accounts = 100
+            eviction_policy = "segmented"
+            workers = 3
+            error_policy = "abort"
+            max_parse_errors = "2.5%"
+            type_matching = "strict"
+            amount_format = "european"
+            delimiter = "tab"
+            has_headers = true
+            output = "out.csv"
+            decimal_places = 2
+            strip_trailing_zeros = true
+
+            [columns]
+            transaction_type = "type"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.engine.as_deref(), Some("concurrent"));
+        assert_eq!(config.max_accounts, Some(100));
+        assert_eq!(config.max_transactions, None);
+        assert_eq!(config.eviction_policy, Some(EvictionPolicy::Segmented));
+        assert_eq!(config.workers, Some(3));
+        assert_eq!(config.error_policy, Some(ErrorPolicy::Abort));
+        assert_eq!(config.max_parse_errors, Some(ParseErrorLimit::Percent(2.5)));
+        assert_eq!(config.type_matching, Some(TypeMatching::Strict));
+        assert_eq!(config.amount_format, Some(AmountFormat::European));
+        assert_eq!(config.delimiter.as_deref(), Some("tab"));
+        assert_eq!(config.has_headers, Some(true));
+        assert_eq!(
+            config.columns.unwrap()["transaction_type"],
+            "type".to_string()
+        );
+        assert_eq!(config.output, Some(PathBuf::from("out.csv")));
+        assert_eq!(config.decimal_places, Some(2));
+        assert_eq!(config.strip_trailing_zeros, Some(true));
+    }
+
+    #[test]
+    fn test_parse_validators() {
+        let config = FileConfig::from_toml_str(
+            r#"
+            max_decimal_places = 4
+            max_amount = "1000.5"
+            blocked_clients = [13, 42]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.max_decimal_places, Some(4));
+        assert_eq!(config.max_amount, Some(Amount::new(10005, 1)));
+        assert_eq!(
+            config.blocked_clients,
+            Some(vec![ClientId::new(13), ClientId::new(42)])
+        );
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        let config = FileConfig::from_toml_str(
+            r#"
+            [policy]
+            allow_deposit_when_locked = true
+            duplicates = "skip_silently"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.policy,
+            Some(EnginePolicy {
+                allow_deposit_when_locked: true,
+                allow_dispute_when_locked: false,
+                allow_negative_available: false,
+                max_open_disputes: None,
+                payout_on_close: false,
+                duplicates: DuplicatePolicy::SkipSilently,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_dispute_window() {
+        let config = FileConfig::from_toml_str(
+            r#"
+            [dispute_window]
+            max_age_secs = 7776000
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.dispute_window,
+            Some(DisputeWindow {
+                max_age_secs: Some(7_776_000),
+                max_transactions: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_fees() {
+        let config = FileConfig::from_toml_str(
+            r#"
+            [fees]
+            first_tx_id = 900
+
+            [[fees.rules]]
+            kind = "maintenance_fee"
+            amount = "2.5"
+
+            [[fees.rules]]
+            kind = "interest"
+            rate = "0.01"
+            "#,
+        )
+        .unwrap();
+        let fees = config.fees.unwrap();
+        assert_eq!(fees.first_tx_id, TxId::new(900));
+        assert_eq!(
+            fees.rules,
+            vec![
+                FeeRule::MaintenanceFee {
+                    amount: Amount::new(25, 1),
+                    waive_above: None,
+                },
+                FeeRule::Interest {
+                    rate: rust_decimal::Decimal::new(1, 2),
+                    min_balance: Amount::ZERO,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_keys_rejected() {
+        let result = FileConfig::from_toml_str("max_acounts = 10");
+        assert!(matches!(result, Err(PaymentsError::ConfigError(_))));
+    }
+}
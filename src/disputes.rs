@@ -0,0 +1,180 @@
+//! Dispute lifecycles for loss-rate analytics.
+//!
+//! `DisputeTracker` is an engine observer recording every dispute the engine accepted,
+//! the amount it held and how it ended: resolved, charged back or still open when the
+//! run finished. Rows the engine processed (accepted or rejected) are numbered from 1,
+//! so `elapsed` is the number of rows between a dispute and its resolve or chargeback.
+//! With the concurrent engine, rows are numbered in the order workers applied them.
+//! A transaction disputed again after a resolve starts a new lifecycle.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::account::ClientId;
+use crate::engine::observer::EngineObserver;
+use crate::errors::PaymentsError;
+use crate::transaction::{Amount, Transaction, TransactionType, TxId};
+
+/// How a dispute ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DisputeOutcome {
+    /// Neither resolved nor charged back yet
+    Open,
+    Resolved,
+    Chargeback,
+}
+
+/// One dispute of a transaction, from the dispute row to its resolve or chargeback
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DisputeLifecycle {
+    pub tx: TxId,
+    pub client: ClientId,
+    /// Amount the dispute put on hold
+    pub amount: Amount,
+    pub outcome: DisputeOutcome,
+    /// Row number of the dispute
+    pub opened: u64,
+    /// Row number of the resolve or chargeback; `None` while open
+    pub closed: Option<u64>,
+    /// Rows from the dispute to its resolve or chargeback; `None` while open
+    pub elapsed: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct Lifecycles {
+    /// Rows the engine processed so far
+    rows: u64,
+    /// Every lifecycle, in the order the disputes were accepted
+    all: Vec<DisputeLifecycle>,
+    /// Index in `all` of each transaction's open dispute
+    open: HashMap<(ClientId, TxId), usize>,
+}
+
+/// Engine observer that records dispute lifecycles. Clones share the same records, so
+/// keep one to write the report after processing.
+#[derive(Debug, Clone, Default)]
+pub struct DisputeTracker {
+    lifecycles: Arc<Mutex<Lifecycles>>,
+}
+
+impl DisputeTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Lifecycles> {
+        match self.lifecycles.lock() {
+            Ok(lifecycles) => lifecycles,
+            Err(e) => e.into_inner(),
+        }
+    }
+
+    /// Every dispute accepted so far, in the order they were opened
+    pub fn report(&self) -> Vec<DisputeLifecycle> {
+        self.lock().all.clone()
+    }
+
+    /// Write the report as CSV with the columns
+    /// `tx,client,amount,outcome,opened,closed,elapsed`; `closed` and `elapsed` are empty
+    /// for open disputes
+    pub fn write_report_csv<W: std::io::Write>(&self, writer: W) -> Result<(), PaymentsError> {
+        let mut wtr = csv::Writer::from_writer(writer);
+        let report = self.report();
+        if report.is_empty() {
+            wtr.write_record([
+                "tx", "client", "amount", "outcome", "opened", "closed", "elapsed",
+            ])?;
+        }
+        for lifecycle in &report {
+            wtr.serialize(lifecycle)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+impl EngineObserver for DisputeTracker {
+    fn on_accepted(&self, transaction: &Transaction) {
+        let mut lifecycles = self.lock();
+        lifecycles.rows += 1;
+        let outcome = match transaction.tx_type {
+            TransactionType::Resolve => DisputeOutcome::Resolved,
+            TransactionType::Chargeback => DisputeOutcome::Chargeback,
+            _ => return,
+        };
+        let rows = lifecycles.rows;
+        let Some(index) = lifecycles
+            .open
+            .remove(&(transaction.client, transaction.tx))
+        else {
+            return;
+        };
+        let lifecycle = &mut lifecycles.all[index];
+        lifecycle.outcome = outcome;
+        lifecycle.closed = Some(rows);
+        lifecycle.elapsed = Some(rows - lifecycle.opened);
+    }
+
+    fn on_rejected(&self, _transaction: &Transaction, _error: &PaymentsError) {
+        self.lock().rows += 1;
+    }
+
+    fn on_dispute_opened(&self, transaction: &Transaction, amount: Amount) {
+        let mut lifecycles = self.lock();
+        let index = lifecycles.all.len();
+        let opened = lifecycles.rows;
+        lifecycles.all.push(DisputeLifecycle {
+            tx: transaction.tx,
+            client: transaction.client,
+            amount,
+            outcome: DisputeOutcome::Open,
+            opened,
+            closed: None,
+            elapsed: None,
+        });
+        lifecycles
+            .open
+            .insert((transaction.client, transaction.tx), index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{EngineConfig, PaymentsEngine};
+
+    #[test]
+    fn test_dispute_lifecycles() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10\n\
+                     deposit,2,2,5\n\
+                     dispute,1,1,\n\
+                     deposit,1,3,1\n\
+                     dispute,2,2,\n\
+                     withdrawal,2,4,100\n\
+                     resolve,1,1,\n\
+                     chargeback,2,2,\n\
+                     dispute,1,1,\n\
+                     dispute,1,9,\n";
+
+        let tracker = DisputeTracker::new();
+        let mut engine = PaymentsEngine::new(EngineConfig::standard());
+        engine.add_observer(tracker.clone());
+        engine
+            .process_transactions_from_reader(input.as_bytes())
+            .unwrap();
+
+        // The rejected withdrawal and dispute still count as rows
+        let mut output = Vec::new();
+        tracker.write_report_csv(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "tx,client,amount,outcome,opened,closed,elapsed\n\
+             1,1,10,resolved,3,7,4\n\
+             2,2,5,chargeback,5,8,3\n\
+             1,1,10,open,9,,\n"
+        );
+    }
+}
@@ -0,0 +1,922 @@
+//! Concurrent engine where every client belongs to one actor and nothing is locked.
+//!
+//! `ActorEngine` splits clients between actors with a `Router`. Each actor is a thread
+//! that owns a `StandardEngine` with its clients' accounts and disputable transactions
+//! and applies the transactions in its mailbox one at a time, so no state is shared
+//! between threads. A dispute always reaches the actor holding the transaction it refers
+//! to, because both rows belong to the same client.
+//!
+//! The reader that fills the mailboxes keeps what no single actor can see: the client
+//! that first used each transaction ID, so an ID reused by a client of another actor is
+//! still rejected, and the recurring schedule, whose occurrences it sends to their
+//! clients' actors before the row that makes them due. Actors give back the IDs of rows
+//! they reject; a row reusing an ID whose first row is still in a mailbox waits for that
+//! row's actor to decide on it. A dispute window limited by
+//! `max_transactions` counts each actor's rows separately.
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+
+use super::concurrent::panic_message;
+use super::expiry::DisputeExpiry;
+use super::metrics::{EngineStats, start_timer};
+use super::observer::{EngineObserver, ObserverList};
+use super::ordering::ClientOrder;
+use super::recurring::RecurringScheduler;
+use super::routing::{ConsistentHashRouter, Router};
+use super::standard::StandardEngine;
+use super::state::EngineState;
+use super::sync::{AtomicBool, AtomicUsize, Ordering};
+use super::validation::{TransactionValidator, ValidatorChain};
+use super::{
+    DuplicatePolicy, EngineInfo, EnginePolicy, ErrorPolicy, ParseErrorLimit, ParseErrorTally,
+    read_transactions, transaction_reader,
+};
+use crate::account::{Account, ClientId, DecimalFormat, LockState};
+use crate::errors::PaymentsError;
+use crate::shutdown::ShutdownFlag;
+use crate::transaction::{InputFormat, Transaction, TransactionType, TxId};
+
+/// Transactions an actor's mailbox holds before the reader waits for it to catch up
+const MAILBOX_CAPACITY: usize = 1024;
+
+/// A transaction in an actor's mailbox
+struct Envelope {
+    /// Input line, or `None` for a recurring occurrence
+    line: Option<u64>,
+    queued_at: Option<Instant>,
+    transaction: Transaction,
+    /// Whether routing claimed the transaction's ID, which the actor gives back if it
+    /// rejects the transaction
+    claimed: bool,
+}
+
+/// Client that used a deposit, withdrawal or close ID
+#[derive(Debug, Clone, Copy)]
+struct TxOwner {
+    client: ClientId,
+    /// Actor and mailbox position of the row that claimed the ID, while that actor may
+    /// not have applied it yet
+    pending: Option<(usize, usize)>,
+}
+
+/// Where `Dispatcher::route` sends a transaction
+#[derive(Debug, Clone, Copy)]
+struct Route {
+    actor: usize,
+    /// Whether the transaction claimed its ID; if it is rejected, `release` the claim
+    claimed: bool,
+}
+
+/// Routes transactions to actors and checks what spans several of them
+#[derive(Debug)]
+struct Dispatcher {
+    router: Box<dyn Router>,
+    /// Client that first used each deposit, withdrawal and close ID. An ID is claimed
+    /// when its row is routed and released again if the actor rejects the row, so
+    /// rejected transactions leave their IDs unused.
+    tx_owners: HashMap<TxId, TxOwner>,
+    /// IDs claimed during the current reader-based run, still marked pending
+    pending: Vec<TxId>,
+    recurring: RecurringScheduler,
+    duplicates: DuplicatePolicy,
+    /// Told about transactions rejected before reaching an actor
+    observers: ObserverList,
+}
+
+impl Dispatcher {
+    fn actor_of(&self, client: ClientId, num_actors: usize) -> usize {
+        self.router.route(client, num_actors)
+    }
+
+    /// Recurring occurrences due before `transaction`, earliest first
+    fn due(&mut self, transaction: &Transaction) -> Vec<Transaction> {
+        match transaction.timestamp {
+            Some(now) if !self.recurring.is_empty() => self.recurring.due(now),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The actor and mailbox position of the row that claimed `transaction`'s ID, if
+    /// that actor may not have decided on it yet. Routing has to wait for the decision,
+    /// as a rejection releases the ID.
+    fn pending_claim(&self, transaction: &Transaction) -> Option<(usize, usize)> {
+        self.tx_owners.get(&transaction.tx)?.pending
+    }
+
+    /// The actor has decided on the row that claimed `tx`: keep the claim unless it was
+    /// released
+    fn settle(&mut self, tx: TxId) {
+        if let Some(owner) = self.tx_owners.get_mut(&tx) {
+            owner.pending = None;
+        }
+    }
+
+    /// Every actor has stopped: give back the IDs of the rows they rejected and keep the
+    /// other claims
+    fn settle_all(&mut self, released: impl IntoIterator<Item = Transaction>) {
+        for rejected in released {
+            self.release(&rejected);
+        }
+        for tx in std::mem::take(&mut self.pending) {
+            self.settle(tx);
+        }
+    }
+
+    /// Give back the ID `transaction` claimed, after its actor rejected it
+    fn release(&mut self, transaction: &Transaction) {
+        if self
+            .tx_owners
+            .get(&transaction.tx)
+            .is_some_and(|owner| owner.client == transaction.client)
+        {
+            self.tx_owners.remove(&transaction.tx);
+        }
+    }
+
+    /// The actor that applies `transaction`, or an error if its ID belongs to a
+    /// transaction of another actor's client. `sent` counts the rows sent to each
+    /// actor's mailbox so far, when the actors run on their own threads.
+    fn route(
+        &mut self,
+        transaction: &Transaction,
+        num_actors: usize,
+        sent: Option<&[usize]>,
+    ) -> Result<Route, PaymentsError> {
+        let actor = self.actor_of(transaction.client, num_actors);
+        let (tx, client) = (transaction.tx, transaction.client);
+        let elsewhere = self
+            .tx_owners
+            .get(&tx)
+            .map(|owner| owner.client)
+            .filter(|owner| self.actor_of(*owner, num_actors) != actor);
+        let mut claimed = false;
+        let result = match (&transaction.tx_type, elsewhere) {
+            (TransactionType::Deposit | TransactionType::Withdrawal, Some(_)) => {
+                self.duplicates.check(transaction, None).map(|()| {
+                    let owner = TxOwner {
+                        client,
+                        pending: None,
+                    };
+                    self.tx_owners.insert(tx, owner);
+                })
+            }
+            (TransactionType::CloseAccount, Some(_)) => Err(PaymentsError::InvalidTransaction(
+                format!("Transaction ID {} already exists", tx),
+            )),
+            (_, Some(owner)) if transaction.amount.is_none() => {
+                Err(PaymentsError::ClientIdMismatch {
+                    tx,
+                    expected: owner,
+                    found: client,
+                })
+            }
+            (
+                TransactionType::Deposit
+                | TransactionType::Withdrawal
+                | TransactionType::CloseAccount,
+                None,
+            ) => {
+                if let Entry::Vacant(entry) = self.tx_owners.entry(tx) {
+                    let pending = sent.map(|sent| (actor, sent[actor] + 1));
+                    entry.insert(TxOwner { client, pending });
+                    if pending.is_some() {
+                        self.pending.push(tx);
+                    }
+                    claimed = true;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        };
+        match result {
+            Ok(()) => Ok(Route { actor, claimed }),
+            Err(e) => {
+                self.observers.rejected(transaction, &e);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Sets its flag and wakes the reader thread when dropped, including while unwinding
+/// from a panic
+struct SetOnDrop<'a>(&'a AtomicBool, thread::Thread);
+
+impl Drop for SetOnDrop<'_> {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Release);
+        self.1.unpark();
+    }
+}
+
+/// Apply the transactions in `mailbox` to `engine` until the reader closes it. Under
+/// `ErrorPolicy::Abort` the first rejected input row stops the actor and tells the reader
+/// through `aborted`, as does an input row that arrives out of order.
+/// Rejected rows whose IDs routing claimed go back to the reader through `released`,
+/// before `handled` counts them. `reader` is woken after each row whose ID was claimed.
+#[allow(clippy::too_many_arguments)]
+fn run_actor(
+    actor_id: usize,
+    engine: &mut StandardEngine,
+    mailbox: mpsc::Receiver<Envelope>,
+    released: mpsc::Sender<Transaction>,
+    reader: &thread::Thread,
+    error_policy: ErrorPolicy,
+    aborted: &AtomicBool,
+    handled: &AtomicUsize,
+    order: &ClientOrder,
+) -> Result<(), PaymentsError> {
+    while let Ok(envelope) = mailbox.recv() {
+        if let Some(line) = envelope.line
+            && let Err(e) = order.check(envelope.transaction.client, line)
+        {
+            aborted.store(true, Ordering::Relaxed);
+            return Err(e);
+        }
+        let result = engine.process_transaction_since(&envelope.transaction, envelope.queued_at);
+        if result.is_err() && envelope.claimed {
+            // The reader is gone only once every actor has stopped
+            let _ = released.send(envelope.transaction.clone());
+        }
+        // Release, so a reader that sees the count also sees the row released above
+        handled.fetch_add(1, Ordering::Release);
+        if envelope.claimed {
+            reader.unpark();
+        }
+        let Err(e) = result else {
+            continue;
+        };
+        let Some(line) = envelope.line else {
+            log::warn!(
+                "Actor {}: Recurring transaction {:?} rejected: {}",
+                actor_id,
+                envelope.transaction,
+                e
+            );
+            continue;
+        };
+        log::error!(
+            "Actor {}: Failed to process transaction {:?}: {}",
+            actor_id,
+            envelope.transaction,
+            e
+        );
+        if error_policy == ErrorPolicy::Abort {
+            aborted.store(true, Ordering::Relaxed);
+            return Err(e.at_line(line));
+        }
+    }
+    Ok(())
+}
+
+/// Engine whose clients are split between actors, each owning its clients' state
+#[derive(Debug)]
+pub struct ActorEngine {
+    /// State of each actor, by actor ID, lent to the actor threads while reading
+    actors: Vec<StandardEngine>,
+    dispatcher: Dispatcher,
+    /// Settings every actor's engine is created with
+    policy: EnginePolicy,
+    decimal_format: DecimalFormat,
+    validators: ValidatorChain,
+    expiry: DisputeExpiry,
+    /// How reader-based processing reacts to bad rows and rejected transactions
+    error_policy: ErrorPolicy,
+    /// How reader-based processing parses the `type` and `amount` columns
+    input_format: InputFormat,
+    /// Parse failures after which reader-based processing fails
+    parse_error_limit: Option<ParseErrorLimit>,
+    /// Checked between records; once requested, no new records are read or dispatched
+    shutdown: ShutdownFlag,
+}
+
+impl ActorEngine {
+    /// An engine with `num_actors` actors (at least one)
+    pub fn new(num_actors: usize) -> Self {
+        let mut engine = Self {
+            actors: Vec::new(),
+            dispatcher: Dispatcher {
+                router: Box::new(ConsistentHashRouter),
+                tx_owners: HashMap::new(),
+                pending: Vec::new(),
+                recurring: RecurringScheduler::default(),
+                duplicates: DuplicatePolicy::default(),
+                observers: ObserverList::default(),
+            },
+            policy: EnginePolicy::default(),
+            decimal_format: DecimalFormat::default(),
+            validators: ValidatorChain::default(),
+            expiry: DisputeExpiry::default(),
+            error_policy: ErrorPolicy::default(),
+            input_format: InputFormat::default(),
+            parse_error_limit: None,
+            shutdown: ShutdownFlag::default(),
+        };
+        engine.actors = (0..num_actors.max(1)).map(|_| engine.new_actor()).collect();
+        engine
+    }
+
+    /// An actor's empty engine with this engine's settings
+    fn new_actor(&self) -> StandardEngine {
+        let mut engine = StandardEngine::new();
+        engine.set_policy(self.policy);
+        engine.set_decimal_format(self.decimal_format);
+        engine.set_validators(self.validators.clone());
+        engine.set_observers(self.dispatcher.observers.clone());
+        engine.set_dispute_expiry(self.expiry.clone());
+        engine
+    }
+
+    pub fn num_actors(&self) -> usize {
+        self.actors.len()
+    }
+
+    /// Split the clients between `num_actors` actors (at least one) instead, moving
+    /// their state to the actors that now own them
+    pub fn set_num_actors(&mut self, num_actors: usize) {
+        let num_actors = num_actors.max(1);
+        if num_actors == self.actors.len() {
+            return;
+        }
+        let state = self.export_state();
+        let changed: Vec<ClientId> = self
+            .actors
+            .iter()
+            .flat_map(|engine| engine.changed_clients().iter().copied())
+            .collect();
+        self.actors = (0..num_actors).map(|_| self.new_actor()).collect();
+        self.import_state(state);
+        for client in changed {
+            let engine = self.actor_for(client);
+            let mut clients = engine.changed_clients().clone();
+            clients.insert(client);
+            engine.set_changed_clients(clients);
+        }
+        log::info!("Split clients between {} actors", num_actors);
+    }
+
+    /// Replace the default `ConsistentHashRouter` used to assign clients to actors.
+    /// Only call this before processing: clients already seen are not moved.
+    pub fn set_router(&mut self, router: impl Router + 'static) {
+        self.dispatcher.router = Box::new(router);
+    }
+
+    fn actor_for(&mut self, client: ClientId) -> &mut StandardEngine {
+        let actor = self.dispatcher.actor_of(client, self.actors.len());
+        &mut self.actors[actor]
+    }
+
+    fn actor_of(&self, client: ClientId) -> &StandardEngine {
+        &self.actors[self.dispatcher.actor_of(client, self.actors.len())]
+    }
+
+    pub fn set_error_policy(&mut self, error_policy: ErrorPolicy) {
+        self.error_policy = error_policy;
+    }
+
+    pub fn set_parse_error_limit(&mut self, limit: Option<ParseErrorLimit>) {
+        self.parse_error_limit = limit;
+    }
+
+    pub fn parse_error_limit(&self) -> Option<ParseErrorLimit> {
+        self.parse_error_limit
+    }
+
+    pub fn set_input_format(&mut self, input_format: InputFormat) {
+        self.input_format = input_format;
+    }
+
+    pub fn input_format(&self) -> &InputFormat {
+        &self.input_format
+    }
+
+    /// Replace the business rules applied to account operations
+    pub fn set_policy(&mut self, policy: EnginePolicy) {
+        self.policy = policy;
+        self.dispatcher.duplicates = policy.duplicates;
+        for engine in &mut self.actors {
+            engine.set_policy(policy);
+        }
+    }
+
+    pub fn policy(&self) -> EnginePolicy {
+        self.policy
+    }
+
+    /// Set how balances are written to account output
+    pub fn set_decimal_format(&mut self, decimal_format: DecimalFormat) {
+        self.decimal_format = decimal_format;
+        for engine in &mut self.actors {
+            engine.set_decimal_format(decimal_format);
+        }
+    }
+
+    pub fn decimal_format(&self) -> DecimalFormat {
+        self.decimal_format
+    }
+
+    /// Run `validator` on every transaction, after the validators already added. Actors
+    /// share it, so it may be called from several threads at once.
+    pub fn add_validator(&mut self, validator: impl TransactionValidator + 'static) {
+        self.validators.push(validator);
+        for engine in &mut self.actors {
+            engine.set_validators(self.validators.clone());
+        }
+    }
+
+    /// Notify `observer` of every transaction processed from now on. Actors share it, so
+    /// it is called from several threads at once.
+    pub fn add_observer(&mut self, observer: impl EngineObserver + 'static) {
+        self.dispatcher.observers.push(observer);
+        for engine in &mut self.actors {
+            engine.set_observers(self.dispatcher.observers.clone());
+        }
+    }
+
+    /// Apply `recurring`'s occurrences as timestamps advance; the reader sends each one
+    /// to its client's actor before the first row stamped at or after it
+    pub fn set_recurring(&mut self, recurring: RecurringScheduler) {
+        self.dispatcher.recurring = recurring;
+    }
+
+    /// Drop disputable transactions once `expiry`'s window ends. Each actor counts its
+    /// own rows towards `max_transactions`.
+    pub fn set_dispute_expiry(&mut self, expiry: DisputeExpiry) {
+        for engine in &mut self.actors {
+            engine.set_dispute_expiry(expiry.clone());
+        }
+        self.expiry = expiry;
+    }
+
+    /// Stop reading new records once `shutdown` is requested. Records already in a
+    /// mailbox are still applied before `process_transactions_from_reader` returns.
+    pub fn set_shutdown_flag(&mut self, shutdown: ShutdownFlag) {
+        self.shutdown = shutdown;
+    }
+
+    /// IDs of the client's transactions that are currently under dispute
+    pub fn open_disputes(&self, client: ClientId) -> Vec<TxId> {
+        self.actor_of(client).open_disputes(client)
+    }
+
+    /// Apply `transaction` on the calling thread, after any recurring occurrences it
+    /// makes due
+    pub fn process_transaction(&mut self, transaction: &Transaction) -> Result<(), PaymentsError> {
+        let num_actors = self.actors.len();
+        for occurrence in self.dispatcher.due(transaction) {
+            let result = self
+                .dispatcher
+                .route(&occurrence, num_actors, None)
+                .and_then(|route| self.apply(route, &occurrence));
+            if let Err(e) = result {
+                log::warn!("Recurring transaction {:?} rejected: {}", occurrence, e);
+            }
+        }
+        let route = self.dispatcher.route(transaction, num_actors, None)?;
+        self.apply(route, transaction)
+    }
+
+    /// Apply a routed transaction on the calling thread, releasing its claimed ID if
+    /// the actor rejects it
+    fn apply(&mut self, route: Route, transaction: &Transaction) -> Result<(), PaymentsError> {
+        let result = self.actors[route.actor].process_transaction(transaction);
+        if result.is_err() && route.claimed {
+            self.dispatcher.release(transaction);
+        }
+        result
+    }
+
+    /// Apply `transactions` in order on the calling thread, returning each one's result
+    pub fn process_transactions(
+        &mut self,
+        transactions: &[Transaction],
+    ) -> Vec<Result<(), PaymentsError>> {
+        transactions
+            .iter()
+            .map(|transaction| self.process_transaction(transaction))
+            .collect()
+    }
+
+    /// Read `reader` on the calling thread and apply each row on the actor owning its
+    /// client, one thread per actor. Rows of different clients may be applied in any
+    /// order; a client's rows are always applied in input order.
+    pub fn process_transactions_from_reader<R: Read>(
+        &mut self,
+        reader: R,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let error_policy = self.error_policy;
+        let mut rdr = transaction_reader(reader, &self.input_format);
+        let mut lines = read_transactions(&mut rdr, self.input_format.clone())?.enumerate();
+        let mut parse_errors = ParseErrorTally::new(self.parse_error_limit);
+        let num_actors = self.actors.len();
+
+        log::debug!("Starting actor processing with {} actors", num_actors);
+
+        let aborted = AtomicBool::new(false);
+        let order = ClientOrder::new();
+        let handled: Vec<AtomicUsize> = (0..num_actors).map(|_| AtomicUsize::new(0)).collect();
+        // Set once an actor has stopped, normally or not
+        let finished: Vec<AtomicBool> = (0..num_actors).map(|_| AtomicBool::new(false)).collect();
+        let (released_sender, released) = mpsc::channel();
+        let reader = thread::current();
+        // Rows sent to each actor
+        let mut sent_to = vec![0usize; num_actors];
+        let mut sent: usize = 0;
+        // Errors to report; under `ErrorPolicy::Abort` the one at the earliest line wins
+        let mut errors = Vec::new();
+        // Line at which records stopped being dispatched before the end of the input
+        let mut stopped_at = None;
+        // Why the run lost records
+        let mut failures = Vec::new();
+
+        let dispatcher = &mut self.dispatcher;
+        let shutdown = &self.shutdown;
+        thread::scope(|scope| {
+            let mut mailboxes = Vec::new();
+            let mut handles = Vec::new();
+            for (actor_id, engine) in self.actors.iter_mut().enumerate() {
+                let (sender, mailbox) = mpsc::sync_channel(MAILBOX_CAPACITY);
+                let (aborted, handled, order) = (&aborted, &handled[actor_id], &order);
+                let finished = &finished[actor_id];
+                let released = released_sender.clone();
+                let reader = reader.clone();
+                handles.push(scope.spawn(move || {
+                    let _finished = SetOnDrop(finished, reader.clone());
+                    run_actor(
+                        actor_id,
+                        engine,
+                        mailbox,
+                        released,
+                        &reader,
+                        error_policy,
+                        aborted,
+                        handled,
+                        order,
+                    )
+                }));
+                mailboxes.push(sender);
+            }
+            drop(released_sender);
+
+            // Wait until the actor that claimed `transaction`'s ID has applied or rejected
+            // the claiming row, then take back the IDs of rejected rows. The actor wakes
+            // this thread once it has handled the row or stopped.
+            let settle = |dispatcher: &mut Dispatcher, transaction: &Transaction| {
+                while let Some((actor, seq)) = dispatcher.pending_claim(transaction) {
+                    while handled[actor].load(Ordering::Acquire) < seq
+                        && !finished[actor].load(Ordering::Acquire)
+                        && !aborted.load(Ordering::Relaxed)
+                    {
+                        thread::park();
+                    }
+                    for rejected in released.try_iter() {
+                        dispatcher.release(&rejected);
+                    }
+                    dispatcher.settle(transaction.tx);
+                }
+            };
+
+            'read: for (idx, record) in lines.by_ref() {
+                if shutdown.is_requested() {
+                    log::warn!(
+                        "Shutdown requested; stopping before line {}, draining actors",
+                        idx + 1
+                    );
+                    break;
+                }
+                if aborted.load(Ordering::Relaxed) {
+                    break;
+                }
+                parse_errors.read();
+                let line = idx as u64 + 1;
+
+                let transaction: Transaction = match record {
+                    Ok(tx) => tx,
+                    Err(e) => {
+                        log::error!("Failed to parse line {}: {}", line, e);
+                        if error_policy == ErrorPolicy::Abort {
+                            errors.push(e.at_line(line));
+                            break;
+                        }
+                        if let Err(e) = parse_errors.failed() {
+                            errors.push(e);
+                            break;
+                        }
+                        continue;
+                    }
+                };
+
+                for occurrence in dispatcher.due(&transaction) {
+                    settle(dispatcher, &occurrence);
+                    let route = match dispatcher.route(&occurrence, num_actors, Some(&sent_to)) {
+                        Ok(route) => route,
+                        Err(e) => {
+                            log::warn!("Recurring transaction {:?} rejected: {}", occurrence, e);
+                            continue;
+                        }
+                    };
+                    let envelope = Envelope {
+                        line: None,
+                        queued_at: start_timer(),
+                        transaction: occurrence,
+                        claimed: route.claimed,
+                    };
+                    if mailboxes[route.actor].send(envelope).is_err() {
+                        stopped_at = Some(line);
+                        break 'read;
+                    }
+                    sent_to[route.actor] += 1;
+                    sent += 1;
+                }
+
+                settle(dispatcher, &transaction);
+                let route = match dispatcher.route(&transaction, num_actors, Some(&sent_to)) {
+                    Ok(route) => route,
+                    Err(e) => {
+                        log::error!("Failed to process transaction {:?}: {}", transaction, e);
+                        if error_policy == ErrorPolicy::Abort {
+                            errors.push(e.at_line(line));
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                let envelope = Envelope {
+                    line: Some(line),
+                    queued_at: start_timer(),
+                    transaction,
+                    claimed: route.claimed,
+                };
+                if let Err(e) = mailboxes[route.actor].send(envelope) {
+                    log::error!("Failed to send transaction to actor {}: {}", route.actor, e);
+                    stopped_at = Some(line);
+                    break;
+                }
+                sent_to[route.actor] += 1;
+                sent += 1;
+            }
+
+            // Closing the mailboxes lets each actor finish what it holds and stop
+            drop(mailboxes);
+            log::info!("Sent {} transactions to actors", sent);
+
+            for (actor_id, handle) in handles.into_iter().enumerate() {
+                match handle.join() {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => errors.push(e),
+                    Err(e) => {
+                        let message = panic_message(e.as_ref());
+                        log::error!("Actor {} panicked: {}", actor_id, message);
+                        failures.push(format!("actor {} panicked: {}", actor_id, message));
+                    }
+                }
+            }
+        });
+
+        dispatcher.settle_all(released.try_iter());
+
+        // Every record sent must have been applied or rejected; anything else was lost
+        // with a panicked actor
+        let handled: usize = handled.iter().map(|n| n.load(Ordering::Acquire)).sum();
+        let lost = sent.saturating_sub(handled) as u64;
+        // Actors stop at their first rejection under `ErrorPolicy::Abort`, so report the
+        // earliest line any of them reached
+        errors.sort_by_key(|e| e.line().unwrap_or(u64::MAX));
+        let mut first_error = errors.into_iter().next();
+        // An actor that stopped on an error explains why the run stopped early
+        let stopped_early = first_error.is_some();
+        if !failures.is_empty() || (!stopped_early && (lost > 0 || stopped_at.is_some())) {
+            if lost > 0 && failures.is_empty() {
+                failures.push("records were dropped before reaching an actor".to_string());
+            }
+            // The record that could not be dispatched and every one after it
+            let unsent = match stopped_at {
+                Some(line) => {
+                    failures.push(format!("dispatch stopped at line {}", line));
+                    1 + lines.count() as u64
+                }
+                None => 0,
+            };
+            first_error = Some(PaymentsError::WorkerFailed {
+                lost,
+                unsent,
+                reason: failures.join("; "),
+            });
+        }
+
+        match first_error {
+            Some(e) => Err(e.into()),
+            None => Ok(parse_errors.finish()?),
+        }
+    }
+
+    /// Snapshot of every actor's state. Processed IDs known to several actors are listed
+    /// once.
+    pub fn export_state(&self) -> EngineState {
+        let mut state = EngineState::default();
+        let mut seen = HashSet::new();
+        for engine in &self.actors {
+            let actor = engine.export_state();
+            state.accounts.extend(actor.accounts);
+            state
+                .disputable_transactions
+                .extend(actor.disputable_transactions);
+            state.processed_tx_ids.extend(
+                actor
+                    .processed_tx_ids
+                    .into_iter()
+                    .filter(|tx| seen.insert(*tx)),
+            );
+            state.idempotency_keys.extend(actor.idempotency_keys);
+        }
+        state
+    }
+
+    /// Replace every actor's state with a previously exported snapshot, giving each
+    /// client's accounts and transactions to its actor. Processed IDs whose client is not
+    /// known are given to every actor, so none of them can be reused.
+    pub fn import_state(&mut self, state: EngineState) {
+        let num_actors = self.actors.len();
+        let mut shards = vec![EngineState::default(); num_actors];
+        self.dispatcher.tx_owners.clear();
+        for account in state.accounts {
+            shards[self.dispatcher.actor_of(account.client, num_actors)]
+                .accounts
+                .push(account);
+        }
+        for (tx, stored) in state.disputable_transactions {
+            let owner = TxOwner {
+                client: stored.client,
+                pending: None,
+            };
+            self.dispatcher.tx_owners.insert(tx, owner);
+            shards[self.dispatcher.actor_of(stored.client, num_actors)]
+                .disputable_transactions
+                .push((tx, stored));
+        }
+        for tx in state.processed_tx_ids {
+            match self.dispatcher.tx_owners.get(&tx) {
+                Some(owner) => shards[self.dispatcher.actor_of(owner.client, num_actors)]
+                    .processed_tx_ids
+                    .push(tx),
+                None => shards
+                    .iter_mut()
+                    .for_each(|shard| shard.processed_tx_ids.push(tx)),
+            }
+        }
+        for record in state.idempotency_keys {
+            shards[self.dispatcher.actor_of(record.client, num_actors)]
+                .idempotency_keys
+                .push(record);
+        }
+        for (engine, shard) in self.actors.iter_mut().zip(shards) {
+            engine.import_state(shard);
+        }
+    }
+
+    pub fn peek_account(&self, client: ClientId) -> Option<&Account> {
+        self.actor_of(client).peek_account(client)
+    }
+
+    /// Inserts an account as-is into its actor, replacing any existing account for the
+    /// same client
+    pub fn insert_account(&mut self, account: Account) {
+        self.actor_for(account.client).insert_account(account);
+    }
+
+    /// Resolve a dispute through any lock state on the actor owning its client; see
+    /// `StandardEngine::force_resolve`
+    pub fn force_resolve(&mut self, transaction: &Transaction) -> Result<(), PaymentsError> {
+        let route = self
+            .dispatcher
+            .route(transaction, self.actors.len(), None)?;
+        self.actors[route.actor].force_resolve(transaction)
+    }
+
+    /// Put the client's account into `lock` on the actor owning it; see
+    /// `StandardEngine::set_lock`
+    pub fn set_lock(&mut self, client: ClientId, lock: LockState, reason: Option<String>) {
+        self.actor_for(client).set_lock(client, lock, reason);
+    }
+
+    /// Every actor's accounts, by client ID
+    fn accounts(&self) -> Vec<&Account> {
+        let mut accounts: Vec<&Account> = self
+            .actors
+            .iter()
+            .flat_map(|engine| engine.accounts_iter())
+            .collect();
+        accounts.sort_unstable_by_key(|account| account.client);
+        accounts
+    }
+
+    /// Up to `limit` accounts by client ID, skipping the first `offset`
+    pub fn accounts_page(&self, offset: usize, limit: usize) -> Vec<Account> {
+        self.accounts()
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    pub fn write_accounts_csv<W: std::io::Write>(
+        &self,
+        writer: W,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.write_accounts_csv_filtered(writer, |_| true)
+    }
+
+    /// Write only the accounts for which `filter` returns true, by client ID
+    pub fn write_accounts_csv_filtered<W: std::io::Write>(
+        &self,
+        writer: W,
+        filter: impl Fn(&Account) -> bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut wtr = csv::WriterBuilder::new()
+            .has_headers(true)
+            .from_writer(writer);
+
+        for account in self
+            .accounts()
+            .into_iter()
+            .filter(|account| filter(account))
+        {
+            wtr.serialize(self.decimal_format.account(account))?;
+        }
+
+        wtr.flush()?;
+        log::info!("Successfully wrote accounts to CSV (actor engine)");
+        Ok(())
+    }
+
+    /// Write the accounts changed since the previous call; see
+    /// `StandardEngine::write_changed_accounts_csv`
+    pub fn write_changed_accounts_csv<W: std::io::Write>(
+        &mut self,
+        writer: W,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.write_changed_accounts_csv_filtered(writer, |_| true)
+    }
+
+    /// Like `write_changed_accounts_csv`, but only writes the changed accounts for which
+    /// `filter` returns true
+    pub fn write_changed_accounts_csv_filtered<W: std::io::Write>(
+        &mut self,
+        writer: W,
+        filter: impl Fn(&Account) -> bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut wtr = csv::WriterBuilder::new()
+            .has_headers(true)
+            .from_writer(writer);
+
+        let mut changed = Vec::new();
+        for engine in &mut self.actors {
+            changed.extend(engine.changed_clients().iter().copied());
+            engine.set_changed_clients(HashSet::new());
+        }
+        changed.sort_unstable();
+        for client in changed {
+            if let Some(account) = self.peek_account(client).filter(|account| filter(account)) {
+                wtr.serialize(self.decimal_format.account(account))?;
+            }
+        }
+
+        wtr.flush()?;
+        Ok(())
+    }
+
+    /// Engine info with counts summed over actors. Latency percentiles are those of the
+    /// slowest actor.
+    pub fn get_engine_info(&self) -> EngineInfo {
+        let mut stats = EngineStats::default();
+        let mut account_count = 0;
+        let mut transaction_count = 0;
+        for engine in &self.actors {
+            let info = engine.get_engine_info();
+            account_count += info.account_count;
+            transaction_count += info.transaction_count.unwrap_or_default();
+            stats.transactions += info.stats.transactions;
+            stats.throughput += info.stats.throughput;
+            stats.p50 = stats.p50.max(info.stats.p50);
+            stats.p95 = stats.p95.max(info.stats.p95);
+            stats.p99 = stats.p99.max(info.stats.p99);
+            stats.max = stats.max.max(info.stats.max);
+        }
+        EngineInfo {
+            engine_type: "Actor".to_string(),
+            memory_bounded: false,
+            concurrent: true,
+            account_count,
+            transaction_count: Some(transaction_count),
+            memory_limits: None,
+            stats,
+            rebalancing: None,
+        }
+    }
+}
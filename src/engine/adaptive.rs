@@ -0,0 +1,417 @@
+use memory_stats::memory_stats;
+use std::collections::HashMap;
+use std::io::Read;
+
+use super::bounded::BoundedEngine;
+use super::expiry::DisputeExpiry;
+use super::observer::EngineObserver;
+use super::recurring::RecurringScheduler;
+use super::standard::StandardEngine;
+use super::store::TransactionStore;
+use super::validation::TransactionValidator;
+use super::{
+    EngineConfig, EngineInfo, EnginePolicy, ErrorPolicy, ParseErrorLimit, ParseErrorTally,
+    read_transactions, state::EngineState, transaction_reader,
+};
+use crate::account::{Account, ClientId, DecimalFormat, LockState};
+use crate::errors::PaymentsError;
+use crate::shutdown::ShutdownFlag;
+use crate::transaction::{InputFormat, StoredTransaction, Transaction, TxId};
+
+/// Transactions processed between resident-memory checks
+const MEMORY_CHECK_INTERVAL: u64 = 10_000;
+
+/// Engine that starts out as a `StandardEngine` and converts itself into a
+/// `BoundedEngine` sized for `memory_budget_mb` once the process's resident memory
+/// crosses that budget. Small inputs keep exact, eviction-free processing; large ones
+/// degrade to LRU-bounded storage instead of running out of memory. `S` stores the
+/// disputable transactions until the switch.
+#[derive(Debug)]
+pub struct AdaptiveEngine<S = HashMap<TxId, StoredTransaction>> {
+    inner: AdaptiveInner<S>,
+
+    /// Resident memory, in MB, above which the engine switches to bounded storage
+    memory_budget_mb: usize,
+
+    /// Transactions processed since the last memory check
+    since_check: u64,
+
+    /// How reader-based processing reacts to bad rows and rejected transactions
+    error_policy: ErrorPolicy,
+
+    /// How reader-based processing parses the `type` and `amount` columns
+    input_format: InputFormat,
+
+    /// Parse failures after which reader-based processing fails
+    parse_error_limit: Option<ParseErrorLimit>,
+
+    /// Checked between records to stop reader-based processing early
+    shutdown: ShutdownFlag,
+}
+
+#[derive(Debug)]
+enum AdaptiveInner<S> {
+    Standard(StandardEngine<S>),
+    Bounded(BoundedEngine),
+}
+
+impl AdaptiveEngine {
+    pub fn new(memory_budget_mb: usize) -> Self {
+        Self::with_store(memory_budget_mb, HashMap::new())
+    }
+}
+
+impl<S: TransactionStore> AdaptiveEngine<S> {
+    /// An engine that keeps disputable transactions in `store` until it switches to
+    /// bounded storage
+    pub fn with_store(memory_budget_mb: usize, store: S) -> Self {
+        Self {
+            inner: AdaptiveInner::Standard(StandardEngine::new().with_store(store)),
+            memory_budget_mb,
+            since_check: 0,
+            error_policy: ErrorPolicy::default(),
+            parse_error_limit: None,
+            input_format: InputFormat::default(),
+            shutdown: ShutdownFlag::default(),
+        }
+    }
+
+    pub fn set_error_policy(&mut self, error_policy: ErrorPolicy) {
+        self.error_policy = error_policy;
+    }
+
+    pub fn set_parse_error_limit(&mut self, limit: Option<ParseErrorLimit>) {
+        self.parse_error_limit = limit;
+    }
+
+    pub fn parse_error_limit(&self) -> Option<ParseErrorLimit> {
+        self.parse_error_limit
+    }
+
+    pub fn set_input_format(&mut self, input_format: InputFormat) {
+        self.input_format = input_format;
+    }
+
+    pub fn input_format(&self) -> &InputFormat {
+        &self.input_format
+    }
+
+    /// Replace the business rules applied to account operations, including after a
+    /// switch to bounded storage.
+    pub fn set_policy(&mut self, policy: EnginePolicy) {
+        match &mut self.inner {
+            AdaptiveInner::Standard(engine) => engine.set_policy(policy),
+            AdaptiveInner::Bounded(engine) => engine.set_policy(policy),
+        }
+    }
+
+    pub fn policy(&self) -> EnginePolicy {
+        match &self.inner {
+            AdaptiveInner::Standard(engine) => engine.policy(),
+            AdaptiveInner::Bounded(engine) => engine.policy(),
+        }
+    }
+
+    /// Set how balances are written to account output, including after a switch to
+    /// bounded storage.
+    pub fn set_decimal_format(&mut self, decimal_format: DecimalFormat) {
+        match &mut self.inner {
+            AdaptiveInner::Standard(engine) => engine.set_decimal_format(decimal_format),
+            AdaptiveInner::Bounded(engine) => engine.set_decimal_format(decimal_format),
+        }
+    }
+
+    pub fn decimal_format(&self) -> DecimalFormat {
+        match &self.inner {
+            AdaptiveInner::Standard(engine) => engine.decimal_format(),
+            AdaptiveInner::Bounded(engine) => engine.decimal_format(),
+        }
+    }
+
+    /// Run `validator` on every transaction, after the validators already added.
+    pub fn add_validator(&mut self, validator: impl TransactionValidator + 'static) {
+        match &mut self.inner {
+            AdaptiveInner::Standard(engine) => engine.add_validator(validator),
+            AdaptiveInner::Bounded(engine) => engine.add_validator(validator),
+        }
+    }
+
+    /// Notify `observer` of every transaction processed from now on.
+    pub fn add_observer(&mut self, observer: impl EngineObserver + 'static) {
+        match &mut self.inner {
+            AdaptiveInner::Standard(engine) => engine.add_observer(observer),
+            AdaptiveInner::Bounded(engine) => engine.add_observer(observer),
+        }
+    }
+
+    /// Apply `recurring`'s occurrences as timestamps advance, including after a switch
+    /// to bounded storage.
+    pub fn set_recurring(&mut self, recurring: RecurringScheduler) {
+        match &mut self.inner {
+            AdaptiveInner::Standard(engine) => engine.set_recurring(recurring),
+            AdaptiveInner::Bounded(engine) => engine.set_recurring(recurring),
+        }
+    }
+
+    /// Drop disputable transactions once `expiry`'s window ends, including after a switch
+    /// to bounded storage.
+    pub fn set_dispute_expiry(&mut self, expiry: DisputeExpiry) {
+        match &mut self.inner {
+            AdaptiveInner::Standard(engine) => engine.set_dispute_expiry(expiry),
+            AdaptiveInner::Bounded(engine) => engine.set_dispute_expiry(expiry),
+        }
+    }
+
+    /// Stop reader-based processing at the next record once `shutdown` is requested.
+    pub fn set_shutdown_flag(&mut self, shutdown: ShutdownFlag) {
+        self.shutdown = shutdown;
+    }
+
+    /// Whether the engine has already switched to bounded storage
+    pub fn is_bounded(&self) -> bool {
+        matches!(self.inner, AdaptiveInner::Bounded(_))
+    }
+
+    /// Move all state into a `BoundedEngine` sized for the memory budget.
+    /// Entries beyond the new limits are evicted least recently used first.
+    pub fn switch_to_bounded(&mut self) {
+        let AdaptiveInner::Standard(standard) = &self.inner else {
+            return;
+        };
+        let state = standard.export_state();
+        let EngineConfig::Bounded {
+            max_accounts,
+            max_disputable_transactions,
+            max_processed_tx_ids,
+            ..
+        } = EngineConfig::for_memory_mb(self.memory_budget_mb)
+        else {
+            unreachable!("for_memory_mb always returns a bounded configuration");
+        };
+
+        log::warn!(
+            "Memory budget of {} MB exceeded; switching to bounded storage \
+             (accounts: {}, transactions: {}, tx_ids: {})",
+            self.memory_budget_mb,
+            max_accounts,
+            max_disputable_transactions,
+            max_processed_tx_ids
+        );
+        let mut bounded = BoundedEngine::new(
+            max_accounts.max(1),
+            max_disputable_transactions.max(1),
+            max_processed_tx_ids.max(1),
+        );
+        bounded.import_state(state);
+        bounded.set_error_policy(self.error_policy);
+        bounded.set_parse_error_limit(self.parse_error_limit);
+        bounded.set_input_format(self.input_format.clone());
+        bounded.set_policy(standard.policy());
+        bounded.set_decimal_format(standard.decimal_format());
+        bounded.set_validators(standard.validators().clone());
+        bounded.set_observers(standard.observers().clone());
+        bounded.set_recurring(standard.recurring().clone());
+        bounded.set_dispute_expiry(standard.dispute_expiry().clone());
+        bounded.set_changed_clients(standard.changed_clients().clone());
+        self.inner = AdaptiveInner::Bounded(bounded);
+    }
+
+    /// Check resident memory every `MEMORY_CHECK_INTERVAL` transactions and switch to
+    /// bounded storage once it exceeds the budget
+    fn check_memory(&mut self) {
+        self.since_check += 1;
+        if self.is_bounded() || self.since_check < MEMORY_CHECK_INTERVAL {
+            return;
+        }
+        self.since_check = 0;
+
+        if let Some(usage) = memory_stats()
+            && usage.physical_mem > self.memory_budget_mb * 1024 * 1024
+        {
+            self.switch_to_bounded();
+        }
+    }
+
+    pub fn process_transaction(&mut self, transaction: &Transaction) -> Result<(), PaymentsError> {
+        let result = match &mut self.inner {
+            AdaptiveInner::Standard(engine) => engine.process_transaction(transaction),
+            AdaptiveInner::Bounded(engine) => engine.process_transaction(transaction),
+        };
+        self.check_memory();
+        result
+    }
+
+    /// Apply `transactions` in order, returning each one's result. Memory is checked as
+    /// for single transactions, so a large batch can switch to bounded storage midway.
+    pub fn process_transactions(
+        &mut self,
+        transactions: &[Transaction],
+    ) -> Vec<Result<(), PaymentsError>> {
+        transactions
+            .iter()
+            .map(|transaction| self.process_transaction(transaction))
+            .collect()
+    }
+
+    pub fn process_transactions_from_reader<R: Read>(
+        &mut self,
+        reader: R,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut rdr = transaction_reader(reader, &self.input_format);
+        let lines = read_transactions(&mut rdr, self.input_format.clone())?.enumerate();
+        let mut parse_errors = ParseErrorTally::new(self.parse_error_limit);
+
+        log::debug!("Starting to process transactions from stream (adaptive engine)");
+
+        for (idx, line) in lines {
+            if self.shutdown.is_requested() {
+                log::warn!("Shutdown requested; stopping before line {}", idx + 1);
+                break;
+            }
+            parse_errors.read();
+            let transaction: Transaction = match line {
+                Ok(tx) => tx,
+                Err(e) => {
+                    log::error!("Failed to parse line {}: {}", idx + 1, e);
+                    if self.error_policy == ErrorPolicy::Abort {
+                        return Err(e.at_line(idx as u64 + 1).into());
+                    }
+                    parse_errors.failed()?;
+                    continue;
+                }
+            };
+
+            if let Err(e) = self.process_transaction(&transaction) {
+                log::error!("Failed to process transaction {:?}: {}", transaction, e);
+                if self.error_policy == ErrorPolicy::Abort {
+                    return Err(e.at_line(idx as u64 + 1).into());
+                }
+            } else {
+                log::debug!("Successfully processed transaction: {:?}", transaction);
+            }
+        }
+        parse_errors.finish()?;
+        Ok(())
+    }
+
+    pub fn open_disputes(&self, client: ClientId) -> Vec<TxId> {
+        match &self.inner {
+            AdaptiveInner::Standard(engine) => engine.open_disputes(client),
+            AdaptiveInner::Bounded(engine) => engine.open_disputes(client),
+        }
+    }
+
+    pub fn export_state(&self) -> EngineState {
+        match &self.inner {
+            AdaptiveInner::Standard(engine) => engine.export_state(),
+            AdaptiveInner::Bounded(engine) => engine.export_state(),
+        }
+    }
+
+    pub fn peek_account(&self, client: ClientId) -> Option<&Account> {
+        match &self.inner {
+            AdaptiveInner::Standard(engine) => engine.peek_account(client),
+            AdaptiveInner::Bounded(engine) => engine.peek_account(client),
+        }
+    }
+
+    /// Every account, in no particular order; see `BoundedEngine::accounts_iter`
+    pub fn accounts_iter(&self) -> Box<dyn Iterator<Item = &Account> + '_> {
+        match &self.inner {
+            AdaptiveInner::Standard(engine) => Box::new(engine.accounts_iter()),
+            AdaptiveInner::Bounded(engine) => Box::new(engine.accounts_iter()),
+        }
+    }
+
+    pub fn accounts_page(&self, offset: usize, limit: usize) -> Vec<Account> {
+        match &self.inner {
+            AdaptiveInner::Standard(engine) => engine.accounts_page(offset, limit),
+            AdaptiveInner::Bounded(engine) => engine.accounts_page(offset, limit),
+        }
+    }
+
+    pub fn import_state(&mut self, state: EngineState) {
+        match &mut self.inner {
+            AdaptiveInner::Standard(engine) => engine.import_state(state),
+            AdaptiveInner::Bounded(engine) => engine.import_state(state),
+        }
+    }
+
+    pub fn insert_account(&mut self, account: Account) {
+        match &mut self.inner {
+            AdaptiveInner::Standard(engine) => engine.insert_account(account),
+            AdaptiveInner::Bounded(engine) => engine.insert_account(account),
+        }
+    }
+
+    /// Resolve a dispute through any lock state; see `StandardEngine::force_resolve`
+    pub fn force_resolve(&mut self, transaction: &Transaction) -> Result<(), PaymentsError> {
+        match &mut self.inner {
+            AdaptiveInner::Standard(engine) => engine.force_resolve(transaction),
+            AdaptiveInner::Bounded(engine) => engine.force_resolve(transaction),
+        }
+    }
+
+    pub fn set_lock(&mut self, client: ClientId, lock: LockState, reason: Option<String>) {
+        match &mut self.inner {
+            AdaptiveInner::Standard(engine) => engine.set_lock(client, lock, reason),
+            AdaptiveInner::Bounded(engine) => engine.set_lock(client, lock, reason),
+        }
+    }
+
+    pub fn write_accounts_csv<W: std::io::Write>(
+        &self,
+        writer: W,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.write_accounts_csv_filtered(writer, |_| true)
+    }
+
+    /// Write only the accounts for which `filter` returns true; see `write_accounts_csv`
+    pub fn write_accounts_csv_filtered<W: std::io::Write>(
+        &self,
+        writer: W,
+        filter: impl Fn(&Account) -> bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match &self.inner {
+            AdaptiveInner::Standard(engine) => engine.write_accounts_csv_filtered(writer, filter),
+            AdaptiveInner::Bounded(engine) => engine.write_accounts_csv_filtered(writer, filter),
+        }
+    }
+
+    /// Write the accounts changed since the previous call; see
+    /// `StandardEngine::write_changed_accounts_csv`
+    pub fn write_changed_accounts_csv<W: std::io::Write>(
+        &mut self,
+        writer: W,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.write_changed_accounts_csv_filtered(writer, |_| true)
+    }
+
+    /// Like `write_changed_accounts_csv`, but only writes the changed accounts for which
+    /// `filter` returns true
+    pub fn write_changed_accounts_csv_filtered<W: std::io::Write>(
+        &mut self,
+        writer: W,
+        filter: impl Fn(&Account) -> bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match &mut self.inner {
+            AdaptiveInner::Standard(engine) => {
+                engine.write_changed_accounts_csv_filtered(writer, filter)
+            }
+            AdaptiveInner::Bounded(engine) => {
+                engine.write_changed_accounts_csv_filtered(writer, filter)
+            }
+        }
+    }
+
+    pub fn get_engine_info(&self) -> EngineInfo {
+        let info = match &self.inner {
+            AdaptiveInner::Standard(engine) => engine.get_engine_info(),
+            AdaptiveInner::Bounded(engine) => engine.get_engine_info(),
+        };
+        EngineInfo {
+            engine_type: format!("Adaptive({})", info.engine_type),
+            ..info
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! Authentication of transaction streams and authorization of what they submit.
+//!
+//! A stream, e.g. one TCP connection handed to
+//! `ConcurrentEngine::process_authenticated_stream`, presents `Credentials`. An
+//! `Authenticator` turns them into an `Identity` naming the clients it may submit
+//! transactions for. The engine rejects rows for any other client with
+//! `PaymentsError::Unauthorized` and reports them to observers through
+//! `on_unauthorized`, so every refused row leaves an audit trail.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Debug};
+
+use crate::account::ClientId;
+use crate::errors::PaymentsError;
+use crate::transaction::Transaction;
+
+/// What a stream presents to prove who it is.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Credentials {
+    /// A shared secret, e.g. from an `Authorization` header
+    ApiKey(String),
+    /// Subject of a client certificate the TLS layer has already verified (mTLS)
+    ClientCertificate(String),
+}
+
+impl Credentials {
+    /// How the stream authenticated, for logs and audit events
+    pub fn method(&self) -> &'static str {
+        match self {
+            Self::ApiKey(_) => "api_key",
+            Self::ClientCertificate(_) => "client_certificate",
+        }
+    }
+
+    /// The certificate subject; API keys are secret and never shown
+    pub fn subject(&self) -> Option<&str> {
+        match self {
+            Self::ApiKey(_) => None,
+            Self::ClientCertificate(subject) => Some(subject),
+        }
+    }
+}
+
+impl Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ApiKey(_) => f.write_str("ApiKey(<redacted>)"),
+            Self::ClientCertificate(subject) => {
+                f.debug_tuple("ClientCertificate").field(subject).finish()
+            }
+        }
+    }
+}
+
+/// Clients an identity may submit transactions for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientScope {
+    /// Every client, e.g. for an internal batch loader
+    All,
+    Only(HashSet<ClientId>),
+}
+
+/// An authenticated caller and what it is allowed to do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    /// Name used in errors, logs and audit events
+    pub name: String,
+    pub clients: ClientScope,
+}
+
+impl Identity {
+    pub fn new(name: impl Into<String>, clients: ClientScope) -> Self {
+        Self {
+            name: name.into(),
+            clients,
+        }
+    }
+
+    /// Fails with `Unauthorized` unless the identity may submit for `transaction`'s client.
+    pub fn authorize(&self, transaction: &Transaction) -> Result<(), PaymentsError> {
+        match &self.clients {
+            ClientScope::All => Ok(()),
+            ClientScope::Only(clients) if clients.contains(&transaction.client) => Ok(()),
+            ClientScope::Only(_) => Err(PaymentsError::Unauthorized {
+                identity: self.name.clone(),
+                client: transaction.client,
+            }),
+        }
+    }
+}
+
+/// Maps a stream's credentials to an identity, or rejects them with
+/// `PaymentsError::Unauthenticated`. Implement it to check credentials against an
+/// external store.
+pub trait Authenticator: Debug + Send + Sync {
+    fn authenticate(&self, credentials: &Credentials) -> Result<Identity, PaymentsError>;
+}
+
+/// Authenticator with a fixed set of API keys and certificate subjects, e.g. loaded from
+/// configuration at startup.
+#[derive(Clone, Default)]
+pub struct StaticAuthenticator {
+    api_keys: HashMap<String, Identity>,
+    certificates: HashMap<String, Identity>,
+}
+
+impl StaticAuthenticator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept `key` as `identity`.
+    pub fn with_api_key(mut self, key: impl Into<String>, identity: Identity) -> Self {
+        self.api_keys.insert(key.into(), identity);
+        self
+    }
+
+    /// Accept client certificates with `subject` as `identity`.
+    pub fn with_certificate(mut self, subject: impl Into<String>, identity: Identity) -> Self {
+        self.certificates.insert(subject.into(), identity);
+        self
+    }
+}
+
+impl Authenticator for StaticAuthenticator {
+    fn authenticate(&self, credentials: &Credentials) -> Result<Identity, PaymentsError> {
+        let identity = match credentials {
+            Credentials::ApiKey(key) => self.api_keys.get(key),
+            Credentials::ClientCertificate(subject) => self.certificates.get(subject),
+        };
+        identity.cloned().ok_or_else(|| {
+            PaymentsError::Unauthenticated(format!("unknown {}", credentials.method()))
+        })
+    }
+}
+
+impl Debug for StaticAuthenticator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StaticAuthenticator")
+            .field("api_keys", &self.api_keys.len())
+            .field("certificates", &self.certificates.keys())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Amount;
+
+    #[test]
+    fn test_static_authenticator() {
+        let merchant = Identity::new("merchant-7", ClientScope::Only([ClientId::new(7)].into()));
+        let authenticator = StaticAuthenticator::new()
+            .with_api_key("secret", merchant.clone())
+            .with_certificate("CN=loader", Identity::new("loader", ClientScope::All));
+
+        let identity = authenticator
+            .authenticate(&Credentials::ApiKey("secret".into()))
+            .unwrap();
+        assert_eq!(identity, merchant);
+        assert!(
+            identity
+                .authorize(&Transaction::deposit(7, 1, Amount::new(1, 0)))
+                .is_ok()
+        );
+        let denied = identity
+            .authorize(&Transaction::deposit(8, 2, Amount::new(1, 0)))
+            .unwrap_err();
+        assert_eq!(denied.code(), 403);
+        assert_eq!(denied.client(), Some(ClientId::new(8)));
+
+        let loader = authenticator
+            .authenticate(&Credentials::ClientCertificate("CN=loader".into()))
+            .unwrap();
+        assert!(loader.authorize(&Transaction::dispute(8, 2)).is_ok());
+
+        let unknown = authenticator
+            .authenticate(&Credentials::ApiKey("guess".into()))
+            .unwrap_err();
+        assert_eq!(unknown.code(), 402);
+        assert!(!format!("{:?}", Credentials::ApiKey("secret".into())).contains("secret"));
+        assert!(!format!("{:?}", authenticator).contains("secret"));
+    }
+}
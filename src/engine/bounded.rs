@@ -1,25 +1,33 @@
-use lru::LruCache;
-use rust_decimal::Decimal;
+use std::collections::HashSet;
 use std::io::Read;
 use std::num::NonZeroUsize;
 
-use super::{EngineInfo, MemoryLimits};
-use crate::account::{Account, ClientId};
+use super::cache::{BoundedCache, EvictionPolicy};
+use super::expiry::DisputeExpiry;
+use super::idempotency::IdempotencyKeys;
+use super::ledger::Ledger;
+use super::memory::MemoryEstimates;
+use super::observer::{EngineObserver, ObserverList};
+use super::recurring::RecurringScheduler;
+use super::store::TransactionStore;
+use super::validation::{TransactionValidator, ValidatorChain};
+use super::{
+    EngineInfo, EnginePolicy, ErrorPolicy, MemoryLimits, ParseErrorLimit, state::EngineState,
+};
+use crate::account::{Account, ClientId, DecimalFormat, LockState};
 use crate::errors::PaymentsError;
-use crate::transaction::{StoredTransaction, Transaction, TransactionType, TxId};
+use crate::shutdown::ShutdownFlag;
+use crate::transaction::{InputFormat, StoredTransaction, Transaction, TxId};
 
 /// Memory-bounded payment engine for handling extremely large datasets.
-/// Uses LRU caches to limit memory usage while still providing correct processing.
+/// Uses fixed-capacity caches (LRU by default) to limit memory usage while still providing
+/// correct processing.
 #[derive(Debug)]
-pub struct BoundedEngine {
-    /// LRU cache of active accounts, evicts least recently used accounts
-    pub accounts: LruCache<ClientId, Account>,
-
-    /// LRU cache of disputable transactions
-    disputable_transactions: LruCache<TxId, StoredTransaction>,
-
-    /// LRU cache of processed transaction IDs for duplicate prevention
-    processed_tx_ids: LruCache<TxId, ()>,
+pub struct BoundedEngine<S = BoundedCache<TxId, StoredTransaction>> {
+    /// Accounts and processed IDs in caches evicted according to the engine's
+    /// `EvictionPolicy`, and disputable transactions in one too unless another store
+    /// was set with `with_store`
+    ledger: Ledger<BoundedCache<ClientId, Account>, BoundedCache<TxId, ()>, S>,
 
     /// Store memory limits for reporting
     memory_limits: MemoryLimits,
@@ -31,251 +39,351 @@ impl BoundedEngine {
         max_disputable_transactions: usize,
         max_processed_tx_ids: usize,
     ) -> Self {
-        Self {
-            accounts: LruCache::new(NonZeroUsize::new(max_accounts).unwrap()),
-            disputable_transactions: LruCache::new(
+        Self::with_eviction_policy(
+            max_accounts,
+            max_disputable_transactions,
+            max_processed_tx_ids,
+            EvictionPolicy::default(),
+        )
+    }
+
+    /// An engine whose caches all evict according to `eviction_policy`
+    pub fn with_eviction_policy(
+        max_accounts: usize,
+        max_disputable_transactions: usize,
+        max_processed_tx_ids: usize,
+        eviction_policy: EvictionPolicy,
+    ) -> Self {
+        let mut ledger = Ledger::new(
+            BoundedCache::new(eviction_policy, NonZeroUsize::new(max_accounts).unwrap()),
+            BoundedCache::new(
+                eviction_policy,
+                NonZeroUsize::new(max_processed_tx_ids).unwrap(),
+            ),
+            BoundedCache::new(
+                eviction_policy,
                 NonZeroUsize::new(max_disputable_transactions).unwrap(),
             ),
-            processed_tx_ids: LruCache::new(NonZeroUsize::new(max_processed_tx_ids).unwrap()),
+        );
+        // Idempotency keys are remembered as long as processed IDs
+        ledger.idempotency = IdempotencyKeys::bounded(max_processed_tx_ids);
+        Self {
+            ledger,
             memory_limits: MemoryLimits {
                 max_accounts,
                 max_disputable_transactions,
                 max_processed_tx_ids,
+                estimates: MemoryEstimates::calibrate(),
             },
         }
     }
+}
 
-    /// Retrieves an existing account or creates a new one if it doesn't exist.
-    /// May evict least recently used account if cache is full.
-    fn get_or_create_account(&mut self, client_id: ClientId) -> &mut Account {
-        if !self.accounts.contains(&client_id) {
-            self.accounts.put(client_id, Account::new(client_id));
+impl<S: TransactionStore> BoundedEngine<S> {
+    /// This engine with its disputable transactions kept in `store` instead of a cache.
+    /// Anything the current store holds is dropped, so swap stores before processing.
+    /// `max_disputable_transactions` no longer applies unless `store` enforces it
+    pub fn with_store<T: TransactionStore>(self, store: T) -> BoundedEngine<T> {
+        BoundedEngine {
+            ledger: self.ledger.with_store(store),
+            memory_limits: self.memory_limits,
         }
-        self.accounts.get_mut(&client_id).unwrap()
     }
 
-    pub fn process_transaction(&mut self, transaction: &Transaction) -> Result<(), PaymentsError> {
-        match transaction.tx_type {
-            TransactionType::Deposit => self.process_deposit(transaction),
-            TransactionType::Withdrawal => self.process_withdrawal(transaction),
-            TransactionType::Dispute => self.process_dispute(transaction),
-            TransactionType::Resolve => self.process_resolve(transaction),
-            TransactionType::Chargeback => self.process_chargeback(transaction),
-        }
+    pub fn memory_limits(&self) -> &MemoryLimits {
+        &self.memory_limits
     }
 
-    fn process_deposit(&mut self, transaction: &Transaction) -> Result<(), PaymentsError> {
-        let amount = transaction.amount.ok_or(PaymentsError::InvalidTransaction(
-            "Deposit transaction must have an amount".to_string(),
-        ))?;
-        if amount <= Decimal::ZERO {
-            return Err(PaymentsError::InvalidTransaction(
-                "Deposit amount must be positive".to_string(),
-            ));
-        }
-        if self.processed_tx_ids.contains(&transaction.tx) {
-            return Err(PaymentsError::InvalidTransaction(format!(
-                "Transaction ID {} already exists",
-                transaction.tx
-            )));
-        }
-        let client_id = transaction.client;
-        let account = self.get_or_create_account(client_id);
-        account.deposit(amount)?;
-
-        // Store disputable transaction for potential future disputes
-        self.disputable_transactions.put(
-            transaction.tx,
-            StoredTransaction {
-                client: client_id,
-                amount,
-                disputed: false,
-            },
-        );
+    pub fn eviction_policy(&self) -> EvictionPolicy {
+        self.ledger.accounts.policy()
+    }
 
-        // Track transaction ID for duplicate prevention
-        self.processed_tx_ids.put(transaction.tx, ());
+    pub fn set_error_policy(&mut self, error_policy: ErrorPolicy) {
+        self.ledger.error_policy = error_policy;
+    }
 
-        Ok(())
+    pub fn set_parse_error_limit(&mut self, limit: Option<ParseErrorLimit>) {
+        self.ledger.parse_error_limit = limit;
     }
 
-    fn process_withdrawal(&mut self, transaction: &Transaction) -> Result<(), PaymentsError> {
-        let amount = transaction.amount.ok_or(PaymentsError::InvalidTransaction(
-            "Withdrawal transaction must have an amount".to_string(),
-        ))?;
-        if amount <= Decimal::ZERO {
-            return Err(PaymentsError::InvalidTransaction(
-                "Withdrawal amount must be positive".to_string(),
-            ));
-        }
-        if self.processed_tx_ids.contains(&transaction.tx) {
-            return Err(PaymentsError::InvalidTransaction(format!(
-                "Transaction ID {} already exists",
-                transaction.tx
-            )));
-        }
-        let client_id = transaction.client;
-        let account = self.get_or_create_account(client_id);
-        account.withdraw(amount)?;
-
-        // Store disputable transaction for potential future disputes
-        self.disputable_transactions.put(
-            transaction.tx,
-            StoredTransaction {
-                client: client_id,
-                amount,
-                disputed: false,
-            },
-        );
+    pub fn parse_error_limit(&self) -> Option<ParseErrorLimit> {
+        self.ledger.parse_error_limit
+    }
 
-        // Track transaction ID for duplicate prevention
-        self.processed_tx_ids.put(transaction.tx, ());
-        Ok(())
+    pub fn set_input_format(&mut self, input_format: InputFormat) {
+        self.ledger.input_format = input_format;
     }
 
-    fn process_dispute(&mut self, transaction: &Transaction) -> Result<(), PaymentsError> {
-        if transaction.amount.is_some() {
-            return Err(PaymentsError::InvalidTransaction(
-                "Dispute transaction should not have an amount".to_string(),
-            ));
-        }
+    pub fn input_format(&self) -> &InputFormat {
+        &self.ledger.input_format
+    }
 
-        let (client_id, amount) = {
-            let stored_tx = self
-                .disputable_transactions
-                .get_mut(&transaction.tx)
-                .ok_or(PaymentsError::TransactionNotFound)?;
+    /// Replace the business rules applied to account operations.
+    pub fn set_policy(&mut self, policy: EnginePolicy) {
+        self.ledger.policy = policy;
+    }
 
-            if stored_tx.client != transaction.client {
-                return Err(PaymentsError::ClientIdMismatch);
-            }
+    pub fn policy(&self) -> EnginePolicy {
+        self.ledger.policy
+    }
 
-            if stored_tx.disputed {
-                return Err(PaymentsError::TransactionAlreadyDisputed(transaction.tx));
-            }
+    /// Set how balances are written to account output
+    pub fn set_decimal_format(&mut self, decimal_format: DecimalFormat) {
+        self.ledger.decimal_format = decimal_format;
+    }
 
-            stored_tx.disputed = true;
+    pub fn decimal_format(&self) -> DecimalFormat {
+        self.ledger.decimal_format
+    }
 
-            (stored_tx.client, stored_tx.amount)
-        };
+    /// Run `validator` on every transaction, after the validators already added
+    pub fn add_validator(&mut self, validator: impl TransactionValidator + 'static) {
+        self.ledger.validators.push(validator);
+    }
 
-        let account = self.get_or_create_account(client_id);
-        account.hold(amount)?;
-        Ok(())
+    pub fn validators(&self) -> &ValidatorChain {
+        &self.ledger.validators
     }
 
-    fn process_resolve(&mut self, transaction: &Transaction) -> Result<(), PaymentsError> {
-        if transaction.amount.is_some() {
-            return Err(PaymentsError::InvalidTransaction(
-                "Resolve transaction should not have an amount".to_string(),
-            ));
-        }
-        let (client_id, amount) = {
-            let stored_tx = self
-                .disputable_transactions
-                .get_mut(&transaction.tx)
-                .ok_or(PaymentsError::TransactionNotFound)?;
-            if stored_tx.client != transaction.client {
-                return Err(PaymentsError::ClientIdMismatch);
-            }
+    pub fn set_validators(&mut self, validators: ValidatorChain) {
+        self.ledger.validators = validators;
+    }
 
-            if !stored_tx.disputed {
-                return Err(PaymentsError::TransactionNotDisputed);
-            }
-            stored_tx.disputed = false;
-            (stored_tx.client, stored_tx.amount)
-        };
+    /// Notify `observer` of every transaction processed from now on
+    pub fn add_observer(&mut self, observer: impl EngineObserver + 'static) {
+        self.ledger.observers.push(observer);
+    }
 
-        let account = self.get_or_create_account(client_id);
-        account.release(amount)?;
+    pub fn observers(&self) -> &ObserverList {
+        &self.ledger.observers
+    }
 
-        Ok(())
+    pub fn set_observers(&mut self, observers: ObserverList) {
+        self.ledger.observers = observers;
+    }
+
+    /// Apply `recurring`'s occurrences before each timestamped transaction that reaches
+    /// them
+    pub fn set_recurring(&mut self, recurring: RecurringScheduler) {
+        self.ledger.recurring = recurring;
+    }
+
+    pub fn recurring(&self) -> &RecurringScheduler {
+        &self.ledger.recurring
+    }
+
+    /// Drop disputable transactions once `expiry`'s window ends, replacing any window
+    /// set before. Remembers as many expired IDs as processed IDs
+    pub fn set_dispute_expiry(&mut self, mut expiry: DisputeExpiry) {
+        expiry.set_max_expired(self.memory_limits.max_processed_tx_ids);
+        self.ledger.expiry = expiry;
+    }
+
+    pub fn dispute_expiry(&self) -> &DisputeExpiry {
+        &self.ledger.expiry
+    }
+
+    pub fn changed_clients(&self) -> &HashSet<ClientId> {
+        &self.ledger.changed
+    }
+
+    /// Replace the set of clients `write_changed_accounts_csv` will write
+    pub fn set_changed_clients(&mut self, changed: HashSet<ClientId>) {
+        self.ledger.changed = changed;
+    }
+
+    /// Stop reader-based processing at the next record once `shutdown` is requested.
+    pub fn set_shutdown_flag(&mut self, shutdown: ShutdownFlag) {
+        self.ledger.shutdown = shutdown;
+    }
+
+    /// IDs of the client's transactions that are currently under dispute, in ascending order
+    /// Includes disputes whose transaction record has since been evicted; their funds stay held.
+    pub fn open_disputes(&self, client: ClientId) -> Vec<TxId> {
+        self.ledger.open_disputes(client)
     }
 
-    fn process_chargeback(&mut self, transaction: &Transaction) -> Result<(), PaymentsError> {
-        if transaction.amount.is_some() {
-            return Err(PaymentsError::InvalidTransaction(
-                "Chargeback transaction should not have an amount".to_string(),
-            ));
+    /// The client's account if it is cached. Does not change LRU order, so monitoring
+    /// reads never keep a cold account from being evicted
+    pub fn peek_account(&self, client: ClientId) -> Option<&Account> {
+        self.ledger.accounts.peek(&client)
+    }
+
+    /// Every cached account, from the one evicted last to the one evicted first (most to
+    /// least recently used under LRU). Does not change LRU order
+    pub fn accounts_iter(&self) -> impl Iterator<Item = &Account> {
+        self.ledger.accounts.iter().map(|(_, account)| account)
+    }
+
+    /// Up to `limit` cached accounts by client ID, skipping the first `offset`. Does not
+    /// change LRU order
+    pub fn accounts_page(&self, offset: usize, limit: usize) -> Vec<Account> {
+        let mut clients: Vec<_> = self
+            .ledger
+            .accounts
+            .iter()
+            .map(|(client, _)| *client)
+            .collect();
+        clients.sort_unstable();
+        clients
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .filter_map(|client| self.ledger.accounts.peek(&client).cloned())
+            .collect()
+    }
+
+    /// Snapshot of all cached accounts, disputable transactions and processed IDs,
+    /// each listed from the next to be evicted to the last. Does not change LRU order.
+    pub fn export_state(&self) -> EngineState {
+        EngineState {
+            accounts: self
+                .ledger
+                .accounts
+                .iter()
+                .rev()
+                .map(|(_, account)| account.clone())
+                .collect(),
+            disputable_transactions: self.ledger.disputable_transactions.entries(),
+            processed_tx_ids: self
+                .ledger
+                .processed_tx_ids
+                .iter()
+                .rev()
+                .map(|(tx, _)| *tx)
+                .collect(),
+            idempotency_keys: self.ledger.idempotency.export(),
         }
-        let (client_id, amount) = {
-            let stored_tx = self
-                .disputable_transactions
-                .get_mut(&transaction.tx)
-                .ok_or(PaymentsError::TransactionNotFound)?;
-            if stored_tx.client != transaction.client {
-                return Err(PaymentsError::ClientIdMismatch);
-            }
+    }
+
+    /// Replaces the engine's state with a previously exported snapshot.
+    /// Entries beyond the configured limits are evicted as usual.
+    pub fn import_state(&mut self, state: EngineState) {
+        self.ledger.accounts.clear();
+        self.ledger.disputable_transactions.clear();
+        self.ledger.processed_tx_ids.clear();
+        self.ledger.disputes_by_client.clear();
 
-            if !stored_tx.disputed {
-                return Err(PaymentsError::TransactionNotDisputed);
+        for account in state.accounts {
+            self.ledger.accounts.put(account.client, account);
+        }
+        for (tx, stored) in state.disputable_transactions {
+            if stored.disputed {
+                self.ledger
+                    .disputes_by_client
+                    .entry(stored.client)
+                    .or_default()
+                    .insert(tx);
             }
-            stored_tx.disputed = false;
-            (stored_tx.client, stored_tx.amount)
-        };
+            self.ledger.disputable_transactions.insert(tx, stored);
+        }
+        for tx in state.processed_tx_ids {
+            self.ledger.processed_tx_ids.put(tx, ());
+        }
+        self.ledger.idempotency.import(state.idempotency_keys);
+    }
 
-        let account = self.get_or_create_account(client_id);
-        account.chargeback(amount)?;
+    /// Inserts an account as-is, replacing any existing account for the same client.
+    /// May evict an account, chosen by the eviction policy, if the cache is full.
+    pub fn insert_account(&mut self, account: Account) {
+        if let Some((evicted, _)) = self.ledger.accounts.push(account.client, account)
+            && !self.ledger.accounts.contains(&evicted)
+        {
+            log::warn!("Account {} evicted while seeding accounts", evicted);
+        }
+    }
 
-        // After chargeback, we can remove the transaction since it's finalized
-        self.disputable_transactions.pop(&transaction.tx);
+    pub fn process_transaction(&mut self, transaction: &Transaction) -> Result<(), PaymentsError> {
+        self.ledger.process_transaction(transaction)
+    }
 
-        Ok(())
+    /// Apply `transactions` in order, returning each one's result
+    pub fn process_transactions(
+        &mut self,
+        transactions: &[Transaction],
+    ) -> Vec<Result<(), PaymentsError>> {
+        self.ledger.process_transactions(transactions)
+    }
+
+    /// Process a transaction whose latency is measured from `started`, e.g. when it was
+    /// queued, rather than from when this call begins
+    pub fn process_transaction_since(
+        &mut self,
+        transaction: &Transaction,
+        started: Option<std::time::Instant>,
+    ) -> Result<(), PaymentsError> {
+        self.ledger.process_transaction_since(transaction, started)
+    }
+
+    /// Resolve the dispute `transaction` refers to even when the account's lock state
+    /// rejects resolves; see `Ledger::force_resolve`
+    pub fn force_resolve(&mut self, transaction: &Transaction) -> Result<(), PaymentsError> {
+        self.ledger.force_resolve(transaction)
+    }
+
+    /// Put the client's account into `lock` with the given reason, creating the account
+    /// if the engine holds none
+    pub fn set_lock(&mut self, client: ClientId, lock: LockState, reason: Option<String>) {
+        self.ledger.set_lock(client, lock, reason);
     }
 
     pub fn process_transactions_from_reader<R: Read>(
         &mut self,
         reader: R,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut rdr = csv::ReaderBuilder::new()
-            .trim(csv::Trim::All)
-            .from_reader(reader);
-
         log::debug!("Starting to process transactions from stream (bounded engine)");
-
-        for (idx, line) in rdr.deserialize().enumerate() {
-            let transaction: Transaction = match line {
-                Ok(tx) => tx,
-                Err(e) => {
-                    log::error!("Failed to parse line {}: {}", idx + 1, e);
-                    continue;
-                }
-            };
-
-            if let Err(e) = self.process_transaction(&transaction) {
-                log::error!("Failed to process transaction {:?}: {}", transaction, e);
-            } else {
-                log::debug!("Successfully processed transaction: {:?}", transaction);
-            }
-        }
-        Ok(())
+        self.ledger.process_transactions_from_reader(reader)
     }
 
     pub fn write_accounts_csv<W: std::io::Write>(
         &self,
         writer: W,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut wtr = csv::WriterBuilder::new()
-            .has_headers(true)
-            .from_writer(writer);
-
-        for (_, account) in self.accounts.iter() {
-            wtr.serialize(account)?;
-        }
+        self.write_accounts_csv_filtered(writer, |_| true)
+    }
 
-        wtr.flush()?;
+    /// Write only the accounts for which `filter` returns true; see `write_accounts_csv`
+    pub fn write_accounts_csv_filtered<W: std::io::Write>(
+        &self,
+        writer: W,
+        filter: impl Fn(&Account) -> bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.ledger.write_accounts_csv_filtered(writer, filter)?;
         log::info!("Successfully wrote accounts to CSV (bounded engine)");
         Ok(())
     }
 
+    /// Write only the accounts changed by transactions since the previous call (or since
+    /// the engine was created), by client ID, and start tracking changes afresh.
+    /// Seeded or imported accounts count as unchanged until a transaction touches them
+    pub fn write_changed_accounts_csv<W: std::io::Write>(
+        &mut self,
+        writer: W,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.write_changed_accounts_csv_filtered(writer, |_| true)
+    }
+
+    /// Like `write_changed_accounts_csv`, but only writes the changed accounts for which
+    /// `filter` returns true
+    pub fn write_changed_accounts_csv_filtered<W: std::io::Write>(
+        &mut self,
+        writer: W,
+        filter: impl Fn(&Account) -> bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.ledger
+            .write_changed_accounts_csv_filtered(writer, filter)
+    }
+
     pub fn get_engine_info(&self) -> EngineInfo {
         EngineInfo {
             engine_type: "Bounded".to_string(),
             memory_bounded: true,
             concurrent: false,
-            account_count: self.accounts.len(),
-            transaction_count: Some(self.disputable_transactions.len()),
+            account_count: self.ledger.accounts.len(),
+            transaction_count: Some(self.ledger.disputable_transactions.len()),
             memory_limits: Some(self.memory_limits.clone()),
+            stats: self.ledger.metrics.stats(),
+            rebalancing: None,
         }
     }
 }
@@ -0,0 +1,372 @@
+//! Fixed-capacity caches with a choice of eviction policy, used by the bounded engine.
+//!
+//! Reads through `peek`, `peek_mut`, `contains` and `iter` never count as a use, so
+//! exporting or monitoring state does not change which entries are evicted next.
+
+use lru::LruCache;
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::Hash;
+use std::num::NonZeroUsize;
+
+/// Which entry a full cache evicts to make room for a new one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EvictionPolicy {
+    /// The least recently used entry
+    #[default]
+    Lru,
+    /// The least frequently used entry; ties go to the least recently used
+    Lfu,
+    /// The oldest entry, however often it is used
+    Fifo,
+    /// Segmented LRU: new entries start on probation and move to a protected segment
+    /// (80% of the capacity) when used again, so a one-off scan only evicts other
+    /// probationary entries
+    Segmented,
+}
+
+impl std::str::FromStr for EvictionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "lru" => Ok(Self::Lru),
+            "lfu" => Ok(Self::Lfu),
+            "fifo" => Ok(Self::Fifo),
+            "segmented" | "slru" => Ok(Self::Segmented),
+            other => Err(format!(
+                "unknown eviction policy '{}' (expected lru, lfu, fifo or segmented)",
+                other
+            )),
+        }
+    }
+}
+
+/// A cache holding at most `cap` entries, evicting according to its `EvictionPolicy`.
+/// Method names and return values follow `lru::LruCache`.
+#[derive(Debug)]
+pub struct BoundedCache<K: Hash + Eq, V> {
+    inner: Inner<K, V>,
+}
+
+#[derive(Debug)]
+enum Inner<K: Hash + Eq, V> {
+    Lru(LruCache<K, V>),
+    /// An `LruCache` that is never promoted, so its LRU entry is the oldest
+    Fifo(LruCache<K, V>),
+    Lfu(Box<LfuCache<K, V>>),
+    Segmented(Box<SegmentedCache<K, V>>),
+}
+
+impl<K: Hash + Eq + Ord + Copy, V> BoundedCache<K, V> {
+    pub fn new(policy: EvictionPolicy, cap: NonZeroUsize) -> Self {
+        let inner = match policy {
+            EvictionPolicy::Lru => Inner::Lru(LruCache::new(cap)),
+            EvictionPolicy::Fifo => Inner::Fifo(LruCache::new(cap)),
+            EvictionPolicy::Lfu => Inner::Lfu(Box::new(LfuCache::new(cap.get()))),
+            EvictionPolicy::Segmented => Inner::Segmented(Box::new(SegmentedCache::new(cap.get()))),
+        };
+        Self { inner }
+    }
+
+    pub fn policy(&self) -> EvictionPolicy {
+        match &self.inner {
+            Inner::Lru(_) => EvictionPolicy::Lru,
+            Inner::Fifo(_) => EvictionPolicy::Fifo,
+            Inner::Lfu(_) => EvictionPolicy::Lfu,
+            Inner::Segmented(_) => EvictionPolicy::Segmented,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.inner {
+            Inner::Lru(cache) | Inner::Fifo(cache) => cache.len(),
+            Inner::Lfu(cache) => cache.entries.len(),
+            Inner::Segmented(cache) => cache.probation.len() + cache.protected.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.peek(key).is_some()
+    }
+
+    /// The value for `key` without counting as a use
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        match &self.inner {
+            Inner::Lru(cache) | Inner::Fifo(cache) => cache.peek(key),
+            Inner::Lfu(cache) => cache.entries.get(key).map(|entry| &entry.value),
+            Inner::Segmented(cache) => cache
+                .protected
+                .peek(key)
+                .or_else(|| cache.probation.peek(key)),
+        }
+    }
+
+    /// The value for `key` without counting as a use
+    pub fn peek_mut(&mut self, key: &K) -> Option<&mut V> {
+        match &mut self.inner {
+            Inner::Lru(cache) | Inner::Fifo(cache) => cache.peek_mut(key),
+            Inner::Lfu(cache) => cache.entries.get_mut(key).map(|entry| &mut entry.value),
+            Inner::Segmented(cache) => {
+                if cache.protected.contains(key) {
+                    cache.protected.peek_mut(key)
+                } else {
+                    cache.probation.peek_mut(key)
+                }
+            }
+        }
+    }
+
+    /// The value for `key`, counting as a use
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        match &mut self.inner {
+            Inner::Lru(cache) => cache.get_mut(key),
+            Inner::Fifo(cache) => cache.peek_mut(key),
+            Inner::Lfu(cache) => cache.get_mut(key),
+            Inner::Segmented(cache) => cache.get_mut(key),
+        }
+    }
+
+    /// Insert or replace the value for `key`, returning the value it replaced. Replacing
+    /// counts as a use.
+    pub fn put(&mut self, key: K, value: V) -> Option<V> {
+        match self.push(key, value) {
+            Some((old_key, old_value)) if old_key == key => Some(old_value),
+            _ => None,
+        }
+    }
+
+    /// Insert or replace the value for `key`. Returns the replaced entry, or the entry
+    /// evicted to make room for a new key.
+    pub fn push(&mut self, key: K, value: V) -> Option<(K, V)> {
+        if let Some(existing) = self.get_mut(&key) {
+            return Some((key, std::mem::replace(existing, value)));
+        }
+        match &mut self.inner {
+            Inner::Lru(cache) | Inner::Fifo(cache) => cache.push(key, value),
+            Inner::Lfu(cache) => cache.insert(key, value),
+            Inner::Segmented(cache) => cache.insert(key, value),
+        }
+    }
+
+    pub fn pop(&mut self, key: &K) -> Option<V> {
+        match &mut self.inner {
+            Inner::Lru(cache) | Inner::Fifo(cache) => cache.pop(key),
+            Inner::Lfu(cache) => cache.remove(key),
+            Inner::Segmented(cache) => cache
+                .protected
+                .pop(key)
+                .or_else(|| cache.probation.pop(key)),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        match &mut self.inner {
+            Inner::Lru(cache) | Inner::Fifo(cache) => cache.clear(),
+            Inner::Lfu(cache) => {
+                cache.entries.clear();
+                cache.order.clear();
+            }
+            Inner::Segmented(cache) => {
+                cache.probation.clear();
+                cache.protected.clear();
+            }
+        }
+    }
+
+    /// Every entry, from the one that would be evicted last to the one evicted first.
+    /// Does not count as a use.
+    pub fn iter(&self) -> Box<dyn DoubleEndedIterator<Item = (&K, &V)> + '_> {
+        match &self.inner {
+            Inner::Lru(cache) | Inner::Fifo(cache) => Box::new(cache.iter()),
+            Inner::Lfu(cache) => Box::new(
+                cache
+                    .order
+                    .iter()
+                    .rev()
+                    .map(|(_, _, key)| (key, &cache.entries[key].value)),
+            ),
+            Inner::Segmented(cache) => {
+                Box::new(cache.protected.iter().chain(cache.probation.iter()))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct LfuEntry<V> {
+    value: V,
+    uses: u64,
+    /// When the entry was last used, to break ties between equal use counts
+    last_used: u64,
+}
+
+/// Entries ordered by `(uses, last_used)`, so the first is the next to be evicted
+#[derive(Debug)]
+struct LfuCache<K, V> {
+    cap: usize,
+    entries: HashMap<K, LfuEntry<V>>,
+    order: BTreeSet<(u64, u64, K)>,
+    clock: u64,
+}
+
+impl<K: Hash + Eq + Ord + Copy, V> LfuCache<K, V> {
+    fn new(cap: usize) -> Self {
+        Self {
+            cap,
+            entries: HashMap::new(),
+            order: BTreeSet::new(),
+            clock: 0,
+        }
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let entry = self.entries.get_mut(key)?;
+        self.order.remove(&(entry.uses, entry.last_used, *key));
+        self.clock += 1;
+        entry.uses += 1;
+        entry.last_used = self.clock;
+        self.order.insert((entry.uses, entry.last_used, *key));
+        Some(&mut entry.value)
+    }
+
+    /// Insert a key that is not in the cache yet
+    fn insert(&mut self, key: K, value: V) -> Option<(K, V)> {
+        let evicted = if self.entries.len() >= self.cap {
+            self.order
+                .pop_first()
+                .and_then(|(_, _, evicted)| self.entries.remove_entry(&evicted))
+                .map(|(evicted, entry)| (evicted, entry.value))
+        } else {
+            None
+        };
+        self.clock += 1;
+        self.entries.insert(
+            key,
+            LfuEntry {
+                value,
+                uses: 1,
+                last_used: self.clock,
+            },
+        );
+        self.order.insert((1, self.clock, key));
+        evicted
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.order.remove(&(entry.uses, entry.last_used, *key));
+        Some(entry.value)
+    }
+}
+
+/// Segmented LRU with a probationary and a protected segment
+#[derive(Debug)]
+struct SegmentedCache<K: Hash + Eq, V> {
+    cap: usize,
+    protected_cap: usize,
+    probation: LruCache<K, V>,
+    protected: LruCache<K, V>,
+}
+
+impl<K: Hash + Eq + Copy, V> SegmentedCache<K, V> {
+    fn new(cap: usize) -> Self {
+        Self {
+            cap,
+            protected_cap: cap * 4 / 5,
+            probation: LruCache::unbounded(),
+            protected: LruCache::unbounded(),
+        }
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        if self.protected.contains(key) {
+            return self.protected.get_mut(key);
+        }
+        let value = self.probation.pop(key)?;
+        if self.protected_cap == 0 {
+            self.probation.push(*key, value);
+            return self.probation.get_mut(key);
+        }
+        self.protected.push(*key, value);
+        // A full protected segment demotes its least recently used entry
+        if self.protected.len() > self.protected_cap
+            && let Some((demoted, value)) = self.protected.pop_lru()
+        {
+            self.probation.push(demoted, value);
+        }
+        self.protected.get_mut(key)
+    }
+
+    /// Insert a key that is not in the cache yet
+    fn insert(&mut self, key: K, value: V) -> Option<(K, V)> {
+        let evicted = if self.probation.len() + self.protected.len() >= self.cap {
+            self.probation
+                .pop_lru()
+                .or_else(|| self.protected.pop_lru())
+        } else {
+            None
+        };
+        self.probation.push(key, value);
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fill a cache of `cap` with keys `1..=cap`, use `used` in order, insert a new key
+    /// and return the key that was evicted for it
+    fn evicted(policy: EvictionPolicy, cap: usize, used: &[u32]) -> Option<u32> {
+        let mut cache = BoundedCache::new(policy, NonZeroUsize::new(cap).unwrap());
+        for key in 1..=cap as u32 {
+            assert!(cache.push(key, ()).is_none());
+        }
+        for key in used {
+            cache.get_mut(key).unwrap();
+            // Reads do not count as a use
+            cache.peek(&1).unwrap();
+        }
+        let evicted = cache.push(cap as u32 + 1, ()).map(|(key, _)| key);
+        assert_eq!(cache.len(), cap);
+        evicted
+    }
+
+    #[test]
+    fn test_eviction_policies() {
+        assert_eq!(evicted(EvictionPolicy::Lru, 3, &[1, 2]), Some(3));
+        assert_eq!(evicted(EvictionPolicy::Fifo, 3, &[1, 2]), Some(1));
+        assert_eq!(evicted(EvictionPolicy::Lfu, 3, &[1, 1, 3, 2, 2]), Some(3));
+        assert_eq!(evicted(EvictionPolicy::Segmented, 5, &[1, 2]), Some(3));
+    }
+
+    #[test]
+    fn test_segmented_resists_scans() {
+        let mut cache = BoundedCache::new(EvictionPolicy::Segmented, NonZeroUsize::new(5).unwrap());
+        cache.put(1, "hot");
+        cache.get_mut(&1).unwrap();
+        for key in 2..100 {
+            cache.put(key, "scan");
+        }
+        assert_eq!(cache.peek(&1), Some(&"hot"));
+        assert_eq!(cache.len(), 5);
+
+        // Replacing a value keeps a single entry
+        assert_eq!(cache.put(1, "updated"), Some("hot"));
+        assert_eq!(cache.pop(&1), Some("updated"));
+        assert!(!cache.contains(&1));
+    }
+
+    #[test]
+    fn test_parse_eviction_policy() {
+        assert_eq!("LFU".parse(), Ok(EvictionPolicy::Lfu));
+        assert_eq!("slru".parse(), Ok(EvictionPolicy::Segmented));
+        assert!("mru".parse::<EvictionPolicy>().is_err());
+    }
+}
@@ -0,0 +1,106 @@
+//! Fault injection for `ConcurrentEngine` workers, enabled with the `testing` feature.
+//!
+//! A `FaultInjector` installed with `ConcurrentEngine::set_fault_injector` makes the
+//! workers of `process_transactions_from_reader` sleep before a record, discard a record
+//! as if it was lost in the channel, or panic. Whether a record is hit depends only on the
+//! seed and the record's input index, so a failing run can be replayed exactly. Dropped
+//! records and panics must come back as `PaymentsError::WorkerFailed`, never as `Ok`.
+
+use std::time::Duration;
+
+/// What a worker does to a record instead of, or before, applying it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Sleep, then apply the record
+    Delay(Duration),
+    /// Discard the record without applying or counting it
+    Drop,
+    /// Panic instead of applying the record
+    Panic,
+}
+
+/// Rates of each fault, as probabilities per record
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FaultInjector {
+    /// Chance that a worker sleeps before applying a record
+    pub delay_rate: f64,
+    /// Longest injected sleep; each delay is uniform up to this
+    pub max_delay: Duration,
+    /// Chance that a worker drops a record
+    pub drop_rate: f64,
+    /// Chance that a worker panics on a record
+    pub panic_rate: f64,
+    pub seed: u64,
+}
+
+impl Default for FaultInjector {
+    fn default() -> Self {
+        Self {
+            delay_rate: 0.0,
+            max_delay: Duration::from_millis(1),
+            drop_rate: 0.0,
+            panic_rate: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+impl FaultInjector {
+    /// The fault injected on the record at input index `seq`, if any. Panics take
+    /// precedence over drops, and drops over delays.
+    pub fn fault(&self, seq: usize) -> Option<Fault> {
+        let hash = splitmix64(self.seed ^ seq as u64);
+        let roll = unit(hash);
+        if roll < self.panic_rate {
+            Some(Fault::Panic)
+        } else if roll < self.panic_rate + self.drop_rate {
+            Some(Fault::Drop)
+        } else if roll < self.panic_rate + self.drop_rate + self.delay_rate {
+            Some(Fault::Delay(self.max_delay.mul_f64(unit(splitmix64(hash)))))
+        } else {
+            None
+        }
+    }
+}
+
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// `x` mapped to `[0, 1)`
+fn unit(x: u64) -> f64 {
+    (x >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fault_rates() {
+        let injector = FaultInjector {
+            delay_rate: 0.2,
+            drop_rate: 0.1,
+            panic_rate: 0.01,
+            seed: 7,
+            ..FaultInjector::default()
+        };
+        let faults: Vec<_> = (0..10_000).map(|seq| injector.fault(seq)).collect();
+        let count =
+            |matches: fn(&Option<Fault>) -> bool| faults.iter().filter(|f| matches(f)).count();
+
+        assert!((50..150).contains(&count(|f| *f == Some(Fault::Panic))));
+        assert!((800..1200).contains(&count(|f| *f == Some(Fault::Drop))));
+        assert!((1700..2300).contains(&count(|f| matches!(f, Some(Fault::Delay(_))))));
+        assert!(faults.iter().all(|f| match f {
+            Some(Fault::Delay(delay)) => *delay <= injector.max_delay,
+            _ => true,
+        }));
+        // Decisions depend only on the seed and the input index
+        assert_eq!(injector.fault(1234), faults[1234]);
+        assert_eq!(FaultInjector::default().fault(0), None);
+    }
+}
@@ -1,20 +1,218 @@
+use std::collections::HashMap;
 use std::io::Read;
-use std::sync::{Arc, Mutex};
 
 use std::sync::mpsc;
 use std::thread;
 
-use super::{EngineInfo, MemoryLimits, bounded::BoundedEngine};
+use super::auth::{Authenticator, Credentials, Identity};
+use super::cache::BoundedCache;
+#[cfg(any(test, feature = "testing"))]
+use super::chaos::{Fault, FaultInjector};
+use super::expiry::DisputeExpiry;
+use super::metrics::{EngineStats, start_timer};
+use super::observer::EngineObserver;
+use super::ordering::ClientOrder;
+use super::pipeline::{
+    BATCHES_PER_PARSER, MAX_IN_FLIGHT, ParsedBatch, PipelineCounters, PipelineStats, Reorder,
+    parse_stage, read_stage,
+};
+use super::ratelimit::{RateLimit, StreamThrottle};
+use super::recurring::RecurringScheduler;
+use super::replay::{Schedule, ScheduleEntry};
+use super::routing::{ConsistentHashRouter, Router};
+use super::scheduler::{ClientScheduler, RunQueue, Work};
+use super::state::EngineState;
+use super::store::TransactionStore;
+use super::sync::{self, Arc, AtomicUsize, Condvar, Mutex, Ordering};
+use super::validation::TransactionValidator;
+use super::{
+    EngineInfo, EnginePolicy, ErrorPolicy, MemoryLimits, ParseErrorLimit, ParseErrorTally,
+    RebalanceStats, SourceTxIds, bounded::BoundedEngine, read_transactions, transaction_headers,
+    transaction_reader,
+};
+use crate::account::{Account, ClientId, DecimalFormat, LockState};
 use crate::errors::PaymentsError;
-use crate::transaction::Transaction;
+use crate::shutdown::ShutdownFlag;
+use crate::transaction::{InputFormat, StoredTransaction, Transaction, TxId};
 
 /// Concurrent TCP stream processing engine for handling thousands of concurrent streams.
 /// Uses thread-safe Arc<Mutex<BoundedEngine>> for shared state management.
 /// Each stream processes transactions independently while maintaining global consistency.
 #[derive(Debug)]
-pub struct ConcurrentEngine {
-    engine: Arc<Mutex<BoundedEngine>>,
+pub struct ConcurrentEngine<S = BoundedCache<TxId, StoredTransaction>> {
+    engine: Arc<Mutex<BoundedEngine<S>>>,
     memory_limits: MemoryLimits,
+    /// Order in which workers applied input records, when recording is enabled
+    schedule: Option<Arc<Mutex<Vec<ScheduleEntry>>>>,
+    /// Worker thread count for reader-based processing; defaults to available parallelism
+    worker_pool: WorkerPool,
+    /// Assigns each client to the worker that processes all of its transactions
+    router: Box<dyn Router>,
+    /// How reader-based processing reacts to bad rows and rejected transactions
+    error_policy: ErrorPolicy,
+    /// How reader-based processing parses the `type` and `amount` columns
+    input_format: InputFormat,
+    /// Parse failures after which reader-based processing fails
+    parse_error_limit: Option<ParseErrorLimit>,
+    /// Checked between records; once requested, no new records are read or dispatched
+    shutdown: ShutdownFlag,
+    /// Pace applied to each stream of `process_stream_transactions`
+    stream_rate_limit: Option<RateLimit>,
+    /// Number of `process_stream_transactions` threads still running, for `drain`
+    active_streams: Arc<(Mutex<usize>, Condvar)>,
+    /// Per-worker counters of the latest `process_transactions_from_reader` call, by worker ID
+    worker_counters: Mutex<Vec<Arc<WorkerCounters>>>,
+    /// Threads parsing records in `process_transactions_from_reader`; 0 for the default
+    parse_threads: usize,
+    /// Per-stage counters of the latest `process_transactions_from_reader` call
+    pipeline_counters: Mutex<Arc<PipelineCounters>>,
+    /// Faults injected into reader-based processing workers
+    #[cfg(any(test, feature = "testing"))]
+    faults: Option<FaultInjector>,
+}
+
+/// A record queued for a worker: input index, when it was queued, the counters of its
+/// client's home worker (whose queue depth includes it), and the transaction
+type QueuedTransaction = (
+    usize,
+    Option<std::time::Instant>,
+    Arc<WorkerCounters>,
+    Transaction,
+);
+
+/// Result of one `process_transactions_from_reader` worker thread: records applied
+type WorkerHandle = thread::JoinHandle<Result<usize, Box<dyn std::error::Error + Send + Sync>>>;
+
+/// How long the dispatcher sleeps while `MAX_IN_FLIGHT` records wait for the workers
+const IN_FLIGHT_WAIT: std::time::Duration = std::time::Duration::from_micros(100);
+
+/// What the apply stage of `process_transactions_from_reader` left for the calling thread
+struct Dispatch {
+    /// Every worker thread started, with its worker ID
+    handles: Vec<(usize, WorkerHandle)>,
+    /// By worker ID
+    counters: Vec<Arc<WorkerCounters>>,
+    sent_count: usize,
+    parse_errors: ParseErrorTally,
+    /// Why dispatching stopped at a bad row, if it did
+    parse_error: Option<PaymentsError>,
+    /// Line at which records stopped being dispatched before the end of the input
+    stopped_at: Option<usize>,
+}
+
+/// Records the workers with `counters` have applied or rejected
+fn handled(counters: &[Arc<WorkerCounters>]) -> usize {
+    counters
+        .iter()
+        .map(|counters| {
+            counters.processed.load(Ordering::Relaxed) + counters.errors.load(Ordering::Relaxed)
+        })
+        .sum()
+}
+
+/// Why a stream stopped before the end of its input
+pub type StreamError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Outcome of one stream of `ConcurrentEngine::process_concurrent_streams`
+#[derive(Debug, Default)]
+pub struct StreamResult {
+    pub stream_id: u64,
+    /// Transactions applied
+    pub processed: usize,
+    /// Rows that failed to parse or were rejected by the engine
+    pub errors: usize,
+    /// Time spent waiting for the stream rate limit
+    pub throttled: std::time::Duration,
+    /// Time from the start of the stream until it was read to the end or stopped
+    pub elapsed: std::time::Duration,
+    /// Why the stream stopped early, if it did; downcast to `PaymentsError` for its code
+    pub failure: Option<StreamError>,
+}
+
+/// Message of a panicked thread's payload
+pub(crate) fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_default()
+}
+
+/// Live counters for one `process_transactions_from_reader` worker
+#[derive(Debug, Default)]
+struct WorkerCounters {
+    processed: AtomicUsize,
+    errors: AtomicUsize,
+    queued: AtomicUsize,
+    clients: AtomicUsize,
+    /// Clients taken over from another worker's run queue
+    steals: AtomicUsize,
+    /// Records applied for clients whose home is another worker
+    stolen: AtomicUsize,
+}
+
+/// Snapshot of one worker's activity during the most recent (or still running)
+/// `process_transactions_from_reader` call. A worker that handles far more records or
+/// clients than the others points at a skewed client distribution.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WorkerStats {
+    pub worker_id: usize,
+    /// Records applied successfully
+    pub processed: usize,
+    /// Records rejected by the engine
+    pub errors: usize,
+    /// Records sent to the worker but not yet applied
+    pub queue_depth: usize,
+    /// Clients currently assigned to the worker
+    pub accounts: usize,
+    /// Clients the worker took over from another worker's run queue while idle
+    pub steals: usize,
+    /// Records the worker applied for clients assigned to other workers
+    pub stolen: usize,
+}
+
+/// Shared, resizable worker count for `ConcurrentEngine::process_transactions_from_reader`.
+/// Clones refer to the same pool, so another thread can grow or shrink the pool of a
+/// running call; the new size takes effect at the next record.
+#[derive(Debug, Clone, Default)]
+pub struct WorkerPool(Arc<AtomicUsize>);
+
+impl WorkerPool {
+    /// Run `num_workers` workers (at least one) instead of the available parallelism
+    pub fn resize(&self, num_workers: usize) {
+        self.0.store(num_workers.max(1), Ordering::SeqCst);
+    }
+
+    /// Requested worker count, or `None` when sized from the available parallelism
+    pub fn size(&self) -> Option<usize> {
+        match self.0.load(Ordering::SeqCst) {
+            0 => None,
+            n => Some(n),
+        }
+    }
+
+    fn effective_size(&self) -> usize {
+        self.size().unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(4)
+        })
+    }
+}
+
+/// Decrements the active stream count when a stream thread finishes, including on
+/// error or panic, and wakes any thread blocked in `ConcurrentEngine::drain`.
+struct ActiveStreamGuard(Arc<(Mutex<usize>, Condvar)>);
+
+impl Drop for ActiveStreamGuard {
+    fn drop(&mut self) {
+        let (count, finished) = &*self.0;
+        let mut count = count.lock().unwrap_or_else(|e| e.into_inner());
+        *count -= 1;
+        if *count == 0 {
+            finished.notify_all();
+        }
+    }
 }
 
 impl ConcurrentEngine {
@@ -23,27 +221,316 @@ impl ConcurrentEngine {
         max_disputable_transactions: usize,
         max_processed_tx_ids: usize,
     ) -> Self {
-        let engine = BoundedEngine::new(
+        Self::from_engine(BoundedEngine::new(
             max_accounts,
             max_disputable_transactions,
             max_processed_tx_ids,
-        );
-        let memory_limits = MemoryLimits {
-            max_accounts,
-            max_disputable_transactions,
-            max_processed_tx_ids,
-        };
+        ))
+    }
+}
+
+impl<S: TransactionStore + 'static> ConcurrentEngine<S> {
+    /// Share `engine`, e.g. one set up with `BoundedEngine::with_store`, between the
+    /// worker threads
+    pub fn from_engine(engine: BoundedEngine<S>) -> Self {
         Self {
+            memory_limits: engine.memory_limits().clone(),
             engine: Arc::new(Mutex::new(engine)),
-            memory_limits,
+            schedule: None,
+            worker_pool: WorkerPool::default(),
+            router: Box::new(ConsistentHashRouter),
+            error_policy: ErrorPolicy::default(),
+            parse_error_limit: None,
+            input_format: InputFormat::default(),
+            shutdown: ShutdownFlag::default(),
+            stream_rate_limit: None,
+            active_streams: Arc::new((Mutex::new(0), Condvar::new())),
+            worker_counters: Mutex::new(Vec::new()),
+            parse_threads: 0,
+            pipeline_counters: Mutex::default(),
+            #[cfg(any(test, feature = "testing"))]
+            faults: None,
+        }
+    }
+
+    /// Use a fixed number of worker threads instead of the available parallelism
+    pub fn set_num_workers(&mut self, num_workers: usize) {
+        self.worker_pool.resize(num_workers);
+    }
+
+    /// Handle for resizing the worker pool, including while
+    /// `process_transactions_from_reader` is running on another thread
+    pub fn worker_pool(&self) -> WorkerPool {
+        self.worker_pool.clone()
+    }
+
+    /// Replace the default `ConsistentHashRouter` used to assign clients to workers
+    pub fn set_router(&mut self, router: impl Router + 'static) {
+        self.router = Box::new(router);
+    }
+
+    pub fn set_error_policy(&mut self, error_policy: ErrorPolicy) {
+        self.error_policy = error_policy;
+    }
+
+    pub fn set_parse_error_limit(&mut self, limit: Option<ParseErrorLimit>) {
+        self.parse_error_limit = limit;
+    }
+
+    pub fn parse_error_limit(&self) -> Option<ParseErrorLimit> {
+        self.parse_error_limit
+    }
+
+    pub fn set_input_format(&mut self, input_format: InputFormat) {
+        self.input_format = input_format;
+    }
+
+    /// Slow each stream started from now on down to `limit`, before it takes the engine
+    /// lock, so one busy connection cannot crowd out the others
+    pub fn set_stream_rate_limit(&mut self, limit: Option<RateLimit>) {
+        self.stream_rate_limit = limit;
+    }
+
+    pub fn input_format(&self) -> &InputFormat {
+        &self.input_format
+    }
+
+    /// IDs of the client's transactions that are currently under dispute; see
+    /// `BoundedEngine::open_disputes`
+    pub fn open_disputes(&self, client: ClientId) -> Vec<TxId> {
+        match self.engine.lock() {
+            Ok(engine) => engine.open_disputes(client),
+            Err(e) => {
+                log::error!("Failed to acquire engine lock: {}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Run `validator` on every transaction, after the validators already added
+    pub fn add_validator(&mut self, validator: impl TransactionValidator + 'static) {
+        match self.engine.lock() {
+            Ok(mut engine) => engine.add_validator(validator),
+            Err(e) => log::error!("Failed to acquire engine lock: {}", e),
+        }
+    }
+
+    /// Notify `observer` of every transaction processed from now on. Observers run on
+    /// the worker threads while they hold the engine lock.
+    pub fn add_observer(&mut self, observer: impl EngineObserver + 'static) {
+        match self.engine.lock() {
+            Ok(mut engine) => engine.add_observer(observer),
+            Err(e) => log::error!("Failed to acquire engine lock: {}", e),
+        }
+    }
+
+    /// Apply `recurring`'s occurrences as timestamps advance. Occurrences are applied by
+    /// whichever worker first processes a row stamped at or after them.
+    pub fn set_recurring(&mut self, recurring: RecurringScheduler) {
+        match self.engine.lock() {
+            Ok(mut engine) => engine.set_recurring(recurring),
+            Err(e) => log::error!("Failed to acquire engine lock: {}", e),
+        }
+    }
+
+    /// Drop disputable transactions once `expiry`'s window ends. With several workers,
+    /// rows count towards `max_transactions` in the order the workers process them.
+    pub fn set_dispute_expiry(&mut self, expiry: DisputeExpiry) {
+        match self.engine.lock() {
+            Ok(mut engine) => engine.set_dispute_expiry(expiry),
+            Err(e) => log::error!("Failed to acquire engine lock: {}", e),
+        }
+    }
+
+    /// Replace the business rules applied to account operations
+    pub fn set_policy(&mut self, policy: EnginePolicy) {
+        match self.engine.lock() {
+            Ok(mut engine) => engine.set_policy(policy),
+            Err(e) => log::error!("Failed to acquire engine lock: {}", e),
         }
     }
 
+    /// Set how balances are written to account output
+    pub fn set_decimal_format(&mut self, decimal_format: DecimalFormat) {
+        match self.engine.lock() {
+            Ok(mut engine) => engine.set_decimal_format(decimal_format),
+            Err(e) => log::error!("Failed to acquire engine lock: {}", e),
+        }
+    }
+
+    pub fn decimal_format(&self) -> DecimalFormat {
+        self.engine
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .decimal_format()
+    }
+
+    /// The business rules applied to account operations
+    pub fn policy(&self) -> Result<EnginePolicy, PaymentsError> {
+        let engine = self
+            .engine
+            .lock()
+            .map_err(|_| PaymentsError::LockPoisoned("engine"))?;
+        Ok(engine.policy())
+    }
+
+    /// Stop reading new records once `shutdown` is requested. Records already sent to
+    /// workers are still processed before `process_transactions_from_reader` returns.
+    pub fn set_shutdown_flag(&mut self, shutdown: ShutdownFlag) {
+        self.shutdown = shutdown;
+    }
+
+    /// Make the workers of `process_transactions_from_reader` delay, drop or panic on
+    /// records as `faults` decides; see `chaos`
+    #[cfg(any(test, feature = "testing"))]
+    pub fn set_fault_injector(&mut self, faults: Option<FaultInjector>) {
+        self.faults = faults;
+    }
+
+    /// Record the order in which worker threads apply records in
+    /// `process_transactions_from_reader`, so the run can be replayed exactly.
+    pub fn enable_schedule_recording(&mut self) {
+        self.schedule = Some(Arc::new(Mutex::new(Vec::new())));
+    }
+
+    /// Stop recording and return the schedule captured so far.
+    pub fn take_schedule(&mut self) -> Option<Schedule> {
+        let schedule = self.schedule.take()?;
+        let entries = match schedule.lock() {
+            Ok(mut entries) => std::mem::take(&mut *entries),
+            Err(e) => {
+                log::error!("Failed to acquire schedule lock: {}", e);
+                return None;
+            }
+        };
+        Some(Schedule { entries })
+    }
+
     pub fn process_transaction(&mut self, transaction: &Transaction) -> Result<(), PaymentsError> {
-        let mut engine_guard = self.engine.lock().map_err(|e| {
-            PaymentsError::InvalidTransaction(format!("Failed to acquire engine lock: {}", e))
-        })?;
-        engine_guard.process_transaction(transaction)
+        self.process_transaction_shared(transaction)
+    }
+
+    /// Process a single transaction through a shared reference.
+    /// Safe to call from several threads at once; each call holds the engine lock
+    /// for the whole duplicate check and balance update.
+    /// Recorded latency includes the time spent waiting for the lock.
+    pub fn process_transaction_shared(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<(), PaymentsError> {
+        let started = start_timer();
+        let mut engine_guard = self
+            .engine
+            .lock()
+            .map_err(|_| PaymentsError::LockPoisoned("engine"))?;
+        engine_guard.process_transaction_since(transaction, started)
+    }
+
+    /// Apply `transactions` in order under a single hold of the engine lock, returning
+    /// each one's result. If the lock is poisoned every transaction fails with
+    /// `LockPoisoned`.
+    pub fn process_transactions(
+        &mut self,
+        transactions: &[Transaction],
+    ) -> Vec<Result<(), PaymentsError>> {
+        let Ok(mut engine_guard) = self.engine.lock() else {
+            return transactions
+                .iter()
+                .map(|_| Err(PaymentsError::LockPoisoned("engine")))
+                .collect();
+        };
+        transactions
+            .iter()
+            .map(|transaction| engine_guard.process_transaction_since(transaction, start_timer()))
+            .collect()
+    }
+
+    /// Snapshot of the shared engine's state; see `BoundedEngine::export_state`.
+    pub fn export_state(&self) -> Result<EngineState, PaymentsError> {
+        let engine_guard = self
+            .engine
+            .lock()
+            .map_err(|_| PaymentsError::LockPoisoned("engine"))?;
+        Ok(engine_guard.export_state())
+    }
+
+    /// A copy of the client's account; see `BoundedEngine::peek_account`.
+    pub fn peek_account(&self, client: ClientId) -> Result<Option<Account>, PaymentsError> {
+        let engine_guard = self
+            .engine
+            .lock()
+            .map_err(|_| PaymentsError::LockPoisoned("engine"))?;
+        Ok(engine_guard.peek_account(client).cloned())
+    }
+
+    /// Up to `limit` accounts by client ID, skipping the first `offset`; see
+    /// `BoundedEngine::accounts_page`.
+    pub fn accounts_page(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<Account>, PaymentsError> {
+        let engine_guard = self
+            .engine
+            .lock()
+            .map_err(|_| PaymentsError::LockPoisoned("engine"))?;
+        Ok(engine_guard.accounts_page(offset, limit))
+    }
+
+    /// Replaces the shared engine's state with a previously exported snapshot.
+    /// This also recovers an engine that is no longer healthy: the state a panicked
+    /// thread left behind is discarded and the engine accepts calls again.
+    pub fn import_state(&self, state: EngineState) -> Result<(), PaymentsError> {
+        let mut engine_guard = self.engine.lock().unwrap_or_else(|e| e.into_inner());
+        engine_guard.import_state(state);
+        if sync::is_poisoned(&self.engine) {
+            sync::clear_poison(&self.engine);
+            log::warn!("Engine recovered from a poisoned lock by importing a snapshot");
+        }
+        Ok(())
+    }
+
+    /// False once a thread has panicked while holding the engine lock, which may have
+    /// left a half-applied transaction behind. Calls that need the engine then fail with
+    /// `LockPoisoned` until `import_state` rebuilds it, e.g. from the last checkpoint.
+    pub fn is_healthy(&self) -> bool {
+        !sync::is_poisoned(&self.engine)
+    }
+
+    /// Inserts an account as-is, replacing any existing account for the same client.
+    pub fn insert_account(&self, account: Account) -> Result<(), PaymentsError> {
+        let mut engine_guard = self
+            .engine
+            .lock()
+            .map_err(|_| PaymentsError::LockPoisoned("engine"))?;
+        engine_guard.insert_account(account);
+        Ok(())
+    }
+
+    /// Resolve a dispute through any lock state under a single hold of the engine lock;
+    /// see `BoundedEngine::force_resolve`.
+    pub fn force_resolve(&self, transaction: &Transaction) -> Result<(), PaymentsError> {
+        let mut engine_guard = self
+            .engine
+            .lock()
+            .map_err(|_| PaymentsError::LockPoisoned("engine"))?;
+        engine_guard.force_resolve(transaction)
+    }
+
+    /// Put the client's account into `lock` under a single hold of the engine lock, so
+    /// no transaction applied meanwhile is lost; see `BoundedEngine::set_lock`.
+    pub fn set_lock(
+        &self,
+        client: ClientId,
+        lock: LockState,
+        reason: Option<String>,
+    ) -> Result<(), PaymentsError> {
+        let mut engine_guard = self
+            .engine
+            .lock()
+            .map_err(|_| PaymentsError::LockPoisoned("engine"))?;
+        engine_guard.set_lock(client, lock, reason);
+        Ok(())
     }
 
     /// Process transactions from a single TCP stream.
@@ -53,174 +540,451 @@ impl ConcurrentEngine {
         &self,
         reader: R,
         stream_id: u64,
-    ) -> std::thread::JoinHandle<Result<(), Box<dyn std::error::Error + Send + Sync>>> {
+    ) -> std::thread::JoinHandle<Result<(), StreamError>> {
+        self.spawn_stream(reader, stream_id, None, |_, result| result)
+    }
+
+    /// Like `process_stream_transactions`, for a stream that must authenticate first.
+    /// Fails without reading the stream if `authenticator` rejects `credentials`. Rows
+    /// for clients the identity may not submit for are rejected with `Unauthorized` and
+    /// reported to observers, and the rest of the stream is processed as usual.
+    pub fn process_authenticated_stream<R: Read + Send + 'static>(
+        &self,
+        reader: R,
+        stream_id: u64,
+        credentials: &Credentials,
+        authenticator: &dyn Authenticator,
+    ) -> Result<std::thread::JoinHandle<Result<(), StreamError>>, PaymentsError> {
+        let identity = match authenticator.authenticate(credentials) {
+            Ok(identity) => identity,
+            Err(e) => {
+                log::warn!("Stream {}: {} ({:?})", stream_id, e, credentials);
+                self.engine
+                    .lock()
+                    .map_err(|_| PaymentsError::LockPoisoned("engine"))?
+                    .observers()
+                    .authentication_failed(credentials, &e);
+                return Err(e);
+            }
+        };
+        log::info!("Stream {} authenticated as {}", stream_id, identity.name);
+        Ok(self.spawn_stream(reader, stream_id, Some(identity), |_, result| result))
+    }
+
+    /// Read `reader` on a new thread as stream `stream_id`, rejecting rows `identity`
+    /// may not submit, then hand its counts and outcome to `finish`
+    fn spawn_stream<R: Read + Send + 'static, T: Send + 'static>(
+        &self,
+        reader: R,
+        stream_id: u64,
+        identity: Option<Identity>,
+        finish: fn(StreamResult, Result<(), StreamError>) -> T,
+    ) -> std::thread::JoinHandle<T> {
         let engine = self.engine.clone();
+        let shutdown = self.shutdown.clone();
+        let input_format = self.input_format.clone();
+        let mut parse_errors = ParseErrorTally::new(self.parse_error_limit);
+        let mut throttle = self.stream_rate_limit.map(StreamThrottle::new);
+        {
+            let (count, _) = &*self.active_streams;
+            *count.lock().unwrap_or_else(|e| e.into_inner()) += 1;
+        }
+        let guard = ActiveStreamGuard(self.active_streams.clone());
+        let spawned = std::time::Instant::now();
 
         std::thread::spawn(move || {
-            let mut rdr = csv::ReaderBuilder::new()
-                .trim(csv::Trim::All)
-                .from_reader(reader);
+            let _guard = guard;
+            let mut counts = StreamResult {
+                stream_id,
+                ..StreamResult::default()
+            };
+            let read = || -> Result<(), StreamError> {
+                let mut rdr = transaction_reader(reader, &input_format);
+                let lines = read_transactions(&mut rdr, input_format)?.enumerate();
 
-            log::debug!("Processing transactions from stream {}", stream_id);
+                log::debug!("Processing transactions from stream {}", stream_id);
 
-            for (idx, line) in rdr.deserialize().enumerate() {
-                let transaction: Transaction = match line {
-                    Ok(tx) => tx,
-                    Err(e) => {
-                        log::error!(
-                            "Stream {}: Failed to parse line {}: {}",
+                for (idx, line) in lines {
+                    if shutdown.is_requested() {
+                        log::warn!(
+                            "Stream {}: Shutdown requested; stopping before line {}",
                             stream_id,
-                            idx + 1,
-                            e
+                            idx + 1
                         );
+                        break;
+                    }
+                    parse_errors.read();
+                    let transaction: Transaction = match line {
+                        Ok(tx) => tx,
+                        Err(e) => {
+                            log::error!(
+                                "Stream {}: Failed to parse line {}: {}",
+                                stream_id,
+                                idx + 1,
+                                e
+                            );
+                            counts.errors += 1;
+                            parse_errors.failed()?;
+                            continue;
+                        }
+                    };
+
+                    if let Some(identity) = &identity
+                        && let Err(e) = identity.authorize(&transaction)
+                    {
+                        counts.errors += 1;
+                        log::warn!("Stream {}: {}", stream_id, e);
+                        let engine_guard = engine
+                            .lock()
+                            .map_err(|_| PaymentsError::LockPoisoned("engine"))?;
+                        engine_guard.observers().rejected(&transaction, &e);
+                        engine_guard
+                            .observers()
+                            .unauthorized(identity, &transaction);
                         continue;
                     }
-                };
 
-                // Acquire lock only for the duration of transaction processing
-                let result = {
-                    let mut engine_guard = engine
-                        .lock()
-                        .map_err(|e| format!("Failed to acquire engine lock: {}", e))?;
-                    engine_guard.process_transaction(&transaction)
-                };
+                    if let Some(throttle) = &mut throttle {
+                        throttle.wait();
+                    }
 
-                if let Err(e) = result {
-                    log::error!(
-                        "Stream {}: Failed to process transaction {:?}: {}",
-                        stream_id,
-                        transaction,
-                        e
-                    );
-                } else {
-                    log::debug!(
-                        "Stream {}: Successfully processed transaction: {:?}",
-                        stream_id,
-                        transaction
-                    );
+                    // Acquire lock only for the duration of transaction processing
+                    let started = start_timer();
+                    let result = {
+                        let mut engine_guard = engine
+                            .lock()
+                            .map_err(|_| PaymentsError::LockPoisoned("engine"))?;
+                        engine_guard.process_transaction_since(&transaction, started)
+                    };
+
+                    if let Err(e) = result {
+                        counts.errors += 1;
+                        log::error!(
+                            "Stream {}: Failed to process transaction {:?}: {}",
+                            stream_id,
+                            transaction,
+                            e
+                        );
+                    } else {
+                        counts.processed += 1;
+                        log::debug!(
+                            "Stream {}: Successfully processed transaction: {:?}",
+                            stream_id,
+                            transaction
+                        );
+                    }
                 }
-            }
 
-            log::info!("Completed processing stream {}", stream_id);
-            Ok(())
+                parse_errors.finish()?;
+                log::info!("Completed processing stream {}", stream_id);
+                Ok(())
+            };
+            let result = read();
+            if let Some(throttle) = &throttle {
+                counts.throttled = throttle.throttled;
+            }
+            counts.elapsed = spawned.elapsed();
+            finish(counts, result)
         })
     }
 
-    // Process transactions from reader using concurrent worker threads
-    /// This version assigns transactions to workers based on client ID to avoid race conditions
-    /// All transactions for the same client are processed by the same worker thread
-    pub fn process_transactions_from_reader<R: Read>(
-        &mut self,
-        reader: R,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let num_workers = std::thread::available_parallelism()
-            .map(|n| n.get())
-            .unwrap_or(4);
-
-        // Create separate channels for each worker
-        let mut worker_senders = Vec::new();
-        let mut worker_receivers = Vec::new();
-        for _ in 0..num_workers {
-            let (tx, rx) = mpsc::channel::<Transaction>();
-            worker_senders.push(tx);
-            worker_receivers.push(rx);
+    /// Block until every stream started with `process_stream_transactions` has been read
+    /// to the end and all of its transactions applied. Call this before
+    /// `write_accounts_csv` or `export_state` to avoid capturing a partially-applied snapshot.
+    /// Streams started while draining are waited for as well.
+    pub fn drain(&self) {
+        let (count, finished) = &*self.active_streams;
+        let mut count = count.lock().unwrap_or_else(|e| e.into_inner());
+        while *count > 0 {
+            log::debug!("Draining {} active streams", *count);
+            count = finished.wait(count).unwrap_or_else(|e| e.into_inner());
         }
+    }
 
-        log::debug!(
-            "Starting concurrent transaction processing with {} workers (client-based assignment)",
-            num_workers
+    /// Process several streams at once, one thread per stream, and wait for all of them.
+    /// Stream IDs are assigned from the position of each reader in `readers`.
+    /// Callers should route all transactions for a client to the same stream, otherwise
+    /// a dispute may be applied before the deposit it refers to.
+    ///
+    /// Returns one result per stream, in stream order. A stream that fails does not stop
+    /// the others; its `failure` says why, e.g. to retry just that stream.
+    pub fn process_concurrent_streams<R: Read + Send + 'static>(
+        &self,
+        readers: Vec<R>,
+    ) -> Vec<StreamResult> {
+        let handles: Vec<_> = readers
+            .into_iter()
+            .enumerate()
+            .map(|(stream_id, reader)| {
+                self.spawn_stream(reader, stream_id as u64, None, |mut counts, result| {
+                    counts.failure = result.err();
+                    counts
+                })
+            })
+            .collect();
+
+        let results: Vec<StreamResult> = handles
+            .into_iter()
+            .enumerate()
+            .map(|(stream_id, handle)| {
+                handle.join().unwrap_or_else(|e| StreamResult {
+                    stream_id: stream_id as u64,
+                    failure: Some(panic_message(e.as_ref()).into()),
+                    ..StreamResult::default()
+                })
+            })
+            .collect();
+
+        let failed_streams = results.iter().filter(|result| result.failure.is_some());
+        for result in failed_streams.clone() {
+            if let Some(failure) = &result.failure {
+                log::error!("Stream {} failed: {}", result.stream_id, failure);
+            }
+        }
+        log::info!(
+            "Completed {} streams ({} failed)",
+            results.len(),
+            failed_streams.count()
         );
+        results
+    }
 
-        let mut handles = Vec::new();
-        for worker_id in 0..num_workers {
-            let engine = self.engine.clone();
-            let rx = worker_receivers.remove(0);
-
-            let handle = thread::spawn(
-                move || -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
-                    let mut processed_count = 0;
-
-                    while let Ok(transaction) = rx.recv() {
-
-                        // Process the transaction
-                        let result = {
-                            let mut engine_guard = engine.lock().map_err(|e| {
-                                format!(
-                                    "Worker {}: Failed to acquire engine lock: {}",
-                                    worker_id, e
-                                )
-                            })?;
-                            engine_guard.process_transaction(&transaction)
-                        };
-
-                        match result {
-                            Ok(()) => {
-                                processed_count += 1;
-                                log::debug!(
-                                    "Worker {}: Successfully processed transaction: {:?}",
-                                    worker_id,
-                                    transaction
-                                );
-                            }
-                            Err(e) => {
-                                log::error!(
-                                    "Worker {}: Failed to process transaction {:?}: {}",
-                                    worker_id,
-                                    transaction,
-                                    e
-                                );
+    /// Per-worker activity of the most recent `process_transactions_from_reader` call,
+    /// ordered by worker ID. Can be polled from another thread while the call is running.
+    pub fn get_worker_stats(&self) -> Vec<WorkerStats> {
+        let counters = self
+            .worker_counters
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        counters
+            .iter()
+            .enumerate()
+            .map(|(worker_id, counters)| WorkerStats {
+                worker_id,
+                processed: counters.processed.load(Ordering::Relaxed),
+                errors: counters.errors.load(Ordering::Relaxed),
+                queue_depth: counters.queued.load(Ordering::Relaxed),
+                accounts: counters.clients.load(Ordering::Relaxed),
+                steals: counters.steals.load(Ordering::Relaxed),
+                stolen: counters.stolen.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// Counters for `worker_id`, created on first use
+    fn counters_for(&self, worker_id: usize) -> Arc<WorkerCounters> {
+        let mut counters = self
+            .worker_counters
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if counters.len() <= worker_id {
+            counters.resize_with(worker_id + 1, Default::default);
+        }
+        counters[worker_id].clone()
+    }
+
+    /// Spawn worker `worker_id` for `process_transactions_from_reader`. The worker applies
+    /// the records of the clients on its run queue `own`, stealing clients from other
+    /// workers whenever that queue is empty, until the scheduler is closed and no work is
+    /// left. Once retired, it only drains its own queue.
+    fn spawn_worker(
+        &self,
+        worker_id: usize,
+        scheduler: Arc<ClientScheduler<QueuedTransaction>>,
+        own: Arc<RunQueue>,
+        pipeline: Arc<PipelineCounters>,
+        order: Arc<ClientOrder>,
+    ) -> WorkerHandle {
+        let counters = self.counters_for(worker_id);
+        let engine = self.engine.clone();
+        let schedule = self.schedule.clone();
+        let error_policy = self.error_policy;
+        #[cfg(any(test, feature = "testing"))]
+        let faults = self.faults;
+
+        thread::spawn(
+            move || -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+                scheduler.register(&own);
+                let mut processed_count = 0;
+
+                loop {
+                    // Checked before looking for work, so nothing queued before closing
+                    // is left behind
+                    let closed = scheduler.is_closed();
+                    let (client, (seq, queued_at, home, transaction)) = match scheduler.next(&own) {
+                        (Work::Apply { client, record }, stolen) => {
+                            if stolen {
+                                counters.steals.fetch_add(1, Ordering::Relaxed);
+                                log::debug!("Worker {}: Took over client {}", worker_id, client);
                             }
+                            (client, record)
                         }
+                        (Work::Idle, _) if closed || own.is_retired() => break,
+                        (Work::Idle, _) => {
+                            scheduler.wait();
+                            continue;
+                        }
+                    };
+
+                    #[cfg(any(test, feature = "testing"))]
+                    match faults.and_then(|faults| faults.fault(seq)) {
+                        Some(Fault::Delay(delay)) => thread::sleep(delay),
+                        Some(Fault::Drop) => {
+                            scheduler.done(client);
+                            continue;
+                        }
+                        Some(Fault::Panic) => {
+                            panic!("Worker {}: injected panic at record {}", worker_id, seq)
+                        }
+                        None => {}
                     }
 
-                    log::info!(
-                        "Worker {} completed, processed {} transactions",
-                        worker_id,
-                        processed_count
-                    );
-                    Ok(processed_count)
-                },
-            );
+                    // Process the transaction
+                    let result = pipeline.apply.busy(|| -> Result<_, PaymentsError> {
+                        let mut engine_guard = engine
+                            .lock()
+                            .map_err(|_| PaymentsError::LockPoisoned("engine"))?;
+                        // Checked under the engine lock, so it sees the order the shared
+                        // state does
+                        order.check(transaction.client, seq as u64 + 1)?;
+                        // Record while holding the engine lock so the schedule
+                        // matches the order the shared state saw
+                        if let Some(schedule) = &schedule {
+                            schedule
+                                .lock()
+                                .map_err(|_| PaymentsError::LockPoisoned("schedule"))?
+                                .push(ScheduleEntry {
+                                    seq,
+                                    worker: worker_id,
+                                });
+                        }
+                        // Latency covers queueing and lock wait as well as the update
+                        Ok(engine_guard.process_transaction_since(&transaction, queued_at))
+                    })?;
+                    pipeline.apply.add_rows(1);
+                    scheduler.done(client);
+                    home.queued.fetch_sub(1, Ordering::Relaxed);
+                    if !Arc::ptr_eq(&home, &counters) {
+                        counters.stolen.fetch_add(1, Ordering::Relaxed);
+                    }
 
-            handles.push(handle);
-        }
+                    match result {
+                        Ok(()) => {
+                            processed_count += 1;
+                            counters.processed.fetch_add(1, Ordering::Relaxed);
+                            log::debug!(
+                                "Worker {}: Successfully processed transaction: {:?}",
+                                worker_id,
+                                transaction
+                            );
+                        }
+                        Err(e) => {
+                            counters.errors.fetch_add(1, Ordering::Relaxed);
+                            log::error!(
+                                "Worker {}: Failed to process transaction {:?}: {}",
+                                worker_id,
+                                transaction,
+                                e
+                            );
+                            // The reader stops dispatching once it sees the worker has
+                            // finished
+                            if error_policy == ErrorPolicy::Abort {
+                                return Err(e.at_line(seq as u64 + 1).into());
+                            }
+                        }
+                    }
+                }
 
-        // Read and send transactions to workers based on client ID
-        let mut rdr = csv::ReaderBuilder::new()
-            .trim(csv::Trim::All)
-            .from_reader(reader);
+                log::info!(
+                    "Worker {} completed, processed {} transactions",
+                    worker_id,
+                    processed_count
+                );
+                Ok(processed_count)
+            },
+        )
+    }
 
-        let mut sent_count = 0;
-        for (idx, line) in rdr.deserialize().enumerate() {
-            let transaction: Transaction = match line {
-                Ok(tx) => tx,
-                Err(e) => {
-                    log::error!("Failed to parse line {}: {}", idx + 1, e);
-                    continue;
-                }
-            };
+    // Process transactions from reader using concurrent worker threads
+    /// This version assigns transactions to workers based on client ID to avoid race conditions
+    /// All transactions for the same client are processed by one worker at a time
+    ///
+    /// The input goes through three stages joined by bounded channels: this thread reads
+    /// the records, a pool of parse threads turns them into transactions, and the workers
+    /// apply them; see `pipeline` and `pipeline_stats`.
+    ///
+    /// Each client has a home worker picked by the router. A worker that runs out of work
+    /// steals a waiting client, with all of its queued records, from another worker's
+    /// run queue; see `get_engine_info().rebalancing` and `get_worker_stats` for how much
+    /// was stolen.
+    ///
+    /// The pool can be resized while this runs through the handle from `worker_pool`.
+    /// Clients move to their new home worker once their queued records have been
+    /// applied, so per-client ordering holds across resizes. Removed workers exit once
+    /// their run queue is empty.
+    ///
+    /// Workers check that each client's rows are applied in input order; a row that is
+    /// not is left unapplied and the call fails with `OutOfOrder`.
+    pub fn process_transactions_from_reader<R: Read>(
+        &mut self,
+        reader: R,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // Check the header before any thread is started
+        let mut rdr = transaction_reader(reader, &self.input_format);
+        let headers = transaction_headers(&mut rdr, &self.input_format)?;
+        let num_parsers = self.effective_parse_threads();
 
-            // Assign transaction to worker based on client ID
-            let worker_id = (transaction.client as usize) % num_workers;
-            let tx_sender = &worker_senders[worker_id];
+        // Statistics describe this call only
+        self.worker_counters
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+        let pipeline = Arc::new(PipelineCounters::new(num_parsers));
+        *self
+            .pipeline_counters
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = pipeline.clone();
 
-            if let Err(e) = tx_sender.send(transaction) {
-                log::error!("Failed to send transaction to worker {}: {}", worker_id, e);
-                break;
+        let this = &*self;
+        let (rows_read, dispatch) = thread::scope(|scope| {
+            let capacity = num_parsers * BATCHES_PER_PARSER;
+            let (records, raw) = mpsc::sync_channel(capacity);
+            let (parsed, transactions) = mpsc::sync_channel(capacity);
+            let raw = Arc::new(Mutex::new(raw));
+            for _ in 0..num_parsers {
+                let (raw, parsed) = (raw.clone(), parsed.clone());
+                let (headers, pipeline) = (&headers, &pipeline);
+                scope.spawn(move || {
+                    parse_stage(raw, parsed, headers, &this.input_format, &pipeline.parse)
+                });
             }
-            sent_count += 1;
-        }
-
-        // Close all channels to signal workers to stop
-        for tx in worker_senders {
-            drop(tx);
-        }
+            // The parse threads hold the only other ends, so each stage notices when the
+            // one after it stops
+            drop((raw, parsed));
+            let dispatcher = scope.spawn(|| this.apply_stage(transactions, &pipeline));
+            let rows_read = read_stage(&mut rdr, records, &this.shutdown, &pipeline.read);
+            let dispatch = dispatcher
+                .join()
+                .unwrap_or_else(|e| std::panic::resume_unwind(e));
+            (rows_read, dispatch)
+        });
+        let Dispatch {
+            handles,
+            counters,
+            sent_count,
+            parse_errors,
+            parse_error,
+            stopped_at,
+        } = dispatch;
 
         log::info!("Sent {} transactions to workers", sent_count);
 
         // Wait for all workers to complete and collect results
         let mut total_processed = 0;
-        for (worker_id, handle) in handles.into_iter().enumerate() {
+        let mut first_error: Option<Box<dyn std::error::Error>> = parse_error.map(Into::into);
+        // Why the run lost records, besides panics found when joining the workers
+        let mut failures = Vec::new();
+        for (worker_id, handle) in handles {
             match handle.join() {
                 Ok(Ok(processed)) => {
                     total_processed += processed;
@@ -230,37 +994,333 @@ impl ConcurrentEngine {
                         processed
                     );
                 }
-                Ok(Err(e)) => log::error!("Worker {} failed: {}", worker_id, e),
-                Err(e) => log::error!("Worker {} panicked: {:?}", worker_id, e),
+                // Besides an aborting rejection, only a poisoned lock ends a worker early
+                Ok(Err(e)) => {
+                    log::error!("Worker {} failed: {}", worker_id, e);
+                    if first_error.is_none() {
+                        first_error = Some(e);
+                    }
+                }
+                Err(e) => {
+                    let message = panic_message(e.as_ref());
+                    log::error!("Worker {} panicked: {}", worker_id, message);
+                    failures.push(format!("worker {} panicked: {}", worker_id, message));
+                }
+            }
+        }
+
+        // Every record sent must have been applied or rejected; anything else was lost
+        // by a worker, whether or not the worker noticed
+        let lost = sent_count.saturating_sub(handled(&counters)) as u64;
+        // Stopping early is expected when a worker stopped on an error: a rejected
+        // transaction under `ErrorPolicy::Abort`, or a row applied out of order
+        let aborted = first_error.is_some();
+        if !failures.is_empty() || (!aborted && (lost > 0 || stopped_at.is_some())) {
+            if lost > 0 && failures.is_empty() {
+                failures.push("records were dropped before reaching the engine".to_string());
             }
+            // The record that could not be dispatched and every one after it
+            let unsent = match stopped_at {
+                Some(line) => {
+                    failures.push(format!("dispatch stopped at line {}", line));
+                    (rows_read + rdr.records().count() + 1 - line) as u64
+                }
+                None => 0,
+            };
+            first_error = Some(
+                PaymentsError::WorkerFailed {
+                    lost,
+                    unsent,
+                    reason: failures.join("; "),
+                }
+                .into(),
+            );
         }
 
         log::info!(
             "All workers completed. Total processed: {}",
             total_processed
         );
-        Ok(())
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(parse_errors.finish()?),
+        }
+    }
+
+    /// Stage 3 of `process_transactions_from_reader`: put the parsed batches back in input
+    /// order and queue each transaction for its client's worker, starting and retiring
+    /// workers as the pool is resized
+    fn apply_stage(
+        &self,
+        batches: mpsc::Receiver<ParsedBatch>,
+        pipeline: &Arc<PipelineCounters>,
+    ) -> Dispatch {
+        let error_policy = self.error_policy;
+        let mut parse_errors = ParseErrorTally::new(self.parse_error_limit);
+        let mut source_ids = SourceTxIds::default();
+        let mut num_workers = self.worker_pool.effective_size();
+        let scheduler = Arc::new(ClientScheduler::new());
+        let order = Arc::new(ClientOrder::new());
+
+        log::debug!(
+            "Starting concurrent transaction processing with {} workers (client-based assignment)",
+            num_workers
+        );
+
+        // Every thread started, by worker ID, and the index in `handles` of the thread
+        // currently serving each worker ID
+        let mut handles = Vec::new();
+        let mut current = Vec::new();
+        let mut counters = Vec::new();
+        for worker_id in 0..num_workers {
+            let own = scheduler.add_worker(worker_id);
+            current.push(handles.len());
+            handles.push((
+                worker_id,
+                self.spawn_worker(
+                    worker_id,
+                    scheduler.clone(),
+                    own,
+                    pipeline.clone(),
+                    order.clone(),
+                ),
+            ));
+            counters.push(self.counters_for(worker_id));
+        }
+
+        // Home worker of each client seen so far
+        let mut owners: HashMap<ClientId, usize> = HashMap::new();
+
+        // Queue transactions for the workers based on client ID, in input order
+        let mut reorder = Reorder::default();
+        let mut sent_count: usize = 0;
+        // Records the workers were last seen to have applied or rejected
+        let mut applied = 0;
+        let mut parse_error = None;
+        // Line at which records stopped being dispatched before the end of the input
+        let mut stopped_at = None;
+        'dispatch: loop {
+            let Some(batch) = reorder.pop() else {
+                match pipeline.apply.input_wait(|| batches.recv()) {
+                    Ok(batch) => {
+                        reorder.push(batch);
+                        continue;
+                    }
+                    Err(_) => break,
+                }
+            };
+            for (offset, line) in batch.rows.into_iter().enumerate() {
+                let idx = batch.first_row + offset;
+                if self.shutdown.is_requested() {
+                    log::warn!(
+                        "Shutdown requested; stopping before line {}, draining workers",
+                        idx + 1
+                    );
+                    break 'dispatch;
+                }
+                parse_errors.read();
+
+                let requested = self.worker_pool.effective_size();
+                if requested != num_workers {
+                    log::info!(
+                        "Resizing worker pool from {} to {} workers",
+                        num_workers,
+                        requested
+                    );
+                    for worker_id in num_workers..requested {
+                        let own = scheduler.add_worker(worker_id);
+                        if worker_id == current.len() {
+                            current.push(0);
+                            counters.push(self.counters_for(worker_id));
+                        }
+                        current[worker_id] = handles.len();
+                        handles.push((
+                            worker_id,
+                            self.spawn_worker(
+                                worker_id,
+                                scheduler.clone(),
+                                own,
+                                pipeline.clone(),
+                                order.clone(),
+                            ),
+                        ));
+                    }
+                    for (client, owner) in owners.iter_mut() {
+                        let desired = self.router.route(*client, requested);
+                        if *owner != desired {
+                            counters[*owner].clients.fetch_sub(1, Ordering::Relaxed);
+                            counters[desired].clients.fetch_add(1, Ordering::Relaxed);
+                            *owner = desired;
+                        }
+                    }
+                    // Clients already waiting on a removed worker are applied there first
+                    for worker_id in requested..num_workers {
+                        scheduler.retire(worker_id);
+                        log::info!("Worker {} retired", worker_id);
+                    }
+                    num_workers = requested;
+                }
+
+                let line = line.and_then(|tx| source_ids.assign(tx, &self.input_format.tx_ids));
+                let transaction: Transaction = match line {
+                    Ok(tx) => tx,
+                    Err(e) => {
+                        log::error!("Failed to parse line {}: {}", idx + 1, e);
+                        if error_policy == ErrorPolicy::Abort {
+                            parse_error = Some(e.at_line(idx as u64 + 1));
+                            break 'dispatch;
+                        }
+                        if let Err(e) = parse_errors.failed() {
+                            parse_error = Some(e);
+                            break 'dispatch;
+                        }
+                        continue;
+                    }
+                };
+
+                // Queue the transaction for its client's home worker
+                let client = transaction.client;
+                let worker_id = *owners.entry(client).or_insert_with(|| {
+                    let home = self.router.route(client, num_workers);
+                    counters[home].clients.fetch_add(1, Ordering::Relaxed);
+                    home
+                });
+                // Workers only finish early by aborting on a rejected record or panicking
+                let running = |worker_id: usize| !handles[current[worker_id]].1.is_finished();
+                if !running(worker_id) {
+                    log::error!("Worker {} is no longer running", worker_id);
+                    stopped_at = Some(idx + 1);
+                    break 'dispatch;
+                }
+
+                // Let the workers catch up before queueing more, unless one of them has
+                // stopped and may never take its records
+                if sent_count - applied >= MAX_IN_FLIGHT {
+                    applied = pipeline.apply.output_wait(|| {
+                        loop {
+                            let applied = handled(&counters);
+                            if sent_count - applied < MAX_IN_FLIGHT
+                                || !(0..num_workers).all(running)
+                            {
+                                break applied;
+                            }
+                            thread::sleep(IN_FLIGHT_WAIT);
+                        }
+                    });
+                }
+
+                counters[worker_id].queued.fetch_add(1, Ordering::Relaxed);
+                scheduler.push(
+                    client,
+                    (idx, start_timer(), counters[worker_id].clone(), transaction),
+                    worker_id,
+                );
+                sent_count += 1;
+            }
+        }
+
+        // Let the workers finish what is queued and stop
+        scheduler.close();
+
+        Dispatch {
+            handles,
+            counters,
+            sent_count,
+            parse_errors,
+            parse_error,
+            stopped_at,
+        }
+    }
+
+    /// Set the number of parse threads of `process_transactions_from_reader`; 0 picks
+    /// half the available parallelism
+    pub fn set_parse_threads(&mut self, parse_threads: usize) {
+        self.parse_threads = parse_threads;
+    }
+
+    fn effective_parse_threads(&self) -> usize {
+        match self.parse_threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get().div_ceil(2)),
+            n => n,
+        }
+    }
+
+    /// Per-stage activity of the most recent `process_transactions_from_reader` call.
+    /// Can be polled from another thread while the call is running.
+    pub fn pipeline_stats(&self) -> PipelineStats {
+        self.pipeline_counters
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .snapshot()
     }
 
     pub fn write_accounts_csv<W: std::io::Write>(
         &self,
         writer: W,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let engine = self.engine.lock().map_err(|e| {
-            std::io::Error::other(format!("Failed to acquire engine lock for export: {}", e))
-        })?;
-        engine.write_accounts_csv(writer)
+        self.write_accounts_csv_filtered(writer, |_| true)
+    }
+
+    /// Write only the accounts for which `filter` returns true; see `write_accounts_csv`
+    pub fn write_accounts_csv_filtered<W: std::io::Write>(
+        &self,
+        writer: W,
+        filter: impl Fn(&Account) -> bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let engine = self
+            .engine
+            .lock()
+            .map_err(|_| PaymentsError::LockPoisoned("engine"))?;
+        engine.write_accounts_csv_filtered(writer, filter)
+    }
+
+    /// Write the accounts changed since the previous call; see
+    /// `BoundedEngine::write_changed_accounts_csv`
+    pub fn write_changed_accounts_csv<W: std::io::Write>(
+        &self,
+        writer: W,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.write_changed_accounts_csv_filtered(writer, |_| true)
+    }
+
+    /// Like `write_changed_accounts_csv`, but only writes the changed accounts for which
+    /// `filter` returns true
+    pub fn write_changed_accounts_csv_filtered<W: std::io::Write>(
+        &self,
+        writer: W,
+        filter: impl Fn(&Account) -> bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut engine = self
+            .engine
+            .lock()
+            .map_err(|_| PaymentsError::LockPoisoned("engine"))?;
+        engine.write_changed_accounts_csv_filtered(writer, filter)
+    }
+
+    /// Work the workers of the most recent `process_transactions_from_reader` call took
+    /// over from each other
+    pub fn rebalance_stats(&self) -> RebalanceStats {
+        self.get_worker_stats()
+            .iter()
+            .fold(RebalanceStats::default(), |total, worker| RebalanceStats {
+                steals: total.steals + worker.steals,
+                stolen_records: total.stolen_records + worker.stolen,
+            })
     }
 
     pub fn get_engine_info(&self) -> EngineInfo {
+        let rebalancing = Some(self.rebalance_stats());
         if let Ok(engine) = self.engine.lock() {
+            let info = engine.get_engine_info();
             EngineInfo {
                 engine_type: "Concurrent".to_string(),
                 memory_bounded: true,
                 concurrent: true,
-                account_count: engine.accounts.len(),
+                account_count: info.account_count,
                 transaction_count: None,
                 memory_limits: Some(self.memory_limits.clone()),
+                stats: info.stats,
+                rebalancing,
             }
         } else {
             EngineInfo {
@@ -270,6 +1330,8 @@ impl ConcurrentEngine {
                 account_count: 0,
                 transaction_count: None,
                 memory_limits: Some(self.memory_limits.clone()),
+                stats: EngineStats::default(),
+                rebalancing,
             }
         }
     }
@@ -0,0 +1,165 @@
+//! The dispute lifecycle of a stored deposit or withdrawal, shared by every engine.
+//!
+//! A stored transaction is `Undisputed` until a dispute row holds its amount. A resolve
+//! releases the hold and makes it disputable again; a chargeback removes the funds,
+//! locks the account and ends the lifecycle, after which the engine drops it. Engines
+//! look up the stored transaction, ask `DisputeAction::transition` whether the row is
+//! allowed, and apply the resulting `Transition` to the account.
+
+use crate::account::{Account, ClientId};
+use crate::engine::EnginePolicy;
+use crate::errors::PaymentsError;
+use crate::transaction::{Amount, StoredTransaction, Transaction, TxId};
+
+/// Where a stored transaction is in its dispute lifecycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisputeState {
+    Undisputed,
+    Disputed,
+    /// Final; the stored transaction is dropped
+    ChargedBack,
+}
+
+impl DisputeState {
+    pub fn of(stored: &StoredTransaction) -> Self {
+        if stored.disputed {
+            Self::Disputed
+        } else {
+            Self::Undisputed
+        }
+    }
+
+    /// The state `action` leads to, or why it is not allowed from this one
+    pub fn next(self, action: DisputeAction, tx: TxId) -> Result<Self, PaymentsError> {
+        match (self, action) {
+            (Self::Undisputed, DisputeAction::Open) => Ok(Self::Disputed),
+            (Self::Disputed, DisputeAction::Open) => {
+                Err(PaymentsError::TransactionAlreadyDisputed(tx))
+            }
+            (Self::Disputed, DisputeAction::Resolve | DisputeAction::ForceResolve) => {
+                Ok(Self::Undisputed)
+            }
+            (Self::Disputed, DisputeAction::Chargeback) => Ok(Self::ChargedBack),
+            (
+                Self::Undisputed,
+                DisputeAction::Resolve | DisputeAction::ForceResolve | DisputeAction::Chargeback,
+            ) => Err(PaymentsError::TransactionNotDisputed(tx)),
+            (Self::ChargedBack, _) => Err(PaymentsError::TransactionNotFound(tx)),
+        }
+    }
+}
+
+/// What a dispute, resolve or chargeback row asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisputeAction {
+    Open,
+    Resolve,
+    /// A resolve an operator forces through a lock state that rejects resolves
+    ForceResolve,
+    Chargeback,
+}
+
+impl DisputeAction {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Open => "Dispute",
+            Self::Resolve | Self::ForceResolve => "Resolve",
+            Self::Chargeback => "Chargeback",
+        }
+    }
+
+    /// Check `transaction` against `stored`, the transaction it refers to. `not_found`
+    /// gives the error when nothing is stored under its ID, and `open_disputes` is the
+    /// number of the client's transactions already under dispute.
+    pub fn transition(
+        self,
+        transaction: &Transaction,
+        stored: Option<&StoredTransaction>,
+        not_found: impl FnOnce() -> PaymentsError,
+        open_disputes: usize,
+        policy: &EnginePolicy,
+    ) -> Result<Transition, PaymentsError> {
+        if transaction.amount.is_some() {
+            return Err(PaymentsError::InvalidTransaction(format!(
+                "{} transaction should not have an amount",
+                self.name()
+            )));
+        }
+        let stored = stored.ok_or_else(not_found)?;
+        if stored.client != transaction.client {
+            return Err(PaymentsError::ClientIdMismatch {
+                tx: transaction.tx,
+                expected: stored.client,
+                found: transaction.client,
+            });
+        }
+        let to = DisputeState::of(stored).next(self, transaction.tx)?;
+        if self == Self::Open
+            && policy
+                .max_open_disputes
+                .is_some_and(|max| open_disputes >= max)
+        {
+            return Err(PaymentsError::TooManyOpenDisputes(stored.client));
+        }
+        Ok(Transition {
+            action: self,
+            tx: transaction.tx,
+            client: stored.client,
+            amount: stored.amount,
+            to,
+        })
+    }
+}
+
+/// An allowed step of the lifecycle, not yet applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+    pub action: DisputeAction,
+    /// The stored transaction the row refers to
+    pub tx: TxId,
+    pub client: ClientId,
+    /// The stored transaction's amount, which is held, released or charged back
+    pub amount: Amount,
+    pub to: DisputeState,
+}
+
+impl Transition {
+    /// Move the funds on the client's account. On error the account is unchanged and the
+    /// stored transaction must stay in its current state.
+    pub fn apply(&self, account: &mut Account, policy: &EnginePolicy) -> Result<(), PaymentsError> {
+        match self.action {
+            DisputeAction::Open => account.hold(self.amount, policy),
+            DisputeAction::Resolve => account.release(self.amount),
+            DisputeAction::ForceResolve => account.force_release(self.amount),
+            DisputeAction::Chargeback => account.chargeback(self.amount, self.tx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lifecycle() {
+        let mut state = DisputeState::Undisputed;
+        for (action, expected) in [
+            (DisputeAction::Open, DisputeState::Disputed),
+            (DisputeAction::Resolve, DisputeState::Undisputed),
+            (DisputeAction::Open, DisputeState::Disputed),
+            (DisputeAction::Chargeback, DisputeState::ChargedBack),
+        ] {
+            state = state.next(action, TxId::new(1)).unwrap();
+            assert_eq!(state, expected);
+        }
+
+        assert!(matches!(
+            DisputeState::Undisputed.next(DisputeAction::Chargeback, TxId::new(1)),
+            Err(PaymentsError::TransactionNotDisputed(tx)) if tx == TxId::new(1)
+        ));
+        assert!(matches!(
+            DisputeState::Disputed.next(DisputeAction::Open, TxId::new(1)),
+            Err(PaymentsError::TransactionAlreadyDisputed(_))
+        ));
+    }
+}
@@ -0,0 +1,214 @@
+//! Expiry of disputable transactions after a fixed window.
+//!
+//! A deposit or withdrawal can be disputed until its window ends: once a row's
+//! `timestamp` is more than `max_age_secs` past the transaction's, or once more than
+//! `max_transactions` rows were processed after it. The engine then drops the stored
+//! transaction, so memory is freed in input order rather than by cache pressure, and
+//! rejects later disputes with `PaymentsError::DisputeWindowExpired`. A transaction
+//! still under dispute when its window ends is kept and gets a new window.
+
+use serde::Deserialize;
+use std::collections::{HashSet, VecDeque};
+
+use crate::errors::PaymentsError;
+use crate::transaction::TxId;
+
+/// How long stored transactions stay disputable; unlimited when both are `None`.
+///
+/// ```toml
+/// [dispute_window]
+/// max_age_secs = 7776000
+/// max_transactions = 1000000
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisputeWindow {
+    /// Seconds after the transaction's timestamp, or the latest timestamp seen before it
+    /// if it has none. Transactions stored before any timestamped row never age out.
+    pub max_age_secs: Option<u64>,
+    /// Rows processed after the transaction, accepted or not
+    pub max_transactions: Option<u64>,
+}
+
+impl DisputeWindow {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_age_secs.is_none() && self.max_transactions.is_none()
+    }
+}
+
+/// A stored transaction whose window has not ended yet
+#[derive(Debug, Clone, Copy)]
+struct Tracked {
+    tx: TxId,
+    /// Rows processed when it was stored
+    seq: u64,
+    timestamp: Option<u64>,
+}
+
+/// A `DisputeWindow` with the transactions it tracks and the IDs it expired.
+#[derive(Debug, Clone, Default)]
+pub struct DisputeExpiry {
+    window: DisputeWindow,
+    /// Rows processed so far
+    processed: u64,
+    /// Latest timestamp seen
+    now: Option<u64>,
+    /// In the order they were stored, which is also the order their windows end
+    tracked: VecDeque<Tracked>,
+    expired: HashSet<TxId>,
+    /// `expired` in insertion order, to forget the oldest beyond `max_expired`
+    expired_order: VecDeque<TxId>,
+    /// Expired IDs remembered; unlimited when `None`
+    max_expired: Option<usize>,
+}
+
+impl DisputeExpiry {
+    /// Check the window's limits; zero is rejected since nothing could be disputed
+    pub fn new(window: DisputeWindow) -> Result<Self, PaymentsError> {
+        if window.max_age_secs == Some(0) || window.max_transactions == Some(0) {
+            return Err(PaymentsError::ConfigError(
+                "dispute window limits must be positive".to_string(),
+            ));
+        }
+        Ok(Self {
+            window,
+            ..Self::default()
+        })
+    }
+
+    pub fn window(&self) -> DisputeWindow {
+        self.window
+    }
+
+    /// Remember at most `max` expired IDs; disputes of older ones are reported as
+    /// `TransactionNotFound`
+    pub fn set_max_expired(&mut self, max: usize) {
+        self.max_expired = Some(max);
+        self.forget_expired();
+    }
+
+    /// Count one more row, at `timestamp` if it has one, and return the tracked IDs whose
+    /// window ended. The caller drops them and passes each to `expire`, or to `track`
+    /// again if it is still under dispute.
+    pub fn advance(&mut self, timestamp: Option<u64>) -> Vec<TxId> {
+        if self.window.is_unlimited() {
+            return Vec::new();
+        }
+        self.processed += 1;
+        if let Some(timestamp) = timestamp {
+            self.now = Some(self.now.map_or(timestamp, |now| now.max(timestamp)));
+        }
+        let mut ended = Vec::new();
+        while let Some(tracked) = self.tracked.front()
+            && self.has_ended(tracked)
+        {
+            ended.push(tracked.tx);
+            self.tracked.pop_front();
+        }
+        ended
+    }
+
+    fn has_ended(&self, tracked: &Tracked) -> bool {
+        let too_old = matches!(
+            (self.window.max_age_secs, tracked.timestamp, self.now),
+            (Some(max), Some(at), Some(now)) if now.saturating_sub(at) > max
+        );
+        let too_far = self
+            .window
+            .max_transactions
+            .is_some_and(|max| self.processed - tracked.seq > max);
+        too_old || too_far
+    }
+
+    /// Start the window of a transaction stored by the current row
+    pub fn track(&mut self, tx: TxId) {
+        if self.window.is_unlimited() {
+            return;
+        }
+        self.tracked.push_back(Tracked {
+            tx,
+            seq: self.processed,
+            timestamp: self.now,
+        });
+    }
+
+    /// Record that `tx` was dropped at the end of its window
+    pub fn expire(&mut self, tx: TxId) {
+        if self.expired.insert(tx) {
+            self.expired_order.push_back(tx);
+            self.forget_expired();
+        }
+    }
+
+    fn forget_expired(&mut self) {
+        let Some(max) = self.max_expired else {
+            return;
+        };
+        while self.expired_order.len() > max {
+            if let Some(tx) = self.expired_order.pop_front() {
+                self.expired.remove(&tx);
+            }
+        }
+    }
+
+    pub fn is_expired(&self, tx: TxId) -> bool {
+        self.expired.contains(&tx)
+    }
+
+    /// The error for a dispute of `tx`, which is not stored
+    pub fn not_found(&self, tx: TxId) -> PaymentsError {
+        if self.is_expired(tx) {
+            PaymentsError::DisputeWindowExpired(tx)
+        } else {
+            PaymentsError::TransactionNotFound(tx)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_windows_end() {
+        let (tx1, tx2) = (TxId::new(1), TxId::new(2));
+        let mut expiry = DisputeExpiry::new(DisputeWindow {
+            max_age_secs: Some(100),
+            max_transactions: Some(3),
+        })
+        .unwrap();
+        assert!(expiry.advance(Some(1000)).is_empty());
+        expiry.track(tx1);
+        assert!(expiry.advance(Some(1050)).is_empty());
+        expiry.track(tx2);
+        // 101 seconds after tx 1
+        assert_eq!(expiry.advance(Some(1101)), vec![tx1]);
+        assert!(expiry.advance(None).is_empty());
+        assert!(expiry.advance(None).is_empty());
+        // The fourth row after tx 2
+        assert_eq!(expiry.advance(None), vec![tx2]);
+
+        expiry.set_max_expired(1);
+        expiry.expire(tx1);
+        expiry.expire(tx2);
+        assert!(matches!(
+            expiry.not_found(tx1),
+            PaymentsError::TransactionNotFound(tx) if tx == tx1
+        ));
+        assert!(matches!(
+            expiry.not_found(tx2),
+            PaymentsError::DisputeWindowExpired(tx) if tx == tx2
+        ));
+    }
+
+    #[test]
+    fn test_zero_window_rejected() {
+        assert!(
+            DisputeExpiry::new(DisputeWindow {
+                max_transactions: Some(0),
+                ..DisputeWindow::default()
+            })
+            .is_err()
+        );
+    }
+}
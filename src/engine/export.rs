@@ -0,0 +1,120 @@
+//! Account export for large account sets
+//!
+//! `PaymentsEngine::write_accounts_csv` formats every account on one thread, holding the
+//! concurrent engine's lock until the last row is written. The writers here read accounts a
+//! page at a time through `PaymentsEngine::accounts_iter`, so the lock is only held while a
+//! page is copied, and `write_accounts_csv_sharded` formats and writes each shard on its
+//! own thread.
+
+use std::io::Write;
+use std::sync::mpsc;
+use std::thread;
+
+use super::ACCOUNTS_PAGE_SIZE;
+use crate::account::{Account, ClientId, DecimalFormat};
+use crate::errors::PaymentsError;
+
+/// Pages of accounts queued for each shard's writer before the reader waits for it
+pub const SHARD_QUEUE_PAGES: usize = 4;
+
+/// Shard `client`'s account is written to by `write_accounts_csv_sharded`
+pub fn shard_of(client: ClientId, shards: usize) -> usize {
+    usize::from(u16::from(client)) % shards
+}
+
+/// Write the accounts for which `filter` returns true as CSV, formatted with `format`, as
+/// they arrive from `accounts`. Returns the accounts written.
+pub fn write_accounts_csv_streaming<W: Write>(
+    accounts: impl IntoIterator<Item = Result<Account, PaymentsError>>,
+    writer: W,
+    format: DecimalFormat,
+    filter: impl Fn(&Account) -> bool,
+) -> Result<usize, PaymentsError> {
+    let mut wtr = csv::WriterBuilder::new()
+        .has_headers(true)
+        .from_writer(writer);
+    let mut written = 0;
+    for account in accounts {
+        let account = account?;
+        if filter(&account) {
+            wtr.serialize(format.account(&account))?;
+            written += 1;
+        }
+    }
+    wtr.flush()?;
+    Ok(written)
+}
+
+/// Write the accounts for which `filter` returns true as CSV, split over `writers` by
+/// `shard_of`, one thread per writer. Each shard is in client ID order if `accounts` is.
+/// Returns the accounts written to each shard.
+pub fn write_accounts_csv_sharded<W: Write + Send>(
+    accounts: impl IntoIterator<Item = Result<Account, PaymentsError>>,
+    writers: Vec<W>,
+    format: DecimalFormat,
+    filter: impl Fn(&Account) -> bool,
+) -> Result<Vec<usize>, PaymentsError> {
+    if writers.is_empty() {
+        return Err(PaymentsError::ConfigError(
+            "at least one output shard is required".to_string(),
+        ));
+    }
+    let shards = writers.len();
+    thread::scope(|scope| {
+        let mut senders = Vec::with_capacity(shards);
+        let mut handles = Vec::with_capacity(shards);
+        for writer in writers {
+            let (sender, pages) = mpsc::sync_channel::<Vec<Account>>(SHARD_QUEUE_PAGES);
+            senders.push(sender);
+            handles.push(scope.spawn(move || {
+                let accounts = pages.into_iter().flatten().map(Ok);
+                write_accounts_csv_streaming(accounts, writer, format, |_| true)
+            }));
+        }
+
+        let mut pages: Vec<Vec<Account>> = vec![Vec::new(); shards];
+        let mut read_error = None;
+        for account in accounts {
+            let account = match account {
+                Ok(account) => account,
+                Err(e) => {
+                    read_error = Some(e);
+                    break;
+                }
+            };
+            if !filter(&account) {
+                continue;
+            }
+            let shard = shard_of(account.client, shards);
+            pages[shard].push(account);
+            if pages[shard].len() == ACCOUNTS_PAGE_SIZE {
+                let page = std::mem::take(&mut pages[shard]);
+                // A closed queue means the writer failed; its error is reported below
+                if senders[shard].send(page).is_err() {
+                    break;
+                }
+            }
+        }
+        if read_error.is_none() {
+            for (sender, page) in senders.iter().zip(pages) {
+                if !page.is_empty() {
+                    let _ = sender.send(page);
+                }
+            }
+        }
+        drop(senders);
+
+        let written = handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect::<Result<Vec<usize>, PaymentsError>>()?;
+        match read_error {
+            Some(e) => Err(e),
+            None => Ok(written),
+        }
+    })
+}
@@ -0,0 +1,117 @@
+//! Periodic fee and interest assessment.
+//!
+//! A `FeeSchedule` turns each open account into maintenance-fee withdrawals and interest
+//! deposits. `PaymentsEngine::apply_fee_schedule` processes them like input rows, so they
+//! are stored, disputable and seen by observers.
+
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::Deserialize;
+
+use crate::account::Account;
+use crate::transaction::{Amount, TransactionType, TxId};
+
+/// First ID of the range reserved for generated transactions by default
+pub const DEFAULT_FIRST_FEE_TX_ID: TxId = TxId::new(4_000_000_000);
+
+/// Decimal places interest is rounded down to
+const INTEREST_DECIMAL_PLACES: u32 = 4;
+
+/// Rules applied to every open account by `PaymentsEngine::apply_fee_schedule`.
+///
+/// ```toml
+/// [fees]
+/// first_tx_id = 4000000000
+///
+/// [[fees.rules]]
+/// kind = "maintenance_fee"
+/// amount = "2.5"
+/// waive_above = "1000"
+///
+/// [[fees.rules]]
+/// kind = "interest"
+/// rate = "0.001"
+/// min_balance = "100"
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeeSchedule {
+    /// Generated transactions take the lowest unused IDs from here upwards; input rows
+    /// should not use this range
+    pub first_tx_id: TxId,
+    /// Applied in order; each sees the account as it was before the assessment
+    pub rules: Vec<FeeRule>,
+}
+
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        Self {
+            first_tx_id: DEFAULT_FIRST_FEE_TX_ID,
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// One fee or interest rule
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum FeeRule {
+    /// Withdraw a flat fee from accounts whose available balance covers it
+    MaintenanceFee {
+        amount: Amount,
+        /// Waive the fee for accounts with at least this much available
+        #[serde(default)]
+        waive_above: Option<Amount>,
+    },
+    /// Deposit `rate` times the available balance, rounded down to four decimal places
+    Interest {
+        rate: Decimal,
+        /// Only accounts with at least this much available earn interest
+        #[serde(default)]
+        min_balance: Amount,
+    },
+}
+
+impl FeeRule {
+    /// The transaction this rule generates for `account`, if any
+    pub fn assess(&self, account: &Account) -> Option<(TransactionType, Amount)> {
+        match self {
+            Self::MaintenanceFee {
+                amount,
+                waive_above,
+            } => {
+                let waived = waive_above.is_some_and(|limit| account.available >= limit);
+                (!waived && *amount > Amount::ZERO && account.available >= *amount)
+                    .then_some((TransactionType::Withdrawal, *amount))
+            }
+            Self::Interest { rate, min_balance } => {
+                if account.available < *min_balance {
+                    return None;
+                }
+                let interest = Amount::from(
+                    (account.available.value() * rate)
+                        .round_dp_with_strategy(INTEREST_DECIMAL_PLACES, RoundingStrategy::ToZero)
+                        .normalize(),
+                );
+                (interest > Amount::ZERO).then_some((TransactionType::Deposit, interest))
+            }
+        }
+    }
+}
+
+impl FeeSchedule {
+    /// IDs available to generated transactions
+    pub fn tx_ids(&self) -> impl Iterator<Item = TxId> {
+        (self.first_tx_id.get()..=u32::MAX).map(TxId::new)
+    }
+
+    /// Transactions the rules generate for `account`; none for locked or closed accounts
+    pub fn assess(&self, account: &Account) -> Vec<(TransactionType, Amount)> {
+        if account.is_locked() || account.closed {
+            return Vec::new();
+        }
+        self.rules
+            .iter()
+            .filter_map(|rule| rule.assess(account))
+            .collect()
+    }
+}
@@ -0,0 +1,207 @@
+//! Idempotency keys that let an upstream retry a transaction safely.
+//!
+//! A transaction may carry an `idempotency_key`. Once a transaction is accepted under a
+//! key, a resubmission of the same client, key and payload (type, amount, and for
+//! dispute, resolve and chargeback rows the disputed `tx`) is reported as accepted
+//! without being applied again, whatever its own `tx`. Reusing the key for a different
+//! payload is rejected with `PaymentsError::IdempotencyConflict`. Rejected transactions
+//! do not claim their key, so a retry after e.g. `InsufficientFunds` is applied normally.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+use crate::account::ClientId;
+use crate::errors::PaymentsError;
+use crate::transaction::{Amount, Transaction, TransactionType, TxId};
+
+/// What a transaction accepted under a key was, compared with later submissions
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Payload {
+    tx_type: TransactionType,
+    amount: Option<Amount>,
+    disputed_tx: Option<TxId>,
+}
+
+impl Payload {
+    fn of(transaction: &Transaction) -> Self {
+        let disputed_tx = matches!(
+            transaction.tx_type,
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback
+        )
+        .then_some(transaction.tx);
+        Self {
+            tx_type: transaction.tx_type.clone(),
+            amount: transaction.amount,
+            disputed_tx,
+        }
+    }
+}
+
+/// A key and the transaction accepted under it, as kept in `EngineState`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct IdempotencyRecord {
+    pub client: ClientId,
+    pub key: String,
+    #[serde(rename = "type")]
+    pub tx_type: TransactionType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount: Option<Amount>,
+    /// The disputed transaction, for dispute, resolve and chargeback rows.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disputed_tx: Option<TxId>,
+}
+
+/// Keys of accepted transactions, by client, up to an optional limit.
+#[derive(Debug, Clone, Default)]
+pub struct IdempotencyKeys {
+    accepted: HashMap<(ClientId, String), Payload>,
+    /// Keys of `accepted` in insertion order, to forget the oldest beyond `capacity`
+    order: VecDeque<(ClientId, String)>,
+    capacity: Option<usize>,
+}
+
+impl IdempotencyKeys {
+    /// Keys kept without limit.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// At most `capacity` keys, forgetting the oldest first. A resubmission under a
+    /// forgotten key is applied as a new transaction.
+    pub fn bounded(capacity: usize) -> Self {
+        Self {
+            capacity: Some(capacity),
+            ..Self::default()
+        }
+    }
+
+    /// Whether `transaction` repeats the transaction accepted under its key.
+    pub fn is_resubmission(&self, transaction: &Transaction) -> bool {
+        self.payload(transaction)
+            .is_some_and(|payload| *payload == Payload::of(transaction))
+    }
+
+    /// Fails with `IdempotencyConflict` if `transaction`'s key was already used for a
+    /// different transaction.
+    pub fn check(&self, transaction: &Transaction) -> Result<(), PaymentsError> {
+        match (self.payload(transaction), &transaction.idempotency_key) {
+            (Some(payload), Some(key)) if *payload != Payload::of(transaction) => {
+                Err(PaymentsError::IdempotencyConflict {
+                    client: transaction.client,
+                    key: key.clone(),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Remember the key of the accepted `transaction`, if it has one.
+    pub fn record(&mut self, transaction: &Transaction) {
+        if let Some(key) = &transaction.idempotency_key {
+            self.insert((transaction.client, key.clone()), Payload::of(transaction));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.accepted.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accepted.is_empty()
+    }
+
+    /// Every key, oldest first.
+    pub fn export(&self) -> Vec<IdempotencyRecord> {
+        self.order
+            .iter()
+            .filter_map(|id| {
+                let payload = self.accepted.get(id)?;
+                Some(IdempotencyRecord {
+                    client: id.0,
+                    key: id.1.clone(),
+                    tx_type: payload.tx_type.clone(),
+                    amount: payload.amount,
+                    disputed_tx: payload.disputed_tx,
+                })
+            })
+            .collect()
+    }
+
+    /// Replace the keys with `records`, oldest first.
+    pub fn import(&mut self, records: Vec<IdempotencyRecord>) {
+        self.accepted.clear();
+        self.order.clear();
+        for record in records {
+            let payload = Payload {
+                tx_type: record.tx_type,
+                amount: record.amount,
+                disputed_tx: record.disputed_tx,
+            };
+            self.insert((record.client, record.key), payload);
+        }
+    }
+
+    fn payload(&self, transaction: &Transaction) -> Option<&Payload> {
+        let key = transaction.idempotency_key.as_ref()?;
+        self.accepted.get(&(transaction.client, key.clone()))
+    }
+
+    fn insert(&mut self, id: (ClientId, String), payload: Payload) {
+        if self.accepted.insert(id.clone(), payload).is_none() {
+            self.order.push_back(id);
+        }
+        while self
+            .capacity
+            .is_some_and(|capacity| self.order.len() > capacity)
+        {
+            if let Some(oldest) = self.order.pop_front() {
+                self.accepted.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyed(mut transaction: Transaction, key: &str) -> Transaction {
+        transaction.idempotency_key = Some(key.to_string());
+        transaction
+    }
+
+    #[test]
+    fn test_idempotency_keys() {
+        let mut keys = IdempotencyKeys::bounded(2);
+        let deposit = keyed(Transaction::deposit(1, 1, Amount::new(5, 0)), "a");
+        assert!(!keys.is_resubmission(&deposit));
+        keys.record(&deposit);
+
+        // The same payload under a new ID is a resubmission
+        let retry = keyed(Transaction::deposit(1, 9, Amount::new(5, 0)), "a");
+        assert!(keys.is_resubmission(&retry));
+        assert!(keys.check(&retry).is_ok());
+
+        let changed = keyed(Transaction::deposit(1, 2, Amount::new(6, 0)), "a");
+        assert!(!keys.is_resubmission(&changed));
+        assert_eq!(keys.check(&changed).unwrap_err().code(), 108);
+
+        // Keys are scoped to the client
+        let other_client = keyed(Transaction::deposit(2, 3, Amount::new(6, 0)), "a");
+        assert!(keys.check(&other_client).is_ok());
+        assert!(!keys.is_resubmission(&Transaction::deposit(1, 1, Amount::new(5, 0))));
+
+        keys.record(&keyed(Transaction::dispute(1, 1), "b"));
+        assert!(keys.is_resubmission(&keyed(Transaction::dispute(1, 1), "b")));
+        assert!(keys.check(&keyed(Transaction::dispute(1, 4), "b")).is_err());
+
+        let mut restored = IdempotencyKeys::new();
+        restored.import(keys.export());
+        assert_eq!(restored.export(), keys.export());
+
+        // The oldest key is forgotten beyond the capacity
+        keys.record(&other_client);
+        assert_eq!(keys.len(), 2);
+        assert!(!keys.is_resubmission(&retry));
+    }
+}
@@ -0,0 +1,151 @@
+//! Balance invariants every account must satisfy, checked on demand.
+//!
+//! `PaymentsEngine::verify_invariants` scans the accounts and reports each one that
+//! breaks a rule: `total == available + held`, `held >= 0`, and `total >= 0` unless
+//! `EnginePolicy::allow_negative_available` permits overdrafts. It is meant as a gate
+//! before publishing output, e.g. after a concurrent run. `repair_invariants` also
+//! rewrites each mismatched total as `available + held`; the other violations have no
+//! safe automatic fix and are only reported.
+
+use std::fmt;
+
+use crate::account::{Account, ClientId};
+use crate::transaction::Amount;
+
+/// One rule an account breaks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvariantViolation {
+    /// `total` is not `available + held`
+    TotalMismatch {
+        client: ClientId,
+        available: Amount,
+        held: Amount,
+        total: Amount,
+    },
+    /// Held funds are negative
+    NegativeHeld { client: ClientId, held: Amount },
+    /// The total is negative although overdrafts are not allowed
+    NegativeTotal { client: ClientId, total: Amount },
+}
+
+impl InvariantViolation {
+    pub fn client(&self) -> ClientId {
+        match self {
+            Self::TotalMismatch { client, .. }
+            | Self::NegativeHeld { client, .. }
+            | Self::NegativeTotal { client, .. } => *client,
+        }
+    }
+
+    /// Every rule `account` breaks, in the order listed on `InvariantViolation`
+    pub fn check(account: &Account, allow_negative: bool) -> Vec<Self> {
+        let client = account.client;
+        let mut violations = Vec::new();
+        if account.total != account.available + account.held {
+            violations.push(Self::TotalMismatch {
+                client,
+                available: account.available,
+                held: account.held,
+                total: account.total,
+            });
+        }
+        if account.held < Amount::ZERO {
+            violations.push(Self::NegativeHeld {
+                client,
+                held: account.held,
+            });
+        }
+        if !allow_negative && account.total < Amount::ZERO {
+            violations.push(Self::NegativeTotal {
+                client,
+                total: account.total,
+            });
+        }
+        violations
+    }
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TotalMismatch {
+                client,
+                available,
+                held,
+                total,
+            } => write!(
+                f,
+                "client {}: total {} != available {} + held {}",
+                client, total, available, held
+            ),
+            Self::NegativeHeld { client, held } => {
+                write!(f, "client {}: held {} is negative", client, held)
+            }
+            Self::NegativeTotal { client, total } => {
+                write!(f, "client {}: total {} is negative", client, total)
+            }
+        }
+    }
+}
+
+/// Result of an invariant scan
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InvariantReport {
+    /// Accounts scanned; a bounded engine only scans the accounts it still holds
+    pub accounts_checked: usize,
+    /// Every violation found, by client ID
+    pub violations: Vec<InvariantViolation>,
+    /// Totals rewritten as `available + held` by `repair_invariants`
+    pub repaired: usize,
+}
+
+impl InvariantReport {
+    /// Whether no violation was found
+    pub fn is_clean(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+impl fmt::Display for InvariantReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} accounts checked, {} violations, {} totals repaired",
+            self.accounts_checked,
+            self.violations.len(),
+            self.repaired
+        )?;
+        for violation in &self.violations {
+            write!(f, "\n  {}", violation)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let mut account = Account::new(1);
+        account.available = Amount::new(-5, 0);
+        account.held = Amount::new(-1, 0);
+        account.total = Amount::new(-5, 0);
+
+        let violations = InvariantViolation::check(&account, false);
+        assert_eq!(violations.len(), 3);
+        assert_eq!(
+            violations[0].to_string(),
+            "client 1: total -5 != available -5 + held -1"
+        );
+        assert!(matches!(
+            InvariantViolation::check(&account, true)[..],
+            [
+                InvariantViolation::TotalMismatch { .. },
+                InvariantViolation::NegativeHeld { .. }
+            ]
+        ));
+        assert!(InvariantViolation::check(&Account::new(2), false).is_empty());
+    }
+}
@@ -0,0 +1,676 @@
+//! The transaction pipeline shared by `StandardEngine` and `BoundedEngine`.
+//!
+//! `Ledger` holds an engine's accounts, disputable transactions and processed IDs along
+//! with its policies, validators and observers, and applies transactions to them. It is
+//! generic over where accounts and processed IDs are kept: `StandardEngine` uses a
+//! `HashMap` and `HashSet` that grow without limit, `BoundedEngine` `BoundedCache`s
+//! that evict by their `EvictionPolicy`. The engines add their own construction, state
+//! export and reporting around it.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Debug;
+use std::io::Read;
+
+use super::cache::BoundedCache;
+use super::dispute::{DisputeAction, DisputeState};
+use super::expiry::DisputeExpiry;
+use super::idempotency::IdempotencyKeys;
+use super::metrics::{EngineMetrics, start_timer};
+use super::observer::{AccountDelta, ObserverList};
+use super::recurring::RecurringScheduler;
+use super::store::TransactionStore;
+use super::validation::ValidatorChain;
+use super::{
+    DuplicatePolicy, EnginePolicy, ErrorPolicy, ParseErrorLimit, ParseErrorTally,
+    read_transactions, transaction_reader,
+};
+use crate::account::{Account, ClientId, DecimalFormat, LockState};
+use crate::errors::PaymentsError;
+use crate::shutdown::ShutdownFlag;
+use crate::transaction::{
+    Amount, InputFormat, StoredTransaction, Transaction, TransactionType, TxId,
+};
+
+/// Accounts by client ID.
+pub trait AccountMap: Debug + Send {
+    /// The client's account, without counting as a use.
+    fn peek(&self, client: ClientId) -> Option<&Account>;
+
+    /// The client's account for updating; counts as a use for maps that evict.
+    fn get_mut(&mut self, client: ClientId) -> Option<&mut Account>;
+
+    /// The client's account for updating, created with zero balances if there is none.
+    /// Maps with a capacity may evict another account to make room.
+    fn get_or_create(&mut self, client: ClientId) -> &mut Account;
+
+    /// Every account. Maps that evict list the one evicted last first.
+    fn accounts(&self) -> Box<dyn Iterator<Item = &Account> + '_>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl AccountMap for HashMap<ClientId, Account> {
+    fn peek(&self, client: ClientId) -> Option<&Account> {
+        self.get(&client)
+    }
+
+    fn get_mut(&mut self, client: ClientId) -> Option<&mut Account> {
+        HashMap::get_mut(self, &client)
+    }
+
+    fn get_or_create(&mut self, client: ClientId) -> &mut Account {
+        self.entry(client).or_insert_with(|| Account::new(client))
+    }
+
+    fn accounts(&self) -> Box<dyn Iterator<Item = &Account> + '_> {
+        Box::new(self.values())
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+}
+
+impl AccountMap for BoundedCache<ClientId, Account> {
+    fn peek(&self, client: ClientId) -> Option<&Account> {
+        BoundedCache::peek(self, &client)
+    }
+
+    fn get_mut(&mut self, client: ClientId) -> Option<&mut Account> {
+        BoundedCache::get_mut(self, &client)
+    }
+
+    fn get_or_create(&mut self, client: ClientId) -> &mut Account {
+        if !self.contains(&client) {
+            self.put(client, Account::new(client));
+        }
+        BoundedCache::get_mut(self, &client).unwrap()
+    }
+
+    fn accounts(&self) -> Box<dyn Iterator<Item = &Account> + '_> {
+        Box::new(self.iter().map(|(_, account)| account))
+    }
+
+    fn len(&self) -> usize {
+        BoundedCache::len(self)
+    }
+}
+
+/// IDs of the deposits, withdrawals and closes already applied, to reject duplicates.
+pub trait ProcessedTxIds: Debug + Send {
+    fn contains(&self, tx: TxId) -> bool;
+
+    /// Record `tx`. Sets with a capacity may forget another ID to make room.
+    fn insert(&mut self, tx: TxId);
+}
+
+impl ProcessedTxIds for HashSet<TxId> {
+    fn contains(&self, tx: TxId) -> bool {
+        HashSet::contains(self, &tx)
+    }
+
+    fn insert(&mut self, tx: TxId) {
+        HashSet::insert(self, tx);
+    }
+}
+
+impl ProcessedTxIds for BoundedCache<TxId, ()> {
+    fn contains(&self, tx: TxId) -> bool {
+        BoundedCache::contains(self, &tx)
+    }
+
+    fn insert(&mut self, tx: TxId) {
+        self.put(tx, ());
+    }
+}
+
+/// An engine's state and settings, and the pipeline applying transactions to them.
+#[derive(Debug, Clone, Default)]
+pub struct Ledger<A, P, S> {
+    /// Mapping of client IDs to their accounts.
+    pub(crate) accounts: A,
+
+    /// Record of disputable transactions (deposits/withdrawals) keyed by transaction ID.
+    /// Only stores transactions that can potentially be disputed.
+    pub(crate) disputable_transactions: S,
+
+    /// IDs of processed transactions, to prevent duplicates.
+    pub(crate) processed_tx_ids: P,
+
+    /// How reader-based processing reacts to bad rows and rejected transactions.
+    pub(crate) error_policy: ErrorPolicy,
+
+    /// How reader-based processing parses the `type` and `amount` columns.
+    pub(crate) input_format: InputFormat,
+
+    /// Parse failures after which reader-based processing fails.
+    pub(crate) parse_error_limit: Option<ParseErrorLimit>,
+
+    /// Business rules applied to account operations.
+    pub(crate) policy: EnginePolicy,
+
+    /// How balances are written to account output.
+    pub(crate) decimal_format: DecimalFormat,
+
+    /// Transactions currently under dispute, by client. Includes disputes whose
+    /// transaction record a bounded store has since evicted; their funds stay held.
+    pub(crate) disputes_by_client: HashMap<ClientId, BTreeSet<TxId>>,
+
+    /// Checks every transaction must pass before it is applied.
+    pub(crate) validators: ValidatorChain,
+
+    /// Notified after every processed transaction.
+    pub(crate) observers: ObserverList,
+
+    /// Recurring transactions applied as timestamps advance.
+    pub(crate) recurring: RecurringScheduler,
+
+    /// Drops disputable transactions once their dispute window ends.
+    pub(crate) expiry: DisputeExpiry,
+
+    /// Idempotency keys of accepted transactions, to recognise resubmissions.
+    pub(crate) idempotency: IdempotencyKeys,
+
+    /// Clients whose accounts changed since the last `write_changed_accounts_csv`.
+    pub(crate) changed: HashSet<ClientId>,
+
+    /// Checked between records to stop reader-based processing early.
+    pub(crate) shutdown: ShutdownFlag,
+
+    /// Latency and throughput of processed transactions.
+    pub(crate) metrics: EngineMetrics,
+}
+
+impl<A: AccountMap, P: ProcessedTxIds, S: TransactionStore> Ledger<A, P, S> {
+    /// An empty ledger keeping its state in the given containers, with default settings.
+    pub fn new(accounts: A, processed_tx_ids: P, disputable_transactions: S) -> Self {
+        Self {
+            accounts,
+            disputable_transactions,
+            processed_tx_ids,
+            error_policy: ErrorPolicy::default(),
+            input_format: InputFormat::default(),
+            parse_error_limit: None,
+            policy: EnginePolicy::default(),
+            decimal_format: DecimalFormat::default(),
+            disputes_by_client: HashMap::new(),
+            validators: ValidatorChain::default(),
+            observers: ObserverList::default(),
+            recurring: RecurringScheduler::default(),
+            expiry: DisputeExpiry::default(),
+            idempotency: IdempotencyKeys::default(),
+            changed: HashSet::new(),
+            shutdown: ShutdownFlag::default(),
+            metrics: EngineMetrics::default(),
+        }
+    }
+
+    /// This ledger with its disputable transactions kept in `store` instead. Anything
+    /// the current store holds is dropped.
+    pub fn with_store<T: TransactionStore>(self, store: T) -> Ledger<A, P, T> {
+        Ledger {
+            disputable_transactions: store,
+            accounts: self.accounts,
+            processed_tx_ids: self.processed_tx_ids,
+            error_policy: self.error_policy,
+            parse_error_limit: self.parse_error_limit,
+            input_format: self.input_format,
+            policy: self.policy,
+            decimal_format: self.decimal_format,
+            disputes_by_client: self.disputes_by_client,
+            validators: self.validators,
+            observers: self.observers,
+            recurring: self.recurring,
+            expiry: self.expiry,
+            idempotency: self.idempotency,
+            changed: self.changed,
+            shutdown: self.shutdown,
+            metrics: self.metrics,
+        }
+    }
+
+    /// IDs of the client's transactions that are currently under dispute, in ascending order.
+    pub fn open_disputes(&self, client: ClientId) -> Vec<TxId> {
+        self.disputes_by_client
+            .get(&client)
+            .map(|disputes| disputes.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Put the client's account into `lock` with the given reason, creating the account
+    /// if there is none.
+    pub fn set_lock(&mut self, client: ClientId, lock: LockState, reason: Option<String>) {
+        self.accounts.get_or_create(client).set_lock(lock, reason);
+    }
+
+    pub fn process_transaction(&mut self, transaction: &Transaction) -> Result<(), PaymentsError> {
+        self.process_transaction_since(transaction, start_timer())
+    }
+
+    /// Apply `transactions` in order, returning each one's result.
+    pub fn process_transactions(
+        &mut self,
+        transactions: &[Transaction],
+    ) -> Vec<Result<(), PaymentsError>> {
+        transactions
+            .iter()
+            .map(|transaction| self.process_transaction(transaction))
+            .collect()
+    }
+
+    /// Process a transaction whose latency is measured from `started`, e.g. when it was
+    /// queued, rather than from when this call begins.
+    pub fn process_transaction_since(
+        &mut self,
+        transaction: &Transaction,
+        started: Option<std::time::Instant>,
+    ) -> Result<(), PaymentsError> {
+        self.process_with(transaction, started, Self::apply_transaction)
+    }
+
+    /// Resolve the dispute `transaction` refers to like a resolve row, even when the
+    /// account's lock state rejects resolves. The account keeps its lock state.
+    pub fn force_resolve(&mut self, transaction: &Transaction) -> Result<(), PaymentsError> {
+        self.process_with(transaction, start_timer(), |ledger, transaction| {
+            ledger.process_dispute_action(transaction, DisputeAction::ForceResolve)
+        })
+    }
+
+    /// Run `transaction` through the checks, `apply` and the observers.
+    fn process_with(
+        &mut self,
+        transaction: &Transaction,
+        started: Option<std::time::Instant>,
+        apply: impl FnOnce(&mut Self, &Transaction) -> Result<(), PaymentsError>,
+    ) -> Result<(), PaymentsError> {
+        if self.is_replay(transaction) {
+            log::debug!("Skipping replay of transaction {}", transaction.tx);
+            return Ok(());
+        }
+        if self.idempotency.is_resubmission(transaction) {
+            log::debug!(
+                "Skipping resubmission of transaction {} under its idempotency key",
+                transaction.tx
+            );
+            return Ok(());
+        }
+        self.expire_disputable(transaction.timestamp);
+        if let Some(now) = transaction.timestamp
+            && !self.recurring.is_empty()
+        {
+            self.process_recurring(now);
+        }
+        // Observers get the account's balances before and after the transaction
+        let observed = (!self.observers.is_empty()).then(|| {
+            (
+                self.accounts.peek(transaction.client).cloned(),
+                self.with_stored_case(transaction),
+            )
+        });
+        let result = self
+            .idempotency
+            .check(transaction)
+            .and_then(|()| self.validators.validate(transaction))
+            .and_then(|()| apply(self, transaction));
+        self.metrics.record_since(started);
+        if result.is_ok() {
+            self.changed.insert(transaction.client);
+            self.idempotency.record(transaction);
+        }
+        if let Some((before, with_case)) = observed {
+            self.notify(with_case.as_ref().unwrap_or(transaction), &result, before);
+        }
+        result
+    }
+
+    /// Whether the policy skips `transaction` as a replay of the deposit or withdrawal
+    /// already applied under its ID.
+    fn is_replay(&self, transaction: &Transaction) -> bool {
+        self.policy.duplicates == DuplicatePolicy::SkipSilently
+            && self.processed_tx_ids.contains(transaction.tx)
+            && self
+                .disputable_transactions
+                .get(transaction.tx)
+                .is_some_and(|stored| stored.matches(transaction))
+    }
+
+    /// `transaction` with the case metadata recorded against the transaction it disputes
+    /// filled in, for observers. Looked up before applying, since a chargeback removes it.
+    fn with_stored_case(&self, transaction: &Transaction) -> Option<Transaction> {
+        if matches!(
+            transaction.tx_type,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        ) {
+            return None;
+        }
+        let stored_tx = self.disputable_transactions.get(transaction.tx)?;
+        Some(transaction.with_case(stored_tx.case.as_deref()?))
+    }
+
+    /// Drop the disputable transactions whose window ended before a row at `timestamp`.
+    /// Those under dispute are kept until their next window ends.
+    fn expire_disputable(&mut self, timestamp: Option<u64>) {
+        for tx in self.expiry.advance(timestamp) {
+            match self.disputable_transactions.get(tx) {
+                Some(stored_tx) if stored_tx.disputed => self.expiry.track(tx),
+                Some(_) => {
+                    self.disputable_transactions.remove(tx);
+                    self.expiry.expire(tx);
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// Apply the recurring transactions due by `now`. Rejections are reported to
+    /// observers and logged, and do not affect the transaction that triggered them.
+    fn process_recurring(&mut self, now: u64) {
+        for transaction in self.recurring.due(now) {
+            if let Err(e) = self.process_transaction_since(&transaction, start_timer()) {
+                log::warn!("Recurring transaction {:?} rejected: {}", transaction, e);
+            }
+        }
+    }
+
+    fn notify(
+        &self,
+        transaction: &Transaction,
+        result: &Result<(), PaymentsError>,
+        before: Option<Account>,
+    ) {
+        if let Err(e) = result {
+            self.observers.rejected(transaction, e);
+            if let (TransactionType::Dispute, PaymentsError::DisputeWindowExpired(_)) =
+                (&transaction.tx_type, e)
+            {
+                self.observers.dispute_expired(transaction);
+            }
+            return;
+        }
+        self.observers.accepted(transaction);
+        let after = self.accounts.peek(transaction.client);
+        // A close that paid out the balance is followed by its payout withdrawal
+        if let (TransactionType::CloseAccount, Some(before), Some(after)) =
+            (&transaction.tx_type, &before, after)
+            && after.available < before.available
+        {
+            self.observers
+                .accepted(&transaction.payout(before.available - after.available));
+        }
+        if let Some(after) = after
+            && before.as_ref() != Some(after)
+        {
+            self.observers
+                .account_changed(&AccountDelta::new(Some(transaction.tx), before, after));
+        }
+        match transaction.tx_type {
+            TransactionType::Chargeback => {
+                if let Some(account) = self.accounts.peek(transaction.client) {
+                    self.observers.account_locked(account, transaction);
+                }
+            }
+            TransactionType::Dispute => {
+                if let Some(stored_tx) = self.disputable_transactions.get(transaction.tx) {
+                    self.observers.dispute_opened(transaction, stored_tx.amount);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn apply_transaction(&mut self, transaction: &Transaction) -> Result<(), PaymentsError> {
+        match transaction.tx_type {
+            TransactionType::Deposit => self.process_deposit(transaction),
+            TransactionType::Withdrawal => self.process_withdrawal(transaction),
+            TransactionType::Dispute => {
+                self.process_dispute_action(transaction, DisputeAction::Open)
+            }
+            TransactionType::Resolve => {
+                self.process_dispute_action(transaction, DisputeAction::Resolve)
+            }
+            TransactionType::Chargeback => {
+                self.process_dispute_action(transaction, DisputeAction::Chargeback)
+            }
+            TransactionType::CloseAccount => self.process_close(transaction),
+        }
+    }
+
+    fn process_deposit(&mut self, transaction: &Transaction) -> Result<(), PaymentsError> {
+        let amount = transaction.amount.ok_or(PaymentsError::InvalidTransaction(
+            "Deposit transaction must have an amount".to_string(),
+        ))?;
+        if amount <= Amount::ZERO {
+            return Err(PaymentsError::InvalidTransaction(
+                "Deposit amount must be positive".to_string(),
+            ));
+        }
+        if self.processed_tx_ids.contains(transaction.tx) {
+            let stored = self.disputable_transactions.get(transaction.tx);
+            self.policy
+                .duplicates
+                .check(transaction, stored.as_deref())?;
+        }
+        let client_id = transaction.client;
+        let policy = self.policy;
+        let account = self.accounts.get_or_create(client_id);
+        account.deposit(amount, &policy)?;
+
+        // Store disputable transaction for potential future disputes
+        self.disputable_transactions.insert(
+            transaction.tx,
+            StoredTransaction {
+                client: client_id,
+                amount,
+                disputed: false,
+                withdrawal: false,
+                case: None,
+            },
+        );
+
+        self.expiry.track(transaction.tx);
+
+        // Track transaction ID for duplicate prevention
+        self.processed_tx_ids.insert(transaction.tx);
+
+        Ok(())
+    }
+
+    fn process_withdrawal(&mut self, transaction: &Transaction) -> Result<(), PaymentsError> {
+        let amount = transaction.amount.ok_or(PaymentsError::InvalidTransaction(
+            "Withdrawal transaction must have an amount".to_string(),
+        ))?;
+        if amount <= Amount::ZERO {
+            return Err(PaymentsError::InvalidTransaction(
+                "Withdrawal amount must be positive".to_string(),
+            ));
+        }
+        if self.processed_tx_ids.contains(transaction.tx) {
+            let stored = self.disputable_transactions.get(transaction.tx);
+            self.policy
+                .duplicates
+                .check(transaction, stored.as_deref())?;
+        }
+        let client_id = transaction.client;
+        let account = self.accounts.get_or_create(client_id);
+        account.withdraw(amount)?;
+
+        // Store disputable transaction for potential future disputes
+        self.disputable_transactions.insert(
+            transaction.tx,
+            StoredTransaction {
+                client: client_id,
+                amount,
+                disputed: false,
+                withdrawal: true,
+                case: None,
+            },
+        );
+
+        self.expiry.track(transaction.tx);
+
+        // Track transaction ID for duplicate prevention
+        self.processed_tx_ids.insert(transaction.tx);
+        Ok(())
+    }
+
+    /// Apply a dispute, resolve or chargeback row; see `dispute` for the lifecycle.
+    fn process_dispute_action(
+        &mut self,
+        transaction: &Transaction,
+        action: DisputeAction,
+    ) -> Result<(), PaymentsError> {
+        let open_disputes = self
+            .disputes_by_client
+            .get(&transaction.client)
+            .map_or(0, BTreeSet::len);
+        let transition = action.transition(
+            transaction,
+            self.disputable_transactions.get(transaction.tx).as_deref(),
+            || self.expiry.not_found(transaction.tx),
+            open_disputes,
+            &self.policy,
+        )?;
+
+        let policy = self.policy;
+        transition.apply(self.accounts.get_or_create(transition.client), &policy)?;
+        self.set_disputed(transaction, transition.to == DisputeState::Disputed);
+        // A chargeback is final, so the transaction is no longer needed
+        if transition.to == DisputeState::ChargedBack {
+            self.disputable_transactions.remove(transaction.tx);
+        }
+        Ok(())
+    }
+
+    /// Flip a stored transaction's dispute flag and record the row's case metadata. Called
+    /// only after the account update succeeded, so a rejected dispute, resolve or
+    /// chargeback leaves no partial state.
+    fn set_disputed(&mut self, transaction: &Transaction, disputed: bool) {
+        let (client, tx) = (transaction.client, transaction.tx);
+        let disputes = self.disputes_by_client.entry(client).or_default();
+        if disputed {
+            disputes.insert(tx);
+        } else {
+            disputes.remove(&tx);
+            if disputes.is_empty() {
+                self.disputes_by_client.remove(&client);
+            }
+        }
+        if let Some(stored_tx) = self.disputable_transactions.get_mut(tx) {
+            stored_tx.disputed = disputed;
+            if transaction.reason_code.is_some() || transaction.case_ref.is_some() {
+                stored_tx.case.get_or_insert_default().update(transaction);
+            }
+        }
+    }
+
+    fn process_close(&mut self, transaction: &Transaction) -> Result<(), PaymentsError> {
+        if transaction.amount.is_some() {
+            return Err(PaymentsError::InvalidTransaction(
+                "Close transaction should not have an amount".to_string(),
+            ));
+        }
+        // The ID is reserved for the payout withdrawal
+        if self.processed_tx_ids.contains(transaction.tx) {
+            return Err(PaymentsError::InvalidTransaction(format!(
+                "Transaction ID {} already exists",
+                transaction.tx
+            )));
+        }
+        let payout = self.policy.payout_on_close;
+        let account =
+            self.accounts
+                .get_mut(transaction.client)
+                .ok_or(PaymentsError::InvalidTransaction(format!(
+                    "Client {} has no account to close",
+                    transaction.client
+                )))?;
+        account.close(payout)?;
+
+        self.processed_tx_ids.insert(transaction.tx);
+        Ok(())
+    }
+
+    pub fn process_transactions_from_reader<R: Read>(
+        &mut self,
+        reader: R,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut rdr = transaction_reader(reader, &self.input_format);
+        let lines = read_transactions(&mut rdr, self.input_format.clone())?.enumerate();
+        let mut parse_errors = ParseErrorTally::new(self.parse_error_limit);
+
+        for (idx, line) in lines {
+            if self.shutdown.is_requested() {
+                log::warn!("Shutdown requested; stopping before line {}", idx + 1);
+                break;
+            }
+            parse_errors.read();
+            let transaction: Transaction = match line {
+                Ok(tx) => tx,
+                Err(e) => {
+                    log::error!("Failed to parse line {}: {}", idx + 1, e);
+                    if self.error_policy == ErrorPolicy::Abort {
+                        return Err(e.at_line(idx as u64 + 1).into());
+                    }
+                    parse_errors.failed()?;
+                    continue;
+                }
+            };
+
+            if let Err(e) = self.process_transaction(&transaction) {
+                log::error!("Failed to process transaction {:?}: {}", transaction, e);
+                if self.error_policy == ErrorPolicy::Abort {
+                    return Err(e.at_line(idx as u64 + 1).into());
+                }
+            } else {
+                log::debug!("Successfully processed transaction: {:?}", transaction);
+            }
+        }
+        parse_errors.finish()?;
+        Ok(())
+    }
+
+    /// Write the accounts for which `filter` returns true, in the accounts' own order.
+    pub fn write_accounts_csv_filtered<W: std::io::Write>(
+        &self,
+        writer: W,
+        filter: impl Fn(&Account) -> bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut wtr = csv::WriterBuilder::new()
+            .has_headers(true)
+            .from_writer(writer);
+
+        for account in self.accounts.accounts().filter(|account| filter(account)) {
+            wtr.serialize(self.decimal_format.account(account))?;
+        }
+
+        wtr.flush()?;
+        Ok(())
+    }
+
+    /// Write the accounts changed by transactions since the previous call for which
+    /// `filter` returns true, by client ID, and start tracking changes afresh.
+    pub fn write_changed_accounts_csv_filtered<W: std::io::Write>(
+        &mut self,
+        writer: W,
+        filter: impl Fn(&Account) -> bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut wtr = csv::WriterBuilder::new()
+            .has_headers(true)
+            .from_writer(writer);
+
+        let mut changed: Vec<_> = self.changed.drain().collect();
+        changed.sort_unstable();
+        for client in changed {
+            if let Some(account) = self.accounts.peek(client).filter(|account| filter(account)) {
+                wtr.serialize(self.decimal_format.account(account))?;
+            }
+        }
+
+        wtr.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+use serde::Serialize;
+use std::mem::size_of;
+
+use crate::account::{Account, ClientId};
+use crate::transaction::{StoredTransaction, TxId};
+
+/// Bytes the allocator adds to every heap allocation (size header, rounded to 16)
+const ALLOCATION_OVERHEAD: usize = 16;
+/// Allocations are rounded up to this alignment
+const ALLOCATION_ALIGN: usize = 16;
+
+/// Approximate heap cost in bytes of one entry in each of the bounded engine's caches.
+/// Derived from the concrete types with `size_of` plus the layout of `lru::LruCache`:
+/// every entry is a separately allocated node holding the key, the value and two list
+/// pointers, indexed by a hash table slot of two pointers and a control byte that is at
+/// most 7/8 full.
+///
+/// These are lower bounds, not the engine's full cost per entry. They leave out heap data
+/// owned by the values, such as lock reasons and dispute case references, and the
+/// frequency index of the LFU policy. They also leave out the engine's indexes beside
+/// the caches: open disputes by client, dispute expiry tracking and changed clients.
+/// `tests/memory.rs` checks them against what the caches really allocate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct MemoryEstimates {
+    pub account_bytes: usize,
+    pub transaction_bytes: usize,
+    pub tx_id_bytes: usize,
+}
+
+impl MemoryEstimates {
+    /// Compute per-entry costs of an LRU cache for the types this build was compiled with
+    pub fn calibrate() -> Self {
+        Self {
+            account_bytes: lru_entry_bytes(size_of::<ClientId>(), size_of::<Account>()),
+            transaction_bytes: lru_entry_bytes(size_of::<TxId>(), size_of::<StoredTransaction>()),
+            tx_id_bytes: lru_entry_bytes(size_of::<TxId>(), size_of::<()>()),
+        }
+    }
+
+    /// Estimated heap use of caches holding the given numbers of entries
+    pub fn total_bytes(&self, accounts: usize, transactions: usize, tx_ids: usize) -> usize {
+        accounts * self.account_bytes
+            + transactions * self.transaction_bytes
+            + tx_ids * self.tx_id_bytes
+    }
+}
+
+impl Default for MemoryEstimates {
+    fn default() -> Self {
+        Self::calibrate()
+    }
+}
+
+fn lru_entry_bytes(key: usize, value: usize) -> usize {
+    let pointer = size_of::<usize>();
+    let node = key + value + 2 * pointer;
+    let allocation = (node + ALLOCATION_OVERHEAD).div_ceil(ALLOCATION_ALIGN) * ALLOCATION_ALIGN;
+    let table_slot = (2 * pointer + 1) * 8 / 7 + 1;
+    allocation + table_slot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimates_cover_types() {
+        let estimates = MemoryEstimates::calibrate();
+        assert!(estimates.account_bytes > size_of::<Account>());
+        assert!(estimates.transaction_bytes > size_of::<StoredTransaction>());
+        assert!(estimates.tx_id_bytes > size_of::<TxId>());
+        assert!(estimates.account_bytes > estimates.transaction_bytes);
+        assert!(estimates.transaction_bytes > estimates.tx_id_bytes);
+    }
+}
@@ -0,0 +1,208 @@
+use serde::{Serialize, Serializer};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Sub-buckets per power of two; latencies are reported to within 25%
+const SUB_BUCKETS: usize = 4;
+const BUCKETS: usize = 65 * SUB_BUCKETS;
+
+/// Length of the window used for rolling throughput
+const THROUGHPUT_WINDOW_SECS: u64 = 10;
+
+/// Latency and throughput summary of the transactions an engine has processed.
+/// Serialized with latencies in microseconds, as `p50_us` and so on.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct EngineStats {
+    /// Transactions timed so far, including rejected ones
+    pub transactions: u64,
+    #[serde(rename = "p50_us", serialize_with = "micros")]
+    pub p50: Duration,
+    #[serde(rename = "p95_us", serialize_with = "micros")]
+    pub p95: Duration,
+    #[serde(rename = "p99_us", serialize_with = "micros")]
+    pub p99: Duration,
+    #[serde(rename = "max_us", serialize_with = "micros")]
+    pub max: Duration,
+    /// Transactions per second over the last ten seconds of activity
+    pub throughput: f64,
+}
+
+fn micros<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64() * 1e6)
+}
+
+/// Records per-transaction latency in a log-linear histogram plus a per-second
+/// throughput window. Recording is a handful of integer operations, cheap enough to do
+/// for every transaction.
+#[derive(Debug, Clone)]
+pub struct EngineMetrics {
+    buckets: Vec<u64>,
+    count: u64,
+    max: Duration,
+
+    /// When the first transaction was started and the last one finished
+    started: Option<Instant>,
+    finished: Option<Instant>,
+
+    /// (seconds since `started`, transactions completed in that second), oldest first
+    window: VecDeque<(u64, u64)>,
+}
+
+impl Default for EngineMetrics {
+    fn default() -> Self {
+        Self {
+            buckets: vec![0; BUCKETS],
+            count: 0,
+            max: Duration::ZERO,
+            started: None,
+            finished: None,
+            window: VecDeque::new(),
+        }
+    }
+}
+
+/// Start timing a transaction. `None` where no monotonic clock is available
+/// (`wasm32-unknown-unknown`), which turns recording into a no-op.
+pub fn start_timer() -> Option<Instant> {
+    if cfg!(target_arch = "wasm32") {
+        None
+    } else {
+        Some(Instant::now())
+    }
+}
+
+impl EngineMetrics {
+    /// Record a transaction that started at `started` and has just finished.
+    pub fn record_since(&mut self, started: Option<Instant>) {
+        let Some(started) = started else {
+            return;
+        };
+        let now = Instant::now();
+        let latency = now.saturating_duration_since(started);
+
+        self.buckets[Self::bucket(latency)] += 1;
+        self.count += 1;
+        self.max = self.max.max(latency);
+        self.finished = Some(now);
+
+        let second = now
+            .saturating_duration_since(*self.started.get_or_insert(started))
+            .as_secs();
+        match self.window.back_mut() {
+            Some((last, count)) if *last == second => *count += 1,
+            _ => self.window.push_back((second, 1)),
+        }
+        while self
+            .window
+            .front()
+            .is_some_and(|(first, _)| first + THROUGHPUT_WINDOW_SECS <= second)
+        {
+            self.window.pop_front();
+        }
+    }
+
+    /// Percentiles, maximum and rolling throughput of everything recorded so far.
+    pub fn stats(&self) -> EngineStats {
+        EngineStats {
+            transactions: self.count,
+            p50: self.percentile(0.50),
+            p95: self.percentile(0.95),
+            p99: self.percentile(0.99),
+            max: self.max,
+            throughput: self.throughput(),
+        }
+    }
+
+    fn bucket(latency: Duration) -> usize {
+        let nanos = latency.as_nanos().min(u64::MAX as u128) as u64;
+        if nanos < SUB_BUCKETS as u64 {
+            return nanos as usize;
+        }
+        let msb = 63 - nanos.leading_zeros() as usize;
+        let sub = (nanos >> (msb - 2)) as usize & (SUB_BUCKETS - 1);
+        msb * SUB_BUCKETS + sub
+    }
+
+    /// Upper bound of the bucket holding the `p`-th fraction of recorded latencies,
+    /// capped at the largest latency actually seen
+    fn percentile(&self, p: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        let rank = ((self.count as f64 * p).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (idx, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let upper = if idx < SUB_BUCKETS {
+                    idx as u64
+                } else {
+                    let msb = idx / SUB_BUCKETS;
+                    let sub = (idx % SUB_BUCKETS) as u64;
+                    ((SUB_BUCKETS as u64 + sub + 1) << (msb - 2)).saturating_sub(1)
+                };
+                return Duration::from_nanos(upper).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    fn throughput(&self) -> f64 {
+        let (Some(&(first, _)), Some(&(last, _))) = (self.window.front(), self.window.back())
+        else {
+            return 0.0;
+        };
+        let total: u64 = self.window.iter().map(|(_, count)| count).sum();
+        // While the window still reaches back to the first transaction, use the exact
+        // elapsed time so short runs are not rounded up to a whole second
+        let span = match (first, self.started, self.finished) {
+            (0, Some(started), Some(finished)) => finished.duration_since(started).as_secs_f64(),
+            _ => (last - first + 1) as f64,
+        };
+        if span > 0.0 { total as f64 / span } else { 0.0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles() {
+        let mut metrics = EngineMetrics::default();
+        let now = Instant::now();
+        // 98 fast transactions, then two slow ones
+        for _ in 0..98 {
+            metrics.record_since(Some(now));
+        }
+        metrics.record_since(Some(Instant::now() - Duration::from_millis(50)));
+        metrics.record_since(Some(Instant::now() - Duration::from_millis(50)));
+
+        let stats = metrics.stats();
+        assert_eq!(stats.transactions, 100);
+        assert!(stats.p50 < Duration::from_millis(10), "{:?}", stats.p50);
+        assert!(stats.p95 < Duration::from_millis(10), "{:?}", stats.p95);
+        assert!(stats.p99 >= Duration::from_millis(37), "{:?}", stats.p99);
+        assert!(stats.p99 <= stats.max);
+        assert!(stats.max >= Duration::from_millis(50));
+        assert!(stats.throughput > 0.0);
+    }
+
+    #[test]
+    fn test_bucket_bounds() {
+        for nanos in [0u64, 3, 4, 7, 8, 1000, 123_456_789] {
+            let idx = EngineMetrics::bucket(Duration::from_nanos(nanos));
+            let mut metrics = EngineMetrics::default();
+            metrics.buckets[idx] = 1;
+            metrics.count = 1;
+            metrics.max = Duration::MAX;
+            let upper = metrics.percentile(1.0).as_nanos() as u64;
+            assert!(
+                upper >= nanos && upper <= nanos + nanos / 4 + 1,
+                "{} -> {}",
+                nanos,
+                upper
+            );
+        }
+    }
+}
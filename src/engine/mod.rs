@@ -1,33 +1,111 @@
-use std::io::{BufReader, Read};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "fs")]
+use std::io::BufReader;
+use std::io::Read;
 
+use serde::{Deserialize, Serialize};
+
+use crate::account::{Account, ClientId, DecimalFormat, LockState};
 use crate::errors::PaymentsError;
-use crate::transaction::Transaction;
+use crate::shutdown::ShutdownFlag;
+use crate::transaction::{
+    AmountFormat, InputFormat, StoredTransaction, Transaction, TxId, TxIdScope, TypeMatching,
+};
 
+#[cfg(feature = "concurrent")]
+pub mod actor;
+#[cfg(feature = "adaptive")]
+pub mod adaptive;
+pub mod auth;
 pub mod bounded;
+pub mod cache;
+#[cfg(all(feature = "concurrent", any(test, feature = "testing")))]
+pub mod chaos;
+#[cfg(feature = "concurrent")]
 pub mod concurrent;
+pub mod dispute;
+pub mod expiry;
+pub mod export;
+pub mod fees;
+pub mod idempotency;
+pub mod invariants;
+pub mod ledger;
+pub mod memory;
+pub mod metrics;
+pub mod observer;
+#[cfg(feature = "concurrent")]
+mod ordering;
+#[cfg(feature = "concurrent")]
+pub mod pipeline;
+pub mod profile;
+pub mod ratelimit;
+pub mod recurring;
+#[cfg(feature = "redis")]
+pub mod redis;
+pub mod replay;
+#[cfg(feature = "concurrent")]
+pub mod routing;
+#[cfg(feature = "concurrent")]
+mod scheduler;
+#[cfg(feature = "redis")]
+pub mod shared;
 pub mod standard;
+pub mod state;
+pub mod store;
+#[cfg(feature = "concurrent")]
+mod sync;
+pub mod tenant;
+pub mod validation;
 
+#[cfg(feature = "concurrent")]
+use actor::ActorEngine;
+#[cfg(feature = "adaptive")]
+use adaptive::AdaptiveEngine;
 use bounded::BoundedEngine;
+use cache::EvictionPolicy;
+#[cfg(feature = "concurrent")]
 use concurrent::ConcurrentEngine;
+use expiry::{DisputeExpiry, DisputeWindow};
+use fees::FeeSchedule;
+use invariants::{InvariantReport, InvariantViolation};
+use memory::MemoryEstimates;
+use metrics::EngineStats;
+use observer::{AccountChangeObserver, AccountDelta, EngineObserver};
+use profile::InputProfile;
+use recurring::{RecurringSchedule, RecurringScheduler};
+use replay::Schedule;
 use standard::StandardEngine;
+use state::EngineState;
+use validation::TransactionValidator;
 
 /// Configuration for creating different types of payment engines
 #[derive(Debug, Clone)]
 pub enum EngineConfig {
     /// Standard payment engine with unlimited memory usage
     Standard,
-    /// Memory-bounded engine with LRU eviction
+    /// Memory-bounded engine that evicts according to `policy` (LRU by default)
     Bounded {
         max_accounts: usize,
         max_disputable_transactions: usize,
         max_processed_tx_ids: usize,
+        policy: EvictionPolicy,
     },
     /// Concurrent engine for handling multiple streams
+    #[cfg(feature = "concurrent")]
     Concurrent {
         max_accounts: usize,
         max_disputable_transactions: usize,
         max_processed_tx_ids: usize,
     },
+    /// Engine whose clients are split between actor threads, each owning its clients'
+    /// state
+    #[cfg(feature = "concurrent")]
+    Actor { actors: usize },
+    /// Standard engine that switches to bounded storage once resident memory
+    /// exceeds the budget
+    #[cfg(feature = "adaptive")]
+    Adaptive { memory_budget_mb: usize },
 }
 
 impl EngineConfig {
@@ -46,10 +124,20 @@ impl EngineConfig {
             max_accounts,
             max_disputable_transactions,
             max_processed_tx_ids,
+            policy: EvictionPolicy::default(),
+        }
+    }
+
+    /// Use `eviction_policy` for a bounded configuration; other engines are unchanged
+    pub fn with_eviction_policy(mut self, eviction_policy: EvictionPolicy) -> Self {
+        if let Self::Bounded { policy, .. } = &mut self {
+            *policy = eviction_policy;
         }
+        self
     }
 
     /// Create a concurrent configuration for high-throughput server environments
+    #[cfg(feature = "concurrent")]
     pub fn concurrent(
         max_accounts: usize,
         max_disputable_transactions: usize,
@@ -62,27 +150,74 @@ impl EngineConfig {
         }
     }
 
+    /// Create an actor configuration with `actors` actor threads (at least one)
+    #[cfg(feature = "concurrent")]
+    pub fn actor(actors: usize) -> Self {
+        Self::Actor { actors }
+    }
+
+    /// Create an adaptive configuration that starts unbounded and switches to bounded
+    /// storage sized for `memory_budget_mb` once the process uses more than that
+    #[cfg(feature = "adaptive")]
+    pub fn adaptive(memory_budget_mb: usize) -> Self {
+        Self::Adaptive { memory_budget_mb }
+    }
+
     /// Create a bounded configuration optimized for the given available memory in MB
-    /// Rough estimates: Account ~200 bytes, Transaction ~100 bytes, TxId ~4 bytes
+    /// Per-entry costs come from `MemoryEstimates::calibrate`, a lower bound, so the
+    /// engine can use more than `available_memory_mb`
     /// Accounts: 25%, Transactions: 50%, TxIds: 25%
     pub fn for_memory_mb(available_memory_mb: usize) -> Self {
+        let estimates = MemoryEstimates::calibrate();
         let account_memory_mb = available_memory_mb / 4;
         let transaction_memory_mb = available_memory_mb / 2;
         let tx_id_memory_mb = available_memory_mb / 4;
 
-        let max_accounts = (account_memory_mb * 1024 * 1024) / 200;
-        let max_transactions = (transaction_memory_mb * 1024 * 1024) / 100;
-        let max_tx_ids = (tx_id_memory_mb * 1024 * 1024) / 4;
+        let max_accounts = (account_memory_mb * 1024 * 1024) / estimates.account_bytes;
+        let max_transactions = (transaction_memory_mb * 1024 * 1024) / estimates.transaction_bytes;
+        let max_tx_ids = (tx_id_memory_mb * 1024 * 1024) / estimates.tx_id_bytes;
 
         Self::bounded(max_accounts, max_transactions, max_tx_ids)
     }
 
+    /// Pick an engine for the CSV file at `path` from its size and a sample of its rows.
+    /// See `auto_for_profile` for how the choice is made.
+    #[cfg(feature = "fs")]
+    pub fn auto_for_file(path: &std::path::Path) -> Result<Self, PaymentsError> {
+        let profile = InputProfile::from_file(path)?;
+        log::info!(
+            "Input profile: {} bytes, ~{} rows, ~{} clients",
+            profile.total_bytes,
+            profile.estimated_rows(),
+            profile.estimated_clients()
+        );
+        Ok(Self::auto_for_profile(&profile))
+    }
+
+    /// Standard engine for inputs of up to `AUTO_STANDARD_MAX_ROWS` rows, where keeping
+    /// every transaction in memory is affordable; otherwise a bounded engine that keeps
+    /// every client's account but caps disputable transactions and processed IDs.
+    /// The concurrent engine is never picked: its global lock makes it slower than the
+    /// single-threaded engines on a single file.
+    pub fn auto_for_profile(profile: &InputProfile) -> Self {
+        let rows = profile.estimated_rows();
+        if rows <= AUTO_STANDARD_MAX_ROWS {
+            return Self::standard();
+        }
+        Self::bounded(
+            profile.estimated_clients().max(1),
+            AUTO_MAX_DISPUTABLE_TRANSACTIONS,
+            AUTO_MAX_PROCESSED_TX_IDS,
+        )
+    }
+
     /// Create engine config from optional CLI parameters
     /// This encapsulates the logic for handling optional engine type and custom limits
     /// max_accounts: default 10_000
     /// max_transactions: default 50_000
     /// max_tx_ids: default 1_000_000
     /// memory_limit_mb: default 100
+    /// For the adaptive engine, memory_limit_mb is the budget that triggers the switch
     pub fn from_cli_params(
         engine_type: Option<&str>,
         max_accounts: Option<usize>,
@@ -90,6 +225,11 @@ impl EngineConfig {
         max_tx_ids: Option<usize>,
         memory_limit_mb: Option<usize>,
     ) -> Self {
+        #[cfg(feature = "adaptive")]
+        if engine_type.is_some_and(|engine_type| engine_type.eq_ignore_ascii_case("adaptive")) {
+            return Self::adaptive(memory_limit_mb.unwrap_or(100));
+        }
+
         // If memory limit is specified, use it to auto-configure bounded engine
         if let Some(memory_mb) = memory_limit_mb {
             return Self::for_memory_mb(memory_mb);
@@ -104,7 +244,14 @@ impl EngineConfig {
         match engine_type.as_str() {
             "standard" => Self::standard(),
             "bounded" => Self::bounded(max_accounts, max_transactions, max_tx_ids),
+            #[cfg(feature = "concurrent")]
             "concurrent" => Self::concurrent(max_accounts, max_transactions, max_tx_ids),
+            #[cfg(feature = "concurrent")]
+            "actor" => Self::actor(
+                std::thread::available_parallelism()
+                    .map(usize::from)
+                    .unwrap_or(1),
+            ),
             _ => {
                 log::warn!(
                     "Unknown engine type: {}, defaulting to standard",
@@ -116,8 +263,440 @@ impl EngineConfig {
     }
 }
 
-/// Information about the engine's current state and capabilities
-#[derive(Debug, Clone)]
+/// Largest estimated input, in rows, for which `EngineConfig::auto_for_profile` keeps
+/// the standard engine (roughly 1 GB of transaction records and IDs)
+pub const AUTO_STANDARD_MAX_ROWS: u64 = 5_000_000;
+/// Disputable transactions kept by the bounded engine chosen for larger inputs
+const AUTO_MAX_DISPUTABLE_TRANSACTIONS: usize = 5_000_000;
+/// Processed transaction IDs kept by the bounded engine chosen for larger inputs
+const AUTO_MAX_PROCESSED_TX_IDS: usize = 20_000_000;
+
+/// How reader-based processing reacts to rows that fail to parse or are rejected
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorPolicy {
+    /// Log the problem and continue with the next row
+    #[default]
+    Skip,
+    /// Stop processing and return the first error
+    Abort,
+}
+
+impl std::str::FromStr for ErrorPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "skip" => Ok(Self::Skip),
+            "abort" => Ok(Self::Abort),
+            other => Err(format!(
+                "unknown error policy '{}' (expected skip or abort)",
+                other
+            )),
+        }
+    }
+}
+
+/// Rows that fail to parse after which reader-based processing fails with
+/// `TooManyParseErrors` instead of skipping them. Written as a count (`100`) or a
+/// percentage of the rows read (`5%`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParseErrorLimit {
+    /// Fail as soon as more than this many rows failed to parse
+    Count(u64),
+    /// Fail at the end of the input if more than this percentage of its rows failed to
+    /// parse
+    Percent(f64),
+}
+
+impl ParseErrorLimit {
+    /// Whether `failed` of `rows` goes over this limit
+    pub fn exceeded_by(&self, failed: u64, rows: u64) -> bool {
+        match *self {
+            Self::Count(max) => failed > max,
+            Self::Percent(max) => failed as f64 * 100.0 > max * rows as f64,
+        }
+    }
+}
+
+impl std::str::FromStr for ParseErrorLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let invalid = || {
+            format!(
+                "invalid parse error limit '{}' (expected a count such as 100 or a percentage such as 5%)",
+                s
+            )
+        };
+        match s.strip_suffix('%') {
+            Some(percent) => match percent.trim().parse::<f64>() {
+                Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(Self::Percent(percent)),
+                _ => Err(invalid()),
+            },
+            None => s.parse().map(Self::Count).map_err(|_| invalid()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ParseErrorLimit {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct LimitVisitor;
+
+        impl serde::de::Visitor<'_> for LimitVisitor {
+            type Value = ParseErrorLimit;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a count such as 100 or a percentage such as \"5%\"")
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, count: u64) -> Result<Self::Value, E> {
+                Ok(ParseErrorLimit::Count(count))
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, count: i64) -> Result<Self::Value, E> {
+                u64::try_from(count)
+                    .map(ParseErrorLimit::Count)
+                    .map_err(|_| E::custom("parse error limit must not be negative"))
+            }
+
+            fn visit_str<E: serde::de::Error>(self, limit: &str) -> Result<Self::Value, E> {
+                limit.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(LimitVisitor)
+    }
+}
+
+/// Rows read and failed by one reader loop, checked against its `ParseErrorLimit`
+#[derive(Debug, Default)]
+pub(crate) struct ParseErrorTally {
+    limit: Option<ParseErrorLimit>,
+    rows: u64,
+    failed: u64,
+}
+
+impl ParseErrorTally {
+    pub(crate) fn new(limit: Option<ParseErrorLimit>) -> Self {
+        Self {
+            limit,
+            ..Self::default()
+        }
+    }
+
+    /// Count a row read
+    pub(crate) fn read(&mut self) {
+        self.rows += 1;
+    }
+
+    /// Count a row that failed to parse; an error once the failures exceed a count limit
+    pub(crate) fn failed(&mut self) -> Result<(), PaymentsError> {
+        self.failed += 1;
+        match self.limit {
+            Some(limit @ ParseErrorLimit::Count(_))
+                if limit.exceeded_by(self.failed, self.rows) =>
+            {
+                Err(self.exceeded())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// An error if the failures over the whole input exceed a percentage limit
+    pub(crate) fn finish(&self) -> Result<(), PaymentsError> {
+        match self.limit {
+            Some(limit @ ParseErrorLimit::Percent(_))
+                if limit.exceeded_by(self.failed, self.rows) =>
+            {
+                Err(self.exceeded())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn exceeded(&self) -> PaymentsError {
+        PaymentsError::TooManyParseErrors {
+            failed: self.failed,
+            rows: self.rows,
+        }
+    }
+}
+
+/// Column names of a transaction file without a header row, in order
+const DEFAULT_COLUMNS: [&str; 9] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "timestamp",
+    "reason_code",
+    "case_ref",
+    "tenant",
+    "idempotency_key",
+];
+
+/// A CSV reader over `reader` in the dialect of `format`
+pub(crate) fn transaction_reader<R: Read>(reader: R, format: &InputFormat) -> csv::Reader<R> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .delimiter(format.delimiter)
+        .has_headers(format.has_headers)
+        .flexible(!format.has_headers)
+        .from_reader(reader)
+}
+
+/// Columns every header row must name
+const REQUIRED_COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+/// The column names of `rdr` with the renames of `format.columns` applied, or
+/// `DEFAULT_COLUMNS` if it has no header row. A header row missing any of
+/// `REQUIRED_COLUMNS` is an `InvalidHeader` error; an empty input has no header to check.
+pub(crate) fn transaction_headers<R: Read>(
+    rdr: &mut csv::Reader<R>,
+    format: &InputFormat,
+) -> Result<csv::StringRecord, PaymentsError> {
+    if !format.has_headers {
+        return Ok(csv::StringRecord::from(DEFAULT_COLUMNS.to_vec()));
+    }
+    let found = rdr.headers()?.clone();
+    let headers: csv::StringRecord = found
+        .iter()
+        .map(|header| format.columns.get(header).map_or(header, String::as_str))
+        .collect();
+    let missing: Vec<&str> = REQUIRED_COLUMNS
+        .into_iter()
+        .filter(|column| !headers.iter().any(|header| header == *column))
+        .collect();
+    if !missing.is_empty() && !found.is_empty() {
+        return Err(PaymentsError::InvalidHeader {
+            missing: missing.join(", "),
+            expected: REQUIRED_COLUMNS.join(", "),
+            found: found.iter().collect::<Vec<_>>().join(", "),
+        });
+    }
+    Ok(headers)
+}
+
+/// The transactions in `rdr`, in order, read with `parse_transaction`, or an
+/// `InvalidHeader` error before any row is read if its header is unusable. Under
+/// `TxIdScope::PerSource`, `rdr` is one source and its IDs are replaced.
+pub(crate) fn read_transactions<R: Read>(
+    rdr: &mut csv::Reader<R>,
+    format: InputFormat,
+) -> Result<impl Iterator<Item = Result<Transaction, PaymentsError>> + '_, PaymentsError> {
+    let headers = transaction_headers(rdr, &format)?;
+    let mut source_ids = SourceTxIds::default();
+    Ok(rdr.records().map(move |record| {
+        let transaction = parse_transaction(&record?, &headers, &format)?;
+        source_ids.assign(transaction, &format.tx_ids)
+    }))
+}
+
+/// Transaction IDs given so far to the rows of one source under `TxIdScope::PerSource`,
+/// by the ID the source used
+#[derive(Debug, Default)]
+pub(crate) struct SourceTxIds(HashMap<TxId, TxId>);
+
+impl SourceTxIds {
+    /// `transaction` with its ID replaced if `scope` is `TxIdScope::PerSource`. Rows must
+    /// be passed in input order so every source ID keeps the ID it was first given.
+    pub(crate) fn assign(
+        &mut self,
+        mut transaction: Transaction,
+        scope: &TxIdScope,
+    ) -> Result<Transaction, PaymentsError> {
+        if let TxIdScope::PerSource(namespaces) = scope {
+            transaction.tx = match self.0.get(&transaction.tx) {
+                Some(tx) => *tx,
+                None => {
+                    let tx = namespaces.assign().ok_or_else(|| {
+                        PaymentsError::InvalidTransaction(
+                            "no transaction IDs left to assign to this source".to_string(),
+                        )
+                    })?;
+                    self.0.insert(transaction.tx, tx);
+                    tx
+                }
+            };
+        }
+        Ok(transaction)
+    }
+}
+
+/// The transaction in `record`, whose columns are named by `headers`, with its `amount`
+/// read in `format.amount_format`. Under `TypeMatching::Strict` a `type` not spelled
+/// exactly as `TransactionType::as_str` is an error naming the value.
+pub(crate) fn parse_transaction(
+    record: &csv::StringRecord,
+    headers: &csv::StringRecord,
+    format: &InputFormat,
+) -> Result<Transaction, PaymentsError> {
+    let column = |name: &str| headers.iter().position(|header| header == name);
+    let transaction: Transaction = match (format.amount_format, column("amount")) {
+        (AmountFormat::Plain, _) | (_, None) => record.deserialize(Some(headers))?,
+        (amount_format, Some(amount_column)) => {
+            let mut fields: Vec<Cow<str>> = record.iter().map(Cow::Borrowed).collect();
+            if let Some(amount) = fields.get_mut(amount_column)
+                && !amount.is_empty()
+            {
+                *amount = Cow::Owned(amount_format.normalize(amount).ok_or_else(|| {
+                    PaymentsError::InvalidTransaction(format!(
+                        "amount '{}' is not in the {:?} format",
+                        amount, amount_format
+                    ))
+                })?);
+            }
+            csv::StringRecord::from(fields).deserialize(Some(headers))?
+        }
+    };
+    if format.type_matching == TypeMatching::Strict
+        && let Some(name) = column("type").and_then(|i| record.get(i))
+        && name != transaction.tx_type.as_str()
+    {
+        return Err(PaymentsError::InvalidTransaction(format!(
+            "transaction type '{}' must be written '{}'",
+            name,
+            transaction.tx_type.as_str()
+        )));
+    }
+    Ok(transaction)
+}
+
+/// Business rules applied to account operations. The default rejects every deposit and
+/// dispute on a locked account, and every dispute the available balance cannot cover.
+///
+/// ```toml
+/// [policy]
+/// allow_deposit_when_locked = true
+/// allow_dispute_when_locked = false
+/// allow_negative_available = true
+/// max_open_disputes = 5
+/// payout_on_close = true
+/// duplicates = "skip_silently"
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnginePolicy {
+    /// Credit deposits (e.g. refunds) to accounts frozen by a chargeback
+    pub allow_deposit_when_locked: bool,
+    /// Hold funds for disputes raised against transactions of a frozen account
+    pub allow_dispute_when_locked: bool,
+    /// Hold the full disputed amount even when that drives `available` below zero, e.g.
+    /// when a deposit is disputed after the funds were already withdrawn
+    pub allow_negative_available: bool,
+    /// Reject further disputes from a client with this many transactions already under
+    /// dispute; unlimited when `None`
+    pub max_open_disputes: Option<usize>,
+    /// Withdraw the remaining available balance when an account is closed, instead of
+    /// leaving it on the closed account
+    pub payout_on_close: bool,
+    /// What to do with a deposit or withdrawal whose ID was already processed
+    pub duplicates: DuplicatePolicy,
+}
+
+/// How a deposit or withdrawal reusing an already processed transaction ID is handled.
+/// Close rows always reject a reused ID.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicatePolicy {
+    /// Reject it
+    #[default]
+    Error,
+    /// Accept it without applying it again when it repeats the original's type, client
+    /// and amount, e.g. a row redelivered by an at-least-once upstream. Reject it when the
+    /// payload differs, or when the original is no longer kept for disputes (charged
+    /// back, expired or evicted) so there is nothing to compare it against.
+    SkipSilently,
+    /// Apply it as a new transaction, replacing the original's record for later disputes.
+    /// The original's effect on the balances stays. Rejected while the original is under
+    /// dispute.
+    Overwrite,
+}
+
+impl DuplicatePolicy {
+    /// Check `transaction`, whose ID was already processed and is not a replay that is
+    /// skipped, against `stored`, the record kept for the original
+    pub fn check(
+        self,
+        transaction: &Transaction,
+        stored: Option<&StoredTransaction>,
+    ) -> Result<(), PaymentsError> {
+        match (self, stored) {
+            (Self::Overwrite, Some(stored)) if stored.disputed => {
+                Err(PaymentsError::TransactionAlreadyDisputed(transaction.tx))
+            }
+            (Self::Overwrite, _) => Ok(()),
+            (Self::SkipSilently, Some(_)) => Err(PaymentsError::InvalidTransaction(format!(
+                "Transaction ID {} already exists with a different payload",
+                transaction.tx
+            ))),
+            _ => Err(PaymentsError::InvalidTransaction(format!(
+                "Transaction ID {} already exists",
+                transaction.tx
+            ))),
+        }
+    }
+}
+
+impl std::str::FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().replace('-', "_").as_str() {
+            "error" => Ok(Self::Error),
+            "skip_silently" => Ok(Self::SkipSilently),
+            "overwrite" => Ok(Self::Overwrite),
+            other => Err(format!(
+                "unknown duplicate policy '{}' (expected error, skip-silently or overwrite)",
+                other
+            )),
+        }
+    }
+}
+
+/// Accounts fetched per call to `PaymentsEngine::accounts_page` by `AccountsIter`
+pub const ACCOUNTS_PAGE_SIZE: usize = 1024;
+
+/// Streams every account from `PaymentsEngine::accounts_iter`. Yields an error and stops
+/// if a page cannot be read.
+#[derive(Debug)]
+pub struct AccountsIter<'a> {
+    engine: &'a PaymentsEngine,
+    offset: usize,
+    page: std::vec::IntoIter<Account>,
+    done: bool,
+}
+
+impl Iterator for AccountsIter<'_> {
+    type Item = Result<Account, PaymentsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(account) = self.page.next() {
+            return Some(Ok(account));
+        }
+        if self.done {
+            return None;
+        }
+        match self.engine.accounts_page(self.offset, ACCOUNTS_PAGE_SIZE) {
+            Ok(page) => {
+                self.offset += page.len();
+                self.done = page.len() < ACCOUNTS_PAGE_SIZE;
+                self.page = page.into_iter();
+                self.page.next().map(Ok)
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Information about the engine's current state and capabilities. Serializes with the
+/// field names below; `Display` renders it as a two-column table for people.
+#[derive(Debug, Clone, Serialize)]
 pub struct EngineInfo {
     pub engine_type: String,
     pub memory_bounded: bool,
@@ -125,13 +704,88 @@ pub struct EngineInfo {
     pub account_count: usize,
     pub transaction_count: Option<usize>,
     pub memory_limits: Option<MemoryLimits>,
+    /// Per-transaction latency percentiles and rolling throughput
+    pub stats: EngineStats,
+    /// Work stealing between the concurrent engine's workers; `None` for the other engines
+    pub rebalancing: Option<RebalanceStats>,
 }
 
-#[derive(Debug, Clone)]
+/// How much work the concurrent engine's workers took over from each other during the
+/// most recent reader-based run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct RebalanceStats {
+    /// Clients an idle worker took over, with their queued records, from a busier one
+    pub steals: usize,
+    /// Records applied by a worker other than their client's home worker
+    pub stolen_records: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct MemoryLimits {
     pub max_accounts: usize,
     pub max_disputable_transactions: usize,
     pub max_processed_tx_ids: usize,
+    /// Per-entry costs used for `estimated_bytes`
+    pub estimates: MemoryEstimates,
+}
+
+impl MemoryLimits {
+    /// Estimated heap use once every cache is full
+    pub fn estimated_bytes(&self) -> usize {
+        self.estimates.total_bytes(
+            self.max_accounts,
+            self.max_disputable_transactions,
+            self.max_processed_tx_ids,
+        )
+    }
+}
+
+impl std::fmt::Display for EngineInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let stats = &self.stats;
+        let mut rows = vec![
+            ("engine", self.engine_type.clone()),
+            ("memory bounded", self.memory_bounded.to_string()),
+            ("concurrent", self.concurrent.to_string()),
+            ("accounts", self.account_count.to_string()),
+            (
+                "transactions in memory",
+                self.transaction_count
+                    .map_or_else(|| "-".to_string(), |count| count.to_string()),
+            ),
+            ("transactions timed", stats.transactions.to_string()),
+            ("latency p50", format!("{:?}", stats.p50)),
+            ("latency p95", format!("{:?}", stats.p95)),
+            ("latency p99", format!("{:?}", stats.p99)),
+            ("latency max", format!("{:?}", stats.max)),
+            ("throughput", format!("{:.0} tx/s", stats.throughput)),
+        ];
+        if let Some(limits) = &self.memory_limits {
+            rows.extend([
+                ("max accounts", limits.max_accounts.to_string()),
+                (
+                    "max disputable transactions",
+                    limits.max_disputable_transactions.to_string(),
+                ),
+                (
+                    "max processed tx ids",
+                    limits.max_processed_tx_ids.to_string(),
+                ),
+                ("estimated bytes", limits.estimated_bytes().to_string()),
+            ]);
+        }
+        if let Some(rebalancing) = &self.rebalancing {
+            rows.extend([
+                ("steals", rebalancing.steals.to_string()),
+                ("stolen records", rebalancing.stolen_records.to_string()),
+            ]);
+        }
+        let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        for (name, value) in rows {
+            writeln!(f, "{:<width$}  {}", name, value)?;
+        }
+        Ok(())
+    }
 }
 
 /// Unified payment engine that wraps different engine implementations
@@ -143,7 +797,14 @@ pub enum PaymentsEngine {
     /// Memory-bounded engine for large datasets
     Bounded(BoundedEngine),
     /// Concurrent engine for high-throughput scenarios
+    #[cfg(feature = "concurrent")]
     Concurrent(ConcurrentEngine),
+    /// Lock-free engine whose clients are split between actor threads
+    #[cfg(feature = "concurrent")]
+    Actor(ActorEngine),
+    /// Standard engine that turns into a bounded one under memory pressure
+    #[cfg(feature = "adaptive")]
+    Adaptive(AdaptiveEngine),
 }
 
 impl PaymentsEngine {
@@ -155,11 +816,14 @@ impl PaymentsEngine {
                 max_accounts,
                 max_disputable_transactions,
                 max_processed_tx_ids,
-            } => Self::Bounded(BoundedEngine::new(
+                policy,
+            } => Self::Bounded(BoundedEngine::with_eviction_policy(
                 max_accounts,
                 max_disputable_transactions,
                 max_processed_tx_ids,
+                policy,
             )),
+            #[cfg(feature = "concurrent")]
             EngineConfig::Concurrent {
                 max_accounts,
                 max_disputable_transactions,
@@ -169,6 +833,12 @@ impl PaymentsEngine {
                 max_disputable_transactions,
                 max_processed_tx_ids,
             )),
+            #[cfg(feature = "concurrent")]
+            EngineConfig::Actor { actors } => Self::Actor(ActorEngine::new(actors)),
+            #[cfg(feature = "adaptive")]
+            EngineConfig::Adaptive { memory_budget_mb } => {
+                Self::Adaptive(AdaptiveEngine::new(memory_budget_mb))
+            }
         }
     }
 
@@ -177,7 +847,31 @@ impl PaymentsEngine {
         match self {
             Self::Standard(engine) => engine.process_transaction(transaction),
             Self::Bounded(engine) => engine.process_transaction(transaction),
+            #[cfg(feature = "concurrent")]
             Self::Concurrent(engine) => engine.process_transaction(transaction),
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => engine.process_transaction(transaction),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => engine.process_transaction(transaction),
+        }
+    }
+
+    /// Apply `transactions` in order, e.g. a batch taken from an embedder's own queue,
+    /// returning each one's result at its index. A rejected transaction does not stop the
+    /// rest of the batch. The concurrent engine takes its lock once for the whole batch.
+    pub fn process_transactions(
+        &mut self,
+        transactions: &[Transaction],
+    ) -> Vec<Result<(), PaymentsError>> {
+        match self {
+            Self::Standard(engine) => engine.process_transactions(transactions),
+            Self::Bounded(engine) => engine.process_transactions(transactions),
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.process_transactions(transactions),
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => engine.process_transactions(transactions),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => engine.process_transactions(transactions),
         }
     }
 
@@ -189,11 +883,259 @@ impl PaymentsEngine {
         match self {
             Self::Standard(engine) => engine.process_transactions_from_reader(reader),
             Self::Bounded(engine) => engine.process_transactions_from_reader(reader),
+            #[cfg(feature = "concurrent")]
             Self::Concurrent(engine) => engine.process_transactions_from_reader(reader),
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => engine.process_transactions_from_reader(reader),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => engine.process_transactions_from_reader(reader),
+        }
+    }
+
+    /// Process several independent transaction streams, e.g. one per TCP connection.
+    /// The concurrent engine reads them in parallel, one thread per stream; the other
+    /// engines process them one after another in order. All transactions for a client
+    /// should arrive on the same stream so their relative order is preserved.
+    ///
+    /// Fails with the first failed stream's error once every stream has finished;
+    /// `ConcurrentEngine::process_concurrent_streams` reports every stream.
+    pub fn process_streams<R: Read + Send + 'static>(
+        &mut self,
+        readers: Vec<R>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => {
+                match engine
+                    .process_concurrent_streams(readers)
+                    .into_iter()
+                    .find_map(|result| result.failure)
+                {
+                    Some(e) => Err(e),
+                    None => Ok(()),
+                }
+            }
+            _ => {
+                for (stream_id, reader) in readers.into_iter().enumerate() {
+                    log::debug!("Processing stream {} sequentially", stream_id);
+                    self.process_transactions_from_reader(reader)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Snapshot of the engine's full state, for checkpointing or moving between engines
+    pub fn export_state(&self) -> Result<EngineState, PaymentsError> {
+        match self {
+            Self::Standard(engine) => Ok(engine.export_state()),
+            Self::Bounded(engine) => Ok(engine.export_state()),
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.export_state(),
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => Ok(engine.export_state()),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => Ok(engine.export_state()),
+        }
+    }
+
+    /// A copy of the client's account, if the engine holds one. Unlike processing a
+    /// transaction, this does not make the account recently used in a bounded engine.
+    pub fn peek_account(&self, client: ClientId) -> Result<Option<Account>, PaymentsError> {
+        match self {
+            Self::Standard(engine) => Ok(engine.peek_account(client).cloned()),
+            Self::Bounded(engine) => Ok(engine.peek_account(client).cloned()),
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.peek_account(client),
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => Ok(engine.peek_account(client).cloned()),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => Ok(engine.peek_account(client).cloned()),
+        }
+    }
+
+    /// Up to `limit` accounts by client ID, skipping the first `offset`, without copying
+    /// the rest of the engine's state. Bounded engines only page through cached accounts.
+    pub fn accounts_page(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<Account>, PaymentsError> {
+        match self {
+            Self::Standard(engine) => Ok(engine.accounts_page(offset, limit)),
+            Self::Bounded(engine) => Ok(engine.accounts_page(offset, limit)),
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.accounts_page(offset, limit),
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => Ok(engine.accounts_page(offset, limit)),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => Ok(engine.accounts_page(offset, limit)),
+        }
+    }
+
+    /// Every account by client ID, fetched `ACCOUNTS_PAGE_SIZE` at a time with
+    /// `accounts_page`, so a concurrent engine is only locked while a page is copied.
+    /// Accounts created or removed between pages may be skipped or repeated.
+    pub fn accounts_iter(&self) -> AccountsIter<'_> {
+        AccountsIter {
+            engine: self,
+            offset: 0,
+            page: Vec::new().into_iter(),
+            done: false,
+        }
+    }
+
+    /// Replace the engine's state with a snapshot from `export_state`
+    pub fn import_state(&mut self, state: EngineState) -> Result<(), PaymentsError> {
+        match self {
+            Self::Standard(engine) => engine.import_state(state),
+            Self::Bounded(engine) => engine.import_state(state),
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.import_state(state)?,
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => engine.import_state(state),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => engine.import_state(state),
+        }
+        Ok(())
+    }
+
+    /// False once a concurrent engine's lock has been poisoned by a panicked thread; see
+    /// `ConcurrentEngine::is_healthy`. The other engines are always healthy.
+    pub fn is_healthy(&self) -> bool {
+        match self {
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.is_healthy(),
+            _ => true,
+        }
+    }
+
+    /// Fold another engine's accounts and transaction records into this one, e.g. to
+    /// combine shards of an input processed separately. See `EngineState::merge` for how
+    /// balances are combined; overlapping transaction IDs are rejected.
+    pub fn merge(&mut self, other: &PaymentsEngine) -> Result<(), PaymentsError> {
+        let mut state = self.export_state()?;
+        state.merge(other.export_state()?)?;
+        self.import_state(state)
+    }
+
+    /// Seed account balances from an accounts CSV in the engine's own output format
+    /// (`client,available,held,total,locked,closed,lock_state,lock_reason`, where the last
+    /// three may be left out), e.g.
+    /// yesterday's closing snapshot. Existing accounts for the same clients are replaced. Returns the number of
+    /// accounts loaded; stops at the first malformed or inconsistent row.
+    pub fn load_accounts<R: Read>(&mut self, reader: R) -> Result<usize, PaymentsError> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+
+        let mut loaded = 0;
+        for (idx, line) in rdr.deserialize().enumerate() {
+            let line_number = idx as u64 + 1;
+            let account: Account = line.map_err(|e| PaymentsError::from(e).at_line(line_number))?;
+            if account.total != account.available + account.held {
+                return Err(PaymentsError::InvalidAccount(format!(
+                    "client {} total {} != available {} + held {}",
+                    account.client, account.total, account.available, account.held
+                ))
+                .at_line(line_number));
+            }
+
+            match self {
+                Self::Standard(engine) => engine.insert_account(account),
+                Self::Bounded(engine) => engine.insert_account(account),
+                #[cfg(feature = "concurrent")]
+                Self::Concurrent(engine) => engine.insert_account(account)?,
+                #[cfg(feature = "concurrent")]
+                Self::Actor(engine) => engine.insert_account(account),
+                #[cfg(feature = "adaptive")]
+                Self::Adaptive(engine) => engine.insert_account(account),
+            }
+            loaded += 1;
+        }
+
+        log::info!("Seeded {} accounts", loaded);
+        Ok(loaded)
+    }
+
+    /// Put the client's account into `lock` with the given reason, e.g. an
+    /// `AdminFreeze` by an operator, creating the account if the engine holds none.
+    /// Replaces any previous lock, so `LockState::Unlocked` lifts it.
+    pub fn set_lock(
+        &mut self,
+        client: ClientId,
+        lock: LockState,
+        reason: Option<String>,
+    ) -> Result<(), PaymentsError> {
+        match self {
+            Self::Standard(engine) => engine.set_lock(client, lock, reason),
+            Self::Bounded(engine) => engine.set_lock(client, lock, reason),
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.set_lock(client, lock, reason)?,
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => engine.set_lock(client, lock, reason),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => engine.set_lock(client, lock, reason),
+        }
+        log::info!("Account {} lock set to {}", client, lock);
+        Ok(())
+    }
+
+    /// Resolve the open dispute of `tx` like a `resolve` row, even when the account's
+    /// lock state rejects resolves (an `AdminFreeze`), e.g. when an operator settles a
+    /// case. The account keeps its lock state and reason.
+    pub fn force_resolve(&mut self, client: ClientId, tx: TxId) -> Result<(), PaymentsError> {
+        let resolve = Transaction::resolve(client, tx);
+        match self {
+            Self::Standard(engine) => engine.force_resolve(&resolve),
+            Self::Bounded(engine) => engine.force_resolve(&resolve),
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.force_resolve(&resolve),
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => engine.force_resolve(&resolve),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => engine.force_resolve(&resolve),
+        }?;
+        log::info!("Dispute of transaction {} force-resolved", tx);
+        Ok(())
+    }
+
+    /// Charge fees and credit interest on every open account, by client ID, as the
+    /// schedule's rules dictate. Each generated withdrawal or deposit gets the next unused
+    /// ID from the schedule's reserved range and is processed like an input row, so it
+    /// can be disputed later. Returns the transactions that were applied; ones the engine
+    /// rejects are logged and skipped.
+    pub fn apply_fee_schedule(
+        &mut self,
+        schedule: &FeeSchedule,
+    ) -> Result<Vec<Transaction>, PaymentsError> {
+        let state = self.export_state()?;
+        let used: HashSet<TxId> = state.processed_tx_ids.into_iter().collect();
+        let mut tx_ids = schedule.tx_ids().filter(|tx| !used.contains(tx));
+        let mut accounts = state.accounts;
+        accounts.sort_unstable_by_key(|account| account.client);
+
+        let mut applied = Vec::new();
+        for account in &accounts {
+            for (tx_type, amount) in schedule.assess(account) {
+                let tx = tx_ids.next().ok_or_else(|| {
+                    PaymentsError::InvalidTransaction(
+                        "No unused transaction IDs left for fees".to_string(),
+                    )
+                })?;
+                let transaction = Transaction::new(tx_type, account.client, tx, Some(amount));
+                match self.process_transaction(&transaction) {
+                    Ok(()) => applied.push(transaction),
+                    Err(e) => log::warn!("Skipping fee transaction {:?}: {}", transaction, e),
+                }
+            }
         }
+        log::info!("Applied {} fee and interest transactions", applied.len());
+        Ok(applied)
     }
 
     /// Process transactions from a CSV file
+    #[cfg(feature = "fs")]
     pub fn process_transactions_from_file(
         &mut self,
         file_path: &std::path::Path,
@@ -208,73 +1150,2765 @@ impl PaymentsEngine {
         &self,
         writer: W,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        match self {
-            Self::Standard(engine) => engine.write_accounts_csv(writer),
-            Self::Bounded(engine) => engine.write_accounts_csv(writer),
-            Self::Concurrent(engine) => engine.write_accounts_csv(writer),
-        }
+        self.write_accounts_csv_filtered(writer, |_| true)
     }
 
-    /// Get engine-specific information
-    pub fn get_engine_info(&self) -> EngineInfo {
+    /// Write the accounts for which `filter` returns true, e.g. `AccountFilter::matches`
+    pub fn write_accounts_csv_filtered<W: std::io::Write>(
+        &self,
+        writer: W,
+        filter: impl Fn(&Account) -> bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
         match self {
-            Self::Standard(engine) => engine.get_engine_info(),
-            Self::Bounded(engine) => engine.get_engine_info(),
-            Self::Concurrent(engine) => engine.get_engine_info(),
+            Self::Standard(engine) => engine.write_accounts_csv_filtered(writer, filter),
+            Self::Bounded(engine) => engine.write_accounts_csv_filtered(writer, filter),
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.write_accounts_csv_filtered(writer, filter),
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => engine.write_accounts_csv_filtered(writer, filter),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => engine.write_accounts_csv_filtered(writer, filter),
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::transaction::{Transaction, TransactionType};
-    use rust_decimal::Decimal;
+    /// Write every account as an Arrow IPC file, see `columnar::write_accounts_ipc`
+    #[cfg(feature = "arrow")]
+    pub fn write_accounts_ipc<W: std::io::Write>(&self, writer: W) -> Result<(), PaymentsError> {
+        crate::columnar::write_accounts_ipc(writer, self.accounts_iter())
+    }
 
-    #[test]
-    fn test_standard_engine() {
-        let mut engine = PaymentsEngine::new(EngineConfig::standard());
-        let tx = Transaction {
-            tx_type: TransactionType::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Some(Decimal::new(1000, 2)), // 10.00
-        };
-        engine.process_transaction(&tx).unwrap();
-        let accounts = engine.get_engine_info().account_count;
-        assert_eq!(accounts, 1);
+    /// Write the deposits and withdrawals kept for disputes as an Arrow IPC file, see
+    /// `columnar::write_ledger_ipc`
+    #[cfg(feature = "arrow")]
+    pub fn write_ledger_ipc<W: std::io::Write>(&self, writer: W) -> Result<(), PaymentsError> {
+        let mut records = self.export_state()?.disputable_transactions;
+        records.sort_unstable_by_key(|(tx, _)| *tx);
+        crate::columnar::write_ledger_ipc(writer, records)
     }
 
-    #[test]
-    fn test_bounded_engine() {
-        let mut engine = PaymentsEngine::new(EngineConfig::bounded(100, 100, 1000));
-        let tx = Transaction {
-            tx_type: TransactionType::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Some(Decimal::new(1000, 2)),
-        };
-        engine.process_transaction(&tx).unwrap();
-        let info = engine.get_engine_info();
-        assert_eq!(info.engine_type, "Bounded");
-        assert!(info.memory_bounded);
-        assert!(!info.concurrent);
+    /// Like `write_accounts_csv_filtered`, but reads the accounts a page at a time through
+    /// `accounts_iter`, so a concurrent engine keeps processing between pages instead of
+    /// waiting for the whole file. Returns the accounts written.
+    pub fn write_accounts_csv_streaming<W: std::io::Write>(
+        &self,
+        writer: W,
+        filter: impl Fn(&Account) -> bool,
+    ) -> Result<usize, PaymentsError> {
+        export::write_accounts_csv_streaming(
+            self.accounts_iter(),
+            writer,
+            self.decimal_format(),
+            filter,
+        )
     }
 
-    #[test]
-    fn test_concurrent_engine() {
-        let engine = PaymentsEngine::new(EngineConfig::concurrent(100, 100, 1000));
-        let info = engine.get_engine_info();
-        assert_eq!(info.engine_type, "Concurrent");
-        assert!(info.memory_bounded);
-        assert!(info.concurrent);
+    /// Like `write_accounts_csv_streaming`, but splits the accounts over `writers` by
+    /// client ID (see `export::shard_of`) and writes each shard on its own thread.
+    /// Returns the accounts written to each shard.
+    pub fn write_accounts_csv_sharded<W: std::io::Write + Send>(
+        &self,
+        writers: Vec<W>,
+        filter: impl Fn(&Account) -> bool,
+    ) -> Result<Vec<usize>, PaymentsError> {
+        export::write_accounts_csv_sharded(
+            self.accounts_iter(),
+            writers,
+            self.decimal_format(),
+            filter,
+        )
     }
 
-    #[test]
-    fn test_memory_config() {
-        let config = EngineConfig::for_memory_mb(100); // 100MB
-        let engine = PaymentsEngine::new(config);
-        let info = engine.get_engine_info();
-        assert!(info.memory_limits.is_some());
+    /// Write only the accounts transactions changed since the previous call (or since the
+    /// engine was created), by client ID, for downstream loaders that apply output as
+    /// upserts. Seeded or imported accounts are left out until a transaction touches them.
+    pub fn write_changed_accounts_csv<W: std::io::Write>(
+        &mut self,
+        writer: W,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.write_changed_accounts_csv_filtered(writer, |_| true)
+    }
+
+    /// Like `write_changed_accounts_csv`, but only changed accounts for which `filter`
+    /// returns true are written. The others still count as written.
+    pub fn write_changed_accounts_csv_filtered<W: std::io::Write>(
+        &mut self,
+        writer: W,
+        filter: impl Fn(&Account) -> bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            Self::Standard(engine) => engine.write_changed_accounts_csv_filtered(writer, filter),
+            Self::Bounded(engine) => engine.write_changed_accounts_csv_filtered(writer, filter),
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.write_changed_accounts_csv_filtered(writer, filter),
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => engine.write_changed_accounts_csv_filtered(writer, filter),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => engine.write_changed_accounts_csv_filtered(writer, filter),
+        }
+    }
+
+    /// Set how balances are written by `write_accounts_csv` and the other account writers
+    pub fn set_decimal_format(&mut self, decimal_format: DecimalFormat) {
+        match self {
+            Self::Standard(engine) => engine.set_decimal_format(decimal_format),
+            Self::Bounded(engine) => engine.set_decimal_format(decimal_format),
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.set_decimal_format(decimal_format),
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => engine.set_decimal_format(decimal_format),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => engine.set_decimal_format(decimal_format),
+        }
+    }
+
+    /// How balances are rounded and padded when accounts are written
+    pub fn decimal_format(&self) -> DecimalFormat {
+        match self {
+            Self::Standard(engine) => engine.decimal_format(),
+            Self::Bounded(engine) => engine.decimal_format(),
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.decimal_format(),
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => engine.decimal_format(),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => engine.decimal_format(),
+        }
+    }
+
+    /// Set how reader-based processing reacts to bad rows and rejected transactions
+    pub fn set_error_policy(&mut self, error_policy: ErrorPolicy) {
+        match self {
+            Self::Standard(engine) => engine.set_error_policy(error_policy),
+            Self::Bounded(engine) => engine.set_error_policy(error_policy),
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.set_error_policy(error_policy),
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => engine.set_error_policy(error_policy),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => engine.set_error_policy(error_policy),
+        }
+    }
+
+    /// Parse failures after which reader-based processing fails; `None` skips any number
+    pub fn set_parse_error_limit(&mut self, limit: Option<ParseErrorLimit>) {
+        match self {
+            Self::Standard(engine) => engine.set_parse_error_limit(limit),
+            Self::Bounded(engine) => engine.set_parse_error_limit(limit),
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.set_parse_error_limit(limit),
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => engine.set_parse_error_limit(limit),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => engine.set_parse_error_limit(limit),
+        }
+    }
+
+    pub fn parse_error_limit(&self) -> Option<ParseErrorLimit> {
+        match self {
+            Self::Standard(engine) => engine.parse_error_limit(),
+            Self::Bounded(engine) => engine.parse_error_limit(),
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.parse_error_limit(),
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => engine.parse_error_limit(),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => engine.parse_error_limit(),
+        }
+    }
+    /// The CSV dialect reader-based processing expects and how it parses the `type` and
+    /// `amount` columns
+    pub fn input_format(&self) -> &InputFormat {
+        match self {
+            Self::Standard(engine) => engine.input_format(),
+            Self::Bounded(engine) => engine.input_format(),
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.input_format(),
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => engine.input_format(),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => engine.input_format(),
+        }
+    }
+
+    /// Set the CSV dialect of reader-based processing and how it parses the `type` and
+    /// `amount` columns
+    pub fn set_input_format(&mut self, input_format: InputFormat) {
+        match self {
+            Self::Standard(engine) => engine.set_input_format(input_format),
+            Self::Bounded(engine) => engine.set_input_format(input_format),
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.set_input_format(input_format),
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => engine.set_input_format(input_format),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => engine.set_input_format(input_format),
+        }
+    }
+
+    /// IDs of the client's transactions that are currently under dispute, in ascending order
+    pub fn open_disputes(&self, client: ClientId) -> Vec<TxId> {
+        match self {
+            Self::Standard(engine) => engine.open_disputes(client),
+            Self::Bounded(engine) => engine.open_disputes(client),
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.open_disputes(client),
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => engine.open_disputes(client),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => engine.open_disputes(client),
+        }
+    }
+
+    /// Run `validator` on every transaction before it is applied, after the validators
+    /// already added. A rejection is returned like any other processing error.
+    pub fn add_validator(&mut self, validator: impl TransactionValidator + 'static) {
+        match self {
+            Self::Standard(engine) => engine.add_validator(validator),
+            Self::Bounded(engine) => engine.add_validator(validator),
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.add_validator(validator),
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => engine.add_validator(validator),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => engine.add_validator(validator),
+        }
+    }
+
+    /// Notify `observer` after every transaction this engine processes, accepted or
+    /// rejected. Use `observer::ChannelObserver` to handle events on another thread.
+    pub fn add_observer(&mut self, observer: impl EngineObserver + 'static) {
+        match self {
+            Self::Standard(engine) => engine.add_observer(observer),
+            Self::Bounded(engine) => engine.add_observer(observer),
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.add_observer(observer),
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => engine.add_observer(observer),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => engine.add_observer(observer),
+        }
+    }
+
+    /// Stream every account change made from now on to the returned receiver, e.g. to
+    /// keep a cache or websocket clients up to date without polling
+    /// `write_accounts_csv`. Dropping the receiver stops delivery but not processing.
+    pub fn subscribe_account_changes(&mut self) -> std::sync::mpsc::Receiver<AccountDelta> {
+        let (observer, receiver) = AccountChangeObserver::new();
+        self.add_observer(observer);
+        receiver
+    }
+
+    /// Replace the business rules applied to account operations
+    pub fn set_policy(&mut self, policy: EnginePolicy) {
+        match self {
+            Self::Standard(engine) => engine.set_policy(policy),
+            Self::Bounded(engine) => engine.set_policy(policy),
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.set_policy(policy),
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => engine.set_policy(policy),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => engine.set_policy(policy),
+        }
+    }
+
+    /// The business rules applied to account operations
+    pub fn policy(&self) -> Result<EnginePolicy, PaymentsError> {
+        match self {
+            Self::Standard(engine) => Ok(engine.policy()),
+            Self::Bounded(engine) => Ok(engine.policy()),
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.policy(),
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => Ok(engine.policy()),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => Ok(engine.policy()),
+        }
+    }
+
+    /// Check every account against the balance invariants; see `invariants`
+    pub fn verify_invariants(&self) -> Result<InvariantReport, PaymentsError> {
+        let allow_negative = self.policy()?.allow_negative_available;
+        let mut report = InvariantReport::default();
+        for account in self.accounts_iter() {
+            report
+                .violations
+                .extend(InvariantViolation::check(&account?, allow_negative));
+            report.accounts_checked += 1;
+        }
+        Ok(report)
+    }
+
+    /// Like `verify_invariants`, but also rewrite each mismatched total as
+    /// `available + held`. The report lists the violations found before the repair.
+    pub fn repair_invariants(&mut self) -> Result<InvariantReport, PaymentsError> {
+        let mut report = self.verify_invariants()?;
+        for violation in &report.violations {
+            let InvariantViolation::TotalMismatch { client, .. } = *violation else {
+                continue;
+            };
+            let Some(mut account) = self.peek_account(client)? else {
+                continue;
+            };
+            account.total = account.available + account.held;
+            log::warn!("Repaired total of client {} to {}", client, account.total);
+            match self {
+                Self::Standard(engine) => engine.insert_account(account),
+                Self::Bounded(engine) => engine.insert_account(account),
+                #[cfg(feature = "concurrent")]
+                Self::Concurrent(engine) => engine.insert_account(account)?,
+                #[cfg(feature = "concurrent")]
+                Self::Actor(engine) => engine.insert_account(account),
+                #[cfg(feature = "adaptive")]
+                Self::Adaptive(engine) => engine.insert_account(account),
+            }
+            report.repaired += 1;
+        }
+        Ok(report)
+    }
+
+    /// Expand `schedule`'s recurring transactions as processing reaches their timestamps,
+    /// replacing any schedule set before. Fails if an entry is invalid.
+    pub fn set_recurring_schedule(
+        &mut self,
+        schedule: RecurringSchedule,
+    ) -> Result<(), PaymentsError> {
+        let recurring = RecurringScheduler::new(schedule)?;
+        match self {
+            Self::Standard(engine) => engine.set_recurring(recurring),
+            Self::Bounded(engine) => engine.set_recurring(recurring),
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.set_recurring(recurring),
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => engine.set_recurring(recurring),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => engine.set_recurring(recurring),
+        }
+        Ok(())
+    }
+
+    /// Drop disputable transactions once `window` ends, replacing any window set before.
+    /// Later disputes of them are rejected with `PaymentsError::DisputeWindowExpired`.
+    /// Transactions already stored are not affected. Fails if a limit is zero.
+    pub fn set_dispute_window(&mut self, window: DisputeWindow) -> Result<(), PaymentsError> {
+        let expiry = DisputeExpiry::new(window)?;
+        match self {
+            Self::Standard(engine) => engine.set_dispute_expiry(expiry),
+            Self::Bounded(engine) => engine.set_dispute_expiry(expiry),
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.set_dispute_expiry(expiry),
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => engine.set_dispute_expiry(expiry),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => engine.set_dispute_expiry(expiry),
+        }
+        Ok(())
+    }
+
+    /// Stop reader-based processing at the next record boundary once `shutdown` is requested
+    pub fn set_shutdown_flag(&mut self, shutdown: ShutdownFlag) {
+        match self {
+            Self::Standard(engine) => engine.set_shutdown_flag(shutdown),
+            Self::Bounded(engine) => engine.set_shutdown_flag(shutdown),
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.set_shutdown_flag(shutdown),
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => engine.set_shutdown_flag(shutdown),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => engine.set_shutdown_flag(shutdown),
+        }
+    }
+
+    /// Wait until every transaction queued on the engine has been applied.
+    /// Only the concurrent engine processes streams in the background; the other engines
+    /// apply transactions before returning and have nothing to drain.
+    pub fn drain(&self) {
+        #[cfg(feature = "concurrent")]
+        if let Self::Concurrent(engine) = self {
+            engine.drain();
+        }
+    }
+
+    /// Set the number of worker threads used by the concurrent engine, or of actors
+    /// used by the actor engine. Other engines are single-threaded and ignore this.
+    pub fn set_num_workers(&mut self, num_workers: usize) {
+        #[cfg(feature = "concurrent")]
+        match self {
+            Self::Concurrent(engine) => engine.set_num_workers(num_workers),
+            Self::Actor(engine) => engine.set_num_actors(num_workers),
+            _ => {}
+        }
+        #[cfg(not(feature = "concurrent"))]
+        let _ = num_workers;
+    }
+
+    /// Per-worker activity of the concurrent engine's last reader-based run.
+    /// Empty for the single-threaded engines.
+    #[cfg(feature = "concurrent")]
+    pub fn get_worker_stats(&self) -> Vec<concurrent::WorkerStats> {
+        match self {
+            Self::Concurrent(engine) => engine.get_worker_stats(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Per-stage activity of the concurrent engine's last reader-based run; `None` for
+    /// the other engines
+    #[cfg(feature = "concurrent")]
+    pub fn pipeline_stats(&self) -> Option<pipeline::PipelineStats> {
+        match self {
+            Self::Concurrent(engine) => Some(engine.pipeline_stats()),
+            _ => None,
+        }
+    }
+
+    /// Start recording the order in which transactions are applied.
+    /// Only the concurrent engine interleaves work nondeterministically; the other
+    /// engines always apply records in input order and ignore this.
+    pub fn enable_schedule_recording(&mut self) {
+        #[cfg(feature = "concurrent")]
+        if let Self::Concurrent(engine) = self {
+            engine.enable_schedule_recording();
+        }
+    }
+
+    /// Take the schedule recorded since `enable_schedule_recording`, if any.
+    pub fn take_schedule(&mut self) -> Option<Schedule> {
+        match self {
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.take_schedule(),
+            _ => None,
+        }
+    }
+
+    /// Apply the records from `reader` one at a time in the order given by `schedule`,
+    /// reproducing a recorded concurrent run exactly. The whole input is buffered in
+    /// memory, so this is meant for debugging rather than production volumes.
+    pub fn replay_transactions_from_reader<R: Read>(
+        &mut self,
+        reader: R,
+        schedule: &Schedule,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut rdr = transaction_reader(reader, self.input_format());
+        let records: Vec<Option<Transaction>> =
+            read_transactions(&mut rdr, self.input_format().clone())?
+                .map(Result::ok)
+                .collect();
+
+        log::debug!(
+            "Replaying {} scheduled transactions from {} input records",
+            schedule.entries.len(),
+            records.len()
+        );
+
+        for entry in &schedule.entries {
+            let Some(Some(transaction)) = records.get(entry.seq) else {
+                log::error!(
+                    "Schedule refers to missing or unparseable record {}",
+                    entry.seq
+                );
+                continue;
+            };
+
+            if let Err(e) = self.process_transaction(transaction) {
+                log::error!(
+                    "Replay (worker {}): Failed to process transaction {:?}: {}",
+                    entry.worker,
+                    transaction,
+                    e
+                );
+            } else {
+                log::debug!(
+                    "Replay (worker {}): Successfully processed transaction: {:?}",
+                    entry.worker,
+                    transaction
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Get engine-specific information
+    pub fn get_engine_info(&self) -> EngineInfo {
+        match self {
+            Self::Standard(engine) => engine.get_engine_info(),
+            Self::Bounded(engine) => engine.get_engine_info(),
+            #[cfg(feature = "concurrent")]
+            Self::Concurrent(engine) => engine.get_engine_info(),
+            #[cfg(feature = "concurrent")]
+            Self::Actor(engine) => engine.get_engine_info(),
+            #[cfg(feature = "adaptive")]
+            Self::Adaptive(engine) => engine.get_engine_info(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::AccountFilter;
+    use crate::transaction::{Amount, DisputeCase, Transaction, TransactionType};
+    use rust_decimal::Decimal;
+    use validation::{ClientBlocklist, MaxAmount, MaxPrecision};
+
+    /// One small configuration per engine type compiled into this build
+    fn small_engine_configs() -> Vec<EngineConfig> {
+        vec![
+            EngineConfig::standard(),
+            EngineConfig::bounded(10, 10, 10),
+            EngineConfig::bounded(10, 10, 10).with_eviction_policy(EvictionPolicy::Lfu),
+            EngineConfig::bounded(10, 10, 10).with_eviction_policy(EvictionPolicy::Fifo),
+            EngineConfig::bounded(10, 10, 10).with_eviction_policy(EvictionPolicy::Segmented),
+            #[cfg(feature = "concurrent")]
+            EngineConfig::concurrent(10, 10, 10),
+            #[cfg(feature = "concurrent")]
+            EngineConfig::actor(2),
+            #[cfg(feature = "adaptive")]
+            EngineConfig::adaptive(1024 * 1024),
+        ]
+    }
+
+    #[test]
+    fn test_process_transactions() {
+        let batch = vec![
+            Transaction::deposit(1, 1, Amount::new(10, 0)),
+            Transaction::withdrawal(1, 2, Amount::new(20, 0)),
+            Transaction::deposit(2, 3, Amount::new(5, 0)),
+            Transaction::deposit(2, 3, Amount::new(5, 0)),
+            Transaction::dispute(1, 1),
+            Transaction::withdrawal(1, 4, Amount::new(1, 0)),
+        ];
+
+        for config in small_engine_configs() {
+            let mut engine = PaymentsEngine::new(config);
+            let results = engine.process_transactions(&batch);
+            let codes: Vec<Option<u16>> = results
+                .iter()
+                .map(|result| result.as_ref().err().map(PaymentsError::code))
+                .collect();
+            assert_eq!(
+                codes,
+                vec![None, Some(202), None, Some(103), None, Some(202)],
+                "{:?}",
+                engine.get_engine_info().engine_type
+            );
+            let account = engine.peek_account(1.into()).unwrap().unwrap();
+            assert_eq!(account.held, Amount::new(10, 0));
+            assert_eq!(account.available, Amount::ZERO);
+            assert!(engine.process_transactions(&[]).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_idempotency_keys() {
+        let input = "type,client,tx,amount,idempotency_key\n\
+                     deposit,1,1,10,k1\n\
+                     deposit,1,2,10,k1\n\
+                     deposit,1,3,100,\n\
+                     withdrawal,1,4,50,k2\n\
+                     withdrawal,1,5,50,k2\n\
+                     deposit,1,2,1,\n";
+        let keyed = |mut transaction: Transaction, key: &str| {
+            transaction.idempotency_key = Some(key.to_string());
+            transaction
+        };
+
+        for config in small_engine_configs() {
+            let mut engine = PaymentsEngine::new(config.clone());
+            engine.set_num_workers(2);
+            engine
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap();
+            // Resubmissions are accepted without being applied, so tx 2 is still free
+            let account = engine.peek_account(1.into()).unwrap().unwrap();
+            assert_eq!(account.total, Amount::new(61, 0));
+
+            let results = engine.process_transactions(&[
+                keyed(Transaction::deposit(1, 6, Amount::new(20, 0)), "k1"),
+                keyed(Transaction::withdrawal(1, 7, Amount::new(500, 0)), "k3"),
+                keyed(Transaction::withdrawal(1, 8, Amount::new(1, 0)), "k3"),
+                keyed(Transaction::withdrawal(1, 9, Amount::new(1, 0)), "k3"),
+                keyed(Transaction::deposit(2, 10, Amount::new(20, 0)), "k1"),
+            ]);
+            let codes: Vec<Option<u16>> = results
+                .iter()
+                .map(|result| result.as_ref().err().map(PaymentsError::code))
+                .collect();
+            // A rejected transaction does not claim its key
+            assert_eq!(codes, vec![Some(108), Some(202), None, None, None]);
+
+            // Keys survive a snapshot
+            let mut restored = PaymentsEngine::new(config);
+            restored
+                .import_state(engine.export_state().unwrap())
+                .unwrap();
+            let retry = keyed(Transaction::deposit(1, 11, Amount::new(10, 0)), "k1");
+            restored.process_transaction(&retry).unwrap();
+            let changed = keyed(Transaction::deposit(2, 12, Amount::new(1, 0)), "k1");
+            assert_eq!(
+                restored.process_transaction(&changed).unwrap_err().code(),
+                108
+            );
+            let account = restored.peek_account(1.into()).unwrap().unwrap();
+            assert_eq!(account.total, Amount::new(60, 0));
+        }
+    }
+
+    #[test]
+    fn test_standard_engine() {
+        let mut engine = PaymentsEngine::new(EngineConfig::standard());
+        let tx = Transaction::deposit(1, 1, Amount::new(1000, 2)); // 10.00
+        engine.process_transaction(&tx).unwrap();
+        let accounts = engine.get_engine_info().account_count;
+        assert_eq!(accounts, 1);
+    }
+
+    #[test]
+    fn test_bounded_engine() {
+        let mut engine = PaymentsEngine::new(EngineConfig::bounded(100, 100, 1000));
+        let tx = Transaction::deposit(1, 1, Amount::new(1000, 2));
+        engine.process_transaction(&tx).unwrap();
+        let info = engine.get_engine_info();
+        assert_eq!(info.engine_type, "Bounded");
+        assert!(info.memory_bounded);
+        assert!(!info.concurrent);
+
+        let table = info.to_string();
+        let lines: Vec<_> = table.lines().collect();
+        assert_eq!(lines[0], "engine                       Bounded");
+        assert!(lines.contains(&"max accounts                 100"));
+        assert!(!table.contains("steals"));
+    }
+
+    #[test]
+    fn test_transaction_stores() {
+        use store::BitmapStore;
+
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10\n\
+                     deposit,2,5000,7\n\
+                     withdrawal,1,2,3\n\
+                     dispute,1,1,\n\
+                     resolve,1,1,\n\
+                     dispute,2,5000,\n\
+                     chargeback,2,5000,\n\
+                     dispute,2,5000,\n";
+        let balances = |accounts: Vec<Option<&Account>>| {
+            accounts
+                .into_iter()
+                .map(|account| account.map(|account| (account.total, account.is_locked())))
+                .collect::<Vec<_>>()
+        };
+        let expected = vec![Some((Amount::new(7, 0), false)), Some((Amount::ZERO, true))];
+
+        let mut standard = StandardEngine::new().with_store(BitmapStore::default());
+        standard
+            .process_transactions_from_reader(input.as_bytes())
+            .unwrap();
+        assert_eq!(
+            balances(vec![
+                standard.peek_account(1.into()),
+                standard.peek_account(2.into())
+            ]),
+            expected
+        );
+
+        let mut bounded = BoundedEngine::new(10, 10, 10).with_store(BitmapStore::default());
+        bounded
+            .process_transactions_from_reader(input.as_bytes())
+            .unwrap();
+        assert_eq!(
+            balances(vec![
+                bounded.peek_account(1.into()),
+                bounded.peek_account(2.into())
+            ]),
+            expected
+        );
+
+        #[cfg(feature = "fs")]
+        {
+            let path = std::env::temp_dir().join(format!("disk-store-{}", std::process::id()));
+            let store = store::DiskStore::create(&path).unwrap();
+            let mut standard = StandardEngine::new().with_store(store);
+            standard
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap();
+            assert_eq!(
+                balances(vec![
+                    standard.peek_account(1.into()),
+                    standard.peek_account(2.into())
+                ]),
+                expected
+            );
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "concurrent")]
+    fn test_concurrent_engine() {
+        let engine = PaymentsEngine::new(EngineConfig::concurrent(100, 100, 1000));
+        let info = engine.get_engine_info();
+        assert_eq!(info.engine_type, "Concurrent");
+        assert!(info.memory_bounded);
+        assert!(info.concurrent);
+    }
+
+    #[test]
+    #[cfg(all(
+        feature = "concurrent",
+        any(feature = "benchmark", feature = "testing")
+    ))]
+    fn test_schedule_replay() {
+        use crate::testing::{final_accounts, transaction_sequence_strategy};
+        use proptest::strategy::{Strategy, ValueTree};
+        use proptest::test_runner::TestRunner;
+
+        let transactions = transaction_sequence_strategy(500, 20)
+            .new_tree(&mut TestRunner::deterministic())
+            .unwrap()
+            .current();
+        let csv_data = crate::PaymentEngineBenchmark::transactions_to_csv(&transactions);
+
+        let mut recorded = PaymentsEngine::new(EngineConfig::concurrent(1000, 1000, 1000));
+        recorded.enable_schedule_recording();
+        recorded
+            .process_transactions_from_reader(csv_data.as_bytes())
+            .unwrap();
+        let schedule = recorded.take_schedule().unwrap();
+        assert_eq!(schedule.entries.len(), transactions.len());
+
+        let mut written = Vec::new();
+        schedule.write_csv(&mut written).unwrap();
+        let schedule = Schedule::from_reader(written.as_slice()).unwrap();
+
+        let mut replayed = PaymentsEngine::new(EngineConfig::standard());
+        replayed
+            .replay_transactions_from_reader(csv_data.as_bytes(), &schedule)
+            .unwrap();
+        assert_eq!(final_accounts(&recorded), final_accounts(&replayed));
+    }
+
+    #[test]
+    #[cfg(feature = "concurrent")]
+    fn test_recover_from_poisoned_lock() {
+        /// Panics while the worker holds the engine lock
+        #[derive(Debug)]
+        struct PanickingObserver;
+
+        impl EngineObserver for PanickingObserver {
+            fn on_accepted(&self, transaction: &Transaction) {
+                assert_ne!(transaction.tx, TxId::new(2), "observer failed");
+            }
+        }
+
+        let mut engine = PaymentsEngine::new(EngineConfig::concurrent(100, 100, 1000));
+        engine
+            .process_transaction(&Transaction::deposit(1, 1, Amount::ONE))
+            .unwrap();
+        let checkpoint = engine.export_state().unwrap();
+        engine.add_observer(PanickingObserver);
+
+        let deposit = Transaction::deposit(1, 2, Amount::ONE);
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            engine.process_transaction(&deposit)
+        }));
+        assert!(panicked.is_err());
+        assert!(!engine.is_healthy());
+        let error = engine
+            .process_transaction(&Transaction::deposit(1, 3, Amount::ONE))
+            .unwrap_err();
+        assert!(matches!(error, PaymentsError::LockPoisoned("engine")));
+        assert_eq!(
+            error.category(),
+            crate::errors::ErrorCategory::InternalError
+        );
+
+        engine.import_state(checkpoint).unwrap();
+        assert!(engine.is_healthy());
+        engine
+            .process_transaction(&Transaction::deposit(1, 3, Amount::ONE))
+            .unwrap();
+        assert_eq!(engine.export_state().unwrap().processed_tx_ids.len(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "concurrent")]
+    fn test_drain_waits_for_streams() {
+        let engine = ConcurrentEngine::new(1000, 1000, 1000);
+        for stream_id in 0..4u16 {
+            let mut input = String::from("type,client,tx,amount\n");
+            for i in 0..100u32 {
+                input.push_str(&format!(
+                    "deposit,{},{},1.0\n",
+                    stream_id,
+                    u32::from(stream_id) * 1000 + i
+                ));
+            }
+            // Handles are dropped on purpose; drain must still wait for the threads
+            drop(engine.process_stream_transactions(std::io::Cursor::new(input), stream_id.into()));
+        }
+
+        engine.drain();
+        let state = engine.export_state().unwrap();
+        assert_eq!(state.accounts.len(), 4);
+        assert_eq!(state.processed_tx_ids.len(), 400);
+    }
+
+    #[test]
+    #[cfg(feature = "concurrent")]
+    fn test_concurrent_stream_results() {
+        let streams = [
+            "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,1,2,1.0\n",
+            "type,client,tx,amount\ndeposit,2,3,5.0\nwithdrawal,2,4,9.0\ndeposit,2,x,1.0\n",
+            "kind,client,tx,amount\ndeposit,3,5,5.0\n",
+        ];
+        let engine = ConcurrentEngine::new(1000, 1000, 1000);
+        let results =
+            engine.process_concurrent_streams(streams.iter().map(|s| s.as_bytes()).collect());
+
+        let counts: Vec<_> = results
+            .iter()
+            .map(|result| (result.stream_id, result.processed, result.errors))
+            .collect();
+        assert_eq!(counts, [(0, 2, 0), (1, 1, 2), (2, 0, 0)]);
+        assert!(results[..2].iter().all(|result| result.failure.is_none()));
+        let failure = results[2].failure.as_ref().unwrap();
+        assert!(matches!(
+            failure.downcast_ref::<PaymentsError>(),
+            Some(PaymentsError::InvalidHeader { .. })
+        ));
+
+        let mut engine = PaymentsEngine::new(EngineConfig::concurrent(1000, 1000, 1000));
+        let error = engine
+            .process_streams(streams.iter().map(|s| s.as_bytes()).collect())
+            .unwrap_err();
+        assert!(
+            error.to_string().starts_with("Invalid input header"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "concurrent")]
+    fn test_authenticated_streams() {
+        use auth::{ClientScope, Credentials, Identity, StaticAuthenticator};
+        use observer::{ChannelObserver, EngineEvent};
+
+        let authenticator = StaticAuthenticator::new().with_api_key(
+            "merchant-key",
+            Identity::new("merchant", ClientScope::Only([ClientId::new(1)].into())),
+        );
+        let mut engine = ConcurrentEngine::new(1000, 1000, 1000);
+        let (observer, events) = ChannelObserver::new();
+        engine.add_observer(observer);
+
+        let input = "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,2,2,7.0\ndeposit,1,3,1.0\n";
+        engine
+            .process_authenticated_stream(
+                input.as_bytes(),
+                0,
+                &Credentials::ApiKey("merchant-key".into()),
+                &authenticator,
+            )
+            .unwrap()
+            .join()
+            .unwrap()
+            .unwrap();
+        let error = engine
+            .process_authenticated_stream(
+                input.as_bytes(),
+                1,
+                &Credentials::ApiKey("stolen".into()),
+                &authenticator,
+            )
+            .unwrap_err();
+        assert!(matches!(error, PaymentsError::Unauthenticated(_)));
+
+        // Only client 1's rows were applied
+        let state = engine.export_state().unwrap();
+        assert_eq!(state.accounts.len(), 1);
+        assert_eq!(state.accounts[0].total, Amount::new(6, 0));
+
+        let audit: Vec<_> = events
+            .try_iter()
+            .filter_map(|event| match event {
+                EngineEvent::Unauthorized(identity, transaction) => {
+                    Some(format!("{} {}", identity, transaction.tx))
+                }
+                EngineEvent::AuthenticationFailed(method, _) => Some(method.to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(audit, ["merchant 2", "api_key"]);
+    }
+
+    #[test]
+    #[cfg(feature = "concurrent")]
+    fn test_fault_injection() {
+        use chaos::FaultInjector;
+
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx in 1..=500u32 {
+            input.push_str(&format!("deposit,{},{},1.0\n", tx % 20 + 1, tx));
+        }
+        let run = |faults| {
+            let mut engine = ConcurrentEngine::new(1000, 1000, 1000);
+            engine.set_num_workers(4);
+            engine.set_fault_injector(Some(faults));
+            let result = engine.process_transactions_from_reader(input.as_bytes());
+            (engine, result)
+        };
+
+        // Delays reorder the workers but lose nothing
+        let (engine, result) = run(FaultInjector {
+            delay_rate: 0.1,
+            ..FaultInjector::default()
+        });
+        result.unwrap();
+        assert_eq!(engine.export_state().unwrap().processed_tx_ids.len(), 500);
+
+        let dropping = FaultInjector {
+            drop_rate: 0.05,
+            ..FaultInjector::default()
+        };
+        let panicking = FaultInjector {
+            panic_rate: 0.01,
+            ..FaultInjector::default()
+        };
+        for (faults, reason) in [(dropping, "dropped"), (panicking, "panicked")] {
+            let (engine, result) = run(faults);
+            let error = result.unwrap_err();
+            let Some(PaymentsError::WorkerFailed {
+                lost,
+                unsent,
+                reason: why,
+            }) = error.downcast_ref::<PaymentsError>()
+            else {
+                panic!("expected a worker failure, got {}", error);
+            };
+            assert!(why.contains(reason), "{}", why);
+            assert!(*lost > 0);
+            // Every row is either applied, lost by a worker or never sent
+            let applied = engine.export_state().unwrap().processed_tx_ids.len() as u64;
+            assert_eq!(applied + lost + unsent, 500);
+        }
+    }
+
+    #[test]
+    #[cfg(all(
+        feature = "concurrent",
+        any(feature = "benchmark", feature = "testing")
+    ))]
+    fn test_worker_pool_resize_keeps_results() {
+        use crate::testing::{final_accounts, transaction_sequence_strategy};
+        use proptest::strategy::{Strategy, ValueTree};
+        use proptest::test_runner::TestRunner;
+
+        /// Resizes the pool to the next size each time another `step` bytes have been read
+        struct ResizingReader<'a> {
+            data: &'a [u8],
+            read: usize,
+            step: usize,
+            sizes: Vec<usize>,
+            pool: concurrent::WorkerPool,
+        }
+
+        impl Read for ResizingReader<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                if self.read >= self.step * (4 - self.sizes.len())
+                    && let Some(size) = self.sizes.pop()
+                {
+                    self.pool.resize(size);
+                }
+                let n = buf.len().min(64).min(self.data.len() - self.read);
+                buf[..n].copy_from_slice(&self.data[self.read..self.read + n]);
+                self.read += n;
+                Ok(n)
+            }
+        }
+
+        // Long enough that each resize is seen by the dispatcher before the next one, even
+        // though the read stage runs a few batches ahead of it
+        let transactions = transaction_sequence_strategy(20_000, 50)
+            .new_tree(&mut TestRunner::deterministic())
+            .unwrap()
+            .current();
+        let csv_data = crate::PaymentEngineBenchmark::transactions_to_csv(&transactions);
+
+        let mut expected = PaymentsEngine::new(EngineConfig::standard());
+        expected
+            .process_transactions_from_reader(csv_data.as_bytes())
+            .unwrap();
+
+        let mut engine = ConcurrentEngine::new(1000, 10_000, 10_000);
+        engine.set_num_workers(4);
+        let reader = ResizingReader {
+            data: csv_data.as_bytes(),
+            read: 0,
+            step: csv_data.len() / 4,
+            sizes: vec![3, 1, 6, 2],
+            pool: engine.worker_pool(),
+        };
+        engine.process_transactions_from_reader(reader).unwrap();
+        assert_eq!(engine.worker_pool().size(), Some(3));
+
+        let stats = engine.get_worker_stats();
+        assert_eq!(stats.len(), 6);
+        let applied: usize = stats.iter().map(|s| s.processed + s.errors).sum();
+        assert_eq!(applied, transactions.len());
+        assert!(stats.iter().all(|s| s.queue_depth == 0));
+
+        let resized = PaymentsEngine::Concurrent(engine);
+        assert_eq!(final_accounts(&expected), final_accounts(&resized));
+    }
+
+    #[test]
+    #[cfg(all(
+        feature = "concurrent",
+        any(feature = "benchmark", feature = "testing")
+    ))]
+    fn test_pipeline_stages() {
+        use crate::testing::final_accounts;
+
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx in 1..=2000u32 {
+            input.push_str(&format!("deposit,{},{},1.0\n", tx % 7 + 1, tx));
+        }
+        input.push_str("bogus,1,2001,1.0\n");
+        input.push_str("withdrawal,1,2002,1.0\n");
+
+        let mut expected = PaymentsEngine::new(EngineConfig::standard());
+        expected
+            .process_transactions_from_reader(input.as_bytes())
+            .unwrap();
+
+        let mut engine = ConcurrentEngine::new(100, 10_000, 10_000);
+        engine.set_num_workers(3);
+        engine.set_parse_threads(4);
+        engine
+            .process_transactions_from_reader(input.as_bytes())
+            .unwrap();
+
+        let stats = engine.pipeline_stats();
+        assert_eq!(stats.parse_threads, 4);
+        assert_eq!(stats.read.rows, 2002);
+        assert_eq!(stats.parse.rows, 2002);
+        // The bad row never reaches a worker
+        assert_eq!(stats.apply.rows, 2001);
+        assert!(stats.read.busy > std::time::Duration::ZERO);
+        assert!(stats.parse.busy > std::time::Duration::ZERO);
+        assert!(stats.apply.busy > std::time::Duration::ZERO);
+
+        // Batches parsed out of order are applied in input order
+        let engine = PaymentsEngine::Concurrent(engine);
+        assert_eq!(final_accounts(&expected), final_accounts(&engine));
+        assert_eq!(
+            engine.pipeline_stats().map(|stats| stats.apply.rows),
+            Some(2001)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "concurrent")]
+    fn test_work_stealing() {
+        use chaos::FaultInjector;
+        use routing::Router;
+
+        /// Sends every client to worker 0, leaving the others with nothing but stealing
+        #[derive(Debug)]
+        struct FirstWorker;
+
+        impl Router for FirstWorker {
+            fn route(&self, _client: ClientId, _num_workers: usize) -> usize {
+                0
+            }
+        }
+
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx in 1..=400u32 {
+            input.push_str(&format!("deposit,{},{},1.0\n", tx % 8 + 1, tx));
+        }
+        let mut engine = ConcurrentEngine::new(1000, 1000, 1000);
+        engine.set_num_workers(4);
+        engine.set_router(FirstWorker);
+        engine.set_fault_injector(Some(FaultInjector {
+            delay_rate: 0.5,
+            ..FaultInjector::default()
+        }));
+        engine
+            .process_transactions_from_reader(input.as_bytes())
+            .unwrap();
+
+        let stats = engine.get_worker_stats();
+        assert_eq!(stats[0].accounts, 8);
+        assert!(stats[1..].iter().any(|s| s.processed > 0));
+        let rebalancing = engine.get_engine_info().rebalancing.unwrap();
+        assert!(rebalancing.steals > 0);
+        assert_eq!(
+            rebalancing.stolen_records,
+            stats[1..].iter().map(|s| s.processed).sum::<usize>()
+        );
+        for client in 1..=8u16 {
+            let account = engine.peek_account(client.into()).unwrap().unwrap();
+            assert_eq!(account.available, Amount::new(50, 0));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "adaptive")]
+    fn test_adaptive_switches_to_bounded() {
+        let input = "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,2,2,3.0\n";
+        let mut engine = AdaptiveEngine::new(16);
+        engine
+            .process_transactions_from_reader(input.as_bytes())
+            .unwrap();
+        assert!(!engine.is_bounded());
+        assert_eq!(engine.get_engine_info().engine_type, "Adaptive(Standard)");
+
+        engine.switch_to_bounded();
+        assert!(engine.is_bounded());
+        let info = engine.get_engine_info();
+        assert_eq!(info.engine_type, "Adaptive(Bounded)");
+        assert_eq!(info.account_count, 2);
+
+        // Duplicate detection and disputes still see the transactions from before
+        let duplicate = Transaction::deposit(1, 1, Amount::ONE);
+        assert!(engine.process_transaction(&duplicate).is_err());
+        engine
+            .process_transaction(&Transaction::dispute(1, 1))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_error_policy_abort() {
+        let input =
+            "type,client,tx,amount\ndeposit,1,1,5.0\nwithdrawal,1,2,10.0\ndeposit,1,3,5.0\n";
+
+        let mut skipping = PaymentsEngine::new(EngineConfig::standard());
+        assert!(
+            skipping
+                .process_transactions_from_reader(input.as_bytes())
+                .is_ok()
+        );
+        assert_eq!(skipping.get_engine_info().transaction_count, Some(2));
+
+        let configs = small_engine_configs();
+        for config in configs {
+            let mut engine = PaymentsEngine::new(config);
+            engine.set_error_policy(ErrorPolicy::Abort);
+            engine.set_num_workers(2);
+            let error = engine
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap_err();
+            let error = error.downcast_ref::<PaymentsError>().unwrap();
+            assert!(matches!(error.inner(), PaymentsError::InsufficientFunds(_)));
+            assert_eq!(error.code(), 202);
+            assert_eq!(error.client(), Some(ClientId::new(1)));
+            assert_eq!(error.line(), Some(2));
+        }
+    }
+
+    #[test]
+    fn test_type_matching() {
+        let input = "type,client,tx,amount\nDeposit,1,1,5.0\nDEPOSIT,1,2,5.0\n\
+                     dispute,1,1,\ncharge_back,1,1,\n";
+
+        for config in small_engine_configs() {
+            let mut lenient = PaymentsEngine::new(config.clone());
+            lenient.set_num_workers(2);
+            lenient
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap();
+            let account = lenient.peek_account(ClientId::new(1)).unwrap().unwrap();
+            assert_eq!(account.total, Amount::new(5, 0));
+            assert!(account.is_locked());
+
+            let mut strict = PaymentsEngine::new(config);
+            strict.set_input_format(InputFormat {
+                type_matching: TypeMatching::Strict,
+                ..InputFormat::default()
+            });
+            strict.set_error_policy(ErrorPolicy::Abort);
+            strict.set_num_workers(2);
+            let error = strict
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap_err();
+            let error = error.downcast_ref::<PaymentsError>().unwrap();
+            assert_eq!(error.line(), Some(1));
+            assert_eq!(
+                error.to_string(),
+                "line 1: Invalid transaction: transaction type 'Deposit' must be written 'deposit'"
+            );
+        }
+    }
+
+    #[test]
+    fn test_amount_format() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,\"1.234,50\"\n\
+                     withdrawal,1,2,+34\n\
+                     dispute,1,2,\n\
+                     deposit,2,3,\"1,5,0\"\n";
+
+        for config in small_engine_configs() {
+            let mut engine = PaymentsEngine::new(config);
+            engine.set_input_format(InputFormat {
+                amount_format: AmountFormat::European,
+                ..InputFormat::default()
+            });
+            engine.set_num_workers(2);
+            engine
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap();
+            let account = engine.peek_account(ClientId::new(1)).unwrap().unwrap();
+            assert_eq!(
+                (account.available, account.held),
+                (Amount::new(11665, 1), Amount::new(34, 0))
+            );
+            // The malformed amount is rejected rather than misread
+            assert!(engine.peek_account(ClientId::new(2)).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_csv_dialect() {
+        let renamed = "transaction_type;client_id;tx;amount\n\
+                       deposit;1;1;5.0\n\
+                       withdrawal;1;2;1.5\n";
+        let headerless = "deposit\t1\t1\t5.0\nwithdrawal\t1\t2\t1.5\ndispute\t1\t2\n";
+        let renamed_format = InputFormat {
+            delimiter: b';',
+            columns: [("transaction_type", "type"), ("client_id", "client")]
+                .into_iter()
+                .map(|(header, name)| (header.to_string(), name.to_string()))
+                .collect(),
+            ..InputFormat::default()
+        };
+        let headerless_format = InputFormat {
+            delimiter: b'\t',
+            has_headers: false,
+            ..InputFormat::default()
+        };
+
+        for (input, format, held) in [
+            (renamed, renamed_format, Amount::ZERO),
+            (headerless, headerless_format, Amount::new(15, 1)),
+        ] {
+            for config in small_engine_configs() {
+                let mut engine = PaymentsEngine::new(config);
+                engine.set_input_format(format.clone());
+                engine.set_error_policy(ErrorPolicy::Abort);
+                engine.set_num_workers(2);
+                engine
+                    .process_transactions_from_reader(input.as_bytes())
+                    .unwrap();
+                let account = engine.peek_account(ClientId::new(1)).unwrap().unwrap();
+                assert_eq!(account.total, Amount::new(35, 1));
+                assert_eq!(account.held, held);
+            }
+        }
+    }
+
+    #[test]
+    fn test_header_validation() {
+        let input = "type,client_id,tx,amount\ndeposit,1,1,5.0\n";
+
+        for config in small_engine_configs() {
+            let mut engine = PaymentsEngine::new(config);
+            engine.set_num_workers(2);
+            // Fails even though rows are skipped by default
+            let error = engine
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap_err();
+            let error = error.downcast_ref::<PaymentsError>().unwrap();
+            assert!(matches!(error, PaymentsError::InvalidHeader { .. }));
+            assert_eq!(
+                error.to_string(),
+                "Invalid input header: missing client (expected columns \
+                 type, client, tx, amount; found type, client_id, tx, amount)"
+            );
+            assert!(engine.peek_account(ClientId::new(1)).unwrap().is_none());
+
+            // An empty input has no header to check
+            engine.process_transactions_from_reader(&b""[..]).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_parse_error_limit() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,5.0\n\
+                     deposit,1,x,1.0\n\
+                     deposit,1,3,abc\n\
+                     refund,1,4,1.0\n\
+                     deposit,1,5,1.0\n";
+        assert_eq!("2".parse(), Ok(ParseErrorLimit::Count(2)));
+        assert_eq!("50 %".parse(), Ok(ParseErrorLimit::Percent(50.0)));
+        assert!("150%".parse::<ParseErrorLimit>().is_err());
+
+        for (limit, failed) in [("2", true), ("3", false), ("50%", true), ("60%", false)] {
+            for config in small_engine_configs() {
+                let mut engine = PaymentsEngine::new(config);
+                engine.set_parse_error_limit(Some(limit.parse().unwrap()));
+                engine.set_num_workers(2);
+                let result = engine.process_transactions_from_reader(input.as_bytes());
+                match result {
+                    Err(e) => {
+                        assert!(failed, "limit {} failed: {}", limit, e);
+                        assert!(matches!(
+                            e.downcast_ref::<PaymentsError>(),
+                            Some(PaymentsError::TooManyParseErrors { failed: 3, .. })
+                        ));
+                    }
+                    Ok(()) => assert!(!failed, "limit {} passed", limit),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_load_accounts() {
+        let seed = "client,available,held,total,locked\n1,10.5,2,12.5,false\n2,0,0,0,true\n";
+        let configs = small_engine_configs();
+
+        for config in configs {
+            let mut engine = PaymentsEngine::new(config);
+            assert_eq!(engine.load_accounts(seed.as_bytes()).unwrap(), 2);
+
+            let deposit = Transaction::deposit(1, 1, Amount::new(15, 1));
+            engine.process_transaction(&deposit).unwrap();
+            let locked = Transaction::deposit(2, 2, Amount::new(15, 1));
+            assert!(matches!(
+                engine.process_transaction(&locked),
+                Err(PaymentsError::AccountFrozen(_))
+            ));
+
+            let mut output = Vec::new();
+            engine.write_accounts_csv(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(
+                output.contains("1,12.0000,2.0000,14.0000,false"),
+                "{}",
+                output
+            );
+        }
+    }
+
+    #[test]
+    fn test_rejected_transactions_leave_ids_unused() {
+        // Each ID is first used by a rejected row, then by an accepted one
+        let input = "type,client,tx,amount\n\
+                     withdrawal,1,1,10\n\
+                     dispute,1,1,\n\
+                     deposit,1,1,10\n\
+                     deposit,2,2,-5\n\
+                     deposit,2,2,5\n\
+                     close,3,3,\n\
+                     deposit,3,3,1\n\
+                     dispute,1,1,\n";
+
+        for config in small_engine_configs() {
+            let mut engine = PaymentsEngine::new(config);
+            engine.set_num_workers(2);
+            engine
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap();
+
+            let balances = |client: u16| {
+                let account = engine.peek_account(ClientId::new(client)).unwrap().unwrap();
+                (account.available, account.held)
+            };
+            assert_eq!(balances(1), (Amount::ZERO, Amount::new(10, 0)));
+            assert_eq!(balances(2), (Amount::new(5, 0), Amount::ZERO));
+            assert_eq!(balances(3), (Amount::ONE, Amount::ZERO));
+
+            let state = engine.export_state().unwrap();
+            let mut processed = state.processed_tx_ids;
+            processed.sort();
+            assert_eq!(processed, [1, 2, 3].map(TxId::new));
+            assert_eq!(state.disputable_transactions.len(), 3);
+        }
+
+        // The ID is free for a client of another actor too, whether the rows are read or
+        // applied one by one
+        let input = "type,client,tx,amount\n\
+                     withdrawal,1,1,10\n\
+                     deposit,4,1,10\n";
+        let rows = [
+            Transaction::withdrawal(1, 1, Amount::new(10, 0)),
+            Transaction::deposit(4, 1, Amount::new(10, 0)),
+        ];
+        for config in small_engine_configs() {
+            let mut engine = PaymentsEngine::new(config.clone());
+            engine.set_num_workers(2);
+            engine
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap();
+            let account = engine.peek_account(ClientId::new(4)).unwrap().unwrap();
+            assert_eq!(account.total, Amount::new(10, 0), "{:?}", config);
+
+            let mut engine = PaymentsEngine::new(config.clone());
+            engine.set_num_workers(2);
+            let results = engine.process_transactions(&rows);
+            assert!(results[0].is_err() && results[1].is_ok(), "{:?}", config);
+        }
+    }
+
+    #[test]
+    fn test_duplicate_policy() {
+        let deposit = Transaction::deposit(1, 1, Amount::new(10, 0));
+        for config in small_engine_configs() {
+            let mut skipping = PaymentsEngine::new(config.clone());
+            skipping.set_policy(EnginePolicy {
+                duplicates: DuplicatePolicy::SkipSilently,
+                ..EnginePolicy::default()
+            });
+            skipping.process_transaction(&deposit).unwrap();
+            skipping.process_transaction(&deposit).unwrap();
+            // Same ID with a different amount, type or client
+            for changed in [
+                Transaction::deposit(1, 1, Amount::new(5, 0)),
+                Transaction::withdrawal(1, 1, Amount::new(10, 0)),
+                Transaction::deposit(2, 1, Amount::new(10, 0)),
+            ] {
+                assert!(skipping.process_transaction(&changed).is_err());
+            }
+            let account = skipping.peek_account(ClientId::new(1)).unwrap().unwrap();
+            assert_eq!(account.total, Amount::new(10, 0));
+
+            let mut overwriting = PaymentsEngine::new(config);
+            overwriting.set_policy(EnginePolicy {
+                duplicates: DuplicatePolicy::Overwrite,
+                ..EnginePolicy::default()
+            });
+            overwriting.process_transaction(&deposit).unwrap();
+            overwriting
+                .process_transaction(&Transaction::deposit(1, 1, Amount::new(5, 0)))
+                .unwrap();
+            // The dispute holds the replacing deposit's amount
+            overwriting
+                .process_transaction(&Transaction::dispute(1, 1))
+                .unwrap();
+            let account = overwriting.peek_account(ClientId::new(1)).unwrap().unwrap();
+            assert_eq!(
+                (account.available, account.held),
+                (Amount::new(10, 0), Amount::new(5, 0))
+            );
+            assert!(matches!(
+                overwriting.process_transaction(&deposit),
+                Err(PaymentsError::TransactionAlreadyDisputed(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_policy_allows_deposit_when_locked() {
+        let input = "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,1,2,5.0\n\
+                     dispute,1,1,\nchargeback,1,1,\ndeposit,1,3,2.5\ndispute,1,2,\n";
+
+        for config in small_engine_configs() {
+            let mut engine = PaymentsEngine::new(config);
+            engine.set_policy(EnginePolicy {
+                allow_deposit_when_locked: true,
+                ..EnginePolicy::default()
+            });
+            engine.set_num_workers(2);
+            engine
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap();
+
+            // The refund is credited but the dispute on tx 2 is still rejected
+            let mut output = Vec::new();
+            engine.write_accounts_csv(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains("1,7.5000,0.0000,7.5000,true"), "{}", output);
+        }
+    }
+
+    #[test]
+    fn test_lock_states() {
+        let frozen = |result: Result<(), PaymentsError>| matches!(result, Err(PaymentsError::AccountFrozen(client)) if client == ClientId::new(1));
+
+        for config in small_engine_configs() {
+            let mut engine = PaymentsEngine::new(config);
+            engine.set_num_workers(2);
+            for transaction in [
+                Transaction::deposit(1, 1, Amount::new(10, 0)),
+                Transaction::deposit(1, 2, Amount::new(5, 0)),
+                Transaction::dispute(1, 1),
+                Transaction::dispute(1, 2),
+            ] {
+                engine.process_transaction(&transaction).unwrap();
+            }
+
+            // An admin freeze blocks everything, even settling a dispute
+            engine
+                .set_lock(
+                    ClientId::new(1),
+                    LockState::AdminFreeze,
+                    Some("case 7".into()),
+                )
+                .unwrap();
+            assert!(frozen(
+                engine.process_transaction(&Transaction::resolve(1, 2))
+            ));
+            assert!(frozen(engine.process_transaction(&Transaction::deposit(
+                1,
+                3,
+                Amount::new(1, 0)
+            ))));
+
+            // An operator can still settle a dispute without lifting the freeze, and
+            // subscribers only ever see the account frozen
+            let changes = engine.subscribe_account_changes();
+            engine
+                .force_resolve(ClientId::new(1), TxId::from(1))
+                .unwrap();
+            let changes: Vec<AccountDelta> = changes.try_iter().collect();
+            assert_eq!(changes.len(), 1);
+            assert_eq!(changes[0].account.lock, LockState::AdminFreeze);
+            assert_eq!(changes[0].held_change, Amount::new(-10, 0));
+            let account = engine.peek_account(ClientId::new(1)).unwrap().unwrap();
+            assert_eq!(account.lock, LockState::AdminFreeze);
+            assert_eq!(account.lock_reason.as_deref(), Some("case 7"));
+            assert_eq!(account.held, Amount::new(5, 0));
+
+            // A compliance hold lets the dispute settle, and the chargeback keeps the hold
+            engine
+                .set_lock(
+                    ClientId::new(1),
+                    LockState::ComplianceHold,
+                    Some("kyc".into()),
+                )
+                .unwrap();
+            assert!(frozen(engine.process_transaction(&Transaction::deposit(
+                1,
+                3,
+                Amount::new(1, 0)
+            ))));
+            assert!(frozen(engine.process_transaction(
+                &Transaction::withdrawal(1, 4, Amount::new(1, 0))
+            )));
+            engine
+                .process_transaction(&Transaction::chargeback(1, 2))
+                .unwrap();
+            let account = engine.peek_account(ClientId::new(1)).unwrap().unwrap();
+            assert_eq!(account.lock, LockState::ComplianceHold);
+            assert_eq!(account.lock_reason.as_deref(), Some("kyc"));
+            assert_eq!(account.total, Amount::new(10, 0));
+
+            engine
+                .set_lock(ClientId::new(1), LockState::Unlocked, None)
+                .unwrap();
+            engine
+                .process_transaction(&Transaction::deposit(1, 3, Amount::new(1, 0)))
+                .unwrap();
+
+            // Clients without an account can be frozen ahead of their first transaction
+            engine
+                .set_lock(ClientId::new(9), LockState::AdminFreeze, None)
+                .unwrap();
+            assert!(matches!(
+                engine.process_transaction(&Transaction::deposit(9, 5, Amount::new(1, 0))),
+                Err(PaymentsError::AccountFrozen(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_dispute_after_withdrawal() {
+        // Client 1 withdraws everything before the deposit is disputed
+        let input = "type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,10.0\n\
+                     dispute,1,1,\nresolve,1,1,\n";
+
+        for config in small_engine_configs() {
+            // Rejected dispute must leave the transaction undisputed
+            let mut engine = PaymentsEngine::new(config.clone());
+            engine.set_num_workers(2);
+            engine
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap();
+            let dispute = Transaction::dispute(1, 1);
+            assert!(matches!(
+                engine.process_transaction(&dispute),
+                Err(PaymentsError::InsufficientFunds(_))
+            ));
+            let state = engine.export_state().unwrap();
+            let (_, stored) = state
+                .disputable_transactions
+                .iter()
+                .find(|(tx, _)| *tx == TxId::new(1))
+                .unwrap();
+            assert!(!stored.disputed);
+
+            let mut engine = PaymentsEngine::new(config);
+            engine.set_policy(EnginePolicy {
+                allow_negative_available: true,
+                ..EnginePolicy::default()
+            });
+            engine.set_num_workers(2);
+            engine
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap();
+            engine.process_transaction(&dispute).unwrap();
+            let mut output = Vec::new();
+            engine.write_accounts_csv(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(
+                output.contains("1,-10.0000,10.0000,0.0000,false"),
+                "{}",
+                output
+            );
+        }
+    }
+
+    #[test]
+    fn test_dispute_case_metadata() {
+        // The resolve updates the reason code and keeps the dispute's case reference
+        let input = "type,client,tx,amount,reason_code,case_ref\n\
+                     deposit,1,1,10.0,,\ndeposit,1,2,5.0,,\n\
+                     dispute,1,1,,4853,CASE-7\nresolve,1,1,,4853-W,\n\
+                     dispute,1,2,,,\n";
+
+        for config in small_engine_configs() {
+            let mut engine = PaymentsEngine::new(config);
+            engine.set_num_workers(2);
+            engine
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap();
+            let state = engine.export_state().unwrap();
+            let case = |id: TxId| {
+                let (_, stored) = state
+                    .disputable_transactions
+                    .iter()
+                    .find(|(tx, _)| *tx == id)
+                    .unwrap();
+                stored.case.clone()
+            };
+            assert_eq!(
+                case(TxId::new(1)).as_deref(),
+                Some(&DisputeCase {
+                    reason_code: Some("4853-W".to_string()),
+                    case_ref: Some("CASE-7".to_string()),
+                })
+            );
+            assert_eq!(case(TxId::new(2)), None);
+        }
+    }
+
+    #[test]
+    fn test_max_open_disputes() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,1.0\n\
+                     deposit,1,3,1.0\ndeposit,2,4,1.0\ndispute,1,3,\ndispute,1,1,\n";
+        for config in small_engine_configs() {
+            let mut engine = PaymentsEngine::new(config);
+            engine.set_policy(EnginePolicy {
+                max_open_disputes: Some(2),
+                ..EnginePolicy::default()
+            });
+            engine.set_num_workers(2);
+            engine
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap();
+            assert_eq!(
+                engine.open_disputes(1.into()),
+                vec![TxId::new(1), TxId::new(3)]
+            );
+
+            assert!(matches!(
+                engine.process_transaction(&Transaction::dispute(1, 2)),
+                Err(PaymentsError::TooManyOpenDisputes(client)) if client == ClientId::new(1)
+            ));
+            // The limit is per client
+            engine
+                .process_transaction(&Transaction::dispute(2, 4))
+                .unwrap();
+
+            engine
+                .process_transaction(&Transaction::resolve(1, 1))
+                .unwrap();
+            engine
+                .process_transaction(&Transaction::dispute(1, 2))
+                .unwrap();
+            assert_eq!(
+                engine.open_disputes(1.into()),
+                vec![TxId::new(2), TxId::new(3)]
+            );
+            assert_eq!(engine.open_disputes(3.into()), Vec::<TxId>::new());
+        }
+    }
+
+    #[test]
+    fn test_validators() {
+        let input = "type,client,tx,amount\ndeposit,1,1,10.123\ndeposit,1,2,1.12345\n\
+                     deposit,2,3,500\ndeposit,3,4,1.0\n";
+
+        for config in small_engine_configs() {
+            let mut engine = PaymentsEngine::new(config);
+            engine.add_validator(MaxPrecision(4));
+            engine.add_validator(MaxAmount(Amount::new(100, 0)));
+            engine.add_validator(ClientBlocklist([ClientId::new(3)].into()));
+            engine.set_num_workers(2);
+            engine
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap();
+
+            // Only the first deposit passes every validator
+            let mut output = Vec::new();
+            engine.write_accounts_csv(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert_eq!(output.lines().count(), 2, "{}", output);
+            assert!(
+                output.contains("1,10.1230,0.0000,10.1230,false"),
+                "{}",
+                output
+            );
+
+            let blocked = Transaction::deposit(3, 5, Amount::ONE);
+            assert!(matches!(
+                engine.process_transaction(&blocked),
+                Err(PaymentsError::ValidationFailed(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_rate_limits() {
+        use ratelimit::{ClientRateLimiter, RateLimit, RateLimitStats};
+
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,1.0\n\
+                     deposit,1,3,1.0\ndeposit,2,4,1.0\n";
+
+        for config in small_engine_configs() {
+            // Two transactions per client, then practically no refill
+            let limiter = ClientRateLimiter::new(RateLimit {
+                rate: 0.001,
+                burst: 2.0,
+            });
+            let mut engine = PaymentsEngine::new(config);
+            engine.add_validator(limiter.clone());
+            engine.set_num_workers(2);
+            engine
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap();
+
+            let error = engine
+                .process_transaction(&Transaction::deposit(1, 5, Amount::ONE))
+                .unwrap_err();
+            assert!(matches!(error, PaymentsError::RateLimited(client) if client == 1.into()));
+            assert!(error.is_retryable());
+            assert_eq!(
+                limiter.stats(),
+                RateLimitStats {
+                    allowed: 3,
+                    limited: 2
+                }
+            );
+            assert_eq!(limiter.limited_clients(), [(ClientId::new(1), 2)]);
+        }
+
+        #[cfg(feature = "concurrent")]
+        {
+            // Streams are slowed down rather than rejected
+            let mut engine = ConcurrentEngine::new(1000, 1000, 1000);
+            engine.set_stream_rate_limit(Some(RateLimit {
+                rate: 200.0,
+                burst: 1.0,
+            }));
+            let results = engine.process_concurrent_streams(vec![input.as_bytes()]);
+            assert_eq!(results[0].processed, 4);
+            assert!(results[0].throttled >= std::time::Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_per_source_tx_ids() {
+        use crate::transaction::{TxIdNamespaces, TxIdScope};
+
+        let day1 = "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,2,2,5.0\n";
+        let day2 = "type,client,tx,amount\ndeposit,1,1,3.0\ndispute,1,1,\ndispute,2,2,\n";
+
+        for config in small_engine_configs() {
+            // By default the second day's IDs are duplicates
+            let mut engine = PaymentsEngine::new(config.clone());
+            engine.set_num_workers(2);
+            for day in [day1, day2] {
+                engine
+                    .process_transactions_from_reader(day.as_bytes())
+                    .unwrap();
+            }
+            let account = engine.peek_account(ClientId::new(1)).unwrap().unwrap();
+            assert_eq!(account.total, Amount::new(10, 0));
+
+            let mut engine = PaymentsEngine::new(config);
+            engine.set_num_workers(2);
+            engine.set_input_format(InputFormat {
+                tx_ids: TxIdScope::PerSource(TxIdNamespaces::new()),
+                ..InputFormat::default()
+            });
+            for day in [day1, day2] {
+                engine
+                    .process_transactions_from_reader(day.as_bytes())
+                    .unwrap();
+            }
+            // Day 2's dispute of tx 1 holds day 2's deposit; its tx 2 does not exist
+            let account = engine.peek_account(ClientId::new(1)).unwrap().unwrap();
+            assert_eq!(account.available, Amount::new(10, 0));
+            assert_eq!(account.held, Amount::new(3, 0));
+            let account = engine.peek_account(ClientId::new(2)).unwrap().unwrap();
+            assert_eq!(account.held, Amount::ZERO);
+        }
+
+        #[cfg(feature = "concurrent")]
+        {
+            // Each stream is its own source
+            let mut engine = ConcurrentEngine::new(1000, 1000, 1000);
+            engine.set_input_format(InputFormat {
+                tx_ids: TxIdScope::PerSource(TxIdNamespaces::new()),
+                ..InputFormat::default()
+            });
+            let results = engine.process_concurrent_streams(vec![day1.as_bytes(), day1.as_bytes()]);
+            assert!(results.iter().all(|r| r.processed == 2 && r.errors == 0));
+        }
+    }
+
+    #[test]
+    fn test_tenants() {
+        use crate::account::TenantId;
+        use tenant::TenantEngines;
+
+        let acme = TenantId::from("acme");
+        let globex = TenantId::from("globex");
+        // Both tenants use client 1 and tx 1
+        let mixed = "type,client,tx,amount,tenant\ndeposit,1,1,10.0,acme\n\
+                     deposit,1,1,3.0,globex\ndispute,1,1,,acme\nwithdrawal,1,2,1.0,globex\n\
+                     deposit,1,2,1.0,\n";
+
+        for config in small_engine_configs() {
+            let mut tenants = TenantEngines::new(config);
+            tenants.set_engine_setup(|_, engine| engine.set_num_workers(2));
+            tenants
+                .process_transactions_from_reader(mixed.as_bytes())
+                .unwrap();
+
+            let account = tenants.peek_account(&acme, 1.into()).unwrap().unwrap();
+            assert_eq!(account.available, Amount::ZERO);
+            assert_eq!(account.held, Amount::new(10, 0));
+            let account = tenants.peek_account(&globex, 1.into()).unwrap().unwrap();
+            assert_eq!(account.available, Amount::new(2, 0));
+            assert_eq!(account.held, Amount::ZERO);
+            assert_eq!(
+                tenants.tenants().map(TenantId::as_str).collect::<Vec<_>>(),
+                ["", "acme", "globex"]
+            );
+
+            // Streams and single transactions are booked to the tenant they are given
+            tenants
+                .process_tenant_stream(
+                    &globex,
+                    "type,client,tx,amount\ndeposit,2,3,5.0\n".as_bytes(),
+                )
+                .unwrap();
+            tenants
+                .process_transaction(&acme, &Transaction::deposit(2, 3, Amount::ONE))
+                .unwrap();
+            assert!(
+                tenants
+                    .process_transaction(&acme, &Transaction::deposit(2, 3, Amount::ONE))
+                    .is_err()
+            );
+
+            let mut output = Vec::new();
+            tenants.write_accounts_csv(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            let mut lines = output.lines();
+            assert!(
+                lines
+                    .next()
+                    .unwrap()
+                    .starts_with("tenant,client,available,held,total,locked")
+            );
+            // tenant, client and available balance of each account
+            let mut rows: Vec<_> = lines
+                .map(|line| {
+                    let fields: Vec<_> = line.split(',').collect();
+                    let available: Decimal = fields[2].parse().unwrap();
+                    (fields[0].to_string(), fields[1].to_string(), available)
+                })
+                .collect();
+            rows.sort();
+            let expected = [
+                ("", "1", 1),
+                ("acme", "1", 0),
+                ("acme", "2", 1),
+                ("globex", "1", 2),
+                ("globex", "2", 5),
+            ]
+            .map(|(t, c, a)| (t.to_string(), c.to_string(), Decimal::from(a)));
+            assert_eq!(rows, expected);
+        }
+    }
+
+    #[test]
+    fn test_observer_events() {
+        use observer::{ChannelObserver, EngineEvent};
+
+        let input = "type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,50.0\n\
+                     dispute,1,1,\nchargeback,1,1,\n";
+
+        for config in small_engine_configs() {
+            let mut engine = PaymentsEngine::new(config);
+            let (observer, events) = ChannelObserver::new();
+            engine.add_observer(observer);
+            engine.set_num_workers(2);
+            engine
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap();
+            drop(engine);
+
+            let events: Vec<_> = events
+                .iter()
+                .filter(|event| !matches!(event, EngineEvent::AccountChanged(_)))
+                .collect();
+            assert_eq!(events.len(), 6, "{:?}", events);
+            assert!(matches!(&events[0], EngineEvent::Accepted(tx) if tx.tx == TxId::new(1)));
+            assert!(matches!(&events[1], EngineEvent::Rejected(tx, _) if tx.tx == TxId::new(2)));
+            assert!(matches!(&events[3], EngineEvent::DisputeOpened(_, amount)
+                if *amount == Amount::new(100, 1)));
+            assert!(matches!(&events[5], EngineEvent::AccountLocked(account, _)
+                if account.is_locked() && account.total == Amount::ZERO));
+        }
+    }
+
+    #[test]
+    fn test_close_account() {
+        use observer::{ChannelObserver, EngineEvent};
+
+        // Client 1 cannot close while tx 2 is disputed; client 2 has no account
+        let input = "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,1,2,2.5\n\
+                     dispute,1,2,\nclose,1,3,\nresolve,1,2,\nclose,1,3,\nclose,2,4,\n";
+        let deposit = Transaction::deposit(1, 5, Amount::ONE);
+
+        for payout_on_close in [false, true] {
+            for config in small_engine_configs() {
+                let mut engine = PaymentsEngine::new(config);
+                engine.set_policy(EnginePolicy {
+                    payout_on_close,
+                    ..EnginePolicy::default()
+                });
+                let (observer, events) = ChannelObserver::new();
+                engine.add_observer(observer);
+                engine.set_num_workers(2);
+                engine
+                    .process_transactions_from_reader(input.as_bytes())
+                    .unwrap();
+                assert!(matches!(
+                    engine.process_transaction(&deposit),
+                    Err(PaymentsError::AccountClosed(client)) if client == ClientId::new(1)
+                ));
+
+                let mut output = Vec::new();
+                engine.write_accounts_csv(&mut output).unwrap();
+                let expected = if payout_on_close {
+                    "client,available,held,total,locked,closed,lock_state,lock_reason\n1,0.0000,0.0000,0.0000,false,true,unlocked,\n"
+                } else {
+                    "client,available,held,total,locked,closed,lock_state,lock_reason\n1,12.5000,0.0000,12.5000,false,true,unlocked,\n"
+                };
+                assert_eq!(String::from_utf8(output).unwrap(), expected);
+                drop(engine);
+
+                let payouts: Vec<_> = events
+                    .iter()
+                    .filter_map(|event| match event {
+                        EngineEvent::Accepted(tx)
+                            if matches!(tx.tx_type, TransactionType::Withdrawal) =>
+                        {
+                            Some((tx.tx.get(), tx.amount))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                let expected = if payout_on_close {
+                    vec![(3, Some(Amount::new(125, 1)))]
+                } else {
+                    vec![]
+                };
+                assert_eq!(payouts, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_apply_fee_schedule() {
+        use fees::FeeRule;
+
+        // Tx 100 is taken by an input row, so generated IDs start at 101
+        let input = "type,client,tx,amount\ndeposit,1,100,10.0\ndeposit,2,2,2000\n\
+                     deposit,3,3,1.0\n";
+        let schedule = FeeSchedule {
+            first_tx_id: TxId::new(100),
+            rules: vec![
+                FeeRule::MaintenanceFee {
+                    amount: Amount::new(25, 1),
+                    waive_above: Some(Amount::new(1000, 0)),
+                },
+                FeeRule::Interest {
+                    rate: Decimal::new(1, 2),
+                    min_balance: Amount::new(100, 0),
+                },
+            ],
+        };
+
+        for config in small_engine_configs() {
+            let mut engine = PaymentsEngine::new(config);
+            engine.set_num_workers(2);
+            engine
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap();
+            let applied: Vec<_> = engine
+                .apply_fee_schedule(&schedule)
+                .unwrap()
+                .into_iter()
+                .map(|tx| (tx.client.get(), tx.tx.get(), tx.amount))
+                .collect();
+            assert_eq!(
+                applied,
+                vec![
+                    (1, 101, Some(Amount::new(25, 1))),
+                    (2, 102, Some(Amount::new(20, 0))),
+                ]
+            );
+
+            // Generated transactions can be disputed like input rows
+            let dispute = Transaction::dispute(2, 102);
+            engine.process_transaction(&dispute).unwrap();
+            assert_eq!(engine.open_disputes(2.into()), vec![TxId::new(102)]);
+        }
+    }
+
+    #[test]
+    fn test_recurring_transactions() {
+        use recurring::RecurringTransaction;
+
+        let schedule = RecurringSchedule {
+            first_tx_id: TxId::new(1000),
+            transactions: vec![
+                RecurringTransaction {
+                    client: ClientId::new(1),
+                    tx_type: TransactionType::Deposit,
+                    amount: Amount::new(10, 0),
+                    start: 1000,
+                    interval_secs: 100,
+                    end: Some(1250),
+                },
+                RecurringTransaction {
+                    client: ClientId::new(2),
+                    tx_type: TransactionType::Withdrawal,
+                    amount: Amount::new(5, 0),
+                    start: 1050,
+                    interval_secs: 100,
+                    end: None,
+                },
+            ],
+        };
+        // Client 1 gets deposits at 1000, 1100 and 1200; client 2's withdrawals at 1150
+        // and 1250 exceed the balance left after the first one. Nothing is due after
+        // the last row, at 1300.
+        let input = "type,client,tx,amount,timestamp\ndeposit,2,1,6,900\n\
+                     deposit,3,2,1,1150\ndeposit,3,3,1,1300\n";
+        let balances = |engine: &PaymentsEngine| {
+            let mut accounts = engine.export_state().unwrap().accounts;
+            accounts.sort_unstable_by_key(|account| account.client);
+            accounts
+                .iter()
+                .map(|account| (account.client.get(), account.total))
+                .collect::<Vec<_>>()
+        };
+        let expected = vec![
+            (1, Amount::new(30, 0)),
+            (2, Amount::new(1, 0)),
+            (3, Amount::new(2, 0)),
+        ];
+
+        for config in small_engine_configs() {
+            // The concurrent engine's workers apply occurrences when they first reach a
+            // later row of any client, so only a single worker keeps them in input order
+            let workers = match config {
+                #[cfg(feature = "concurrent")]
+                EngineConfig::Concurrent { .. } => 1,
+                _ => 2,
+            };
+            let mut engine = PaymentsEngine::new(config.clone());
+            engine.set_recurring_schedule(schedule.clone()).unwrap();
+            engine.set_num_workers(workers);
+            engine
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap();
+            assert_eq!(balances(&engine), expected);
+
+            // Replaying after a restore rejects the occurrences already applied
+            let mut resumed = PaymentsEngine::new(config);
+            resumed
+                .import_state(engine.export_state().unwrap())
+                .unwrap();
+            resumed.set_recurring_schedule(schedule.clone()).unwrap();
+            resumed.set_num_workers(workers);
+            resumed
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap();
+            assert_eq!(balances(&resumed), expected);
+        }
+
+        let mut engine = PaymentsEngine::new(EngineConfig::standard());
+        let mut invalid = schedule;
+        invalid.transactions[0].interval_secs = 0;
+        assert!(matches!(
+            engine.set_recurring_schedule(invalid),
+            Err(PaymentsError::ConfigError(_))
+        ));
+    }
+
+    #[test]
+    fn test_dispute_window() {
+        use observer::{ChannelObserver, EngineEvent};
+
+        let window = DisputeWindow {
+            max_age_secs: Some(100),
+            max_transactions: Some(3),
+        };
+        // Tx 1 ages out before its dispute; tx 2 is under dispute when its window ends,
+        // so it is kept and can still be resolved, then ages out like tx 1
+        let input = "type,client,tx,amount,timestamp
+                     deposit,1,1,10,1000
+                     deposit,1,2,5,1050
+                     dispute,1,2,,1060
+                     deposit,1,3,1,1120
+                     dispute,1,1,,1130
+                     deposit,1,4,1,1140
+                     resolve,1,2,,1150
+                     dispute,1,9,,1160
+                     deposit,1,5,1,1300
+                     dispute,1,2,,1310
+";
+
+        for config in small_engine_configs() {
+            let mut engine = PaymentsEngine::new(config);
+            engine.set_dispute_window(window).unwrap();
+            engine.set_num_workers(2);
+            let (observer, events) = ChannelObserver::new();
+            engine.add_observer(observer);
+            engine
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap();
+
+            let expired: Vec<_> = events
+                .try_iter()
+                .filter_map(|event| match event {
+                    EngineEvent::DisputeExpired(transaction) => Some(transaction.tx.get()),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(expired, vec![1, 2]);
+            let account = engine.peek_account(1.into()).unwrap().unwrap();
+            assert_eq!(account.total, Amount::new(18, 0));
+            assert_eq!(account.held, Amount::ZERO);
+        }
+
+        let mut engine = PaymentsEngine::new(EngineConfig::standard());
+        assert!(matches!(
+            engine.set_dispute_window(DisputeWindow {
+                max_age_secs: Some(0),
+                ..DisputeWindow::default()
+            }),
+            Err(PaymentsError::ConfigError(_))
+        ));
+    }
+
+    #[test]
+    fn test_subscribe_account_changes() {
+        let input = "type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,50.0\n\
+                     deposit,2,3,1.5\ndispute,1,1,\n";
+
+        for config in small_engine_configs() {
+            let mut engine = PaymentsEngine::new(config);
+            let changes = engine.subscribe_account_changes();
+            engine.set_num_workers(2);
+            engine
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap();
+            drop(engine);
+
+            // The rejected withdrawal changes nothing. Clients' changes may interleave in
+            // any order, but each client's come in input order.
+            let mut changes: Vec<_> = changes.iter().collect();
+            changes.sort_by_key(|delta| delta.account.client);
+            let summary: Vec<_> = changes
+                .iter()
+                .map(|delta| {
+                    (
+                        delta.tx.map(TxId::get),
+                        delta.account.client.get(),
+                        delta.available_change,
+                    )
+                })
+                .collect();
+            assert_eq!(
+                summary,
+                vec![
+                    (Some(1), 1, Amount::new(100, 1)),
+                    (Some(1), 1, Amount::new(-100, 1)),
+                    (Some(3), 2, Amount::new(15, 1)),
+                ]
+            );
+            assert_eq!(changes[1].held_change, Amount::new(100, 1));
+            assert_eq!(changes[1].total_change, Amount::ZERO);
+        }
+    }
+
+    #[test]
+    fn test_load_accounts_rejects_inconsistent_totals() {
+        let seed = "client,available,held,total,locked\n1,10,2,11,false\n";
+        let mut engine = PaymentsEngine::new(EngineConfig::standard());
+        let error = engine.load_accounts(seed.as_bytes()).unwrap_err();
+        assert!(matches!(error.inner(), PaymentsError::InvalidAccount(_)));
+        assert_eq!(error.line(), Some(1));
+    }
+
+    #[test]
+    fn test_write_changed_accounts() {
+        let seed = "client,available,held,total,locked\n1,1,0,1,false\n2,2,0,2,false\n";
+        // Client 1 is seeded but untouched; the rejected withdrawal changes nothing
+        let input = "type,client,tx,amount\ndeposit,3,1,1\ndeposit,2,2,1\n\
+                     withdrawal,1,3,5.0\n";
+        let header = "client,available,held,total,locked,closed,lock_state,lock_reason\n";
+
+        for config in small_engine_configs() {
+            let mut engine = PaymentsEngine::new(config);
+            engine.set_num_workers(2);
+            engine.load_accounts(seed.as_bytes()).unwrap();
+            engine
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap();
+
+            let mut output = Vec::new();
+            engine.write_changed_accounts_csv(&mut output).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                format!(
+                    "{header}2,3.0000,0.0000,3.0000,false,false,unlocked,\n3,1.0000,0.0000,1.0000,false,false,unlocked,\n"
+                )
+            );
+
+            // Nothing changed since the previous write
+            let mut output = Vec::new();
+            engine.write_changed_accounts_csv(&mut output).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), "");
+        }
+    }
+
+    #[test]
+    fn test_decimal_format() {
+        let input = "type,client,tx,amount\ndeposit,1,1,10\ndeposit,2,2,0.12345\n\
+                     dispute,2,2,\n";
+        let header = "client,available,held,total,locked,closed,lock_state,lock_reason\n";
+
+        for config in small_engine_configs() {
+            let mut engine = PaymentsEngine::new(config);
+            engine.set_num_workers(2);
+            engine
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap();
+            let mut write = |decimal_format: DecimalFormat| {
+                engine.set_decimal_format(decimal_format);
+                let mut output = Vec::new();
+                engine.write_accounts_csv(&mut output).unwrap();
+                let mut rows: Vec<_> = String::from_utf8(output)
+                    .unwrap()
+                    .lines()
+                    .skip(1)
+                    .map(|row| format!("{row}\n"))
+                    .collect();
+                rows.sort();
+                format!("{header}{}", rows.concat())
+            };
+
+            assert_eq!(
+                write(DecimalFormat::default()),
+                format!(
+                    "{header}1,10.0000,0.0000,10.0000,false,false,unlocked,\n\
+                     2,0.0000,0.1235,0.1235,false,false,unlocked,\n"
+                )
+            );
+            assert_eq!(
+                write(DecimalFormat {
+                    decimal_places: 2,
+                    strip_trailing_zeros: true,
+                }),
+                format!(
+                    "{header}1,10,0,10,false,false,unlocked,\n2,0,0.12,0.12,false,false,unlocked,\n"
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_verify_invariants() {
+        for config in small_engine_configs() {
+            let mut engine = PaymentsEngine::new(config);
+            engine
+                .process_transaction(&Transaction::deposit(1, 1, Amount::new(10, 0)))
+                .unwrap();
+            assert!(engine.verify_invariants().unwrap().is_clean());
+
+            let mut state = engine.export_state().unwrap();
+            state.accounts[0].total = Amount::new(11, 0);
+            let mut overdrawn = Account::new(2);
+            overdrawn.held = Amount::new(-1, 0);
+            overdrawn.total = Amount::new(-1, 0);
+            state.accounts.push(overdrawn);
+            engine.import_state(state).unwrap();
+
+            let report = engine.verify_invariants().unwrap();
+            assert_eq!(report.accounts_checked, 2);
+            let mut violations = report.violations.clone();
+            violations.sort_by_key(InvariantViolation::client);
+            assert!(matches!(
+                violations[..],
+                [
+                    InvariantViolation::TotalMismatch { .. },
+                    InvariantViolation::NegativeHeld { .. },
+                    InvariantViolation::NegativeTotal { .. },
+                ]
+            ));
+
+            // Only the mismatched total can be repaired
+            assert_eq!(engine.repair_invariants().unwrap().repaired, 1);
+            let account = engine.peek_account(ClientId::new(1)).unwrap().unwrap();
+            assert_eq!(account.total, Amount::new(10, 0));
+            assert_eq!(engine.verify_invariants().unwrap().violations.len(), 2);
+
+            engine.set_policy(EnginePolicy {
+                allow_negative_available: true,
+                ..EnginePolicy::default()
+            });
+            assert_eq!(engine.verify_invariants().unwrap().violations.len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_write_filtered_accounts() {
+        let input = "type,client,tx,amount\ndeposit,1,1,5\ndeposit,7,2,150\n\
+                     deposit,42,3,200\ndispute,42,3,\nchargeback,42,3,\n";
+        let header = "client,available,held,total,locked,closed,lock_state,lock_reason\n";
+
+        for config in small_engine_configs() {
+            let mut engine = PaymentsEngine::new(config);
+            engine.set_num_workers(2);
+            engine
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap();
+
+            let write = |filter: AccountFilter| {
+                let mut output = Vec::new();
+                engine
+                    .write_accounts_csv_filtered(&mut output, |account| filter.matches(account))
+                    .unwrap();
+                String::from_utf8(output).unwrap()
+            };
+            let clients = AccountFilter {
+                clients: [ClientId::new(1), ClientId::new(7)].into(),
+                min_total: Some(Amount::new(100, 0)),
+                ..AccountFilter::default()
+            };
+            assert_eq!(
+                write(clients),
+                format!("{header}7,150.0000,0.0000,150.0000,false,false,unlocked,\n")
+            );
+            let locked = AccountFilter {
+                only_locked: true,
+                ..AccountFilter::default()
+            };
+            assert_eq!(
+                write(locked),
+                format!(
+                    "{header}42,0.0000,0.0000,0.0000,true,false,chargeback_lock,\
+                     chargeback of transaction 3\n"
+                )
+            );
+
+            // Filtered-out changes are still consumed
+            let mut output = Vec::new();
+            engine
+                .write_changed_accounts_csv_filtered(&mut output, |account| {
+                    account.client == ClientId::new(1)
+                })
+                .unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                format!("{header}1,5.0000,0.0000,5.0000,false,false,unlocked,\n")
+            );
+            let mut output = Vec::new();
+            engine.write_changed_accounts_csv(&mut output).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), "");
+        }
+    }
+
+    #[test]
+    fn test_accounts_page() {
+        let input = "type,client,tx,amount\ndeposit,5,1,5\ndeposit,3,2,3\n\
+                     deposit,1,3,1\ndeposit,4,4,4\ndeposit,2,5,2\n";
+
+        for config in small_engine_configs() {
+            let mut engine = PaymentsEngine::new(config);
+            engine.set_num_workers(2);
+            engine
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap();
+
+            let clients = |accounts: Vec<Account>| -> Vec<u16> {
+                accounts
+                    .iter()
+                    .map(|account| account.client.get())
+                    .collect()
+            };
+            assert_eq!(clients(engine.accounts_page(1, 2).unwrap()), vec![2, 3]);
+            assert_eq!(clients(engine.accounts_page(4, 10).unwrap()), vec![5]);
+            assert!(engine.accounts_page(5, 10).unwrap().is_empty());
+            let all: Vec<_> = engine.accounts_iter().collect::<Result<_, _>>().unwrap();
+            assert_eq!(clients(all), vec![1, 2, 3, 4, 5]);
+        }
+
+        // More accounts than one page
+        let mut seed = String::from("client,available,held,total,locked\n");
+        for client in 0..2500 {
+            seed.push_str(&format!("{client},1,0,1,false\n"));
+        }
+        let mut engine = PaymentsEngine::new(EngineConfig::standard());
+        engine.load_accounts(seed.as_bytes()).unwrap();
+        let clients: Vec<_> = engine
+            .accounts_iter()
+            .map(|account| account.unwrap().client.get())
+            .collect();
+        assert_eq!(clients, (0..2500).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_write_accounts_sharded() {
+        let mut seed = String::from("client,available,held,total,locked\n");
+        for client in 0..2500 {
+            seed.push_str(&format!("{client},1,0,1,{}\n", client % 7 == 0));
+        }
+        let client = |row: &str| -> ClientId { row.split(',').next().unwrap().parse().unwrap() };
+
+        for config in small_engine_configs() {
+            let mut engine = PaymentsEngine::new(config);
+            engine.load_accounts(seed.as_bytes()).unwrap();
+            let mut expected = Vec::new();
+            engine.write_accounts_csv(&mut expected).unwrap();
+            let expected = String::from_utf8(expected).unwrap();
+            let mut expected_rows: Vec<&str> = expected.lines().skip(1).collect();
+            expected_rows.sort_by_key(|row| client(row));
+
+            // Streamed accounts come in client ID order
+            let mut streamed = Vec::new();
+            let written = engine
+                .write_accounts_csv_streaming(&mut streamed, |_| true)
+                .unwrap();
+            let streamed = String::from_utf8(streamed).unwrap();
+            assert_eq!(streamed.lines().next(), expected.lines().next());
+            assert_eq!(streamed.lines().skip(1).collect::<Vec<_>>(), expected_rows);
+            assert_eq!(written, expected_rows.len());
+
+            let mut shards = vec![Vec::new(); 3];
+            let counts = engine
+                .write_accounts_csv_sharded(shards.iter_mut().collect(), |_| true)
+                .unwrap();
+            assert_eq!(counts.iter().sum::<usize>(), written);
+            let mut rows = Vec::new();
+            for (shard, output) in shards.iter().enumerate() {
+                let output = std::str::from_utf8(output).unwrap();
+                let mut lines = output.lines();
+                assert_eq!(lines.next(), expected.lines().next());
+                for row in lines {
+                    assert_eq!(export::shard_of(client(row), 3), shard);
+                    rows.push(row);
+                }
+            }
+            rows.sort_by_key(|row| client(row));
+            assert_eq!(rows, expected_rows);
+
+            let locked = engine
+                .write_accounts_csv_sharded(vec![Vec::new(), Vec::new()], |account| {
+                    account.is_locked()
+                })
+                .unwrap();
+            assert_eq!(
+                locked.iter().sum::<usize>(),
+                expected_rows
+                    .iter()
+                    .filter(|row| row.contains(",true,false,"))
+                    .count()
+            );
+        }
+    }
+
+    #[test]
+    fn test_reads_keep_lru_order() {
+        let mut engine = PaymentsEngine::new(EngineConfig::bounded(2, 10, 10));
+        engine
+            .process_transactions_from_reader(
+                "type,client,tx,amount\ndeposit,1,1,1\ndeposit,2,2,2\n".as_bytes(),
+            )
+            .unwrap();
+
+        // Client 1 stays least recently used however it is read
+        assert_eq!(
+            engine.peek_account(1.into()).unwrap().unwrap().total,
+            Amount::ONE
+        );
+        engine.write_accounts_csv(Vec::new()).unwrap();
+        engine.export_state().unwrap();
+        assert_eq!(
+            engine.accounts_page(0, 1).unwrap()[0].client,
+            ClientId::new(1)
+        );
+        assert_eq!(engine.accounts_iter().count(), 2);
+
+        engine
+            .process_transactions_from_reader("type,client,tx,amount\ndeposit,3,3,3\n".as_bytes())
+            .unwrap();
+        assert!(engine.peek_account(1.into()).unwrap().is_none());
+        assert!(engine.peek_account(2.into()).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_snapshot_diff() {
+        let first = "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,2,2,5.0\n";
+        // Client 2 is untouched by the second batch and client 3 is new
+        let second = "type,client,tx,amount\nwithdrawal,1,3,4.0\ndeposit,3,4,1.0\n";
+
+        for config in small_engine_configs() {
+            let mut engine = PaymentsEngine::new(config);
+            engine.set_num_workers(2);
+            engine
+                .process_transactions_from_reader(first.as_bytes())
+                .unwrap();
+            let before = engine.export_state().unwrap();
+            engine
+                .process_transactions_from_reader(second.as_bytes())
+                .unwrap();
+            let after = engine.export_state().unwrap();
+
+            let deltas: Vec<_> = state::snapshot_diff(&before, &after)
+                .into_iter()
+                .map(|delta| {
+                    (
+                        delta.tx.map(TxId::get),
+                        delta.account.client.get(),
+                        delta.total_change,
+                    )
+                })
+                .collect();
+            assert_eq!(
+                deltas,
+                vec![
+                    (None, 1, Amount::new(-40, 1)),
+                    (None, 3, Amount::new(10, 1)),
+                ]
+            );
+            assert!(state::snapshot_diff(&after, &after).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let shard_a = "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,2,2,3.0\ndispute,2,2,\n";
+        let shard_b = "type,client,tx,amount\ndeposit,1,3,2.5\ndeposit,3,4,1.0\n";
+        for config in small_engine_configs() {
+            let mut merged = PaymentsEngine::new(config);
+            merged
+                .process_transactions_from_reader(shard_a.as_bytes())
+                .unwrap();
+            let mut other = PaymentsEngine::new(EngineConfig::standard());
+            other
+                .process_transactions_from_reader(shard_b.as_bytes())
+                .unwrap();
+            merged.merge(&other).unwrap();
+
+            let mut accounts = merged.export_state().unwrap().accounts;
+            accounts.sort_by_key(|account| account.client);
+            assert_eq!(accounts.len(), 3);
+            assert_eq!(accounts[0].total, Amount::new(75, 1));
+            assert_eq!(accounts[1].held, Amount::new(3, 0));
+
+            // tx 2 is now known to the merged engine, so it can be resolved
+            let resolve = Transaction::resolve(2, 2);
+            merged.process_transaction(&resolve).unwrap();
+
+            assert!(matches!(
+                merged.merge(&other),
+                Err(PaymentsError::MergeConflict(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_process_streams() {
+        let streams = [
+            "type,client,tx,amount\ndeposit,1,1,5.0\nwithdrawal,1,2,1.0\n",
+            "type,client,tx,amount\ndeposit,2,3,2.0\ndispute,2,3,\n",
+        ];
+        for config in small_engine_configs() {
+            let mut engine = PaymentsEngine::new(config);
+            engine
+                .process_streams(streams.iter().map(|s| s.as_bytes()).collect())
+                .unwrap();
+
+            let mut accounts = engine.export_state().unwrap().accounts;
+            accounts.sort_by_key(|account| account.client);
+            assert_eq!(accounts.len(), 2);
+            assert_eq!(accounts[0].available, Amount::new(4, 0));
+            assert_eq!(accounts[1].held, Amount::new(2, 0));
+        }
+    }
+
+    #[test]
+    fn test_shutdown_stops_reading() {
+        let input = "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,2,2,5.0\n";
+        for config in small_engine_configs() {
+            let mut engine = PaymentsEngine::new(config);
+            let shutdown = ShutdownFlag::new();
+            engine.set_shutdown_flag(shutdown.clone());
+            shutdown.request();
+
+            engine
+                .process_transactions_from_reader(input.as_bytes())
+                .unwrap();
+            assert_eq!(engine.get_engine_info().account_count, 0);
+        }
+    }
+
+    #[test]
+    fn test_auto_engine_selection() {
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx in 0..1000 {
+            input.push_str(&format!("deposit,{},{},1.0\n", tx % 50, tx));
+        }
+        let profile = InputProfile::from_reader(input.as_bytes(), input.len() as u64).unwrap();
+        assert_eq!(profile.sampled_rows, 1000);
+        assert_eq!(profile.estimated_rows(), 1000);
+        assert_eq!(profile.sampled_clients, 50);
+        assert!(matches!(
+            EngineConfig::auto_for_profile(&profile),
+            EngineConfig::Standard
+        ));
+
+        // The same rows repeated through a 100 GB file
+        let huge = InputProfile {
+            total_bytes: 100 << 30,
+            ..profile
+        };
+        assert!(huge.estimated_rows() > AUTO_STANDARD_MAX_ROWS);
+        match EngineConfig::auto_for_profile(&huge) {
+            EngineConfig::Bounded { max_accounts, .. } => assert_eq!(max_accounts, 65536),
+            other => panic!("expected bounded engine, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_memory_config() {
+        let config = EngineConfig::for_memory_mb(100); // 100MB
+        let engine = PaymentsEngine::new(config);
+        let info = engine.get_engine_info();
+        let limits = info.memory_limits.unwrap();
+        let estimated_mb = limits.estimated_bytes() / (1024 * 1024);
+        assert!((95..=100).contains(&estimated_mb), "{}", estimated_mb);
+    }
+
+    #[test]
+    #[cfg(feature = "concurrent")]
+    fn test_actor_engine() {
+        use routing::{ConsistentHashRouter, Router};
+
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx in 1..=200u32 {
+            let client = tx % 17 + 1;
+            input.push_str(&format!("deposit,{},{},{}.5\n", client, tx, tx));
+            if tx % 3 == 0 {
+                input.push_str(&format!("withdrawal,{},{},1.0\n", client, 1000 + tx));
+            }
+            if tx % 5 == 0 {
+                input.push_str(&format!("dispute,{},{},\n", client, tx));
+            }
+            if tx % 10 == 0 {
+                input.push_str(&format!("chargeback,{},{},\n", client, tx));
+            }
+        }
+        let mut expected = PaymentsEngine::new(EngineConfig::standard());
+        expected
+            .process_transactions_from_reader(input.as_bytes())
+            .unwrap();
+        let mut engine = PaymentsEngine::new(EngineConfig::actor(4));
+        engine
+            .process_transactions_from_reader(input.as_bytes())
+            .unwrap();
+        assert_eq!(
+            engine.accounts_page(0, 100).unwrap(),
+            expected.accounts_page(0, 100).unwrap()
+        );
+        let info = engine.get_engine_info();
+        assert_eq!(info.engine_type, "Actor");
+        assert_eq!(info.account_count, 17);
+
+        // Two clients owned by different actors
+        let router = ConsistentHashRouter;
+        let a = ClientId::new(1);
+        let b = (2..)
+            .map(ClientId::new)
+            .find(|client| router.route(*client, 4) != router.route(a, 4))
+            .unwrap();
+        let input = format!(
+            "type,client,tx,amount\ndeposit,{a},5000,1.0\ndeposit,{b},5000,2.0\n\
+             dispute,{b},5000,\ndeposit,{b},5001,3.0\n"
+        );
+        let mut engine = PaymentsEngine::new(EngineConfig::actor(4));
+        engine
+            .process_transactions_from_reader(input.as_bytes())
+            .unwrap();
+        // The reused ID and the dispute of another client's transaction are rejected
+        let account = engine.peek_account(b).unwrap().unwrap();
+        assert_eq!(account.available, Amount::new(3, 0));
+        assert_eq!(account.held, Amount::ZERO);
+        assert!(matches!(
+            engine.process_transaction(&Transaction::dispute(b, 5000)),
+            Err(PaymentsError::ClientIdMismatch { expected, found, .. })
+                if expected == a && found == b
+        ));
+
+        let mut engine = PaymentsEngine::new(EngineConfig::actor(4));
+        engine.set_error_policy(ErrorPolicy::Abort);
+        let err = engine
+            .process_transactions_from_reader(input.as_bytes())
+            .unwrap_err();
+        let err = err.downcast_ref::<PaymentsError>().unwrap();
+        assert_eq!(err.line(), Some(2));
+
+        // Changing the number of actors moves clients with their state
+        let mut engine = PaymentsEngine::new(EngineConfig::actor(4));
+        engine
+            .process_transactions_from_reader(input.as_bytes())
+            .unwrap();
+        engine.set_num_workers(3);
+        assert_eq!(
+            engine.peek_account(b).unwrap().unwrap().available,
+            Amount::new(3, 0)
+        );
+        assert!(
+            engine
+                .process_transaction(&Transaction::deposit(b, 5000, Amount::ONE))
+                .is_err()
+        );
     }
 }
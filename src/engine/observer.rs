@@ -0,0 +1,235 @@
+//! Callbacks fired after an engine has processed a transaction.
+//!
+//! Observers run synchronously on the processing thread (a concurrent engine's worker,
+//! while it holds the engine lock), so they should return quickly. `ChannelObserver`
+//! forwards every event to a receiver on another thread instead.
+
+use std::fmt::Debug;
+use std::sync::{Arc, mpsc};
+
+use super::auth::{Credentials, Identity};
+use crate::account::Account;
+use crate::errors::PaymentsError;
+use crate::transaction::{Amount, Transaction, TxId};
+
+/// Receives engine events. Every callback has an empty default, so implementors only
+/// override what they need.
+pub trait EngineObserver: Debug + Send + Sync {
+    /// The transaction was applied
+    fn on_accepted(&self, _transaction: &Transaction) {}
+
+    /// The transaction was rejected and left no changes behind
+    fn on_rejected(&self, _transaction: &Transaction, _error: &PaymentsError) {}
+
+    /// A chargeback locked `account`; called after `on_accepted`
+    fn on_account_locked(&self, _account: &Account, _transaction: &Transaction) {}
+
+    /// A dispute put `amount` on hold; called after `on_accepted`
+    fn on_dispute_opened(&self, _transaction: &Transaction, _amount: Amount) {}
+
+    /// An accepted transaction changed an account's balances or lock; called after
+    /// `on_accepted`
+    fn on_account_changed(&self, _delta: &AccountDelta) {}
+
+    /// A dispute arrived for a transaction past its dispute window; called after
+    /// `on_rejected`
+    fn on_dispute_expired(&self, _transaction: &Transaction) {}
+
+    /// An authenticated stream submitted a transaction for a client `identity` may not
+    /// submit for; called after `on_rejected`
+    fn on_unauthorized(&self, _identity: &Identity, _transaction: &Transaction) {}
+
+    /// A stream's credentials were rejected before any of its rows were read
+    fn on_authentication_failed(&self, _credentials: &Credentials, _error: &PaymentsError) {}
+}
+
+/// How one transaction changed one account
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountDelta {
+    /// `tx` of the row that caused the change; for disputes, resolves and chargebacks
+    /// this is the disputed transaction. `None` for deltas between two snapshots.
+    pub tx: Option<TxId>,
+    /// The account after the change
+    pub account: Account,
+    pub available_change: Amount,
+    pub held_change: Amount,
+    pub total_change: Amount,
+}
+
+impl AccountDelta {
+    /// Delta between two states of an account; `before` is `None` for a new account
+    pub fn new(tx: Option<TxId>, before: Option<Account>, after: &Account) -> Self {
+        let before = before.unwrap_or_else(|| Account::new(after.client));
+        Self {
+            tx,
+            account: after.clone(),
+            available_change: after.available - before.available,
+            held_change: after.held - before.held,
+            total_change: after.total - before.total,
+        }
+    }
+}
+
+/// Observers notified in the order they were registered.
+#[derive(Debug, Clone, Default)]
+pub struct ObserverList {
+    observers: Vec<Arc<dyn EngineObserver>>,
+}
+
+impl ObserverList {
+    pub fn push(&mut self, observer: impl EngineObserver + 'static) {
+        self.observers.push(Arc::new(observer));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.observers.is_empty()
+    }
+
+    pub fn accepted(&self, transaction: &Transaction) {
+        for observer in &self.observers {
+            observer.on_accepted(transaction);
+        }
+    }
+
+    pub fn rejected(&self, transaction: &Transaction, error: &PaymentsError) {
+        for observer in &self.observers {
+            observer.on_rejected(transaction, error);
+        }
+    }
+
+    pub fn account_locked(&self, account: &Account, transaction: &Transaction) {
+        for observer in &self.observers {
+            observer.on_account_locked(account, transaction);
+        }
+    }
+
+    pub fn dispute_opened(&self, transaction: &Transaction, amount: Amount) {
+        for observer in &self.observers {
+            observer.on_dispute_opened(transaction, amount);
+        }
+    }
+
+    pub fn account_changed(&self, delta: &AccountDelta) {
+        for observer in &self.observers {
+            observer.on_account_changed(delta);
+        }
+    }
+
+    pub fn dispute_expired(&self, transaction: &Transaction) {
+        for observer in &self.observers {
+            observer.on_dispute_expired(transaction);
+        }
+    }
+
+    pub fn unauthorized(&self, identity: &Identity, transaction: &Transaction) {
+        for observer in &self.observers {
+            observer.on_unauthorized(identity, transaction);
+        }
+    }
+
+    pub fn authentication_failed(&self, credentials: &Credentials, error: &PaymentsError) {
+        for observer in &self.observers {
+            observer.on_authentication_failed(credentials, error);
+        }
+    }
+}
+
+/// An observer callback as a value, for `ChannelObserver`
+#[derive(Debug, Clone)]
+pub enum EngineEvent {
+    Accepted(Transaction),
+    /// The rejected transaction and the error's message
+    Rejected(Transaction, String),
+    AccountLocked(Account, Transaction),
+    DisputeOpened(Transaction, Amount),
+    AccountChanged(AccountDelta),
+    DisputeExpired(Transaction),
+    /// The identity's name and the transaction it was refused
+    Unauthorized(String, Transaction),
+    /// The authentication method and the error's message
+    AuthenticationFailed(&'static str, String),
+}
+
+/// Sends every event to an `mpsc` channel. Events are dropped once the receiver is gone.
+#[derive(Debug, Clone)]
+pub struct ChannelObserver {
+    sender: mpsc::Sender<EngineEvent>,
+}
+
+impl ChannelObserver {
+    /// Create an observer and the receiving end of its channel
+    pub fn new() -> (Self, mpsc::Receiver<EngineEvent>) {
+        let (sender, receiver) = mpsc::channel();
+        (Self { sender }, receiver)
+    }
+
+    fn send(&self, event: EngineEvent) {
+        let _ = self.sender.send(event);
+    }
+}
+
+impl EngineObserver for ChannelObserver {
+    fn on_accepted(&self, transaction: &Transaction) {
+        self.send(EngineEvent::Accepted(transaction.clone()));
+    }
+
+    fn on_rejected(&self, transaction: &Transaction, error: &PaymentsError) {
+        self.send(EngineEvent::Rejected(
+            transaction.clone(),
+            error.to_string(),
+        ));
+    }
+
+    fn on_account_locked(&self, account: &Account, transaction: &Transaction) {
+        self.send(EngineEvent::AccountLocked(
+            account.clone(),
+            transaction.clone(),
+        ));
+    }
+
+    fn on_dispute_opened(&self, transaction: &Transaction, amount: Amount) {
+        self.send(EngineEvent::DisputeOpened(transaction.clone(), amount));
+    }
+
+    fn on_account_changed(&self, delta: &AccountDelta) {
+        self.send(EngineEvent::AccountChanged(delta.clone()));
+    }
+
+    fn on_dispute_expired(&self, transaction: &Transaction) {
+        self.send(EngineEvent::DisputeExpired(transaction.clone()));
+    }
+
+    fn on_unauthorized(&self, identity: &Identity, transaction: &Transaction) {
+        self.send(EngineEvent::Unauthorized(
+            identity.name.clone(),
+            transaction.clone(),
+        ));
+    }
+
+    fn on_authentication_failed(&self, credentials: &Credentials, error: &PaymentsError) {
+        self.send(EngineEvent::AuthenticationFailed(
+            credentials.method(),
+            error.to_string(),
+        ));
+    }
+}
+
+/// Forwards only account changes; see `PaymentsEngine::subscribe_account_changes`
+#[derive(Debug, Clone)]
+pub struct AccountChangeObserver {
+    sender: mpsc::Sender<AccountDelta>,
+}
+
+impl AccountChangeObserver {
+    /// Create an observer and the receiving end of its channel
+    pub fn new() -> (Self, mpsc::Receiver<AccountDelta>) {
+        let (sender, receiver) = mpsc::channel();
+        (Self { sender }, receiver)
+    }
+}
+
+impl EngineObserver for AccountChangeObserver {
+    fn on_account_changed(&self, delta: &AccountDelta) {
+        let _ = self.sender.send(delta.clone());
+    }
+}
@@ -0,0 +1,69 @@
+//! Per-client ordering check for the engines that apply rows on several threads.
+//!
+//! Every input row is tagged with its line number when it is read. The concurrent and
+//! actor engines promise to apply each client's rows in input order, whichever thread
+//! applies them, so a dispute can never overtake the deposit it refers to. `ClientOrder`
+//! holds them to that promise: it remembers the last line applied for each client and
+//! refuses a row of the same client that comes earlier in the input.
+
+use super::sync::{AtomicU64, Ordering};
+use crate::account::ClientId;
+use crate::errors::PaymentsError;
+
+/// Last input line applied for each client during one reader-based run
+#[derive(Debug)]
+pub(crate) struct ClientOrder {
+    /// By client ID; 0 before any row of the client is applied
+    last_line: Vec<AtomicU64>,
+}
+
+impl ClientOrder {
+    pub(crate) fn new() -> Self {
+        Self {
+            last_line: (0..=usize::from(ClientId::MAX.get()))
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+        }
+    }
+
+    /// Record that line `line` of `client` is about to be applied; an `OutOfOrder` error,
+    /// attributed to `line`, if a later line of the client was applied already
+    pub(crate) fn check(&self, client: ClientId, line: u64) -> Result<(), PaymentsError> {
+        let last = self.last_line[usize::from(client.get())].fetch_max(line, Ordering::AcqRel);
+        if last >= line {
+            return Err(PaymentsError::OutOfOrder {
+                client,
+                after: last,
+            }
+            .at_line(line));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_order() {
+        let order = ClientOrder::new();
+        let (a, b) = (ClientId::new(1), ClientId::new(2));
+        order.check(a, 1).unwrap();
+        order.check(b, 3).unwrap();
+        // Clients are ordered independently of each other
+        order.check(a, 2).unwrap();
+        order.check(a, 5).unwrap();
+
+        let error = order.check(a, 4).unwrap_err();
+        assert_eq!(error.code(), 504);
+        assert_eq!(error.client(), Some(a));
+        assert_eq!(error.line(), Some(4));
+        assert_eq!(
+            error.to_string(),
+            "line 4: Transaction for client 1 arrived out of order, after line 5"
+        );
+        // A row applied twice is out of order too
+        assert!(order.check(b, 3).is_err());
+    }
+}
@@ -0,0 +1,269 @@
+//! Stages of `ConcurrentEngine::process_transactions_from_reader`.
+//!
+//! Input goes through three stages joined by bounded channels:
+//!
+//! 1. read: the calling thread splits the input into CSV records, in batches
+//! 2. parse: a pool of threads turns each batch of records into transactions
+//! 3. apply: a dispatcher puts the batches back in input order and queues each
+//!    transaction on its client's worker, which applies it to the shared state
+//!
+//! A stage whose output channel is full waits for the next stage to catch up, so a slow
+//! stage holds back the ones before it instead of letting batches pile up in memory.
+//! `PipelineStats` says how long each stage worked and how long it waited on the others.
+
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::sync::mpsc::{Receiver, SyncSender};
+use std::time::{Duration, Instant};
+
+use super::parse_transaction;
+use super::sync::{Arc, AtomicU64, Mutex, Ordering};
+use crate::errors::PaymentsError;
+use crate::shutdown::ShutdownFlag;
+use crate::transaction::{InputFormat, Transaction};
+
+/// Rows per batch handed from one stage to the next
+pub(crate) const BATCH_ROWS: usize = 256;
+
+/// Batches each channel holds per parse thread before its sender waits
+pub(crate) const BATCHES_PER_PARSER: usize = 2;
+
+/// Records queued on workers but not yet applied before the dispatcher waits
+pub(crate) const MAX_IN_FLIGHT: usize = 64 * 1024;
+
+/// Records read by stage 1, starting at input row `first_row` (0-based)
+#[derive(Debug)]
+pub(crate) struct RawBatch {
+    pub(crate) first_row: usize,
+    pub(crate) records: Vec<Result<csv::StringRecord, PaymentsError>>,
+}
+
+/// The rows of one `RawBatch` after stage 2
+#[derive(Debug)]
+pub(crate) struct ParsedBatch {
+    pub(crate) first_row: usize,
+    pub(crate) rows: Vec<Result<Transaction, PaymentsError>>,
+}
+
+/// Time one stage spent on the rows of a `process_transactions_from_reader` call
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StageStats {
+    /// Rows that went through the stage
+    pub rows: u64,
+    /// Time spent working, summed over the stage's threads
+    pub busy: Duration,
+    /// Time spent waiting for rows from the previous stage
+    pub input_wait: Duration,
+    /// Time spent waiting for the next stage to take rows (backpressure)
+    pub output_wait: Duration,
+}
+
+/// Per-stage activity of the most recent (or still running)
+/// `process_transactions_from_reader` call. The stage that is busy while the others wait
+/// on it is the one limiting throughput.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipelineStats {
+    /// Splitting the input into records
+    pub read: StageStats,
+    /// Turning records into transactions
+    pub parse: StageStats,
+    /// Applying transactions to the shared state. `input_wait` and `output_wait` are the
+    /// dispatcher's, `busy` is summed over the workers.
+    pub apply: StageStats,
+    /// Threads in the parse stage
+    pub parse_threads: usize,
+}
+
+/// Live counters behind one `StageStats`
+#[derive(Debug, Default)]
+pub(crate) struct StageCounters {
+    rows: AtomicU64,
+    busy: AtomicU64,
+    input_wait: AtomicU64,
+    output_wait: AtomicU64,
+}
+
+impl StageCounters {
+    pub(crate) fn add_rows(&self, rows: usize) {
+        self.rows.fetch_add(rows as u64, Ordering::Relaxed);
+    }
+
+    /// Run `f`, counting its time as work
+    pub(crate) fn busy<T>(&self, f: impl FnOnce() -> T) -> T {
+        timed(&self.busy, f)
+    }
+
+    /// Run `f`, counting its time as waiting for input
+    pub(crate) fn input_wait<T>(&self, f: impl FnOnce() -> T) -> T {
+        timed(&self.input_wait, f)
+    }
+
+    /// Run `f`, counting its time as waiting for the next stage
+    pub(crate) fn output_wait<T>(&self, f: impl FnOnce() -> T) -> T {
+        timed(&self.output_wait, f)
+    }
+
+    fn snapshot(&self) -> StageStats {
+        let duration = |nanos: &AtomicU64| Duration::from_nanos(nanos.load(Ordering::Relaxed));
+        StageStats {
+            rows: self.rows.load(Ordering::Relaxed),
+            busy: duration(&self.busy),
+            input_wait: duration(&self.input_wait),
+            output_wait: duration(&self.output_wait),
+        }
+    }
+}
+
+fn timed<T>(nanos: &AtomicU64, f: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = f();
+    nanos.fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    result
+}
+
+/// Live counters of one `process_transactions_from_reader` call
+#[derive(Debug, Default)]
+pub(crate) struct PipelineCounters {
+    pub(crate) read: StageCounters,
+    pub(crate) parse: StageCounters,
+    pub(crate) apply: StageCounters,
+    pub(crate) parse_threads: usize,
+}
+
+impl PipelineCounters {
+    pub(crate) fn new(parse_threads: usize) -> Self {
+        Self {
+            parse_threads,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> PipelineStats {
+        PipelineStats {
+            read: self.read.snapshot(),
+            parse: self.parse.snapshot(),
+            apply: self.apply.snapshot(),
+            parse_threads: self.parse_threads,
+        }
+    }
+}
+
+/// Stage 1: send the records of `rdr` to the parse stage in batches until the input ends,
+/// the parse stage stops or `shutdown` is requested. Returns the rows read.
+pub(crate) fn read_stage<R: Read>(
+    rdr: &mut csv::Reader<R>,
+    batches: SyncSender<RawBatch>,
+    shutdown: &ShutdownFlag,
+    counters: &StageCounters,
+) -> usize {
+    let mut records = rdr.records();
+    let mut rows = 0;
+    loop {
+        if shutdown.is_requested() {
+            log::warn!("Shutdown requested; stopping reading at line {}", rows + 1);
+            break;
+        }
+        let batch: Vec<_> = counters.busy(|| {
+            records
+                .by_ref()
+                .take(BATCH_ROWS)
+                .map(|record| record.map_err(PaymentsError::from))
+                .collect()
+        });
+        if batch.is_empty() {
+            break;
+        }
+        let batch = RawBatch {
+            first_row: rows,
+            records: batch,
+        };
+        rows += batch.records.len();
+        counters.add_rows(batch.records.len());
+        if counters.output_wait(|| batches.send(batch)).is_err() {
+            break;
+        }
+    }
+    rows
+}
+
+/// Stage 2, run on each parse thread: parse batches from `records` until the read stage
+/// is done or the apply stage stops
+pub(crate) fn parse_stage(
+    records: Arc<Mutex<Receiver<RawBatch>>>,
+    parsed: SyncSender<ParsedBatch>,
+    headers: &csv::StringRecord,
+    format: &InputFormat,
+    counters: &StageCounters,
+) {
+    loop {
+        let batch =
+            counters.input_wait(|| records.lock().unwrap_or_else(|e| e.into_inner()).recv());
+        let Ok(batch) = batch else {
+            break;
+        };
+        let rows: Vec<_> = counters.busy(|| {
+            batch
+                .records
+                .into_iter()
+                .map(|record| record.and_then(|record| parse_transaction(&record, headers, format)))
+                .collect()
+        });
+        counters.add_rows(rows.len());
+        let batch = ParsedBatch {
+            first_row: batch.first_row,
+            rows,
+        };
+        if counters.output_wait(|| parsed.send(batch)).is_err() {
+            break;
+        }
+    }
+}
+
+/// Puts batches finished out of order by the parse threads back in input order
+#[derive(Debug, Default)]
+pub(crate) struct Reorder {
+    next_row: usize,
+    waiting: BTreeMap<usize, ParsedBatch>,
+}
+
+impl Reorder {
+    /// Hold `batch` until every batch before it has been taken
+    pub(crate) fn push(&mut self, batch: ParsedBatch) {
+        self.waiting.insert(batch.first_row, batch);
+    }
+
+    /// The next batch in input order, if it has arrived
+    pub(crate) fn pop(&mut self) -> Option<ParsedBatch> {
+        let batch = self.waiting.remove(&self.next_row)?;
+        self.next_row += batch.rows.len();
+        Some(batch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch(first_row: usize, rows: usize) -> ParsedBatch {
+        ParsedBatch {
+            first_row,
+            rows: (0..rows)
+                .map(|_| Err(PaymentsError::InvalidTransaction("test".to_string())))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_reorder() {
+        let mut reorder = Reorder::default();
+        reorder.push(batch(2, 3));
+        assert!(reorder.pop().is_none());
+        reorder.push(batch(5, 1));
+        reorder.push(batch(0, 2));
+        let order: Vec<usize> = std::iter::from_fn(|| reorder.pop())
+            .map(|batch| batch.first_row)
+            .collect();
+        assert_eq!(order, vec![0, 2, 5]);
+        assert!(reorder.pop().is_none());
+    }
+}
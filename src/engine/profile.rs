@@ -0,0 +1,84 @@
+use std::collections::HashSet;
+use std::io::Read;
+
+use crate::account::ClientId;
+use crate::errors::PaymentsError;
+use crate::transaction::Transaction;
+
+/// Rows read from the start of a file when profiling it
+const SAMPLE_ROWS: usize = 10_000;
+
+/// Size and client cardinality of an input, estimated from a sample of its first rows.
+/// Used by `EngineConfig::auto_for_profile` to pick an engine.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputProfile {
+    /// Total size of the input in bytes
+    pub total_bytes: u64,
+    /// Rows in the sample, including ones that failed to parse
+    pub sampled_rows: usize,
+    /// Size of the header row in bytes
+    pub header_bytes: u64,
+    /// Bytes covered by the sample, header excluded
+    pub sampled_bytes: u64,
+    /// Distinct clients seen in the sample
+    pub sampled_clients: usize,
+}
+
+impl InputProfile {
+    /// Sample the first rows of a CSV input whose total size is `total_bytes`
+    pub fn from_reader<R: Read>(reader: R, total_bytes: u64) -> Result<Self, PaymentsError> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let headers = rdr.headers()?.clone();
+        let header_bytes = rdr.position().byte();
+
+        let mut clients: HashSet<ClientId> = HashSet::new();
+        let mut sampled_rows = 0;
+        let mut record = csv::StringRecord::new();
+
+        while sampled_rows < SAMPLE_ROWS && rdr.read_record(&mut record)? {
+            sampled_rows += 1;
+            if let Ok(transaction) = record.deserialize::<Transaction>(Some(&headers)) {
+                clients.insert(transaction.client);
+            }
+        }
+
+        Ok(Self {
+            total_bytes,
+            sampled_rows,
+            header_bytes,
+            sampled_bytes: rdr.position().byte() - header_bytes,
+            sampled_clients: clients.len(),
+        })
+    }
+
+    /// Sample the first rows of the CSV file at `path`
+    #[cfg(feature = "fs")]
+    pub fn from_file(path: &std::path::Path) -> Result<Self, PaymentsError> {
+        let file = std::fs::File::open(path)?;
+        let total_bytes = file.metadata()?.len();
+        Self::from_reader(std::io::BufReader::new(file), total_bytes)
+    }
+
+    /// Estimated number of rows in the whole input, from the sample's average row size
+    pub fn estimated_rows(&self) -> u64 {
+        if self.sampled_rows == 0 || self.sampled_bytes == 0 {
+            return self.sampled_rows as u64;
+        }
+        let bytes_per_row = self.sampled_bytes as f64 / self.sampled_rows as f64;
+        let data_bytes = self.total_bytes.saturating_sub(self.header_bytes);
+        ((data_bytes as f64 / bytes_per_row) as u64).max(self.sampled_rows as u64)
+    }
+
+    /// Upper estimate of the distinct clients in the whole input: the sample's count
+    /// extrapolated linearly, capped at the number of possible client IDs
+    pub fn estimated_clients(&self) -> usize {
+        if self.sampled_rows == 0 {
+            return 0;
+        }
+        let scale = self.estimated_rows() as f64 / self.sampled_rows as f64;
+        ((self.sampled_clients as f64 * scale) as usize)
+            .clamp(self.sampled_clients, usize::from(ClientId::MAX.get()) + 1)
+    }
+}
@@ -0,0 +1,259 @@
+//! Token-bucket rate limits for clients and streams.
+//!
+//! `ClientRateLimiter` is a `TransactionValidator`: once a client has used up its burst,
+//! its transactions are rejected with `PaymentsError::RateLimited` until tokens refill,
+//! so one runaway partner cannot monopolize the engine. Streams of
+//! `ConcurrentEngine::process_stream_transactions` can be limited as well with
+//! `ConcurrentEngine::set_stream_rate_limit`; a stream over its limit is slowed down
+//! before it takes the engine lock rather than having rows rejected.
+//!
+//! Limits use the monotonic clock. Where none is available (`wasm32-unknown-unknown`),
+//! nothing is limited.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "concurrent")]
+use std::time::Duration;
+use std::time::Instant;
+
+use super::metrics::start_timer;
+use super::validation::TransactionValidator;
+use crate::account::ClientId;
+use crate::errors::PaymentsError;
+use crate::transaction::Transaction;
+
+/// `rate` transactions per second on average, with bursts of up to `burst`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub rate: f64,
+    pub burst: f64,
+}
+
+impl RateLimit {
+    /// `rate` per second with a burst of one second's worth (at least one)
+    pub fn per_second(rate: f64) -> Self {
+        Self {
+            rate,
+            burst: rate.max(1.0),
+        }
+    }
+}
+
+/// Tokens left at the last update; starts full
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(limit: &RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate).min(limit.burst);
+        self.updated = now;
+    }
+
+    /// Take a token if one is left
+    fn try_take(&mut self, limit: &RateLimit, now: Instant) -> bool {
+        self.refill(limit, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Take a token, going into debt if none is left, and return how long to wait
+    /// until the debt is paid off
+    #[cfg(feature = "concurrent")]
+    fn take(&mut self, limit: &RateLimit, now: Instant) -> Duration {
+        self.refill(limit, now);
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 || limit.rate <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / limit.rate)
+        }
+    }
+}
+
+/// Transactions allowed and rejected by a `ClientRateLimiter`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimitStats {
+    pub allowed: u64,
+    pub limited: u64,
+}
+
+#[derive(Debug, Default)]
+struct ClientState {
+    buckets: HashMap<ClientId, Bucket>,
+    /// Rejections so far, by client
+    limited: HashMap<ClientId, u64>,
+    stats: RateLimitStats,
+}
+
+/// Rejects transactions of clients that exceed `limit`, each client with its own bucket.
+/// Clones share the same buckets and counters, so keep one to read `stats` after adding
+/// the limiter to an engine.
+#[derive(Debug, Clone)]
+pub struct ClientRateLimiter {
+    limit: RateLimit,
+    state: Arc<Mutex<ClientState>>,
+}
+
+impl ClientRateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            state: Arc::new(Mutex::new(ClientState::default())),
+        }
+    }
+
+    pub fn stats(&self) -> RateLimitStats {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).stats
+    }
+
+    /// Clients that were rejected at least once, with their rejection counts, most
+    /// rejected first
+    pub fn limited_clients(&self) -> Vec<(ClientId, u64)> {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let mut clients: Vec<_> = state.limited.iter().map(|(c, n)| (*c, *n)).collect();
+        clients.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        clients
+    }
+
+    /// Whether `client` may submit a transaction at `now`, taking a token if so
+    fn check_at(&self, client: ClientId, now: Instant) -> Result<(), PaymentsError> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let limit = &self.limit;
+        let allowed = state
+            .buckets
+            .entry(client)
+            .or_insert_with(|| Bucket::new(limit, now))
+            .try_take(limit, now);
+        if allowed {
+            state.stats.allowed += 1;
+            Ok(())
+        } else {
+            state.stats.limited += 1;
+            *state.limited.entry(client).or_default() += 1;
+            Err(PaymentsError::RateLimited(client))
+        }
+    }
+}
+
+impl TransactionValidator for ClientRateLimiter {
+    fn validate(&self, transaction: &Transaction) -> Result<(), PaymentsError> {
+        match start_timer() {
+            Some(now) => self.check_at(transaction.client, now),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Paces one stream to its limit by sleeping before each transaction once the burst is
+/// used up
+#[cfg(feature = "concurrent")]
+#[derive(Debug)]
+pub(crate) struct StreamThrottle {
+    limit: RateLimit,
+    bucket: Option<Bucket>,
+    /// Time spent sleeping so far
+    pub(crate) throttled: Duration,
+}
+
+#[cfg(feature = "concurrent")]
+impl StreamThrottle {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            bucket: None,
+            throttled: Duration::ZERO,
+        }
+    }
+
+    /// Block until the stream may submit its next transaction
+    pub(crate) fn wait(&mut self) {
+        let Some(now) = start_timer() else {
+            return;
+        };
+        let limit = &self.limit;
+        let wait = self
+            .bucket
+            .get_or_insert_with(|| Bucket::new(limit, now))
+            .take(limit, now);
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+            self.throttled += wait;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_client_rate_limit() {
+        let limiter = ClientRateLimiter::new(RateLimit {
+            rate: 10.0,
+            burst: 2.0,
+        });
+        let start = Instant::now();
+        let client = ClientId::new(1);
+
+        assert!(limiter.check_at(client, start).is_ok());
+        assert!(limiter.check_at(client, start).is_ok());
+        assert!(matches!(
+            limiter.check_at(client, start),
+            Err(PaymentsError::RateLimited(c)) if c == client
+        ));
+        // Other clients have their own buckets
+        assert!(limiter.check_at(ClientId::new(2), start).is_ok());
+        // One token refills every 100ms
+        assert!(
+            limiter
+                .check_at(client, start + Duration::from_millis(100))
+                .is_ok()
+        );
+        assert!(
+            limiter
+                .check_at(client, start + Duration::from_millis(150))
+                .is_err()
+        );
+
+        assert_eq!(
+            limiter.stats(),
+            RateLimitStats {
+                allowed: 4,
+                limited: 2
+            }
+        );
+        assert_eq!(limiter.limited_clients(), [(client, 2)]);
+    }
+
+    #[test]
+    #[cfg(feature = "concurrent")]
+    fn test_stream_throttle() {
+        let limit = RateLimit {
+            rate: 1000.0,
+            burst: 5.0,
+        };
+        let mut bucket = Bucket::new(&limit, Instant::now());
+        let now = bucket.updated;
+        for _ in 0..5 {
+            assert_eq!(bucket.take(&limit, now), Duration::ZERO);
+        }
+        assert_eq!(bucket.take(&limit, now), Duration::from_millis(1));
+        assert_eq!(bucket.take(&limit, now), Duration::from_millis(2));
+    }
+}
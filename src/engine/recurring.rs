@@ -0,0 +1,151 @@
+//! Recurring deposits and withdrawals expanded as processing reaches their timestamps.
+//!
+//! Before an engine applies a row with a `timestamp`, it first applies every recurring
+//! occurrence due at or before that time, earliest first. Occurrences after the last
+//! timestamped row are not generated. Each occurrence has a fixed ID, so replaying input
+//! after restoring a checkpoint rejects occurrences already applied as duplicates.
+
+use serde::Deserialize;
+
+use crate::account::ClientId;
+use crate::errors::PaymentsError;
+use crate::transaction::{Amount, Transaction, TransactionType, TxId};
+
+/// First ID of the range reserved for recurring transactions by default
+pub const DEFAULT_FIRST_RECURRING_TX_ID: TxId = TxId::new(3_000_000_000);
+
+/// A deposit or withdrawal repeated at a fixed interval
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecurringTransaction {
+    pub client: ClientId,
+    /// `deposit` or `withdrawal`
+    #[serde(rename = "type")]
+    pub tx_type: TransactionType,
+    pub amount: Amount,
+    /// First occurrence, in seconds since the Unix epoch
+    pub start: u64,
+    /// Seconds between occurrences
+    pub interval_secs: u64,
+    /// No occurrences after this timestamp
+    #[serde(default)]
+    pub end: Option<u64>,
+}
+
+/// Recurring transactions and the IDs reserved for them.
+///
+/// ```toml
+/// [recurring]
+/// first_tx_id = 3000000000
+///
+/// [[recurring.transactions]]
+/// client = 7
+/// type = "withdrawal"
+/// amount = "9.99"
+/// start = 1709251200
+/// interval_secs = 2592000
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecurringSchedule {
+    /// Occurrence `n` of entry `i` gets ID `first_tx_id + n * entries + i`; input rows
+    /// should stay below this
+    pub first_tx_id: TxId,
+    pub transactions: Vec<RecurringTransaction>,
+}
+
+impl Default for RecurringSchedule {
+    fn default() -> Self {
+        Self {
+            first_tx_id: DEFAULT_FIRST_RECURRING_TX_ID,
+            transactions: Vec::new(),
+        }
+    }
+}
+
+/// A `RecurringSchedule` with the progress of each entry.
+#[derive(Debug, Clone, Default)]
+pub struct RecurringScheduler {
+    first_tx_id: TxId,
+    entries: Vec<RecurringTransaction>,
+    /// Occurrences generated so far, per entry
+    occurrences: Vec<u64>,
+}
+
+impl RecurringScheduler {
+    /// Check every entry and start the schedule from its first occurrences
+    pub fn new(schedule: RecurringSchedule) -> Result<Self, PaymentsError> {
+        for (idx, entry) in schedule.transactions.iter().enumerate() {
+            let problem = if !matches!(
+                entry.tx_type,
+                TransactionType::Deposit | TransactionType::Withdrawal
+            ) {
+                Some("type must be deposit or withdrawal")
+            } else if entry.amount <= Amount::ZERO {
+                Some("amount must be positive")
+            } else if entry.interval_secs == 0 {
+                Some("interval_secs must be positive")
+            } else {
+                None
+            };
+            if let Some(problem) = problem {
+                return Err(PaymentsError::ConfigError(format!(
+                    "recurring transaction {}: {}",
+                    idx + 1,
+                    problem
+                )));
+            }
+        }
+        Ok(Self {
+            first_tx_id: schedule.first_tx_id,
+            occurrences: vec![0; schedule.transactions.len()],
+            entries: schedule.transactions,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Occurrences due at or before `now` that have not been generated yet, earliest
+    /// first. Occurrences whose ID would not fit in a `TxId` are never generated.
+    pub fn due(&mut self, now: u64) -> Vec<Transaction> {
+        let mut due = Vec::new();
+        loop {
+            let next = (0..self.entries.len())
+                .filter_map(|idx| self.next_occurrence(idx).map(|at| (at, idx)))
+                .filter(|(at, _)| *at <= now)
+                .min();
+            let Some((at, idx)) = next else {
+                break;
+            };
+            let entry = &self.entries[idx];
+            let id = u64::from(self.first_tx_id.get())
+                + self.occurrences[idx] * self.entries.len() as u64
+                + idx as u64;
+            self.occurrences[idx] += 1;
+            let Ok(tx) = u32::try_from(id).map(TxId::new) else {
+                log::warn!("Recurring transaction IDs exhausted for entry {}", idx + 1);
+                self.occurrences[idx] = u64::MAX;
+                continue;
+            };
+            due.push(
+                Transaction::new(entry.tx_type.clone(), entry.client, tx, Some(entry.amount))
+                    .at(at),
+            );
+        }
+        due
+    }
+
+    /// Timestamp of the entry's next occurrence, or `None` once it has ended
+    fn next_occurrence(&self, idx: usize) -> Option<u64> {
+        let entry = &self.entries[idx];
+        let at = self.occurrences[idx]
+            .checked_mul(entry.interval_secs)
+            .and_then(|offset| entry.start.checked_add(offset))?;
+        match entry.end {
+            Some(end) if at > end => None,
+            _ => Some(at),
+        }
+    }
+}
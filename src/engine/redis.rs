@@ -0,0 +1,524 @@
+//! `SharedStore` on a Redis server, spoken to over RESP on a plain TCP connection.
+//!
+//! Keys are namespaced by a prefix, so several independent ledgers can share a server:
+//!
+//! | Key | Type | Holds |
+//! |-----|------|-------|
+//! | `{prefix}:account:{client}` | string | the account, as JSON |
+//! | `{prefix}:clients` | set | clients with an account |
+//! | `{prefix}:tx:{tx}` | string | the disputable transaction record, as JSON |
+//! | `{prefix}:txs` | set | transaction IDs with a record |
+//! | `{prefix}:processed` | set | processed transaction IDs |
+//! | `{prefix}:disputed:{client}` | set | the client's transactions under dispute |
+//!
+//! Commits run as one Lua script, which Redis executes atomically: it compares the
+//! account and record with the values the change was computed from and adds the ID to
+//! the processed set before writing anything.
+
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use super::shared::{SharedChange, SharedSlice, SharedStore, needs_open_disputes};
+use super::state::EngineState;
+use crate::account::{Account, ClientId};
+use crate::errors::PaymentsError;
+use crate::transaction::{StoredTransaction, Transaction, TxId};
+
+/// Keys and arguments of `COMMIT_SCRIPT` are listed in `RedisStore::commit`
+const COMMIT_SCRIPT: &str = r#"
+if (redis.call('GET', KEYS[1]) or '') ~= ARGV[1] then return 0 end
+if (redis.call('GET', KEYS[2]) or '') ~= ARGV[3] then return 0 end
+if ARGV[7] == '1' and redis.call('SADD', KEYS[3], ARGV[5]) == 0 then return 0 end
+if ARGV[2] ~= '' then
+    redis.call('SET', KEYS[1], ARGV[2])
+    redis.call('SADD', KEYS[5], ARGV[6])
+end
+if ARGV[4] == '' then
+    redis.call('DEL', KEYS[2])
+    redis.call('SREM', KEYS[6], ARGV[5])
+else
+    redis.call('SET', KEYS[2], ARGV[4])
+    redis.call('SADD', KEYS[6], ARGV[5])
+end
+if ARGV[8] == '1' then
+    redis.call('SADD', KEYS[4], ARGV[5])
+elseif ARGV[8] == '-1' then
+    redis.call('SREM', KEYS[4], ARGV[5])
+end
+return 1
+"#;
+
+/// Keys read per `MGET` when listing the whole store
+const MGET_CHUNK: usize = 1000;
+
+/// A RESP reply
+#[derive(Debug, Clone, PartialEq)]
+enum Reply {
+    Status(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Reply>>),
+}
+
+impl Reply {
+    fn integer(self) -> std::io::Result<i64> {
+        match self {
+            Self::Integer(n) => Ok(n),
+            other => Err(unexpected(&other)),
+        }
+    }
+
+    fn bulk(self) -> std::io::Result<Option<Vec<u8>>> {
+        match self {
+            Self::Bulk(value) => Ok(value),
+            other => Err(unexpected(&other)),
+        }
+    }
+
+    fn array(self) -> std::io::Result<Vec<Reply>> {
+        match self {
+            Self::Array(items) => Ok(items.unwrap_or_default()),
+            other => Err(unexpected(&other)),
+        }
+    }
+}
+
+fn unexpected(reply: &Reply) -> std::io::Error {
+    std::io::Error::other(format!("unexpected Redis reply: {:?}", reply))
+}
+
+/// Write one command as a RESP array of bulk strings
+fn write_command(writer: &mut impl Write, args: &[&[u8]]) -> std::io::Result<()> {
+    write!(writer, "*{}\r\n", args.len())?;
+    for arg in args {
+        write!(writer, "${}\r\n", arg.len())?;
+        writer.write_all(arg)?;
+        writer.write_all(b"\r\n")?;
+    }
+    Ok(())
+}
+
+/// Read one reply; an error reply becomes an `io::Error` with the server's message
+fn read_reply(reader: &mut impl BufRead) -> std::io::Result<Reply> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    let line = line.trim_end_matches("\r\n");
+    let (kind, rest) = line.split_at_checked(1).unwrap_or(("", ""));
+    let length = || -> std::io::Result<i64> {
+        rest.parse()
+            .map_err(|_| std::io::Error::other(format!("bad Redis reply line: {}", line)))
+    };
+    match kind {
+        "+" => Ok(Reply::Status(rest.to_string())),
+        "-" => Err(std::io::Error::other(rest.to_string())),
+        ":" => Ok(Reply::Integer(length()?)),
+        "$" => match length()? {
+            -1 => Ok(Reply::Bulk(None)),
+            len => {
+                let mut value = vec![0; len as usize + 2];
+                reader.read_exact(&mut value)?;
+                value.truncate(len as usize);
+                Ok(Reply::Bulk(Some(value)))
+            }
+        },
+        "*" => match length()? {
+            -1 => Ok(Reply::Array(None)),
+            len => (0..len)
+                .map(|_| read_reply(reader))
+                .collect::<std::io::Result<_>>()
+                .map(|items| Reply::Array(Some(items))),
+        },
+        _ => Err(std::io::Error::other(format!(
+            "bad Redis reply line: {}",
+            line
+        ))),
+    }
+}
+
+fn to_json<T: Serialize>(value: Option<&T>) -> Result<Vec<u8>, PaymentsError> {
+    match value {
+        Some(value) => serde_json::to_vec(value)
+            .map_err(|e| PaymentsError::InvalidAccount(format!("cannot encode: {}", e))),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn from_json<T: DeserializeOwned>(value: Option<Vec<u8>>) -> Result<Option<T>, PaymentsError> {
+    value
+        .map(|value| {
+            serde_json::from_slice(&value)
+                .map_err(|e| PaymentsError::InvalidAccount(format!("bad value in Redis: {}", e)))
+        })
+        .transpose()
+}
+
+fn parse_id<T: std::str::FromStr>(value: &Reply) -> Result<T, PaymentsError> {
+    match value {
+        Reply::Bulk(Some(value)) => std::str::from_utf8(value)
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .ok_or_else(|| {
+                PaymentsError::InvalidAccount(format!(
+                    "bad ID in Redis: {}",
+                    String::from_utf8_lossy(value)
+                ))
+            }),
+        other => Err(unexpected(other).into()),
+    }
+}
+
+/// `SharedStore` on a Redis server
+#[derive(Debug)]
+pub struct RedisStore {
+    address: String,
+    prefix: String,
+    writer: BufWriter<TcpStream>,
+    reader: BufReader<TcpStream>,
+    /// SHA1 of `COMMIT_SCRIPT` as loaded on the server
+    script: String,
+}
+
+impl RedisStore {
+    /// Connect to the server at `address` (`host:port`) and keep state under keys
+    /// starting with `prefix:`
+    pub fn connect(address: &str, prefix: &str) -> Result<Self, PaymentsError> {
+        let (writer, reader) = Self::open(address)?;
+        let mut store = Self {
+            address: address.to_string(),
+            prefix: prefix.to_string(),
+            writer,
+            reader,
+            script: String::new(),
+        };
+        store.load_script()?;
+        Ok(store)
+    }
+
+    fn open(address: &str) -> std::io::Result<(BufWriter<TcpStream>, BufReader<TcpStream>)> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        stream.set_nodelay(true)?;
+        let reader = BufReader::new(stream.try_clone()?);
+        Ok((BufWriter::new(stream), reader))
+    }
+
+    /// Re-establish the connection after an error, e.g. a server restart
+    pub fn reconnect(&mut self) -> Result<(), PaymentsError> {
+        (self.writer, self.reader) = Self::open(&self.address)?;
+        self.load_script()
+    }
+
+    fn load_script(&mut self) -> Result<(), PaymentsError> {
+        let sha = self
+            .command(&[b"SCRIPT", b"LOAD", COMMIT_SCRIPT.as_bytes()])?
+            .bulk()?
+            .unwrap_or_default();
+        self.script = String::from_utf8_lossy(&sha).into_owned();
+        Ok(())
+    }
+
+    fn key(&self, parts: std::fmt::Arguments) -> Vec<u8> {
+        format!("{}:{}", self.prefix, parts).into_bytes()
+    }
+
+    /// Send `commands` at once and read their replies in order
+    fn pipeline(&mut self, commands: &[Vec<&[u8]>]) -> std::io::Result<Vec<Reply>> {
+        for args in commands {
+            write_command(&mut self.writer, args)?;
+        }
+        self.writer.flush()?;
+        // Read every reply even after an error reply, so the connection stays in step
+        let replies: Vec<_> = commands
+            .iter()
+            .map(|_| read_reply(&mut self.reader))
+            .collect();
+        replies.into_iter().collect()
+    }
+
+    fn command(&mut self, args: &[&[u8]]) -> std::io::Result<Reply> {
+        let mut replies = self.pipeline(&[args.to_vec()])?;
+        Ok(replies.remove(0))
+    }
+
+    /// Values of `keys`, read `MGET_CHUNK` at a time
+    fn mget(&mut self, keys: &[Vec<u8>]) -> std::io::Result<Vec<Option<Vec<u8>>>> {
+        let mut values = Vec::with_capacity(keys.len());
+        for chunk in keys.chunks(MGET_CHUNK) {
+            let mut args: Vec<&[u8]> = vec![b"MGET"];
+            args.extend(chunk.iter().map(Vec::as_slice));
+            for value in self.command(&args)?.array()? {
+                values.push(value.bulk()?);
+            }
+        }
+        Ok(values)
+    }
+
+    fn members(&mut self, key: &[u8]) -> std::io::Result<Vec<Reply>> {
+        self.command(&[b"SMEMBERS", key])?.array()
+    }
+
+    /// Records stored under `ids`, skipping IDs whose record is gone
+    fn records(&mut self, ids: Vec<TxId>) -> Result<Vec<(TxId, StoredTransaction)>, PaymentsError> {
+        let keys: Vec<_> = ids
+            .iter()
+            .map(|tx| self.key(format_args!("tx:{}", tx)))
+            .collect();
+        let mut records = Vec::with_capacity(ids.len());
+        for (tx, value) in ids.into_iter().zip(self.mget(&keys)?) {
+            if let Some(record) = from_json(value)? {
+                records.push((tx, record));
+            }
+        }
+        Ok(records)
+    }
+}
+
+impl SharedStore for RedisStore {
+    fn load(&mut self, transaction: &Transaction) -> Result<SharedSlice, PaymentsError> {
+        let account = self.key(format_args!("account:{}", transaction.client));
+        let record = self.key(format_args!("tx:{}", transaction.tx));
+        let processed = self.key(format_args!("processed"));
+        let tx = transaction.tx.to_string();
+        let mut replies = self
+            .pipeline(&[
+                vec![b"GET", &account],
+                vec![b"GET", &record],
+                vec![b"SISMEMBER", &processed, tx.as_bytes()],
+            ])?
+            .into_iter();
+        let mut next = || {
+            replies
+                .next()
+                .ok_or_else(|| unexpected(&Reply::Array(None)))
+        };
+        let mut slice = SharedSlice {
+            account: from_json(next()?.bulk()?)?,
+            record: from_json(next()?.bulk()?)?,
+            processed: next()?.integer()? == 1,
+            open_disputes: Vec::new(),
+        };
+        if needs_open_disputes(transaction) {
+            let disputed = self.key(format_args!("disputed:{}", transaction.client));
+            let ids = self
+                .members(&disputed)?
+                .iter()
+                .map(parse_id)
+                .collect::<Result<_, _>>()?;
+            slice.open_disputes = self.records(ids)?;
+        }
+        Ok(slice)
+    }
+
+    /// Runs `COMMIT_SCRIPT` with keys account, record, processed set, the record's
+    /// client's disputed set, clients set and records set, and arguments expected
+    /// account, new account, expected record, new record, transaction ID, client ID,
+    /// whether to mark the ID processed and the change to the disputed set (1, -1 or 0).
+    /// An empty value stands for a missing key.
+    fn commit(&mut self, change: &SharedChange) -> Result<bool, PaymentsError> {
+        let record_client = change
+            .record
+            .as_ref()
+            .or(change.before.record.as_ref())
+            .map_or(change.client, |record| record.client);
+        let disputed = |record: &Option<StoredTransaction>| {
+            record.as_ref().is_some_and(|record| record.disputed)
+        };
+        let dispute_change = match (disputed(&change.before.record), disputed(&change.record)) {
+            (false, true) => "1",
+            (true, false) => "-1",
+            _ => "0",
+        };
+        let keys = [
+            self.key(format_args!("account:{}", change.client)),
+            self.key(format_args!("tx:{}", change.tx)),
+            self.key(format_args!("processed")),
+            self.key(format_args!("disputed:{}", record_client)),
+            self.key(format_args!("clients")),
+            self.key(format_args!("txs")),
+        ];
+        let values = [
+            to_json(change.before.account.as_ref())?,
+            to_json(change.account.as_ref())?,
+            to_json(change.before.record.as_ref())?,
+            to_json(change.record.as_ref())?,
+        ];
+        let (tx, client) = (change.tx.to_string(), change.client.to_string());
+        let processed: &[u8] = if change.processed { b"1" } else { b"0" };
+        let args = |command: &'static [u8], script: &[u8]| {
+            let mut args: Vec<Vec<u8>> = vec![command.to_vec(), script.to_vec(), b"6".to_vec()];
+            args.extend(keys.iter().cloned());
+            args.extend(values.iter().cloned());
+            args.extend([
+                tx.clone().into_bytes(),
+                client.clone().into_bytes(),
+                processed.to_vec(),
+                dispute_change.as_bytes().to_vec(),
+            ]);
+            args
+        };
+
+        let evalsha = args(b"EVALSHA", self.script.as_bytes());
+        let evalsha: Vec<&[u8]> = evalsha.iter().map(Vec::as_slice).collect();
+        let reply = match self.command(&evalsha) {
+            // The server lost its script cache, e.g. after a restart
+            Err(e) if e.to_string().starts_with("NOSCRIPT") => {
+                let eval = args(b"EVAL", COMMIT_SCRIPT.as_bytes());
+                let eval: Vec<&[u8]> = eval.iter().map(Vec::as_slice).collect();
+                self.command(&eval)?
+            }
+            reply => reply?,
+        };
+        Ok(reply.integer()? == 1)
+    }
+
+    fn account(&mut self, client: ClientId) -> Result<Option<Account>, PaymentsError> {
+        let key = self.key(format_args!("account:{}", client));
+        from_json(self.command(&[b"GET", &key])?.bulk()?)
+    }
+
+    fn accounts(&mut self) -> Result<Vec<Account>, PaymentsError> {
+        let clients = self.key(format_args!("clients"));
+        let keys: Vec<_> = self
+            .members(&clients)?
+            .iter()
+            .map(|client| {
+                let client: ClientId = parse_id(client)?;
+                Ok(self.key(format_args!("account:{}", client)))
+            })
+            .collect::<Result<_, PaymentsError>>()?;
+        self.mget(&keys)?
+            .into_iter()
+            .filter_map(|value| from_json(value).transpose())
+            .collect()
+    }
+
+    fn export_state(&mut self) -> Result<EngineState, PaymentsError> {
+        let accounts = self.accounts()?;
+        let txs = self.key(format_args!("txs"));
+        let ids = self
+            .members(&txs)?
+            .iter()
+            .map(parse_id)
+            .collect::<Result<_, _>>()?;
+        let disputable_transactions = self.records(ids)?;
+        let processed = self.key(format_args!("processed"));
+        let processed_tx_ids = self
+            .members(&processed)?
+            .iter()
+            .map(parse_id)
+            .collect::<Result<_, _>>()?;
+        Ok(EngineState {
+            accounts,
+            disputable_transactions,
+            processed_tx_ids,
+            idempotency_keys: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::shared::SharedEngine;
+    use crate::transaction::Amount;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_resp() {
+        let mut command = Vec::new();
+        write_command(&mut command, &[b"GET", b"payments:account:1"]).unwrap();
+        assert_eq!(command, b"*2\r\n$3\r\nGET\r\n$18\r\npayments:account:1\r\n");
+
+        let mut replies: &[u8] =
+            b"+OK\r\n:1\r\n$-1\r\n$5\r\nhello\r\n*2\r\n$1\r\n7\r\n$-1\r\n-NOSCRIPT missing\r\n";
+        assert_eq!(
+            read_reply(&mut replies).unwrap(),
+            Reply::Status("OK".into())
+        );
+        assert_eq!(read_reply(&mut replies).unwrap(), Reply::Integer(1));
+        assert_eq!(read_reply(&mut replies).unwrap(), Reply::Bulk(None));
+        assert_eq!(
+            read_reply(&mut replies).unwrap(),
+            Reply::Bulk(Some(b"hello".to_vec()))
+        );
+        assert_eq!(
+            read_reply(&mut replies).unwrap(),
+            Reply::Array(Some(vec![
+                Reply::Bulk(Some(b"7".to_vec())),
+                Reply::Bulk(None)
+            ]))
+        );
+        let error = read_reply(&mut replies).unwrap_err();
+        assert_eq!(error.to_string(), "NOSCRIPT missing");
+        assert_eq!(
+            read_reply(&mut replies).unwrap_err().kind(),
+            std::io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn test_redis_store() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut commands = Vec::new();
+            // SCRIPT LOAD, then GET, GET and SISMEMBER for the load, then EVALSHA
+            let replies: [&[u8]; 5] = [
+                b"$3\r\nabc\r\n",
+                b"$-1\r\n",
+                b"$-1\r\n",
+                b":0\r\n",
+                b":1\r\n",
+            ];
+            for reply in replies {
+                let Reply::Array(Some(args)) = read_reply(&mut reader).unwrap() else {
+                    panic!("expected a command");
+                };
+                let args: Vec<String> = args
+                    .into_iter()
+                    .map(|arg| String::from_utf8(arg.bulk().unwrap().unwrap()).unwrap())
+                    .collect();
+                commands.push(args);
+                writer.write_all(reply).unwrap();
+            }
+            commands
+        });
+
+        let mut engine = SharedEngine::new(RedisStore::connect(&address, "ledger").unwrap());
+        let deposit = Transaction::deposit(ClientId::new(4), TxId::new(9), Amount::new(2, 0));
+        engine.process_transaction(&deposit).unwrap();
+
+        let commands = server.join().unwrap();
+        assert_eq!(commands[0][..2], ["SCRIPT", "LOAD"]);
+        assert_eq!(commands[1], ["GET", "ledger:account:4"]);
+        assert_eq!(commands[2], ["GET", "ledger:tx:9"]);
+        assert_eq!(commands[3], ["SISMEMBER", "ledger:processed", "9"]);
+        let commit = &commands[4];
+        assert_eq!(commit[..3], ["EVALSHA", "abc", "6"]);
+        assert_eq!(
+            commit[3..9],
+            [
+                "ledger:account:4",
+                "ledger:tx:9",
+                "ledger:processed",
+                "ledger:disputed:4",
+                "ledger:clients",
+                "ledger:txs"
+            ]
+        );
+        // Expected account and record are missing; the ID is marked processed
+        assert_eq!(commit[9], "");
+        assert!(commit[10].contains("\"client\":4"), "{}", commit[10]);
+        assert_eq!(commit[11], "");
+        assert_eq!(commit[13..], ["9", "4", "1", "0"]);
+    }
+}
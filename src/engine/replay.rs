@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+
+use crate::errors::PaymentsError;
+
+/// One step of a recorded schedule: which worker applied which input record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ScheduleEntry {
+    /// Zero-based index of the record in the input CSV (header excluded).
+    pub seq: usize,
+
+    /// Worker that applied the record during the recorded run.
+    pub worker: usize,
+}
+
+/// The exact order in which a concurrent run applied its input records.
+/// Entries are appended while the engine lock is held, so the recorded order is the
+/// order the shared state actually saw, regardless of how the workers were scheduled.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Schedule {
+    pub entries: Vec<ScheduleEntry>,
+}
+
+impl Schedule {
+    /// Read a schedule previously written by `write_csv`.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, PaymentsError> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let entries = rdr.deserialize().collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { entries })
+    }
+
+    /// Write the schedule as CSV with a `seq,worker` header.
+    pub fn write_csv<W: std::io::Write>(&self, writer: W) -> Result<(), PaymentsError> {
+        let mut wtr = csv::WriterBuilder::new()
+            .has_headers(true)
+            .from_writer(writer);
+        for entry in &self.entries {
+            wtr.serialize(entry)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
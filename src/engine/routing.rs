@@ -0,0 +1,80 @@
+//! Client-to-worker routing for the concurrent engine.
+//!
+//! Every transaction for a client must go to the same worker to stay in order, so the
+//! router is the only thing deciding which worker owns which client.
+
+use std::fmt::Debug;
+
+use crate::account::ClientId;
+
+/// Picks the worker that owns all transactions for a client.
+pub trait Router: Debug + Send + Sync {
+    /// Worker index in `0..num_workers` for `client`. `num_workers` is never zero.
+    fn route(&self, client: ClientId, num_workers: usize) -> usize;
+}
+
+/// `client % num_workers`. Spreads sequential client IDs perfectly evenly, but changing
+/// the worker count moves almost every client to a different worker.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ModuloRouter;
+
+impl Router for ModuloRouter {
+    fn route(&self, client: ClientId, num_workers: usize) -> usize {
+        usize::from(client.get()) % num_workers
+    }
+}
+
+/// Jump consistent hash (Lamping & Veach). Deterministic across runs and machines, and
+/// going from `n` to `n + 1` workers moves only about `1 / (n + 1)` of the clients, all of
+/// them onto the new worker.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConsistentHashRouter;
+
+impl Router for ConsistentHashRouter {
+    fn route(&self, client: ClientId, num_workers: usize) -> usize {
+        let mut key = u64::from(client.get());
+        let mut bucket: i64 = -1;
+        let mut next: i64 = 0;
+        while next < num_workers as i64 {
+            bucket = next;
+            key = key.wrapping_mul(2_862_933_555_777_941_757).wrapping_add(1);
+            next = ((bucket + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+        }
+        bucket as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_consistent_hash_moves_few_clients() {
+        let router = ConsistentHashRouter;
+        let mut moved = 0;
+        let mut counts = [0usize; 9];
+        for client in 0..=u16::MAX {
+            let before = router.route(ClientId::new(client), 8);
+            let after = router.route(ClientId::new(client), 9);
+            assert!(before < 8 && after < 9);
+            if before != after {
+                assert_eq!(after, 8, "clients only move onto the new worker");
+                moved += 1;
+            }
+            counts[after] += 1;
+        }
+
+        // Expect about 1/9 of the clients to move and each worker to own about 1/9
+        let expected = 65536 / 9;
+        assert!(moved > expected * 9 / 10 && moved < expected * 11 / 10);
+        for count in counts {
+            assert!(count > expected * 9 / 10 && count < expected * 11 / 10);
+        }
+    }
+
+    #[test]
+    fn test_modulo_router() {
+        assert_eq!(ModuloRouter.route(ClientId::new(10), 4), 2);
+        assert_eq!(ModuloRouter.route(ClientId::new(3), 1), 0);
+    }
+}
@@ -0,0 +1,351 @@
+//! Work-stealing run queues for `ConcurrentEngine::process_transactions_from_reader`.
+//!
+//! Each client's records wait in their own FIFO queue, and each record puts a ticket for
+//! its client on the run queue of the worker that owns the client, in input order. The
+//! owner is the client's home worker (the one the `Router` picks) whenever the client
+//! has nothing in flight. Only the owner applies a client's records, so they are still
+//! applied one by one in input order, and a worker that steals nothing applies its
+//! records in input order too.
+//!
+//! A worker with an empty run queue takes over the oldest waiting client of another
+//! worker, with all of its queued records, as long as that worker is not applying one
+//! of them at that moment. Tickets left behind on the old owner's queue are skipped.
+
+use std::collections::VecDeque;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crossbeam_deque::Steal;
+
+use super::sync::{
+    self, Arc, AtomicBool, Injector, Mutex, MutexGuard, Ordering, RwLock, RwLockReadGuard, Thread,
+};
+use crate::account::ClientId;
+
+/// How long an idle worker sleeps before looking for work to steal again
+const IDLE_WAIT: Duration = Duration::from_millis(1);
+
+/// Records of one client waiting to be applied
+#[derive(Debug)]
+struct ClientQueue<T> {
+    records: VecDeque<T>,
+    /// Run queue of the worker that applies the client's records
+    owner: Option<Arc<RunQueue>>,
+    /// The owner is applying one of the client's records
+    busy: bool,
+}
+
+impl<T> ClientQueue<T> {
+    fn is_owned_by(&self, queue: &Arc<RunQueue>) -> bool {
+        self.owner
+            .as_ref()
+            .is_some_and(|owner| Arc::ptr_eq(owner, queue))
+    }
+}
+
+/// Tickets of the clients whose records one worker applies, oldest first
+#[derive(Debug, Default)]
+pub(crate) struct RunQueue {
+    tickets: Injector<ClientId>,
+    /// Set once the worker is removed from the pool; it then stops stealing and exits
+    /// once its own run queue is empty
+    retired: AtomicBool,
+    /// The worker's thread, woken when a ticket is put on the queue
+    thread: OnceLock<Thread>,
+}
+
+impl RunQueue {
+    /// Take the oldest ticket on this queue
+    fn take(&self) -> Option<ClientId> {
+        loop {
+            match self.tickets.steal() {
+                Steal::Success(client) => return Some(client),
+                Steal::Empty => return None,
+                Steal::Retry => {}
+            }
+        }
+    }
+
+    fn push(&self, client: ClientId) {
+        self.tickets.push(client);
+        if let Some(thread) = self.thread.get() {
+            sync::unpark(thread);
+        }
+    }
+
+    pub(crate) fn is_retired(&self) -> bool {
+        self.retired.load(Ordering::Acquire)
+    }
+}
+
+/// Run queues by worker ID, plus every queue created so far, including those of
+/// replaced workers, which may still hold tickets
+#[derive(Debug, Default)]
+struct RunQueues {
+    by_worker: Vec<Arc<RunQueue>>,
+    all: Vec<Arc<RunQueue>>,
+}
+
+/// What a worker should do next
+#[derive(Debug)]
+pub(crate) enum Work<T> {
+    /// Apply this record of `client`, then call `ClientScheduler::done`
+    Apply { client: ClientId, record: T },
+    /// Nothing to do right now
+    Idle,
+}
+
+/// Client queues plus one run queue per worker
+#[derive(Debug)]
+pub(crate) struct ClientScheduler<T> {
+    /// By client ID
+    clients: Vec<Mutex<ClientQueue<T>>>,
+    run_queues: RwLock<RunQueues>,
+    /// Set once no more records will be pushed
+    closed: AtomicBool,
+}
+
+impl<T> ClientScheduler<T> {
+    pub(crate) fn new() -> Self {
+        Self::with_clients(usize::from(ClientId::MAX.get()) + 1)
+    }
+
+    /// A scheduler for the clients with IDs below `clients`
+    fn with_clients(clients: usize) -> Self {
+        Self {
+            clients: (0..clients)
+                .map(|_| {
+                    Mutex::new(ClientQueue {
+                        records: VecDeque::new(),
+                        owner: None,
+                        busy: false,
+                    })
+                })
+                .collect(),
+            run_queues: RwLock::new(RunQueues::default()),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    fn client(&self, client: ClientId) -> MutexGuard<'_, ClientQueue<T>> {
+        self.clients[usize::from(client.get())]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn run_queues(&self) -> RwLockReadGuard<'_, RunQueues> {
+        self.run_queues.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// A new run queue for `worker_id`, replacing that of a retired worker with the same ID.
+    /// The retired worker keeps draining its old queue.
+    pub(crate) fn add_worker(&self, worker_id: usize) -> Arc<RunQueue> {
+        let queue = Arc::new(RunQueue::default());
+        let mut queues = self.run_queues.write().unwrap_or_else(|e| e.into_inner());
+        if worker_id < queues.by_worker.len() {
+            queues.by_worker[worker_id] = queue.clone();
+        } else {
+            queues.by_worker.resize_with(worker_id, Default::default);
+            queues.by_worker.push(queue.clone());
+        }
+        queues.all.push(queue.clone());
+        queue
+    }
+
+    /// Stop giving idle clients to `worker_id`. Clients it still owns stay with it until
+    /// their queued records have been applied.
+    pub(crate) fn retire(&self, worker_id: usize) {
+        if let Some(queue) = self.run_queues().by_worker.get(worker_id) {
+            queue.retired.store(true, Ordering::Release);
+        }
+    }
+
+    /// Queue `record` for `client`. A client with nothing in flight moves to `home`;
+    /// otherwise it stays with its current owner.
+    pub(crate) fn push(&self, client: ClientId, record: T, home: usize) {
+        let mut queue = self.client(client);
+        if queue.records.is_empty() && !queue.busy {
+            queue.owner = Some(self.run_queues().by_worker[home].clone());
+        }
+        queue.records.push_back(record);
+        if let Some(owner) = &queue.owner {
+            owner.push(client);
+        }
+    }
+
+    /// The next record for the worker owning `own`: from its own run queue, or else from
+    /// a client taken over from another worker, with whether a client was taken over
+    pub(crate) fn next(&self, own: &Arc<RunQueue>) -> (Work<T>, bool) {
+        while let Some(client) = own.take() {
+            let mut queue = self.client(client);
+            // Tickets left behind when another worker took the client over
+            if !queue.is_owned_by(own) {
+                continue;
+            }
+            if let Some(record) = queue.records.pop_front() {
+                queue.busy = true;
+                return (Work::Apply { client, record }, false);
+            }
+        }
+        if own.is_retired() {
+            return (Work::Idle, false);
+        }
+        match self.steal(own) {
+            Some((client, record)) => (Work::Apply { client, record }, true),
+            None => (Work::Idle, false),
+        }
+    }
+
+    /// Take over the oldest waiting client of another worker, moving its tickets to `own`,
+    /// and hand out its first record. The record is taken along with the client, so the
+    /// client cannot be taken over again before `own` applies anything.
+    fn steal(&self, own: &Arc<RunQueue>) -> Option<(ClientId, T)> {
+        let queues = self.run_queues();
+        for victim in queues.all.iter().filter(|queue| !Arc::ptr_eq(queue, own)) {
+            while let Some(client) = victim.take() {
+                let mut queue = self.client(client);
+                if !queue.is_owned_by(victim) {
+                    continue;
+                }
+                if queue.busy {
+                    // The victim is applying one of its records; leave the client to it.
+                    // The ticket goes back before the client is unlocked, so the victim
+                    // finds it once it is done with the record.
+                    victim.push(client);
+                    break;
+                }
+                // A ticket left over from records the victim has applied already
+                let Some(record) = queue.records.pop_front() else {
+                    continue;
+                };
+                queue.owner = Some(own.clone());
+                queue.busy = true;
+                for _ in 0..queue.records.len() {
+                    own.push(client);
+                }
+                return Some((client, record));
+            }
+        }
+        None
+    }
+
+    /// The record of `client` handed out by `next` has been applied
+    pub(crate) fn done(&self, client: ClientId) {
+        self.client(client).busy = false;
+    }
+
+    /// Register the calling thread as the worker of `own`, to be woken when tickets arrive
+    pub(crate) fn register(&self, own: &RunQueue) {
+        let _ = own.thread.set(sync::current_thread());
+    }
+
+    /// Sleep until a ticket arrives, the scheduler is closed or it is time to look for
+    /// work to steal again
+    pub(crate) fn wait(&self) {
+        sync::park_timeout(IDLE_WAIT);
+    }
+
+    /// No more records will be pushed; wake every worker so it can finish
+    pub(crate) fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        for queue in &self.run_queues().all {
+            if let Some(thread) = queue.thread.get() {
+                sync::unpark(thread);
+            }
+        }
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The record `next` hands out, or `None` when idle
+    fn next(scheduler: &ClientScheduler<u32>, own: &Arc<RunQueue>) -> Option<(u32, bool)> {
+        match scheduler.next(own) {
+            (Work::Apply { client, record }, stolen) => {
+                scheduler.done(client);
+                Some((record, stolen))
+            }
+            (Work::Idle, _) => None,
+        }
+    }
+
+    #[test]
+    fn test_client_scheduler() {
+        let scheduler = ClientScheduler::new();
+        let busy = scheduler.add_worker(0);
+        let idle = scheduler.add_worker(1);
+        let (a, b) = (ClientId::new(1), ClientId::new(2));
+        for (client, record) in [(a, 1), (b, 2), (a, 3), (b, 4)] {
+            scheduler.push(client, record, 0);
+        }
+
+        // A worker applies its own records in input order
+        assert_eq!(next(&scheduler, &busy), Some((1, false)));
+        // An idle worker takes over the oldest waiting client with all its records
+        assert_eq!(next(&scheduler, &idle), Some((2, true)));
+        assert_eq!(next(&scheduler, &idle), Some((4, false)));
+        // Tickets left behind for the client taken over are skipped
+        assert_eq!(next(&scheduler, &busy), Some((3, false)));
+        assert_eq!(next(&scheduler, &busy), None);
+
+        // A client being applied is not taken over
+        scheduler.push(a, 5, 0);
+        scheduler.push(a, 6, 0);
+        let (Work::Apply { record: 5, .. }, false) = scheduler.next(&busy) else {
+            panic!("expected record 5");
+        };
+        assert_eq!(next(&scheduler, &idle), None);
+        scheduler.done(a);
+        assert_eq!(next(&scheduler, &busy), Some((6, false)));
+
+        // Retired workers drain their own queue but do not steal
+        scheduler.retire(1);
+        assert!(idle.is_retired());
+        scheduler.push(b, 7, 0);
+        assert_eq!(next(&scheduler, &idle), None);
+        assert_eq!(next(&scheduler, &busy), Some((7, false)));
+    }
+
+    /// Two workers race for a client's records, one applying them while the other tries
+    /// to take the client over. Run with:
+    /// `RUSTFLAGS="--cfg loom" cargo test --release --lib scheduler::tests::loom`
+    #[cfg(loom)]
+    #[test]
+    fn loom_client_hand_off() {
+        loom::model(|| {
+            let scheduler = Arc::new(ClientScheduler::with_clients(2));
+            let client = ClientId::new(1);
+            let queues = [scheduler.add_worker(0), scheduler.add_worker(1)];
+            for record in [1, 2, 3] {
+                scheduler.push(client, record, 0);
+            }
+            // Closed up front, so workers stop at their first idle turn instead of waiting
+            scheduler.close();
+
+            let applied = Arc::new(Mutex::new(Vec::new()));
+            let workers: Vec<_> = queues
+                .into_iter()
+                .map(|own| {
+                    let (scheduler, applied) = (scheduler.clone(), applied.clone());
+                    loom::thread::spawn(move || {
+                        while let (Work::Apply { client, record }, _) = scheduler.next(&own) {
+                            applied.lock().unwrap().push(record);
+                            scheduler.done(client);
+                        }
+                    })
+                })
+                .collect();
+            for worker in workers {
+                worker.join().unwrap();
+            }
+            // Every record is applied once, in input order
+            assert_eq!(*applied.lock().unwrap(), [1, 2, 3]);
+        });
+    }
+}
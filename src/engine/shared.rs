@@ -0,0 +1,446 @@
+//! Engine whose state lives in a store shared by several engine processes, e.g. Redis
+//! (see `redis::RedisStore`), so processes on different hosts can apply transactions to
+//! the same accounts.
+//!
+//! Each transaction is applied optimistically: the engine loads the slice of state the
+//! transaction depends on (its client's account, the record kept under its ID and whether
+//! the ID was processed), applies it with the usual `StandardEngine` rules, and commits
+//! the result only if that slice has not changed in the meantime. On a conflict it loads
+//! the slice again and retries.
+
+use std::fmt::Debug;
+use std::io::Read;
+
+use super::standard::StandardEngine;
+use super::state::EngineState;
+use super::validation::TransactionValidator;
+use super::{
+    EngineInfo, EnginePolicy, ErrorPolicy, ParseErrorLimit, ParseErrorTally, read_transactions,
+    transaction_reader,
+};
+use crate::account::{Account, ClientId, DecimalFormat};
+use crate::errors::PaymentsError;
+use crate::shutdown::ShutdownFlag;
+use crate::transaction::{InputFormat, StoredTransaction, Transaction, TransactionType, TxId};
+
+/// Times a transaction is applied before giving up on conflicting writes
+const MAX_ATTEMPTS: u32 = 16;
+
+/// The state one transaction depends on
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SharedSlice {
+    /// The account of the transaction's client
+    pub account: Option<Account>,
+    /// The record kept under the transaction's ID
+    pub record: Option<StoredTransaction>,
+    /// Whether the transaction's ID was processed already
+    pub processed: bool,
+    /// For disputes, the client's other transactions under dispute, which count towards
+    /// `EnginePolicy::max_open_disputes`
+    pub open_disputes: Vec<(TxId, StoredTransaction)>,
+}
+
+/// What applying one transaction changed, to be written only if `before` still holds
+#[derive(Debug, Clone, PartialEq)]
+pub struct SharedChange {
+    pub client: ClientId,
+    pub tx: TxId,
+    /// The slice the change was computed from
+    pub before: SharedSlice,
+    /// The client's account afterwards
+    pub account: Option<Account>,
+    /// The record under `tx` afterwards; `None` removes it
+    pub record: Option<StoredTransaction>,
+    /// `tx` was processed for the first time
+    pub processed: bool,
+}
+
+impl SharedChange {
+    fn is_empty(&self) -> bool {
+        self.account == self.before.account && self.record == self.before.record && !self.processed
+    }
+}
+
+/// State shared by several engines, read and written one transaction at a time
+pub trait SharedStore: Debug + Send {
+    /// The slice of state `transaction` depends on
+    fn load(&mut self, transaction: &Transaction) -> Result<SharedSlice, PaymentsError>;
+
+    /// Write `change` atomically if the state still matches `change.before`. Returns false,
+    /// writing nothing, if another engine changed it since it was loaded or processed
+    /// the same ID first.
+    fn commit(&mut self, change: &SharedChange) -> Result<bool, PaymentsError>;
+
+    /// The client's account
+    fn account(&mut self, client: ClientId) -> Result<Option<Account>, PaymentsError>;
+
+    /// Every account in the store
+    fn accounts(&mut self) -> Result<Vec<Account>, PaymentsError>;
+
+    /// Snapshot of the whole store
+    fn export_state(&mut self) -> Result<EngineState, PaymentsError>;
+}
+
+/// Engine applying transactions to the state in a `SharedStore`. Dispute expiry,
+/// recurring transactions and observers are not supported, since they span more state
+/// than one transaction's slice.
+#[derive(Debug)]
+pub struct SharedEngine<S> {
+    store: S,
+    /// Applies each transaction to the slice it was loaded with, under the engine's rules
+    scratch: StandardEngine,
+    /// How reader-based processing reacts to bad rows and rejected transactions
+    error_policy: ErrorPolicy,
+    /// How reader-based processing parses the `type` and `amount` columns
+    input_format: InputFormat,
+    /// Parse failures after which reader-based processing fails
+    parse_error_limit: Option<ParseErrorLimit>,
+    /// Checked between records; once requested, no new records are read
+    shutdown: ShutdownFlag,
+    /// Commits retried because another engine changed the state first
+    conflicts: u64,
+}
+
+impl<S: SharedStore> SharedEngine<S> {
+    pub fn new(store: S) -> Self {
+        Self {
+            store,
+            scratch: StandardEngine::new(),
+            error_policy: ErrorPolicy::default(),
+            input_format: InputFormat::default(),
+            parse_error_limit: None,
+            shutdown: ShutdownFlag::default(),
+            conflicts: 0,
+        }
+    }
+
+    pub fn set_error_policy(&mut self, error_policy: ErrorPolicy) {
+        self.error_policy = error_policy;
+    }
+
+    pub fn set_parse_error_limit(&mut self, limit: Option<ParseErrorLimit>) {
+        self.parse_error_limit = limit;
+    }
+
+    pub fn set_input_format(&mut self, input_format: InputFormat) {
+        self.input_format = input_format;
+    }
+
+    pub fn set_shutdown_flag(&mut self, shutdown: ShutdownFlag) {
+        self.shutdown = shutdown;
+    }
+
+    /// Replace the business rules applied to account operations. Every engine sharing
+    /// the store should use the same rules.
+    pub fn set_policy(&mut self, policy: EnginePolicy) {
+        self.scratch.set_policy(policy);
+    }
+
+    pub fn set_decimal_format(&mut self, decimal_format: DecimalFormat) {
+        self.scratch.set_decimal_format(decimal_format);
+    }
+
+    /// Run `validator` on every transaction, after the validators already added
+    pub fn add_validator(&mut self, validator: impl TransactionValidator + 'static) {
+        self.scratch.add_validator(validator);
+    }
+
+    /// Commits retried so far because another engine changed the state first
+    pub fn conflicts(&self) -> u64 {
+        self.conflicts
+    }
+
+    /// Apply `transaction` to the shared state, retrying while other engines change the
+    /// state it depends on. Fails with `WriteConflict` after `MAX_ATTEMPTS` tries.
+    pub fn process_transaction(&mut self, transaction: &Transaction) -> Result<(), PaymentsError> {
+        for _ in 0..MAX_ATTEMPTS {
+            let before = self.store.load(transaction)?;
+            self.scratch
+                .import_state(slice_state(transaction.tx, &before));
+            let result = self.scratch.process_transaction(transaction);
+
+            let after = self.scratch.export_state();
+            let record = after
+                .disputable_transactions
+                .into_iter()
+                .find_map(|(tx, record)| (tx == transaction.tx).then_some(record));
+            let change = SharedChange {
+                client: transaction.client,
+                tx: transaction.tx,
+                account: after
+                    .accounts
+                    .into_iter()
+                    .find(|account| account.client == transaction.client),
+                record,
+                processed: !before.processed && after.processed_tx_ids.contains(&transaction.tx),
+                before,
+            };
+            if change.is_empty() || self.store.commit(&change)? {
+                return result;
+            }
+            self.conflicts += 1;
+            log::debug!(
+                "Transaction {} conflicted with another engine; retrying",
+                transaction.tx
+            );
+        }
+        Err(PaymentsError::WriteConflict {
+            tx: transaction.tx,
+            attempts: MAX_ATTEMPTS,
+        })
+    }
+
+    pub fn process_transactions_from_reader<R: Read>(
+        &mut self,
+        reader: R,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut rdr = transaction_reader(reader, &self.input_format);
+        let lines = read_transactions(&mut rdr, self.input_format.clone())?.enumerate();
+        let mut parse_errors = ParseErrorTally::new(self.parse_error_limit);
+
+        for (idx, line) in lines {
+            if self.shutdown.is_requested() {
+                log::warn!("Shutdown requested; stopping before line {}", idx + 1);
+                break;
+            }
+            parse_errors.read();
+            let transaction: Transaction = match line {
+                Ok(tx) => tx,
+                Err(e) => {
+                    log::error!("Failed to parse line {}: {}", idx + 1, e);
+                    if self.error_policy == ErrorPolicy::Abort {
+                        return Err(e.at_line(idx as u64 + 1).into());
+                    }
+                    parse_errors.failed()?;
+                    continue;
+                }
+            };
+
+            match self.process_transaction(&transaction) {
+                Ok(()) => log::debug!("Successfully processed transaction: {:?}", transaction),
+                // Losing the store loses every row after this one too
+                Err(e @ PaymentsError::IoError(_)) => return Err(e.at_line(idx as u64 + 1).into()),
+                Err(e) => {
+                    log::error!("Failed to process transaction {:?}: {}", transaction, e);
+                    if self.error_policy == ErrorPolicy::Abort {
+                        return Err(e.at_line(idx as u64 + 1).into());
+                    }
+                }
+            }
+        }
+        parse_errors.finish()?;
+        Ok(())
+    }
+
+    /// The client's account as currently stored
+    pub fn peek_account(&mut self, client: ClientId) -> Result<Option<Account>, PaymentsError> {
+        self.store.account(client)
+    }
+
+    /// Snapshot of the shared state, e.g. for a checkpoint
+    pub fn export_state(&mut self) -> Result<EngineState, PaymentsError> {
+        self.store.export_state()
+    }
+
+    /// Write every account in the store, by client ID
+    pub fn write_accounts_csv<W: std::io::Write>(
+        &mut self,
+        writer: W,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut accounts = self.store.accounts()?;
+        accounts.sort_unstable_by_key(|account| account.client);
+
+        let mut wtr = csv::WriterBuilder::new()
+            .has_headers(true)
+            .from_writer(writer);
+        let decimal_format = self.scratch.decimal_format();
+        for account in &accounts {
+            wtr.serialize(decimal_format.account(account))?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    /// Latency figures cover only this engine's transactions, including retries;
+    /// `account_count` is not known without reading the whole store
+    pub fn get_engine_info(&self) -> EngineInfo {
+        EngineInfo {
+            engine_type: "Shared".to_string(),
+            memory_bounded: true,
+            concurrent: true,
+            account_count: 0,
+            transaction_count: None,
+            memory_limits: None,
+            stats: self.scratch.get_engine_info().stats,
+            rebalancing: None,
+        }
+    }
+}
+
+/// `slice` as the state of an engine about to apply transaction `tx`
+fn slice_state(tx: TxId, slice: &SharedSlice) -> EngineState {
+    let mut disputable_transactions: Vec<_> = slice
+        .open_disputes
+        .iter()
+        .filter(|(id, _)| *id != tx)
+        .cloned()
+        .collect();
+    disputable_transactions.extend(slice.record.clone().map(|record| (tx, record)));
+    EngineState {
+        accounts: slice.account.clone().into_iter().collect(),
+        disputable_transactions,
+        processed_tx_ids: slice.processed.then_some(tx).into_iter().collect(),
+        idempotency_keys: Vec::new(),
+    }
+}
+
+/// Whether applying `transaction` depends on the client's open disputes
+pub(crate) fn needs_open_disputes(transaction: &Transaction) -> bool {
+    transaction.tx_type == TransactionType::Dispute
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Amount;
+    use std::collections::{HashMap, HashSet};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, Default)]
+    struct MemoryState {
+        accounts: HashMap<ClientId, Account>,
+        records: HashMap<TxId, StoredTransaction>,
+        processed: HashSet<TxId>,
+        /// Commits to refuse before accepting any, as if another engine wrote first
+        conflicts: usize,
+    }
+
+    /// Store shared by engines in the same process, standing in for Redis
+    #[derive(Debug, Clone, Default)]
+    struct MemoryStore(Arc<Mutex<MemoryState>>);
+
+    impl SharedStore for MemoryStore {
+        fn load(&mut self, transaction: &Transaction) -> Result<SharedSlice, PaymentsError> {
+            let state = self.0.lock().unwrap();
+            Ok(SharedSlice {
+                account: state.accounts.get(&transaction.client).cloned(),
+                record: state.records.get(&transaction.tx).cloned(),
+                processed: state.processed.contains(&transaction.tx),
+                open_disputes: state
+                    .records
+                    .iter()
+                    .filter(|(_, record)| record.client == transaction.client && record.disputed)
+                    .map(|(tx, record)| (*tx, record.clone()))
+                    .collect(),
+            })
+        }
+
+        fn commit(&mut self, change: &SharedChange) -> Result<bool, PaymentsError> {
+            let mut state = self.0.lock().unwrap();
+            if state.conflicts > 0 {
+                state.conflicts -= 1;
+                return Ok(false);
+            }
+            if state.accounts.get(&change.client) != change.before.account.as_ref()
+                || state.records.get(&change.tx) != change.before.record.as_ref()
+                || (change.processed && !state.processed.insert(change.tx))
+            {
+                return Ok(false);
+            }
+            if let Some(account) = &change.account {
+                state.accounts.insert(change.client, account.clone());
+            }
+            match &change.record {
+                Some(record) => state.records.insert(change.tx, record.clone()),
+                None => state.records.remove(&change.tx),
+            };
+            Ok(true)
+        }
+
+        fn account(&mut self, client: ClientId) -> Result<Option<Account>, PaymentsError> {
+            Ok(self.0.lock().unwrap().accounts.get(&client).cloned())
+        }
+
+        fn accounts(&mut self) -> Result<Vec<Account>, PaymentsError> {
+            Ok(self.0.lock().unwrap().accounts.values().cloned().collect())
+        }
+
+        fn export_state(&mut self) -> Result<EngineState, PaymentsError> {
+            let state = self.0.lock().unwrap();
+            Ok(EngineState {
+                accounts: state.accounts.values().cloned().collect(),
+                disputable_transactions: state
+                    .records
+                    .iter()
+                    .map(|(tx, record)| (*tx, record.clone()))
+                    .collect(),
+                processed_tx_ids: state.processed.iter().copied().collect(),
+                idempotency_keys: Vec::new(),
+            })
+        }
+    }
+
+    #[test]
+    fn test_engines_share_state() {
+        let store = MemoryStore::default();
+        let mut first = SharedEngine::new(store.clone());
+        let mut second = SharedEngine::new(store.clone());
+
+        // A deposit applied by one engine can be disputed through the other
+        first
+            .process_transactions_from_reader(
+                "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,2,2,5.0\n".as_bytes(),
+            )
+            .unwrap();
+        second
+            .process_transactions_from_reader(
+                "type,client,tx,amount\ndispute,1,1,\ndeposit,1,1,10.0\nwithdrawal,2,3,1.0\n"
+                    .as_bytes(),
+            )
+            .unwrap();
+        first
+            .process_transactions_from_reader("type,client,tx,amount\nchargeback,1,1,\n".as_bytes())
+            .unwrap();
+
+        let account = second.peek_account(ClientId::new(1)).unwrap().unwrap();
+        assert_eq!(account.total, Amount::ZERO);
+        assert!(account.is_locked());
+        let account = first.peek_account(ClientId::new(2)).unwrap().unwrap();
+        assert_eq!(account.available, Amount::new(4, 0));
+
+        let mut output = Vec::new();
+        second.write_accounts_csv(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.lines().nth(1).unwrap().starts_with("1,"),
+            "{}",
+            output
+        );
+        // The duplicate deposit was not applied again
+        assert_eq!(first.export_state().unwrap().processed_tx_ids.len(), 3);
+    }
+
+    #[test]
+    fn test_conflicts_are_retried() {
+        let store = MemoryStore::default();
+        store.0.lock().unwrap().conflicts = 2;
+        let mut engine = SharedEngine::new(store.clone());
+        let deposit = Transaction::deposit(ClientId::new(1), TxId::new(1), Amount::new(3, 0));
+        engine.process_transaction(&deposit).unwrap();
+        assert_eq!(engine.conflicts(), 2);
+        assert_eq!(
+            engine
+                .peek_account(ClientId::new(1))
+                .unwrap()
+                .unwrap()
+                .total,
+            Amount::new(3, 0)
+        );
+
+        store.0.lock().unwrap().conflicts = usize::MAX;
+        let deposit = Transaction::deposit(ClientId::new(1), TxId::new(2), Amount::new(3, 0));
+        let error = engine.process_transaction(&deposit).unwrap_err();
+        assert_eq!(error.code(), 505);
+        assert!(error.is_retryable());
+    }
+}
@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use super::idempotency::IdempotencyRecord;
+use super::observer::AccountDelta;
+use crate::account::{Account, ClientId};
+use crate::errors::PaymentsError;
+use crate::transaction::{StoredTransaction, TxId};
+
+/// Complete, engine-independent snapshot of everything an engine needs to carry on
+/// processing: balances, disputable transactions and the IDs used for duplicate detection.
+/// For the bounded engines, entries are listed from least to most recently used so that
+/// importing them restores the same eviction order.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct EngineState {
+    pub accounts: Vec<Account>,
+    pub disputable_transactions: Vec<(TxId, StoredTransaction)>,
+    pub processed_tx_ids: Vec<TxId>,
+    /// Keys of transactions accepted under an idempotency key, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub idempotency_keys: Vec<IdempotencyRecord>,
+}
+
+impl EngineState {
+    /// Fold `other` into this state, as produced by processing a different shard of the input.
+    /// Balances of clients present in both are summed and an account locked or closed in
+    /// either stays so. Transaction IDs must not overlap: the same ID in both states means a
+    /// transaction was applied twice (or two different transactions share an ID), so
+    /// that is reported as a conflict and `self` is left unchanged.
+    pub fn merge(&mut self, other: EngineState) -> Result<(), PaymentsError> {
+        let ids: HashSet<TxId> = self.processed_tx_ids.iter().copied().collect();
+        if let Some(tx) = other.processed_tx_ids.iter().find(|tx| ids.contains(tx)) {
+            return Err(PaymentsError::MergeConflict(format!(
+                "transaction ID {} was processed by both engines",
+                tx
+            )));
+        }
+        let disputable: HashSet<TxId> = self
+            .disputable_transactions
+            .iter()
+            .map(|(tx, _)| *tx)
+            .collect();
+        if let Some((tx, _)) = other
+            .disputable_transactions
+            .iter()
+            .find(|(tx, _)| disputable.contains(tx))
+        {
+            return Err(PaymentsError::MergeConflict(format!(
+                "transaction ID {} is disputable in both engines",
+                tx
+            )));
+        }
+
+        let mut positions: HashMap<ClientId, usize> = self
+            .accounts
+            .iter()
+            .enumerate()
+            .map(|(idx, account)| (account.client, idx))
+            .collect();
+        for account in other.accounts {
+            match positions.get(&account.client) {
+                Some(&idx) => {
+                    let existing = &mut self.accounts[idx];
+                    existing.available += account.available;
+                    existing.held += account.held;
+                    existing.total += account.total;
+                    if account.lock > existing.lock {
+                        existing.lock = account.lock;
+                        existing.lock_reason = account.lock_reason;
+                    }
+                    existing.closed |= account.closed;
+                }
+                None => {
+                    positions.insert(account.client, self.accounts.len());
+                    self.accounts.push(account);
+                }
+            }
+        }
+        self.disputable_transactions
+            .extend(other.disputable_transactions);
+        self.processed_tx_ids.extend(other.processed_tx_ids);
+        let keys: HashSet<(ClientId, String)> = self
+            .idempotency_keys
+            .iter()
+            .map(|record| (record.client, record.key.clone()))
+            .collect();
+        self.idempotency_keys.extend(
+            other
+                .idempotency_keys
+                .into_iter()
+                .filter(|record| !keys.contains(&(record.client, record.key.clone()))),
+        );
+        Ok(())
+    }
+}
+
+/// Accounts that are new or changed in `after` compared with `before`, by client ID, so
+/// incremental consumers only receive what changed. Snapshots are typically loaded from
+/// checkpoints or other serialized `EngineState`s. Accounts missing from `after` (e.g.
+/// evicted by a bounded engine) are not reported.
+pub fn snapshot_diff(before: &EngineState, after: &EngineState) -> Vec<AccountDelta> {
+    let previous: HashMap<ClientId, &Account> = before
+        .accounts
+        .iter()
+        .map(|account| (account.client, account))
+        .collect();
+    let mut deltas: Vec<_> = after
+        .accounts
+        .iter()
+        .filter_map(|account| {
+            let previous = previous.get(&account.client).copied();
+            (previous != Some(account)).then(|| AccountDelta::new(None, previous.cloned(), account))
+        })
+        .collect();
+    deltas.sort_unstable_by_key(|delta| delta.account.client);
+    deltas
+}
@@ -0,0 +1,460 @@
+//! Storage for the deposits and withdrawals an engine keeps so they can be disputed.
+//!
+//! Engines are generic over `TransactionStore`. `StandardEngine` defaults to a `HashMap`
+//! and `BoundedEngine` to a `BoundedCache`, which evicts by its `EvictionPolicy`.
+//! `BitmapStore` packs densely numbered IDs tighter than a hash map, and `DiskStore`
+//! (`fs` feature) keeps entries in a file with only an index in memory.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use super::cache::BoundedCache;
+use crate::transaction::{StoredTransaction, TxId};
+
+/// Disputable transactions by ID.
+pub trait TransactionStore: Debug + Send {
+    /// Store `stored` under `tx`, replacing any earlier entry. Stores with a capacity may
+    /// evict another entry to make room.
+    fn insert(&mut self, tx: TxId, stored: StoredTransaction);
+
+    /// The entry for `tx`, without counting as a use
+    fn get(&self, tx: TxId) -> Option<Cow<'_, StoredTransaction>>;
+
+    /// The entry for `tx` for updating; counts as a use for stores that evict
+    fn get_mut(&mut self, tx: TxId) -> Option<&mut StoredTransaction>;
+
+    fn remove(&mut self, tx: TxId) -> Option<StoredTransaction>;
+
+    fn contains(&self, tx: TxId) -> bool;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn clear(&mut self);
+
+    /// Every entry. Stores that evict list the next to be evicted first, so inserting
+    /// the entries in order rebuilds the same eviction order.
+    fn entries(&self) -> Vec<(TxId, StoredTransaction)>;
+}
+
+impl TransactionStore for HashMap<TxId, StoredTransaction> {
+    fn insert(&mut self, tx: TxId, stored: StoredTransaction) {
+        HashMap::insert(self, tx, stored);
+    }
+
+    fn get(&self, tx: TxId) -> Option<Cow<'_, StoredTransaction>> {
+        HashMap::get(self, &tx).map(Cow::Borrowed)
+    }
+
+    fn get_mut(&mut self, tx: TxId) -> Option<&mut StoredTransaction> {
+        HashMap::get_mut(self, &tx)
+    }
+
+    fn remove(&mut self, tx: TxId) -> Option<StoredTransaction> {
+        HashMap::remove(self, &tx)
+    }
+
+    fn contains(&self, tx: TxId) -> bool {
+        self.contains_key(&tx)
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn clear(&mut self) {
+        HashMap::clear(self);
+    }
+
+    fn entries(&self) -> Vec<(TxId, StoredTransaction)> {
+        self.iter()
+            .map(|(tx, stored)| (*tx, stored.clone()))
+            .collect()
+    }
+}
+
+impl TransactionStore for BoundedCache<TxId, StoredTransaction> {
+    fn insert(&mut self, tx: TxId, stored: StoredTransaction) {
+        self.put(tx, stored);
+    }
+
+    fn get(&self, tx: TxId) -> Option<Cow<'_, StoredTransaction>> {
+        self.peek(&tx).map(Cow::Borrowed)
+    }
+
+    fn get_mut(&mut self, tx: TxId) -> Option<&mut StoredTransaction> {
+        BoundedCache::get_mut(self, &tx)
+    }
+
+    fn remove(&mut self, tx: TxId) -> Option<StoredTransaction> {
+        self.pop(&tx)
+    }
+
+    fn contains(&self, tx: TxId) -> bool {
+        BoundedCache::contains(self, &tx)
+    }
+
+    fn len(&self) -> usize {
+        BoundedCache::len(self)
+    }
+
+    fn clear(&mut self) {
+        BoundedCache::clear(self);
+    }
+
+    fn entries(&self) -> Vec<(TxId, StoredTransaction)> {
+        self.iter()
+            .rev()
+            .map(|(tx, stored)| (*tx, stored.clone()))
+            .collect()
+    }
+}
+
+/// IDs per `BitmapStore` page, as a power of two
+const PAGE_BITS: u32 = 12;
+const PAGE_WORDS: usize = (1 << PAGE_BITS) / 64;
+
+/// Store for densely numbered IDs. IDs are grouped into pages of 4096; each page has a
+/// bitmap of the IDs present and their entries packed in ID order, so there is no
+/// per-entry key or hashing overhead. Sparse IDs cost a page each.
+#[derive(Debug, Clone, Default)]
+pub struct BitmapStore {
+    pages: HashMap<u32, Page>,
+    len: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Page {
+    present: [u64; PAGE_WORDS],
+    /// One per bit set in `present`, in ID order
+    entries: Vec<StoredTransaction>,
+}
+
+impl Page {
+    fn new() -> Self {
+        Self {
+            present: [0; PAGE_WORDS],
+            entries: Vec::new(),
+        }
+    }
+
+    fn contains(&self, slot: usize) -> bool {
+        self.present[slot / 64] & (1 << (slot % 64)) != 0
+    }
+
+    /// Index in `entries` of `slot`: the number of slots before it that are present
+    fn rank(&self, slot: usize) -> usize {
+        let before: u32 = self.present[..slot / 64]
+            .iter()
+            .map(|word| word.count_ones())
+            .sum();
+        let partial = self.present[slot / 64] & ((1 << (slot % 64)) - 1);
+        (before + partial.count_ones()) as usize
+    }
+}
+
+/// Page number and slot within the page of `tx`
+fn page_slot(tx: TxId) -> (u32, usize) {
+    let tx = tx.get();
+    (tx >> PAGE_BITS, (tx & ((1 << PAGE_BITS) - 1)) as usize)
+}
+
+impl TransactionStore for BitmapStore {
+    fn insert(&mut self, tx: TxId, stored: StoredTransaction) {
+        let (page, slot) = page_slot(tx);
+        let page = self.pages.entry(page).or_insert_with(Page::new);
+        let rank = page.rank(slot);
+        if page.contains(slot) {
+            page.entries[rank] = stored;
+        } else {
+            page.present[slot / 64] |= 1 << (slot % 64);
+            page.entries.insert(rank, stored);
+            self.len += 1;
+        }
+    }
+
+    fn get(&self, tx: TxId) -> Option<Cow<'_, StoredTransaction>> {
+        let (page, slot) = page_slot(tx);
+        let page = self.pages.get(&page)?;
+        page.contains(slot)
+            .then(|| Cow::Borrowed(&page.entries[page.rank(slot)]))
+    }
+
+    fn get_mut(&mut self, tx: TxId) -> Option<&mut StoredTransaction> {
+        let (page, slot) = page_slot(tx);
+        let page = self.pages.get_mut(&page)?;
+        if !page.contains(slot) {
+            return None;
+        }
+        let rank = page.rank(slot);
+        Some(&mut page.entries[rank])
+    }
+
+    fn remove(&mut self, tx: TxId) -> Option<StoredTransaction> {
+        let (page_id, slot) = page_slot(tx);
+        let page = self.pages.get_mut(&page_id)?;
+        if !page.contains(slot) {
+            return None;
+        }
+        page.present[slot / 64] &= !(1 << (slot % 64));
+        let stored = page.entries.remove(page.rank(slot));
+        if page.entries.is_empty() {
+            self.pages.remove(&page_id);
+        }
+        self.len -= 1;
+        Some(stored)
+    }
+
+    fn contains(&self, tx: TxId) -> bool {
+        let (page, slot) = page_slot(tx);
+        self.pages
+            .get(&page)
+            .is_some_and(|page| page.contains(slot))
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn clear(&mut self) {
+        self.pages.clear();
+        self.len = 0;
+    }
+
+    fn entries(&self) -> Vec<(TxId, StoredTransaction)> {
+        let mut entries = Vec::with_capacity(self.len);
+        for (page_id, page) in &self.pages {
+            let mut stored = page.entries.iter();
+            for (word_idx, word) in page.present.iter().enumerate() {
+                let mut bits = *word;
+                while bits != 0 {
+                    let slot = word_idx * 64 + bits.trailing_zeros() as usize;
+                    bits &= bits - 1;
+                    if let Some(stored) = stored.next() {
+                        let tx = TxId::new((page_id << PAGE_BITS) | slot as u32);
+                        entries.push((tx, stored.clone()));
+                    }
+                }
+            }
+        }
+        entries
+    }
+}
+
+/// Store that appends entries to a scratch file as JSON lines and keeps only their
+/// offsets in memory. The entry last returned by `get_mut` is held in memory and
+/// written back by the next call that changes the store. Rewritten and removed entries
+/// leave their old records behind; the file only shrinks on `clear`.
+///
+/// Write failures are logged and lose the entry, so later disputes of it are rejected
+/// as `TransactionNotFound`.
+#[cfg(feature = "fs")]
+#[derive(Debug)]
+pub struct DiskStore {
+    file: std::fs::File,
+    /// Offset and length of each entry's latest record
+    index: HashMap<TxId, (u64, usize)>,
+    /// Bytes written to `file`
+    end: u64,
+    /// The entry handed out by `get_mut`, not yet written back
+    dirty: Option<(TxId, StoredTransaction)>,
+}
+
+#[cfg(feature = "fs")]
+impl DiskStore {
+    /// Use `path` as the store's file, truncating it if it exists
+    pub fn create(path: &std::path::Path) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        file.set_len(0)?;
+        Ok(Self {
+            file,
+            index: HashMap::new(),
+            end: 0,
+            dirty: None,
+        })
+    }
+
+    fn read(&self, tx: TxId) -> Option<StoredTransaction> {
+        use std::io::{Read, Seek, SeekFrom};
+
+        let (offset, len) = *self.index.get(&tx)?;
+        let mut record = vec![0; len];
+        let mut file = &self.file;
+        let result = file
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| file.read_exact(&mut record))
+            .and_then(|()| serde_json::from_slice(&record).map_err(std::io::Error::from));
+        match result {
+            Ok(stored) => Some(stored),
+            Err(e) => {
+                log::error!("Failed to read transaction {} from disk store: {}", tx, e);
+                None
+            }
+        }
+    }
+
+    fn write(&mut self, tx: TxId, stored: &StoredTransaction) {
+        use std::io::Write;
+
+        let result = serde_json::to_vec(stored)
+            .map_err(std::io::Error::from)
+            .and_then(|mut record| {
+                record.push(b'\n');
+                self.file.write_all(&record)?;
+                Ok(record.len())
+            });
+        match result {
+            Ok(len) => {
+                self.index.insert(tx, (self.end, len - 1));
+                self.end += len as u64;
+            }
+            Err(e) => {
+                log::error!("Failed to write transaction {} to disk store: {}", tx, e);
+                self.index.remove(&tx);
+            }
+        }
+    }
+
+    fn write_back(&mut self) {
+        if let Some((tx, stored)) = self.dirty.take() {
+            self.write(tx, &stored);
+        }
+    }
+}
+
+#[cfg(feature = "fs")]
+impl TransactionStore for DiskStore {
+    fn insert(&mut self, tx: TxId, stored: StoredTransaction) {
+        if self.dirty.as_ref().is_some_and(|(dirty, _)| *dirty == tx) {
+            self.dirty = None;
+        }
+        self.write_back();
+        self.write(tx, &stored);
+    }
+
+    fn get(&self, tx: TxId) -> Option<Cow<'_, StoredTransaction>> {
+        match &self.dirty {
+            Some((dirty, stored)) if *dirty == tx => Some(Cow::Borrowed(stored)),
+            _ => self.read(tx).map(Cow::Owned),
+        }
+    }
+
+    fn get_mut(&mut self, tx: TxId) -> Option<&mut StoredTransaction> {
+        if self.dirty.as_ref().is_none_or(|(dirty, _)| *dirty != tx) {
+            self.write_back();
+            let stored = self.read(tx)?;
+            self.dirty = Some((tx, stored));
+        }
+        self.dirty.as_mut().map(|(_, stored)| stored)
+    }
+
+    fn remove(&mut self, tx: TxId) -> Option<StoredTransaction> {
+        let stored = match self.dirty.take() {
+            Some((dirty, stored)) if dirty == tx => Some(stored),
+            dirty => {
+                self.dirty = dirty;
+                self.read(tx)
+            }
+        };
+        self.index.remove(&tx);
+        stored
+    }
+
+    fn contains(&self, tx: TxId) -> bool {
+        self.index.contains_key(&tx)
+    }
+
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    fn clear(&mut self) {
+        self.index.clear();
+        self.dirty = None;
+        self.end = 0;
+        if let Err(e) = self.file.set_len(0) {
+            log::error!("Failed to truncate disk store: {}", e);
+        }
+    }
+
+    fn entries(&self) -> Vec<(TxId, StoredTransaction)> {
+        self.index
+            .keys()
+            .filter_map(|tx| Some((*tx, self.get(*tx)?.into_owned())))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::ClientId;
+    use crate::engine::cache::EvictionPolicy;
+    use crate::transaction::Amount;
+    use std::num::NonZeroUsize;
+
+    fn stored(amount: i64) -> StoredTransaction {
+        StoredTransaction {
+            client: ClientId::new(1),
+            amount: Amount::new(amount, 0),
+            disputed: false,
+            withdrawal: false,
+            case: None,
+        }
+    }
+
+    /// The same operations give the same results on every store
+    fn exercise(mut store: impl TransactionStore) {
+        let tx = TxId::new;
+        for id in [5, 4095, 4096, 1 << 20] {
+            store.insert(tx(id), stored(i64::from(id)));
+        }
+        store.insert(tx(4095), stored(7));
+        assert_eq!(store.len(), 4);
+        assert!(store.contains(tx(4096)) && !store.contains(tx(6)));
+
+        store.get_mut(tx(5)).unwrap().disputed = true;
+        assert!(store.get(tx(5)).unwrap().disputed);
+        assert_eq!(store.get(tx(4095)).unwrap().amount, Amount::new(7, 0));
+        assert_eq!(
+            store.remove(tx(4096)).map(|stored| stored.amount),
+            Some(Amount::new(4096, 0))
+        );
+        assert!(store.remove(tx(4096)).is_none() && store.get(tx(4096)).is_none());
+
+        let mut entries: Vec<_> = store
+            .entries()
+            .into_iter()
+            .map(|(tx, stored)| (tx.get(), stored.disputed))
+            .collect();
+        entries.sort_unstable();
+        assert_eq!(entries, vec![(5, true), (4095, false), (1 << 20, false)]);
+
+        store.clear();
+        assert!(store.is_empty() && store.get(tx(5)).is_none());
+    }
+
+    #[test]
+    fn test_stores_agree() {
+        exercise(HashMap::new());
+        exercise(BoundedCache::new(
+            EvictionPolicy::Lru,
+            NonZeroUsize::new(10).unwrap(),
+        ));
+        exercise(BitmapStore::default());
+        #[cfg(feature = "fs")]
+        {
+            let path = std::env::temp_dir().join(format!("store-test-{}", std::process::id()));
+            exercise(DiskStore::create(&path).unwrap());
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,96 @@
+//! Synchronization primitives shared by the concurrent engines.
+//!
+//! Builds with `--cfg loom` swap these for loom's model-checked versions so the
+//! shared-state paths can be exercised under every possible interleaving. Channels,
+//! `OnceLock` and spawning threads stay std's: loom has no bounded channels, no
+//! `OnceLock` and no scoped threads. Crossbeam's `Injector` is replaced by a locked
+//! queue, which loom can see.
+
+use std::time::Duration;
+
+#[cfg(loom)]
+use crossbeam_deque::Steal;
+
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+#[cfg(loom)]
+pub(crate) use loom::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard};
+#[cfg(loom)]
+pub(crate) use loom::thread::{Thread, current as current_thread};
+
+#[cfg(not(loom))]
+pub(crate) use crossbeam_deque::Injector;
+#[cfg(not(loom))]
+pub(crate) use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+#[cfg(not(loom))]
+pub(crate) use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard};
+#[cfg(not(loom))]
+pub(crate) use std::thread::{Thread, current as current_thread};
+
+/// Whether a thread panicked while holding `mutex`. Loom's mutex is never poisoned.
+pub(crate) fn is_poisoned<T>(mutex: &Mutex<T>) -> bool {
+    #[cfg(not(loom))]
+    return mutex.is_poisoned();
+    #[cfg(loom)]
+    {
+        let _ = mutex;
+        false
+    }
+}
+
+/// Forget that a thread panicked while holding `mutex`, once its data has been repaired
+pub(crate) fn clear_poison<T>(mutex: &Mutex<T>) {
+    #[cfg(not(loom))]
+    mutex.clear_poison();
+    #[cfg(loom)]
+    let _ = mutex;
+}
+
+/// Block until the calling thread is unparked or `timeout` passes. Loom has no timed
+/// park, so under loom this yields instead, as if the timeout had passed at once.
+pub(crate) fn park_timeout(timeout: Duration) {
+    #[cfg(not(loom))]
+    std::thread::park_timeout(timeout);
+    #[cfg(loom)]
+    {
+        let _ = timeout;
+        loom::thread::yield_now();
+    }
+}
+
+/// Wake `thread` if it is blocked in `park_timeout`. Under loom, where `park_timeout`
+/// never blocks, this does nothing: loom would wake the thread even if it were waiting
+/// for a lock.
+pub(crate) fn unpark(thread: &Thread) {
+    #[cfg(not(loom))]
+    thread.unpark();
+    #[cfg(loom)]
+    let _ = thread;
+}
+
+/// A FIFO queue shared by all threads, standing in for crossbeam's `Injector` under loom
+#[cfg(loom)]
+#[derive(Debug)]
+pub(crate) struct Injector<T>(Mutex<std::collections::VecDeque<T>>);
+
+#[cfg(loom)]
+impl<T> Default for Injector<T> {
+    fn default() -> Self {
+        Self(Mutex::new(std::collections::VecDeque::new()))
+    }
+}
+
+#[cfg(loom)]
+impl<T> Injector<T> {
+    pub(crate) fn push(&self, task: T) {
+        self.0.lock().unwrap().push_back(task);
+    }
+
+    pub(crate) fn steal(&self) -> Steal<T> {
+        self.0
+            .lock()
+            .unwrap()
+            .pop_front()
+            .map_or(Steal::Empty, Steal::Success)
+    }
+}
@@ -0,0 +1,221 @@
+//! Several tenants served by one process, each with its own engine.
+//!
+//! Client and transaction IDs are only unique within a tenant (e.g. a merchant), so
+//! `TenantEngines` keeps a separate `PaymentsEngine` per tenant, created from a shared
+//! `EngineConfig` the first time the tenant is seen. A transaction's tenant comes from
+//! the caller (`process_transaction`), from the stream it arrived on
+//! (`process_tenant_stream`), or from the optional `tenant` column of a mixed file
+//! (`process_transactions_from_reader`); rows without one belong to the empty tenant.
+//! Account output gains a leading `tenant` column.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Read;
+
+use super::{
+    EngineConfig, ErrorPolicy, ParseErrorLimit, ParseErrorTally, PaymentsEngine, read_transactions,
+    transaction_reader,
+};
+use crate::account::{Account, ClientId, TenantId};
+use crate::errors::PaymentsError;
+use crate::transaction::{InputFormat, Transaction};
+
+/// Configures a tenant's engine right after it is created
+type EngineSetup = Box<dyn Fn(&TenantId, &mut PaymentsEngine)>;
+
+/// One engine per tenant, by tenant ID
+pub struct TenantEngines {
+    config: EngineConfig,
+    setup: Option<EngineSetup>,
+    engines: BTreeMap<TenantId, PaymentsEngine>,
+    /// Applied to every tenant's engine as well as to mixed files
+    error_policy: ErrorPolicy,
+    input_format: InputFormat,
+    parse_error_limit: Option<ParseErrorLimit>,
+}
+
+impl fmt::Debug for TenantEngines {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TenantEngines")
+            .field("config", &self.config)
+            .field("tenants", &self.engines.keys().collect::<Vec<_>>())
+            .field("error_policy", &self.error_policy)
+            .field("input_format", &self.input_format)
+            .field("parse_error_limit", &self.parse_error_limit)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TenantEngines {
+    /// No tenants yet; each one gets a `PaymentsEngine` built from `config`
+    pub fn new(config: EngineConfig) -> Self {
+        Self {
+            config,
+            setup: None,
+            engines: BTreeMap::new(),
+            error_policy: ErrorPolicy::default(),
+            input_format: InputFormat::default(),
+            parse_error_limit: None,
+        }
+    }
+
+    /// Run `setup` on each tenant's engine when it is created, e.g. to set its policy
+    /// or add validators and observers. Engines that already exist are not affected.
+    pub fn set_engine_setup(&mut self, setup: impl Fn(&TenantId, &mut PaymentsEngine) + 'static) {
+        self.setup = Some(Box::new(setup));
+    }
+
+    pub fn set_error_policy(&mut self, error_policy: ErrorPolicy) {
+        self.error_policy = error_policy;
+        for engine in self.engines.values_mut() {
+            engine.set_error_policy(error_policy);
+        }
+    }
+
+    pub fn set_input_format(&mut self, input_format: InputFormat) {
+        for engine in self.engines.values_mut() {
+            engine.set_input_format(input_format.clone());
+        }
+        self.input_format = input_format;
+    }
+
+    pub fn set_parse_error_limit(&mut self, limit: Option<ParseErrorLimit>) {
+        self.parse_error_limit = limit;
+        for engine in self.engines.values_mut() {
+            engine.set_parse_error_limit(limit);
+        }
+    }
+
+    /// Tenants seen so far, in order
+    pub fn tenants(&self) -> impl Iterator<Item = &TenantId> {
+        self.engines.keys()
+    }
+
+    /// The engine of `tenant`, if it has been seen
+    pub fn engine(&self, tenant: &TenantId) -> Option<&PaymentsEngine> {
+        self.engines.get(tenant)
+    }
+
+    /// The engine of `tenant`, created if it has not been seen yet
+    pub fn engine_mut(&mut self, tenant: &TenantId) -> &mut PaymentsEngine {
+        if !self.engines.contains_key(tenant) {
+            let mut engine = PaymentsEngine::new(self.config.clone());
+            engine.set_error_policy(self.error_policy);
+            engine.set_input_format(self.input_format.clone());
+            engine.set_parse_error_limit(self.parse_error_limit);
+            if let Some(setup) = &self.setup {
+                setup(tenant, &mut engine);
+            }
+            log::info!("Created engine for tenant '{}'", tenant);
+            self.engines.insert(tenant.clone(), engine);
+        }
+        self.engines
+            .get_mut(tenant)
+            .expect("tenant engine was just inserted")
+    }
+
+    /// Apply `transaction` to `tenant`'s engine, whatever tenant the transaction names
+    pub fn process_transaction(
+        &mut self,
+        tenant: &TenantId,
+        transaction: &Transaction,
+    ) -> Result<(), PaymentsError> {
+        self.engine_mut(tenant).process_transaction(transaction)
+    }
+
+    /// Process a stream that belongs to `tenant` as a whole, e.g. one merchant's
+    /// connection. Its rows need no `tenant` column; one they carry is ignored.
+    pub fn process_tenant_stream<R: Read>(
+        &mut self,
+        tenant: &TenantId,
+        reader: R,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.engine_mut(tenant)
+            .process_transactions_from_reader(reader)
+    }
+
+    /// Process a file mixing several tenants, sending each row to the engine of the
+    /// tenant in its `tenant` column, under this collection's error policy and parse
+    /// error limit
+    pub fn process_transactions_from_reader<R: Read>(
+        &mut self,
+        reader: R,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut rdr = transaction_reader(reader, &self.input_format);
+        let lines = read_transactions(&mut rdr, self.input_format.clone())?.enumerate();
+        let mut parse_errors = ParseErrorTally::new(self.parse_error_limit);
+        let no_tenant = TenantId::default();
+
+        for (idx, line) in lines {
+            parse_errors.read();
+            let transaction: Transaction = match line {
+                Ok(tx) => tx,
+                Err(e) => {
+                    log::error!("Failed to parse line {}: {}", idx + 1, e);
+                    if self.error_policy == ErrorPolicy::Abort {
+                        return Err(e.at_line(idx as u64 + 1).into());
+                    }
+                    parse_errors.failed()?;
+                    continue;
+                }
+            };
+
+            let tenant = transaction.tenant.as_ref().unwrap_or(&no_tenant);
+            if let Err(e) = self.engine_mut(tenant).process_transaction(&transaction) {
+                log::error!(
+                    "Failed to process transaction {:?} of tenant '{}': {}",
+                    transaction,
+                    tenant,
+                    e
+                );
+                if self.error_policy == ErrorPolicy::Abort {
+                    return Err(e.at_line(idx as u64 + 1).into());
+                }
+            }
+        }
+        parse_errors.finish()?;
+        Ok(())
+    }
+
+    /// The account of `client` of `tenant`, if both exist
+    pub fn peek_account(
+        &self,
+        tenant: &TenantId,
+        client: ClientId,
+    ) -> Result<Option<Account>, PaymentsError> {
+        match self.engines.get(tenant) {
+            Some(engine) => engine.peek_account(client),
+            None => Ok(None),
+        }
+    }
+
+    /// Every tenant's accounts as written by its engine, with the tenant ID as an extra
+    /// first column, ordered by tenant
+    pub fn write_accounts_csv<W: std::io::Write>(
+        &self,
+        writer: W,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut wtr = csv::Writer::from_writer(writer);
+        let mut header_written = false;
+
+        for (tenant, engine) in &self.engines {
+            let mut accounts = Vec::new();
+            engine.write_accounts_csv(&mut accounts)?;
+            let mut rdr = csv::Reader::from_reader(accounts.as_slice());
+            let headers = rdr.headers()?.clone();
+            if headers.is_empty() {
+                continue;
+            }
+            if !header_written {
+                wtr.write_record(std::iter::once("tenant").chain(headers.iter()))?;
+                header_written = true;
+            }
+            for record in rdr.records() {
+                wtr.write_record(std::iter::once(tenant.as_str()).chain(record?.iter()))?;
+            }
+        }
+
+        wtr.flush()?;
+        Ok(())
+    }
+}
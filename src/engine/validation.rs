@@ -0,0 +1,91 @@
+//! Pluggable checks run on every transaction before an engine applies it.
+//!
+//! Validators see only the incoming transaction, never engine state, so the same chain
+//! can be shared by every engine type, including the concurrent engine's workers.
+
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use crate::account::ClientId;
+use crate::errors::PaymentsError;
+use crate::transaction::{Amount, Transaction};
+
+/// A check a transaction must pass before it is applied. Return
+/// `PaymentsError::ValidationFailed` (or any other error) to reject it.
+pub trait TransactionValidator: Debug + Send + Sync {
+    fn validate(&self, transaction: &Transaction) -> Result<(), PaymentsError>;
+}
+
+/// Validators run in the order they were added; the first rejection wins.
+#[derive(Debug, Clone, Default)]
+pub struct ValidatorChain {
+    validators: Vec<Arc<dyn TransactionValidator>>,
+}
+
+impl ValidatorChain {
+    pub fn push(&mut self, validator: impl TransactionValidator + 'static) {
+        self.validators.push(Arc::new(validator));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.validators.is_empty()
+    }
+
+    pub fn validate(&self, transaction: &Transaction) -> Result<(), PaymentsError> {
+        self.validators
+            .iter()
+            .try_for_each(|validator| validator.validate(transaction))
+    }
+}
+
+/// Rejects amounts with more significant decimal places than allowed.
+/// Trailing zeros do not count, so `1.50` passes a two-place limit.
+#[derive(Debug, Clone, Copy)]
+pub struct MaxPrecision(pub u32);
+
+impl TransactionValidator for MaxPrecision {
+    fn validate(&self, transaction: &Transaction) -> Result<(), PaymentsError> {
+        match transaction.amount {
+            Some(amount) if amount.normalize().scale() > self.0 => {
+                Err(PaymentsError::ValidationFailed(format!(
+                    "amount {} has more than {} decimal places",
+                    amount, self.0
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Rejects deposits and withdrawals above a fixed amount.
+#[derive(Debug, Clone, Copy)]
+pub struct MaxAmount(pub Amount);
+
+impl TransactionValidator for MaxAmount {
+    fn validate(&self, transaction: &Transaction) -> Result<(), PaymentsError> {
+        match transaction.amount {
+            Some(amount) if amount > self.0 => Err(PaymentsError::ValidationFailed(format!(
+                "amount {} exceeds the limit of {}",
+                amount, self.0
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Rejects every transaction from the listed clients.
+#[derive(Debug, Clone, Default)]
+pub struct ClientBlocklist(pub HashSet<ClientId>);
+
+impl TransactionValidator for ClientBlocklist {
+    fn validate(&self, transaction: &Transaction) -> Result<(), PaymentsError> {
+        if self.0.contains(&transaction.client) {
+            return Err(PaymentsError::ValidationFailed(format!(
+                "client {} is blocked",
+                transaction.client
+            )));
+        }
+        Ok(())
+    }
+}
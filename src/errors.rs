@@ -1,9 +1,11 @@
+use crate::account::ClientId;
 use crate::transaction::TxId;
 use thiserror::Error;
 
 /// Custom error type for payment processing errors.
 /// Includes errors for account issues, transaction problems, and invalid operations.
-/// Each variant provides a descriptive message for easier debugging and user feedback.
+/// Each variant provides a descriptive message for easier debugging and user feedback,
+/// a stable numeric `code`, and the transaction and client it concerns where known.
 #[derive(Error, Debug)]
 pub enum PaymentsError {
     #[error("Failed to parse CSV: {0}")]
@@ -12,18 +14,295 @@ pub enum PaymentsError {
     DecimalError(#[from] rust_decimal::Error),
     #[error("I/O error: {0}")]
     IoError(#[from] std::io::Error),
-    #[error("Account is frozen due to chargeback")]
-    AccountFrozen,
-    #[error("Insufficient funds for withdrawal")]
-    InsufficientFunds,
-    #[error("Transaction not found")]
-    TransactionNotFound,
+    #[error("Account {0} is frozen")]
+    AccountFrozen(ClientId),
+    #[error("Account {0} is closed")]
+    AccountClosed(ClientId),
+    #[error("Insufficient funds in account {0}")]
+    InsufficientFunds(ClientId),
+    #[error("Transaction {0} not found")]
+    TransactionNotFound(TxId),
+    #[error("Transaction {0} is past its dispute window")]
+    DisputeWindowExpired(TxId),
     #[error("Transaction already disputed: {0}")]
     TransactionAlreadyDisputed(TxId),
-    #[error("Transaction is not under dispute")]
-    TransactionNotDisputed,
-    #[error("Client ID mismatch")]
-    ClientIdMismatch,
+    #[error("Client {0} has reached the limit of open disputes")]
+    TooManyOpenDisputes(ClientId),
+    #[error("Transaction {0} is not under dispute")]
+    TransactionNotDisputed(TxId),
+    #[error("Client ID mismatch: transaction {tx} belongs to client {expected}, not {found}")]
+    ClientIdMismatch {
+        tx: TxId,
+        expected: ClientId,
+        found: ClientId,
+    },
     #[error("Invalid transaction: {0}")]
     InvalidTransaction(String),
+    #[error("Transaction failed validation: {0}")]
+    ValidationFailed(String),
+    #[error("Client {0} exceeded its rate limit")]
+    RateLimited(ClientId),
+    /// A stream's credentials were missing, unknown or expired
+    #[error("Authentication failed: {0}")]
+    Unauthenticated(String),
+    /// An authenticated stream submitted a transaction for a client outside its grant
+    #[error("{identity} may not submit transactions for client {client}")]
+    Unauthorized { identity: String, client: ClientId },
+    #[error("Invalid account record: {0}")]
+    InvalidAccount(String),
+    #[error("Invalid input header: missing {missing} (expected columns {expected}; found {found})")]
+    InvalidHeader {
+        missing: String,
+        expected: String,
+        found: String,
+    },
+    #[error("Too many unparseable rows: {failed} of {rows} read failed to parse")]
+    TooManyParseErrors { failed: u64, rows: u64 },
+    /// `client` reused idempotency key `key` for a transaction different from the one
+    /// first accepted under it
+    #[error(
+        "Idempotency key '{key}' of client {client} was already used for a different transaction"
+    )]
+    IdempotencyConflict { client: ClientId, key: String },
+    #[error("Cannot merge engine states: {0}")]
+    MergeConflict(String),
+    #[error("Failed to publish events: {0}")]
+    PublishFailed(String),
+    #[error("Invalid configuration: {0}")]
+    ConfigError(String),
+    #[error("Failed to acquire {0} lock: poisoned by a panicked thread")]
+    LockPoisoned(&'static str),
+    /// A concurrent run lost records: `lost` were sent to workers but never applied or
+    /// rejected, and `unsent` were never dispatched because the run stopped early
+    #[error(
+        "Worker failed: {reason} ({lost} records sent to workers were not applied, {unsent} were never sent)"
+    )]
+    WorkerFailed {
+        lost: u64,
+        unsent: u64,
+        reason: String,
+    },
+    /// A concurrent engine was about to apply a row of `client` after one that comes later
+    /// in the input, line `after`. The row is not applied.
+    #[error("Transaction for client {client} arrived out of order, after line {after}")]
+    OutOfOrder { client: ClientId, after: u64 },
+    /// Other engines sharing the state kept changing what transaction `tx` depends on;
+    /// it was not applied after `attempts` tries
+    #[error("Transaction {tx} conflicted with other engines {attempts} times and was not applied")]
+    WriteConflict { tx: TxId, attempts: u32 },
+    /// `source` raised by a row of an input file. `line` counts rows from 1, not
+    /// counting the header.
+    #[error("line {line}: {source}")]
+    AtLine {
+        line: u64,
+        source: Box<PaymentsError>,
+    },
+}
+
+impl PaymentsError {
+    /// Stable numeric code of the variant, for reports and API responses that should not
+    /// parse messages. `AtLine` has the code of the error it wraps.
+    ///
+    /// | Range | Errors |
+    /// |-------|--------|
+    /// | 1xx | Input and configuration |
+    /// | 2xx | Account state |
+    /// | 3xx | Dispute lifecycle |
+    /// | 4xx | Validation |
+    /// | 5xx | Engine state and publishing |
+    pub fn code(&self) -> u16 {
+        match self {
+            Self::CsvError(_) => 100,
+            Self::DecimalError(_) => 101,
+            Self::IoError(_) => 102,
+            Self::InvalidTransaction(_) => 103,
+            Self::InvalidAccount(_) => 104,
+            Self::ConfigError(_) => 105,
+            Self::InvalidHeader { .. } => 106,
+            Self::TooManyParseErrors { .. } => 107,
+            Self::IdempotencyConflict { .. } => 108,
+            Self::AccountFrozen(_) => 200,
+            Self::AccountClosed(_) => 201,
+            Self::InsufficientFunds(_) => 202,
+            Self::TransactionNotFound(_) => 300,
+            Self::DisputeWindowExpired(_) => 301,
+            Self::TransactionAlreadyDisputed(_) => 302,
+            Self::TooManyOpenDisputes(_) => 303,
+            Self::TransactionNotDisputed(_) => 304,
+            Self::ClientIdMismatch { .. } => 305,
+            Self::ValidationFailed(_) => 400,
+            Self::RateLimited(_) => 401,
+            Self::Unauthenticated(_) => 402,
+            Self::Unauthorized { .. } => 403,
+            Self::MergeConflict(_) => 500,
+            Self::PublishFailed(_) => 501,
+            Self::LockPoisoned(_) => 502,
+            Self::WorkerFailed { .. } => 503,
+            Self::OutOfOrder { .. } => 504,
+            Self::WriteConflict { .. } => 505,
+            Self::AtLine { source, .. } => source.code(),
+        }
+    }
+
+    /// Whether the error comes from the submission, the rules it broke, or the engine
+    /// itself
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            Self::CsvError(_)
+            | Self::DecimalError(_)
+            | Self::InvalidTransaction(_)
+            | Self::InvalidAccount(_)
+            | Self::InvalidHeader { .. }
+            | Self::TooManyParseErrors { .. }
+            | Self::IdempotencyConflict { .. }
+            | Self::ConfigError(_)
+            | Self::MergeConflict(_) => ErrorCategory::InputError,
+            Self::AccountFrozen(_)
+            | Self::AccountClosed(_)
+            | Self::InsufficientFunds(_)
+            | Self::TransactionNotFound(_)
+            | Self::DisputeWindowExpired(_)
+            | Self::TransactionAlreadyDisputed(_)
+            | Self::TooManyOpenDisputes(_)
+            | Self::TransactionNotDisputed(_)
+            | Self::ClientIdMismatch { .. }
+            | Self::ValidationFailed(_)
+            | Self::RateLimited(_)
+            | Self::Unauthenticated(_)
+            | Self::Unauthorized { .. } => ErrorCategory::BusinessRuleViolation,
+            Self::IoError(_)
+            | Self::PublishFailed(_)
+            | Self::LockPoisoned(_)
+            | Self::WorkerFailed { .. }
+            | Self::OutOfOrder { .. }
+            | Self::WriteConflict { .. } => ErrorCategory::InternalError,
+            Self::AtLine { source, .. } => source.category(),
+        }
+    }
+
+    /// Whether the same submission may succeed if tried again. Only transient I/O and
+    /// publishing failures, rate limiting and write conflicts on shared state are; a
+    /// poisoned lock stays poisoned.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self.inner(),
+            Self::IoError(_)
+                | Self::PublishFailed(_)
+                | Self::RateLimited(_)
+                | Self::WriteConflict { .. }
+        )
+    }
+
+    /// The transaction the error concerns, if it names one
+    pub fn tx(&self) -> Option<TxId> {
+        match self {
+            Self::TransactionNotFound(tx)
+            | Self::DisputeWindowExpired(tx)
+            | Self::TransactionAlreadyDisputed(tx)
+            | Self::TransactionNotDisputed(tx)
+            | Self::ClientIdMismatch { tx, .. }
+            | Self::WriteConflict { tx, .. } => Some(*tx),
+            Self::AtLine { source, .. } => source.tx(),
+            _ => None,
+        }
+    }
+
+    /// The client the error concerns, if it names one. For `ClientIdMismatch` this is the
+    /// client on the offending row.
+    pub fn client(&self) -> Option<ClientId> {
+        match self {
+            Self::AccountFrozen(client)
+            | Self::AccountClosed(client)
+            | Self::InsufficientFunds(client)
+            | Self::TooManyOpenDisputes(client)
+            | Self::RateLimited(client)
+            | Self::ClientIdMismatch { found: client, .. }
+            | Self::IdempotencyConflict { client, .. }
+            | Self::Unauthorized { client, .. }
+            | Self::OutOfOrder { client, .. } => Some(*client),
+            Self::AtLine { source, .. } => source.client(),
+            _ => None,
+        }
+    }
+
+    /// The input row that raised the error, if known
+    pub fn line(&self) -> Option<u64> {
+        match self {
+            Self::AtLine { line, .. } => Some(*line),
+            _ => None,
+        }
+    }
+
+    /// This error attributed to input row `line`; an error already attributed keeps its
+    /// line
+    pub fn at_line(self, line: u64) -> Self {
+        match self {
+            Self::AtLine { .. } => self,
+            source => Self::AtLine {
+                line,
+                source: Box::new(source),
+            },
+        }
+    }
+
+    /// The error without any line attribution
+    pub fn inner(&self) -> &PaymentsError {
+        match self {
+            Self::AtLine { source, .. } => source.inner(),
+            _ => self,
+        }
+    }
+}
+
+/// Broad class of a `PaymentsError`, for callers deciding how to report it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCategory {
+    /// The submission is malformed or inconsistent; fix it before resubmitting.
+    InputError,
+    /// The submission is well formed but breaks an account or dispute rule.
+    BusinessRuleViolation,
+    /// The engine or its environment failed; the submission may not be at fault.
+    InternalError,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context() {
+        let error = PaymentsError::ClientIdMismatch {
+            tx: TxId::new(7),
+            expected: ClientId::new(1),
+            found: ClientId::new(2),
+        }
+        .at_line(3);
+        assert_eq!(error.code(), 305);
+        assert_eq!(error.tx(), Some(TxId::new(7)));
+        assert_eq!(error.client(), Some(ClientId::new(2)));
+        assert_eq!(error.line(), Some(3));
+        assert_eq!(
+            error.to_string(),
+            "line 3: Client ID mismatch: transaction 7 belongs to client 1, not 2"
+        );
+        assert_eq!(error.at_line(9).line(), Some(3));
+    }
+
+    #[test]
+    fn test_category() {
+        let insufficient = PaymentsError::InsufficientFunds(ClientId::new(1)).at_line(2);
+        assert_eq!(
+            insufficient.category(),
+            ErrorCategory::BusinessRuleViolation
+        );
+        assert!(!insufficient.is_retryable());
+
+        let poisoned = PaymentsError::LockPoisoned("engine");
+        assert_eq!(poisoned.category(), ErrorCategory::InternalError);
+        assert!(!poisoned.is_retryable());
+
+        let io = PaymentsError::from(std::io::Error::other("broken pipe"));
+        assert_eq!(io.category(), ErrorCategory::InternalError);
+        assert!(io.is_retryable());
+    }
 }
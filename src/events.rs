@@ -0,0 +1,459 @@
+//! Publishing of domain events (accepted transactions, account locks, expired disputes
+//! and refused stream access) to a message broker for downstream consumers such as
+//! fraud analytics and audit.
+//!
+//! Events are JSON objects tagged with an `event` field. Delivery is at-least-once: an
+//! event is only produced after the engine processed its transaction, every published
+//! event is kept until the broker has confirmed it, and unconfirmed events are resent
+//! after a reconnect. Consumers should deduplicate on `tx`.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::account::Account;
+use crate::engine::auth::{Credentials, Identity};
+use crate::engine::observer::EngineObserver;
+use crate::errors::PaymentsError;
+use crate::transaction::{Transaction, TxId};
+
+/// Events published between delivery confirmations
+const CONFIRM_EVERY: usize = 1000;
+/// Attempts to deliver outstanding events in `EventSink::flush`
+const FLUSH_ATTEMPTS: u32 = 5;
+
+/// An event as published to the broker
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum DomainEvent {
+    TransactionAccepted {
+        #[serde(flatten)]
+        transaction: Transaction,
+    },
+    AccountLocked {
+        /// The chargeback's transaction ID
+        tx: TxId,
+        #[serde(flatten)]
+        account: Account,
+        /// The chargeback's reason code, or the dispute's if it gave none
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason_code: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        case_ref: Option<String>,
+    },
+    /// A dispute was rejected because its transaction is past the dispute window
+    DisputeExpired {
+        #[serde(flatten)]
+        transaction: Transaction,
+    },
+    /// An authenticated stream submitted a row for a client outside its grant
+    Unauthorized {
+        identity: String,
+        #[serde(flatten)]
+        transaction: Transaction,
+    },
+    /// A stream's credentials were rejected; API keys are never included
+    AuthenticationFailed {
+        method: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        subject: Option<String>,
+        error: String,
+    },
+}
+
+/// A connection to a broker topic or subject.
+pub trait EventPublisher: Debug + Send {
+    /// Queue one serialized event for sending
+    fn publish(&mut self, payload: &[u8]) -> std::io::Result<()>;
+
+    /// Return once the broker has received everything published so far
+    fn confirm(&mut self) -> std::io::Result<()>;
+
+    /// Re-establish the connection after an error. Events published since the last
+    /// successful `confirm` are sent again afterwards.
+    fn reconnect(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<P: EventPublisher + ?Sized> EventPublisher for Box<P> {
+    fn publish(&mut self, payload: &[u8]) -> std::io::Result<()> {
+        (**self).publish(payload)
+    }
+
+    fn confirm(&mut self) -> std::io::Result<()> {
+        (**self).confirm()
+    }
+
+    fn reconnect(&mut self) -> std::io::Result<()> {
+        (**self).reconnect()
+    }
+}
+
+/// Core NATS publisher on a plain TCP connection. `confirm` sends a `PING` and waits for
+/// the server's `PONG`, which the server only sends after processing every earlier `PUB`.
+#[derive(Debug)]
+pub struct NatsPublisher {
+    address: String,
+    subject: String,
+    writer: BufWriter<TcpStream>,
+    reader: BufReader<TcpStream>,
+}
+
+impl NatsPublisher {
+    /// Connect to the server at `address` (`host:port`) and publish to `subject`.
+    pub fn connect(address: &str, subject: &str) -> std::io::Result<Self> {
+        let (writer, reader) = Self::open(address)?;
+        Ok(Self {
+            address: address.to_string(),
+            subject: subject.to_string(),
+            writer,
+            reader,
+        })
+    }
+
+    fn open(address: &str) -> std::io::Result<(BufWriter<TcpStream>, BufReader<TcpStream>)> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream);
+
+        // The server greets with INFO before accepting CONNECT
+        let mut info = String::new();
+        reader.read_line(&mut info)?;
+        if !info.starts_with("INFO") {
+            return Err(std::io::Error::other(format!(
+                "unexpected NATS greeting: {}",
+                info.trim_end()
+            )));
+        }
+        writer.write_all(
+            b"CONNECT {\"verbose\":false,\"pedantic\":false,\"name\":\"payment-engine\"}\r\n",
+        )?;
+        writer.flush()?;
+        Ok((writer, reader))
+    }
+}
+
+impl EventPublisher for NatsPublisher {
+    fn publish(&mut self, payload: &[u8]) -> std::io::Result<()> {
+        write!(self.writer, "PUB {} {}\r\n", self.subject, payload.len())?;
+        self.writer.write_all(payload)?;
+        self.writer.write_all(b"\r\n")
+    }
+
+    fn confirm(&mut self) -> std::io::Result<()> {
+        self.writer.write_all(b"PING\r\n")?;
+        self.writer.flush()?;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            match line.trim_end() {
+                "PONG" => return Ok(()),
+                "PING" => {
+                    self.writer.write_all(b"PONG\r\n")?;
+                    self.writer.flush()?;
+                }
+                err if err.starts_with("-ERR") => {
+                    return Err(std::io::Error::other(err.to_string()));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn reconnect(&mut self) -> std::io::Result<()> {
+        (self.writer, self.reader) = Self::open(&self.address)?;
+        Ok(())
+    }
+}
+
+/// Newline-delimited JSON to any writer, e.g. the stdin of `kcat -P -t <topic>` to
+/// produce to Kafka, or a file.
+#[derive(Debug)]
+pub struct LinePublisher<W: Write + Debug + Send> {
+    writer: W,
+}
+
+impl<W: Write + Debug + Send> LinePublisher<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write + Debug + Send> EventPublisher for LinePublisher<W> {
+    fn publish(&mut self, payload: &[u8]) -> std::io::Result<()> {
+        self.writer.write_all(payload)?;
+        self.writer.write_all(b"\n")
+    }
+
+    fn confirm(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Engine observer that publishes a `DomainEvent` for every accepted transaction, every
+/// account lock and every dispute past its window. Clones share the same publisher, so
+/// keep one to call `flush` on once processing is done.
+#[derive(Debug, Clone)]
+pub struct EventSink {
+    inner: Arc<Mutex<SinkState>>,
+}
+
+#[derive(Debug)]
+struct SinkState {
+    publisher: Box<dyn EventPublisher>,
+    /// Serialized events not yet handed to the publisher
+    pending: VecDeque<Vec<u8>>,
+    /// Events handed to the publisher since the last successful confirmation
+    unconfirmed: Vec<Vec<u8>>,
+    /// The last delivery failed; retry only every `CONFIRM_EVERY` events until it works
+    failing: bool,
+}
+
+impl EventSink {
+    pub fn new(publisher: impl EventPublisher + 'static) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(SinkState {
+                publisher: Box::new(publisher),
+                pending: VecDeque::new(),
+                unconfirmed: Vec::new(),
+                failing: false,
+            })),
+        }
+    }
+
+    fn enqueue(&self, event: &DomainEvent) {
+        let payload = match serde_json::to_vec(event) {
+            Ok(payload) => payload,
+            Err(e) => {
+                log::error!("Failed to serialize event {:?}: {}", event, e);
+                return;
+            }
+        };
+        let Ok(mut state) = self.inner.lock() else {
+            log::error!("Event sink lock poisoned; dropping event");
+            return;
+        };
+        state.pending.push_back(payload);
+        if state.failing && state.pending.len() % CONFIRM_EVERY != 0 {
+            return;
+        }
+        if let Err(e) = state.deliver(CONFIRM_EVERY) {
+            log::warn!("Event delivery failed, will retry: {}", e);
+        }
+    }
+
+    /// Deliver and confirm every outstanding event, reconnecting and retrying a few
+    /// times. Call before reporting a run as successful.
+    pub fn flush(&self) -> Result<(), PaymentsError> {
+        let mut state = self
+            .inner
+            .lock()
+            .map_err(|_| PaymentsError::LockPoisoned("event sink"))?;
+        let mut last_error = None;
+        for attempt in 0..FLUSH_ATTEMPTS {
+            if attempt > 0 {
+                std::thread::sleep(Duration::from_millis(100 << attempt));
+            }
+            match state.deliver(0) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    log::warn!("Event delivery attempt {} failed: {}", attempt + 1, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(PaymentsError::PublishFailed(format!(
+            "{} events undelivered: {}",
+            state.pending.len(),
+            last_error.map(|e| e.to_string()).unwrap_or_default()
+        )))
+    }
+}
+
+impl SinkState {
+    /// Publish pending events and confirm once more than `batch` are unconfirmed.
+    /// On failure, unconfirmed events go back to the front of the queue and the
+    /// publisher reconnects, so nothing is lost.
+    fn deliver(&mut self, batch: usize) -> std::io::Result<()> {
+        let result = self.try_deliver(batch);
+        self.failing = result.is_err();
+        if self.failing {
+            for payload in self.unconfirmed.drain(..).rev() {
+                self.pending.push_front(payload);
+            }
+            if let Err(e) = self.publisher.reconnect() {
+                log::warn!("Event publisher reconnect failed: {}", e);
+            }
+        }
+        result
+    }
+
+    fn try_deliver(&mut self, batch: usize) -> std::io::Result<()> {
+        while let Some(payload) = self.pending.pop_front() {
+            let result = self.publisher.publish(&payload);
+            self.unconfirmed.push(payload);
+            result?;
+        }
+        if self.unconfirmed.len() > batch {
+            self.publisher.confirm()?;
+            self.unconfirmed.clear();
+        }
+        Ok(())
+    }
+}
+
+impl EngineObserver for EventSink {
+    fn on_accepted(&self, transaction: &Transaction) {
+        self.enqueue(&DomainEvent::TransactionAccepted {
+            transaction: transaction.clone(),
+        });
+    }
+
+    fn on_account_locked(&self, account: &Account, transaction: &Transaction) {
+        self.enqueue(&DomainEvent::AccountLocked {
+            tx: transaction.tx,
+            account: account.clone(),
+            reason_code: transaction.reason_code.clone(),
+            case_ref: transaction.case_ref.clone(),
+        });
+    }
+
+    fn on_dispute_expired(&self, transaction: &Transaction) {
+        self.enqueue(&DomainEvent::DisputeExpired {
+            transaction: transaction.clone(),
+        });
+    }
+
+    fn on_unauthorized(&self, identity: &Identity, transaction: &Transaction) {
+        self.enqueue(&DomainEvent::Unauthorized {
+            identity: identity.name.clone(),
+            transaction: transaction.clone(),
+        });
+    }
+
+    fn on_authentication_failed(&self, credentials: &Credentials, error: &PaymentsError) {
+        self.enqueue(&DomainEvent::AuthenticationFailed {
+            method: credentials.method(),
+            subject: credentials.subject().map(str::to_string),
+            error: error.to_string(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{EngineConfig, PaymentsEngine};
+    use crate::transaction::Amount;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    /// Publisher whose first `failures` confirmations fail, recording what it received
+    #[derive(Debug, Default)]
+    struct FlakyPublisher {
+        failures: usize,
+        buffered: Vec<Vec<u8>>,
+        received: Arc<Mutex<Vec<Vec<u8>>>>,
+    }
+
+    impl EventPublisher for FlakyPublisher {
+        fn publish(&mut self, payload: &[u8]) -> std::io::Result<()> {
+            self.buffered.push(payload.to_vec());
+            Ok(())
+        }
+
+        fn confirm(&mut self) -> std::io::Result<()> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                self.buffered.clear();
+                return Err(std::io::ErrorKind::ConnectionReset.into());
+            }
+            self.received.lock().unwrap().append(&mut self.buffered);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_events_survive_failed_delivery() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = EventSink::new(FlakyPublisher {
+            failures: 2,
+            received: received.clone(),
+            ..FlakyPublisher::default()
+        });
+
+        let mut engine = PaymentsEngine::new(EngineConfig::standard());
+        engine.add_observer(sink.clone());
+        let input = "type,client,tx,amount,reason_code,case_ref\n\
+                     deposit,1,1,10.0,,\nwithdrawal,1,2,50.0,,\n\
+                     dispute,1,1,,4837,CB-1\nchargeback,1,1,,,\n";
+        engine
+            .process_transactions_from_reader(input.as_bytes())
+            .unwrap();
+        sink.flush().unwrap();
+
+        let received = received.lock().unwrap();
+        let events: Vec<serde_json::Value> = received
+            .iter()
+            .map(|payload| serde_json::from_slice(payload).unwrap())
+            .collect();
+        // The rejected withdrawal produces no event
+        assert_eq!(events.len(), 4, "{:?}", events);
+        assert_eq!(events[0]["event"], "transaction_accepted");
+        assert_eq!(events[0]["type"], "deposit");
+        assert_eq!(
+            events[0]["amount"]
+                .as_str()
+                .unwrap()
+                .parse::<Amount>()
+                .unwrap(),
+            Amount::new(10, 0)
+        );
+        assert_eq!(events[3]["event"], "account_locked");
+        assert_eq!(events[3]["locked"], true);
+        // The chargeback row carries no case metadata, so the dispute's is used
+        for event in &events[1..] {
+            assert_eq!(event["reason_code"], "4837");
+            assert_eq!(event["case_ref"], "CB-1");
+        }
+    }
+
+    #[test]
+    fn test_nats_publisher() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"INFO {}\r\n").unwrap();
+            let mut received = Vec::new();
+            let mut buf = [0; 1024];
+            while !received.ends_with(b"PING\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                received.extend_from_slice(&buf[..n]);
+            }
+            stream.write_all(b"PONG\r\n").unwrap();
+            String::from_utf8(received).unwrap()
+        });
+
+        let mut publisher = NatsPublisher::connect(&address, "payments.events").unwrap();
+        publisher.publish(b"{\"tx\":1}").unwrap();
+        publisher.confirm().unwrap();
+
+        let received = server.join().unwrap();
+        assert!(received.starts_with("CONNECT {"), "{}", received);
+        assert!(
+            received.ends_with("PUB payments.events 8\r\n{\"tx\":1}\r\nPING\r\n"),
+            "{}",
+            received
+        );
+    }
+}
@@ -0,0 +1,139 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Follows a CSV file that is being appended to, like `tail -f`.
+/// Each call to `next_batch` returns the complete rows written since the previous call,
+/// prefixed with the file's header row, if it has one, so the batch can be fed straight
+/// to `process_transactions_from_reader`. A trailing row without a newline is held back
+/// until the writer finishes it.
+#[derive(Debug)]
+pub struct FollowingSource {
+    path: PathBuf,
+    file: File,
+
+    /// Byte offset of the first byte not yet read from the file
+    offset: u64,
+
+    /// Whether the file starts with a header row
+    has_headers: bool,
+
+    /// Header row including its newline, once it has been read
+    header: Option<Vec<u8>>,
+
+    /// Bytes read after the last complete row
+    partial: Vec<u8>,
+}
+
+impl FollowingSource {
+    /// Start following `path` from the beginning of the file.
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: File::open(path)?,
+            offset: 0,
+            has_headers: true,
+            header: None,
+            partial: Vec::new(),
+        })
+    }
+
+    /// Whether the file starts with a header row, as in `InputFormat::has_headers`.
+    /// Without one, every line is a row and batches carry no header.
+    pub fn with_headers(mut self, has_headers: bool) -> Self {
+        self.has_headers = has_headers;
+        self
+    }
+
+    /// Rows appended since the last call, with any header row prepended,
+    /// or `None` if no complete row has been appended.
+    /// If the file shrinks (truncated or replaced), following restarts from the top.
+    pub fn next_batch(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        let len = std::fs::metadata(&self.path)?.len();
+        if len < self.offset {
+            log::warn!(
+                "{:?} shrank from {} to {} bytes; following from the start",
+                self.path,
+                self.offset,
+                len
+            );
+            self.file = File::open(&self.path)?;
+            self.offset = 0;
+            self.header = None;
+            self.partial.clear();
+        }
+
+        self.file.seek(SeekFrom::Start(self.offset))?;
+        let read = self.file.read_to_end(&mut self.partial)?;
+        self.offset += read as u64;
+
+        let Some(last_newline) = self.partial.iter().rposition(|&b| b == b'\n') else {
+            return Ok(None);
+        };
+        let rest = self.partial.split_off(last_newline + 1);
+        let mut rows = std::mem::replace(&mut self.partial, rest);
+
+        let header = match &self.header {
+            Some(header) => header.clone(),
+            None if !self.has_headers => Vec::new(),
+            None => {
+                let header_end = rows.iter().position(|&b| b == b'\n').unwrap_or(0) + 1;
+                let header: Vec<u8> = rows.drain(..header_end).collect();
+                self.header = Some(header.clone());
+                header
+            }
+        };
+
+        if rows.is_empty() {
+            return Ok(None);
+        }
+
+        let mut batch = header;
+        batch.extend_from_slice(&rows);
+        Ok(Some(batch))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_follows_appended_rows() {
+        let path = std::env::temp_dir().join(format!("follow-test-{}.csv", std::process::id()));
+        let mut writer = File::create(&path).unwrap();
+        let mut source = FollowingSource::open(&path).unwrap();
+
+        writer
+            .write_all(b"type,client,tx,amount\ndeposit,1,1,1.0\ndepo")
+            .unwrap();
+        let batch = source.next_batch().unwrap().unwrap();
+        assert_eq!(batch, b"type,client,tx,amount\ndeposit,1,1,1.0\n");
+        assert!(source.next_batch().unwrap().is_none());
+
+        writer.write_all(b"sit,1,2,2.0\n").unwrap();
+        let batch = source.next_batch().unwrap().unwrap();
+        assert_eq!(batch, b"type,client,tx,amount\ndeposit,1,2,2.0\n");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_follows_headerless_rows() {
+        let path =
+            std::env::temp_dir().join(format!("follow-headerless-test-{}.csv", std::process::id()));
+        let mut writer = File::create(&path).unwrap();
+        let mut source = FollowingSource::open(&path).unwrap().with_headers(false);
+
+        writer.write_all(b"deposit,1,1,1.0\n").unwrap();
+        let batch = source.next_batch().unwrap().unwrap();
+        assert_eq!(batch, b"deposit,1,1,1.0\n");
+
+        writer.write_all(b"deposit,1,2,2.0\n").unwrap();
+        let batch = source.next_batch().unwrap().unwrap();
+        assert_eq!(batch, b"deposit,1,2,2.0\n");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,517 @@
+//! Length-prefixed framing of transactions for producers that need to know what
+//! happened to each one.
+//!
+//! Every frame is a 4-byte big-endian payload length followed by the payload. A
+//! producer sends one transaction per frame, either as a JSON object (any payload
+//! starting with `{`) or in the binary layout below, and `serve` answers every frame,
+//! in order, with a `Response` frame in the same encoding: an ACK once the transaction
+//! is applied (or recognised as an idempotent resubmission), or a NACK carrying the
+//! `PaymentsError` code and message.
+//!
+//! Binary transactions, all integers big-endian:
+//!
+//! ```text
+//! u8   version, 1
+//! u8   type: 0 deposit, 1 withdrawal, 2 dispute, 3 resolve, 4 chargeback, 5 close
+//! u16  client
+//! u32  tx
+//! u8   fields present: 0x01 amount, 0x02 timestamp, 0x04 reason_code, 0x08 case_ref,
+//!      0x10 tenant, 0x20 idempotency_key
+//! then each present field in that order:
+//!      amount     i64 mantissa, u8 scale (e.g. 12345, 2 for 123.45)
+//!      timestamp  u64 seconds since the Unix epoch
+//!      strings    u16 length, UTF-8 bytes
+//! ```
+//!
+//! Binary responses are `u8 status (0 ack, 1 nack), u32 tx, u16 code` followed, for a
+//! NACK, by the UTF-8 error message. JSON responses look like
+//! `{"status":"nack","tx":5,"code":202,"error":"Insufficient funds in account 1"}`.
+
+use serde::{Deserialize, Serialize};
+use std::io::{ErrorKind, Read, Write};
+
+use crate::engine::PaymentsEngine;
+use crate::errors::PaymentsError;
+use crate::transaction::{Amount, Transaction, TransactionType, TxId};
+
+/// Largest payload `serve` accepts; a producer announcing more is answered with a NACK
+/// and disconnected
+pub const MAX_FRAME_BYTES: u32 = 64 * 1024;
+
+const BINARY_VERSION: u8 = 1;
+
+const AMOUNT: u8 = 0x01;
+const TIMESTAMP: u8 = 0x02;
+const REASON_CODE: u8 = 0x04;
+const CASE_REF: u8 = 0x08;
+const TENANT: u8 = 0x10;
+const IDEMPOTENCY_KEY: u8 = 0x20;
+
+/// How a frame's payload is encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Binary,
+    Json,
+}
+
+impl Encoding {
+    /// The encoding of `payload`: JSON if it starts with `{`, binary otherwise
+    pub fn of(payload: &[u8]) -> Self {
+        match payload.first() {
+            Some(b'{') => Self::Json,
+            _ => Self::Binary,
+        }
+    }
+}
+
+/// The engine's answer to one transaction frame.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum Response {
+    Ack {
+        tx: TxId,
+    },
+    /// `tx` is 0 if the frame could not be decoded
+    Nack {
+        tx: TxId,
+        code: u16,
+        error: String,
+    },
+}
+
+impl Response {
+    pub fn nack(tx: TxId, error: &PaymentsError) -> Self {
+        Self::Nack {
+            tx,
+            code: error.code(),
+            error: error.to_string(),
+        }
+    }
+
+    pub fn encode(&self, encoding: Encoding) -> Vec<u8> {
+        match encoding {
+            Encoding::Json => serde_json::to_vec(self).expect("responses serialize to JSON"),
+            Encoding::Binary => {
+                let (status, tx, code, error) = match self {
+                    Self::Ack { tx } => (0, tx, 0, ""),
+                    Self::Nack { tx, code, error } => (1, tx, *code, error.as_str()),
+                };
+                let mut payload = vec![status];
+                payload.extend_from_slice(&tx.get().to_be_bytes());
+                payload.extend_from_slice(&code.to_be_bytes());
+                payload.extend_from_slice(error.as_bytes());
+                payload
+            }
+        }
+    }
+
+    pub fn decode(payload: &[u8]) -> Result<Self, PaymentsError> {
+        if Encoding::of(payload) == Encoding::Json {
+            return serde_json::from_slice(payload).map_err(malformed);
+        }
+        let mut decoder = Decoder(payload);
+        let status = decoder.u8()?;
+        let tx = TxId::new(decoder.u32()?);
+        let code = decoder.u16()?;
+        let error = std::str::from_utf8(decoder.0).map_err(malformed)?;
+        match status {
+            0 => Ok(Self::Ack { tx }),
+            1 => Ok(Self::Nack {
+                tx,
+                code,
+                error: error.to_string(),
+            }),
+            _ => Err(malformed(format!("unknown response status {}", status))),
+        }
+    }
+}
+
+/// Encode `transaction` as a frame payload. Fails with `InvalidTransaction` if a binary
+/// amount's mantissa does not fit in an i64 or a string is longer than 65535 bytes.
+pub fn encode_transaction(
+    transaction: &Transaction,
+    encoding: Encoding,
+) -> Result<Vec<u8>, PaymentsError> {
+    if encoding == Encoding::Json {
+        return serde_json::to_vec(transaction).map_err(malformed);
+    }
+    let type_index = TransactionType::ALL
+        .iter()
+        .position(|tx_type| *tx_type == transaction.tx_type)
+        .expect("ALL lists every transaction type");
+    let mut payload = vec![BINARY_VERSION, type_index as u8];
+    payload.extend_from_slice(&transaction.client.get().to_be_bytes());
+    payload.extend_from_slice(&transaction.tx.get().to_be_bytes());
+
+    let strings = [
+        (REASON_CODE, transaction.reason_code.as_deref()),
+        (CASE_REF, transaction.case_ref.as_deref()),
+        (
+            TENANT,
+            transaction.tenant.as_ref().map(|tenant| tenant.as_str()),
+        ),
+        (IDEMPOTENCY_KEY, transaction.idempotency_key.as_deref()),
+    ];
+    let mut fields = 0;
+    if transaction.amount.is_some() {
+        fields |= AMOUNT;
+    }
+    if transaction.timestamp.is_some() {
+        fields |= TIMESTAMP;
+    }
+    for (flag, value) in &strings {
+        if value.is_some() {
+            fields |= flag;
+        }
+    }
+    payload.push(fields);
+
+    if let Some(amount) = transaction.amount {
+        let mantissa = i64::try_from(amount.value().mantissa())
+            .map_err(|_| malformed(format!("amount {} is too precise", amount)))?;
+        payload.extend_from_slice(&mantissa.to_be_bytes());
+        payload.push(amount.scale() as u8);
+    }
+    if let Some(timestamp) = transaction.timestamp {
+        payload.extend_from_slice(&timestamp.to_be_bytes());
+    }
+    for value in strings.into_iter().filter_map(|(_, value)| value) {
+        let length = u16::try_from(value.len())
+            .map_err(|_| malformed(format!("string of {} bytes", value.len())))?;
+        payload.extend_from_slice(&length.to_be_bytes());
+        payload.extend_from_slice(value.as_bytes());
+    }
+    Ok(payload)
+}
+
+/// Decode a frame payload in either encoding. Fails with `InvalidTransaction` if it is
+/// malformed.
+pub fn decode_transaction(payload: &[u8]) -> Result<Transaction, PaymentsError> {
+    if Encoding::of(payload) == Encoding::Json {
+        return serde_json::from_slice(payload).map_err(malformed);
+    }
+    let mut decoder = Decoder(payload);
+    let version = decoder.u8()?;
+    if version != BINARY_VERSION {
+        return Err(malformed(format!("unsupported frame version {}", version)));
+    }
+    let type_index = decoder.u8()?;
+    let tx_type = TransactionType::ALL
+        .get(type_index as usize)
+        .cloned()
+        .ok_or_else(|| malformed(format!("unknown transaction type {}", type_index)))?;
+    let client = decoder.u16()?;
+    let tx = decoder.u32()?;
+    let fields = decoder.u8()?;
+    if fields & !(AMOUNT | TIMESTAMP | REASON_CODE | CASE_REF | TENANT | IDEMPOTENCY_KEY) != 0 {
+        return Err(malformed(format!("unknown field flags {:#04x}", fields)));
+    }
+
+    let mut transaction = Transaction::new(tx_type, client, tx, None);
+    if fields & AMOUNT != 0 {
+        let mantissa = i64::from_be_bytes(decoder.take()?);
+        let scale = decoder.u8()? as u32;
+        if scale > 28 {
+            return Err(malformed(format!("amount scale {}", scale)));
+        }
+        transaction.amount = Some(Amount::new(mantissa, scale));
+    }
+    if fields & TIMESTAMP != 0 {
+        transaction.timestamp = Some(u64::from_be_bytes(decoder.take()?));
+    }
+    if fields & REASON_CODE != 0 {
+        transaction.reason_code = Some(decoder.string()?);
+    }
+    if fields & CASE_REF != 0 {
+        transaction.case_ref = Some(decoder.string()?);
+    }
+    if fields & TENANT != 0 {
+        transaction.tenant = Some(decoder.string()?.into());
+    }
+    if fields & IDEMPOTENCY_KEY != 0 {
+        transaction.idempotency_key = Some(decoder.string()?);
+    }
+    if !decoder.0.is_empty() {
+        return Err(malformed(format!("{} trailing bytes", decoder.0.len())));
+    }
+    Ok(transaction)
+}
+
+/// Write `payload` as one frame.
+pub fn write_frame<W: Write>(writer: &mut W, payload: &[u8]) -> std::io::Result<()> {
+    let length = u32::try_from(payload.len())
+        .map_err(|_| std::io::Error::new(ErrorKind::InvalidInput, "frame too large"))?;
+    writer.write_all(&length.to_be_bytes())?;
+    writer.write_all(payload)
+}
+
+/// Read the next frame's payload, or `None` if the stream ends before a new frame.
+/// Fails with `InvalidData` if the frame is longer than `max_bytes`, leaving its
+/// payload unread, and with `UnexpectedEof` if the stream ends inside a frame.
+pub fn read_frame<R: Read>(reader: &mut R, max_bytes: u32) -> std::io::Result<Option<Vec<u8>>> {
+    let mut length = [0; 4];
+    let mut filled = 0;
+    while filled < length.len() {
+        match reader.read(&mut length[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    let length = u32::from_be_bytes(length);
+    if length > max_bytes {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "frame of {} bytes exceeds the limit of {}",
+                length, max_bytes
+            ),
+        ));
+    }
+    let mut payload = vec![0; length as usize];
+    reader.read_exact(&mut payload)?;
+    Ok(Some(payload))
+}
+
+/// Frames answered by `serve`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    pub acked: u64,
+    pub nacked: u64,
+}
+
+/// Apply the transaction frames read from `stream` to `engine`, answering each before
+/// reading the next, until the producer closes the stream. A frame that does not decode
+/// is NACKed and skipped; an oversized frame is NACKed and ends the connection with an
+/// error, since the rest of the stream cannot be trusted.
+pub fn serve<S: Read + Write>(
+    engine: &mut PaymentsEngine,
+    mut stream: S,
+) -> Result<FrameStats, PaymentsError> {
+    let mut stats = FrameStats::default();
+    loop {
+        let payload = match read_frame(&mut stream, MAX_FRAME_BYTES) {
+            Ok(Some(payload)) => payload,
+            Ok(None) => return Ok(stats),
+            Err(e) if e.kind() == ErrorKind::InvalidData => {
+                let error = PaymentsError::InvalidTransaction(e.to_string());
+                let response = Response::nack(TxId::default(), &error);
+                write_frame(&mut stream, &response.encode(Encoding::Binary))?;
+                stream.flush()?;
+                return Err(error);
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let encoding = Encoding::of(&payload);
+        let response = match decode_transaction(&payload) {
+            Ok(transaction) => match engine.process_transaction(&transaction) {
+                Ok(()) => Response::Ack { tx: transaction.tx },
+                Err(e) => Response::nack(transaction.tx, &e),
+            },
+            Err(e) => Response::nack(TxId::default(), &e),
+        };
+        match response {
+            Response::Ack { .. } => stats.acked += 1,
+            Response::Nack { .. } => stats.nacked += 1,
+        }
+        write_frame(&mut stream, &response.encode(encoding))?;
+        stream.flush()?;
+    }
+}
+
+/// Producer side of the protocol: sends one transaction at a time and waits for its
+/// response.
+#[derive(Debug)]
+pub struct FramedClient<S> {
+    stream: S,
+    encoding: Encoding,
+}
+
+impl<S: Read + Write> FramedClient<S> {
+    pub fn new(stream: S, encoding: Encoding) -> Self {
+        Self { stream, encoding }
+    }
+
+    /// Send `transaction` and return the engine's response.
+    pub fn send(&mut self, transaction: &Transaction) -> Result<Response, PaymentsError> {
+        write_frame(
+            &mut self.stream,
+            &encode_transaction(transaction, self.encoding)?,
+        )?;
+        self.stream.flush()?;
+        let payload = read_frame(&mut self.stream, MAX_FRAME_BYTES)?
+            .ok_or_else(|| std::io::Error::from(ErrorKind::UnexpectedEof))?;
+        Response::decode(&payload)
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+fn malformed(e: impl std::fmt::Display) -> PaymentsError {
+    PaymentsError::InvalidTransaction(format!("malformed frame: {}", e))
+}
+
+/// Reads big-endian fields from the front of a payload
+struct Decoder<'a>(&'a [u8]);
+
+impl Decoder<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], PaymentsError> {
+        let Some((bytes, rest)) = self.0.split_first_chunk::<N>() else {
+            return Err(malformed("payload ends early"));
+        };
+        self.0 = rest;
+        Ok(*bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, PaymentsError> {
+        Ok(u8::from_be_bytes(self.take()?))
+    }
+
+    fn u16(&mut self) -> Result<u16, PaymentsError> {
+        Ok(u16::from_be_bytes(self.take()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, PaymentsError> {
+        Ok(u32::from_be_bytes(self.take()?))
+    }
+
+    fn string(&mut self) -> Result<String, PaymentsError> {
+        let length = self.u16()? as usize;
+        if self.0.len() < length {
+            return Err(malformed("payload ends early"));
+        }
+        let (bytes, rest) = self.0.split_at(length);
+        self.0 = rest;
+        String::from_utf8(bytes.to_vec()).map_err(malformed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::EngineConfig;
+    use std::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn test_transaction_encoding() {
+        let mut dispute = Transaction::dispute(3, 7).at(1_700_000_000);
+        dispute.reason_code = Some("10.4".to_string());
+        dispute.tenant = Some("acme".into());
+        dispute.idempotency_key = Some("retry-1".to_string());
+        let transactions = [
+            Transaction::deposit(1, 1, Amount::new(12345, 2)),
+            Transaction::withdrawal(u16::MAX, u32::MAX, Amount::new(-1, 4)),
+            Transaction::close(2, 9),
+            dispute,
+        ];
+        for transaction in &transactions {
+            for encoding in [Encoding::Binary, Encoding::Json] {
+                let payload = encode_transaction(transaction, encoding).unwrap();
+                assert_eq!(Encoding::of(&payload), encoding);
+                let decoded = decode_transaction(&payload).unwrap();
+                assert_eq!(format!("{:?}", decoded), format!("{:?}", transaction));
+            }
+        }
+
+        let payload = encode_transaction(&transactions[0], Encoding::Binary).unwrap();
+        assert_eq!(
+            payload,
+            [
+                1, 0, 0, 1, 0, 0, 0, 1, AMOUNT, 0, 0, 0, 0, 0, 0, 0x30, 0x39, 2
+            ]
+        );
+        for truncated in 0..payload.len() {
+            let error = decode_transaction(&payload[..truncated]).unwrap_err();
+            assert_eq!(error.code(), 103);
+        }
+        assert!(decode_transaction(&[payload.as_slice(), &[0]].concat()).is_err());
+        assert!(decode_transaction(&[2, 0, 0, 1, 0, 0, 0, 1, 0]).is_err());
+        assert!(decode_transaction(&[1, 6, 0, 1, 0, 0, 0, 1, 0]).is_err());
+
+        for response in [
+            Response::Ack { tx: TxId::new(5) },
+            Response::nack(TxId::new(6), &PaymentsError::InsufficientFunds(1.into())),
+        ] {
+            for encoding in [Encoding::Binary, Encoding::Json] {
+                assert_eq!(
+                    Response::decode(&response.encode(encoding)).unwrap(),
+                    response
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let producer = std::thread::spawn(move || {
+            let stream = TcpStream::connect(address).unwrap();
+            let mut binary = FramedClient::new(stream.try_clone().unwrap(), Encoding::Binary);
+            let mut json = FramedClient::new(stream, Encoding::Json);
+            let responses = vec![
+                binary
+                    .send(&Transaction::deposit(1, 1, Amount::new(5, 0)))
+                    .unwrap(),
+                json.send(&Transaction::withdrawal(1, 2, Amount::new(9, 0)))
+                    .unwrap(),
+                json.send(&Transaction::dispute(1, 1)).unwrap(),
+            ];
+            // Garbage is NACKed without ending the connection
+            let mut stream = binary.into_inner();
+            write_frame(&mut stream, b"\x07junk").unwrap();
+            let nack = Response::decode(&read_frame(&mut stream, 1024).unwrap().unwrap());
+            (responses, nack.unwrap())
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        let mut engine = PaymentsEngine::new(EngineConfig::standard());
+        let stats = serve(&mut engine, stream).unwrap();
+        assert_eq!(
+            stats,
+            FrameStats {
+                acked: 2,
+                nacked: 2
+            }
+        );
+
+        let (responses, nack) = producer.join().unwrap();
+        assert_eq!(
+            responses,
+            [
+                Response::Ack { tx: TxId::new(1) },
+                Response::Nack {
+                    tx: TxId::new(2),
+                    code: 202,
+                    error: "Insufficient funds in account 1".to_string(),
+                },
+                Response::Ack { tx: TxId::new(1) },
+            ]
+        );
+        assert!(matches!(nack, Response::Nack { code: 103, .. }));
+    }
+
+    #[test]
+    fn test_oversized_frame() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let producer = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream
+                .write_all(&(MAX_FRAME_BYTES + 1).to_be_bytes())
+                .unwrap();
+            Response::decode(&read_frame(&mut stream, 1024).unwrap().unwrap()).unwrap()
+        });
+        let (stream, _) = listener.accept().unwrap();
+        let mut engine = PaymentsEngine::new(EngineConfig::standard());
+        assert!(serve(&mut engine, stream).is_err());
+        assert!(matches!(
+            producer.join().unwrap(),
+            Response::Nack { code: 103, .. }
+        ));
+    }
+}
@@ -1,8 +1,52 @@
+// Data goes to stdout only through `output::OutputRouter`
+#![deny(clippy::print_stdout)]
+
 pub mod account;
+pub mod activity;
+#[cfg(all(feature = "admin", unix))]
+pub mod admin;
+#[cfg(feature = "aml")]
+pub mod aml;
+#[cfg(feature = "benchmark")]
 pub mod benchmark;
+#[cfg(feature = "fs")]
+pub mod checkpoint;
+#[cfg(feature = "arrow")]
+pub mod columnar;
+#[cfg(feature = "fs")]
+pub mod config;
+pub mod disputes;
 pub mod engine;
 pub mod errors;
+#[cfg(feature = "events")]
+pub mod events;
+#[cfg(feature = "fs")]
+pub mod follow;
+#[cfg(feature = "framing")]
+pub mod framing;
+#[cfg(feature = "bench-alloc")]
+pub mod heap;
+pub mod locked;
+#[cfg(feature = "fs")]
+pub mod outbox;
+pub mod output;
+pub mod reconcile;
+#[cfg(feature = "risk")]
+pub mod risk;
+pub mod settlement;
+pub mod shutdown;
+pub mod statement;
+pub mod summary;
+#[cfg(any(all(test, feature = "benchmark"), feature = "testing"))]
+pub mod testing;
+#[cfg(feature = "tls")]
+pub mod tls;
 pub mod transaction;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "websocket")]
+pub mod websocket;
 
+#[cfg(feature = "benchmark")]
 pub use benchmark::PaymentEngineBenchmark;
 pub use engine::{EngineConfig, PaymentsEngine};
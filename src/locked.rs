@@ -0,0 +1,198 @@
+//! Accounts locked by chargebacks during a run, for daily triage.
+//!
+//! `LockRecorder` is an engine observer keeping one entry per account a chargeback
+//! locked: the chargeback that locked it first, everything charged back from it and its
+//! balances, kept up to date with later changes so they match the account output.
+//! Accounts that were already locked when the run started, e.g. restored from a
+//! checkpoint, only appear once another chargeback hits them. Amounts are written in the
+//! recorder's `DecimalFormat`, like the account output.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use crate::account::{Account, ClientId, DecimalFormat, LockState};
+use crate::engine::observer::{AccountDelta, EngineObserver};
+use crate::errors::PaymentsError;
+use crate::transaction::{Amount, Transaction, TxId};
+
+/// One locked account
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LockedAccount {
+    pub client: ClientId,
+    /// The charged back transaction that locked the account
+    pub tx: TxId,
+    /// Amount taken back by every chargeback of the account during the run
+    pub charged_back: Amount,
+    pub chargebacks: u64,
+    /// Balances and lock after the account's last change
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub lock_state: LockState,
+    pub lock_reason: Option<String>,
+}
+
+impl LockedAccount {
+    fn update(&mut self, account: &Account) {
+        self.available = account.available;
+        self.held = account.held;
+        self.total = account.total;
+        self.lock_state = account.lock;
+        self.lock_reason = account.lock_reason.clone();
+    }
+
+    /// This entry with its amounts as `format` writes them
+    fn formatted(&self, format: DecimalFormat) -> Self {
+        Self {
+            charged_back: format.apply(self.charged_back),
+            available: format.apply(self.available),
+            held: format.apply(self.held),
+            total: format.apply(self.total),
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Locks {
+    accounts: BTreeMap<ClientId, LockedAccount>,
+    /// Amounts held by open disputes, for the chargeback that may follow
+    disputed: HashMap<(ClientId, TxId), Amount>,
+}
+
+/// Engine observer that records locked accounts. Clones share the same records, so keep
+/// one to write the report after processing.
+#[derive(Debug, Clone, Default)]
+pub struct LockRecorder {
+    locks: Arc<Mutex<Locks>>,
+    decimal_format: DecimalFormat,
+}
+
+impl LockRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write amounts in `decimal_format`, e.g. the engine's, instead of the default
+    pub fn with_decimal_format(mut self, decimal_format: DecimalFormat) -> Self {
+        self.decimal_format = decimal_format;
+        self
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Locks> {
+        match self.locks.lock() {
+            Ok(locks) => locks,
+            Err(e) => e.into_inner(),
+        }
+    }
+
+    /// Every account locked so far, by client
+    pub fn report(&self) -> Vec<LockedAccount> {
+        self.lock()
+            .accounts
+            .values()
+            .map(|locked| locked.formatted(self.decimal_format))
+            .collect()
+    }
+
+    /// Write the report as CSV with the columns
+    /// `client,tx,charged_back,chargebacks,available,held,total,lock_state,lock_reason`
+    pub fn write_report_csv<W: std::io::Write>(&self, writer: W) -> Result<(), PaymentsError> {
+        let mut wtr = csv::Writer::from_writer(writer);
+        let report = self.report();
+        if report.is_empty() {
+            wtr.write_record([
+                "client",
+                "tx",
+                "charged_back",
+                "chargebacks",
+                "available",
+                "held",
+                "total",
+                "lock_state",
+                "lock_reason",
+            ])?;
+        }
+        for account in &report {
+            wtr.serialize(account)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+impl EngineObserver for LockRecorder {
+    fn on_account_locked(&self, account: &Account, transaction: &Transaction) {
+        let mut locks = self.lock();
+        let amount = locks
+            .disputed
+            .remove(&(transaction.client, transaction.tx))
+            .unwrap_or_default();
+        let locked = locks
+            .accounts
+            .entry(account.client)
+            .or_insert_with(|| LockedAccount {
+                client: account.client,
+                tx: transaction.tx,
+                charged_back: Amount::ZERO,
+                chargebacks: 0,
+                available: account.available,
+                held: account.held,
+                total: account.total,
+                lock_state: account.lock,
+                lock_reason: None,
+            });
+        locked.charged_back += amount;
+        locked.chargebacks += 1;
+        locked.update(account);
+    }
+
+    fn on_dispute_opened(&self, transaction: &Transaction, amount: Amount) {
+        self.lock()
+            .disputed
+            .insert((transaction.client, transaction.tx), amount);
+    }
+
+    fn on_account_changed(&self, delta: &AccountDelta) {
+        if let Some(locked) = self.lock().accounts.get_mut(&delta.account.client) {
+            locked.update(&delta.account);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{EngineConfig, PaymentsEngine};
+
+    #[test]
+    fn test_locked_accounts() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10\n\
+                     deposit,1,2,4\n\
+                     deposit,2,3,5\n\
+                     dispute,1,1,\n\
+                     dispute,1,2,\n\
+                     chargeback,1,1,\n\
+                     chargeback,1,2,\n\
+                     dispute,2,3,\n\
+                     resolve,2,3,\n";
+
+        let recorder = LockRecorder::new();
+        let mut engine = PaymentsEngine::new(EngineConfig::standard());
+        engine.add_observer(recorder.clone());
+        engine
+            .process_transactions_from_reader(input.as_bytes())
+            .unwrap();
+
+        // Client 2's resolved dispute locks nothing
+        let mut output = Vec::new();
+        recorder.write_report_csv(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,tx,charged_back,chargebacks,available,held,total,lock_state,lock_reason\n\
+             1,1,14.0000,2,0.0000,0.0000,0.0000,chargeback_lock,chargeback of transaction 1\n"
+        );
+    }
+}
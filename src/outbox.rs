@@ -0,0 +1,453 @@
+//! Transactional outbox for domain events, committed together with checkpoints.
+//!
+//! Publishing events straight from the engine (see `events::EventSink`) can announce a
+//! transaction that a crash then rolls back to the last checkpoint, or lose events the
+//! engine had already applied. With an `Outbox` as the sink's publisher, events are only
+//! staged in memory. Each checkpoint takes the staged events as an `OutboxBatch` and
+//! saves them in the same file as the engine state, so they become durable atomically
+//! with the state changes that produced them. Afterwards they are appended to the outbox
+//! log, a JSON-lines file numbered by a `seq` field, and an `OutboxRelay` publishes the
+//! log to the broker.
+//!
+//! The relay remembers the last `seq` the broker confirmed, so a relay that fails or
+//! restarts resends at most the events after it. Consumers that deduplicate on `seq`
+//! therefore see every event of an applied transaction exactly once, and never one of a
+//! transaction that was rolled back.
+
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "events")]
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::errors::PaymentsError;
+#[cfg(feature = "events")]
+use crate::shutdown::ShutdownFlag;
+
+/// Events staged since the previous checkpoint, as saved in a `Checkpoint`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct OutboxBatch {
+    /// `seq` of the first event; the one after the last event of the previous batch
+    pub first_seq: u64,
+    /// Log lines, each a JSON object starting with its `seq`
+    pub events: Vec<String>,
+}
+
+impl OutboxBatch {
+    /// `seq` of the last event the log holds once this batch is committed
+    pub fn last_seq(&self) -> u64 {
+        self.first_seq + self.events.len() as u64 - 1
+    }
+}
+
+/// Staging area and log of the outbox. Clones share the same state, so one can be
+/// handed to an `EventSink` and another to `CheckpointOptions`.
+#[derive(Debug, Clone)]
+pub struct Outbox {
+    inner: Arc<Mutex<OutboxState>>,
+}
+
+#[derive(Debug)]
+struct OutboxState {
+    path: PathBuf,
+    /// Serialized events not yet taken into a batch
+    staged: Vec<Vec<u8>>,
+    /// `seq` of the last event in the log
+    committed: u64,
+    /// `seq` the next staged event will get
+    next_seq: u64,
+}
+
+impl Outbox {
+    /// Open the log at `path`, creating it if missing. A line left incomplete by a crash
+    /// during an append is cut off; its batch is still in the checkpoint.
+    pub fn open(path: &Path) -> Result<Self, PaymentsError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut reader = BufReader::new(&file);
+        let (mut lines, mut complete_bytes) = (0, 0);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            if read == 0 || !line.ends_with('\n') {
+                break;
+            }
+            lines += 1;
+            complete_bytes += read as u64;
+        }
+        if complete_bytes < file.metadata()?.len() {
+            log::warn!("Cutting off an incomplete event at the end of {:?}", path);
+            file.set_len(complete_bytes)?;
+        }
+        Ok(Self {
+            inner: Arc::new(Mutex::new(OutboxState {
+                path: path.to_path_buf(),
+                staged: Vec::new(),
+                committed: lines,
+                next_seq: lines + 1,
+            })),
+        })
+    }
+
+    fn state(&self) -> Result<std::sync::MutexGuard<'_, OutboxState>, PaymentsError> {
+        self.inner
+            .lock()
+            .map_err(|_| PaymentsError::LockPoisoned("outbox"))
+    }
+
+    /// Stage one serialized event, a JSON object, until the next checkpoint.
+    pub fn stage(&self, payload: &[u8]) -> Result<(), PaymentsError> {
+        if payload.first() != Some(&b'{') {
+            return Err(PaymentsError::PublishFailed(
+                "outbox events must be JSON objects".to_string(),
+            ));
+        }
+        self.state()?.staged.push(payload.to_vec());
+        Ok(())
+    }
+
+    /// Number the staged events and hand them over for a checkpoint.
+    pub fn take_batch(&self) -> Result<OutboxBatch, PaymentsError> {
+        let mut state = self.state()?;
+        let first_seq = state.next_seq;
+        let events: Vec<String> = std::mem::take(&mut state.staged)
+            .into_iter()
+            .zip(first_seq..)
+            .map(|(payload, seq)| {
+                let rest = String::from_utf8_lossy(&payload[1..]);
+                match rest.as_ref() {
+                    "}" => format!("{{\"seq\":{}}}", seq),
+                    _ => format!("{{\"seq\":{},{}", seq, rest),
+                }
+            })
+            .collect();
+        state.next_seq += events.len() as u64;
+        Ok(OutboxBatch { first_seq, events })
+    }
+
+    /// Append the events of `batch` the log does not have yet and sync the log to disk.
+    /// Call once the checkpoint holding `batch` is saved.
+    pub fn commit(&self, batch: &OutboxBatch) -> Result<(), PaymentsError> {
+        let mut state = self.state()?;
+        if batch.first_seq > state.committed + 1 {
+            return Err(PaymentsError::ConfigError(format!(
+                "outbox {:?} ends at event {}, but the checkpoint continues from {}",
+                state.path, state.committed, batch.first_seq
+            )));
+        }
+        let missing = (state.committed + 1 - batch.first_seq) as usize;
+        let Some(events) = batch
+            .events
+            .get(missing..)
+            .filter(|events| !events.is_empty())
+        else {
+            return Ok(());
+        };
+        let mut file = OpenOptions::new().append(true).open(&state.path)?;
+        let mut lines = events.join("\n");
+        lines.push('\n');
+        file.write_all(lines.as_bytes())?;
+        file.sync_data()?;
+        state.committed += events.len() as u64;
+        Ok(())
+    }
+
+    /// Bring the log in line with a checkpoint being resumed: append the part of its
+    /// batch a crash kept out of the log and drop anything staged since. Fails if the
+    /// log already holds events past the checkpoint, e.g. when resuming from an older
+    /// copy of it, since replaying would number them again.
+    pub fn recover(&self, batch: &OutboxBatch) -> Result<(), PaymentsError> {
+        self.commit(batch)?;
+        let mut state = self.state()?;
+        if state.committed > batch.last_seq() {
+            return Err(PaymentsError::ConfigError(format!(
+                "outbox {:?} holds events up to {}, past the checkpoint's {}",
+                state.path,
+                state.committed,
+                batch.last_seq()
+            )));
+        }
+        state.staged.clear();
+        state.next_seq = state.committed + 1;
+        Ok(())
+    }
+
+    /// `seq` of the last event in the log
+    pub fn committed(&self) -> Result<u64, PaymentsError> {
+        Ok(self.state()?.committed)
+    }
+
+    /// Number of events staged for the next checkpoint
+    pub fn staged(&self) -> Result<usize, PaymentsError> {
+        Ok(self.state()?.staged.len())
+    }
+}
+
+#[cfg(feature = "events")]
+impl crate::events::EventPublisher for Outbox {
+    fn publish(&mut self, payload: &[u8]) -> std::io::Result<()> {
+        self.stage(payload).map_err(std::io::Error::other)
+    }
+
+    /// Staged events are confirmed by the next checkpoint, not here
+    fn confirm(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Publishes the committed events of an outbox log, resuming after the last `seq` the
+/// broker confirmed. That position is kept in a `.cursor` file next to the log.
+#[cfg(feature = "events")]
+#[derive(Debug)]
+pub struct OutboxRelay {
+    log: PathBuf,
+    cursor: PathBuf,
+    publisher: Box<dyn crate::events::EventPublisher>,
+}
+
+#[cfg(feature = "events")]
+impl OutboxRelay {
+    pub fn new(log: &Path, publisher: impl crate::events::EventPublisher + 'static) -> Self {
+        let mut cursor = log.as_os_str().to_owned();
+        cursor.push(".cursor");
+        Self {
+            log: log.to_path_buf(),
+            cursor: cursor.into(),
+            publisher: Box::new(publisher),
+        }
+    }
+
+    /// `seq` of the last event the broker confirmed
+    pub fn position(&self) -> Result<u64, PaymentsError> {
+        match std::fs::read_to_string(&self.cursor) {
+            Ok(text) => text.trim().parse().map_err(|e| {
+                PaymentsError::ConfigError(format!("invalid cursor {:?}: {}", self.cursor, e))
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Publish every complete log line after the cursor, wait for the broker to confirm
+    /// them and advance the cursor. Returns how many events were published. On failure
+    /// the publisher reconnects and the cursor stays, so the next call resends them.
+    pub fn relay(&mut self) -> Result<u64, PaymentsError> {
+        let position = self.position()?;
+        let mut reader = BufReader::new(File::open(&self.log)?);
+        let (mut seq, mut published) = (0, 0);
+        let mut line = String::new();
+        let result = loop {
+            line.clear();
+            match reader.read_line(&mut line) {
+                Ok(read) if read == 0 || !line.ends_with('\n') => {
+                    break if published > 0 {
+                        self.publisher.confirm()
+                    } else {
+                        Ok(())
+                    };
+                }
+                Ok(_) => {}
+                Err(e) => break Err(e),
+            }
+            seq += 1;
+            if seq <= position {
+                continue;
+            }
+            if let Err(e) = self.publisher.publish(line.trim_end().as_bytes()) {
+                break Err(e);
+            }
+            published += 1;
+        };
+        if let Err(e) = result {
+            if let Err(e) = self.publisher.reconnect() {
+                log::warn!("Event publisher reconnect failed: {}", e);
+            }
+            return Err(PaymentsError::PublishFailed(format!(
+                "{} outbox events after {} undelivered: {}",
+                seq.saturating_sub(position),
+                position,
+                e
+            )));
+        }
+        if published > 0 {
+            let mut tmp_path = self.cursor.as_os_str().to_owned();
+            tmp_path.push(".tmp");
+            std::fs::write(&tmp_path, format!("{}\n", position + published))?;
+            std::fs::rename(&tmp_path, &self.cursor)?;
+        }
+        Ok(published)
+    }
+
+    /// Relay new events every `interval` until `stop` is requested, then once more so
+    /// everything committed by then is delivered. Failures before that are logged and
+    /// retried; a failure of the last pass is returned, and its events are relayed by
+    /// the next relay on the same log.
+    pub fn follow(
+        &mut self,
+        interval: std::time::Duration,
+        stop: &ShutdownFlag,
+    ) -> Result<(), PaymentsError> {
+        loop {
+            let stopping = stop.is_requested();
+            match self.relay() {
+                Ok(published) if published > 0 => {
+                    log::debug!("Relayed {} outbox events", published)
+                }
+                Ok(_) => {}
+                Err(e) if stopping => return Err(e),
+                Err(e) => log::warn!("Outbox relay failed, will retry: {}", e),
+            }
+            if stopping {
+                return Ok(());
+            }
+            std::thread::sleep(interval);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "events"))]
+mod tests {
+    use super::*;
+    use crate::checkpoint::{Checkpoint, CheckpointOptions, process_file_with_checkpoints};
+    use crate::engine::{EngineConfig, ErrorPolicy, PaymentsEngine};
+    use crate::events::{EventPublisher, EventSink};
+    use crate::shutdown::ShutdownFlag;
+
+    /// Publisher recording confirmed payloads; fails its first `failures` confirmations
+    #[derive(Debug, Default)]
+    struct RecordingPublisher {
+        failures: usize,
+        buffered: Vec<String>,
+        received: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl EventPublisher for RecordingPublisher {
+        fn publish(&mut self, payload: &[u8]) -> std::io::Result<()> {
+            self.buffered
+                .push(String::from_utf8(payload.to_vec()).unwrap());
+            Ok(())
+        }
+
+        fn confirm(&mut self) -> std::io::Result<()> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                self.buffered.clear();
+                return Err(std::io::ErrorKind::ConnectionReset.into());
+            }
+            self.received.lock().unwrap().append(&mut self.buffered);
+            Ok(())
+        }
+    }
+
+    fn run(
+        input: &Path,
+        checkpoint: &Path,
+        outbox: &Outbox,
+        resume: Option<Checkpoint>,
+    ) -> PaymentsEngine {
+        let mut engine = PaymentsEngine::new(EngineConfig::standard());
+        engine.add_observer(EventSink::new(outbox.clone()));
+        process_file_with_checkpoints(
+            &mut engine,
+            input,
+            &CheckpointOptions {
+                path: checkpoint.to_path_buf(),
+                interval: 3,
+                outbox: Some(outbox.clone()),
+            },
+            ErrorPolicy::Skip,
+            resume,
+            &ShutdownFlag::default(),
+        )
+        .unwrap();
+        engine
+    }
+
+    #[test]
+    fn test_outbox_survives_crash_between_checkpoint_and_log() {
+        let dir = std::env::temp_dir();
+        let id = std::process::id();
+        let input = dir.join(format!("outbox-input-{}.csv", id));
+        let prefix = dir.join(format!("outbox-prefix-{}.csv", id));
+        let checkpoint = dir.join(format!("outbox-checkpoint-{}.json", id));
+        let log = dir.join(format!("outbox-{}.jsonl", id));
+        let _ = std::fs::remove_file(&log);
+
+        let rows = [
+            "type,client,tx,amount",
+            "deposit,1,1,10.0",
+            "withdrawal,1,2,50.0",
+            "deposit,2,3,1.0",
+            "dispute,1,1,",
+            "chargeback,1,1,",
+            "deposit,2,4,2.0",
+        ];
+        std::fs::write(&input, rows.join("\n") + "\n").unwrap();
+        std::fs::write(&prefix, rows[..5].join("\n") + "\n").unwrap();
+
+        run(&prefix, &checkpoint, &Outbox::open(&log).unwrap(), None);
+        let saved = Checkpoint::load(&checkpoint).unwrap();
+        let batch = saved.outbox.clone().unwrap();
+        assert_eq!(batch.last_seq(), 3);
+
+        // The process died after saving the final checkpoint, midway through appending
+        // its batch to the log
+        let contents = std::fs::read_to_string(&log).unwrap();
+        let cut = contents[..contents.len() - 1].rfind('\n').unwrap() + 5;
+        std::fs::write(&log, &contents[..cut]).unwrap();
+
+        let outbox = Outbox::open(&log).unwrap();
+        assert_eq!(outbox.committed().unwrap(), 2);
+        run(&input, &checkpoint, &outbox, Some(saved));
+        assert_eq!(outbox.committed().unwrap(), 6);
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let mut relay = OutboxRelay::new(
+            &log,
+            RecordingPublisher {
+                failures: 1,
+                received: received.clone(),
+                ..RecordingPublisher::default()
+            },
+        );
+        assert!(relay.relay().is_err());
+        assert_eq!(relay.position().unwrap(), 0);
+        assert_eq!(relay.relay().unwrap(), 6);
+        assert_eq!(relay.relay().unwrap(), 0);
+
+        let events: Vec<serde_json::Value> = received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let seqs: Vec<u64> = events.iter().map(|e| e["seq"].as_u64().unwrap()).collect();
+        assert_eq!(seqs, [1, 2, 3, 4, 5, 6]);
+        // One event per accepted transaction plus the lock; none for the withdrawal
+        let txs: Vec<u64> = events.iter().map(|e| e["tx"].as_u64().unwrap()).collect();
+        assert_eq!(txs, [1, 3, 1, 1, 1, 4]);
+        assert_eq!(events[4]["event"], "account_locked");
+
+        // Resuming from the older checkpoint would number events again
+        assert!(
+            Outbox::open(&log)
+                .unwrap()
+                .recover(&OutboxBatch {
+                    first_seq: 1,
+                    events: batch.events[..1].to_vec(),
+                })
+                .is_err()
+        );
+
+        for path in [input, prefix, checkpoint, log.clone()] {
+            std::fs::remove_file(path).unwrap();
+        }
+        std::fs::remove_file(relay.cursor).unwrap();
+    }
+}
@@ -0,0 +1,126 @@
+//! Routing of a run's output so stdout only ever carries data.
+//!
+//! Code in this crate never prints to stdout directly (`clippy::print_stdout` is denied);
+//! it writes through the process-wide `OutputRouter` instead:
+//!
+//! - `data()` is stdout, for the account output a caller may pipe into another tool
+//! - `diagnostics()` is stderr, or the log file if one is set, for summaries and errors
+//! - `console()` is for results meant for a person, such as benchmark summaries: stdout
+//!   normally, but the diagnostics stream in machine mode
+//!
+//! A binary installs its router once at startup; until then `router()` returns the
+//! default, which is not in machine mode and has no log file.
+
+use std::fs::File;
+use std::io::Write;
+use std::sync::{Arc, Mutex, OnceLock};
+
+static ROUTER: OnceLock<OutputRouter> = OnceLock::new();
+
+/// Where data, diagnostics and console output go
+#[derive(Debug, Clone, Default)]
+pub struct OutputRouter {
+    machine: bool,
+    log_file: Option<SharedFile>,
+}
+
+impl OutputRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep stdout for data only: console output goes to the diagnostics stream too
+    pub fn machine(self) -> Self {
+        Self {
+            machine: true,
+            ..self
+        }
+    }
+
+    /// Send diagnostics, and so console output in machine mode, to `file` instead of
+    /// stderr
+    pub fn with_log_file(self, file: File) -> Self {
+        Self {
+            log_file: Some(SharedFile(Arc::new(Mutex::new(file)))),
+            ..self
+        }
+    }
+
+    pub fn is_machine(&self) -> bool {
+        self.machine
+    }
+
+    pub fn has_log_file(&self) -> bool {
+        self.log_file.is_some()
+    }
+
+    pub fn data(&self) -> Box<dyn Write + Send> {
+        Box::new(std::io::stdout())
+    }
+
+    pub fn diagnostics(&self) -> Box<dyn Write + Send> {
+        match &self.log_file {
+            Some(file) => Box::new(file.clone()),
+            None => Box::new(std::io::stderr()),
+        }
+    }
+
+    pub fn console(&self) -> Box<dyn Write + Send> {
+        if self.machine {
+            self.diagnostics()
+        } else {
+            self.data()
+        }
+    }
+}
+
+/// Make `router` the process-wide router. Fails, returning it, if one was installed or
+/// used already.
+pub fn install(router: OutputRouter) -> Result<(), OutputRouter> {
+    ROUTER.set(router)
+}
+
+/// The installed router, or the default one
+pub fn router() -> &'static OutputRouter {
+    ROUTER.get_or_init(OutputRouter::default)
+}
+
+/// A log file shared between the logger and the router's diagnostics writers
+#[derive(Debug, Clone)]
+struct SharedFile(Arc<Mutex<File>>);
+
+impl Write for SharedFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .lock()
+            .map_err(|_| std::io::Error::other("log file lock poisoned"))?
+            .write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0
+            .lock()
+            .map_err(|_| std::io::Error::other("log file lock poisoned"))?
+            .flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_machine_console_goes_to_log_file() {
+        let path = std::env::temp_dir().join(format!("output-{}.log", std::process::id()));
+        let router = OutputRouter::new()
+            .machine()
+            .with_log_file(File::create(&path).unwrap());
+        writeln!(router.console(), "summary").unwrap();
+        writeln!(router.diagnostics(), "warning").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "summary\nwarning\n"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
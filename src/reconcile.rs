@@ -0,0 +1,190 @@
+//! Comparison of two account snapshots, e.g. the outputs of two engine versions run
+//! side by side on the same input.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+
+use crate::account::{Account, ClientId};
+use crate::errors::PaymentsError;
+use crate::transaction::Amount;
+
+/// How a client's account differs between snapshot `a` and snapshot `b`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DifferenceKind {
+    OnlyInA,
+    OnlyInB,
+    Changed,
+}
+
+/// A client whose account is missing from one snapshot or differs between them
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountDifference {
+    pub client: ClientId,
+    pub a: Option<Account>,
+    pub b: Option<Account>,
+}
+
+impl AccountDifference {
+    pub fn kind(&self) -> DifferenceKind {
+        match (&self.a, &self.b) {
+            (Some(_), None) => DifferenceKind::OnlyInA,
+            (None, Some(_)) => DifferenceKind::OnlyInB,
+            _ => DifferenceKind::Changed,
+        }
+    }
+
+    /// `b - a` for one balance, counting a missing account as zero
+    fn delta(&self, balance: impl Fn(&Account) -> Amount) -> Amount {
+        let a = self.a.as_ref().map_or(Amount::ZERO, &balance);
+        let b = self.b.as_ref().map_or(Amount::ZERO, &balance);
+        b - a
+    }
+}
+
+/// One row of the machine-readable diff
+#[derive(Debug, Serialize)]
+struct DifferenceRow {
+    client: ClientId,
+    difference: DifferenceKind,
+    available_delta: Amount,
+    held_delta: Amount,
+    total_delta: Amount,
+    locked_a: Option<bool>,
+    locked_b: Option<bool>,
+    closed_a: Option<bool>,
+    closed_b: Option<bool>,
+}
+
+/// Read an accounts CSV in the engine's output format, rejecting repeated clients
+pub fn read_accounts<R: Read>(reader: R) -> Result<BTreeMap<ClientId, Account>, PaymentsError> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+
+    let mut accounts = BTreeMap::new();
+    for (idx, line) in rdr.deserialize().enumerate() {
+        let line_number = idx as u64 + 1;
+        let account: Account = line.map_err(|e| PaymentsError::from(e).at_line(line_number))?;
+        if accounts.contains_key(&account.client) {
+            return Err(PaymentsError::InvalidAccount(format!(
+                "client {} appears more than once",
+                account.client
+            ))
+            .at_line(line_number));
+        }
+        accounts.insert(account.client, account);
+    }
+    Ok(accounts)
+}
+
+/// Clients missing from either snapshot or whose balances, lock or closed status differ,
+/// by client ID. Balances are compared by value, so `1.0` matches `1.00`.
+pub fn diff_accounts(
+    a: &BTreeMap<ClientId, Account>,
+    b: &BTreeMap<ClientId, Account>,
+) -> Vec<AccountDifference> {
+    let clients: BTreeSet<_> = a.keys().chain(b.keys()).collect();
+    clients
+        .into_iter()
+        .filter_map(|client| {
+            let (a, b) = (a.get(client), b.get(client));
+            let same = matches!((a, b), (Some(a), Some(b)) if a == b);
+            (!same).then(|| AccountDifference {
+                client: *client,
+                a: a.cloned(),
+                b: b.cloned(),
+            })
+        })
+        .collect()
+}
+
+/// Read two accounts CSVs and diff them
+pub fn reconcile<A: Read, B: Read>(a: A, b: B) -> Result<Vec<AccountDifference>, PaymentsError> {
+    Ok(diff_accounts(&read_accounts(a)?, &read_accounts(b)?))
+}
+
+/// Write differences as CSV with the columns
+/// `client,difference,available_delta,held_delta,total_delta,locked_a,locked_b,closed_a,closed_b`.
+/// Deltas are `b - a`; the status columns are empty for the snapshot missing the client.
+pub fn write_differences_csv<W: std::io::Write>(
+    differences: &[AccountDifference],
+    writer: W,
+) -> Result<(), PaymentsError> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    if differences.is_empty() {
+        // Still emit the header so consumers can tell an empty diff from a failed run
+        wtr.write_record([
+            "client",
+            "difference",
+            "available_delta",
+            "held_delta",
+            "total_delta",
+            "locked_a",
+            "locked_b",
+            "closed_a",
+            "closed_b",
+        ])?;
+    }
+    for difference in differences {
+        wtr.serialize(DifferenceRow {
+            client: difference.client,
+            difference: difference.kind(),
+            available_delta: difference.delta(|account| account.available),
+            held_delta: difference.delta(|account| account.held),
+            total_delta: difference.delta(|account| account.total),
+            locked_a: difference.a.as_ref().map(|account| account.is_locked()),
+            locked_b: difference.b.as_ref().map(|account| account.is_locked()),
+            closed_a: difference.a.as_ref().map(|account| account.closed),
+            closed_b: difference.b.as_ref().map(|account| account.closed),
+        })?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reconcile() {
+        let a = "client,available,held,total,locked\n\
+                 1,10.0,0,10.0,false\n2,5,1,6,false\n3,1,0,1,false\n";
+        let b = "client,available,held,total,locked,closed\n\
+                 1,10.00,0,10.00,false,false\n2,5,0,5,true,false\n4,2,0,2,false,false\n";
+
+        let differences = reconcile(a.as_bytes(), b.as_bytes()).unwrap();
+        let kinds: Vec<_> = differences
+            .iter()
+            .map(|difference| (difference.client.get(), difference.kind()))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (2, DifferenceKind::Changed),
+                (3, DifferenceKind::OnlyInA),
+                (4, DifferenceKind::OnlyInB),
+            ]
+        );
+
+        let mut output = Vec::new();
+        write_differences_csv(&differences, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,difference,available_delta,held_delta,total_delta,locked_a,locked_b,closed_a,closed_b\n\
+             2,changed,0,-1,-1,false,true,false,false\n\
+             3,only_in_a,-1,0,-1,false,,false,\n\
+             4,only_in_b,2,0,2,,false,,false\n"
+        );
+    }
+
+    #[test]
+    fn test_duplicate_client_rejected() {
+        let a = "client,available,held,total,locked\n1,1,0,1,false\n1,2,0,2,false\n";
+        let error = read_accounts(a.as_bytes()).unwrap_err();
+        assert!(matches!(error.inner(), PaymentsError::InvalidAccount(_)));
+        assert_eq!(error.line(), Some(2));
+    }
+}
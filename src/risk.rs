@@ -0,0 +1,281 @@
+//! First-line fraud heuristics computed from the transactions an engine accepts.
+//!
+//! `RiskMonitor` is an engine observer, so it sees every accepted row in processing
+//! order without changing how transactions are applied. Signals are tracked per client
+//! and turned into flags with the thresholds in `RiskConfig`.
+
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::account::ClientId;
+use crate::engine::observer::EngineObserver;
+use crate::errors::PaymentsError;
+use crate::transaction::{Amount, Transaction, TransactionType};
+
+/// Thresholds for raising risk flags.
+///
+/// ```toml
+/// [risk]
+/// cycle_ratio = "0.9"
+/// max_rapid_cycles = 3
+/// structuring_threshold = "10000"
+/// structuring_margin = "0.1"
+/// max_structuring_deposits = 3
+/// max_dispute_ratio = "0.2"
+/// min_transactions_for_ratio = 5
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RiskConfig {
+    /// A withdrawal right after a deposit of at least this fraction of it is a cycle
+    pub cycle_ratio: Decimal,
+    /// Flag clients with more deposit/withdraw cycles than this
+    pub max_rapid_cycles: u32,
+    /// Reporting threshold that structured deposits stay just under
+    pub structuring_threshold: Amount,
+    /// Deposits within this fraction below the threshold count as structuring
+    pub structuring_margin: Decimal,
+    /// Flag clients with more near-threshold deposits than this
+    pub max_structuring_deposits: u32,
+    /// Flag clients whose disputes exceed this fraction of their deposits and withdrawals
+    pub max_dispute_ratio: Decimal,
+    /// Deposits and withdrawals needed before the dispute ratio is judged
+    pub min_transactions_for_ratio: u32,
+}
+
+impl Default for RiskConfig {
+    fn default() -> Self {
+        Self {
+            cycle_ratio: Decimal::new(9, 1),
+            max_rapid_cycles: 3,
+            structuring_threshold: Amount::new(10_000, 0),
+            structuring_margin: Decimal::new(1, 1),
+            max_structuring_deposits: 3,
+            max_dispute_ratio: Decimal::new(2, 1),
+            min_transactions_for_ratio: 5,
+        }
+    }
+}
+
+/// A heuristic a client tripped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RiskFlag {
+    /// Funds repeatedly withdrawn straight after being deposited
+    RapidCycling,
+    /// Repeated deposits just under the reporting threshold
+    Structuring,
+    /// Unusually many of the client's transactions disputed
+    HighDisputeRatio,
+}
+
+impl RiskFlag {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::RapidCycling => "rapid_cycling",
+            Self::Structuring => "structuring",
+            Self::HighDisputeRatio => "high_dispute_ratio",
+        }
+    }
+}
+
+/// Signals gathered for one client
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClientSignals {
+    /// Accepted deposits and withdrawals
+    pub transactions: u32,
+    /// Accepted disputes
+    pub disputes: u32,
+    /// Withdrawals of most of the deposit that immediately preceded them
+    pub rapid_cycles: u32,
+    /// Deposits just under the structuring threshold
+    pub structuring_deposits: u32,
+    /// Amount of the client's previous accepted transaction, if it was a deposit
+    last_deposit: Option<Amount>,
+}
+
+impl ClientSignals {
+    pub fn dispute_ratio(&self) -> Decimal {
+        if self.transactions == 0 {
+            return Decimal::ZERO;
+        }
+        Decimal::from(self.disputes) / Decimal::from(self.transactions)
+    }
+
+    /// Flags raised by these signals under `config`
+    pub fn flags(&self, config: &RiskConfig) -> Vec<RiskFlag> {
+        let mut flags = Vec::new();
+        if self.rapid_cycles > config.max_rapid_cycles {
+            flags.push(RiskFlag::RapidCycling);
+        }
+        if self.structuring_deposits > config.max_structuring_deposits {
+            flags.push(RiskFlag::Structuring);
+        }
+        if self.transactions >= config.min_transactions_for_ratio
+            && self.dispute_ratio() > config.max_dispute_ratio
+        {
+            flags.push(RiskFlag::HighDisputeRatio);
+        }
+        flags
+    }
+}
+
+/// Engine observer that tracks `ClientSignals` for every client. Clones share the
+/// same signals, so keep one to read the report after processing.
+#[derive(Debug, Clone, Default)]
+pub struct RiskMonitor {
+    config: RiskConfig,
+    clients: Arc<Mutex<HashMap<ClientId, ClientSignals>>>,
+}
+
+impl RiskMonitor {
+    pub fn new(config: RiskConfig) -> Self {
+        Self {
+            config,
+            clients: Arc::default(),
+        }
+    }
+
+    /// Signals and flags for every client seen so far, by client ID
+    pub fn report(&self) -> Vec<(ClientId, ClientSignals, Vec<RiskFlag>)> {
+        let clients = match self.clients.lock() {
+            Ok(clients) => clients,
+            Err(e) => e.into_inner(),
+        };
+        let mut report: Vec<_> = clients
+            .iter()
+            .map(|(client, signals)| (*client, signals.clone(), signals.flags(&self.config)))
+            .collect();
+        report.sort_unstable_by_key(|(client, _, _)| *client);
+        report
+    }
+
+    /// Write the report as CSV with a `;`-separated `flags` column
+    pub fn write_report_csv<W: std::io::Write>(&self, writer: W) -> Result<(), PaymentsError> {
+        let mut wtr = csv::Writer::from_writer(writer);
+        wtr.write_record([
+            "client",
+            "transactions",
+            "disputes",
+            "dispute_ratio",
+            "rapid_cycles",
+            "structuring_deposits",
+            "flags",
+        ])?;
+        for (client, signals, flags) in self.report() {
+            let flags: Vec<_> = flags.iter().map(RiskFlag::as_str).collect();
+            wtr.write_record([
+                client.to_string(),
+                signals.transactions.to_string(),
+                signals.disputes.to_string(),
+                signals.dispute_ratio().round_dp(4).normalize().to_string(),
+                signals.rapid_cycles.to_string(),
+                signals.structuring_deposits.to_string(),
+                flags.join(";"),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    fn is_structuring(&self, amount: Amount) -> bool {
+        let threshold = self.config.structuring_threshold;
+        let floor = threshold - threshold * self.config.structuring_margin;
+        amount >= floor && amount < threshold
+    }
+}
+
+impl EngineObserver for RiskMonitor {
+    fn on_accepted(&self, transaction: &Transaction) {
+        let Ok(mut clients) = self.clients.lock() else {
+            return;
+        };
+        let signals = clients.entry(transaction.client).or_default();
+        let amount = transaction.amount.unwrap_or_default();
+        match transaction.tx_type {
+            TransactionType::Deposit => {
+                signals.transactions += 1;
+                if self.is_structuring(amount) {
+                    signals.structuring_deposits += 1;
+                }
+                signals.last_deposit = Some(amount);
+                return;
+            }
+            TransactionType::Withdrawal => {
+                signals.transactions += 1;
+                if signals
+                    .last_deposit
+                    .is_some_and(|deposit| amount >= deposit * self.config.cycle_ratio)
+                {
+                    signals.rapid_cycles += 1;
+                }
+            }
+            TransactionType::Dispute => signals.disputes += 1,
+            TransactionType::Resolve
+            | TransactionType::Chargeback
+            | TransactionType::CloseAccount => {}
+        }
+        signals.last_deposit = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{EngineConfig, PaymentsEngine};
+
+    #[test]
+    fn test_risk_flags() {
+        let mut input = String::from("type,client,tx,amount\n");
+        let mut tx = 0;
+        let mut row = |input: &mut String, tx_type: &str, client: u16, amount: &str| {
+            tx += 1;
+            input.push_str(&format!("{},{},{},{}\n", tx_type, client, tx, amount));
+        };
+        // Client 1 cycles funds four times
+        for _ in 0..4 {
+            row(&mut input, "deposit", 1, "100");
+            row(&mut input, "withdrawal", 1, "95");
+        }
+        // Client 2 makes four deposits just under 10,000
+        for _ in 0..4 {
+            row(&mut input, "deposit", 2, "9500");
+        }
+        // Client 3 disputes two of five deposits
+        for _ in 0..5 {
+            row(&mut input, "deposit", 3, "10");
+        }
+        input.push_str("dispute,3,13,\ndispute,3,14,\n");
+
+        let monitor = RiskMonitor::new(RiskConfig::default());
+        let mut engine = PaymentsEngine::new(EngineConfig::standard());
+        engine.add_observer(monitor.clone());
+        engine
+            .process_transactions_from_reader(input.as_bytes())
+            .unwrap();
+
+        let flags: Vec<_> = monitor
+            .report()
+            .into_iter()
+            .map(|(client, _, flags)| (client.get(), flags))
+            .collect();
+        assert_eq!(
+            flags,
+            vec![
+                (1, vec![RiskFlag::RapidCycling]),
+                (2, vec![RiskFlag::Structuring]),
+                (3, vec![RiskFlag::HighDisputeRatio]),
+            ]
+        );
+
+        let mut output = Vec::new();
+        monitor.write_report_csv(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains("\n3,5,2,0.4,0,0,high_dispute_ratio\n"),
+            "{}",
+            output
+        );
+    }
+}
@@ -0,0 +1,224 @@
+//! End-of-day settlement: the money each client moved during a run.
+//!
+//! `SettlementCollector` is an engine observer adding up accepted deposits, withdrawals
+//! and chargebacks per client. A chargeback counts with the amount its dispute put on
+//! hold, since the row itself carries none. The net position is deposits minus
+//! withdrawals and chargebacks, so it only covers this run and not balances restored
+//! from a checkpoint. Rejected transactions settle nothing and are not counted.
+//! Amounts are written in the collector's `DecimalFormat`, like the account output.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use crate::account::{ClientId, DecimalFormat};
+use crate::engine::observer::EngineObserver;
+use crate::errors::PaymentsError;
+use crate::transaction::{Amount, Transaction, TransactionType, TxId};
+
+/// Settlement of one client, or of every client when `client` is `None`
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SettlementLine {
+    pub client: Option<ClientId>,
+    pub gross_deposits: Amount,
+    pub gross_withdrawals: Amount,
+    pub chargeback_total: Amount,
+    /// `gross_deposits - gross_withdrawals - chargeback_total`
+    pub net: Amount,
+    /// Accepted transactions by type
+    pub deposits: u64,
+    pub withdrawals: u64,
+    pub disputes: u64,
+    pub resolves: u64,
+    pub chargebacks: u64,
+    pub closes: u64,
+}
+
+impl SettlementLine {
+    /// This line with its amounts as `format` writes them
+    fn formatted(&self, format: DecimalFormat) -> Self {
+        Self {
+            gross_deposits: format.apply(self.gross_deposits),
+            gross_withdrawals: format.apply(self.gross_withdrawals),
+            chargeback_total: format.apply(self.chargeback_total),
+            net: format.apply(self.net),
+            ..self.clone()
+        }
+    }
+
+    fn add(&mut self, other: &SettlementLine) {
+        self.gross_deposits += other.gross_deposits;
+        self.gross_withdrawals += other.gross_withdrawals;
+        self.chargeback_total += other.chargeback_total;
+        self.net += other.net;
+        self.deposits += other.deposits;
+        self.withdrawals += other.withdrawals;
+        self.disputes += other.disputes;
+        self.resolves += other.resolves;
+        self.chargebacks += other.chargebacks;
+        self.closes += other.closes;
+    }
+}
+
+/// Per-client settlement lines, by client, and their sum
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SettlementReport {
+    pub clients: Vec<SettlementLine>,
+    pub total: SettlementLine,
+}
+
+#[derive(Debug, Default)]
+struct Ledger {
+    clients: BTreeMap<ClientId, SettlementLine>,
+    /// Amounts held by open disputes, for the chargeback that may follow
+    disputed: HashMap<(ClientId, TxId), Amount>,
+}
+
+/// Engine observer that accumulates settlement figures per client. Clones share the
+/// same figures, so keep one to write the report after processing.
+#[derive(Debug, Clone, Default)]
+pub struct SettlementCollector {
+    ledger: Arc<Mutex<Ledger>>,
+    decimal_format: DecimalFormat,
+}
+
+impl SettlementCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write amounts in `decimal_format`, e.g. the engine's, instead of the default
+    pub fn with_decimal_format(mut self, decimal_format: DecimalFormat) -> Self {
+        self.decimal_format = decimal_format;
+        self
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Ledger> {
+        match self.ledger.lock() {
+            Ok(ledger) => ledger,
+            Err(e) => e.into_inner(),
+        }
+    }
+
+    /// Settlement of every client with an accepted transaction so far
+    pub fn report(&self) -> SettlementReport {
+        let ledger = self.lock();
+        let mut report = SettlementReport::default();
+        for (client, line) in &ledger.clients {
+            let line = SettlementLine {
+                client: Some(*client),
+                net: line.gross_deposits - line.gross_withdrawals - line.chargeback_total,
+                ..line.clone()
+            };
+            report.total.add(&line);
+            report.clients.push(line.formatted(self.decimal_format));
+        }
+        report.total = report.total.formatted(self.decimal_format);
+        report
+    }
+
+    /// Write the report as CSV, one row per client followed by the total with an empty
+    /// `client`
+    pub fn write_report_csv<W: std::io::Write>(&self, writer: W) -> Result<(), PaymentsError> {
+        let report = self.report();
+        let mut wtr = csv::Writer::from_writer(writer);
+        for line in report.clients.iter().chain([&report.total]) {
+            wtr.serialize(line)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    /// Write the report as a JSON object with the `clients` lines and the `total`
+    #[cfg(feature = "fs")]
+    pub fn write_report_json<W: std::io::Write>(&self, mut writer: W) -> Result<(), PaymentsError> {
+        serde_json::to_writer_pretty(&mut writer, &self.report())
+            .map_err(|e| PaymentsError::IoError(e.into()))?;
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+impl EngineObserver for SettlementCollector {
+    fn on_accepted(&self, transaction: &Transaction) {
+        let mut ledger = self.lock();
+        let key = (transaction.client, transaction.tx);
+        let held = match transaction.tx_type {
+            TransactionType::Chargeback | TransactionType::Resolve => ledger.disputed.remove(&key),
+            _ => None,
+        };
+        let line = ledger.clients.entry(transaction.client).or_default();
+        let amount = transaction.amount.unwrap_or_default();
+        match transaction.tx_type {
+            TransactionType::Deposit => {
+                line.gross_deposits += amount;
+                line.deposits += 1;
+            }
+            TransactionType::Withdrawal => {
+                line.gross_withdrawals += amount;
+                line.withdrawals += 1;
+            }
+            TransactionType::Dispute => line.disputes += 1,
+            TransactionType::Resolve => line.resolves += 1,
+            TransactionType::Chargeback => {
+                line.chargeback_total += held.unwrap_or_default();
+                line.chargebacks += 1;
+            }
+            TransactionType::CloseAccount => line.closes += 1,
+        }
+    }
+
+    fn on_dispute_opened(&self, transaction: &Transaction, amount: Amount) {
+        self.lock()
+            .disputed
+            .insert((transaction.client, transaction.tx), amount);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{EngineConfig, PaymentsEngine};
+
+    #[test]
+    fn test_settlement_report() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10.5\n\
+                     withdrawal,1,2,2.5\n\
+                     deposit,2,3,5\n\
+                     withdrawal,2,4,7\n\
+                     deposit,2,5,3\n\
+                     dispute,2,3,\n\
+                     chargeback,2,3,\n\
+                     deposit,1,6,1\n\
+                     dispute,1,6,\n\
+                     resolve,1,6,\n";
+
+        let collector = SettlementCollector::new();
+        let mut engine = PaymentsEngine::new(EngineConfig::standard());
+        engine.add_observer(collector.clone());
+        engine
+            .process_transactions_from_reader(input.as_bytes())
+            .unwrap();
+
+        let report = collector.report();
+        assert_eq!(report.clients.len(), 2);
+        assert_eq!(report.clients[0].net, Amount::new(9, 0));
+        // The rejected withdrawal settles nothing; the chargeback takes back the deposit
+        assert_eq!(report.clients[1].gross_withdrawals, Amount::ZERO);
+        assert_eq!(report.clients[1].chargeback_total, Amount::new(5, 0));
+        assert_eq!(report.clients[1].net, Amount::new(3, 0));
+        assert_eq!(report.total.net, Amount::new(12, 0));
+        assert_eq!((report.total.deposits, report.total.chargebacks), (4, 1));
+
+        let mut csv = Vec::new();
+        collector.write_report_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "client,gross_deposits,gross_withdrawals,chargeback_total,net,deposits,withdrawals,disputes,resolves,chargebacks,closes\n\
+             1,11.5000,2.5000,0.0000,9.0000,2,1,1,1,0,0\n\
+             2,8.0000,0.0000,5.0000,3.0000,2,0,1,0,1,0\n\
+             ,19.5000,2.5000,5.0000,12.0000,4,1,2,1,1,0\n"
+        );
+    }
+}
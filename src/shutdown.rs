@@ -0,0 +1,41 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Cooperative shutdown flag shared between a signal handler and the engines.
+/// Reader-based processing checks it between records: once requested, no new records
+/// are read, work already handed to workers is drained, and the call returns normally
+/// so the caller can still write its output or checkpoint.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownFlag(Arc<AtomicBool>);
+
+impl ShutdownFlag {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask everything holding a clone of this flag to stop at the next record boundary
+    pub fn request(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Request shutdown on SIGINT/SIGTERM (Ctrl-C on Windows).
+    /// A second signal while shutting down exits the process immediately.
+    #[cfg(feature = "signals")]
+    pub fn install_signal_handler(&self) -> Result<(), ctrlc::Error> {
+        let flag = self.clone();
+        ctrlc::set_handler(move || {
+            if flag.is_requested() {
+                eprintln!("Second interrupt received, exiting immediately");
+                std::process::exit(130);
+            }
+            eprintln!(
+                "Interrupt received, finishing in-flight transactions (press again to force)"
+            );
+            flag.request();
+        })
+    }
+}
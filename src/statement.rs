@@ -0,0 +1,260 @@
+//! Per-client statements: every accepted transaction with the balances it left behind.
+//!
+//! `HistoryRecorder` is an engine observer that pairs each accepted transaction with the
+//! account change it caused, so the balances come from the engine rather than being
+//! recomputed. Rejected rows leave no change and are not part of a statement. Amounts
+//! are written in the recorder's `DecimalFormat`, like the account output.
+
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use crate::account::{ClientId, DecimalFormat};
+use crate::engine::observer::{AccountDelta, EngineObserver};
+use crate::errors::PaymentsError;
+use crate::transaction::{Amount, Transaction, TransactionType, TxId};
+
+/// One line of a statement
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StatementEntry {
+    pub tx: TxId,
+    #[serde(rename = "type")]
+    pub tx_type: TransactionType,
+    pub timestamp: Option<u64>,
+    /// The row's amount; empty for disputes, resolves, chargebacks and closes
+    pub amount: Option<Amount>,
+    /// Balances after the transaction
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+}
+
+impl StatementEntry {
+    /// This entry with its amounts as `format` writes them
+    fn formatted(&self, format: DecimalFormat) -> Self {
+        Self {
+            amount: self.amount.map(|amount| format.apply(amount)),
+            available: format.apply(self.available),
+            held: format.apply(self.held),
+            total: format.apply(self.total),
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct History {
+    /// Accepted transaction waiting for its account change, per client
+    pending: HashMap<ClientId, Transaction>,
+    entries: HashMap<ClientId, Vec<StatementEntry>>,
+}
+
+/// Engine observer that keeps the transaction history of every client, or of selected
+/// clients only. Clones share the same history, so keep one to write statements after
+/// processing.
+#[derive(Debug, Clone, Default)]
+pub struct HistoryRecorder {
+    /// Clients to record; empty records everyone
+    clients: HashSet<ClientId>,
+    history: Arc<Mutex<History>>,
+    decimal_format: DecimalFormat,
+}
+
+impl HistoryRecorder {
+    /// Record every client's history
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record only these clients' histories
+    pub fn for_clients(clients: impl IntoIterator<Item = ClientId>) -> Self {
+        Self {
+            clients: clients.into_iter().collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Write amounts in `decimal_format`, e.g. the engine's, instead of the default
+    pub fn with_decimal_format(mut self, decimal_format: DecimalFormat) -> Self {
+        self.decimal_format = decimal_format;
+        self
+    }
+
+    fn records(&self, client: ClientId) -> bool {
+        self.clients.is_empty() || self.clients.contains(&client)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, History> {
+        match self.history.lock() {
+            Ok(history) => history,
+            Err(e) => e.into_inner(),
+        }
+    }
+
+    /// The client's accepted transactions in processing order
+    pub fn statement(&self, client: ClientId) -> Vec<StatementEntry> {
+        self.lock()
+            .entries
+            .get(&client)
+            .map(|entries| {
+                entries
+                    .iter()
+                    .map(|entry| entry.formatted(self.decimal_format))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Write the client's statement as CSV with the columns
+    /// `tx,type,timestamp,amount,available,held,total,locked`
+    pub fn write_statement_csv<W: std::io::Write>(
+        &self,
+        client: ClientId,
+        writer: W,
+    ) -> Result<(), PaymentsError> {
+        let mut wtr = csv::Writer::from_writer(writer);
+        let statement = self.statement(client);
+        if statement.is_empty() {
+            wtr.write_record([
+                "tx",
+                "type",
+                "timestamp",
+                "amount",
+                "available",
+                "held",
+                "total",
+                "locked",
+            ])?;
+        }
+        for entry in &statement {
+            wtr.serialize(entry)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    /// Write the client's statement as a JSON object with the client ID and its entries
+    #[cfg(feature = "fs")]
+    pub fn write_statement_json<W: std::io::Write>(
+        &self,
+        client: ClientId,
+        mut writer: W,
+    ) -> Result<(), PaymentsError> {
+        #[derive(Serialize)]
+        struct Statement {
+            client: ClientId,
+            transactions: Vec<StatementEntry>,
+        }
+
+        let statement = Statement {
+            client,
+            transactions: self.statement(client),
+        };
+        serde_json::to_writer_pretty(&mut writer, &statement)
+            .map_err(|e| PaymentsError::IoError(e.into()))?;
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+impl EngineObserver for HistoryRecorder {
+    fn on_accepted(&self, transaction: &Transaction) {
+        if !self.records(transaction.client) {
+            return;
+        }
+        let mut history = self.lock();
+        // A close's payout withdrawal shares its ID; the statement shows the close
+        if history
+            .pending
+            .get(&transaction.client)
+            .is_none_or(|pending| pending.tx != transaction.tx)
+        {
+            history
+                .pending
+                .insert(transaction.client, transaction.clone());
+        }
+    }
+
+    fn on_account_changed(&self, delta: &AccountDelta) {
+        let account = &delta.account;
+        if !self.records(account.client) {
+            return;
+        }
+        let mut history = self.lock();
+        let Some(transaction) = history.pending.remove(&account.client) else {
+            return;
+        };
+        history
+            .entries
+            .entry(account.client)
+            .or_default()
+            .push(StatementEntry {
+                tx: transaction.tx,
+                tx_type: transaction.tx_type,
+                timestamp: transaction.timestamp,
+                amount: transaction.amount,
+                available: account.available,
+                held: account.held,
+                total: account.total,
+                locked: account.is_locked(),
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{EngineConfig, EnginePolicy, PaymentsEngine};
+
+    #[test]
+    fn test_statement() {
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,10,100\n\
+                     deposit,2,2,5,110\n\
+                     withdrawal,1,3,4,120\n\
+                     withdrawal,1,4,50,130\n\
+                     deposit,1,5,2,140\n\
+                     dispute,1,5,,150\n\
+                     resolve,1,5,,160\n\
+                     close,1,6,,170\n";
+
+        let recorder = HistoryRecorder::for_clients([ClientId::new(1)]);
+        let mut engine = PaymentsEngine::new(EngineConfig::standard());
+        engine.set_policy(EnginePolicy {
+            payout_on_close: true,
+            ..EnginePolicy::default()
+        });
+        engine.add_observer(recorder.clone());
+        engine
+            .process_transactions_from_reader(input.as_bytes())
+            .unwrap();
+
+        assert!(recorder.statement(ClientId::new(2)).is_empty());
+        let mut output = Vec::new();
+        recorder
+            .write_statement_csv(ClientId::new(1), &mut output)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "tx,type,timestamp,amount,available,held,total,locked\n\
+             1,deposit,100,10.0000,10.0000,0.0000,10.0000,false\n\
+             3,withdrawal,120,4.0000,6.0000,0.0000,6.0000,false\n\
+             5,deposit,140,2.0000,8.0000,0.0000,8.0000,false\n\
+             5,dispute,150,,6.0000,2.0000,8.0000,false\n\
+             5,resolve,160,,8.0000,0.0000,8.0000,false\n\
+             6,close,170,,0.0000,0.0000,0.0000,false\n"
+        );
+
+        #[cfg(feature = "fs")]
+        {
+            let mut output = Vec::new();
+            recorder
+                .write_statement_json(ClientId::new(1), &mut output)
+                .unwrap();
+            let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+            assert_eq!(json["client"], 1);
+            assert_eq!(json["transactions"][1]["available"], "6.0000");
+        }
+    }
+}
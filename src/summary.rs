@@ -0,0 +1,182 @@
+//! Run-level totals for a quick sanity check of an engine's output.
+//!
+//! `SummaryCollector` is an engine observer counting accepted and rejected transactions
+//! by type; `Summary::new` adds account aggregates taken from the engine's final state.
+//! Balances are printed in the summary's `DecimalFormat`, the engine's when summarizing
+//! an engine.
+//! Rows that fail to parse never reach the engine, so they are not counted.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::account::{Account, DecimalFormat};
+use crate::engine::PaymentsEngine;
+use crate::engine::observer::EngineObserver;
+use crate::errors::PaymentsError;
+use crate::transaction::{Amount, Transaction, TransactionType};
+
+/// Accepted and rejected transactions of one type
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransactionCounts {
+    pub accepted: u64,
+    pub rejected: u64,
+}
+
+/// Counts for every transaction type, in `TransactionType::ALL` order
+pub type CountsByType = [TransactionCounts; TransactionType::ALL.len()];
+
+/// Engine observer that counts transactions by type and outcome. Clones share the same
+/// counts, so keep one to build the summary after processing.
+#[derive(Debug, Clone, Default)]
+pub struct SummaryCollector {
+    counts: Arc<Mutex<CountsByType>>,
+}
+
+impl SummaryCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn counts(&self) -> CountsByType {
+        match self.counts.lock() {
+            Ok(counts) => *counts,
+            Err(e) => *e.into_inner(),
+        }
+    }
+
+    /// Summary of `engine`'s accounts and the transactions seen so far
+    pub fn summarize(&self, engine: &PaymentsEngine) -> Result<Summary, PaymentsError> {
+        let mut summary = Summary::new([], self.counts());
+        summary.decimal_format = engine.decimal_format();
+        for account in engine.accounts_iter() {
+            summary.add_account(&account?);
+        }
+        Ok(summary)
+    }
+}
+
+impl EngineObserver for SummaryCollector {
+    fn on_accepted(&self, transaction: &Transaction) {
+        if let Ok(mut counts) = self.counts.lock() {
+            counts[transaction.tx_type.index()].accepted += 1;
+        }
+    }
+
+    fn on_rejected(&self, transaction: &Transaction, _error: &PaymentsError) {
+        if let Ok(mut counts) = self.counts.lock() {
+            counts[transaction.tx_type.index()].rejected += 1;
+        }
+    }
+}
+
+/// Global aggregates over every account plus transaction counts by type
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Summary {
+    pub accounts: usize,
+    pub locked_accounts: usize,
+    pub closed_accounts: usize,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub transactions: CountsByType,
+    /// How the balances are printed
+    pub decimal_format: DecimalFormat,
+}
+
+impl Summary {
+    pub fn new<'a>(
+        accounts: impl IntoIterator<Item = &'a Account>,
+        transactions: CountsByType,
+    ) -> Self {
+        let mut summary = Self {
+            transactions,
+            ..Self::default()
+        };
+        for account in accounts {
+            summary.add_account(account);
+        }
+        summary
+    }
+
+    pub fn add_account(&mut self, account: &Account) {
+        self.accounts += 1;
+        self.locked_accounts += usize::from(account.is_locked());
+        self.closed_accounts += usize::from(account.closed);
+        self.available += account.available;
+        self.held += account.held;
+        self.total += account.total;
+    }
+
+    pub fn accepted(&self) -> u64 {
+        self.transactions.iter().map(|counts| counts.accepted).sum()
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.transactions.iter().map(|counts| counts.rejected).sum()
+    }
+}
+
+/// A single line of `key=value` pairs; per-type counts are `accepted/rejected`
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "accounts={} locked={} closed={} available={} held={} total={} accepted={} rejected={}",
+            self.accounts,
+            self.locked_accounts,
+            self.closed_accounts,
+            self.decimal_format.apply(self.available),
+            self.decimal_format.apply(self.held),
+            self.decimal_format.apply(self.total),
+            self.accepted(),
+            self.rejected()
+        )?;
+        for (tx_type, counts) in TransactionType::ALL.iter().zip(&self.transactions) {
+            write!(
+                f,
+                " {}={}/{}",
+                tx_type.as_str(),
+                counts.accepted,
+                counts.rejected
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::EngineConfig;
+
+    #[test]
+    fn test_summary() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10.5\n\
+                     deposit,2,2,5\n\
+                     withdrawal,2,3,7\n\
+                     deposit,3,4,2\n\
+                     dispute,3,4,\n\
+                     chargeback,3,4,\n\
+                     deposit,1,1,3\n";
+
+        let collector = SummaryCollector::new();
+        let mut engine = PaymentsEngine::new(EngineConfig::standard());
+        engine.add_observer(collector.clone());
+        engine
+            .process_transactions_from_reader(input.as_bytes())
+            .unwrap();
+
+        let summary = collector.summarize(&engine).unwrap();
+        assert_eq!(summary.accounts, 3);
+        assert_eq!(summary.locked_accounts, 1);
+        assert_eq!(summary.total, Amount::new(155, 1));
+        assert_eq!((summary.accepted(), summary.rejected()), (5, 2));
+        assert_eq!(
+            summary.to_string(),
+            "accounts=3 locked=1 closed=0 available=15.5000 held=0.0000 total=15.5000 accepted=5 \
+             rejected=2 \
+             deposit=3/1 withdrawal=0/1 dispute=1/0 resolve=0/0 chargeback=1/0 close=0/0"
+        );
+    }
+}
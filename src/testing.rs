@@ -0,0 +1,260 @@
+//! Property-based testing support, enabled with the `testing` feature.
+//!
+//! Provides proptest strategies for transactions, coherent transaction sequences and
+//! account states, plus invariant checks that can be run against any engine output.
+
+use proptest::arbitrary::Arbitrary;
+use proptest::collection::vec;
+use proptest::prelude::*;
+use proptest::strategy::BoxedStrategy;
+
+use crate::PaymentEngineBenchmark;
+use crate::account::{Account, ClientId, LockState};
+use crate::engine::invariants::InvariantViolation;
+use crate::engine::{EngineConfig, PaymentsEngine};
+use crate::transaction::{Amount, Transaction, TransactionType, TxId};
+
+/// Positive amounts with up to four decimal places, between 0.0001 and 1,000,000.
+pub fn amount_strategy() -> impl Strategy<Value = Amount> {
+    (1i64..=10_000_000_000).prop_map(|units| Amount::new(units, 4))
+}
+
+/// Any of the five transaction types, with equal weight.
+pub fn transaction_type_strategy() -> impl Strategy<Value = TransactionType> {
+    prop_oneof![
+        Just(TransactionType::Deposit),
+        Just(TransactionType::Withdrawal),
+        Just(TransactionType::Dispute),
+        Just(TransactionType::Resolve),
+        Just(TransactionType::Chargeback),
+    ]
+}
+
+/// A single well-formed transaction: deposits and withdrawals carry a positive amount,
+/// disputes, resolves and chargebacks carry none. IDs are not coordinated with anything else.
+pub fn transaction_strategy() -> impl Strategy<Value = Transaction> {
+    (
+        transaction_type_strategy(),
+        any::<ClientId>(),
+        any::<TxId>(),
+        amount_strategy(),
+    )
+        .prop_map(|(tx_type, client, tx, amount)| {
+            let amount = match tx_type {
+                TransactionType::Deposit | TransactionType::Withdrawal => Some(amount),
+                _ => None,
+            };
+            Transaction::new(tx_type, client, tx, amount)
+        })
+}
+
+/// A sequence of up to `max_len` transactions for at most `max_clients` clients.
+/// Deposits and withdrawals get fresh, increasing transaction IDs; disputes, resolves and
+/// chargebacks always reference an earlier deposit or withdrawal of the same client, so
+/// sequences exercise the dispute lifecycle rather than being rejected up front.
+pub fn transaction_sequence_strategy(
+    max_len: usize,
+    max_clients: u16,
+) -> impl Strategy<Value = Vec<Transaction>> {
+    let max_clients = max_clients.max(1);
+    let step = (
+        transaction_type_strategy(),
+        (1..=max_clients).prop_map(ClientId::new),
+        amount_strategy(),
+        any::<prop::sample::Index>(),
+    );
+
+    vec(step, 0..=max_len).prop_map(|steps| {
+        let mut issued: Vec<(ClientId, TxId)> = Vec::new();
+        let mut transactions = Vec::with_capacity(steps.len());
+
+        for (tx_type, client, amount, index) in steps {
+            match tx_type {
+                TransactionType::Deposit | TransactionType::Withdrawal => {
+                    let tx = TxId::new(issued.len() as u32 + 1);
+                    issued.push((client, tx));
+                    transactions.push(Transaction::new(tx_type, client, tx, Some(amount)));
+                }
+                _ if issued.is_empty() => {}
+                _ => {
+                    let (client, tx) = *index.get(&issued);
+                    transactions.push(Transaction::new(tx_type, client, tx, None));
+                }
+            }
+        }
+
+        transactions
+    })
+}
+
+/// An account whose balances satisfy `check_account_invariants`.
+pub fn account_strategy() -> impl Strategy<Value = Account> {
+    (
+        any::<ClientId>(),
+        0i64..=1_000_000_000,
+        0i64..=1_000_000_000,
+        proptest::sample::select(LockState::ALL.to_vec()),
+    )
+        .prop_map(|(client, available, held, lock)| {
+            let available = Amount::new(available, 4);
+            let held = Amount::new(held, 4);
+            Account {
+                client,
+                available,
+                held,
+                total: available + held,
+                lock,
+                lock_reason: lock.is_locked().then(|| format!("{} by test", lock)),
+                closed: false,
+            }
+        })
+}
+
+impl Arbitrary for ClientId {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        any::<u16>().prop_map(ClientId::new).boxed()
+    }
+}
+
+impl Arbitrary for TxId {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        any::<u32>().prop_map(TxId::new).boxed()
+    }
+}
+
+impl Arbitrary for TransactionType {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        transaction_type_strategy().boxed()
+    }
+}
+
+impl Arbitrary for Transaction {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        transaction_strategy().boxed()
+    }
+}
+
+impl Arbitrary for Account {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        account_strategy().boxed()
+    }
+}
+
+/// Checks the balance invariants every account must hold after any sequence of transactions:
+/// `total == available + held` and `held` is never negative.
+pub fn check_account_invariants(account: &Account) -> Result<(), String> {
+    match InvariantViolation::check(account, true).first() {
+        Some(violation) => Err(violation.to_string()),
+        None => Ok(()),
+    }
+}
+
+/// Reads back the accounts an engine exports, sorted by client ID.
+pub fn final_accounts(engine: &PaymentsEngine) -> Vec<Account> {
+    let mut output = Vec::new();
+    engine
+        .write_accounts_csv(&mut output)
+        .expect("failed to export accounts");
+    let mut accounts: Vec<Account> = csv::Reader::from_reader(output.as_slice())
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .expect("failed to read back exported accounts");
+    accounts.sort_by_key(|account| account.client);
+    accounts
+}
+
+/// Engine configurations that must all produce the same final state for any sequence of
+/// at most `capacity` transactions. Bounded limits are sized so nothing is ever evicted.
+/// Add new engines here so the differential tests cover them automatically.
+pub fn equivalent_engine_configs(capacity: usize) -> Vec<EngineConfig> {
+    let capacity = capacity.max(1);
+    vec![
+        EngineConfig::standard(),
+        EngineConfig::bounded(capacity, capacity, capacity),
+        EngineConfig::concurrent(capacity, capacity, capacity),
+        // Budget high enough that the engine never leaves its standard storage
+        EngineConfig::adaptive(1024 * 1024),
+    ]
+}
+
+/// Runs `transactions` through every engine in `equivalent_engine_configs` via the CSV
+/// reader path and returns each engine's type alongside its sorted final accounts.
+pub fn run_all_engines(transactions: &[Transaction]) -> Vec<(String, Vec<Account>)> {
+    let csv_data = PaymentEngineBenchmark::transactions_to_csv(transactions);
+    equivalent_engine_configs(transactions.len())
+        .into_iter()
+        .map(|config| {
+            let mut engine = PaymentsEngine::new(config);
+            engine
+                .process_transactions_from_reader(csv_data.as_bytes())
+                .expect("failed to process transactions");
+            (
+                engine.get_engine_info().engine_type,
+                final_accounts(&engine),
+            )
+        })
+        .collect()
+}
+
+/// Panics with the first diverging engine if any engine disagrees with the standard engine.
+/// Transaction IDs must be unique across clients: engines that partition work by client may
+/// legitimately disagree about which of two clients reusing an ID was seen first.
+pub fn assert_engines_agree(transactions: &[Transaction]) {
+    let mut results = run_all_engines(transactions).into_iter();
+    let (reference_type, reference) = results.next().expect("no engines configured");
+
+    for (engine_type, accounts) in results {
+        assert_eq!(
+            reference, accounts,
+            "{} engine diverged from {} engine",
+            engine_type, reference_type
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accounts_after(transactions: &[Transaction]) -> Vec<Account> {
+        let mut engine = PaymentsEngine::new(EngineConfig::standard());
+        for tx in transactions {
+            let _ = engine.process_transaction(tx);
+        }
+        final_accounts(&engine)
+    }
+
+    proptest! {
+        #[test]
+        fn generated_accounts_satisfy_invariants(account in any::<Account>()) {
+            prop_assert!(check_account_invariants(&account).is_ok());
+        }
+
+        #[test]
+        fn engine_preserves_invariants(transactions in transaction_sequence_strategy(200, 10)) {
+            for account in accounts_after(&transactions) {
+                prop_assert!(check_account_invariants(&account).is_ok(), "{:?}", account);
+            }
+        }
+
+        #[test]
+        fn engines_agree(transactions in transaction_sequence_strategy(200, 10)) {
+            assert_engines_agree(&transactions);
+        }
+    }
+}
@@ -1,14 +1,132 @@
-use crate::account::ClientId;
+use crate::account::{ClientId, TenantId};
+use derive_more::{Add, AddAssign, Display, From, FromStr, Into, Neg, Sub, SubAssign, Sum};
 use rust_decimal::Decimal;
-use serde::Deserialize;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::ops::Mul;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
 
-pub type Amount = Decimal;
+/// A sum of money. Reads and writes as a decimal string, e.g. `"1.5"`.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    Display,
+    FromStr,
+    From,
+    Into,
+    Add,
+    Sub,
+    Neg,
+    AddAssign,
+    SubAssign,
+    Sum,
+    Deserialize,
+    Serialize,
+)]
+#[serde(transparent)]
+pub struct Amount(Decimal);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(Decimal::ZERO);
+    pub const ONE: Amount = Amount(Decimal::ONE);
+
+    /// `num` scaled down by `scale` decimal places, as in `Decimal::new`.
+    pub fn new(num: i64, scale: u32) -> Self {
+        Self(Decimal::new(num, scale))
+    }
+
+    pub const fn value(self) -> Decimal {
+        self.0
+    }
+
+    /// The same amount without trailing zeros.
+    pub fn normalize(self) -> Self {
+        Self(self.0.normalize())
+    }
+
+    /// The amount rounded to `dp` decimal places, half away from zero.
+    pub fn round_dp(self, dp: u32) -> Self {
+        Self(self.0.round_dp(dp))
+    }
+
+    /// The amount rounded to `dp` decimal places, half away from zero, and written with
+    /// exactly that many, e.g. `10` as `10.0000` for four places.
+    pub fn rescale(self, dp: u32) -> Self {
+        let mut value = self
+            .0
+            .round_dp_with_strategy(dp, rust_decimal::RoundingStrategy::MidpointAwayFromZero);
+        value.rescale(dp);
+        Self(value)
+    }
+
+    pub fn scale(self) -> u32 {
+        self.0.scale()
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0.is_zero()
+    }
+
+    pub fn is_sign_negative(self) -> bool {
+        self.0.is_sign_negative()
+    }
+}
+
+/// Scaling by a rate, e.g. a fee percentage.
+impl Mul<Decimal> for Amount {
+    type Output = Amount;
+
+    fn mul(self, rate: Decimal) -> Amount {
+        Amount(self.0 * rate)
+    }
+}
+
+/// Unique identifier for a transaction. Reads and writes as a plain integer.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    Display,
+    FromStr,
+    From,
+    Into,
+    Deserialize,
+    Serialize,
+)]
+#[serde(transparent)]
+pub struct TxId(u32);
+
+impl TxId {
+    pub const MAX: TxId = TxId(u32::MAX);
+
+    pub const fn new(id: u32) -> Self {
+        Self(id)
+    }
+
+    pub const fn get(self) -> u32 {
+        self.0
+    }
+}
 
-pub type TxId = u32;
 /// Transaction types supported by the payment engine.
-/// The `serde` attribute ensures that the enum variants are deserialized
-/// from lowercase strings in the input data.
-#[derive(Debug, Clone, Deserialize)]
+/// Serialized as the lowercase names returned by `as_str`. Deserializing accepts any
+/// spelling `from_name` does.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     /// A deposit transaction.
@@ -25,26 +143,444 @@ pub enum TransactionType {
 
     /// A chargeback transaction.
     Chargeback,
+
+    /// Closes the client's account once no funds are held.
+    #[serde(rename = "close")]
+    CloseAccount,
+}
+
+impl TransactionType {
+    /// Every transaction type, in declaration order.
+    pub const ALL: [TransactionType; 6] = [
+        Self::Deposit,
+        Self::Withdrawal,
+        Self::Dispute,
+        Self::Resolve,
+        Self::Chargeback,
+        Self::CloseAccount,
+    ];
+
+    /// Position of this type in `ALL`.
+    pub(crate) fn index(&self) -> usize {
+        match self {
+            Self::Deposit => 0,
+            Self::Withdrawal => 1,
+            Self::Dispute => 2,
+            Self::Resolve => 3,
+            Self::Chargeback => 4,
+            Self::CloseAccount => 5,
+        }
+    }
+
+    /// The name used in the `type` column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Deposit => "deposit",
+            Self::Withdrawal => "withdrawal",
+            Self::Dispute => "dispute",
+            Self::Resolve => "resolve",
+            Self::Chargeback => "chargeback",
+            Self::CloseAccount => "close",
+        }
+    }
+
+    /// The type called `name`, ignoring case and any `_`, `-` or spaces, so `"DEPOSIT"`
+    /// and `"charge_back"` are accepted. `"close_account"` is accepted for `close`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        if let Some(tx_type) = Self::ALL.into_iter().find(|t| t.as_str() == name) {
+            return Some(tx_type);
+        }
+        Self::ALL
+            .into_iter()
+            .find(|t| loosely_equal(name, t.as_str()))
+            .or_else(|| loosely_equal(name, "closeaccount").then_some(Self::CloseAccount))
+    }
+}
+
+/// Whether `name` spells `canonical` (lowercase letters only) up to case and separators
+fn loosely_equal(name: &str, canonical: &str) -> bool {
+    let mut letters = name.bytes().filter(|b| !matches!(b, b'_' | b'-' | b' '));
+    canonical
+        .bytes()
+        .all(|c| letters.next().is_some_and(|b| b.eq_ignore_ascii_case(&c)))
+        && letters.next().is_none()
+}
+
+impl<'de> Deserialize<'de> for TransactionType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct NameVisitor;
+
+        impl Visitor<'_> for NameVisitor {
+            type Value = TransactionType;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a transaction type")
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<TransactionType, E> {
+                TransactionType::from_name(name).ok_or_else(|| {
+                    E::custom(format!(
+                        "unknown transaction type '{}' (expected deposit, withdrawal, \
+                         dispute, resolve, chargeback or close)",
+                        name
+                    ))
+                })
+            }
+        }
+
+        deserializer.deserialize_str(NameVisitor)
+    }
+}
+
+/// How strictly reader-based processing matches the `type` column.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TypeMatching {
+    /// Accept any spelling `TransactionType::from_name` does.
+    #[default]
+    Lenient,
+    /// Reject a row whose type is not spelled exactly as `TransactionType::as_str`.
+    Strict,
+}
+
+impl std::str::FromStr for TypeMatching {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "lenient" => Ok(Self::Lenient),
+            "strict" => Ok(Self::Strict),
+            other => Err(format!(
+                "unknown type matching '{}' (expected lenient or strict)",
+                other
+            )),
+        }
+    }
+}
+
+/// How the `amount` column is written. The grouped and European formats also accept a
+/// leading `+`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AmountFormat {
+    /// A plain decimal with `.` as the decimal point, e.g. `1234.5`.
+    #[default]
+    Plain,
+    /// `.` as the decimal point with `,`, `_` or spaces between groups of digits,
+    /// e.g. `1,234.5`.
+    Grouped,
+    /// `,` as the decimal point with `.` or spaces between groups of digits, as in many
+    /// European exports, e.g. `1.234,5`.
+    European,
+}
+
+impl AmountFormat {
+    /// `amount` rewritten as a plain decimal, or `None` if it is not written in this
+    /// format. Whether the digits form a valid amount is left to the parser.
+    pub fn normalize(self, amount: &str) -> Option<String> {
+        let amount = amount.trim();
+        let amount = amount.strip_prefix('+').unwrap_or(amount);
+        let (point, separators): (char, &[char]) = match self {
+            Self::Plain => return Some(amount.to_string()),
+            Self::Grouped => ('.', &[',', '_', ' ', '\u{a0}']),
+            Self::European => (',', &['.', ' ', '\u{a0}']),
+        };
+        let (whole, fraction) = match amount.split_once(point) {
+            Some((whole, fraction)) => (whole, Some(fraction)),
+            None => (amount, None),
+        };
+        if fraction
+            .is_some_and(|fraction| fraction.contains(point) || fraction.contains(separators))
+        {
+            return None;
+        }
+        let mut plain: String = whole.chars().filter(|c| !separators.contains(c)).collect();
+        if let Some(fraction) = fraction {
+            plain.push('.');
+            plain.push_str(fraction);
+        }
+        Some(plain)
+    }
+}
+
+impl std::str::FromStr for AmountFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "plain" => Ok(Self::Plain),
+            "grouped" => Ok(Self::Grouped),
+            "european" => Ok(Self::European),
+            other => Err(format!(
+                "unknown amount format '{}' (expected plain, grouped or european)",
+                other
+            )),
+        }
+    }
+}
+
+/// The CSV dialect reader-based processing expects and how it parses the `type` and
+/// `amount` columns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputFormat {
+    pub type_matching: TypeMatching,
+    pub amount_format: AmountFormat,
+    /// Field separator, e.g. `b'\t'` or `b';'`.
+    pub delimiter: u8,
+    /// Whether the first row names the columns. Without one the columns are read in the
+    /// order `type, client, tx, amount`, followed by the optional columns.
+    pub has_headers: bool,
+    /// Header names to read as another column, e.g. `transaction_type` as `type`.
+    pub columns: BTreeMap<String, String>,
+    /// Whether transaction IDs are unique across inputs or only within one.
+    pub tx_ids: TxIdScope,
+}
+
+impl Default for InputFormat {
+    fn default() -> Self {
+        Self {
+            type_matching: TypeMatching::default(),
+            amount_format: AmountFormat::default(),
+            delimiter: b',',
+            has_headers: true,
+            columns: BTreeMap::new(),
+            tx_ids: TxIdScope::default(),
+        }
+    }
+}
+
+/// Which rows of different inputs refer to the same transaction ID.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TxIdScope {
+    /// IDs are unique across every input; reusing one is a duplicate.
+    #[default]
+    Global,
+    /// IDs are only unique within one input, e.g. daily files numbered from 1 again.
+    /// Each call of a reader-based API (or each stream) is its own input: its IDs are
+    /// replaced by fresh ones from `TxIdNamespaces`, so they never match another input's,
+    /// while disputes still find the transactions of the same input. Observers, errors
+    /// and logs see the replacement IDs. Checkpointed runs ignore this.
+    PerSource(TxIdNamespaces),
+}
+
+/// Hands out the engine-wide IDs that `TxIdScope::PerSource` replaces input IDs with,
+/// counting up from 1. Clones share the same counter, so keep one clone per engine.
+#[derive(Debug, Clone)]
+pub struct TxIdNamespaces {
+    next: Arc<AtomicU32>,
+}
+
+impl TxIdNamespaces {
+    pub fn new() -> Self {
+        Self::starting_at(TxId::new(1))
+    }
+
+    /// Hand out IDs from `first` on, e.g. above the IDs of transactions applied before
+    /// switching to per-source IDs
+    pub fn starting_at(first: TxId) -> Self {
+        Self {
+            next: Arc::new(AtomicU32::new(first.get())),
+        }
+    }
+
+    /// A new engine-wide ID, or `None` once every ID was handed out.
+    pub fn assign(&self) -> Option<TxId> {
+        self.next
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next| {
+                next.checked_add(1)
+            })
+            .ok()
+            .map(TxId::new)
+    }
+}
+
+impl Default for TxIdNamespaces {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PartialEq for TxIdNamespaces {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.next, &other.next)
+    }
+}
+
+impl Eq for TxIdNamespaces {}
+
+/// The delimiter written as `delimiter`: a single ASCII character, or `tab` or `\t` for a
+/// tab.
+pub fn parse_delimiter(delimiter: &str) -> Result<u8, String> {
+    match delimiter {
+        "tab" | "\\t" => Ok(b'\t'),
+        _ => match delimiter.as_bytes() {
+            [byte] if byte.is_ascii() => Ok(*byte),
+            _ => Err(format!(
+                "invalid delimiter '{}' (expected a single ASCII character or tab)",
+                delimiter
+            )),
+        },
+    }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Transaction {
     /// The type of transaction.
     #[serde(rename = "type")]
     pub tx_type: TransactionType,
 
     /// The client associated with the transaction.
-    pub client: u16,
+    pub client: ClientId,
 
     /// The unique identifier for the transaction.
     pub tx: TxId,
 
     /// The amount involved in the transaction (if applicable).
     pub amount: Option<Amount>,
+
+    /// When the transaction happened, in seconds since the Unix epoch, from the optional
+    /// `timestamp` column.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+
+    /// Reason code of a dispute, resolve or chargeback, from the optional `reason_code`
+    /// column.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason_code: Option<String>,
+
+    /// Case reference of a dispute, resolve or chargeback, from the optional `case_ref`
+    /// column.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub case_ref: Option<String>,
+
+    /// Tenant the transaction belongs to, from the optional `tenant` column. Only
+    /// `TenantEngines` keeps tenants apart; a single engine ignores it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<TenantId>,
+
+    /// Key an upstream reuses when it resubmits the transaction, from the optional
+    /// `idempotency_key` column. Keys are scoped to the client; see
+    /// `engine::idempotency`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+}
+
+impl Transaction {
+    /// A transaction of any type with no timestamp, reason code or case reference.
+    pub fn new(
+        tx_type: TransactionType,
+        client: impl Into<ClientId>,
+        tx: impl Into<TxId>,
+        amount: Option<Amount>,
+    ) -> Transaction {
+        Transaction {
+            tx_type,
+            client: client.into(),
+            tx: tx.into(),
+            amount,
+            timestamp: None,
+            reason_code: None,
+            case_ref: None,
+            tenant: None,
+            idempotency_key: None,
+        }
+    }
+
+    /// A deposit of `amount` into the client's account.
+    pub fn deposit(
+        client: impl Into<ClientId>,
+        tx: impl Into<TxId>,
+        amount: impl Into<Amount>,
+    ) -> Transaction {
+        Self::new(TransactionType::Deposit, client, tx, Some(amount.into()))
+    }
+
+    /// A withdrawal of `amount` from the client's account.
+    pub fn withdrawal(
+        client: impl Into<ClientId>,
+        tx: impl Into<TxId>,
+        amount: impl Into<Amount>,
+    ) -> Transaction {
+        Self::new(TransactionType::Withdrawal, client, tx, Some(amount.into()))
+    }
+
+    /// A dispute of the client's transaction `tx`.
+    pub fn dispute(client: impl Into<ClientId>, tx: impl Into<TxId>) -> Transaction {
+        Self::new(TransactionType::Dispute, client, tx, None)
+    }
+
+    /// A resolve of the client's disputed transaction `tx`.
+    pub fn resolve(client: impl Into<ClientId>, tx: impl Into<TxId>) -> Transaction {
+        Self::new(TransactionType::Resolve, client, tx, None)
+    }
+
+    /// A chargeback of the client's disputed transaction `tx`.
+    pub fn chargeback(client: impl Into<ClientId>, tx: impl Into<TxId>) -> Transaction {
+        Self::new(TransactionType::Chargeback, client, tx, None)
+    }
+
+    /// A request to close the client's account, recorded under ID `tx`.
+    pub fn close(client: impl Into<ClientId>, tx: impl Into<TxId>) -> Transaction {
+        Self::new(TransactionType::CloseAccount, client, tx, None)
+    }
+
+    /// This transaction stamped with `timestamp`.
+    pub fn at(self, timestamp: u64) -> Transaction {
+        Transaction {
+            timestamp: Some(timestamp),
+            ..self
+        }
+    }
+
+    /// The withdrawal recorded when this close transaction pays out `amount`. It shares
+    /// the close transaction's ID.
+    pub fn payout(&self, amount: Amount) -> Transaction {
+        Transaction {
+            timestamp: self.timestamp,
+            tenant: self.tenant.clone(),
+            ..Self::withdrawal(self.client, self.tx, amount)
+        }
+    }
+
+    /// This transaction with any reason code or case reference it does not carry itself
+    /// taken from `case`.
+    pub fn with_case(&self, case: &DisputeCase) -> Transaction {
+        let mut transaction = self.clone();
+        if transaction.reason_code.is_none() {
+            transaction.reason_code = case.reason_code.clone();
+        }
+        if transaction.case_ref.is_none() {
+            transaction.case_ref = case.case_ref.clone();
+        }
+        transaction
+    }
+}
+
+/// Reason code and case reference recorded against a disputed transaction.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DisputeCase {
+    /// The latest reason code given for the dispute.
+    pub reason_code: Option<String>,
+
+    /// The latest case reference given for the dispute.
+    pub case_ref: Option<String>,
+}
+
+impl DisputeCase {
+    /// Record the reason code and case reference `transaction` carries, keeping the
+    /// earlier values of any it leaves out.
+    pub fn update(&mut self, transaction: &Transaction) {
+        if let Some(reason_code) = &transaction.reason_code {
+            self.reason_code = Some(reason_code.clone());
+        }
+        if let Some(case_ref) = &transaction.case_ref {
+            self.case_ref = Some(case_ref.clone());
+        }
+    }
 }
 
 /// Represents a stored transaction with its details.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct StoredTransaction {
     /// Unique identifier for the client.
     pub client: ClientId,
@@ -54,4 +590,115 @@ pub struct StoredTransaction {
 
     /// Indicates if the transaction is currently disputed.
     pub disputed: bool,
+
+    /// Whether the transaction was a withdrawal rather than a deposit.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub withdrawal: bool,
+
+    /// Reason code and case reference from the dispute rows, once any were given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub case: Option<Box<DisputeCase>>,
+}
+
+impl StoredTransaction {
+    /// Whether `transaction` repeats the deposit or withdrawal this record was kept for.
+    pub fn matches(&self, transaction: &Transaction) -> bool {
+        self.client == transaction.client
+            && Some(self.amount) == transaction.amount
+            && self.withdrawal == (transaction.tx_type == TransactionType::Withdrawal)
+    }
+}
+
+pub(crate) const SECONDS_PER_DAY: u64 = 86_400;
+
+/// `YYYY-MM-DD` for a number of days since 1970-01-01 (Howard Hinnant's civil_from_days)
+pub(crate) fn format_day(days: u64) -> String {
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_type_names() {
+        for (name, expected) in [
+            ("deposit", TransactionType::Deposit),
+            ("Deposit", TransactionType::Deposit),
+            ("WITHDRAWAL", TransactionType::Withdrawal),
+            ("charge_back", TransactionType::Chargeback),
+            ("Charge-Back", TransactionType::Chargeback),
+            ("close_account", TransactionType::CloseAccount),
+        ] {
+            assert_eq!(TransactionType::from_name(name), Some(expected), "{}", name);
+        }
+        for name in ["", "deposits", "depo", "refund"] {
+            assert_eq!(TransactionType::from_name(name), None, "{}", name);
+        }
+
+        let input = "type,client,tx,amount\nRefund,1,1,1.0\n";
+        let error = csv::Reader::from_reader(input.as_bytes())
+            .deserialize::<Transaction>()
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("unknown transaction type 'Refund'")
+        );
+    }
+
+    #[test]
+    fn test_amount_formats() {
+        let normalize = |format: AmountFormat, amount: &str| format.normalize(amount);
+        assert_eq!(
+            normalize(AmountFormat::Plain, " +1234.5 ").as_deref(),
+            Some("1234.5")
+        );
+        assert_eq!(
+            normalize(AmountFormat::Grouped, "+1,234,567.89").as_deref(),
+            Some("1234567.89")
+        );
+        assert_eq!(
+            normalize(AmountFormat::European, "1.234.567,89").as_deref(),
+            Some("1234567.89")
+        );
+        assert_eq!(
+            normalize(AmountFormat::European, "1 234,5").as_deref(),
+            Some("1234.5")
+        );
+        assert_eq!(normalize(AmountFormat::Grouped, "1.2.3"), None);
+        assert_eq!(normalize(AmountFormat::European, "1,23.4"), None);
+    }
+
+    #[test]
+    fn test_tx_id_namespaces() {
+        let namespaces = TxIdNamespaces::new();
+        let shared = namespaces.clone();
+        assert_eq!(namespaces.assign(), Some(TxId::new(1)));
+        assert_eq!(shared.assign(), Some(TxId::new(2)));
+        assert_eq!(namespaces, shared);
+        assert_ne!(namespaces, TxIdNamespaces::new());
+
+        let last = TxIdNamespaces::starting_at(TxId::new(u32::MAX - 1));
+        assert_eq!(last.assign(), Some(TxId::new(u32::MAX - 1)));
+        assert_eq!(last.assign(), None);
+    }
+
+    #[test]
+    fn test_format_day() {
+        assert_eq!(format_day(0), "1970-01-01");
+        assert_eq!(format_day(19_782), "2024-02-29");
+        assert_eq!(format_day(11_016), "2000-02-29");
+    }
 }
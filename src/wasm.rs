@@ -0,0 +1,30 @@
+//! wasm-bindgen bindings for validating transaction files in the browser.
+//!
+//! Build with `cargo rustc --lib --crate-type cdylib --target wasm32-unknown-unknown --no-default-features --features wasm`.
+
+use wasm_bindgen::prelude::*;
+
+use crate::account::Account;
+use crate::engine::{EngineConfig, PaymentsEngine};
+
+/// Process a transactions CSV and return the resulting accounts as a JSON array.
+/// Uses the standard engine; rows that fail to parse or process are skipped, as in the CLI.
+#[wasm_bindgen]
+pub fn process_csv(bytes: &[u8]) -> Result<String, JsValue> {
+    let mut engine = PaymentsEngine::new(EngineConfig::standard());
+    engine
+        .process_transactions_from_reader(bytes)
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+    let mut output = Vec::new();
+    engine
+        .write_accounts_csv(&mut output)
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+    let accounts = csv::Reader::from_reader(output.as_slice())
+        .deserialize()
+        .collect::<Result<Vec<Account>, _>>()
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+    serde_json::to_string(&accounts).map_err(|e| JsValue::from_str(&e.to_string()))
+}
@@ -0,0 +1,347 @@
+//! Live account updates for dashboards over WebSocket.
+//!
+//! `AccountFeed` listens for WebSocket connections and forwards the engine's account
+//! changes (see `PaymentsEngine::subscribe_account_changes`) to them as JSON text
+//! messages, so a dashboard no longer has to poll the output file:
+//!
+//! ```text
+//! {"type":"balance","client":1,"tx":5,"available":"7.5","held":"0","total":"7.5",
+//!  "available_change":"2.5","held_change":"0","total_change":"2.5"}
+//! {"type":"lock","client":1,"tx":9,"lock_state":"chargeback_lock",
+//!  "lock_reason":"chargeback of transaction 9"}
+//! ```
+//!
+//! A `balance` message is sent when a change moves funds, a `lock` message when the
+//! account's lock state or reason differs from the last one the feed saw (unlocked for
+//! clients it has not seen). Connecting to `/?clients=1,7` subscribes to those clients
+//! only; without `clients`, a subscriber gets every client.
+//!
+//! Like `AdminChannel`, the feed is polled from the engine's own thread. A subscriber
+//! that stops reading for `WRITE_TIMEOUT` is disconnected rather than holding up the
+//! engine.
+
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::http::StatusCode;
+use tungstenite::{Message, WebSocket};
+
+use crate::account::{Account, ClientId, LockState};
+use crate::engine::observer::AccountDelta;
+use crate::transaction::{Amount, TxId};
+
+/// How long a connecting dashboard may take to complete the handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long a subscriber may take to accept a message before it is disconnected
+pub const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A message sent to subscribers
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum FeedMessage {
+    Balance {
+        client: ClientId,
+        tx: Option<TxId>,
+        available: Amount,
+        held: Amount,
+        total: Amount,
+        available_change: Amount,
+        held_change: Amount,
+        total_change: Amount,
+    },
+    Lock {
+        client: ClientId,
+        tx: Option<TxId>,
+        lock_state: LockState,
+        lock_reason: Option<String>,
+    },
+}
+
+impl FeedMessage {
+    pub fn client(&self) -> ClientId {
+        match self {
+            Self::Balance { client, .. } | Self::Lock { client, .. } => *client,
+        }
+    }
+}
+
+/// A connected dashboard and the clients it asked for
+#[derive(Debug)]
+struct Subscriber {
+    socket: WebSocket<TcpStream>,
+    /// `None` for every client
+    clients: Option<HashSet<ClientId>>,
+}
+
+/// WebSocket listener fanning account changes out to dashboards.
+#[derive(Debug)]
+pub struct AccountFeed {
+    listener: TcpListener,
+    subscribers: Vec<Subscriber>,
+    /// Last lock state and reason seen per client, to send `lock` messages on changes
+    locks: HashMap<ClientId, (LockState, Option<String>)>,
+}
+
+impl AccountFeed {
+    /// Listen on `address`, e.g. `127.0.0.1:9090`.
+    pub fn bind(address: impl ToSocketAddrs) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        log::info!(
+            "Streaming account updates on ws://{}",
+            listener.local_addr()?
+        );
+        Ok(Self {
+            listener,
+            subscribers: Vec::new(),
+            locks: HashMap::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.len()
+    }
+
+    /// Take on every dashboard waiting to connect. Connections that fail the handshake,
+    /// e.g. with an invalid `clients` list, are refused and skipped.
+    pub fn accept(&mut self) -> std::io::Result<()> {
+        loop {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            match handshake(stream) {
+                Ok(subscriber) => self.subscribers.push(subscriber),
+                Err(e) => log::warn!("Refused account feed subscriber: {}", e),
+            }
+        }
+    }
+
+    /// Send the messages for `delta` to the subscribers of its client.
+    pub fn publish(&mut self, delta: &AccountDelta) {
+        for message in self.messages(delta) {
+            self.send(&message);
+        }
+    }
+
+    /// The messages `delta` calls for, remembering its lock state
+    fn messages(&mut self, delta: &AccountDelta) -> Vec<FeedMessage> {
+        let account = &delta.account;
+        let mut messages = Vec::new();
+        if !(delta.available_change.is_zero()
+            && delta.held_change.is_zero()
+            && delta.total_change.is_zero())
+        {
+            messages.push(FeedMessage::Balance {
+                client: account.client,
+                tx: delta.tx,
+                available: account.available,
+                held: account.held,
+                total: account.total,
+                available_change: delta.available_change,
+                held_change: delta.held_change,
+                total_change: delta.total_change,
+            });
+        }
+        let lock = (account.lock, account.lock_reason.clone());
+        let previous = self.locks.get(&account.client);
+        if previous.unwrap_or(&(LockState::Unlocked, None)) != &lock {
+            messages.push(lock_message(account, delta.tx));
+        }
+        if previous != Some(&lock) {
+            self.locks.insert(account.client, lock);
+        }
+        messages
+    }
+
+    fn send(&mut self, message: &FeedMessage) {
+        let client = message.client();
+        if !self
+            .subscribers
+            .iter()
+            .any(|subscriber| subscribes(subscriber, client))
+        {
+            return;
+        }
+        let text = serde_json::to_string(message).expect("feed messages serialize to JSON");
+        self.subscribers.retain_mut(|subscriber| {
+            if !subscribes(subscriber, client) {
+                return true;
+            }
+            match subscriber.socket.send(Message::Text(text.clone())) {
+                Ok(()) => true,
+                Err(e) => {
+                    log::info!("Account feed subscriber disconnected: {}", e);
+                    false
+                }
+            }
+        });
+    }
+}
+
+fn subscribes(subscriber: &Subscriber, client: ClientId) -> bool {
+    subscriber
+        .clients
+        .as_ref()
+        .is_none_or(|clients| clients.contains(&client))
+}
+
+fn lock_message(account: &Account, tx: Option<TxId>) -> FeedMessage {
+    FeedMessage::Lock {
+        client: account.client,
+        tx,
+        lock_state: account.lock,
+        lock_reason: account.lock_reason.clone(),
+    }
+}
+
+// tungstenite fixes the callback's error type
+#[allow(clippy::result_large_err)]
+fn handshake(stream: TcpStream) -> Result<Subscriber, String> {
+    let configure = |stream: &TcpStream| {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))
+    };
+    configure(&stream).map_err(|e| e.to_string())?;
+
+    let mut clients = None;
+    let socket = tungstenite::accept_hdr(stream, |request: &Request, response: Response| {
+        clients = parse_clients(request.uri().query()).map_err(|e| {
+            let mut response = ErrorResponse::new(Some(e));
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            response
+        })?;
+        Ok(response)
+    })
+    .map_err(|e| e.to_string())?;
+    Ok(Subscriber { socket, clients })
+}
+
+/// The `clients` of a query string like `clients=1,7`; `None` for every client
+fn parse_clients(query: Option<&str>) -> Result<Option<HashSet<ClientId>>, String> {
+    let Some(list) = query
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .find_map(|pair| pair.strip_prefix("clients="))
+    else {
+        return Ok(None);
+    };
+    list.split(',')
+        .map(|client| {
+            client
+                .parse()
+                .map_err(|e| format!("invalid client ID '{}': {}", client, e))
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{EngineConfig, PaymentsEngine};
+    use crate::transaction::Transaction;
+
+    fn connect(feed: &mut AccountFeed, path: &str) -> WebSocket<TcpStream> {
+        let address = feed.local_addr().unwrap();
+        let stream = TcpStream::connect(address).unwrap();
+        let url = format!("ws://{}{}", address, path);
+        let handshake = std::thread::spawn(move || {
+            tungstenite::client(url, stream)
+                .map(|(socket, _)| socket)
+                .map_err(|e| e.to_string())
+        });
+        // Give the client time to send its request before the feed polls
+        while !handshake.is_finished() {
+            feed.accept().unwrap();
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        handshake.join().unwrap().unwrap()
+    }
+
+    fn read_json(socket: &mut WebSocket<TcpStream>) -> serde_json::Value {
+        let message = socket.read().unwrap();
+        serde_json::from_str(message.to_text().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_account_feed() {
+        let mut feed = AccountFeed::bind("127.0.0.1:0").unwrap();
+        let mut all = connect(&mut feed, "/");
+        let mut only_two = connect(&mut feed, "/?clients=2");
+        assert_eq!(feed.subscriber_count(), 2);
+
+        let mut engine = PaymentsEngine::new(EngineConfig::standard());
+        let changes = engine.subscribe_account_changes();
+        for transaction in [
+            Transaction::deposit(1, 1, Amount::new(5, 0)),
+            Transaction::deposit(2, 2, Amount::new(3, 0)),
+            Transaction::dispute(2, 2),
+            Transaction::chargeback(2, 2),
+        ] {
+            engine.process_transaction(&transaction).unwrap();
+        }
+        for delta in changes.try_iter() {
+            feed.publish(&delta);
+        }
+
+        let first = read_json(&mut all);
+        assert_eq!(first["type"], "balance");
+        assert_eq!(first["client"], 1);
+        assert_eq!(first["available"], "5");
+
+        let deposit = read_json(&mut only_two);
+        assert_eq!(deposit["client"], 2);
+        assert_eq!(deposit["total_change"], "3");
+        assert_eq!(read_json(&mut only_two)["held"], "3");
+        assert_eq!(read_json(&mut only_two)["total"], "0");
+        let lock = read_json(&mut only_two);
+        assert_eq!(lock["type"], "lock");
+        assert_eq!(lock["lock_state"], "chargeback_lock");
+        assert_eq!(lock["tx"], 2);
+
+        // A lock set outside a transaction is published without a balance message
+        engine
+            .set_lock(ClientId::new(2), LockState::Unlocked, None)
+            .unwrap();
+        let account = engine.peek_account(ClientId::new(2)).unwrap().unwrap();
+        feed.publish(&AccountDelta::new(None, Some(account.clone()), &account));
+        let unlock = read_json(&mut only_two);
+        assert_eq!(unlock["lock_state"], "unlocked");
+        assert!(unlock["tx"].is_null());
+
+        // Subscribers that went away are dropped once a message to them fails
+        drop(all);
+        let account = engine.peek_account(ClientId::new(1)).unwrap().unwrap();
+        for _ in 0..100 {
+            if feed.subscriber_count() == 1 {
+                break;
+            }
+            feed.publish(&AccountDelta::new(None, None, &account));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(feed.subscriber_count(), 1);
+    }
+
+    #[test]
+    fn test_parse_clients() {
+        assert_eq!(parse_clients(None), Ok(None));
+        assert_eq!(parse_clients(Some("token=x")), Ok(None));
+        assert_eq!(
+            parse_clients(Some("token=x&clients=1,7")),
+            Ok(Some([ClientId::new(1), ClientId::new(7)].into()))
+        );
+        assert!(parse_clients(Some("clients=1,x")).is_err());
+    }
+}
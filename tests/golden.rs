@@ -0,0 +1,93 @@
+//! Golden-file tests: every engine processes each scenario in `tests/fixtures/` and its
+//! account output, rows sorted by client, must match the checked-in `.expected.csv` file
+//! next to it. A behavior change then shows up in review as a changed expected file.
+//!
+//! After an intended change, regenerate the expected files with the standard engine:
+//! `UPDATE_GOLDEN=1 cargo test --test golden`
+
+use std::path::{Path, PathBuf};
+
+use payment_engine::engine::cache::EvictionPolicy;
+use payment_engine::engine::{EngineConfig, PaymentsEngine};
+
+fn engine_configs() -> Vec<(&'static str, EngineConfig)> {
+    vec![
+        ("standard", EngineConfig::standard()),
+        ("bounded", EngineConfig::bounded(1000, 1000, 1000)),
+        (
+            "bounded-segmented",
+            EngineConfig::bounded(1000, 1000, 1000).with_eviction_policy(EvictionPolicy::Segmented),
+        ),
+        #[cfg(feature = "concurrent")]
+        ("concurrent", EngineConfig::concurrent(1000, 1000, 1000)),
+        #[cfg(feature = "adaptive")]
+        ("adaptive", EngineConfig::adaptive(1024 * 1024)),
+    ]
+}
+
+/// Input files of every scenario, by name
+fn fixtures() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let mut inputs: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.to_str()
+                .is_some_and(|path| path.ends_with(".csv") && !path.ends_with(".expected.csv"))
+        })
+        .collect();
+    inputs.sort();
+    assert!(!inputs.is_empty(), "no fixtures in {:?}", dir);
+    inputs
+}
+
+/// Accounts CSV after processing `input`, with the rows after the header sorted
+fn run(config: EngineConfig, input: &Path) -> String {
+    let mut engine = PaymentsEngine::new(config);
+    engine
+        .process_transactions_from_reader(std::fs::File::open(input).unwrap())
+        .unwrap();
+    let mut output = Vec::new();
+    engine.write_accounts_csv(&mut output).unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    let mut lines = output.lines();
+    let header = lines.next().unwrap_or_default();
+    let mut rows: Vec<&str> = lines.collect();
+    rows.sort_by_key(|row| {
+        row.split(',')
+            .next()
+            .and_then(|client| client.parse::<u16>().ok())
+    });
+    std::iter::once(header)
+        .chain(rows)
+        .map(|line| format!("{line}\n"))
+        .collect()
+}
+
+#[test]
+fn engines_match_golden_files() {
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    for input in fixtures() {
+        let expected_path = input.with_extension("expected.csv");
+        if update {
+            std::fs::write(&expected_path, run(EngineConfig::standard(), &input)).unwrap();
+        }
+        let expected = std::fs::read_to_string(&expected_path).unwrap_or_else(|e| {
+            panic!(
+                "{:?}: {} (run with UPDATE_GOLDEN=1 to create it)",
+                expected_path, e
+            )
+        });
+
+        for (name, config) in engine_configs() {
+            assert_eq!(
+                run(config, &input),
+                expected,
+                "{} engine output differs from {:?}",
+                name,
+                expected_path
+            );
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! Loom model tests for the concurrent engine's shared state.
+//!
+//! Run with:
+//! `RUSTFLAGS="--cfg loom" cargo test --release --test loom`
+#![cfg(loom)]
+
+use loom::sync::Arc;
+use loom::thread;
+use rust_decimal::Decimal;
+
+use payment_engine::account::ClientId;
+use payment_engine::engine::concurrent::ConcurrentEngine;
+use payment_engine::engine::routing::{ConsistentHashRouter, Router};
+use payment_engine::transaction::{Amount, Transaction};
+
+fn deposit(client: u16, tx: u32, amount: i64) -> Transaction {
+    Transaction::deposit(client, tx, Decimal::new(amount, 0))
+}
+
+#[test]
+fn duplicate_tx_id_is_accepted_once() {
+    loom::model(|| {
+        let engine = Arc::new(ConcurrentEngine::new(4, 4, 4));
+
+        let handles: Vec<_> = [1u16, 2]
+            .into_iter()
+            .map(|client| {
+                let engine = engine.clone();
+                thread::spawn(move || engine.process_transaction_shared(&deposit(client, 7, 10)))
+            })
+            .collect();
+
+        let accepted = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .filter(Result::is_ok)
+            .count();
+        assert_eq!(accepted, 1);
+        assert_eq!(engine.get_engine_info().account_count, 1);
+    });
+}
+
+#[test]
+fn concurrent_deposits_are_not_lost() {
+    loom::model(|| {
+        let engine = Arc::new(ConcurrentEngine::new(4, 4, 4));
+
+        let handles: Vec<_> = [1u32, 2]
+            .into_iter()
+            .map(|tx| {
+                let engine = engine.clone();
+                thread::spawn(move || engine.process_transaction_shared(&deposit(1, tx, 5)))
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap().unwrap();
+        }
+
+        let account = engine.peek_account(ClientId::new(1)).unwrap().unwrap();
+        assert_eq!(account.available, Amount::new(10, 0));
+        assert_eq!(account.held, Amount::ZERO);
+        assert_eq!(account.total, Amount::new(10, 0));
+        assert!(!account.is_locked());
+    });
+}
+
+#[test]
+fn worker_routing_is_stable() {
+    for workers in 1..=8 {
+        for client in (0..=64u16).map(ClientId::new) {
+            let worker = ConsistentHashRouter.route(client, workers);
+            assert!(worker < workers);
+            assert_eq!(worker, ConsistentHashRouter.route(client, workers));
+        }
+    }
+}
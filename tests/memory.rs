@@ -0,0 +1,94 @@
+//! Checks `MemoryEstimates` against the heap the bounded engine's caches really use,
+//! counted by the `bench-alloc` allocator.
+//!
+//! Run with:
+//! `cargo test --features bench-alloc --test memory`
+#![cfg(feature = "bench-alloc")]
+
+use std::num::NonZeroUsize;
+
+use payment_engine::account::{Account, ClientId};
+use payment_engine::engine::cache::{BoundedCache, EvictionPolicy};
+use payment_engine::engine::memory::MemoryEstimates;
+use payment_engine::heap;
+use payment_engine::transaction::{Amount, StoredTransaction, TxId};
+
+const ENTRIES: usize = 20_000;
+
+/// Bytes the allocator adds to each allocation that the counting allocator cannot see:
+/// the size header and the rounding that `MemoryEstimates` accounts for
+const UNSEEN_OVERHEAD: usize = 32;
+
+/// Heap bytes per entry of a cache of `policy` filled with `ENTRIES` entries
+fn bytes_per_entry<K, V>(policy: EvictionPolicy, entry: impl Fn(usize) -> (K, V)) -> usize
+where
+    K: std::hash::Hash + Eq + Ord + Copy,
+{
+    let before = heap::allocated();
+    let mut cache = BoundedCache::new(policy, NonZeroUsize::new(ENTRIES).unwrap());
+    for i in 0..ENTRIES {
+        let (key, value) = entry(i);
+        cache.put(key, value);
+    }
+    let bytes = heap::allocated().saturating_sub(before) / ENTRIES;
+    drop(cache);
+    bytes
+}
+
+// The allocator's counters are process-wide, so this file holds a single test: no other
+// test thread allocates or frees while it measures.
+#[test]
+fn test_estimates_are_lower_bounds() {
+    let estimates = MemoryEstimates::calibrate();
+    let account = |i: usize| {
+        let client = ClientId::new(i as u16);
+        (client, Account::new(client))
+    };
+    let transaction = |i: usize| {
+        let stored = StoredTransaction {
+            client: ClientId::new(1),
+            amount: Amount::ZERO,
+            disputed: false,
+            withdrawal: false,
+            case: None,
+        };
+        (TxId::new(i as u32), stored)
+    };
+    let tx_id = |i: usize| (TxId::new(i as u32), ());
+
+    for policy in [
+        EvictionPolicy::Lru,
+        EvictionPolicy::Fifo,
+        EvictionPolicy::Lfu,
+        EvictionPolicy::Segmented,
+    ] {
+        for (estimate, measured) in [
+            (estimates.account_bytes, bytes_per_entry(policy, account)),
+            (
+                estimates.transaction_bytes,
+                bytes_per_entry(policy, transaction),
+            ),
+            (estimates.tx_id_bytes, bytes_per_entry(policy, tx_id)),
+        ] {
+            // The real cost includes the allocator overhead the counter cannot see
+            assert!(
+                estimate <= measured + UNSEEN_OVERHEAD,
+                "{:?}: estimated {} bytes per entry, measured {}",
+                policy,
+                estimate,
+                measured
+            );
+            // The LRU layouts are the ones modelled, so there the estimates are exact up
+            // to that overhead; LFU keeps a frequency index on top
+            if policy != EvictionPolicy::Lfu {
+                assert!(
+                    measured <= estimate,
+                    "{:?}: estimated {} bytes per entry, measured {}",
+                    policy,
+                    estimate,
+                    measured
+                );
+            }
+        }
+    }
+}